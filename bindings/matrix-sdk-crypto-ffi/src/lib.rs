@@ -35,7 +35,8 @@ use matrix_sdk_crypto::{
     olm::{IdentityKeys, InboundGroupSession, Session},
     store::{Changes, CryptoStore, RoomSettings as RustRoomSettings},
     types::{EventEncryptionAlgorithm as RustEventEncryptionAlgorithm, SigningKey},
-    EncryptionSettings as RustEncryptionSettings, LocalTrust,
+    CollectStrategy as RustCollectStrategy, EncryptionSettings as RustEncryptionSettings,
+    LocalTrust,
 };
 use matrix_sdk_sqlite::SqliteCryptoStore;
 pub use responses::{
@@ -614,6 +615,34 @@ impl From<HistoryVisibility> for RustHistoryVisibility {
     }
 }
 
+/// Strategy to decide which devices should receive a room key.
+///
+/// Take a look at [`matrix_sdk_crypto::CollectStrategy`] for more info.
+#[derive(uniffi::Enum)]
+pub enum CollectStrategy {
+    /// Share the room key with all the devices that aren't blacklisted.
+    AllDevices,
+    /// Share the room key only with devices that were cross-signed by their
+    /// owner.
+    CrossSignedOnly,
+    /// Share the room key only with devices of verified users.
+    VerifiedUsersOnly,
+    /// Refuse to share the room key if any of the room members isn't
+    /// verified.
+    ErrorOnUnverifiedIdentity,
+}
+
+impl From<CollectStrategy> for RustCollectStrategy {
+    fn from(s: CollectStrategy) -> Self {
+        match s {
+            CollectStrategy::AllDevices => Self::AllDevices,
+            CollectStrategy::CrossSignedOnly => Self::CrossSignedOnly,
+            CollectStrategy::VerifiedUsersOnly => Self::VerifiedUsersOnly,
+            CollectStrategy::ErrorOnUnverifiedIdentity => Self::ErrorOnUnverifiedIdentity,
+        }
+    }
+}
+
 /// Settings that should be used when a room key is shared.
 ///
 /// These settings control which algorithm the room key should use, how long a
@@ -635,6 +664,11 @@ pub struct EncryptionSettings {
     /// Should untrusted devices receive the room key, or should they be
     /// excluded from the conversation.
     pub only_allow_trusted_devices: bool,
+    /// Should sharing the room key fail if the identity of a room member
+    /// changed and the change wasn't acknowledged.
+    pub error_on_identity_violation: bool,
+    /// The strategy deciding which devices should receive the room key.
+    pub sharing_strategy: CollectStrategy,
 }
 
 impl From<EncryptionSettings> for RustEncryptionSettings {
@@ -645,6 +679,8 @@ impl From<EncryptionSettings> for RustEncryptionSettings {
             rotation_period_msgs: v.rotation_period_msgs,
             history_visibility: v.history_visibility.into(),
             only_allow_trusted_devices: v.only_allow_trusted_devices,
+            error_on_identity_violation: v.error_on_identity_violation,
+            sharing_strategy: v.sharing_strategy.into(),
         }
     }
 }
@@ -838,6 +874,7 @@ impl From<RoomSettings> for RustRoomSettings {
         Self {
             algorithm: value.algorithm.into(),
             only_allow_trusted_devices: value.only_allow_trusted_devices,
+            ..Default::default()
         }
     }
 }
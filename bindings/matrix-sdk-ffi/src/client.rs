@@ -112,6 +112,7 @@ impl From<PushFormat> for RumaPushFormat {
 pub trait ClientDelegate: Sync + Send {
     fn did_receive_auth_error(&self, is_soft_logout: bool);
     fn did_refresh_tokens(&self);
+    fn did_wipe_session(&self);
 }
 
 #[uniffi::export(callback_interface)]
@@ -626,6 +627,16 @@ impl Client {
         }
     }
 
+    /// Log out the current user and delete all the local data of the
+    /// session.
+    ///
+    /// The local data is deleted even if logging out fails. This client must
+    /// not be used afterwards.
+    pub fn logout_and_wipe(&self) -> Result<(), ClientError> {
+        RUNTIME.block_on(self.inner.logout_and_wipe())?;
+        Ok(())
+    }
+
     /// Registers a pusher with given parameters
     pub fn set_pusher(
         &self,
@@ -720,10 +731,7 @@ impl Client {
 
     pub fn get_notification_settings(&self) -> Arc<NotificationSettings> {
         RUNTIME.block_on(async move {
-            Arc::new(NotificationSettings::new(
-                self.inner.clone(),
-                self.inner.notification_settings().await,
-            ))
+            Arc::new(NotificationSettings::new(self.inner.notification_settings().await))
         })
     }
 }
@@ -768,6 +776,9 @@ impl Client {
                 SessionChange::TokensRefreshed => {
                     delegate.did_refresh_tokens();
                 }
+                SessionChange::Wiped => {
+                    delegate.did_wipe_session();
+                }
             });
         }
     }
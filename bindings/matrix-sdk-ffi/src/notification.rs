@@ -1,11 +1,12 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
 use matrix_sdk_ui::notification_client::{
     NotificationClient as MatrixNotificationClient,
     NotificationClientBuilder as MatrixNotificationClientBuilder,
     NotificationItem as MatrixNotificationItem,
+    NotificationItemsRequest as MatrixNotificationItemsRequest,
 };
-use ruma::{EventId, RoomId};
+use ruma::{EventId, OwnedEventId, RoomId};
 
 use crate::{error::ClientError, event::TimelineEvent, helpers::unwrap_or_clone_arc, RUNTIME};
 
@@ -42,6 +43,10 @@ pub struct NotificationItem {
     /// Can be `None` if we couldn't determine this, because we lacked
     /// information to create a push context.
     pub is_noisy: Option<bool>,
+    /// Does the event mention the user?
+    /// Can be `None` if we couldn't determine this, because we lacked
+    /// information to create a push context.
+    pub has_mention: Option<bool>,
 }
 
 impl NotificationItem {
@@ -70,10 +75,26 @@ impl NotificationItem {
                 is_direct: item.is_direct_message_room,
             },
             is_noisy: item.is_noisy,
+            has_mention: item.has_mention,
         }
     }
 }
 
+#[derive(uniffi::Record)]
+pub struct NotificationItemsRequest {
+    pub room_id: String,
+    pub event_ids: Vec<String>,
+}
+
+#[derive(uniffi::Enum)]
+pub enum BatchNotificationResult {
+    /// The notification was resolved; `None` means it has been filtered out by
+    /// the user's push rules.
+    Ok { item: Option<NotificationItem> },
+    /// The notification couldn't be resolved.
+    Error { message: String },
+}
+
 #[derive(Clone, uniffi::Object)]
 pub struct NotificationClientBuilder {
     builder: MatrixNotificationClientBuilder,
@@ -145,4 +166,45 @@ impl NotificationClient {
             }
         })
     }
+
+    /// Get the notifications of several events at once, keyed by event ID.
+    ///
+    /// See also documentation of
+    /// `MatrixNotificationClient::get_notifications`.
+    pub fn get_notifications(
+        &self,
+        requests: Vec<NotificationItemsRequest>,
+    ) -> Result<HashMap<String, BatchNotificationResult>, ClientError> {
+        let requests = requests
+            .into_iter()
+            .map(|request| {
+                Ok(MatrixNotificationItemsRequest {
+                    room_id: RoomId::parse(request.room_id)?,
+                    event_ids: request.event_ids.into_iter().map(EventId::parse).collect::<Result<
+                        Vec<OwnedEventId>,
+                        _,
+                    >>(
+                    )?,
+                })
+            })
+            .collect::<Result<Vec<_>, ClientError>>()?;
+
+        RUNTIME.block_on(async move {
+            let results =
+                self.inner.get_notifications(&requests).await.map_err(ClientError::from)?;
+
+            Ok(results
+                .into_iter()
+                .map(|(event_id, result)| {
+                    let result = match result {
+                        Ok(item) => BatchNotificationResult::Ok {
+                            item: item.map(NotificationItem::from_inner),
+                        },
+                        Err(err) => BatchNotificationResult::Error { message: err.to_string() },
+                    };
+                    (event_id.to_string(), result)
+                })
+                .collect())
+        })
+    }
 }
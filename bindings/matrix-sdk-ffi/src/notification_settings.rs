@@ -1,19 +1,15 @@
 use std::sync::Arc;
 
-use matrix_sdk::{
-    event_handler::EventHandlerHandle,
-    notification_settings::{
-        NotificationSettings as SdkNotificationSettings,
-        RoomNotificationMode as SdkRoomNotificationMode,
-    },
-    ruma::events::push_rules::PushRulesEvent,
-    Client as MatrixClient,
+use futures_util::{pin_mut, StreamExt};
+use matrix_sdk::notification_settings::{
+    NotificationSettings as SdkNotificationSettings,
+    RoomNotificationMode as SdkRoomNotificationMode,
 };
 use ruma::{
     push::{PredefinedOverrideRuleId, PredefinedUnderrideRuleId, RuleKind},
     RoomId,
 };
-use tokio::sync::RwLock;
+use tokio::{sync::RwLock, task::JoinHandle};
 
 use super::RUNTIME;
 use crate::error::NotificationSettingsError;
@@ -70,63 +66,51 @@ impl RoomNotificationSettings {
     }
 }
 
-#[derive(Clone, uniffi::Object)]
+#[derive(uniffi::Object)]
 pub struct NotificationSettings {
-    sdk_client: MatrixClient,
     sdk_notification_settings: Arc<RwLock<SdkNotificationSettings>>,
-    pushrules_event_handler: Arc<RwLock<Option<EventHandlerHandle>>>,
+    changes_listener: RwLock<Option<JoinHandle<()>>>,
 }
 
 impl NotificationSettings {
-    pub(crate) fn new(
-        sdk_client: MatrixClient,
-        sdk_notification_settings: SdkNotificationSettings,
-    ) -> Self {
+    pub(crate) fn new(sdk_notification_settings: SdkNotificationSettings) -> Self {
         let sdk_notification_settings = Arc::new(RwLock::new(sdk_notification_settings));
-        Self {
-            sdk_client,
-            sdk_notification_settings,
-            pushrules_event_handler: Arc::new(RwLock::new(None)),
-        }
+        Self { sdk_notification_settings, changes_listener: RwLock::new(None) }
     }
 }
 
 impl Drop for NotificationSettings {
     fn drop(&mut self) {
-        // Remove the event handler on the sdk_client.
-        RUNTIME.block_on(async move {
-            if let Some(event_handler) = self.pushrules_event_handler.read().await.as_ref() {
-                self.sdk_client.remove_event_handler(event_handler.clone());
-            }
-        });
+        // Stop listening to the changes of the settings.
+        if let Some(changes_listener) = self.changes_listener.get_mut().take() {
+            changes_listener.abort();
+        }
     }
 }
 
 #[uniffi::export(async_runtime = "tokio")]
 impl NotificationSettings {
     pub fn set_delegate(&self, delegate: Option<Box<dyn NotificationSettingsDelegate>>) {
-        if let Some(delegate) = delegate {
-            let delegate: Arc<dyn NotificationSettingsDelegate> = Arc::from(delegate);
+        RUNTIME.block_on(async move {
+            let changes_listener = &mut *self.changes_listener.write().await;
 
-            // Add an event handler to listen to `PushRulesEvent`
-            let event_handler =
-                self.sdk_client.add_event_handler(move |_: PushRulesEvent| async move {
-                    delegate.settings_did_change();
-                });
+            // Stop notifying the previous delegate
+            if let Some(changes_listener) = changes_listener.take() {
+                changes_listener.abort();
+            }
 
-            RUNTIME.block_on(async move {
-                *self.pushrules_event_handler.write().await = Some(event_handler);
-            });
-        } else {
-            // Remove the event handler if there is no delegate
-            RUNTIME.block_on(async move {
-                let event_handler = &mut *self.pushrules_event_handler.write().await;
-                if let Some(event_handler) = event_handler {
-                    self.sdk_client.remove_event_handler(event_handler.clone());
-                }
-                *event_handler = None;
-            });
-        }
+            if let Some(delegate) = delegate {
+                // Listen to the changes of the settings, made locally or from another device
+                let changes = self.sdk_notification_settings.read().await.subscribe_to_changes();
+                *changes_listener = Some(RUNTIME.spawn(async move {
+                    pin_mut!(changes);
+
+                    while changes.next().await.is_some() {
+                        delegate.settings_did_change();
+                    }
+                }));
+            }
+        });
     }
 
     /// Get the notification settings for a room.
@@ -173,6 +173,20 @@ impl Room {
         });
     }
 
+    pub fn refresh_encryption_info(&self) {
+        let timeline = match &*RUNTIME.block_on(self.timeline.read()) {
+            Some(t) => Arc::clone(t),
+            None => {
+                error!("Timeline not set up, can't refresh the encryption info");
+                return;
+            }
+        };
+
+        RUNTIME.spawn(async move {
+            timeline.refresh_encryption_info().await;
+        });
+    }
+
     pub fn fetch_members(&self) -> Result<Arc<TaskHandle>, ClientError> {
         let timeline = RUNTIME
             .block_on(self.timeline.read())
@@ -5,6 +5,7 @@ use extension_trait::extension_trait;
 use eyeball_im::VectorDiff;
 use matrix_sdk::{
     attachment::{BaseAudioInfo, BaseFileInfo, BaseImageInfo, BaseThumbnailInfo, BaseVideoInfo},
+    deserialized_responses::ShieldState as RustShieldState,
     ruma::events::{
         location::AssetType as RumaAssetType,
         poll::start::PollKind as RumaPollKind,
@@ -364,6 +365,27 @@ impl EventTimelineItem {
     pub fn origin(&self) -> Option<EventItemOrigin> {
         self.0.origin()
     }
+
+    pub fn get_shield(&self, strict: bool) -> ShieldState {
+        self.0.get_shield(strict).into()
+    }
+}
+
+#[derive(uniffi::Enum)]
+pub enum ShieldState {
+    Red { message: String },
+    Grey { message: String },
+    None,
+}
+
+impl From<RustShieldState> for ShieldState {
+    fn from(value: RustShieldState) -> Self {
+        match value {
+            RustShieldState::Red { message } => Self::Red { message: message.to_owned() },
+            RustShieldState::Grey { message } => Self::Grey { message: message.to_owned() },
+            RustShieldState::None => Self::None,
+        }
+    }
 }
 
 #[derive(uniffi::Record)]
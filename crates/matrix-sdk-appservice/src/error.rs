@@ -41,6 +41,9 @@ pub enum Error {
     #[error("uri path is unknown")]
     UriPathUnknown,
 
+    #[error("the transaction queue is full")]
+    TransactionQueueFull,
+
     #[error("HTTP request parsing error: {0}")]
     FromHttpRequest(#[from] ruma::api::error::FromHttpRequestError),
 
@@ -79,6 +82,9 @@ pub enum Error {
 
     #[error("hyper error: {0}")]
     Hyper(#[from] hyper::Error),
+
+    #[error("task join error: {0}")]
+    Join(#[from] tokio::task::JoinError),
 }
 
 impl Error {
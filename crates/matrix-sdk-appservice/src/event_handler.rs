@@ -17,8 +17,18 @@ use std::{future::Future, pin::Pin, sync::Arc};
 use tokio::sync::Mutex;
 
 use crate::{
-    ruma::api::appservice::query::{
-        query_room_alias::v1 as query_room, query_user_id::v1 as query_user,
+    ruma::{
+        api::appservice::{
+            query::{query_room_alias::v1 as query_room, query_user_id::v1 as query_user},
+            thirdparty::{
+                get_location_for_protocol::v1 as query_location,
+                get_location_for_room_alias::v1 as query_location_by_alias,
+                get_protocol::v1 as query_protocol,
+                get_user_for_protocol::v1 as query_thirdparty_user,
+                get_user_for_user_id::v1 as query_thirdparty_user_by_id,
+            },
+        },
+        thirdparty::{Location, Protocol, User},
     },
     AppService,
 };
@@ -26,24 +36,40 @@ use crate::{
 pub(crate) type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
 pub(crate) type AppserviceFn<A, R> =
     Box<dyn FnMut(AppService, A) -> BoxFuture<'static, R> + Send + Sync + 'static>;
+type Handler<A, R> = Arc<Mutex<Option<AppserviceFn<A, R>>>>;
 
 #[derive(Default, Clone)]
 pub struct EventHandler {
-    pub users: Arc<Mutex<Option<AppserviceFn<query_user::Request, bool>>>>,
-    pub rooms: Arc<Mutex<Option<AppserviceFn<query_room::Request, bool>>>>,
+    pub users: Handler<query_user::Request, bool>,
+    pub rooms: Handler<query_room::Request, bool>,
+    pub protocols: Handler<query_protocol::Request, Option<Protocol>>,
+    pub locations: Handler<query_location::Request, Vec<Location>>,
+    pub locations_by_alias: Handler<query_location_by_alias::Request, Vec<Location>>,
+    pub thirdparty_users: Handler<query_thirdparty_user::Request, Vec<User>>,
+    pub thirdparty_users_by_id: Handler<query_thirdparty_user_by_id::Request, Vec<User>>,
 }
 
 impl std::fmt::Debug for EventHandler {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn field<A, R>(
+            debug: &mut std::fmt::DebugStruct<'_, '_>,
+            name: &str,
+            handler: &Handler<A, R>,
+        ) {
+            match handler.try_lock() {
+                Ok(lock) => debug.field(name, &lock.is_some()),
+                Err(_) => debug.field(name, &format_args!("<locked>")),
+            };
+        }
+
         let mut debug = f.debug_struct("EventHandler");
-        match self.users.try_lock() {
-            Ok(lock) => debug.field("users", &lock.is_some()),
-            Err(_) => debug.field("users", &format_args!("<locked>")),
-        };
-        match self.rooms.try_lock() {
-            Ok(lock) => debug.field("rooms", &lock.is_some()),
-            Err(_) => debug.field("rooms", &format_args!("<locked>")),
-        };
+        field(&mut debug, "users", &self.users);
+        field(&mut debug, "rooms", &self.rooms);
+        field(&mut debug, "protocols", &self.protocols);
+        field(&mut debug, "locations", &self.locations);
+        field(&mut debug, "locations_by_alias", &self.locations_by_alias);
+        field(&mut debug, "thirdparty_users", &self.thirdparty_users);
+        field(&mut debug, "thirdparty_users_by_id", &self.thirdparty_users_by_id);
         debug.finish()
     }
 }
@@ -22,7 +22,9 @@
 //! - [x] receive and validate requests from the homeserver correctly
 //! - [x] allow calling the homeserver with proper user identity assertion
 //! - [x] have consistent room state by leveraging matrix-sdk's state store
-//! - [ ] provide E2EE support by leveraging matrix-sdk's crypto store
+//! - [x] provide E2EE support by leveraging matrix-sdk's crypto store, with the
+//!   `e2e-encryption` feature and a homeserver supporting [MSC2409] and
+//!   [MSC3202]
 //!
 //! # Status
 //!
@@ -76,9 +78,11 @@
 //!
 //! [Application Service]: https://matrix.org/docs/spec/application_service/r0.1.2
 //! [matrix-org/matrix-rust-sdk#228]: https://github.com/matrix-org/matrix-rust-sdk/issues/228
+//! [MSC2409]: https://github.com/matrix-org/matrix-spec-proposals/pull/2409
+//! [MSC3202]: https://github.com/matrix-org/matrix-spec-proposals/pull/3202
 //! [examples directory]: https://github.com/matrix-org/matrix-rust-sdk/tree/main/crates/matrix-sdk-appservice/examples
 
-use std::{fmt::Debug, sync::Arc};
+use std::{fmt::Debug, sync::Arc, time::Duration};
 
 use axum::body::HttpBody;
 use dashmap::DashMap;
@@ -87,18 +91,26 @@ use event_handler::AppserviceFn;
 pub use matrix_sdk;
 #[doc(no_inline)]
 pub use matrix_sdk::ruma;
-use matrix_sdk::{config::RequestConfig, reqwest::Url, Client, ClientBuilder};
+use matrix_sdk::{config::RequestConfig, custom_value_keys, reqwest::Url, Client, ClientBuilder};
 use ruma::{
     api::{
         appservice::{
             event::push_events,
             query::{query_room_alias::v1 as query_room, query_user_id::v1 as query_user},
+            thirdparty::{
+                get_location_for_protocol::v1 as query_location,
+                get_location_for_room_alias::v1 as query_location_by_alias,
+                get_protocol::v1 as query_protocol,
+                get_user_for_protocol::v1 as query_thirdparty_user,
+                get_user_for_user_id::v1 as query_thirdparty_user_by_id,
+            },
         },
-        client::{account::register, sync::sync_events},
+        client::{account::register, appservice::request_ping, sync::sync_events},
     },
     assign,
     events::{room::member::MembershipState, AnyStateEvent, AnyTimelineEvent},
-    DeviceId, OwnedRoomId, OwnedServerName,
+    thirdparty::{Location, Protocol, User},
+    DeviceId, OwnedRoomId, OwnedServerName, RoomId, TransactionId, UserId,
 };
 use serde::Deserialize;
 use thiserror::Error;
@@ -108,18 +120,23 @@ use tracing::{debug, info, warn};
 mod error;
 pub mod event_handler;
 pub mod registration;
+mod transaction;
 pub mod user;
+mod virtual_user;
 mod webserver;
 
-pub use registration::AppServiceRegistration;
 use registration::NamespaceCache;
+pub use registration::{AppServiceRegistration, AppServiceRegistrationBuilder, RegistrationIssue};
+pub use transaction::TransactionMetrics;
+use transaction::{TransactionExtensions, TransactionQueue};
 pub use user::UserBuilder;
+pub use virtual_user::VirtualUser;
 pub use webserver::AppServiceRouter;
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
-const USER_KEY: &[u8] = b"appservice.users.";
-const USER_MEMBER: &[u8] = b"appservice.users.membership.";
+const USER_KEY: &[u8] = custom_value_keys::APPSERVICE_USER;
+const USER_MEMBER: &[u8] = custom_value_keys::APPSERVICE_USER_MEMBERSHIP;
 
 type Localpart = String;
 
@@ -133,6 +150,7 @@ pub struct AppService {
     clients: Arc<DashMap<Localpart, Client>>,
     event_handler: event_handler::EventHandler,
     default_request_config: Option<RequestConfig>,
+    transactions: Arc<TransactionQueue>,
 }
 
 /// Builder for an AppService
@@ -143,6 +161,7 @@ pub struct AppServiceBuilder {
     registration: AppServiceRegistration,
     client_builder: Option<ClientBuilder>,
     default_request_config: Option<RequestConfig>,
+    transaction_queue_capacity: usize,
 }
 
 impl AppServiceBuilder {
@@ -168,6 +187,7 @@ impl AppServiceBuilder {
             registration,
             client_builder: None,
             default_request_config: None,
+            transaction_queue_capacity: transaction::DEFAULT_QUEUE_CAPACITY,
         }
     }
 
@@ -183,6 +203,17 @@ impl AppServiceBuilder {
         self
     }
 
+    /// Set the maximum number of transactions from the homeserver that can
+    /// wait to be processed.
+    ///
+    /// Transactions are processed one at a time, in the order in which they
+    /// were received. When the queue is full, new transactions are rejected
+    /// and the homeserver retries them later. Defaults to 100.
+    pub fn transaction_queue_capacity(mut self, capacity: usize) -> Self {
+        self.transaction_queue_capacity = capacity;
+        self
+    }
+
     /// Build the AppService.
     ///
     /// This will also construct an appservice [`user()`][AppService::user]
@@ -199,6 +230,7 @@ impl AppServiceBuilder {
         let sender_localpart = registration.sender_localpart.clone();
         let event_handler = event_handler::EventHandler::default();
         let default_request_config = self.default_request_config;
+        let transactions = Arc::new(TransactionQueue::new(self.transaction_queue_capacity));
 
         let appservice = AppService {
             homeserver_url,
@@ -208,6 +240,7 @@ impl AppServiceBuilder {
             clients,
             event_handler,
             default_request_config,
+            transactions,
         };
         if let Some(client_builder) = self.client_builder {
             appservice
@@ -283,6 +316,21 @@ impl AppService {
         UserBuilder::new(self, localpart)
     }
 
+    /// Get a lightweight handle to send requests on behalf of the user with
+    /// the given `localpart`.
+    ///
+    /// Contrary to [`user()`][Self::user], this doesn't build a [`Client`] for
+    /// the user, so it scales to a large number of users. See [`VirtualUser`]
+    /// for the limitations.
+    pub async fn virtual_user(&self, localpart: &str) -> Result<VirtualUser> {
+        let user_id = UserId::parse_with_server_name(localpart, &self.server_name)?;
+        if !self.user_id_is_in_namespace(&user_id) {
+            warn!("Virtual user id '{user_id}' is not in the namespace");
+        }
+
+        Ok(VirtualUser::new(self.user(None).await?, user_id))
+    }
+
     /// Get the map containing all constructed appservice user clients.
     pub fn users(&self) -> Arc<DashMap<Localpart, Client>> {
         self.clients.clone()
@@ -330,6 +378,104 @@ impl AppService {
         *self.event_handler.rooms.lock().await = Some(handler);
     }
 
+    /// Register a responder for queries about a third party protocol.
+    ///
+    /// If the handler returns `None`, or if no handler is registered, the
+    /// protocol is reported as not found.
+    ///
+    /// See [GET /_matrix/app/v1/thirdparty/protocol/{protocol}](https://spec.matrix.org/v1.7/application-service-api/#get_matrixappv1thirdpartyprotocolprotocol).
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use matrix_sdk_appservice::AppService;
+    /// # fn run(appservice: AppService) {
+    /// appservice.register_protocol_query(Box::new(|appservice, req| {
+    ///     Box::pin(async move {
+    ///         println!("Got request for {}", req.protocol);
+    ///         None
+    ///     })
+    /// }));
+    /// # }
+    /// ```
+    pub async fn register_protocol_query(
+        &self,
+        handler: AppserviceFn<query_protocol::Request, Option<Protocol>>,
+    ) {
+        *self.event_handler.protocols.lock().await = Some(handler);
+    }
+
+    /// Register a responder for queries about the third party locations
+    /// matching the given fields of a protocol.
+    ///
+    /// If the handler returns no location, or if no handler is registered, the
+    /// query is reported as not found.
+    ///
+    /// See [GET /_matrix/app/v1/thirdparty/location/{protocol}](https://spec.matrix.org/v1.7/application-service-api/#get_matrixappv1thirdpartylocationprotocol).
+    pub async fn register_location_query(
+        &self,
+        handler: AppserviceFn<query_location::Request, Vec<Location>>,
+    ) {
+        *self.event_handler.locations.lock().await = Some(handler);
+    }
+
+    /// Register a responder for queries about the third party locations
+    /// of a room alias.
+    ///
+    /// If the handler returns no location, or if no handler is registered, the
+    /// query is reported as not found.
+    ///
+    /// See [GET /_matrix/app/v1/thirdparty/location](https://spec.matrix.org/v1.7/application-service-api/#get_matrixappv1thirdpartylocation).
+    pub async fn register_location_by_alias_query(
+        &self,
+        handler: AppserviceFn<query_location_by_alias::Request, Vec<Location>>,
+    ) {
+        *self.event_handler.locations_by_alias.lock().await = Some(handler);
+    }
+
+    /// Register a responder for queries about the third party users matching
+    /// the given fields of a protocol.
+    ///
+    /// If the handler returns no user, or if no handler is registered, the
+    /// query is reported as not found.
+    ///
+    /// See [GET /_matrix/app/v1/thirdparty/user/{protocol}](https://spec.matrix.org/v1.7/application-service-api/#get_matrixappv1thirdpartyuserprotocol).
+    pub async fn register_thirdparty_user_query(
+        &self,
+        handler: AppserviceFn<query_thirdparty_user::Request, Vec<User>>,
+    ) {
+        *self.event_handler.thirdparty_users.lock().await = Some(handler);
+    }
+
+    /// Register a responder for queries about the third party users of a
+    /// Matrix user id.
+    ///
+    /// If the handler returns no user, or if no handler is registered, the
+    /// query is reported as not found.
+    ///
+    /// See [GET /_matrix/app/v1/thirdparty/user](https://spec.matrix.org/v1.7/application-service-api/#get_matrixappv1thirdpartyuser).
+    pub async fn register_thirdparty_user_by_id_query(
+        &self,
+        handler: AppserviceFn<query_thirdparty_user_by_id::Request, Vec<User>>,
+    ) {
+        *self.event_handler.thirdparty_users_by_id.lock().await = Some(handler);
+    }
+
+    /// Ask the homeserver to ping the appservice, to check that they can
+    /// reach each other.
+    ///
+    /// Returns the duration of the request made by the homeserver to the
+    /// appservice.
+    ///
+    /// See [POST /_matrix/client/v1/appservice/{appserviceId}/ping](https://spec.matrix.org/v1.7/client-server-api/#post_matrixclientv1appserviceappserviceidping).
+    pub async fn ping_homeserver(&self) -> Result<Duration> {
+        let request = assign!(request_ping::v1::Request::new(self.registration.id.clone()), {
+            transaction_id: Some(TransactionId::new()),
+        });
+
+        let client = self.user(None).await?;
+        Ok(client.send(request, None).await?.duration)
+    }
+
     /// Register an appservice user by sending a [`register::v3::Request`] to
     /// the homeserver.
     ///
@@ -410,13 +556,102 @@ impl AppService {
         webserver::router(self.clone())
     }
 
+    /// Get the metrics about the transactions pushed by the homeserver.
+    pub fn transaction_metrics(&self) -> TransactionMetrics {
+        self.transactions.metrics()
+    }
+
     /// Receive an incoming [transaction], pushing the contained events to
     /// active clients.
     ///
+    /// Transactions are processed one at a time, in the order in which they
+    /// were received. Every client remembers the ids of the transactions it
+    /// processed, so that a transaction retried by the homeserver is only
+    /// pushed to the clients that failed to process it.
+    ///
+    /// Once its turn has come, the transaction is processed in a detached task
+    /// that holds its place in the queue. If the request of the homeserver is
+    /// cancelled, the transaction is still processed to completion before the
+    /// next one starts.
+    ///
     /// [transaction]: https://spec.matrix.org/v1.2/application-service-api/#put_matrixappv1transactionstxnid
-    async fn receive_transaction(&self, transaction: push_events::v1::Request) -> Result<()> {
+    async fn receive_transaction(
+        &self,
+        transaction: push_events::v1::Request,
+        extensions: TransactionExtensions,
+    ) -> Result<()> {
+        let guard = self.transactions.enter().await?;
+        let appservice = self.clone();
+
+        let task = tokio::spawn(async move {
+            let result = appservice.process_transaction(transaction, extensions).await;
+            drop(guard);
+            result
+        });
+
+        match task.await {
+            Ok(result) => result,
+            Err(e) => {
+                warn!("Processing the transaction failed: {e}");
+                self.transactions.record_failed();
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Process a transaction, once it is its turn in the queue.
+    async fn process_transaction(
+        &self,
+        transaction: push_events::v1::Request,
+        extensions: TransactionExtensions,
+    ) -> Result<()> {
         let sender_localpart_client = self.user(None).await?;
+        let txn_id = transaction.txn_id.clone();
+
+        // Spec: https://spec.matrix.org/v1.3/application-service-api/#pushing-events
+        if self.is_transaction_processed(&txn_id).await {
+            debug!(%txn_id, "Ignoring an already processed transaction");
+            self.transactions.record_duplicate();
+            return Ok(());
+        }
+
+        match self.dispatch_transaction(&sender_localpart_client, transaction, extensions).await {
+            Ok(()) => {
+                self.transactions.record_processed();
+                Ok(())
+            }
+            Err(e) => {
+                self.transactions.record_failed();
+                Err(e)
+            }
+        }
+    }
+
+    /// Whether every logged-in client already processed the transaction with
+    /// the given id.
+    ///
+    /// A client that fails to tell is considered to not have processed it, it
+    /// will report the error when the transaction is pushed to it.
+    async fn is_transaction_processed(&self, txn_id: &TransactionId) -> bool {
+        let clients: Vec<_> = self.clients.iter().map(|entry| entry.value().clone()).collect();
+        for client in clients {
+            if client.user_id().is_some()
+                && !matches!(client.is_transaction_processed(txn_id).await, Ok(true))
+            {
+                return false;
+            }
+        }
+
+        true
+    }
 
+    /// Push the events of the given transaction to the active clients.
+    async fn dispatch_transaction(
+        &self,
+        sender_localpart_client: &Client,
+        transaction: push_events::v1::Request,
+        extensions: TransactionExtensions,
+    ) -> Result<()> {
         // Find membership events affecting members in our namespace, and update
         // membership accordingly
         for raw_event in transaction.events.iter() {
@@ -443,13 +678,24 @@ impl AppService {
             room_id: Option<OwnedRoomId>,
         }
 
+        /// Helper type for extracting the type and room id for an ephemeral
+        /// event
+        #[derive(Debug, Deserialize)]
+        struct EphemeralEventInfo {
+            #[serde(rename = "type")]
+            event_type: String,
+            room_id: Option<OwnedRoomId>,
+        }
+
         // Spawn a task for each client that constructs and pushes a sync event
         let mut tasks: Vec<JoinHandle<_>> = Vec::new();
         let transaction = Arc::new(transaction);
+        let extensions = Arc::new(extensions);
         for user_client in self.clients.iter() {
             let client = sender_localpart_client.clone();
             let user_client = user_client.clone();
             let transaction = transaction.clone();
+            let extensions = extensions.clone();
             let sender_localpart = self.registration.sender_localpart.clone();
 
             let task = tokio::spawn(async move {
@@ -472,14 +718,9 @@ impl AppService {
                         warn!("Transaction contained event with no ID");
                         continue;
                     };
-                    let key = &[USER_MEMBER, room_id.as_bytes(), b".", user_localpart.as_bytes()]
-                        .concat();
-                    let membership = match client.store().get_custom_value(key).await? {
-                        Some(value) => String::from_utf8(value).ok().map(MembershipState::from),
-                        // Assume the `sender_localpart` user is in every known room
-                        None if user_localpart == sender_localpart => Some(MembershipState::Join),
-                        None => None,
-                    };
+                    let membership =
+                        room_membership(&client, &room_id, user_localpart, &sender_localpart)
+                            .await?;
 
                     match membership {
                         Some(MembershipState::Join) => {
@@ -500,18 +741,71 @@ impl AppService {
                         None => debug!("Assuming {user_localpart} is not in {room_id}"),
                     }
                 }
+
+                // Ephemeral events are only sent to the members of the room,
+                // like in a regular sync.
+                for raw_event in extensions.ephemeral() {
+                    let event = match raw_event.deserialize_as::<EphemeralEventInfo>() {
+                        Ok(event) => event,
+                        Err(e) => {
+                            warn!("Transaction contained malformed ephemeral event: {e}");
+                            continue;
+                        }
+                    };
+                    match event.room_id {
+                        Some(room_id) => {
+                            let membership = room_membership(
+                                &client,
+                                &room_id,
+                                user_localpart,
+                                &sender_localpart,
+                            )
+                            .await?;
+
+                            if membership == Some(MembershipState::Join) {
+                                let room = response.rooms.join.entry(room_id).or_default();
+                                room.ephemeral.events.push(raw_event.clone().cast());
+                            }
+                        }
+                        None if event.event_type == "m.presence" => {
+                            response.presence.events.push(raw_event.clone().cast());
+                        }
+                        None => debug!(
+                            event_type = event.event_type,
+                            "Transaction contained ephemeral event with no room ID"
+                        ),
+                    }
+                }
+
+                #[cfg(feature = "e2e-encryption")]
+                if let Some(device_id) = user_client.device_id() {
+                    add_encryption_data(&mut response, &transaction, user_id, device_id);
+                }
+
                 user_client.receive_transaction(&transaction.txn_id, response).await?;
                 Ok::<_, Error>(())
             });
 
             tasks.push(task);
         }
+        // Clients only remember the transactions they processed successfully,
+        // so if any of them failed the homeserver can retry the transaction
+        // and it is only pushed again to the clients that failed.
+        let mut result = Ok(());
         for task in tasks {
-            if let Err(e) = task.await {
-                warn!("Joining sync task failed: {e}");
+            match task.await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => {
+                    warn!("Pushing transaction to a client failed: {e}");
+                    result = result.and(Err(e));
+                }
+                Err(e) => {
+                    warn!("Joining sync task failed: {e}");
+                    result = result.and(Err(e.into()));
+                }
             }
         }
-        Ok(())
+        result
     }
 
     /// Convenience method that runs an http server.
@@ -537,28 +831,127 @@ impl AppService {
     }
 }
 
+/// Get the membership of the user with the given localpart in the given room,
+/// according to the store of the `sender_localpart` user.
+///
+/// The `sender_localpart` user is assumed to be in every known room.
+async fn room_membership(
+    client: &Client,
+    room_id: &RoomId,
+    user_localpart: &str,
+    sender_localpart: &str,
+) -> Result<Option<MembershipState>> {
+    let membership = match stored_membership(client, room_id, user_localpart).await? {
+        None if user_localpart == sender_localpart => Some(MembershipState::Join),
+        membership => membership,
+    };
+
+    Ok(membership)
+}
+
+/// Get the membership of the user with the given localpart in the given room,
+/// as stored in the store of the `sender_localpart` user when receiving
+/// transactions.
+pub(crate) async fn stored_membership(
+    client: &Client,
+    room_id: &RoomId,
+    user_localpart: &str,
+) -> Result<Option<MembershipState>> {
+    let key = [USER_MEMBER, room_id.as_bytes(), b".", user_localpart.as_bytes()].concat();
+    let value = client.store().get_custom_value(&key).await?;
+
+    Ok(value.and_then(|value| String::from_utf8(value).ok()).map(MembershipState::from))
+}
+
+/// Add the encryption data of the given transaction that is meant for the
+/// given device to its sync response, as described in [MSC2409] and
+/// [MSC3202].
+///
+/// [MSC2409]: https://github.com/matrix-org/matrix-spec-proposals/pull/2409
+/// [MSC3202]: https://github.com/matrix-org/matrix-spec-proposals/pull/3202
+#[cfg(feature = "e2e-encryption")]
+fn add_encryption_data(
+    response: &mut sync_events::v3::Response,
+    transaction: &push_events::v1::Request,
+    user_id: &UserId,
+    device_id: &DeviceId,
+) {
+    /// Helper type for extracting the recipient of a to-device event
+    #[derive(Debug, Deserialize)]
+    struct ToDeviceRecipient {
+        to_user_id: ruma::OwnedUserId,
+        to_device_id: ruma::OwnedDeviceId,
+    }
+
+    for raw_event in &transaction.to_device {
+        let recipient = match raw_event.deserialize_as::<ToDeviceRecipient>() {
+            Ok(recipient) => recipient,
+            Err(e) => {
+                warn!("Transaction contained to-device event with no valid recipient: {e}");
+                continue;
+            }
+        };
+        if recipient.to_user_id == user_id && recipient.to_device_id == device_id {
+            response.to_device.events.push(raw_event.clone());
+        }
+    }
+
+    // Device list changes concern all the users of the appservice.
+    response.device_lists.changed = transaction.device_lists.changed.clone();
+    response.device_lists.left = transaction.device_lists.left.clone();
+
+    if let Some(counts) = transaction
+        .device_one_time_keys_count
+        .get(user_id)
+        .and_then(|devices| devices.get(device_id))
+    {
+        response.device_one_time_keys_count = counts.clone();
+    }
+
+    response.device_unused_fallback_key_types = transaction
+        .device_unused_fallback_key_types
+        .get(user_id)
+        .and_then(|devices| devices.get(device_id))
+        .cloned();
+}
+
 #[cfg(test)]
 mod tests {
-    use std::sync::{Arc, Mutex};
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
 
     use http::{Method, Request};
     use hyper::Body;
     use matrix_sdk::{
         config::RequestConfig,
-        ruma::{api::appservice::Registration, events::room::member::OriginalSyncRoomMemberEvent},
-        Client, RoomMemberships, RoomState,
+        ruma::{
+            api::appservice::Registration,
+            events::{
+                presence::PresenceEvent, room::member::OriginalSyncRoomMemberEvent,
+                typing::SyncTypingEvent,
+            },
+        },
+        Client, Room, RoomMemberships, RoomState,
+    };
+    use matrix_sdk_test::{
+        appservice::TransactionBuilder, async_test, test_json::DEFAULT_SYNC_ROOM_ID,
+        EphemeralTestEvent, PresenceTestEvent, TimelineTestEvent,
     };
-    use matrix_sdk_test::{appservice::TransactionBuilder, async_test, TimelineTestEvent};
     use ruma::{
         api::{appservice::event::push_events, MatrixVersion},
-        events::AnyTimelineEvent,
-        room_id,
+        events::{room::message::RoomMessageEventContent, AnyTimelineEvent},
+        room_alias_id, room_id,
         serde::Raw,
+        thirdparty::{Location, User},
+        uint, user_id, MilliSecondsSinceUnixEpoch,
     };
     use serde_json::json;
+    use tokio::sync::Notify;
     use tower::{Service, ServiceExt};
     use wiremock::{
-        matchers::{body_json, header, method, path},
+        matchers::{body_json, header, method, path, path_regex, query_param},
         Mock, MockServer, ResponseTemplate,
     };
 
@@ -621,6 +1014,67 @@ mod tests {
         Ok(())
     }
 
+    #[async_test]
+    async fn test_virtual_user() -> Result<()> {
+        let server = MockServer::start().await;
+        let appservice = appservice(Some(server.uri()), None).await?;
+        let room_id = room_id!("!room:localhost");
+
+        Mock::given(method("POST"))
+            .and(path_regex(r"^/_matrix/client/r0/rooms/.*/join"))
+            .and(query_param("user_id", "@_appservice_alice:localhost"))
+            .and(header(
+                "authorization",
+                format!("Bearer {}", appservice.registration().as_token).as_str(),
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "room_id": room_id,
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let alice = appservice.virtual_user("_appservice_alice").await?;
+        assert_eq!(alice.join_room_by_id(room_id).await?, room_id.to_owned());
+        assert_eq!(alice.membership(room_id).await?, None);
+
+        // Only the client of the `sender_localpart` user was built.
+        assert_eq!(appservice.users().len(), 1);
+
+        Ok(())
+    }
+
+    #[async_test]
+    async fn test_virtual_user_send_message_at() -> Result<()> {
+        let server = MockServer::start().await;
+        let appservice = appservice(Some(server.uri()), None).await?;
+        let room_id = room_id!("!room:localhost");
+
+        Mock::given(method("PUT"))
+            .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/m.room.message/"))
+            .and(query_param("user_id", "@_appservice_alice:localhost"))
+            .and(query_param("ts", "1234"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "event_id": "$event:localhost",
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let alice = appservice.virtual_user("_appservice_alice").await?;
+        let event_id = alice
+            .send_message_at(
+                room_id,
+                RoomMessageEventContent::text_plain("Hello from the past"),
+                MilliSecondsSinceUnixEpoch(uint!(1234)),
+            )
+            .await?;
+
+        assert_eq!(event_id, "$event:localhost");
+
+        Ok(())
+    }
+
     #[async_test]
     async fn test_put_transaction() -> Result<()> {
         let uri = "/_matrix/app/v1/transactions/1?access_token=hs_token";
@@ -711,6 +1165,183 @@ mod tests {
             assert!(!on_room_member_called);
         }
 
+        let metrics = appservice.transaction_metrics();
+        assert_eq!(metrics.processed, 1);
+        assert_eq!(metrics.duplicates, 1);
+        assert_eq!(metrics.queued, 0);
+
+        Ok(())
+    }
+
+    #[async_test]
+    async fn test_retry_transaction_after_client_failure() -> Result<()> {
+        let member_event = |localpart: &str| {
+            Raw::new(&json!({
+                "content": {
+                    "membership": "join"
+                },
+                "event_id": format!("${localpart}:localhost"),
+                "origin_server_ts": 151800140,
+                "sender": format!("@{localpart}:localhost"),
+                "state_key": format!("@{localpart}:localhost"),
+                "type": "m.room.member",
+                "room_id": "!coolplace:localhost",
+            }))
+            .map(Raw::cast::<AnyTimelineEvent>)
+        };
+        let events = vec![member_event("_appservice_alice")?, member_event("_appservice_bob")?];
+
+        let appservice = appservice(None, None).await?;
+        let alice = appservice.user(Some("_appservice_alice")).await?;
+        let bob = appservice.user(Some("_appservice_bob")).await?;
+
+        let [alice_calls, bob_calls] = [&alice, &bob].map(|client| {
+            let calls = Arc::new(Mutex::new(0));
+            client.add_event_handler({
+                let calls = calls.clone();
+                move |_ev: OriginalSyncRoomMemberEvent| async move {
+                    *calls.lock().unwrap() += 1;
+                }
+            });
+            calls
+        });
+
+        // Corrupt the list of the transactions processed by Bob, so that his
+        // client fails to process the transaction.
+        bob.store()
+            .set_custom_value(b"appservice.transactions", b"corrupted".to_vec())
+            .await
+            .unwrap();
+
+        let transaction = push_events::v1::Request::new("1".into(), events);
+        appservice.receive_transaction(transaction.clone(), Default::default()).await.unwrap_err();
+
+        assert_eq!(*alice_calls.lock().unwrap(), 2);
+        assert_eq!(*bob_calls.lock().unwrap(), 0);
+        assert!(bob.get_room(room_id!("!coolplace:localhost")).is_none());
+
+        // The homeserver retries the transaction, it is only pushed again to
+        // Bob.
+        bob.store().remove_custom_value(b"appservice.transactions").await.unwrap();
+        appservice.receive_transaction(transaction.clone(), Default::default()).await?;
+
+        assert_eq!(*alice_calls.lock().unwrap(), 2);
+        assert_eq!(*bob_calls.lock().unwrap(), 2);
+        assert_eq!(
+            bob.get_room(room_id!("!coolplace:localhost")).unwrap().state(),
+            RoomState::Joined
+        );
+
+        // Now that every client processed it, the transaction is ignored.
+        appservice.receive_transaction(transaction, Default::default()).await?;
+
+        let metrics = appservice.transaction_metrics();
+        assert_eq!(metrics.failed, 1);
+        assert_eq!(metrics.processed, 1);
+        assert_eq!(metrics.duplicates, 1);
+
+        Ok(())
+    }
+
+    #[async_test]
+    async fn test_cancelled_transaction_is_processed() -> Result<()> {
+        let event = Raw::new(&json!({
+            "content": {
+                "membership": "join"
+            },
+            "event_id": "$_appservice_alice:localhost",
+            "origin_server_ts": 151800140,
+            "sender": "@_appservice_alice:localhost",
+            "state_key": "@_appservice_alice:localhost",
+            "type": "m.room.member",
+            "room_id": "!coolplace:localhost",
+        }))
+        .map(Raw::cast::<AnyTimelineEvent>)?;
+
+        let appservice = appservice(None, None).await?;
+        let alice = appservice.user(Some("_appservice_alice")).await?;
+
+        let calls = Arc::new(Mutex::new(0));
+        let entered = Arc::new(Notify::new());
+        let resume = Arc::new(Notify::new());
+        alice.add_event_handler({
+            let calls = calls.clone();
+            let entered = entered.clone();
+            let resume = resume.clone();
+            move |_ev: OriginalSyncRoomMemberEvent| {
+                let calls = calls.clone();
+                let entered = entered.clone();
+                let resume = resume.clone();
+                async move {
+                    entered.notify_one();
+                    resume.notified().await;
+                    *calls.lock().unwrap() += 1;
+                }
+            }
+        });
+
+        let transaction = push_events::v1::Request::new("1".into(), vec![event]);
+
+        // The homeserver gives up on the request while the transaction is
+        // being processed.
+        let request = tokio::spawn({
+            let appservice = appservice.clone();
+            let transaction = transaction.clone();
+            async move { appservice.receive_transaction(transaction, Default::default()).await }
+        });
+        entered.notified().await;
+        request.abort();
+        assert!(request.await.unwrap_err().is_cancelled());
+
+        // The processing of the transaction continues, and the retried
+        // transaction waits for it to complete.
+        resume.notify_one();
+        appservice.receive_transaction(transaction, Default::default()).await?;
+
+        assert_eq!(*calls.lock().unwrap(), 1);
+        let metrics = appservice.transaction_metrics();
+        assert_eq!(metrics.processed, 1);
+        assert_eq!(metrics.duplicates, 1);
+        assert_eq!(metrics.queued, 0);
+
+        Ok(())
+    }
+
+    #[async_test]
+    async fn test_put_transaction_with_full_queue() -> Result<()> {
+        let uri = "/_matrix/app/v1/transactions/1?access_token=hs_token";
+
+        let mut transaction_builder = TransactionBuilder::new();
+        transaction_builder.add_timeline_event(TimelineTestEvent::Member);
+        let transaction = transaction_builder.build_transaction();
+
+        let registration = AppServiceRegistration::try_from_yaml_str(registration_string())?;
+        let client_builder = Client::builder().server_versions([MatrixVersion::V1_0]);
+        let appservice = AppServiceBuilder::new(
+            "http://localhost:1234".parse()?,
+            "localhost".parse()?,
+            registration,
+        )
+        .client_builder(client_builder)
+        .transaction_queue_capacity(0)
+        .build()
+        .await?;
+
+        let response = appservice
+            .service()
+            .oneshot(
+                Request::builder()
+                    .method(Method::PUT)
+                    .uri(uri)
+                    .body(Body::from(transaction))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), 503);
+        assert_eq!(appservice.transaction_metrics().rejected, 1);
+
         Ok(())
     }
 
@@ -750,6 +1381,250 @@ mod tests {
         Ok(())
     }
 
+    #[async_test]
+    async fn test_get_thirdparty_location() -> Result<()> {
+        let appservice = appservice(None, None).await?;
+        let uri =
+            "/_matrix/app/v1/thirdparty/location?alias=%23magicforest:example.com&access_token=hs_token";
+
+        let response = appservice
+            .service()
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        // No handler was registered.
+        assert_eq!(response.status(), 404);
+
+        appservice
+            .register_location_by_alias_query(Box::new(|_, req| {
+                Box::pin(async move {
+                    vec![Location::new(req.alias, "forest".to_owned(), Default::default())]
+                })
+            }))
+            .await;
+
+        let response = appservice
+            .service()
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), 200);
+
+        let body = hyper::body::to_bytes(response.into_body()).await?;
+        let locations: Vec<Location> = serde_json::from_slice(&body)?;
+        assert_eq!(locations[0].alias.as_str(), "#magicforest:example.com");
+        assert_eq!(locations[0].protocol, "forest");
+
+        Ok(())
+    }
+
+    #[async_test]
+    async fn test_get_thirdparty_protocol() -> Result<()> {
+        let appservice = appservice(None, None).await?;
+        let uri = "/_matrix/app/v1/thirdparty/protocol/forest?access_token=hs_token";
+
+        let response = appservice
+            .service()
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        // No handler was registered.
+        assert_eq!(response.status(), 404);
+
+        let protocol = json!({
+            "user_fields": ["tree"],
+            "location_fields": ["clearing"],
+            "icon": "mxc://example.org/forest",
+            "field_types": {},
+            "instances": [],
+        });
+        appservice
+            .register_protocol_query(Box::new({
+                let protocol = protocol.clone();
+                move |_, req| {
+                    let protocol = protocol.clone();
+                    Box::pin(async move {
+                        (req.protocol == "forest")
+                            .then(|| serde_json::from_value(protocol).unwrap())
+                    })
+                }
+            }))
+            .await;
+
+        let response = appservice
+            .service()
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), 200);
+        let body = hyper::body::to_bytes(response.into_body()).await?;
+        assert_eq!(serde_json::from_slice::<serde_json::Value>(&body)?, protocol);
+
+        // The handler doesn't know this protocol.
+        let response = appservice
+            .service()
+            .oneshot(
+                Request::builder()
+                    .uri("/_matrix/app/v1/thirdparty/protocol/desert?access_token=hs_token")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), 404);
+
+        Ok(())
+    }
+
+    #[async_test]
+    async fn test_get_thirdparty_location_for_protocol() -> Result<()> {
+        let appservice = appservice(None, None).await?;
+        let uri =
+            "/_matrix/app/v1/thirdparty/location/forest?clearing=%23magic&access_token=hs_token";
+
+        let response = appservice
+            .service()
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        // No handler was registered.
+        assert_eq!(response.status(), 404);
+
+        appservice
+            .register_location_query(Box::new(|_, req| {
+                Box::pin(async move {
+                    if req.fields.get("clearing").map(String::as_str) != Some("#magic") {
+                        return Vec::new();
+                    }
+                    let alias = room_alias_id!("#magicforest:example.com").to_owned();
+                    vec![Location::new(alias, req.protocol, req.fields)]
+                })
+            }))
+            .await;
+
+        let response = appservice
+            .service()
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), 200);
+
+        let body = hyper::body::to_bytes(response.into_body()).await?;
+        let locations: Vec<Location> = serde_json::from_slice(&body)?;
+        assert_eq!(locations[0].alias.as_str(), "#magicforest:example.com");
+        assert_eq!(locations[0].protocol, "forest");
+        assert_eq!(locations[0].fields["clearing"], "#magic");
+
+        // No location matches these fields.
+        let response = appservice
+            .service()
+            .oneshot(
+                Request::builder()
+                    .uri("/_matrix/app/v1/thirdparty/location/forest?access_token=hs_token")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), 404);
+
+        Ok(())
+    }
+
+    #[async_test]
+    async fn test_get_thirdparty_user() -> Result<()> {
+        let appservice = appservice(None, None).await?;
+        let uri_by_protocol =
+            "/_matrix/app/v1/thirdparty/user/forest?tree=oak&access_token=hs_token";
+        let uri_by_id =
+            "/_matrix/app/v1/thirdparty/user?userid=%40_appservice_oak:localhost&access_token=hs_token";
+
+        for uri in [uri_by_protocol, uri_by_id] {
+            let response = appservice
+                .service()
+                .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+
+            // No handler was registered.
+            assert_eq!(response.status(), 404);
+        }
+
+        appservice
+            .register_thirdparty_user_query(Box::new(|_, req| {
+                Box::pin(async move {
+                    let user_id = user_id!("@_appservice_oak:localhost").to_owned();
+                    vec![User::new(user_id, req.protocol, req.fields)]
+                })
+            }))
+            .await;
+        appservice
+            .register_thirdparty_user_by_id_query(Box::new(|_, req| {
+                Box::pin(async move {
+                    let fields = [("tree".to_owned(), "oak".to_owned())].into();
+                    vec![User::new(req.userid, "forest".to_owned(), fields)]
+                })
+            }))
+            .await;
+
+        for uri in [uri_by_protocol, uri_by_id] {
+            let response = appservice
+                .service()
+                .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), 200);
+
+            let body = hyper::body::to_bytes(response.into_body()).await?;
+            let users: Vec<User> = serde_json::from_slice(&body)?;
+            assert_eq!(users[0].userid, "@_appservice_oak:localhost");
+            assert_eq!(users[0].protocol, "forest");
+            assert_eq!(users[0].fields["tree"], "oak");
+        }
+
+        Ok(())
+    }
+
+    #[async_test]
+    async fn test_ping() -> Result<()> {
+        let server = MockServer::start().await;
+        let appservice = appservice(Some(server.uri()), None).await?;
+
+        let response = appservice
+            .service()
+            .oneshot(
+                Request::builder()
+                    .method(Method::POST)
+                    .uri("/_matrix/app/v1/ping?access_token=hs_token")
+                    .body(Body::from(r#"{"transaction_id":"1"}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), 200);
+
+        Mock::given(method("POST"))
+            .and(path_regex(r"/appservice/appservice/ping$"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "duration_ms": 123 })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        assert_eq!(appservice.ping_homeserver().await?, Duration::from_millis(123));
+
+        Ok(())
+    }
+
     #[async_test]
     async fn test_invalid_access_token() -> Result<()> {
         let uri = "/_matrix/app/v1/transactions/1?access_token=invalid_token";
@@ -841,6 +1716,125 @@ mod tests {
         Ok(())
     }
 
+    #[async_test]
+    async fn test_ephemeral_event_handler() -> Result<()> {
+        let appservice = appservice(None, None).await?;
+
+        let typing = Arc::new(Mutex::new(Vec::new()));
+        let presence = Arc::new(Mutex::new(Vec::new()));
+        let client = appservice.user(None).await?;
+        client.add_event_handler({
+            let typing = typing.clone();
+            move |ev: SyncTypingEvent, room: Room| async move {
+                typing.lock().unwrap().push((room.room_id().to_owned(), ev.content.user_ids));
+            }
+        });
+        client.add_event_handler({
+            let presence = presence.clone();
+            move |ev: PresenceEvent| async move {
+                presence.lock().unwrap().push(ev.sender);
+            }
+        });
+
+        let uri = "/_matrix/app/v1/transactions/1?access_token=hs_token";
+
+        let mut transaction_builder = TransactionBuilder::new();
+        transaction_builder
+            .add_ephemeral_event(EphemeralTestEvent::Typing)
+            .add_presence_event(PresenceTestEvent::Presence);
+        let transaction = transaction_builder.build_transaction();
+
+        let response = appservice
+            .service()
+            .oneshot(
+                Request::builder()
+                    .method(Method::PUT)
+                    .uri(uri)
+                    .body(Body::from(transaction))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            *typing.lock().unwrap(),
+            [(
+                DEFAULT_SYNC_ROOM_ID.to_owned(),
+                vec![
+                    user_id!("@alice:matrix.org").to_owned(),
+                    user_id!("@bob:example.com").to_owned()
+                ]
+            )]
+        );
+        assert_eq!(*presence.lock().unwrap(), [user_id!("@example:localhost").to_owned()]);
+
+        Ok(())
+    }
+
+    #[async_test]
+    async fn test_ephemeral_events_only_for_room_members() -> Result<()> {
+        use matrix_sdk::ruma::events::receipt::SyncReceiptEvent;
+
+        let appservice = appservice(None, None).await?;
+
+        let received = Arc::new(Mutex::new(Vec::new()));
+        for localpart in [None, Some("_appservice_alice")] {
+            let client = appservice.user(localpart).await?;
+            client.add_event_handler({
+                let received = received.clone();
+                move |_ev: SyncTypingEvent| async move {
+                    received.lock().unwrap().push((localpart, "typing"));
+                }
+            });
+            client.add_event_handler({
+                let received = received.clone();
+                move |_ev: SyncReceiptEvent| async move {
+                    received.lock().unwrap().push((localpart, "receipt"));
+                }
+            });
+        }
+
+        let mut typing = EphemeralTestEvent::Typing.into_json_value();
+        typing["room_id"] = json!(DEFAULT_SYNC_ROOM_ID.to_owned());
+        let mut receipt = EphemeralTestEvent::ReadReceipt.into_json_value();
+        receipt["room_id"] = json!(DEFAULT_SYNC_ROOM_ID.to_owned());
+
+        // The unstable field is ignored when the stable one is used, since it
+        // contains the same events.
+        let transaction_1 = json!({
+            "events": [],
+            "ephemeral": [typing],
+            "de.sorunome.msc2409.ephemeral": [receipt.clone()],
+        });
+        // The unstable field is used when the stable one is missing.
+        let transaction_2 = json!({
+            "events": [],
+            "de.sorunome.msc2409.ephemeral": [receipt, { "malformed": true }],
+        });
+
+        for (txn_id, transaction) in [(1, transaction_1), (2, transaction_2)] {
+            let response = appservice
+                .service()
+                .oneshot(
+                    Request::builder()
+                        .method(Method::PUT)
+                        .uri(format!("/_matrix/app/v1/transactions/{txn_id}?access_token=hs_token"))
+                        .body(Body::from(serde_json::to_vec(&transaction)?))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), 200);
+        }
+
+        // Alice didn't join the room, so only the `sender_localpart` user,
+        // which is assumed to be in every room, gets the events.
+        assert_eq!(*received.lock().unwrap(), [(None, "typing"), (None, "receipt")]);
+
+        Ok(())
+    }
+
     #[async_test]
     async fn test_appservice_on_sub_path() -> Result<()> {
         let room_id = room_id!("!SVkFJHzfwvuaIEawgC:localhost");
@@ -972,7 +1966,10 @@ mod tests {
         let alice = appservice.user(Some("_appservice_alice")).await?;
         let bob = appservice.user(Some("_appservice_bob")).await?;
         appservice
-            .receive_transaction(push_events::v1::Request::new("dontcare".into(), json))
+            .receive_transaction(
+                push_events::v1::Request::new("dontcare".into(), json),
+                Default::default(),
+            )
             .await?;
         let coolplace = room_id!("!coolplace:localhost");
         let boringplace = room_id!("!boringplace:localhost");
@@ -991,10 +1988,181 @@ mod tests {
         Ok(())
     }
 
+    #[cfg(feature = "e2e-encryption")]
+    #[async_test]
+    async fn test_to_device_routing() -> Result<()> {
+        use matrix_sdk::ruma::events::dummy::ToDeviceDummyEvent;
+
+        let appservice = appservice(None, None).await?;
+        let uri = "/_matrix/app/v1/transactions/1?access_token=hs_token";
+
+        let received = Arc::new(Mutex::new(Vec::new()));
+        for (localpart, device_id) in
+            [("_appservice_alice", "ALICEDEVICE"), ("_appservice_bob", "BOBDEVICE")]
+        {
+            let client = appservice
+                .user_builder(localpart)
+                .device_id(Some(device_id.into()))
+                .build()
+                .await?;
+            client.add_event_handler({
+                let received = received.clone();
+                move |_ev: ToDeviceDummyEvent| async move {
+                    received.lock().unwrap().push(localpart);
+                }
+            });
+        }
+
+        let transaction = json!({
+            "events": [],
+            "de.sorunome.msc2409.to_device": [{
+                "type": "m.dummy",
+                "sender": "@someone:localhost",
+                "content": {},
+                "to_user_id": "@_appservice_alice:localhost",
+                "to_device_id": "ALICEDEVICE",
+            }],
+        });
+
+        let response = appservice
+            .service()
+            .oneshot(
+                Request::builder()
+                    .method(Method::PUT)
+                    .uri(uri)
+                    .body(Body::from(serde_json::to_vec(&transaction)?))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(*received.lock().unwrap(), ["_appservice_alice"]);
+
+        Ok(())
+    }
+
+    #[cfg(feature = "e2e-encryption")]
+    #[test]
+    fn test_add_encryption_data() {
+        use std::collections::BTreeMap;
+
+        use ruma::{api::client::sync::sync_events, device_id, uint, DeviceKeyAlgorithm};
+
+        use crate::add_encryption_data;
+
+        let alice = user_id!("@_appservice_alice:localhost");
+        let bob = user_id!("@_appservice_bob:localhost");
+        let alice_device = device_id!("ALICEDEVICE");
+
+        let mut transaction = push_events::v1::Request::new("1".into(), Vec::new());
+        transaction.to_device = vec![
+            Raw::new(&json!({
+                "type": "m.dummy",
+                "sender": "@someone:localhost",
+                "content": {},
+                "to_user_id": alice,
+                "to_device_id": alice_device,
+            }))
+            .unwrap()
+            .cast(),
+            // An event without a recipient is skipped.
+            Raw::new(&json!({
+                "type": "m.dummy",
+                "sender": "@someone:localhost",
+                "content": {},
+            }))
+            .unwrap()
+            .cast(),
+        ];
+        transaction.device_lists.changed = vec![user_id!("@changed:localhost").to_owned()];
+        transaction.device_lists.left = vec![user_id!("@left:localhost").to_owned()];
+        transaction.device_one_time_keys_count = BTreeMap::from([(
+            alice.to_owned(),
+            BTreeMap::from([(
+                alice_device.to_owned(),
+                BTreeMap::from([(DeviceKeyAlgorithm::SignedCurve25519, uint!(50))]),
+            )]),
+        )]);
+        transaction.device_unused_fallback_key_types = BTreeMap::from([(
+            alice.to_owned(),
+            BTreeMap::from([(alice_device.to_owned(), vec![DeviceKeyAlgorithm::SignedCurve25519])]),
+        )]);
+
+        // The recipient device gets its to-device events and key counts.
+        let mut response = sync_events::v3::Response::new("1".to_owned());
+        add_encryption_data(&mut response, &transaction, alice, alice_device);
+
+        assert_eq!(response.to_device.events.len(), 1);
+        assert_eq!(response.device_lists.changed, transaction.device_lists.changed);
+        assert_eq!(response.device_lists.left, transaction.device_lists.left);
+        assert_eq!(
+            response.device_one_time_keys_count,
+            BTreeMap::from([(DeviceKeyAlgorithm::SignedCurve25519, uint!(50))])
+        );
+        assert_eq!(
+            response.device_unused_fallback_key_types,
+            Some(vec![DeviceKeyAlgorithm::SignedCurve25519])
+        );
+
+        // The other devices only get the device list changes.
+        for (user_id, device_id) in [(alice, device_id!("OTHERDEVICE")), (bob, alice_device)] {
+            let mut response = sync_events::v3::Response::new("1".to_owned());
+            add_encryption_data(&mut response, &transaction, user_id, device_id);
+
+            assert!(response.to_device.events.is_empty());
+            assert_eq!(response.device_lists.changed, transaction.device_lists.changed);
+            assert_eq!(response.device_lists.left, transaction.device_lists.left);
+            assert!(response.device_one_time_keys_count.is_empty());
+            assert_eq!(response.device_unused_fallback_key_types, None);
+        }
+    }
+
+    #[cfg(feature = "e2e-encryption")]
+    #[async_test]
+    async fn test_assert_device_identity() -> Result<()> {
+        let server = MockServer::start().await;
+        let appservice = appservice(Some(server.uri()), None).await?;
+
+        // The keys of the new device are uploaded on behalf of the device.
+        Mock::given(method("POST"))
+            .and(path_regex(r"^/_matrix/client/.*/keys/upload"))
+            .and(query_param("user_id", "@_appservice_alice:localhost"))
+            .and(query_param("org.matrix.msc3202.device_id", "ALICEDEVICE"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "one_time_key_counts": {
+                    "signed_curve25519": 50
+                }
+            })))
+            .expect(1..)
+            .mount(&server)
+            .await;
+
+        appservice
+            .user_builder("_appservice_alice")
+            .device_id(Some("ALICEDEVICE".into()))
+            .assert_device_identity()
+            .build()
+            .await?;
+
+        appservice
+            .receive_transaction(
+                push_events::v1::Request::new("1".into(), Vec::new()),
+                Default::default(),
+            )
+            .await?;
+
+        server.verify().await;
+
+        Ok(())
+    }
+
     mod registration {
-        use ruma::api::appservice::Registration;
+        use ruma::{api::appservice::Registration, server_name};
 
-        use crate::{tests::registration_string, AppServiceRegistration, Result};
+        use crate::{
+            tests::registration_string, AppServiceRegistration, RegistrationIssue, Result,
+        };
 
         #[test]
         fn test_registration() -> Result<()> {
@@ -1024,5 +2192,61 @@ mod tests {
 
             Ok(())
         }
+
+        #[test]
+        fn test_registration_builder() -> Result<()> {
+            let registration =
+                AppServiceRegistration::builder("bridge", "http://localhost:9009", "_bridge_bot")
+                    .user_namespace("@_bridge_.*:localhost", true)
+                    .alias_namespace("#_bridge_.*:localhost", true)
+                    .rate_limited(false)
+                    .build();
+
+            assert_eq!(registration.as_token.len(), 64);
+            assert_ne!(registration.as_token, registration.hs_token);
+            assert!(registration.validate(server_name!("localhost")).is_empty());
+
+            let yaml = registration.to_yaml_string()?;
+            let parsed = AppServiceRegistration::try_from_yaml_str(yaml)?;
+
+            assert_eq!(parsed.id, "bridge");
+            assert_eq!(parsed.as_token, registration.as_token);
+            assert_eq!(parsed.hs_token, registration.hs_token);
+            assert_eq!(parsed.namespaces.users[0].regex, "@_bridge_.*:localhost");
+            assert_eq!(parsed.rate_limited, Some(false));
+
+            Ok(())
+        }
+
+        #[test]
+        fn test_registration_validation() {
+            let registration =
+                AppServiceRegistration::builder("bridge", "http://0.0.0.0:9009", "bot")
+                    .as_token("token")
+                    .hs_token("token")
+                    .user_namespace("@.*", true)
+                    .user_namespace("@.*", false)
+                    .room_namespace("[", false)
+                    .build();
+
+            let issues = registration.validate(server_name!("localhost"));
+
+            assert!(issues.contains(&RegistrationIssue::SameTokens));
+            assert!(issues.contains(&RegistrationIssue::UnreachableUrl {
+                url: "http://0.0.0.0:9009".to_owned()
+            }));
+            assert!(issues
+                .contains(&RegistrationIssue::LikelyOverlyBroadRegex { regex: "@.*".to_owned() }));
+            assert!(issues.contains(&RegistrationIssue::ConflictingExclusiveFlags {
+                regex: "@.*".to_owned()
+            }));
+            assert!(issues
+                .iter()
+                .any(|issue| matches!(issue, RegistrationIssue::InvalidRegex { regex, .. } if regex == "[")));
+            // The sender is covered by the overly broad regex.
+            assert!(!issues
+                .iter()
+                .any(|issue| matches!(issue, RegistrationIssue::SenderOutsideNamespaces { .. })));
+        }
     }
 }
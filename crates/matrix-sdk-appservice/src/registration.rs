@@ -14,14 +14,27 @@
 
 //! AppService Registration.
 
-use std::{fs::File, ops::Deref, path::PathBuf};
+use std::{fs::File, net::IpAddr, ops::Deref, path::PathBuf};
 
 use http::Uri;
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
 use regex::Regex;
-use ruma::api::appservice::Registration;
+use ruma::{
+    api::appservice::{Namespace, Namespaces, Registration, RegistrationInit},
+    ServerName,
+};
+use thiserror::Error;
 
 use crate::{Error, Result};
 
+/// The length of the tokens generated by [`AppServiceRegistrationBuilder`].
+const TOKEN_LENGTH: usize = 64;
+
+/// IDs that are not expected to be in the namespaces of an appservice.
+///
+/// A namespace regex that matches one of them is probably too broad.
+const REGULAR_IDS: &[&str] = &["@alice:example.org", "#room:example.org", "!room:example.org"];
+
 pub type Host = String;
 pub type Port = u16;
 
@@ -34,6 +47,22 @@ pub struct AppServiceRegistration {
 }
 
 impl AppServiceRegistration {
+    /// Create a new [`AppServiceRegistrationBuilder`].
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - A unique, user-defined ID of the appservice.
+    /// * `url` - The URL at which the homeserver can reach the appservice.
+    /// * `sender_localpart` - The localpart of the user associated with the
+    ///   appservice.
+    pub fn builder(
+        id: impl Into<String>,
+        url: impl Into<String>,
+        sender_localpart: impl Into<String>,
+    ) -> AppServiceRegistrationBuilder {
+        AppServiceRegistrationBuilder::new(id, url, sender_localpart)
+    }
+
     /// Try to load registration from yaml string
     ///
     /// See the fields of [`Registration`] for the required format
@@ -69,6 +98,285 @@ impl AppServiceRegistration {
 
         Ok((host, port))
     }
+
+    /// Serialize the registration to a yaml string, as expected by the
+    /// homeserver.
+    pub fn to_yaml_string(&self) -> Result<String> {
+        Ok(serde_yaml::to_string(&self.inner)?)
+    }
+
+    /// Write the registration to a yaml file, as expected by the homeserver.
+    pub fn write_yaml_file(&self, path: impl Into<PathBuf>) -> Result<()> {
+        let file = File::create(path.into())?;
+        serde_yaml::to_writer(file, &self.inner)?;
+
+        Ok(())
+    }
+
+    /// Check the registration for problems that would prevent the appservice
+    /// from working as expected.
+    ///
+    /// This should be called before starting the appservice. An empty list
+    /// means that no problem was found.
+    ///
+    /// The namespace checks are heuristics: regexes are not compared with each
+    /// other, so namespaces that overlap without having the same regex are not
+    /// reported, and a broad regex is only detected if it matches one of a few
+    /// sample IDs.
+    ///
+    /// # Arguments
+    ///
+    /// * `server_name` - The name of the homeserver, used to construct the user
+    ///   ID of the `sender_localpart`.
+    pub fn validate(&self, server_name: &ServerName) -> Vec<RegistrationIssue> {
+        let mut issues = Vec::new();
+        let namespaces = &self.inner.namespaces;
+
+        if self.inner.as_token.is_empty() || self.inner.hs_token.is_empty() {
+            issues.push(RegistrationIssue::EmptyToken);
+        } else if self.inner.as_token == self.inner.hs_token {
+            issues.push(RegistrationIssue::SameTokens);
+        }
+
+        match Uri::try_from(&self.inner.url) {
+            Ok(uri) if !matches!(uri.scheme_str(), Some("http" | "https")) => {
+                issues.push(RegistrationIssue::InvalidUrl {
+                    url: self.inner.url.clone(),
+                    error: "the scheme must be http or https".to_owned(),
+                });
+            }
+            Ok(uri) => {
+                // The homeserver can't connect to an unspecified address.
+                let host = uri.host().unwrap_or_default();
+                let host = host.trim_start_matches('[').trim_end_matches(']');
+                if host.parse::<IpAddr>().is_ok_and(|ip| ip.is_unspecified()) {
+                    issues.push(RegistrationIssue::UnreachableUrl { url: self.inner.url.clone() });
+                }
+            }
+            Err(e) => {
+                issues.push(RegistrationIssue::InvalidUrl {
+                    url: self.inner.url.clone(),
+                    error: e.to_string(),
+                });
+            }
+        }
+
+        let mut user_regexes = Vec::new();
+        for (list, is_users) in
+            [(&namespaces.users, true), (&namespaces.aliases, false), (&namespaces.rooms, false)]
+        {
+            for (i, namespace) in list.iter().enumerate() {
+                let conflicts = list[..i]
+                    .iter()
+                    .any(|n| n.regex == namespace.regex && n.exclusive != namespace.exclusive);
+                if conflicts {
+                    issues.push(RegistrationIssue::ConflictingExclusiveFlags {
+                        regex: namespace.regex.clone(),
+                    });
+                }
+
+                let regex = match Regex::new(&namespace.regex) {
+                    Ok(regex) => regex,
+                    Err(e) => {
+                        issues.push(RegistrationIssue::InvalidRegex {
+                            regex: namespace.regex.clone(),
+                            error: e.to_string(),
+                        });
+                        continue;
+                    }
+                };
+
+                if REGULAR_IDS.iter().any(|id| regex.is_match(id)) {
+                    issues.push(RegistrationIssue::LikelyOverlyBroadRegex {
+                        regex: namespace.regex.clone(),
+                    });
+                }
+
+                if is_users {
+                    user_regexes.push(regex);
+                }
+            }
+        }
+
+        let sender = format!("@{}:{server_name}", self.inner.sender_localpart);
+        if !user_regexes.iter().any(|regex| regex.is_match(&sender)) {
+            issues.push(RegistrationIssue::SenderOutsideNamespaces { user_id: sender });
+        }
+
+        issues
+    }
+}
+
+/// A problem found by [`AppServiceRegistration::validate()`].
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RegistrationIssue {
+    /// The `as_token` or the `hs_token` is empty.
+    #[error("the as_token and hs_token must not be empty")]
+    EmptyToken,
+
+    /// The `as_token` and the `hs_token` are the same.
+    #[error("the as_token and hs_token must be different")]
+    SameTokens,
+
+    /// The URL of the appservice is invalid.
+    #[error("invalid url '{url}': {error}")]
+    InvalidUrl {
+        /// The URL of the appservice.
+        url: String,
+        /// The reason why the URL is invalid.
+        error: String,
+    },
+
+    /// The URL of the appservice can't be reached by the homeserver.
+    #[error("the homeserver can't reach the url '{url}'")]
+    UnreachableUrl {
+        /// The URL of the appservice.
+        url: String,
+    },
+
+    /// A namespace regex is invalid.
+    #[error("invalid namespace regex '{regex}': {error}")]
+    InvalidRegex {
+        /// The regex of the namespace.
+        regex: String,
+        /// The reason why the regex is invalid.
+        error: String,
+    },
+
+    /// A namespace regex is likely too broad.
+    ///
+    /// This is a heuristic: the regex matches one of a few sample IDs that are
+    /// unlikely to belong to an appservice, like `@alice:example.org`. Broad
+    /// regexes that don't match these samples are not detected.
+    #[error("the namespace regex '{regex}' is likely too broad")]
+    LikelyOverlyBroadRegex {
+        /// The regex of the namespace.
+        regex: String,
+    },
+
+    /// Several namespaces use the same regex, with different exclusive flags.
+    ///
+    /// Only identical regexes are compared, namespaces whose regexes overlap
+    /// are not detected.
+    #[error("the namespace regex '{regex}' is both exclusive and non-exclusive")]
+    ConflictingExclusiveFlags {
+        /// The regex of the namespaces.
+        regex: String,
+    },
+
+    /// The user of the `sender_localpart` is not in the user namespaces.
+    ///
+    /// Homeservers let the appservice use this user anyway, but it can then be
+    /// claimed in the exclusive namespace of another appservice.
+    #[error("the sender '{user_id}' is not in the user namespaces")]
+    SenderOutsideNamespaces {
+        /// The user ID of the `sender_localpart`.
+        user_id: String,
+    },
+}
+
+/// Builder for an [`AppServiceRegistration`].
+#[derive(Debug, Clone)]
+pub struct AppServiceRegistrationBuilder {
+    id: String,
+    url: String,
+    sender_localpart: String,
+    as_token: Option<String>,
+    hs_token: Option<String>,
+    namespaces: Namespaces,
+    rate_limited: Option<bool>,
+    protocols: Option<Vec<String>>,
+}
+
+impl AppServiceRegistrationBuilder {
+    /// Create a new registration builder.
+    ///
+    /// See [`AppServiceRegistration::builder()`].
+    pub fn new(
+        id: impl Into<String>,
+        url: impl Into<String>,
+        sender_localpart: impl Into<String>,
+    ) -> Self {
+        Self {
+            id: id.into(),
+            url: url.into(),
+            sender_localpart: sender_localpart.into(),
+            as_token: None,
+            hs_token: None,
+            namespaces: Namespaces::new(),
+            rate_limited: None,
+            protocols: None,
+        }
+    }
+
+    /// Set the token used by the appservice to authenticate to the homeserver.
+    ///
+    /// A random token is generated if this is not set.
+    pub fn as_token(mut self, as_token: impl Into<String>) -> Self {
+        self.as_token = Some(as_token.into());
+        self
+    }
+
+    /// Set the token used by the homeserver to authenticate to the
+    /// appservice.
+    ///
+    /// A random token is generated if this is not set.
+    pub fn hs_token(mut self, hs_token: impl Into<String>) -> Self {
+        self.hs_token = Some(hs_token.into());
+        self
+    }
+
+    /// Add a namespace of user IDs.
+    pub fn user_namespace(mut self, regex: impl Into<String>, exclusive: bool) -> Self {
+        self.namespaces.users.push(Namespace::new(exclusive, regex.into()));
+        self
+    }
+
+    /// Add a namespace of room aliases.
+    pub fn alias_namespace(mut self, regex: impl Into<String>, exclusive: bool) -> Self {
+        self.namespaces.aliases.push(Namespace::new(exclusive, regex.into()));
+        self
+    }
+
+    /// Add a namespace of room IDs.
+    pub fn room_namespace(mut self, regex: impl Into<String>, exclusive: bool) -> Self {
+        self.namespaces.rooms.push(Namespace::new(exclusive, regex.into()));
+        self
+    }
+
+    /// Set whether requests from the users of the appservice are rate limited.
+    pub fn rate_limited(mut self, rate_limited: bool) -> Self {
+        self.rate_limited = Some(rate_limited);
+        self
+    }
+
+    /// Set the third party protocols the appservice provides.
+    pub fn protocols(mut self, protocols: Vec<String>) -> Self {
+        self.protocols = Some(protocols);
+        self
+    }
+
+    /// Build the registration.
+    pub fn build(self) -> AppServiceRegistration {
+        let registration: Registration = RegistrationInit {
+            id: self.id,
+            url: self.url,
+            as_token: self.as_token.unwrap_or_else(generate_token),
+            hs_token: self.hs_token.unwrap_or_else(generate_token),
+            sender_localpart: self.sender_localpart,
+            namespaces: self.namespaces,
+            rate_limited: self.rate_limited,
+            protocols: self.protocols,
+        }
+        .into();
+
+        registration.into()
+    }
+}
+
+fn generate_token() -> String {
+    thread_rng().sample_iter(Alphanumeric).take(TOKEN_LENGTH).map(char::from).collect()
 }
 
 impl From<Registration> for AppServiceRegistration {
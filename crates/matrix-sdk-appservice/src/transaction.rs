@@ -0,0 +1,168 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Bookkeeping of the transactions pushed by the homeserver.
+
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use ruma::{events::AnyEphemeralRoomEvent, serde::Raw};
+use serde::Deserialize;
+use tokio::sync::{Mutex, OwnedMutexGuard, OwnedSemaphorePermit, Semaphore};
+
+use crate::{Error, Result};
+
+/// The default number of transactions that can wait to be processed.
+pub(crate) const DEFAULT_QUEUE_CAPACITY: usize = 100;
+
+/// The fields of a transaction that are not supported by Ruma.
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct TransactionExtensions {
+    /// The ephemeral events of the transaction, as described in [MSC2409].
+    ///
+    /// Presence events are not room events, but they are sent in the same
+    /// list.
+    ///
+    /// [MSC2409]: https://github.com/matrix-org/matrix-spec-proposals/pull/2409
+    #[serde(default)]
+    ephemeral: Vec<Raw<AnyEphemeralRoomEvent>>,
+
+    /// The ephemeral events of the transaction, with the unstable prefix of
+    /// [MSC2409].
+    ///
+    /// [MSC2409]: https://github.com/matrix-org/matrix-spec-proposals/pull/2409
+    #[serde(default, rename = "de.sorunome.msc2409.ephemeral")]
+    unstable_ephemeral: Vec<Raw<AnyEphemeralRoomEvent>>,
+}
+
+impl TransactionExtensions {
+    /// The ephemeral events of the transaction, whether they were sent with
+    /// the stable or the unstable field name.
+    ///
+    /// Homeservers in transition may send the same events under both names,
+    /// the unstable field is only used if the stable one is empty.
+    pub(crate) fn ephemeral(&self) -> &[Raw<AnyEphemeralRoomEvent>] {
+        if self.ephemeral.is_empty() {
+            &self.unstable_ephemeral
+        } else {
+            &self.ephemeral
+        }
+    }
+}
+
+/// Metrics about the transactions pushed by the homeserver.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct TransactionMetrics {
+    /// The number of transactions that are waiting to be processed, or being
+    /// processed.
+    pub queued: usize,
+
+    /// The maximum number of transactions that can be queued.
+    ///
+    /// Transactions received while the queue is full are rejected, and will
+    /// be retried later by the homeserver.
+    pub capacity: usize,
+
+    /// The number of transactions that were processed successfully.
+    pub processed: u64,
+
+    /// The number of transactions that were ignored because they were already
+    /// processed.
+    pub duplicates: u64,
+
+    /// The number of transactions that couldn't be processed.
+    pub failed: u64,
+
+    /// The number of transactions that were rejected because the queue was
+    /// full.
+    pub rejected: u64,
+}
+
+/// A bounded queue making sure that transactions are processed one at a time,
+/// in the order in which they were received.
+#[derive(Debug)]
+pub(crate) struct TransactionQueue {
+    capacity: usize,
+    slots: Arc<Semaphore>,
+    // Tokio's mutex is fair, so the transactions are processed in the order in
+    // which they tried to acquire it.
+    processing: Arc<Mutex<()>>,
+    processed: AtomicU64,
+    duplicates: AtomicU64,
+    failed: AtomicU64,
+    rejected: AtomicU64,
+}
+
+impl TransactionQueue {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            slots: Arc::new(Semaphore::new(capacity)),
+            processing: Arc::new(Mutex::new(())),
+            processed: AtomicU64::new(0),
+            duplicates: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    /// Take a slot in the queue, and wait for the previous transactions to be
+    /// processed.
+    ///
+    /// The returned guard doesn't borrow the queue, so that it can be moved
+    /// into the task processing the transaction.
+    ///
+    /// Returns [`Error::TransactionQueueFull`] if there is no slot left.
+    pub(crate) async fn enter(&self) -> Result<QueueGuard> {
+        let Ok(slot) = self.slots.clone().try_acquire_owned() else {
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+            return Err(Error::TransactionQueueFull);
+        };
+        let processing = self.processing.clone().lock_owned().await;
+
+        Ok(QueueGuard { _slot: slot, _processing: processing })
+    }
+
+    pub(crate) fn record_processed(&self) {
+        self.processed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_duplicate(&self) {
+        self.duplicates.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_failed(&self) {
+        self.failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn metrics(&self) -> TransactionMetrics {
+        TransactionMetrics {
+            queued: self.capacity - self.slots.available_permits(),
+            capacity: self.capacity,
+            processed: self.processed.load(Ordering::Relaxed),
+            duplicates: self.duplicates.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// The right to process a transaction, given by [`TransactionQueue::enter`].
+pub(crate) struct QueueGuard {
+    _slot: OwnedSemaphorePermit,
+    _processing: OwnedMutexGuard<()>,
+}
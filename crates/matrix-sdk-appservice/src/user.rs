@@ -35,6 +35,8 @@ pub struct UserBuilder<'a> {
     device_id: Option<OwnedDeviceId>,
     client_builder: ClientBuilder,
     log_in: bool,
+    #[cfg(feature = "e2e-encryption")]
+    assert_device_identity: bool,
     restored_session: Option<Session>,
 }
 
@@ -50,6 +52,8 @@ impl<'a> UserBuilder<'a> {
             device_id: None,
             client_builder: Client::builder(),
             log_in: false,
+            #[cfg(feature = "e2e-encryption")]
+            assert_device_identity: false,
             restored_session: None,
         }
     }
@@ -75,6 +79,21 @@ impl<'a> UserBuilder<'a> {
         self
     }
 
+    /// Act as the device set with [`UserBuilder::device_id()`] in the
+    /// requests of the appservice user, as described in [MSC3202].
+    ///
+    /// This allows the appservice user to take part in encrypted rooms without
+    /// logging in: the keys of the device are uploaded when transactions are
+    /// received. The device must already exist on the homeserver, for example
+    /// by having been created with [`AppService::register_user()`].
+    ///
+    /// [MSC3202]: https://github.com/matrix-org/matrix-spec-proposals/pull/3202
+    #[cfg(feature = "e2e-encryption")]
+    pub fn assert_device_identity(mut self) -> Self {
+        self.assert_device_identity = true;
+        self
+    }
+
     /// Restore a persisted session
     ///
     /// This is primarily useful if you enable
@@ -107,6 +126,15 @@ impl<'a> UserBuilder<'a> {
             builder = builder.assert_identity();
         }
 
+        #[cfg(feature = "e2e-encryption")]
+        if !self.log_in && self.assert_device_identity {
+            if self.device_id.is_some() {
+                builder = builder.assert_device_identity();
+            } else {
+                warn!("Can't act as a device of '{user_id}' without a device id");
+            }
+        }
+
         let client = builder
             .homeserver_url(self.appservice.homeserver_url.clone())
             .appservice_mode()
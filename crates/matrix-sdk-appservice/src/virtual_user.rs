@@ -0,0 +1,161 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Lightweight appservice users.
+
+use std::fmt::Debug;
+
+use matrix_sdk::{config::RequestConfig, Client, HttpError};
+use ruma::{
+    api::{
+        client::{
+            membership::{join_room_by_id, leave_room},
+            message::send_message_event,
+            profile::set_display_name,
+        },
+        error::FromHttpResponseError,
+        OutgoingRequest,
+    },
+    assign,
+    events::{room::member::MembershipState, MessageLikeEventContent},
+    MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedRoomId, OwnedUserId, RoomId, TransactionId,
+    UserId,
+};
+
+use crate::{stored_membership, Result};
+
+/// A user in the namespaces of the appservice, on behalf of which requests
+/// can be sent.
+///
+/// Unlike the [`Client`]s built with [`AppService::user()`], a `VirtualUser`
+/// doesn't have its own HTTP client and store: its requests are sent by the
+/// client of the `sender_localpart` user with [identity assertion], and its
+/// state is loaded on demand from the store of that client. This makes it
+/// cheap to use a large number of virtual users.
+///
+/// A `VirtualUser` doesn't receive events. Event handlers should be added to
+/// the client of the `sender_localpart` user, which receives all the events of
+/// the appservice.
+///
+/// [`AppService::user()`]: crate::AppService::user
+/// [identity assertion]: https://spec.matrix.org/unstable/application-service-api/#identity-assertion
+#[derive(Debug, Clone)]
+pub struct VirtualUser {
+    client: Client,
+    user_id: OwnedUserId,
+}
+
+impl VirtualUser {
+    pub(crate) fn new(client: Client, user_id: OwnedUserId) -> Self {
+        Self { client, user_id }
+    }
+
+    /// The ID of the user.
+    pub fn user_id(&self) -> &UserId {
+        &self.user_id
+    }
+
+    /// The client of the `sender_localpart` user, which sends the requests of
+    /// this user.
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// Send an arbitrary request to the homeserver on behalf of this user.
+    ///
+    /// See [`Client::send_as()`].
+    pub async fn send<Request>(
+        &self,
+        request: Request,
+        config: Option<RequestConfig>,
+    ) -> Result<Request::IncomingResponse>
+    where
+        Request: OutgoingRequest + Debug,
+        HttpError: From<FromHttpResponseError<Request::EndpointError>>,
+    {
+        Ok(self.client.send_as(request, &self.user_id, config).await?)
+    }
+
+    /// Get the membership of this user in the given room, as known from the
+    /// transactions received by the appservice.
+    pub async fn membership(&self, room_id: &RoomId) -> Result<Option<MembershipState>> {
+        stored_membership(&self.client, room_id, self.user_id.localpart()).await
+    }
+
+    /// Join the room with the given ID.
+    pub async fn join_room_by_id(&self, room_id: &RoomId) -> Result<OwnedRoomId> {
+        let request = join_room_by_id::v3::Request::new(room_id.to_owned());
+        Ok(self.send(request, None).await?.room_id)
+    }
+
+    /// Leave the room with the given ID.
+    pub async fn leave_room(&self, room_id: &RoomId) -> Result<()> {
+        let request = leave_room::v3::Request::new(room_id.to_owned());
+        self.send(request, None).await?;
+        Ok(())
+    }
+
+    /// Set the display name of this user.
+    pub async fn set_display_name(&self, name: Option<&str>) -> Result<()> {
+        let request =
+            set_display_name::v3::Request::new(self.user_id.clone(), name.map(ToOwned::to_owned));
+        self.send(request, None).await?;
+        Ok(())
+    }
+
+    /// Send a message-like event to the given room.
+    ///
+    /// Returns the ID of the sent event.
+    pub async fn send_message(
+        &self,
+        room_id: &RoomId,
+        content: impl MessageLikeEventContent,
+    ) -> Result<OwnedEventId> {
+        self.send_message_inner(room_id, content, None).await
+    }
+
+    /// Send a message-like event to the given room, with the given
+    /// `origin_server_ts`.
+    ///
+    /// This uses [timestamp massaging], which is useful to import the history
+    /// of a bridged room.
+    ///
+    /// Returns the ID of the sent event.
+    ///
+    /// [timestamp massaging]: https://spec.matrix.org/v1.7/application-service-api/#timestamp-massaging
+    pub async fn send_message_at(
+        &self,
+        room_id: &RoomId,
+        content: impl MessageLikeEventContent,
+        timestamp: MilliSecondsSinceUnixEpoch,
+    ) -> Result<OwnedEventId> {
+        self.send_message_inner(room_id, content, Some(timestamp)).await
+    }
+
+    async fn send_message_inner(
+        &self,
+        room_id: &RoomId,
+        content: impl MessageLikeEventContent,
+        timestamp: Option<MilliSecondsSinceUnixEpoch>,
+    ) -> Result<OwnedEventId> {
+        let request = send_message_event::v3::Request::new(
+            room_id.to_owned(),
+            TransactionId::new(),
+            &content,
+        )?;
+        let request = assign!(request, { timestamp });
+
+        Ok(self.send(request, None).await?.event_id)
+    }
+}
@@ -23,10 +23,10 @@ use std::{
 use axum::{
     async_trait,
     body::{Bytes, HttpBody},
-    extract::{FromRequest, FromRequestParts, Path},
+    extract::{rejection::PathRejection, FromRequest, FromRequestParts, Path},
     middleware::{self, Next},
     response::{ErrorResponse, IntoResponse, Response},
-    routing::{future::RouteFuture, get, put},
+    routing::{future::RouteFuture, get, post, put},
     BoxError, Extension, Json, Router, ServiceExt,
 };
 use http::StatusCode;
@@ -64,6 +64,12 @@ where
             .route("/_matrix/app/v1/users/:user_id", get(handlers::user))
             .route("/_matrix/app/v1/rooms/:room_id", get(handlers::room))
             .route("/_matrix/app/v1/transactions/:txn_id", put(handlers::transaction))
+            .route("/_matrix/app/v1/ping", post(handlers::ping))
+            .route("/_matrix/app/v1/thirdparty/protocol/:protocol", get(handlers::protocol))
+            .route("/_matrix/app/v1/thirdparty/location/:protocol", get(handlers::location))
+            .route("/_matrix/app/v1/thirdparty/location", get(handlers::location_by_alias))
+            .route("/_matrix/app/v1/thirdparty/user/:protocol", get(handlers::thirdparty_user))
+            .route("/_matrix/app/v1/thirdparty/user", get(handlers::thirdparty_user_by_id))
             .route("/users/:user_id", get(handlers::user))
             .route("/rooms/:room_id", get(handlers::room))
             .route("/transactions/:txn_id", put(handlers::transaction))
@@ -138,25 +144,53 @@ where
 {
     type Rejection = Response;
 
+    async fn from_request(
+        req: http::request::Request<B>,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let MatrixRequestWithBody(request, _) =
+            MatrixRequestWithBody::from_request(req, state).await?;
+        Ok(Self(request))
+    }
+}
+
+/// A [`MatrixRequest`] that also keeps the body of the HTTP request, to read
+/// the fields that are not supported by Ruma.
+pub struct MatrixRequestWithBody<T>(T, Bytes);
+
+#[async_trait]
+impl<S, B, T> FromRequest<S, B> for MatrixRequestWithBody<T>
+where
+    S: Send + Sync,
+    B: HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+    T: IncomingRequest,
+{
+    type Rejection = Response;
+
     async fn from_request(
         req: http::request::Request<B>,
         state: &S,
     ) -> Result<Self, Self::Rejection> {
         let (mut parts, body) = req.into_parts();
-        let path_params = Path::<Vec<String>>::from_request_parts(&mut parts, state)
-            .await
-            .map_err(IntoResponse::into_response)?;
+        let path_params = match Path::<Vec<String>>::from_request_parts(&mut parts, state).await {
+            Ok(Path(path_params)) => path_params,
+            // The route doesn't have path parameters.
+            Err(PathRejection::MissingPathParams(_)) => Vec::new(),
+            Err(e) => return Err(e.into_response()),
+        };
         let bytes = Bytes::from_request(http::Request::new(body), state)
             .await
             .map_err(IntoResponse::into_response)?;
-        let http_request = http::Request::from_parts(parts, bytes);
+        let http_request = http::Request::from_parts(parts, bytes.clone());
 
         let request = T::try_from_http_request(http_request, &path_params).map_err(|_e| {
             // TODO: JSON error response
             StatusCode::BAD_REQUEST.into_response()
         })?;
 
-        Ok(Self(request))
+        Ok(Self(request, bytes))
     }
 }
 
@@ -165,12 +199,20 @@ mod handlers {
     use http::StatusCode;
     use ruma::api::appservice::{
         event::push_events,
+        ping::send_ping,
         query::{query_room_alias, query_user_id},
+        thirdparty::{
+            get_location_for_protocol, get_location_for_room_alias, get_protocol,
+            get_user_for_protocol, get_user_for_user_id,
+        },
     };
     use serde::Serialize;
+    use tracing::debug;
 
-    use super::{ErrorMessage, MatrixRequest};
-    use crate::AppService;
+    use super::{ErrorMessage, MatrixRequest, MatrixRequestWithBody};
+    use crate::{
+        event_handler::AppserviceFn, transaction::TransactionExtensions, AppService, Error,
+    };
 
     #[derive(Serialize)]
     struct EmptyObject {}
@@ -205,14 +247,103 @@ mod handlers {
         }
     }
 
+    pub async fn ping(
+        MatrixRequest(request): MatrixRequest<send_ping::v1::Request>,
+    ) -> impl IntoResponse {
+        debug!(transaction_id = ?request.transaction_id, "Received a ping from the homeserver");
+        Json(EmptyObject {})
+    }
+
+    pub async fn protocol(
+        Extension(appservice): Extension<AppService>,
+        MatrixRequest(request): MatrixRequest<get_protocol::v1::Request>,
+    ) -> impl IntoResponse {
+        let mut handler = appservice.event_handler.protocols.lock().await;
+        let Some(protocol) = handler.as_mut() else {
+            return Err(StatusCode::NOT_FOUND);
+        };
+
+        match protocol(appservice.clone(), request).await {
+            Some(protocol) => Ok(Json(protocol)),
+            None => Err(StatusCode::NOT_FOUND),
+        }
+    }
+
+    pub async fn location(
+        Extension(appservice): Extension<AppService>,
+        MatrixRequest(request): MatrixRequest<get_location_for_protocol::v1::Request>,
+    ) -> impl IntoResponse {
+        let mut handler = appservice.event_handler.locations.lock().await;
+        query_list(handler.as_mut(), &appservice, request).await
+    }
+
+    pub async fn location_by_alias(
+        Extension(appservice): Extension<AppService>,
+        MatrixRequest(request): MatrixRequest<get_location_for_room_alias::v1::Request>,
+    ) -> impl IntoResponse {
+        let mut handler = appservice.event_handler.locations_by_alias.lock().await;
+        query_list(handler.as_mut(), &appservice, request).await
+    }
+
+    pub async fn thirdparty_user(
+        Extension(appservice): Extension<AppService>,
+        MatrixRequest(request): MatrixRequest<get_user_for_protocol::v1::Request>,
+    ) -> impl IntoResponse {
+        let mut handler = appservice.event_handler.thirdparty_users.lock().await;
+        query_list(handler.as_mut(), &appservice, request).await
+    }
+
+    pub async fn thirdparty_user_by_id(
+        Extension(appservice): Extension<AppService>,
+        MatrixRequest(request): MatrixRequest<get_user_for_user_id::v1::Request>,
+    ) -> impl IntoResponse {
+        let mut handler = appservice.event_handler.thirdparty_users_by_id.lock().await;
+        query_list(handler.as_mut(), &appservice, request).await
+    }
+
+    /// Respond to a third party query with the results of the given handler.
+    ///
+    /// No result is reported as not found.
+    async fn query_list<A, R: Serialize>(
+        handler: Option<&mut AppserviceFn<A, Vec<R>>>,
+        appservice: &AppService,
+        request: A,
+    ) -> Result<Json<Vec<R>>, StatusCode> {
+        let Some(handler) = handler else {
+            return Err(StatusCode::NOT_FOUND);
+        };
+
+        let results = handler(appservice.clone(), request).await;
+        if results.is_empty() {
+            Err(StatusCode::NOT_FOUND)
+        } else {
+            Ok(Json(results))
+        }
+    }
+
     pub async fn transaction(
         appservice: Extension<AppService>,
-        MatrixRequest(request): MatrixRequest<push_events::v1::Request>,
+        MatrixRequestWithBody(request, body): MatrixRequestWithBody<push_events::v1::Request>,
     ) -> impl IntoResponse {
-        match appservice.receive_transaction(request).await {
+        let extensions = match serde_json::from_slice::<TransactionExtensions>(&body) {
+            Ok(extensions) => extensions,
+            Err(e) => {
+                let status_code = StatusCode::BAD_REQUEST;
+                return Err((
+                    status_code,
+                    Json(ErrorMessage { code: status_code.as_u16(), message: e.to_string() }),
+                ));
+            }
+        };
+
+        match appservice.receive_transaction(request, extensions).await {
             Ok(_) => Ok(Json(&EmptyObject {})),
             Err(e) => {
-                let status_code = StatusCode::INTERNAL_SERVER_ERROR;
+                let status_code = match e {
+                    // The homeserver will retry the transaction later.
+                    Error::TransactionQueueFull => StatusCode::SERVICE_UNAVAILABLE,
+                    _ => StatusCode::INTERNAL_SERVER_ERROR,
+                };
                 Err((
                     status_code,
                     Json(ErrorMessage { code: status_code.as_u16(), message: e.to_string() }),
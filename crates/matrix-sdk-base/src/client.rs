@@ -58,10 +58,12 @@ use crate::latest_event::{is_suitable_for_latest_event, PossibleLatestEvent};
 use crate::{
     deserialized_responses::{AmbiguityChanges, MembersResponse, SyncTimelineEvent},
     error::Result,
+    push::get_push_actions,
     rooms::{Room, RoomInfo, RoomState},
     store::{
-        ambiguity_map::AmbiguityCache, DynStateStore, MemoryStore, Result as StoreResult,
-        StateChanges, StateStoreDataKey, StateStoreDataValue, StateStoreExt, Store, StoreConfig,
+        ambiguity_map::AmbiguityCache, locks::StateStoreLock, DynStateStore, MemoryStore,
+        Result as StoreResult, StateChanges, StateStoreDataKey, StateStoreDataValue, StateStoreExt,
+        Store, StoreConfig,
     },
     sync::{JoinedRoom, LeftRoom, Rooms, SyncResponse, Timeline},
     RoomStateFilter, SessionMeta,
@@ -173,6 +175,25 @@ impl BaseClient {
         &*self.store
     }
 
+    /// Create a cross-process lock on the state store.
+    ///
+    /// See [`StateStoreLock`] for more details.
+    pub fn create_state_store_lock(&self, lock_key: String, lock_holder: String) -> StateStoreLock {
+        self.store.create_store_lock(lock_key, lock_holder)
+    }
+
+    /// Reload the rooms and the sync token from the state store if another
+    /// process wrote to it since `lock_holder` last held the cross-process
+    /// lock.
+    ///
+    /// This must be called right after acquiring the lock created with
+    /// [`BaseClient::create_state_store_lock`], before processing any
+    /// response, so this client doesn't overwrite the changes of the other
+    /// process with stale data. Returns whether anything was reloaded.
+    pub async fn reload_state_if_modified(&self, lock_holder: &str) -> StoreResult<bool> {
+        self.store.reload_if_modified_by_other_process(lock_holder).await
+    }
+
     /// Is the client logged in.
     pub fn logged_in(&self) -> bool {
         self.store.session_meta().is_some()
@@ -223,6 +244,21 @@ impl BaseClient {
         Ok(())
     }
 
+    /// Forget all the state loaded in memory by this client: the rooms, the
+    /// sync token and, if encryption is enabled, the `OlmMachine`.
+    ///
+    /// The stores are left untouched, this is meant to be used after they
+    /// were deleted. The session meta is kept, but the client shouldn't be
+    /// used anymore afterwards.
+    pub async fn clear_in_memory_state(&self) {
+        self.store.clear_in_memory_state().await;
+
+        #[cfg(feature = "e2e-encryption")]
+        {
+            *self.olm_machine.write().await = None;
+        }
+    }
+
     /// Get the current, if any, sync token of the client.
     /// This will be None if the client didn't sync at least once.
     pub async fn sync_token(&self) -> Option<String> {
@@ -397,13 +433,18 @@ impl BaseClient {
                     }
 
                     if let Some(context) = &push_context {
-                        let actions = push_rules.get_actions(&event.event, context);
+                        // Events that couldn't be decrypted can only match the
+                        // `.m.rule.encrypted` rule, so the user is still notified about them.
+                        let actions = get_push_actions(push_rules, &event.event, context)
+                            .unwrap_or_else(|| {
+                                push_rules.get_actions(&event.event, context).to_owned()
+                            });
 
                         if actions.iter().any(Action::should_notify) {
                             changes.add_notification(
                                 room.room_id(),
                                 Notification::new(
-                                    actions.to_owned(),
+                                    actions.clone(),
                                     event.event.clone(),
                                     false,
                                     room.room_id().to_owned(),
@@ -411,7 +452,7 @@ impl BaseClient {
                                 ),
                             );
                         }
-                        event.push_actions = actions.to_owned();
+                        event.push_actions = actions;
                     }
                 }
                 Err(e) => {
@@ -1110,7 +1151,11 @@ impl BaseClient {
                 let members = self.store.get_user_ids(room_id, filter).await?;
 
                 let settings = settings.ok_or(Error::EncryptionNotEnabled)?;
-                let settings = EncryptionSettings::new(settings, history_visibility, false);
+                let mut settings = EncryptionSettings::new(settings, history_visibility, false);
+                settings.error_on_identity_violation =
+                    o.store().get_error_on_identity_violation(room_id).await?;
+                settings.sharing_strategy =
+                    o.store().get_room_key_sharing_strategy(room_id).await?;
 
                 Ok(o.share_room_key(room_id, members.iter().map(Deref::deref), settings).await?)
             }
@@ -1298,10 +1343,11 @@ impl Default for BaseClient {
 mod tests {
     use matrix_sdk_test::{
         async_test, response_from_file, InvitedRoomBuilder, JoinedRoomBuilder, LeftRoomBuilder,
-        StrippedStateTestEvent, SyncResponseBuilder, TimelineTestEvent,
+        StateTestEvent, StrippedStateTestEvent, SyncResponseBuilder, TimelineTestEvent,
     };
     use ruma::{
         api::{client as api, IncomingResponse},
+        push::Action,
         room_id, user_id, RoomId, UserId,
     };
     use serde_json::json;
@@ -1511,6 +1557,52 @@ mod tests {
         client.get_room(room_id).expect("Just-created room not found!")
     }
 
+    #[async_test]
+    async fn test_undecryptable_event_notifies() {
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!test:example.org");
+
+        let client = logged_in_client(user_id).await;
+
+        let mut ev_builder = SyncResponseBuilder::new();
+        let response = ev_builder
+            .add_joined_room(
+                JoinedRoomBuilder::new(room_id)
+                    .add_state_event(StateTestEvent::Custom(json!({
+                        "content": {
+                            "displayname": "Alice",
+                            "membership": "join",
+                        },
+                        "event_id": "$member",
+                        "origin_server_ts": 1432135524678u64,
+                        "sender": user_id,
+                        "state_key": user_id,
+                        "type": "m.room.member",
+                    })))
+                    .add_state_event(StateTestEvent::PowerLevels)
+                    .add_timeline_event(TimelineTestEvent::Custom(json!({
+                        "content": {
+                            "algorithm": "m.megolm.v1.aes-sha2",
+                            "ciphertext": "AwgAEnAC",
+                            "device_id": "BOBDEVICE",
+                            "sender_key": "sender_key",
+                            "session_id": "session_id",
+                        },
+                        "event_id": "$encrypted",
+                        "origin_server_ts": 1432135524679u64,
+                        "sender": "@bob:example.org",
+                        "type": "m.room.encrypted",
+                    }))),
+            )
+            .build_sync_response();
+        let response = client.receive_sync_response(response).await.unwrap();
+
+        // The event matches the `.m.rule.encrypted` rule.
+        let event = &response.rooms.join[room_id].timeline.events[0];
+        assert!(event.push_actions.iter().any(Action::should_notify));
+        assert_eq!(response.notifications[room_id].len(), 1);
+    }
+
     #[async_test]
     async fn deserialization_failure_test() {
         let user_id = user_id!("@alice:example.org");
@@ -29,6 +29,7 @@ pub mod deserialized_responses;
 mod error;
 pub mod latest_event;
 pub mod media;
+pub mod push;
 mod rooms;
 #[cfg(feature = "experimental-sliding-sync")]
 mod sliding_sync;
@@ -0,0 +1,193 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Utilities to evaluate the push rules of the user on the device.
+
+use ruma::{
+    events::TimelineEventType,
+    push::{
+        Action, PredefinedContentRuleId, PredefinedOverrideRuleId, PushConditionRoomCtx, RuleKind,
+        Ruleset,
+    },
+    serde::Raw,
+};
+use serde::{de::IgnoredAny, Deserialize};
+
+/// Get the push actions for the given event, according to the given push rules
+/// and room context.
+///
+/// Returns `None` if the event is still encrypted, because the push rules can
+/// only be evaluated on its plaintext: the ciphertext only matches the
+/// `.m.rule.encrypted` rule, which notifies for every message.
+///
+/// If the event declares its mentions with an `m.mentions` property, the
+/// legacy mention rules are ignored, as described in the [intentional
+/// mentions] specification.
+///
+/// [intentional mentions]: https://spec.matrix.org/v1.7/client-server-api/#user-and-room-mentions
+pub fn get_push_actions<T>(
+    push_rules: &Ruleset,
+    event: &Raw<T>,
+    push_context: &PushConditionRoomCtx,
+) -> Option<Vec<Action>> {
+    if is_encrypted(event) {
+        return None;
+    }
+
+    if !has_intentional_mentions(event) {
+        return Some(push_rules.get_actions(event, push_context).to_owned());
+    }
+
+    let mut push_rules = push_rules.clone();
+
+    #[allow(deprecated)]
+    {
+        _ = push_rules.set_enabled(
+            RuleKind::Override,
+            PredefinedOverrideRuleId::ContainsDisplayName,
+            false,
+        );
+        _ = push_rules.set_enabled(RuleKind::Override, PredefinedOverrideRuleId::RoomNotif, false);
+        _ = push_rules.set_enabled(
+            RuleKind::Content,
+            PredefinedContentRuleId::ContainsUserName,
+            false,
+        );
+    }
+
+    Some(push_rules.get_actions(event, push_context).to_owned())
+}
+
+/// Whether the given event is still encrypted.
+fn is_encrypted<T>(event: &Raw<T>) -> bool {
+    event
+        .get_field::<TimelineEventType>("type")
+        .ok()
+        .flatten()
+        .is_some_and(|event_type| event_type == TimelineEventType::RoomEncrypted)
+}
+
+/// Whether the content of the given event has an `m.mentions` property.
+fn has_intentional_mentions<T>(event: &Raw<T>) -> bool {
+    #[derive(Deserialize)]
+    struct EventMentions {
+        content: ContentMentions,
+    }
+
+    #[derive(Deserialize)]
+    struct ContentMentions {
+        #[serde(rename = "m.mentions")]
+        mentions: Option<IgnoredAny>,
+    }
+
+    event.deserialize_as::<EventMentions>().is_ok_and(|event| event.content.mentions.is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use ruma::{
+        events::room::power_levels::NotificationPowerLevels,
+        int,
+        push::{PushConditionRoomCtx, Ruleset},
+        room_id,
+        serde::Raw,
+        uint, user_id,
+    };
+    use serde_json::json;
+
+    use super::get_push_actions;
+
+    fn push_rules_and_context() -> (Ruleset, PushConditionRoomCtx) {
+        let user_id = user_id!("@alice:example.org");
+        let push_context = PushConditionRoomCtx {
+            room_id: room_id!("!room:example.org").to_owned(),
+            member_count: uint!(3),
+            user_id: user_id.to_owned(),
+            user_display_name: "Alice".to_owned(),
+            users_power_levels: BTreeMap::new(),
+            default_power_level: int!(0),
+            notification_power_levels: NotificationPowerLevels::new(),
+        };
+
+        (Ruleset::server_default(user_id), push_context)
+    }
+
+    fn message(content: serde_json::Value) -> Raw<serde_json::Value> {
+        Raw::new(&json!({
+            "type": "m.room.message",
+            "event_id": "$message",
+            "room_id": "!room:example.org",
+            "sender": "@bob:example.org",
+            "origin_server_ts": 1,
+            "content": content,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_encrypted_event_has_no_push_actions() {
+        let (push_rules, push_context) = push_rules_and_context();
+        let event = Raw::new(&json!({
+            "type": "m.room.encrypted",
+            "event_id": "$encrypted",
+            "room_id": "!room:example.org",
+            "sender": "@bob:example.org",
+            "origin_server_ts": 1,
+            "content": {
+                "algorithm": "m.megolm.v1.aes-sha2",
+                "ciphertext": "AwgAEnAC",
+                "device_id": "BOBDEVICE",
+                "sender_key": "sender_key",
+                "session_id": "session_id",
+            },
+        }))
+        .unwrap();
+
+        assert!(get_push_actions(&push_rules, &event, &push_context).is_none());
+    }
+
+    #[test]
+    fn test_legacy_mention() {
+        let (push_rules, push_context) = push_rules_and_context();
+        let event = message(json!({ "msgtype": "m.text", "body": "Hello Alice" }));
+
+        let actions = get_push_actions(&push_rules, &event, &push_context).unwrap();
+        assert!(actions.iter().any(|a| a.is_highlight()));
+    }
+
+    #[test]
+    fn test_intentional_mentions() {
+        let (push_rules, push_context) = push_rules_and_context();
+
+        // The display name in the body is not a mention if the event has `m.mentions`.
+        let event = message(json!({
+            "msgtype": "m.text",
+            "body": "Hello Alice",
+            "m.mentions": {},
+        }));
+        let actions = get_push_actions(&push_rules, &event, &push_context).unwrap();
+        assert!(!actions.iter().any(|a| a.is_highlight()));
+
+        // The user is mentioned in `m.mentions`.
+        let event = message(json!({
+            "msgtype": "m.text",
+            "body": "Hello",
+            "m.mentions": { "user_ids": ["@alice:example.org"] },
+        }));
+        let actions = get_push_actions(&push_rules, &event, &push_context).unwrap();
+        assert!(actions.iter().any(|a| a.is_highlight()));
+    }
+}
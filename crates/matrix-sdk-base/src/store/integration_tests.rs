@@ -32,9 +32,11 @@ use serde_json::{json, value::Value as JsonValue};
 
 use super::DynStateStore;
 use crate::{
-    deserialized_responses::MemberEvent,
+    deserialized_responses::{MemberEvent, RawAnySyncOrStrippedState},
     media::{MediaFormat, MediaRequest, MediaThumbnailSize},
-    store::{Result, StateStoreExt},
+    store::{
+        custom_value_keys, migrate_state_store, IntoStateStore, MemoryStore, Result, StateStoreExt,
+    },
     RoomInfo, RoomMemberships, RoomState, StateChanges, StateStoreDataKey, StateStoreDataValue,
 };
 
@@ -77,6 +79,12 @@ pub trait StateStoreIntegrationTests {
     async fn test_presence_saving(&self);
     /// Test display names saving.
     async fn test_display_names_saving(&self);
+    /// Test listing all the events of the store.
+    async fn test_get_all_events(&self) -> Result<()>;
+    /// Test migrating the content of another store into this store.
+    async fn test_migration(&self) -> Result<()>;
+    /// Test taking and extending leases of cross-process locks.
+    async fn test_lease_locks(&self) -> Result<()>;
 }
 
 #[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
@@ -1122,6 +1130,187 @@ impl StateStoreIntegrationTests for DynStateStore {
         let names = self.get_users_with_display_names(room_id, &[]).await;
         assert!(names.unwrap().is_empty());
     }
+
+    async fn test_get_all_events(&self) -> Result<()> {
+        let room_id = room_id();
+        let stripped_room_id = stripped_room_id();
+        let user_id = user_id();
+        self.populate().await?;
+
+        let state = self.get_all_state_events(room_id).await?;
+        assert_eq!(state.len(), 4, "Expected to find the name, topic and 2 member events");
+        assert!(state.iter().all(|e| matches!(e, RawAnySyncOrStrippedState::Sync(_))));
+
+        let stripped_state = self.get_all_state_events(stripped_room_id).await?;
+        assert_eq!(stripped_state.len(), 2, "Expected to find the name and member events");
+        assert!(stripped_state.iter().all(|e| matches!(e, RawAnySyncOrStrippedState::Stripped(_))));
+
+        let account_data = self.get_all_account_data_events().await?;
+        assert_eq!(account_data.len(), 1);
+        assert_eq!(account_data[0].get_field::<String>("type")?.as_deref(), Some("m.push_rules"));
+
+        let room_account_data = self.get_all_room_account_data_events(room_id).await?;
+        assert_eq!(room_account_data.len(), 1);
+        assert_eq!(room_account_data[0].get_field::<String>("type")?.as_deref(), Some("m.tag"));
+        assert!(self.get_all_room_account_data_events(stripped_room_id).await?.is_empty());
+
+        let receipts = self.get_room_receipt_events(room_id, ReceiptType::Read).await?;
+        assert_eq!(receipts.len(), 1);
+        let (receipt_user_id, receipt_event_id, receipt) = &receipts[0];
+        assert_eq!(receipt_user_id, user_id);
+        assert_eq!(receipt_event_id, first_receipt_event_id());
+        assert_matches!(receipt.thread, ReceiptThread::Unthreaded);
+        assert!(self.get_room_receipt_events(room_id, ReceiptType::ReadPrivate).await?.is_empty());
+
+        Ok(())
+    }
+
+    async fn test_migration(&self) -> Result<()> {
+        let room_id = room_id();
+        let stripped_room_id = stripped_room_id();
+        let user_id = user_id();
+
+        let source = MemoryStore::new().into_state_store();
+        source.populate().await?;
+
+        // Events of types that the SDK doesn't know about are copied too.
+        let mut changes = StateChanges::default();
+        let custom_state: Raw<AnySyncStateEvent> = Raw::new(&json!({
+            "type": "org.example.custom_state",
+            "state_key": "custom",
+            "event_id": "$custom_state",
+            "sender": user_id,
+            "origin_server_ts": 1,
+            "content": { "custom": true },
+        }))?
+        .cast();
+        changes
+            .state
+            .entry(room_id.to_owned())
+            .or_default()
+            .entry("org.example.custom_state".into())
+            .or_default()
+            .insert("custom".to_owned(), custom_state);
+        let custom_account_data: Raw<AnyRoomAccountDataEvent> = Raw::new(&json!({
+            "type": "org.example.custom_account_data",
+            "content": { "custom": true },
+        }))?
+        .cast();
+        changes
+            .room_account_data
+            .entry(room_id.to_owned())
+            .or_default()
+            .insert("org.example.custom_account_data".into(), custom_account_data);
+        source.save_changes(&changes).await?;
+
+        source
+            .set_kv_data(
+                StateStoreDataKey::UserAvatarUrl(user_id),
+                StateStoreDataValue::UserAvatarUrl("mxc://localhost/avatar".to_owned()),
+            )
+            .await?;
+        let membership_key = [
+            custom_value_keys::APPSERVICE_USER_MEMBERSHIP,
+            room_id.as_bytes(),
+            b".",
+            user_id.localpart().as_bytes(),
+        ]
+        .concat();
+        source.set_custom_value(&membership_key, b"join".to_vec()).await?;
+        source.set_custom_value(custom_value_keys::PROCESSED_TRANSACTIONS, b"[]".to_vec()).await?;
+
+        let report = migrate_state_store(&source, self, |_, _, _| {}).await?;
+        assert_eq!(report.rooms, 2);
+        assert_eq!(report.state_events, 7);
+        assert_eq!(report.receipts, 1);
+        assert_eq!(report.account_data, 3);
+        assert_eq!(report.presence, 1);
+        assert_eq!(report.avatar_urls, 1);
+        assert_eq!(report.custom_values, 2);
+
+        assert_eq!(
+            self.get_kv_data(StateStoreDataKey::UserAvatarUrl(user_id))
+                .await?
+                .and_then(|v| v.into_user_avatar_url())
+                .as_deref(),
+            Some("mxc://localhost/avatar")
+        );
+        assert_eq!(self.get_custom_value(&membership_key).await?.as_deref(), Some(&b"join"[..]));
+        assert!(self.get_custom_value(custom_value_keys::PROCESSED_TRANSACTIONS).await?.is_some());
+
+        assert!(self
+            .get_state_event(room_id, "org.example.custom_state".into(), "custom")
+            .await?
+            .is_some());
+        assert!(self
+            .get_room_account_data_event(room_id, "org.example.custom_account_data".into())
+            .await?
+            .is_some());
+
+        assert!(self.get_kv_data(StateStoreDataKey::SyncToken).await?.is_some());
+        assert!(self
+            .get_account_data_event(GlobalAccountDataEventType::PushRules)
+            .await?
+            .is_some());
+        assert!(self.get_presence_event(user_id).await?.is_some());
+
+        assert_eq!(self.get_room_infos().await?.len(), 2);
+        assert!(self.get_state_event(room_id, StateEventType::RoomName, "").await?.is_some());
+        assert!(self
+            .get_room_account_data_event(room_id, RoomAccountDataEventType::Tag)
+            .await?
+            .is_some());
+        assert_eq!(self.get_user_ids(room_id, RoomMemberships::empty()).await?.len(), 2);
+        assert!(self.get_profile(room_id, user_id).await?.is_some());
+        assert_eq!(self.get_users_with_display_name(room_id, "example").await?.len(), 2);
+        assert!(self
+            .get_user_room_receipt_event(
+                room_id,
+                ReceiptType::Read,
+                ReceiptThread::Unthreaded,
+                user_id
+            )
+            .await?
+            .is_some());
+
+        assert!(self
+            .get_state_event(stripped_room_id, StateEventType::RoomName, "")
+            .await?
+            .is_some());
+        assert_eq!(
+            self.get_user_ids(stripped_room_id, RoomMemberships::empty()).await?,
+            vec![user_id.to_owned()]
+        );
+
+        Ok(())
+    }
+
+    async fn test_lease_locks(&self) -> Result<()> {
+        let acquired0 = self.try_take_leased_lock(0, "key", "alice").await?;
+        assert!(acquired0);
+
+        // Should extend the lease automatically (same holder).
+        let acquired2 = self.try_take_leased_lock(300, "key", "alice").await?;
+        assert!(acquired2);
+
+        // Should extend the lease automatically (same holder + time is ok).
+        let acquired3 = self.try_take_leased_lock(300, "key", "alice").await?;
+        assert!(acquired3);
+
+        // Another attempt at taking the lock should fail, because it's taken.
+        let acquired4 = self.try_take_leased_lock(300, "key", "bob").await?;
+        assert!(!acquired4);
+
+        // Even if we insist.
+        let acquired5 = self.try_take_leased_lock(300, "key", "bob").await?;
+        assert!(!acquired5);
+
+        // A lock on another key is independent.
+        let acquired6 = self.try_take_leased_lock(300, "other_key", "bob").await?;
+        assert!(acquired6);
+
+        Ok(())
+    }
 }
 
 /// Macro building to allow your StateStore implementation to run the entire
@@ -1258,6 +1447,24 @@ macro_rules! statestore_integration_tests {
             let store = get_store().await.expect("creating store failed").into_state_store();
             store.test_display_names_saving().await;
         }
+
+        #[async_test]
+        async fn test_get_all_events() -> StoreResult<()> {
+            let store = get_store().await?.into_state_store();
+            store.test_get_all_events().await
+        }
+
+        #[async_test]
+        async fn test_migration() -> StoreResult<()> {
+            let store = get_store().await?.into_state_store();
+            store.test_migration().await
+        }
+
+        #[async_test]
+        async fn test_lease_locks() -> StoreResult<()> {
+            let store = get_store().await?.into_state_store();
+            store.test_lease_locks().await
+        }
     };
 }
 
@@ -0,0 +1,107 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A cross-process lock implemented on top of the state store.
+//!
+//! This lock may be used when multiple processes share the same state store,
+//! for instance the main app and a notification extension, to make sure that
+//! only one of them processes sync responses at a time. Writes of two
+//! interleaved sync responses would otherwise leave the room infos or the
+//! sliding sync caches in an inconsistent state.
+//!
+//! It works like the crypto store lock: the lock is a time-based lease on a
+//! value of the state store, see `StateStore::try_take_leased_lock`, and the
+//! lease handling is shared with it, see [`matrix_sdk_common::store_locks`].
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use matrix_sdk_common::store_locks::{
+    BackingStore, CrossProcessStoreLock, CrossProcessStoreLockGuard, LockTimeoutError,
+};
+
+use super::{DynStateStore, StoreError};
+
+/// A guard on the state store lock.
+///
+/// The lock will be automatically released a short period of time after all the
+/// guards have dropped.
+pub type StateStoreLockGuard = CrossProcessStoreLockGuard;
+
+/// A store-based lock for the `StateStore`.
+pub type StateStoreLock = CrossProcessStoreLock<LockableStateStore>;
+
+/// A state store, used as the backing store of a [`StateStoreLock`].
+#[derive(Clone, Debug)]
+pub struct LockableStateStore(pub(crate) Arc<DynStateStore>);
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl BackingStore for LockableStateStore {
+    type LockError = StoreError;
+
+    async fn try_lock(
+        &self,
+        lease_duration_ms: u32,
+        key: &str,
+        holder: &str,
+    ) -> Result<bool, Self::LockError> {
+        self.0.try_take_leased_lock(lease_duration_ms, key, holder).await
+    }
+}
+
+impl From<LockTimeoutError> for StoreError {
+    fn from(_: LockTimeoutError) -> Self {
+        Self::LockTimeout
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+    use matrix_sdk_test::async_test;
+    use tokio::{
+        spawn,
+        time::{sleep, Duration},
+    };
+
+    use super::{LockableStateStore, StateStoreLock};
+    use crate::store::{IntoStateStore as _, MemoryStore, Result, StoreError};
+
+    #[async_test]
+    async fn test_multiple_processes() -> Result<()> {
+        let store = LockableStateStore(MemoryStore::new().into_state_store());
+
+        let lock1 = StateStoreLock::new(store.clone(), "key".to_owned(), "first".to_owned());
+        let lock2 = StateStoreLock::new(store, "key".to_owned(), "second".to_owned());
+
+        // When the first process takes the lock, the second can't take it.
+        let acquired1 = lock1.try_lock_once().await?;
+        assert!(acquired1.is_some());
+        assert!(lock2.try_lock_once().await?.is_none());
+
+        let lock2_clone = lock2.clone();
+        let handle = spawn(async move { lock2_clone.spin_lock(Some(1000)).await });
+
+        sleep(Duration::from_millis(100)).await;
+        drop(acquired1);
+
+        // The second process gets the lock once the first one released it.
+        let _acquired2 = handle.await.expect("join handle is properly awaited")?;
+
+        assert_matches!(lock1.spin_lock(Some(200)).await, Err(StoreError::LockTimeout));
+
+        Ok(())
+    }
+}
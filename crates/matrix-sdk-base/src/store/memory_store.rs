@@ -16,6 +16,7 @@ use std::{
     collections::{BTreeMap, BTreeSet},
     iter,
     sync::RwLock,
+    time::Duration,
 };
 
 use async_trait::async_trait;
@@ -73,6 +74,7 @@ pub struct MemoryStore {
         DashMap<(String, Option<String>), DashMap<OwnedEventId, DashMap<OwnedUserId, Receipt>>>,
     >,
     custom: DashMap<Vec<u8>, Vec<u8>>,
+    leases: DashMap<String, (String, Instant)>,
 }
 
 impl Default for MemoryStore {
@@ -102,6 +104,7 @@ impl MemoryStore {
             room_user_receipts: Default::default(),
             room_event_receipts: Default::default(),
             custom: Default::default(),
+            leases: Default::default(),
         }
     }
 
@@ -590,6 +593,24 @@ impl MemoryStore {
 
         Ok(())
     }
+
+    fn try_take_leased_lock(&self, lease_duration_ms: u32, key: &str, holder: &str) -> bool {
+        let now = Instant::now();
+        let expiration = now + Duration::from_millis(lease_duration_ms.into());
+
+        match self.leases.get_mut(key) {
+            // We had the lease before, or the previous one expired: take it.
+            Some(mut prev) if prev.0 == holder || prev.1 < now => {
+                *prev = (holder.to_owned(), expiration);
+                true
+            }
+            Some(_) => false,
+            None => {
+                self.leases.insert(key.to_owned(), (holder.to_owned(), expiration));
+                true
+            }
+        }
+    }
 }
 
 #[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
@@ -760,6 +781,72 @@ impl StateStore for MemoryStore {
         self.get_event_room_receipt_events(room_id, receipt_type, thread, event_id).await
     }
 
+    async fn get_all_state_events(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<RawAnySyncOrStrippedState>> {
+        let mut events = Vec::new();
+
+        if let Some(state) = self.room_state.get(room_id) {
+            events.extend(state.iter().flat_map(|events| {
+                events
+                    .iter()
+                    .map(|e| RawAnySyncOrStrippedState::Sync(e.clone()))
+                    .collect::<Vec<_>>()
+            }));
+        }
+
+        if let Some(state) = self.stripped_room_state.get(room_id) {
+            events.extend(state.iter().flat_map(|events| {
+                events
+                    .iter()
+                    .map(|e| RawAnySyncOrStrippedState::Stripped(e.clone()))
+                    .collect::<Vec<_>>()
+            }));
+        }
+
+        Ok(events)
+    }
+
+    async fn get_all_account_data_events(&self) -> Result<Vec<Raw<AnyGlobalAccountDataEvent>>> {
+        Ok(self.account_data.iter().map(|e| e.value().clone()).collect())
+    }
+
+    async fn get_all_room_account_data_events(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<Raw<AnyRoomAccountDataEvent>>> {
+        Ok(self
+            .room_account_data
+            .get(room_id)
+            .map(|events| events.iter().map(|e| e.value().clone()).collect())
+            .unwrap_or_default())
+    }
+
+    async fn get_room_receipt_events(
+        &self,
+        room_id: &RoomId,
+        receipt_type: ReceiptType,
+    ) -> Result<Vec<(OwnedUserId, OwnedEventId, Receipt)>> {
+        let receipt_type = receipt_type.to_string();
+
+        Ok(self
+            .room_user_receipts
+            .get(room_id)
+            .map(|receipts| {
+                receipts
+                    .iter()
+                    .filter(|r| r.key().0 == receipt_type)
+                    .flat_map(|r| {
+                        r.iter()
+                            .map(|r| (r.key().clone(), r.0.clone(), r.1.clone()))
+                            .collect::<Vec<_>>()
+                    })
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
     async fn get_custom_value(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
         self.get_custom_value(key).await
     }
@@ -791,6 +878,15 @@ impl StateStore for MemoryStore {
     async fn remove_room(&self, room_id: &RoomId) -> Result<()> {
         self.remove_room(room_id).await
     }
+
+    async fn try_take_leased_lock(
+        &self,
+        lease_duration_ms: u32,
+        key: &str,
+        holder: &str,
+    ) -> Result<bool> {
+        Ok(self.try_take_leased_lock(lease_duration_ms, key, holder))
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,477 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Copy of the content of a state store into another state store.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use ruma::{
+    events::receipt::{ReceiptEventContent, ReceiptType},
+    serde::Raw,
+    OwnedUserId, RoomId,
+};
+use tracing::{debug, info, warn};
+
+use super::{
+    DynStateStore, Result, StateChanges, StateStoreDataKey, StateStoreDataValue, StoreError,
+};
+use crate::{deserialized_responses::RawAnySyncOrStrippedState, RoomInfo, RoomMemberships};
+
+/// The types of receipts that are copied.
+///
+/// The stores can't list the receipt types they hold, so only the receipt
+/// types defined by the spec are copied.
+const RECEIPT_TYPES: &[ReceiptType] = &[ReceiptType::Read, ReceiptType::ReadPrivate];
+
+/// The keys of the custom values that are set by the crates of the SDK.
+///
+/// The stores can't list the custom values they hold, so only the custom
+/// values with these keys are copied by [`migrate_state_store`].
+pub mod custom_value_keys {
+    /// The key of the custom value holding the ids of the last appservice
+    /// transactions processed by a client.
+    pub const PROCESSED_TRANSACTIONS: &[u8] = b"appservice.transactions";
+
+    /// The prefix of the key of the custom value recording that an appservice
+    /// user is registered, followed by the localpart of the user.
+    pub const APPSERVICE_USER: &[u8] = b"appservice.users.";
+
+    /// The prefix of the key of the custom value holding the membership of an
+    /// appservice user in a room, followed by the room ID, a `.` and the
+    /// localpart of the user.
+    pub const APPSERVICE_USER_MEMBERSHIP: &[u8] = b"appservice.users.membership.";
+}
+
+/// The steps of a state store migration, reported to the progress listener of
+/// [`migrate_state_store`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StateStoreMigrationStep {
+    /// The sync token and the global account data are copied.
+    Account,
+    /// The rooms are copied, with their state, members, receipts and account
+    /// data. The presence of their members is copied at the end of this step.
+    Rooms,
+    /// The content of the target store is checked against the source store.
+    Verification,
+}
+
+/// Report of the data that was copied by [`migrate_state_store`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StateStoreMigrationReport {
+    /// The number of rooms that were copied.
+    pub rooms: usize,
+    /// The number of state events that were copied, including member events.
+    pub state_events: usize,
+    /// The number of room member profiles that were copied.
+    pub profiles: usize,
+    /// The number of read receipts that were copied.
+    pub receipts: usize,
+    /// The number of global and room account data events that were copied.
+    pub account_data: usize,
+    /// The number of presence events that were copied.
+    pub presence: usize,
+    /// The number of avatar URLs of users that were copied.
+    pub avatar_urls: usize,
+    /// The number of custom values that were copied.
+    pub custom_values: usize,
+}
+
+/// Copy the content of the `source` state store into the `target` state
+/// store, for example to switch to another store implementation without
+/// having to log in again.
+///
+/// The sync token, the room infos, the state events including the member
+/// events, the member profiles and display names, the read receipts, the
+/// global and room account data events, the presence and the avatar URLs of
+/// the members are copied. Events without a type or a state key can't be
+/// stored again and are skipped.
+///
+/// The custom values with a key listed in [`custom_value_keys`] are copied,
+/// those of the appservice users are looked up for the members of the rooms.
+/// Other custom values, filters and media aren't copied, they are recreated
+/// when needed. Once everything is copied, the content of the target store is
+/// checked against the source store.
+///
+/// The rooms are copied one at a time so the whole content of the store
+/// doesn't need to fit in memory. An interrupted migration can be started
+/// again.
+///
+/// # Arguments
+///
+/// * `source` - The store to read the data from, it is left untouched.
+///
+/// * `target` - The store to copy the data into.
+///
+/// * `progress_listener` - A closure that is called with the current step of
+/// the migration, the number of items of this step that were copied so far,
+/// and the total number of items of this step.
+pub async fn migrate_state_store(
+    source: &DynStateStore,
+    target: &DynStateStore,
+    progress_listener: impl Fn(StateStoreMigrationStep, usize, usize),
+) -> Result<StateStoreMigrationReport> {
+    let mut report = StateStoreMigrationReport::default();
+
+    progress_listener(StateStoreMigrationStep::Account, 0, 1);
+
+    let mut changes = StateChanges {
+        sync_token: source
+            .get_kv_data(StateStoreDataKey::SyncToken)
+            .await?
+            .and_then(|v| v.into_sync_token()),
+        ..Default::default()
+    };
+
+    for event in source.get_all_account_data_events().await? {
+        let Some(event_type) = event_type(&event) else {
+            warn!("Skipping a global account data event without a type");
+            continue;
+        };
+
+        changes.account_data.insert(event_type.as_str().into(), event);
+    }
+
+    report.account_data += changes.account_data.len();
+    target.save_changes(&changes).await?;
+
+    report.custom_values +=
+        migrate_custom_value(source, target, custom_value_keys::PROCESSED_TRANSACTIONS).await?;
+
+    progress_listener(StateStoreMigrationStep::Account, 1, 1);
+
+    let rooms = source.get_room_infos().await?;
+    let total = rooms.len();
+    progress_listener(StateStoreMigrationStep::Rooms, 0, total);
+
+    let mut members = BTreeSet::new();
+
+    for (i, room) in rooms.iter().enumerate() {
+        members.extend(migrate_room(source, target, room, &mut report).await?);
+        progress_listener(StateStoreMigrationStep::Rooms, i + 1, total);
+    }
+
+    report.rooms = total;
+
+    let members: Vec<_> = members.into_iter().collect();
+    let mut changes = StateChanges::default();
+
+    for event in source.get_presence_events(&members).await? {
+        if let Ok(Some(sender)) = event.get_field::<OwnedUserId>("sender") {
+            changes.presence.insert(sender, event);
+        }
+    }
+
+    report.presence = changes.presence.len();
+    target.save_changes(&changes).await?;
+
+    migrate_users(source, target, &members, &mut report).await?;
+
+    verify(source, target, &rooms, &progress_listener).await?;
+
+    info!(?report, "Migrated the state store");
+
+    Ok(report)
+}
+
+/// Copy a room with its state, members, receipts and account data, and
+/// return the members of the room.
+async fn migrate_room(
+    source: &DynStateStore,
+    target: &DynStateStore,
+    room: &RoomInfo,
+    report: &mut StateStoreMigrationReport,
+) -> Result<Vec<OwnedUserId>> {
+    let room_id = room.room_id();
+    let mut changes = StateChanges::default();
+
+    for event in source.get_all_state_events(room_id).await? {
+        let Some((event_type, state_key)) = state_event_key(&event) else {
+            warn!(%room_id, "Skipping a state event without a type or a state key");
+            continue;
+        };
+
+        match event {
+            RawAnySyncOrStrippedState::Sync(raw) => {
+                changes
+                    .state
+                    .entry(room_id.to_owned())
+                    .or_default()
+                    .entry(event_type.as_str().into())
+                    .or_default()
+                    .insert(state_key, raw);
+            }
+            RawAnySyncOrStrippedState::Stripped(raw) => {
+                changes
+                    .stripped_state
+                    .entry(room_id.to_owned())
+                    .or_default()
+                    .entry(event_type.as_str().into())
+                    .or_default()
+                    .insert(state_key, raw);
+            }
+        }
+
+        report.state_events += 1;
+    }
+
+    let user_ids = source.get_user_ids(room_id, RoomMemberships::empty()).await?;
+
+    let profiles = source.get_profiles(room_id, &user_ids).await?;
+    let display_names: BTreeSet<String> = profiles
+        .iter()
+        .map(|(user_id, profile)| {
+            profile
+                .as_original()
+                .and_then(|e| e.content.displayname.clone())
+                .unwrap_or_else(|| user_id.localpart().to_owned())
+        })
+        .collect();
+    let display_names: Vec<_> = display_names.into_iter().collect();
+
+    report.profiles += profiles.len();
+    changes.profiles.insert(
+        room_id.to_owned(),
+        profiles.into_iter().map(|(user_id, profile)| (user_id.to_owned(), profile)).collect(),
+    );
+
+    let ambiguity_map = source.get_users_with_display_names(room_id, &display_names).await?;
+    changes.ambiguity_maps.insert(
+        room_id.to_owned(),
+        ambiguity_map.into_iter().map(|(name, users)| (name.to_owned(), users)).collect(),
+    );
+
+    migrate_receipts(source, room_id, &mut changes, report).await?;
+
+    for event in source.get_all_room_account_data_events(room_id).await? {
+        let Some(event_type) = event_type(&event) else {
+            warn!(%room_id, "Skipping a room account data event without a type");
+            continue;
+        };
+
+        changes
+            .room_account_data
+            .entry(room_id.to_owned())
+            .or_default()
+            .insert(event_type.as_str().into(), event);
+        report.account_data += 1;
+    }
+
+    for user_id in &user_ids {
+        let key = [
+            custom_value_keys::APPSERVICE_USER_MEMBERSHIP,
+            room_id.as_bytes(),
+            b".",
+            user_id.localpart().as_bytes(),
+        ]
+        .concat();
+        report.custom_values += migrate_custom_value(source, target, &key).await?;
+    }
+
+    // The room info is saved with the rest of the room, so the room only
+    // appears in the target store once it is complete.
+    changes.add_room(room.clone());
+    target.save_changes(&changes).await?;
+
+    Ok(user_ids)
+}
+
+/// Copy the avatar URLs and the appservice registrations of the given users.
+async fn migrate_users(
+    source: &DynStateStore,
+    target: &DynStateStore,
+    user_ids: &[OwnedUserId],
+    report: &mut StateStoreMigrationReport,
+) -> Result<()> {
+    let localparts: BTreeSet<_> = user_ids.iter().map(|user_id| user_id.localpart()).collect();
+
+    for localpart in localparts {
+        let key = [custom_value_keys::APPSERVICE_USER, localpart.as_bytes()].concat();
+        report.custom_values += migrate_custom_value(source, target, &key).await?;
+    }
+
+    for user_id in user_ids {
+        let key = StateStoreDataKey::UserAvatarUrl(user_id);
+
+        if let Some(StateStoreDataValue::UserAvatarUrl(url)) = source.get_kv_data(key).await? {
+            target.set_kv_data(key, StateStoreDataValue::UserAvatarUrl(url)).await?;
+            report.avatar_urls += 1;
+        }
+    }
+
+    Ok(())
+}
+
+/// Copy the custom value with the given key, if it exists.
+///
+/// Returns the number of custom values that were copied.
+async fn migrate_custom_value(
+    source: &DynStateStore,
+    target: &DynStateStore,
+    key: &[u8],
+) -> Result<usize> {
+    let Some(value) = source.get_custom_value(key).await? else {
+        return Ok(0);
+    };
+
+    target.set_custom_value(key, value).await?;
+
+    Ok(1)
+}
+
+/// Collect the receipts of a room.
+async fn migrate_receipts(
+    source: &DynStateStore,
+    room_id: &RoomId,
+    changes: &mut StateChanges,
+    report: &mut StateStoreMigrationReport,
+) -> Result<()> {
+    let mut content = ReceiptEventContent(BTreeMap::new());
+
+    for receipt_type in RECEIPT_TYPES {
+        for (user_id, event_id, receipt) in
+            source.get_room_receipt_events(room_id, receipt_type.clone()).await?
+        {
+            content
+                .0
+                .entry(event_id)
+                .or_default()
+                .entry(receipt_type.clone())
+                .or_default()
+                .insert(user_id, receipt);
+            report.receipts += 1;
+        }
+    }
+
+    if !content.0.is_empty() {
+        changes.add_receipts(room_id, content);
+    }
+
+    Ok(())
+}
+
+/// Check that the content of the target store matches the content of the
+/// source store for the given rooms.
+async fn verify(
+    source: &DynStateStore,
+    target: &DynStateStore,
+    rooms: &[RoomInfo],
+    progress_listener: &impl Fn(StateStoreMigrationStep, usize, usize),
+) -> Result<()> {
+    let total = rooms.len();
+    progress_listener(StateStoreMigrationStep::Verification, 0, total);
+
+    let source_types = event_types(source.get_all_account_data_events().await?);
+    let target_types = event_types(target.get_all_account_data_events().await?);
+
+    if source_types != target_types {
+        return Err(mismatch("the global account data events"));
+    }
+
+    let target_rooms: BTreeSet<_> =
+        target.get_room_infos().await?.into_iter().map(|r| r.room_id().to_owned()).collect();
+
+    for (i, room) in rooms.iter().enumerate() {
+        let room_id = room.room_id();
+
+        if !target_rooms.contains(room_id) {
+            return Err(mismatch(&format!("the room {room_id}")));
+        }
+
+        let source_state = state_event_keys(source.get_all_state_events(room_id).await?);
+        let target_state = state_event_keys(target.get_all_state_events(room_id).await?);
+
+        if source_state != target_state {
+            return Err(mismatch(&format!("the state events of {room_id}")));
+        }
+
+        let source_members: BTreeSet<_> =
+            source.get_user_ids(room_id, RoomMemberships::empty()).await?.into_iter().collect();
+        let target_members: BTreeSet<_> =
+            target.get_user_ids(room_id, RoomMemberships::empty()).await?.into_iter().collect();
+
+        if source_members != target_members {
+            return Err(mismatch(&format!("the members of {room_id}")));
+        }
+
+        if receipt_keys(source, room_id).await? != receipt_keys(target, room_id).await? {
+            return Err(mismatch(&format!("the receipts of {room_id}")));
+        }
+
+        let source_types = event_types(source.get_all_room_account_data_events(room_id).await?);
+        let target_types = event_types(target.get_all_room_account_data_events(room_id).await?);
+
+        if source_types != target_types {
+            return Err(mismatch(&format!("the account data events of {room_id}")));
+        }
+
+        progress_listener(StateStoreMigrationStep::Verification, i + 1, total);
+    }
+
+    debug!("The content of the migrated state store matches the source store");
+
+    Ok(())
+}
+
+/// Get the type of the given event.
+fn event_type<T>(event: &Raw<T>) -> Option<String> {
+    event.get_field("type").ok().flatten()
+}
+
+/// Get the type and the state key of the given state event.
+fn state_event_key(event: &RawAnySyncOrStrippedState) -> Option<(String, String)> {
+    let (event_type, state_key) = match event {
+        RawAnySyncOrStrippedState::Sync(raw) => (event_type(raw), raw.get_field("state_key")),
+        RawAnySyncOrStrippedState::Stripped(raw) => (event_type(raw), raw.get_field("state_key")),
+    };
+
+    Some((event_type?, state_key.ok().flatten()?))
+}
+
+/// Get the types of the given events, skipping the events without a type.
+fn event_types<T>(events: Vec<Raw<T>>) -> BTreeSet<String> {
+    events.iter().filter_map(event_type).collect()
+}
+
+/// Get the types and state keys of the given state events, skipping the
+/// events without a type or a state key.
+fn state_event_keys(events: Vec<RawAnySyncOrStrippedState>) -> BTreeSet<(String, String)> {
+    events.iter().filter_map(state_event_key).collect()
+}
+
+/// Get the type, user, event and thread of all the receipts of a room.
+async fn receipt_keys(
+    store: &DynStateStore,
+    room_id: &RoomId,
+) -> Result<BTreeSet<(String, String, String, Option<String>)>> {
+    let mut keys = BTreeSet::new();
+
+    for receipt_type in RECEIPT_TYPES {
+        for (user_id, event_id, receipt) in
+            store.get_room_receipt_events(room_id, receipt_type.clone()).await?
+        {
+            keys.insert((
+                receipt_type.to_string(),
+                user_id.to_string(),
+                event_id.to_string(),
+                receipt.thread.as_str().map(ToOwned::to_owned),
+            ));
+        }
+    }
+
+    Ok(keys)
+}
+
+fn mismatch(what: &str) -> StoreError {
+    StoreError::MigrationMismatch(format!("{what} differ between the stores"))
+}
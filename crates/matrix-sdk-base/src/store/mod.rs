@@ -35,6 +35,7 @@ use once_cell::sync::OnceCell;
 #[cfg(any(test, feature = "testing"))]
 #[macro_use]
 pub mod integration_tests;
+pub mod locks;
 mod traits;
 
 use dashmap::DashMap;
@@ -65,11 +66,16 @@ use crate::{
 
 pub(crate) mod ambiguity_map;
 mod memory_store;
+mod migration;
 
 #[cfg(any(test, feature = "testing"))]
 pub use self::integration_tests::StateStoreIntegrationTests;
+use self::locks::{LockableStateStore, StateStoreLock};
 pub use self::{
     memory_store::MemoryStore,
+    migration::{
+        custom_value_keys, migrate_state_store, StateStoreMigrationReport, StateStoreMigrationStep,
+    },
     traits::{
         DynStateStore, IntoStateStore, StateStore, StateStoreDataKey, StateStoreDataValue,
         StateStoreExt,
@@ -115,6 +121,13 @@ pub enum StoreError {
     /// This should never happen.
     #[error("Redaction failed: {0}")]
     Redaction(#[source] ruma::canonical_json::RedactionError),
+    /// Spent too long waiting for the cross-process lock of the store.
+    #[error("The store lock timed out")]
+    LockTimeout,
+    /// The content of a store that was migrated doesn't match the content of
+    /// the store it was migrated from.
+    #[error("The migrated store doesn't match the source store: {0}")]
+    MigrationMismatch(String),
 }
 
 impl StoreError {
@@ -133,6 +146,10 @@ impl StoreError {
 /// A `StateStore` specific result type.
 pub type Result<T, E = StoreError> = std::result::Result<T, E>;
 
+/// Custom value key under which the holder of the cross-process lock that
+/// last wrote to the store is saved.
+const LAST_LOCK_HOLDER_KEY: &[u8] = b"state_store_last_lock_holder";
+
 /// A state store wrapper for the SDK.
 ///
 /// This adds additional higher level store functionality on top of a
@@ -169,6 +186,46 @@ impl Store {
         &self.sync_lock
     }
 
+    /// Create a new cross-process lock on top of this store.
+    pub fn create_store_lock(&self, lock_key: String, lock_holder: String) -> StateStoreLock {
+        StateStoreLock::new(LockableStateStore(self.inner.clone()), lock_key, lock_holder)
+    }
+
+    /// Reload the rooms and the sync token from the inner `StateStore`, if
+    /// another process holding the cross-process lock has written to it since
+    /// `lock_holder` last held the lock.
+    ///
+    /// This must only be called while holding the cross-process lock. Returns
+    /// whether the in-memory state was reloaded.
+    pub async fn reload_if_modified_by_other_process(&self, lock_holder: &str) -> Result<bool> {
+        let last_holder = self.inner.get_custom_value(LAST_LOCK_HOLDER_KEY).await?;
+
+        if last_holder.as_deref() == Some(lock_holder.as_bytes()) {
+            return Ok(false);
+        }
+
+        if let Some(session_meta) = self.session_meta.get() {
+            for info in self.inner.get_room_infos().await? {
+                if let Some(room) = self.rooms.get(&info.room_id) {
+                    room.update_summary(info);
+                } else {
+                    let room = Room::restore(&session_meta.user_id, self.inner.clone(), info);
+                    self.rooms.insert(room.room_id().to_owned(), room);
+                }
+            }
+
+            let token = self
+                .get_kv_data(StateStoreDataKey::SyncToken)
+                .await?
+                .and_then(|s| s.into_sync_token());
+            *self.sync_token.write().await = token;
+        }
+
+        self.inner.set_custom_value(LAST_LOCK_HOLDER_KEY, lock_holder.as_bytes().to_vec()).await?;
+
+        Ok(true)
+    }
+
     /// Set the meta of the session.
     ///
     /// Restores the state of this `Store` from the given `SessionMeta` and the
@@ -190,6 +247,14 @@ impl Store {
         Ok(())
     }
 
+    /// Forget the rooms and the sync token loaded in memory.
+    ///
+    /// The inner `StateStore` is left untouched.
+    pub async fn clear_in_memory_state(&self) {
+        self.rooms.clear();
+        *self.sync_token.write().await = None;
+    }
+
     /// The current [`SessionMeta`] containing our user ID and device ID.
     pub fn session_meta(&self) -> Option<&SessionMeta> {
         self.session_meta.get()
@@ -294,6 +294,50 @@ pub trait StateStore: AsyncTraitDeps {
         event_id: &EventId,
     ) -> Result<Vec<(OwnedUserId, Receipt)>, Self::Error>;
 
+    /// Get all the state events of a room, whatever their type.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - The id of the room for which the state events should be
+    ///   fetched.
+    async fn get_all_state_events(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<RawAnySyncOrStrippedState>, Self::Error>;
+
+    /// Get all the events out of the account data store, whatever their type.
+    async fn get_all_account_data_events(
+        &self,
+    ) -> Result<Vec<Raw<AnyGlobalAccountDataEvent>>, Self::Error>;
+
+    /// Get all the events out of the room account data store for the given
+    /// room, whatever their type.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - The id of the room for which the room account data events
+    ///   should be fetched.
+    async fn get_all_room_account_data_events(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<Raw<AnyRoomAccountDataEvent>>, Self::Error>;
+
+    /// Get all the receipts of the given type in a room, in every thread.
+    ///
+    /// The thread of a receipt is available in [`Receipt::thread`].
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - The id of the room for which the receipts should be
+    ///   fetched.
+    ///
+    /// * `receipt_type` - The type of the receipts.
+    async fn get_room_receipt_events(
+        &self,
+        room_id: &RoomId,
+        receipt_type: ReceiptType,
+    ) -> Result<Vec<(OwnedUserId, OwnedEventId, Receipt)>, Self::Error>;
+
     /// Get arbitrary data from the custom store
     ///
     /// # Arguments
@@ -365,6 +409,24 @@ pub trait StateStore: AsyncTraitDeps {
     ///
     /// * `room_id` - The `RoomId` of the room to delete.
     async fn remove_room(&self, room_id: &RoomId) -> Result<(), Self::Error>;
+
+    /// Try to take a leased lock.
+    ///
+    /// This attempts to take a lock for the given lease duration.
+    ///
+    /// - If we already had the lease, this will extend the lease.
+    /// - If we didn't, but the previous lease has expired, we will acquire the
+    ///   lock.
+    /// - If there was no previous lease, we will acquire the lock.
+    /// - Otherwise, we don't get the lock.
+    ///
+    /// Returns whether taking the lock succeeded.
+    async fn try_take_leased_lock(
+        &self,
+        lease_duration_ms: u32,
+        key: &str,
+        holder: &str,
+    ) -> Result<bool, Self::Error>;
 }
 
 #[repr(transparent)]
@@ -545,6 +607,34 @@ impl<T: StateStore> StateStore for EraseStateStoreError<T> {
             .map_err(Into::into)
     }
 
+    async fn get_all_state_events(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<RawAnySyncOrStrippedState>, Self::Error> {
+        self.0.get_all_state_events(room_id).await.map_err(Into::into)
+    }
+
+    async fn get_all_account_data_events(
+        &self,
+    ) -> Result<Vec<Raw<AnyGlobalAccountDataEvent>>, Self::Error> {
+        self.0.get_all_account_data_events().await.map_err(Into::into)
+    }
+
+    async fn get_all_room_account_data_events(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<Raw<AnyRoomAccountDataEvent>>, Self::Error> {
+        self.0.get_all_room_account_data_events(room_id).await.map_err(Into::into)
+    }
+
+    async fn get_room_receipt_events(
+        &self,
+        room_id: &RoomId,
+        receipt_type: ReceiptType,
+    ) -> Result<Vec<(OwnedUserId, OwnedEventId, Receipt)>, Self::Error> {
+        self.0.get_room_receipt_events(room_id, receipt_type).await.map_err(Into::into)
+    }
+
     async fn get_custom_value(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Self::Error> {
         self.0.get_custom_value(key).await.map_err(Into::into)
     }
@@ -587,6 +677,15 @@ impl<T: StateStore> StateStore for EraseStateStoreError<T> {
     async fn remove_room(&self, room_id: &RoomId) -> Result<(), Self::Error> {
         self.0.remove_room(room_id).await.map_err(Into::into)
     }
+
+    async fn try_take_leased_lock(
+        &self,
+        lease_duration_ms: u32,
+        key: &str,
+        holder: &str,
+    ) -> Result<bool, Self::Error> {
+        self.0.try_take_leased_lock(lease_duration_ms, key, holder).await.map_err(Into::into)
+    }
 }
 
 /// Convenience functionality for state stores.
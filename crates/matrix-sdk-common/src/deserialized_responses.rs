@@ -29,6 +29,7 @@ const AUTHENTICITY_NOT_GUARANTEED: &str =
 const UNVERIFIED_IDENTITY: &str = "Encrypted by an unverified user.";
 const UNSIGNED_DEVICE: &str = "Encrypted by a device not verified by its owner.";
 const UNKNOWN_DEVICE: &str = "Encrypted by an unknown or deleted device.";
+const SENT_IN_CLEAR: &str = "Not encrypted.";
 
 /// Represents the state of verification for a decrypted message sent by a
 /// device.
@@ -188,6 +189,14 @@ pub enum ShieldState {
     None,
 }
 
+impl ShieldState {
+    /// The shield that should be presented for an unencrypted message that
+    /// was sent in an encrypted room.
+    pub fn sent_in_clear() -> Self {
+        Self::Red { message: SENT_IN_CLEAR }
+    }
+}
+
 /// The algorithm specific information of a decrypted event.
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum AlgorithmInfo {
@@ -221,6 +230,13 @@ pub struct EncryptionInfo {
     /// Callers that persist this should mark the state as dirty when a device
     /// change is received down the sync.
     pub verification_state: VerificationState,
+    /// The ID of the Megolm session that was used to decrypt the event, if
+    /// known.
+    ///
+    /// This can be used to recompute the `verification_state` later on, when
+    /// the trust of the sending device or user identity changes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
 }
 
 /// A customized version of a room event coming from a sync that holds optional
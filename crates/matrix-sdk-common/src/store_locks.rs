@@ -0,0 +1,471 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A cross-process lock implemented on top of a store.
+//!
+//! This is a per-process lock that may be used only for very specific use
+//! cases, where multiple processes might concurrently write to the same
+//! database at the same time. Such a lock can be acquired multiple times by
+//! the same process, and it remains active as long as there's at least one user
+//! in a given process.
+//!
+//! The lock is implemented using time-based leases to values inserted in a
+//! store, see [`BackingStore::try_lock`]. The store maintains the lock
+//! identifier (key), who's the current holder (value), and an expiration
+//! timestamp on the side.
+//!
+//! The lock is initially acquired for a certain period of time (namely, the
+//! duration of a lease, aka `LEASE_DURATION_MS`), and then a "heartbeat" task
+//! renews the lease to extend its duration, every so often (namely, every
+//! `EXTEND_LEASE_EVERY_MS`). Since the tokio scheduler might be busy, the
+//! extension request should happen way more frequently than the duration of a
+//! lease, in case a deadline is missed. The current values have been chosen to
+//! reflect that, with a ratio of 1:10 as of 2023-06-23.
+//!
+//! Releasing the lock happens naturally, by not renewing a lease. It happens
+//! automatically after the duration of the last lease, at most.
+
+use std::{
+    error::Error,
+    fmt,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use tokio::sync::Mutex;
+use tracing::{debug, error, instrument, trace};
+
+use crate::{
+    executor::{spawn, JoinHandle},
+    SendOutsideWasm, SyncOutsideWasm,
+};
+
+/// A store that can hold the leases of a [`CrossProcessStoreLock`].
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait BackingStore {
+    /// The error type of the store, it must be able to represent a lock
+    /// timeout.
+    type LockError: Error + From<LockTimeoutError> + SendOutsideWasm;
+
+    /// Try to take a lease of the lock named `key`, on behalf of `holder`.
+    ///
+    /// Returns whether the lease was obtained. Taking the lease succeeds if
+    /// the lock is free, if its previous lease has expired, or if it's
+    /// already held by `holder`, in which case the lease is extended.
+    async fn try_lock(
+        &self,
+        lease_duration_ms: u32,
+        key: &str,
+        holder: &str,
+    ) -> Result<bool, Self::LockError>;
+}
+
+/// Error returned when the lock couldn't be taken before the maximal backoff
+/// has been reached.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LockTimeoutError(());
+
+impl fmt::Display for LockTimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "a lock timed out")
+    }
+}
+
+impl Error for LockTimeoutError {}
+
+/// A guard on a [`CrossProcessStoreLock`].
+///
+/// The lock will be automatically released a short period of time after all the
+/// guards have dropped.
+#[derive(Debug)]
+pub struct CrossProcessStoreLockGuard {
+    num_holders: Arc<AtomicU32>,
+}
+
+impl Drop for CrossProcessStoreLockGuard {
+    fn drop(&mut self) {
+        self.num_holders.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// A store-based lock, shared by several processes using the same store.
+#[derive(Clone, Debug)]
+pub struct CrossProcessStoreLock<S> {
+    /// The store we're using to lock.
+    store: S,
+
+    /// Number of holders of the lock in this process.
+    ///
+    /// If greater than 0, this means we've already acquired this lock, in this
+    /// process, and the store lock mustn't be touched.
+    ///
+    /// When the number of holders is decreased to 0, then the lock must be
+    /// released in the store.
+    num_holders: Arc<AtomicU32>,
+
+    /// A mutex to control an attempt to take the lock, to avoid making it
+    /// reentrant.
+    locking_attempt: Arc<Mutex<()>>,
+
+    /// Current renew task spawned by `try_lock_once`.
+    renew_task: Arc<Mutex<Option<JoinHandle<()>>>>,
+
+    /// The key used in the key/value mapping for the lock entry.
+    lock_key: String,
+
+    /// A specific value to identify the lock's holder.
+    lock_holder: String,
+}
+
+impl<S: BackingStore + Clone + SendOutsideWasm + SyncOutsideWasm + 'static>
+    CrossProcessStoreLock<S>
+{
+    /// Amount of time a lease of the lock should last, in milliseconds.
+    pub const LEASE_DURATION_MS: u32 = 500;
+
+    /// Period of time between two attempts to extend the lease. We'll
+    /// re-request a lease for an entire duration of `LEASE_DURATION_MS`
+    /// milliseconds, every `EXTEND_LEASE_EVERY_MS`, so this has to
+    /// be an amount safely low compared to `LEASE_DURATION_MS`, to make sure
+    /// that we can miss a deadline without compromising the lock.
+    pub const EXTEND_LEASE_EVERY_MS: u64 = 50;
+
+    /// Initial backoff, in milliseconds. This is the time we wait the first
+    /// time, if taking the lock initially failed.
+    const INITIAL_BACKOFF_MS: u32 = 10;
+
+    /// Maximal backoff, in milliseconds. This is the maximum amount of time
+    /// we'll wait for the lock, *between two attempts*.
+    pub const MAX_BACKOFF_MS: u32 = 1000;
+
+    /// Create a new store-based lock implemented as a value in the store.
+    ///
+    /// # Parameters
+    ///
+    /// - `lock_key`: key in the key-value store to store the lock's state.
+    /// - `lock_holder`: identify the lock's holder with this given value.
+    pub fn new(store: S, lock_key: String, lock_holder: String) -> Self {
+        Self {
+            store,
+            lock_key,
+            lock_holder,
+            num_holders: Arc::new(0.into()),
+            locking_attempt: Arc::new(Mutex::new(())),
+            renew_task: Default::default(),
+        }
+    }
+
+    /// Try to lock once, returns whether the lock was obtained or not.
+    #[instrument(skip(self), fields(?self.lock_key, ?self.lock_holder))]
+    pub async fn try_lock_once(&self) -> Result<Option<CrossProcessStoreLockGuard>, S::LockError> {
+        // Hold onto the locking attempt mutex for the entire lifetime of this
+        // function, to avoid multiple reentrant calls.
+        let _attempt = self.locking_attempt.lock().await;
+
+        // If another thread obtained the lock, make sure to only superficially increase
+        // the number of holders, and carry on.
+        if self.num_holders.load(Ordering::SeqCst) > 0 {
+            // Note: between the above load and the fetch_add below, another thread may
+            // decrement `num_holders`. That's fine because that means the lock
+            // was taken by at least one thread, and after this call it will be
+            // taken by at least one thread.
+            trace!("We already had the lock, incrementing holder count");
+            self.num_holders.fetch_add(1, Ordering::SeqCst);
+            return Ok(Some(CrossProcessStoreLockGuard { num_holders: self.num_holders.clone() }));
+        }
+
+        let acquired =
+            self.store.try_lock(Self::LEASE_DURATION_MS, &self.lock_key, &self.lock_holder).await?;
+
+        if !acquired {
+            trace!("Couldn't acquire the lock immediately.");
+            return Ok(None);
+        }
+
+        trace!("Acquired the lock, spawning the lease extension task.");
+
+        // This is the first time we've acquired the lock. We're going to spawn the task
+        // that will renew the lease.
+
+        // Clone data to be owned by the task.
+        let this = self.clone();
+
+        let mut renew_task = self.renew_task.lock().await;
+
+        // Cancel the previous task, if any. That's safe to do, because:
+        // - either the task was done,
+        // - or it was still running, but taking a lock in the db has to be an atomic
+        //   operation running in a transaction.
+        if let Some(_prev) = renew_task.take() {
+            #[cfg(not(target_arch = "wasm32"))]
+            _prev.abort();
+        }
+
+        // Restart a new one.
+        *renew_task = Some(spawn(async move {
+            loop {
+                {
+                    // First, check if there are still users of this lock.
+                    //
+                    // This is not racy, because:
+                    // - the `locking_attempt` mutex makes sure we don't have unexpected
+                    // interactions with the non-atomic sequence above in `try_lock_once`
+                    // (check > 0, then add 1).
+                    // - other entities holding onto the `num_holders` atomic will only
+                    // decrease it over time.
+                    let _guard = this.locking_attempt.lock().await;
+
+                    // If there are no more users, we can quit.
+                    if this.num_holders.load(Ordering::SeqCst) == 0 {
+                        debug!("exiting the lease extension loop");
+
+                        // Cancel the lease with another 0ms lease.
+                        // If we don't get the lock, that's (weird but) fine.
+                        let _ = this.store.try_lock(0, &this.lock_key, &this.lock_holder).await;
+
+                        // Exit the loop.
+                        break;
+                    }
+                }
+
+                sleep(Duration::from_millis(Self::EXTEND_LEASE_EVERY_MS)).await;
+
+                if let Err(err) = this
+                    .store
+                    .try_lock(Self::LEASE_DURATION_MS, &this.lock_key, &this.lock_holder)
+                    .await
+                {
+                    error!("error when extending lock lease: {err:#}");
+                    // Exit the loop.
+                    break;
+                }
+            }
+        }));
+
+        self.num_holders.fetch_add(1, Ordering::SeqCst);
+
+        Ok(Some(CrossProcessStoreLockGuard { num_holders: self.num_holders.clone() }))
+    }
+
+    /// Attempt to take the lock, with exponential backoff if the lock has
+    /// already been taken by another process.
+    ///
+    /// The `max_backoff` parameter is the maximum time (in milliseconds) that
+    /// should be waited for, between two attempts. Once the backoff reaches
+    /// that value, the lock is tried one last time and a [`LockTimeoutError`]
+    /// is returned if it still couldn't be taken. If not provided,
+    /// [`Self::MAX_BACKOFF_MS`] is used.
+    #[instrument(skip(self), fields(?self.lock_key, ?self.lock_holder))]
+    pub async fn spin_lock(
+        &self,
+        max_backoff: Option<u32>,
+    ) -> Result<CrossProcessStoreLockGuard, S::LockError> {
+        let max_backoff = max_backoff.unwrap_or(Self::MAX_BACKOFF_MS);
+        let mut backoff = Self::INITIAL_BACKOFF_MS;
+
+        loop {
+            if let Some(guard) = self.try_lock_once().await? {
+                return Ok(guard);
+            }
+
+            if backoff >= max_backoff {
+                return Err(LockTimeoutError(()).into());
+            }
+
+            debug!("Waiting {backoff} before re-attempting to take the lock");
+            sleep(Duration::from_millis(backoff.into())).await;
+
+            backoff = backoff.saturating_mul(2).min(max_backoff);
+        }
+    }
+
+    /// Returns the value in the database that represents the holder's
+    /// identifier.
+    pub fn lock_holder(&self) -> &str {
+        &self.lock_holder
+    }
+}
+
+async fn sleep(duration: Duration) {
+    #[cfg(not(target_arch = "wasm32"))]
+    tokio::time::sleep(duration).await;
+
+    #[cfg(target_arch = "wasm32")]
+    gloo_timers::future::TimeoutFuture::new(
+        u32::try_from(duration.as_millis()).expect("Overlong duration"),
+    )
+    .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{atomic::Ordering, Arc, Mutex},
+        time::Duration,
+    };
+
+    use async_trait::async_trait;
+    use instant::Instant;
+    use matrix_sdk_test::async_test;
+    use tokio::{spawn, time::sleep};
+
+    use super::{
+        BackingStore, CrossProcessStoreLock, CrossProcessStoreLockGuard, LockTimeoutError,
+    };
+
+    /// A store keeping the leases in memory, like the memory stores do.
+    #[derive(Clone, Debug, Default)]
+    struct TestStore {
+        leases: Arc<Mutex<HashMap<String, (String, Instant)>>>,
+    }
+
+    #[async_trait]
+    impl BackingStore for TestStore {
+        type LockError = LockTimeoutError;
+
+        async fn try_lock(
+            &self,
+            lease_duration_ms: u32,
+            key: &str,
+            holder: &str,
+        ) -> Result<bool, Self::LockError> {
+            let now = Instant::now();
+            let expiration = now + Duration::from_millis(lease_duration_ms.into());
+
+            let mut leases = self.leases.lock().unwrap();
+            let acquired = match leases.get(key) {
+                Some((prev_holder, prev_expiration)) => {
+                    prev_holder == holder || *prev_expiration < now
+                }
+                None => true,
+            };
+
+            if acquired {
+                leases.insert(key.to_owned(), (holder.to_owned(), expiration));
+            }
+
+            Ok(acquired)
+        }
+    }
+
+    type TestLock = CrossProcessStoreLock<TestStore>;
+
+    async fn release_lock(guard: Option<CrossProcessStoreLockGuard>) {
+        drop(guard);
+        sleep(Duration::from_millis(TestLock::EXTEND_LEASE_EVERY_MS)).await;
+    }
+
+    #[async_test]
+    async fn test_simple_lock_unlock() -> Result<(), LockTimeoutError> {
+        let store = TestStore::default();
+        let lock = TestLock::new(store, "key".to_owned(), "first".to_owned());
+
+        // The lock plain works when used with a single holder.
+        let acquired = lock.try_lock_once().await?;
+        assert!(acquired.is_some());
+        assert_eq!(lock.num_holders.load(Ordering::SeqCst), 1);
+
+        // Releasing works.
+        release_lock(acquired).await;
+        assert_eq!(lock.num_holders.load(Ordering::SeqCst), 0);
+
+        // Spin locking on the same lock always works, assuming no concurrent access.
+        let acquired = lock.spin_lock(None).await?;
+
+        // Releasing still works.
+        release_lock(Some(acquired)).await;
+        assert_eq!(lock.num_holders.load(Ordering::SeqCst), 0);
+
+        Ok(())
+    }
+
+    #[async_test]
+    async fn test_self_recovery() -> Result<(), LockTimeoutError> {
+        let store = TestStore::default();
+        let lock = TestLock::new(store.clone(), "key".to_owned(), "first".to_owned());
+
+        // When a lock is acquired...
+        let acquired = lock.try_lock_once().await?;
+        assert!(acquired.is_some());
+        assert_eq!(lock.num_holders.load(Ordering::SeqCst), 1);
+
+        // But then forgotten... (note: no need to release the guard)
+        drop(lock);
+
+        // And when rematerializing the lock with the same key/value...
+        let lock = TestLock::new(store, "key".to_owned(), "first".to_owned());
+
+        // We still got it.
+        let acquired = lock.try_lock_once().await?;
+        assert!(acquired.is_some());
+        assert_eq!(lock.num_holders.load(Ordering::SeqCst), 1);
+
+        Ok(())
+    }
+
+    #[async_test]
+    async fn test_multiple_holders_same_process() -> Result<(), LockTimeoutError> {
+        let store = TestStore::default();
+        let lock = TestLock::new(store, "key".to_owned(), "first".to_owned());
+
+        // Taking the lock twice...
+        let acquired = lock.try_lock_once().await?;
+        let acquired2 = lock.try_lock_once().await?;
+        assert!(acquired.is_some() && acquired2.is_some());
+        assert_eq!(lock.num_holders.load(Ordering::SeqCst), 2);
+
+        // ...means we can release it twice.
+        release_lock(acquired).await;
+        assert_eq!(lock.num_holders.load(Ordering::SeqCst), 1);
+
+        release_lock(acquired2).await;
+        assert_eq!(lock.num_holders.load(Ordering::SeqCst), 0);
+
+        Ok(())
+    }
+
+    #[async_test]
+    async fn test_multiple_processes() -> Result<(), LockTimeoutError> {
+        let store = TestStore::default();
+
+        let lock1 = TestLock::new(store.clone(), "key".to_owned(), "first".to_owned());
+        let lock2 = TestLock::new(store, "key".to_owned(), "second".to_owned());
+
+        // When the first process takes the lock, the second can't take it.
+        let acquired1 = lock1.try_lock_once().await?;
+        assert!(acquired1.is_some());
+        assert!(lock2.try_lock_once().await?.is_none());
+
+        let lock2_clone = lock2.clone();
+        let handle = spawn(async move { lock2_clone.spin_lock(Some(1000)).await });
+
+        sleep(Duration::from_millis(100)).await;
+        drop(acquired1);
+
+        // The second process gets the lock once the first one released it.
+        let _acquired2 = handle.await.expect("join handle is properly awaited")?;
+
+        // Now if the first process tries to get the lock with a small timeout, it
+        // will fail.
+        assert!(matches!(lock1.spin_lock(Some(200)).await, Err(LockTimeoutError(_))));
+
+        Ok(())
+    }
+}
@@ -73,6 +73,22 @@ pub enum OlmError {
             have a valid Olm session with us"
     )]
     MissingSession,
+
+    /// Room keys weren't shared because the identities of some of the room
+    /// members changed and the changes weren't acknowledged.
+    #[error(
+        "room keys weren't shared because the identities of the users {0:?} \
+            changed and the changes weren't acknowledged"
+    )]
+    IdentityViolations(Vec<OwnedUserId>),
+
+    /// Room keys weren't shared because the identities of some of the room
+    /// members aren't verified, and the
+    /// [`CollectStrategy::ErrorOnUnverifiedIdentity`] strategy was used.
+    ///
+    /// [`CollectStrategy::ErrorOnUnverifiedIdentity`]: crate::CollectStrategy::ErrorOnUnverifiedIdentity
+    #[error("room keys weren't shared because the identities of the users {0:?} aren't verified")]
+    UnverifiedIdentities(Vec<OwnedUserId>),
 }
 
 /// Error representing a failure during a group encryption operation.
@@ -15,6 +15,8 @@
 use std::{
     collections::BTreeMap,
     io::{Error as IoError, ErrorKind, Read},
+    pin::Pin,
+    task::{ready, Context, Poll},
 };
 
 use aes::{
@@ -22,6 +24,7 @@ use aes::{
     Aes256,
 };
 use base64::DecodeError;
+use futures_core::Stream;
 use rand::{thread_rng, RngCore};
 use ruma::{
     events::room::{EncryptedFile, JsonWebKey, JsonWebKeyInit},
@@ -130,29 +133,66 @@ impl<'a, R: Read + 'a> AttachmentDecryptor<'a, R> {
         input: &'a mut R,
         info: MediaEncryptionInfo,
     ) -> Result<AttachmentDecryptor<'a, R>, DecryptorError> {
-        if info.version != VERSION {
-            return Err(DecryptorError::UnknownVersion);
-        }
+        let (expected_hash, aes) = decryption_cipher(info)?;
 
-        let hash =
-            info.hashes.get("sha256").ok_or(DecryptorError::MissingHash)?.as_bytes().to_owned();
-        let mut key = info.key.k.into_inner();
-        let iv = info.iv.into_inner();
+        Ok(AttachmentDecryptor { inner: input, expected_hash, sha: Sha256::default(), aes })
+    }
+}
 
-        if key.len() != KEY_SIZE {
-            return Err(DecryptorError::KeyNonceLength);
-        }
+/// Check the given encryption info and create the cipher that decrypts the
+/// attachment, along with the hash the ciphertext should have.
+fn decryption_cipher(info: MediaEncryptionInfo) -> Result<(Vec<u8>, Aes256Ctr), DecryptorError> {
+    if info.version != VERSION {
+        return Err(DecryptorError::UnknownVersion);
+    }
+
+    let hash = info.hashes.get("sha256").ok_or(DecryptorError::MissingHash)?.as_bytes().to_owned();
+    let mut key = info.key.k.into_inner();
+    let iv = info.iv.into_inner();
 
-        let key_array = GenericArray::from_slice(&key);
-        let iv = GenericArray::from_exact_iter(iv).ok_or(DecryptorError::KeyNonceLength)?;
+    if key.len() != KEY_SIZE {
+        return Err(DecryptorError::KeyNonceLength);
+    }
 
-        let sha = Sha256::default();
+    let key_array = GenericArray::from_slice(&key);
+    let iv = GenericArray::from_exact_iter(iv).ok_or(DecryptorError::KeyNonceLength)?;
 
-        let aes = Aes256Ctr::new(key_array, &iv);
-        key.zeroize();
+    let aes = Aes256Ctr::new(key_array, &iv);
+    key.zeroize();
 
-        Ok(AttachmentDecryptor { inner: input, expected_hash: hash, sha, aes })
-    }
+    Ok((hash, aes))
+}
+
+/// Create a fresh key and initialization vector, and the cipher that encrypts
+/// an attachment using them.
+fn encryption_cipher() -> (JsonWebKey, Base64, Aes256Ctr) {
+    let mut key = [0u8; KEY_SIZE];
+    let mut iv = [0u8; IV_SIZE];
+
+    let mut rng = thread_rng();
+
+    rng.fill_bytes(&mut key);
+    // Only populate the first 8 bytes with randomness, the rest is 0
+    // initialized for the counter.
+    rng.fill_bytes(&mut iv[0..8]);
+
+    let web_key = JsonWebKey::from(JsonWebKeyInit {
+        kty: "oct".to_owned(),
+        key_ops: vec!["encrypt".to_owned(), "decrypt".to_owned()],
+        alg: "A256CTR".to_owned(),
+        #[allow(clippy::unnecessary_to_owned)]
+        k: Base64::new(key.to_vec()),
+        ext: true,
+    });
+    #[allow(clippy::unnecessary_to_owned)]
+    let encoded_iv = Base64::new(iv.to_vec());
+
+    let key_array = &key.into();
+
+    let aes = Aes256Ctr::new(key_array, &iv.into());
+    key.zeroize();
+
+    (web_key, encoded_iv, aes)
 }
 
 /// A wrapper that transparently encrypts anything that implements `Read`.
@@ -224,31 +264,7 @@ impl<'a, R: Read + ?Sized + 'a> AttachmentEncryptor<'a, R> {
     /// let key = encryptor.finish();
     /// ```
     pub fn new(reader: &'a mut R) -> Self {
-        let mut key = [0u8; KEY_SIZE];
-        let mut iv = [0u8; IV_SIZE];
-
-        let mut rng = thread_rng();
-
-        rng.fill_bytes(&mut key);
-        // Only populate the first 8 bytes with randomness, the rest is 0
-        // initialized for the counter.
-        rng.fill_bytes(&mut iv[0..8]);
-
-        let web_key = JsonWebKey::from(JsonWebKeyInit {
-            kty: "oct".to_owned(),
-            key_ops: vec!["encrypt".to_owned(), "decrypt".to_owned()],
-            alg: "A256CTR".to_owned(),
-            #[allow(clippy::unnecessary_to_owned)]
-            k: Base64::new(key.to_vec()),
-            ext: true,
-        });
-        #[allow(clippy::unnecessary_to_owned)]
-        let encoded_iv = Base64::new(iv.to_vec());
-
-        let key_array = &key.into();
-
-        let aes = Aes256Ctr::new(key_array, &iv.into());
-        key.zeroize();
+        let (web_key, encoded_iv, aes) = encryption_cipher();
 
         AttachmentEncryptor {
             finished: false,
@@ -277,6 +293,170 @@ impl<'a, R: Read + ?Sized + 'a> AttachmentEncryptor<'a, R> {
     }
 }
 
+/// A wrapper that transparently decrypts a [`Stream`] of chunks of an
+/// encrypted Matrix attachment.
+///
+/// Unlike the [`AttachmentDecryptor`], this never holds more than a single
+/// chunk in memory, which makes it suitable to decrypt large files while
+/// they are being downloaded.
+///
+/// The stream yields an error if the inner stream yields an error, or if the
+/// hash of the attachment doesn't match the expected one once the inner stream
+/// is exhausted. The decrypted data must not be trusted before the stream
+/// ended successfully.
+pub struct AttachmentStreamDecryptor<S> {
+    inner: S,
+    expected_hash: Vec<u8>,
+    sha: Sha256,
+    aes: Aes256Ctr,
+    done: bool,
+}
+
+impl<S> std::fmt::Debug for AttachmentStreamDecryptor<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AttachmentStreamDecryptor")
+            .field("expected_hash", &self.expected_hash)
+            .field("done", &self.done)
+            .finish()
+    }
+}
+
+impl<S> AttachmentStreamDecryptor<S> {
+    /// Wrap the given stream, decrypting all the chunks it yields.
+    ///
+    /// # Arguments
+    ///
+    /// * `stream` - The [`Stream`] of encrypted chunks that should be
+    /// decrypted.
+    ///
+    /// * `info` - The encryption info that is necessary to decrypt the chunks.
+    pub fn new(stream: S, info: MediaEncryptionInfo) -> Result<Self, DecryptorError> {
+        let (expected_hash, aes) = decryption_cipher(info)?;
+
+        Ok(Self { inner: stream, expected_hash, sha: Sha256::default(), aes, done: false })
+    }
+}
+
+impl<S, B, E> Stream for AttachmentStreamDecryptor<S>
+where
+    S: Stream<Item = Result<B, E>> + Unpin,
+    B: AsRef<[u8]>,
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    type Item = std::io::Result<Vec<u8>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+
+        if this.done {
+            return Poll::Ready(None);
+        }
+
+        match ready!(Pin::new(&mut this.inner).poll_next(cx)) {
+            Some(Ok(chunk)) => {
+                let mut chunk = chunk.as_ref().to_vec();
+
+                this.sha.update(&chunk);
+                this.aes.apply_keystream(&mut chunk);
+
+                Poll::Ready(Some(Ok(chunk)))
+            }
+            Some(Err(e)) => {
+                this.done = true;
+                Poll::Ready(Some(Err(IoError::new(ErrorKind::Other, e))))
+            }
+            None => {
+                this.done = true;
+                let hash = this.sha.finalize_reset();
+
+                if hash.as_slice() == this.expected_hash.as_slice() {
+                    Poll::Ready(None)
+                } else {
+                    Poll::Ready(Some(Err(IoError::new(
+                        ErrorKind::Other,
+                        "Hash mismatch while decrypting",
+                    ))))
+                }
+            }
+        }
+    }
+}
+
+/// A wrapper that transparently encrypts the chunks of a [`Stream`] as a
+/// Matrix attachment.
+///
+/// This is the streaming counterpart of the [`AttachmentEncryptor`], once the
+/// stream is exhausted a call to [`finish()`](#method.finish) is necessary to
+/// get the decryption key for the data.
+pub struct AttachmentStreamEncryptor<S> {
+    inner: S,
+    web_key: JsonWebKey,
+    iv: Base64,
+    aes: Aes256Ctr,
+    sha: Sha256,
+}
+
+impl<S> std::fmt::Debug for AttachmentStreamEncryptor<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AttachmentStreamEncryptor").finish_non_exhaustive()
+    }
+}
+
+impl<S> AttachmentStreamEncryptor<S> {
+    /// Wrap the given stream, encrypting all the chunks it yields.
+    ///
+    /// # Panics
+    ///
+    /// Panics if we can't generate enough random data to create a fresh
+    /// encryption key.
+    pub fn new(stream: S) -> Self {
+        let (web_key, iv, aes) = encryption_cipher();
+
+        Self { inner: stream, web_key, iv, aes, sha: Sha256::default() }
+    }
+
+    /// Consume the encryptor and get the encryption key.
+    ///
+    /// This should only be called once the stream is exhausted, otherwise the
+    /// hash of the encrypted data will be wrong.
+    pub fn finish(self) -> MediaEncryptionInfo {
+        let hash = self.sha.finalize();
+
+        MediaEncryptionInfo {
+            version: VERSION.to_owned(),
+            hashes: BTreeMap::from([(
+                "sha256".to_owned(),
+                Base64::new(hash.as_slice().to_owned()),
+            )]),
+            iv: self.iv,
+            key: self.web_key,
+        }
+    }
+}
+
+impl<S, B, E> Stream for AttachmentStreamEncryptor<S>
+where
+    S: Stream<Item = Result<B, E>> + Unpin,
+    B: AsRef<[u8]>,
+{
+    type Item = Result<Vec<u8>, E>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+
+        Poll::Ready(ready!(Pin::new(&mut this.inner).poll_next(cx)).map(|result| {
+            result.map(|chunk| {
+                let mut chunk = chunk.as_ref().to_vec();
+
+                this.aes.apply_keystream(&mut chunk);
+                this.sha.update(&chunk);
+
+                chunk
+            })
+        }))
+    }
+}
+
 /// Struct holding all the information that is needed to decrypt an encrypted
 /// file.
 #[derive(Debug, Serialize, Deserialize)]
@@ -300,11 +480,17 @@ impl From<EncryptedFile> for MediaEncryptionInfo {
 
 #[cfg(test)]
 mod tests {
-    use std::io::{Cursor, Read};
+    use std::io::{Cursor, Error as IoError, Read};
 
+    use futures_core::Stream;
+    use futures_executor::block_on;
+    use futures_util::{stream, StreamExt, TryStreamExt};
     use serde_json::json;
 
-    use super::{AttachmentDecryptor, AttachmentEncryptor, MediaEncryptionInfo};
+    use super::{
+        AttachmentDecryptor, AttachmentEncryptor, AttachmentStreamDecryptor,
+        AttachmentStreamEncryptor, MediaEncryptionInfo,
+    };
 
     const EXAMPLE_DATA: &[u8] = &[
         179, 154, 118, 127, 186, 127, 110, 33, 203, 33, 33, 134, 67, 100, 173, 46, 235, 27, 215,
@@ -378,4 +564,44 @@ mod tests {
 
         decryptor.read_to_end(&mut decrypted_data).unwrap_err();
     }
+
+    fn chunks(data: &[u8]) -> impl Stream<Item = Result<Vec<u8>, IoError>> + Unpin {
+        stream::iter(data.chunks(5).map(|c| Ok(c.to_vec())).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn stream_encrypt_decrypt_cycle() {
+        let data = "Hello world, this is a slightly longer message".to_owned();
+
+        let mut encryptor = AttachmentStreamEncryptor::new(chunks(data.as_bytes()));
+        let encrypted: Vec<Vec<u8>> = block_on(encryptor.by_ref().try_collect::<Vec<_>>()).unwrap();
+        let encrypted = encrypted.concat();
+        let key = encryptor.finish();
+        assert_ne!(encrypted.as_slice(), data.as_bytes());
+
+        let decryptor = AttachmentStreamDecryptor::new(chunks(&encrypted), key).unwrap();
+        let decrypted_data = block_on(decryptor.try_collect::<Vec<_>>()).unwrap().concat();
+
+        let decrypted = String::from_utf8(decrypted_data).unwrap();
+
+        assert_eq!(data, decrypted);
+    }
+
+    #[test]
+    fn stream_real_decrypt() {
+        let decryptor =
+            AttachmentStreamDecryptor::new(chunks(EXAMPLE_DATA), example_key()).unwrap();
+        let decrypted_data = block_on(decryptor.try_collect::<Vec<_>>()).unwrap().concat();
+        let decrypted = String::from_utf8(decrypted_data).unwrap();
+
+        assert_eq!("It's a secret to everybody", decrypted);
+    }
+
+    #[test]
+    fn stream_decrypt_invalid_hash() {
+        let decryptor =
+            AttachmentStreamDecryptor::new(chunks(b"fake message"), example_key()).unwrap();
+
+        block_on(decryptor.try_collect::<Vec<_>>()).unwrap_err();
+    }
 }
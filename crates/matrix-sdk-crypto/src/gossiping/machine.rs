@@ -20,6 +20,8 @@
 // If we don't trust the device store an object that remembers the request and
 // let the users introspect that object.
 
+#[cfg(feature = "automatic-room-key-forwarding")]
+use std::sync::RwLock as StdRwLock;
 use std::{
     collections::BTreeMap,
     sync::{atomic::AtomicBool, Arc},
@@ -27,6 +29,10 @@ use std::{
 
 use atomic::Ordering;
 use dashmap::{mapref::entry::Entry, DashMap, DashSet};
+#[cfg(feature = "automatic-room-key-forwarding")]
+use futures_core::Stream;
+#[cfg(feature = "automatic-room-key-forwarding")]
+use futures_util::StreamExt;
 use ruma::{
     api::client::keys::claim_keys::v3::Request as KeysClaimRequest,
     events::secret::request::{
@@ -35,10 +41,16 @@ use ruma::{
     DeviceId, DeviceKeyAlgorithm, OwnedDeviceId, OwnedTransactionId, OwnedUserId, RoomId,
     TransactionId, UserId,
 };
+#[cfg(feature = "automatic-room-key-forwarding")]
+use tokio::sync::broadcast;
+#[cfg(feature = "automatic-room-key-forwarding")]
+use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream};
 use tracing::{debug, field::debug, info, instrument, trace, warn, Span};
 use vodozemac::{megolm::SessionOrdering, Curve25519PublicKey};
 
 use super::{GossipRequest, GossippedSecret, RequestEvent, RequestInfo, SecretInfo, WaitQueue};
+#[cfg(feature = "automatic-room-key-forwarding")]
+use super::{KeyForwardDecision, KeyForwardingAuditEntry, KeyForwardingPolicy};
 use crate::{
     error::{EventError, OlmError, OlmResult},
     olm::{InboundGroupSession, Session},
@@ -56,6 +68,14 @@ use crate::{
     Device, MegolmError,
 };
 
+/// The number of key forwarding audit entries that are kept for the slowest
+/// reader of the audit stream, older entries are dropped for that reader.
+///
+/// Room key requests often come in bursts, e.g. when a new device of the user
+/// requests all the keys of the rooms it just saw.
+#[cfg(feature = "automatic-room-key-forwarding")]
+const KEY_FORWARDING_AUDIT_CAPACITY: usize = 1000;
+
 #[derive(Clone, Debug)]
 pub(crate) struct GossipMachine {
     inner: Arc<GossipMachineInner>,
@@ -73,6 +93,10 @@ pub(crate) struct GossipMachineInner {
     wait_queue: WaitQueue,
     users_for_key_claim: Arc<DashMap<OwnedUserId, DashSet<OwnedDeviceId>>>,
     room_key_forwarding_enabled: AtomicBool,
+    #[cfg(feature = "automatic-room-key-forwarding")]
+    key_forwarding_policy: StdRwLock<KeyForwardingPolicy>,
+    #[cfg(feature = "automatic-room-key-forwarding")]
+    key_forwarding_audit_sender: broadcast::Sender<KeyForwardingAuditEntry>,
 }
 
 impl GossipMachine {
@@ -98,6 +122,10 @@ impl GossipMachine {
                 wait_queue: WaitQueue::new(),
                 users_for_key_claim,
                 room_key_forwarding_enabled,
+                #[cfg(feature = "automatic-room-key-forwarding")]
+                key_forwarding_policy: Default::default(),
+                #[cfg(feature = "automatic-room-key-forwarding")]
+                key_forwarding_audit_sender: broadcast::Sender::new(KEY_FORWARDING_AUDIT_CAPACITY),
             }),
         }
     }
@@ -111,6 +139,51 @@ impl GossipMachine {
         self.inner.room_key_forwarding_enabled.load(Ordering::SeqCst)
     }
 
+    #[cfg(feature = "automatic-room-key-forwarding")]
+    pub fn set_key_forwarding_policy(&self, policy: KeyForwardingPolicy) {
+        *self.inner.key_forwarding_policy.write().unwrap() = policy;
+    }
+
+    #[cfg(feature = "automatic-room-key-forwarding")]
+    pub fn key_forwarding_policy(&self) -> KeyForwardingPolicy {
+        self.inner.key_forwarding_policy.read().unwrap().clone()
+    }
+
+    #[cfg(feature = "automatic-room-key-forwarding")]
+    pub fn key_forwarding_audit_stream(&self) -> impl Stream<Item = KeyForwardingAuditEntry> {
+        let stream = BroadcastStream::new(self.inner.key_forwarding_audit_sender.subscribe());
+
+        stream.filter_map(|result| async move {
+            match result {
+                Ok(entry) => Some(entry),
+                Err(BroadcastStreamRecvError::Lagged(lag)) => {
+                    warn!("key_forwarding_audit_stream missed {lag} entries");
+                    None
+                }
+            }
+        })
+    }
+
+    /// Record the decision taken for an incoming room key request in the audit
+    /// stream.
+    #[cfg(feature = "automatic-room-key-forwarding")]
+    fn audit_key_request(
+        &self,
+        event: &RoomKeyRequestEvent,
+        room_id: &RoomId,
+        session_id: &str,
+        decision: Result<Option<u32>, KeyForwardDecision>,
+    ) {
+        // Sending only fails if nobody is listening, which is fine.
+        let _ = self.inner.key_forwarding_audit_sender.send(KeyForwardingAuditEntry {
+            user_id: event.sender.clone(),
+            device_id: event.content.requesting_device_id.clone(),
+            room_id: room_id.to_owned(),
+            session_id: session_id.to_owned(),
+            decision,
+        });
+    }
+
     /// Load stored outgoing requests that were not yet sent out.
     async fn load_outgoing_requests(&self) -> Result<Vec<OutgoingRequest>, CryptoStoreError> {
         Ok(self
@@ -384,19 +457,26 @@ impl GossipMachine {
         event: &RoomKeyRequestEvent,
         session: &InboundGroupSession,
     ) -> OlmResult<Option<Session>> {
-        use super::KeyForwardDecision;
-
         let device =
             self.inner.store.get_device(&event.sender, &event.content.requesting_device_id).await?;
 
         let Some(device) = device else {
             warn!("Received a key request from an unknown device");
+            self.audit_key_request(
+                event,
+                session.room_id(),
+                session.session_id(),
+                Err(KeyForwardDecision::UnknownDevice),
+            );
             self.inner.store.mark_user_as_changed(&event.sender).await?;
 
             return Ok(None);
         };
 
-        match self.should_share_key(&device, session).await {
+        let decision = self.should_share_key(&device, session).await;
+        self.audit_key_request(event, session.room_id(), session.session_id(), decision.clone());
+
+        match decision {
             Ok(message_index) => {
                 self.try_to_forward_room_key(event, device, session, message_index).await
             }
@@ -434,12 +514,30 @@ impl GossipMachine {
         room_id: &RoomId,
         session_id: &str,
     ) -> OlmResult<Option<Session>> {
+        if !self.inner.room_key_forwarding_enabled.load(Ordering::SeqCst) {
+            debug!("Received a room key request, but room key forwarding has been turned off");
+            self.audit_key_request(
+                event,
+                room_id,
+                session_id,
+                Err(KeyForwardDecision::ForwardingDisabled),
+            );
+
+            return Ok(None);
+        }
+
         let session = self.inner.store.get_inbound_group_session(room_id, session_id).await?;
 
         if let Some(s) = session {
             self.answer_room_key_request(event, &s).await
         } else {
             debug!("Received a room key request for an unknown inbound group session",);
+            self.audit_key_request(
+                event,
+                room_id,
+                session_id,
+                Err(KeyForwardDecision::UnknownSession),
+            );
 
             Ok(None)
         }
@@ -450,34 +548,26 @@ impl GossipMachine {
     async fn handle_key_request(&self, event: &RoomKeyRequestEvent) -> OlmResult<Option<Session>> {
         use crate::types::events::room_key_request::{Action, RequestedKeyInfo};
 
-        if self.inner.room_key_forwarding_enabled.load(Ordering::SeqCst) {
-            match &event.content.action {
-                Action::Request(info) => match info {
-                    RequestedKeyInfo::MegolmV1AesSha2(i) => {
-                        self.handle_supported_key_request(event, &i.room_id, &i.session_id).await
-                    }
-                    #[cfg(feature = "experimental-algorithms")]
-                    RequestedKeyInfo::MegolmV2AesSha2(i) => {
-                        self.handle_supported_key_request(event, &i.room_id, &i.session_id).await
-                    }
-                    RequestedKeyInfo::Unknown(i) => {
-                        debug!(
-                            sender = ?event.sender,
-                            algorithm = ?i.algorithm,
-                            "Received a room key request for a unsupported algorithm"
-                        );
-                        Ok(None)
-                    }
-                },
-                // We ignore cancellations here since there's nothing to serve.
-                Action::Cancellation => Ok(None),
-            }
-        } else {
-            debug!(
-                sender = ?event.sender,
-                "Received a room key request, but room key forwarding has been turned off"
-            );
-            Ok(None)
+        match &event.content.action {
+            Action::Request(info) => match info {
+                RequestedKeyInfo::MegolmV1AesSha2(i) => {
+                    self.handle_supported_key_request(event, &i.room_id, &i.session_id).await
+                }
+                #[cfg(feature = "experimental-algorithms")]
+                RequestedKeyInfo::MegolmV2AesSha2(i) => {
+                    self.handle_supported_key_request(event, &i.room_id, &i.session_id).await
+                }
+                RequestedKeyInfo::Unknown(i) => {
+                    debug!(
+                        sender = ?event.sender,
+                        algorithm = ?i.algorithm,
+                        "Received a room key request for a unsupported algorithm"
+                    );
+                    Ok(None)
+                }
+            },
+            // We ignore cancellations here since there's nothing to serve.
+            Action::Cancellation => Ok(None),
         }
     }
 
@@ -534,7 +624,8 @@ impl GossipMachine {
 
     /// Check if it's ok to share a session with the given device.
     ///
-    /// The logic for this is currently as follows:
+    /// The decision is taken by the configured [`KeyForwardingPolicy`], the
+    /// default one works as follows:
     ///
     /// * Share the session in full, starting from the earliest known index, if
     /// the requesting device is our own, trusted (verified) device.
@@ -570,37 +661,15 @@ impl GossipMachine {
         &self,
         device: &Device,
         session: &InboundGroupSession,
-    ) -> Result<Option<u32>, super::KeyForwardDecision> {
-        use super::KeyForwardDecision;
-        use crate::olm::ShareState;
-
+    ) -> Result<Option<u32>, KeyForwardDecision> {
         let outbound_session = self
             .inner
             .outbound_group_sessions
             .get_with_id(session.room_id(), session.session_id())
             .await;
+        let share_state = outbound_session.map(|outbound| outbound.is_shared_with(device));
 
-        // If this is our own, verified device, we share the entire session from the
-        // earliest known index.
-        if device.user_id() == self.user_id() && device.is_verified() {
-            Ok(None)
-        // Otherwise, if the records show we previously shared with this device,
-        // we'll reshare the session from the index we previously shared
-        // at. For this, we need an outbound session because this
-        // information is recorded there.
-        } else if let Some(outbound) = outbound_session {
-            match outbound.is_shared_with(device) {
-                ShareState::Shared(message_index) => Ok(Some(message_index)),
-                ShareState::SharedButChangedSenderKey => Err(KeyForwardDecision::ChangedSenderKey),
-                ShareState::NotShared => Err(KeyForwardDecision::OutboundSessionNotShared),
-            }
-        // Otherwise, there's not enough info to decide if we can safely share
-        // the session.
-        } else if device.user_id() == self.user_id() {
-            Err(KeyForwardDecision::UntrustedDevice)
-        } else {
-            Err(KeyForwardDecision::MissingOutboundSession)
-        }
+        self.key_forwarding_policy().decide(self.user_id(), device, session, share_state)
     }
 
     /// Check if it's ok, or rather if it makes sense to automatically request
@@ -1051,7 +1120,7 @@ mod tests {
     use super::GossipMachine;
     #[cfg(feature = "automatic-room-key-forwarding")]
     use crate::{
-        gossiping::KeyForwardDecision,
+        gossiping::{KeyForwardDecision, KeyForwardRequest, KeyForwardingPolicy},
         olm::OutboundGroupSession,
         types::{
             events::{
@@ -1562,6 +1631,64 @@ mod tests {
         assert_matches!(machine.should_share_key(&own_device, &other_inbound).await, Ok(None));
     }
 
+    #[async_test]
+    #[cfg(feature = "automatic-room-key-forwarding")]
+    async fn key_forwarding_policies() {
+        let machine = get_machine().await;
+        let account = account();
+
+        let own_device =
+            machine.inner.store.get_device(alice_id(), alice2_device_id()).await.unwrap().unwrap();
+        let bob_device = ReadOnlyDevice::from_account(&bob_account()).await;
+        machine.inner.store.save_devices(&[bob_device]).await.unwrap();
+        let bob_device =
+            machine.inner.store.get_device(bob_id(), bob_device_id()).await.unwrap().unwrap();
+
+        let (_, inbound) = account.create_group_session_pair_with_defaults(room_id()).await;
+
+        own_device.set_trust_state(LocalTrust::Verified);
+
+        // Nothing gets shared if forwarding is refused altogether.
+        machine.set_key_forwarding_policy(KeyForwardingPolicy::Never);
+        assert_matches!(
+            machine.should_share_key(&own_device, &inbound).await,
+            Err(KeyForwardDecision::RefusedByPolicy)
+        );
+
+        // Our own verified device gets the session, other users don't.
+        machine.set_key_forwarding_policy(KeyForwardingPolicy::OwnVerifiedDevicesOnly);
+        assert_matches!(machine.should_share_key(&own_device, &inbound).await, Ok(None));
+        assert_matches!(
+            machine.should_share_key(&bob_device, &inbound).await,
+            Err(KeyForwardDecision::RefusedByPolicy)
+        );
+
+        // Our own device doesn't get the session if we never shared it with it.
+        machine.set_key_forwarding_policy(KeyForwardingPolicy::PreviouslySharedOnly);
+        assert_matches!(
+            machine.should_share_key(&own_device, &inbound).await,
+            Err(KeyForwardDecision::MissingOutboundSession)
+        );
+
+        // A custom policy can decide on its own.
+        fn other_users_only(
+            request: &KeyForwardRequest<'_>,
+        ) -> Result<Option<u32>, KeyForwardDecision> {
+            if request.device.user_id() == request.own_user_id {
+                Err(KeyForwardDecision::RefusedByPolicy)
+            } else {
+                Ok(request.shared_message_index.or(Some(0)))
+            }
+        }
+
+        machine.set_key_forwarding_policy(KeyForwardingPolicy::Custom(Arc::new(other_users_only)));
+        assert_matches!(
+            machine.should_share_key(&own_device, &inbound).await,
+            Err(KeyForwardDecision::RefusedByPolicy)
+        );
+        assert_matches!(machine.should_share_key(&bob_device, &inbound).await, Ok(Some(0)));
+    }
+
     #[cfg(feature = "automatic-room-key-forwarding")]
     async fn key_share_cycle(algorithm: EventEncryptionAlgorithm) {
         let (alice_machine, alice_account, group_session, bob_machine) =
@@ -1686,6 +1813,42 @@ mod tests {
         key_share_cycle(EventEncryptionAlgorithm::MegolmV2AesSha2).await;
     }
 
+    #[async_test]
+    #[cfg(feature = "automatic-room-key-forwarding")]
+    async fn key_forwarding_audit_stream() {
+        use futures_util::{FutureExt, StreamExt};
+
+        let (alice_machine, _, group_session, bob_machine) =
+            machines_for_key_share(alice_id(), true, EventEncryptionAlgorithm::MegolmV1AesSha2)
+                .await;
+
+        let mut audit_stream = bob_machine.key_forwarding_audit_stream();
+
+        let requests = alice_machine.outgoing_to_device_requests().await.unwrap();
+        let event = request_to_event(alice_id(), alice_id(), &requests[0]);
+
+        bob_machine.receive_incoming_key_request(&event);
+        bob_machine.collect_incoming_key_requests().await.unwrap();
+
+        // The decision taken for the request is reported on the audit stream.
+        let entry = audit_stream.next().now_or_never().flatten().unwrap();
+        assert_eq!(entry.user_id, alice_id());
+        assert_eq!(entry.device_id, alice_device_id());
+        assert_eq!(entry.room_id, room_id());
+        assert_eq!(entry.session_id, group_session.session_id());
+        assert_matches!(entry.decision, Ok(_));
+
+        // Refused requests are reported as well.
+        bob_machine.set_key_forwarding_policy(KeyForwardingPolicy::Never);
+        bob_machine.receive_incoming_key_request(&event);
+        bob_machine.collect_incoming_key_requests().await.unwrap();
+
+        let entry = audit_stream.next().now_or_never().flatten().unwrap();
+        assert_eq!(entry.session_id, group_session.session_id());
+        assert_matches!(entry.decision, Err(KeyForwardDecision::RefusedByPolicy));
+        assert!(audit_stream.next().now_or_never().is_none());
+    }
+
     #[async_test]
     async fn secret_share_cycle() {
         let alice_machine = get_machine().await;
@@ -18,6 +18,8 @@ use std::sync::Arc;
 
 use dashmap::{DashMap, DashSet};
 pub(crate) use machine::GossipMachine;
+#[cfg(feature = "automatic-room-key-forwarding")]
+use ruma::OwnedRoomId;
 use ruma::{
     events::{
         room_key_request::{Action, ToDeviceRoomKeyRequestEventContent},
@@ -33,6 +35,8 @@ use ruma::{
 };
 use serde::{Deserialize, Serialize};
 
+#[cfg(feature = "automatic-room-key-forwarding")]
+use crate::olm::{InboundGroupSession, ShareState};
 use crate::{
     requests::{OutgoingRequest, ToDeviceRequest},
     types::events::{
@@ -78,6 +82,214 @@ pub enum KeyForwardDecision {
     /// accidentally or maliciously changed their curve25519 sender key.
     #[error("the device has changed their curve25519 sender key")]
     ChangedSenderKey,
+    /// The configured [`KeyForwardingPolicy`] doesn't allow the session to be
+    /// shared with the requesting device.
+    #[error("the key forwarding policy doesn't allow sharing with the requesting device")]
+    RefusedByPolicy,
+    /// Room key forwarding has been turned off.
+    #[error("room key forwarding is disabled")]
+    ForwardingDisabled,
+    /// The requesting device isn't known to us.
+    #[error("the requesting device is unknown")]
+    UnknownDevice,
+    /// The requested session isn't known to us.
+    #[error("the requested session is unknown")]
+    UnknownSession,
+}
+
+/// The policy deciding which incoming room key requests should be answered.
+#[cfg(feature = "automatic-room-key-forwarding")]
+#[derive(Clone, Default)]
+pub enum KeyForwardingPolicy {
+    /// Share the session in full with our own verified devices, and share it
+    /// with other devices from the message index we originally shared it at,
+    /// if we did.
+    #[default]
+    Default,
+    /// Never answer room key requests.
+    Never,
+    /// Only share sessions with our own verified devices.
+    OwnVerifiedDevicesOnly,
+    /// Only share sessions with devices we originally shared them with,
+    /// starting from the message index we shared them at. This applies to our
+    /// own devices as well.
+    PreviouslySharedOnly,
+    /// Let the given closure decide.
+    ///
+    /// The closure returns the message index the session should be shared
+    /// from, `None` meaning the earliest known index, or the reason why the
+    /// session must not be shared.
+    ///
+    /// Requests from devices that changed their Curve25519 sender key since
+    /// the session was shared with them are always refused, before the closure
+    /// gets called.
+    ///
+    /// The closure can't be persisted in the store: a machine restored from
+    /// the store uses [`KeyForwardingPolicy::Never`] instead, until the policy
+    /// is set again.
+    Custom(
+        Arc<
+            dyn Fn(&KeyForwardRequest<'_>) -> Result<Option<u32>, KeyForwardDecision> + Send + Sync,
+        >,
+    ),
+}
+
+#[cfg(feature = "automatic-room-key-forwarding")]
+impl std::fmt::Debug for KeyForwardingPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Default => write!(f, "Default"),
+            Self::Never => write!(f, "Never"),
+            Self::OwnVerifiedDevicesOnly => write!(f, "OwnVerifiedDevicesOnly"),
+            Self::PreviouslySharedOnly => write!(f, "PreviouslySharedOnly"),
+            Self::Custom(_) => write!(f, "Custom"),
+        }
+    }
+}
+
+#[cfg(feature = "automatic-room-key-forwarding")]
+impl KeyForwardingPolicy {
+    /// Decide if the given session should be shared with the given device.
+    ///
+    /// `share_state` is the information the outbound session has about the
+    /// requesting device, if we still have the outbound session.
+    pub(crate) fn decide(
+        &self,
+        own_user_id: &UserId,
+        device: &Device,
+        session: &InboundGroupSession,
+        share_state: Option<ShareState>,
+    ) -> Result<Option<u32>, KeyForwardDecision> {
+        let is_own_device = device.user_id() == own_user_id;
+
+        let previously_shared = || match share_state {
+            Some(ShareState::Shared(message_index)) => Ok(Some(message_index)),
+            Some(ShareState::SharedButChangedSenderKey) => {
+                Err(KeyForwardDecision::ChangedSenderKey)
+            }
+            Some(ShareState::NotShared) => Err(KeyForwardDecision::OutboundSessionNotShared),
+            None => Err(KeyForwardDecision::MissingOutboundSession),
+        };
+
+        match self {
+            Self::Default => {
+                if is_own_device && device.is_verified() {
+                    // If this is our own, verified device, we share the entire session from
+                    // the earliest known index.
+                    Ok(None)
+                } else if share_state.is_some() {
+                    // Otherwise, if the records show we previously shared with this device,
+                    // we'll reshare the session from the index we previously shared at. For
+                    // this, we need an outbound session because this information is
+                    // recorded there.
+                    previously_shared()
+                } else if is_own_device {
+                    // Otherwise, there's not enough info to decide if we can safely share
+                    // the session.
+                    Err(KeyForwardDecision::UntrustedDevice)
+                } else {
+                    Err(KeyForwardDecision::MissingOutboundSession)
+                }
+            }
+            Self::Never => Err(KeyForwardDecision::RefusedByPolicy),
+            Self::OwnVerifiedDevicesOnly => {
+                if !is_own_device {
+                    Err(KeyForwardDecision::RefusedByPolicy)
+                } else if device.is_verified() {
+                    Ok(None)
+                } else {
+                    Err(KeyForwardDecision::UntrustedDevice)
+                }
+            }
+            Self::PreviouslySharedOnly => previously_shared(),
+            Self::Custom(decide) => {
+                let shared_message_index = match share_state {
+                    Some(ShareState::SharedButChangedSenderKey) => {
+                        return Err(KeyForwardDecision::ChangedSenderKey);
+                    }
+                    Some(ShareState::Shared(message_index)) => Some(message_index),
+                    Some(ShareState::NotShared) | None => None,
+                };
+
+                decide(&KeyForwardRequest { own_user_id, device, session, shared_message_index })
+            }
+        }
+    }
+}
+
+/// The form in which a [`KeyForwardingPolicy`] is persisted in the store.
+///
+/// The closure of a custom policy can't be persisted, such a policy is restored
+/// as [`KeyForwardingPolicy::Never`], so no session gets shared before the
+/// closure is set again.
+#[cfg(feature = "automatic-room-key-forwarding")]
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) enum StoredKeyForwardingPolicy {
+    Default,
+    Never,
+    OwnVerifiedDevicesOnly,
+    PreviouslySharedOnly,
+    Custom,
+}
+
+#[cfg(feature = "automatic-room-key-forwarding")]
+impl From<&KeyForwardingPolicy> for StoredKeyForwardingPolicy {
+    fn from(policy: &KeyForwardingPolicy) -> Self {
+        match policy {
+            KeyForwardingPolicy::Default => Self::Default,
+            KeyForwardingPolicy::Never => Self::Never,
+            KeyForwardingPolicy::OwnVerifiedDevicesOnly => Self::OwnVerifiedDevicesOnly,
+            KeyForwardingPolicy::PreviouslySharedOnly => Self::PreviouslySharedOnly,
+            KeyForwardingPolicy::Custom(_) => Self::Custom,
+        }
+    }
+}
+
+#[cfg(feature = "automatic-room-key-forwarding")]
+impl From<StoredKeyForwardingPolicy> for KeyForwardingPolicy {
+    fn from(policy: StoredKeyForwardingPolicy) -> Self {
+        match policy {
+            StoredKeyForwardingPolicy::Default => Self::Default,
+            StoredKeyForwardingPolicy::Never | StoredKeyForwardingPolicy::Custom => Self::Never,
+            StoredKeyForwardingPolicy::OwnVerifiedDevicesOnly => Self::OwnVerifiedDevicesOnly,
+            StoredKeyForwardingPolicy::PreviouslySharedOnly => Self::PreviouslySharedOnly,
+        }
+    }
+}
+
+/// The information about an incoming room key request that is given to a
+/// [`KeyForwardingPolicy::Custom`] closure.
+#[cfg(feature = "automatic-room-key-forwarding")]
+#[derive(Debug)]
+pub struct KeyForwardRequest<'a> {
+    /// Our own user ID.
+    pub own_user_id: &'a UserId,
+    /// The device that is requesting the session.
+    pub device: &'a Device,
+    /// The session that was requested.
+    pub session: &'a InboundGroupSession,
+    /// The message index at which we originally shared the session with the
+    /// requesting device, if we did and still have the information.
+    pub shared_message_index: Option<u32>,
+}
+
+/// An entry of the key forwarding audit log, describing an incoming room key
+/// request and the decision that was taken for it.
+#[cfg(feature = "automatic-room-key-forwarding")]
+#[derive(Clone, Debug)]
+pub struct KeyForwardingAuditEntry {
+    /// The user that sent the request.
+    pub user_id: OwnedUserId,
+    /// The device that sent the request.
+    pub device_id: OwnedDeviceId,
+    /// The room of the requested session.
+    pub room_id: OwnedRoomId,
+    /// The ID of the requested session.
+    pub session_id: String,
+    /// The decision that was taken: `Ok(None)` if the session is shared in
+    /// full, `Ok(Some(index))` if it is shared from the given message index,
+    /// the reason of the refusal otherwise.
+    pub decision: Result<Option<u32>, KeyForwardDecision>,
 }
 
 /// A struct describing an outgoing key request.
@@ -468,6 +468,50 @@ impl Device {
             .await
     }
 
+    /// Encrypt an event of the given type for this `Device`.
+    ///
+    /// This can be used to send arbitrary, application specific, to-device
+    /// messages over an Olm channel.
+    ///
+    /// An Olm session with the device needs to exist before this method is
+    /// called, missing sessions can be established using the
+    /// [`OlmMachine::get_missing_sessions()`] method.
+    ///
+    /// The Olm session that was used to encrypt the content will be persisted
+    /// since the encryption step ratchets the session forward. If multiple
+    /// events are encrypted for the same device, they should be sent out in
+    /// the same order they were encrypted in.
+    ///
+    /// *Note*: To encrypt an event meant for a room use the
+    /// [`OlmMachine::encrypt_room_event()`] method instead.
+    ///
+    /// # Arguments
+    ///
+    /// * `event_type` - The type of the event that should be encrypted.
+    ///
+    /// * `content` - The content of the event that should be encrypted.
+    ///
+    /// Returns the encrypted `m.room.encrypted` content that needs to be sent
+    /// to the device, or an [`OlmError::MissingSession`] error if no Olm
+    /// session with the device exists.
+    ///
+    /// [`OlmMachine::get_missing_sessions()`]: crate::OlmMachine::get_missing_sessions
+    /// [`OlmMachine::encrypt_room_event()`]: crate::OlmMachine::encrypt_room_event
+    pub async fn encrypt_event_raw(
+        &self,
+        event_type: &str,
+        content: &Value,
+    ) -> OlmResult<Raw<ToDeviceEncryptedEventContent>> {
+        let (used_session, encrypted) = self.encrypt(event_type, content.clone()).await?;
+
+        // The session got ratcheted forward, persist it so we don't reuse the
+        // same message keys.
+        let changes = Changes { sessions: vec![used_session], ..Default::default() };
+        self.verification_machine.store.save_changes(changes).await?;
+
+        Ok(encrypted)
+    }
+
     pub(crate) async fn maybe_encrypt_room_key(
         &self,
         session: OutboundGroupSession,
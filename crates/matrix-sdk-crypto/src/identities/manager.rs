@@ -617,9 +617,37 @@ impl IdentityManager {
             .await?;
         }
 
+        self.mark_verified_identities(&mut changes).await?;
+
         Ok((changes, changed_identity))
     }
 
+    /// Remember which of the given identities of other users are verified, so
+    /// we can detect when a verified user changes their identity later on.
+    async fn mark_verified_identities(&self, changes: &mut IdentityChanges) -> StoreResult<()> {
+        let own_identity = match changes.new.iter().chain(&changes.changed).find_map(|i| i.own()) {
+            Some(i) => Some(i.clone()),
+            None => {
+                self.store.get_user_identity(self.user_id()).await?.and_then(|i| i.own().cloned())
+            }
+        };
+
+        let Some(own_identity) = own_identity else { return Ok(()) };
+
+        for identity in changes.new.iter_mut().chain(changes.changed.iter_mut()) {
+            if let ReadOnlyUserIdentities::Other(identity) = identity {
+                if !identity.was_previously_verified()
+                    && own_identity.is_identity_signed(identity).is_ok()
+                {
+                    trace!(user_id = ?identity.user_id(), "Marking a user identity as verified");
+                    identity.mark_as_previously_verified();
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     /// Generate an "out-of-band" key query request for the given set of users.
     ///
     /// Unlike the regular key query requests returned by `users_for_key_query`,
@@ -53,8 +53,8 @@ pub use device::{Device, LocalTrust, ReadOnlyDevice, UserDevices};
 pub(crate) use manager::IdentityManager;
 use serde::{Deserialize, Deserializer, Serializer};
 pub use user::{
-    OwnUserIdentity, ReadOnlyOwnUserIdentity, ReadOnlyUserIdentities, ReadOnlyUserIdentity,
-    UserIdentities, UserIdentity,
+    IdentityViolation, OwnUserIdentity, ReadOnlyOwnUserIdentity, ReadOnlyUserIdentities,
+    ReadOnlyUserIdentity, UserIdentities, UserIdentity,
 };
 
 // These methods are only here because Serialize and Deserialize don't seem to
@@ -34,7 +34,7 @@ use tracing::error;
 use super::{atomic_bool_deserializer, atomic_bool_serializer};
 use crate::{
     error::SignatureError,
-    store::{Changes, IdentityChanges},
+    store::{Changes, IdentityChanges, Store},
     types::{MasterPubkey, SelfSigningPubkey, UserSigningPubkey},
     verification::VerificationMachine,
     CryptoStoreError, OutgoingVerificationRequest, ReadOnlyDevice, VerificationRequest,
@@ -187,6 +187,7 @@ pub struct UserIdentity {
     pub(crate) inner: ReadOnlyUserIdentity,
     pub(crate) own_identity: Option<ReadOnlyOwnUserIdentity>,
     pub(crate) verification_machine: VerificationMachine,
+    pub(crate) store: Store,
 }
 
 impl Deref for UserIdentity {
@@ -203,6 +204,64 @@ impl UserIdentity {
         self.own_identity.as_ref().is_some_and(|o| o.is_identity_signed(&self.inner).is_ok())
     }
 
+    /// Get the identity violation this user identity is in, if any.
+    ///
+    /// A violation is reported if the master key of the user changed since we
+    /// pinned it and the change hasn't been acknowledged using the
+    /// [`UserIdentity::pin_current_master_key()`] method, or if we verified
+    /// the user at some point but the current identity isn't verified anymore.
+    pub fn identity_violation(&self) -> Option<IdentityViolation> {
+        self.inner.violation(self.own_identity.as_ref())
+    }
+
+    /// Acknowledge a change of the master key of this user by pinning the
+    /// current master key.
+    ///
+    /// The master key of this [`UserIdentity`] gets pinned, if the identity
+    /// got updated in the meantime the new master key will still need to be
+    /// acknowledged.
+    ///
+    /// This resolves an [`IdentityViolation::PinViolation`].
+    pub async fn pin_current_master_key(&self) -> Result<(), CryptoStoreError> {
+        let master_key = self.inner.master_key.clone();
+        self.update_and_save(|identity| identity.pin_master_key(master_key)).await
+    }
+
+    /// Withdraw the verification of this user.
+    ///
+    /// After the identity of a previously verified user changed, this should
+    /// be called to accept that the user isn't verified anymore.
+    ///
+    /// This resolves an [`IdentityViolation::VerificationViolation`].
+    pub async fn withdraw_verification(&self) -> Result<(), CryptoStoreError> {
+        self.update_and_save(ReadOnlyUserIdentity::withdraw_verification).await
+    }
+
+    /// Apply the given change to the current version of this identity and
+    /// persist it.
+    ///
+    /// The identity is reloaded from the store first, this handle might be
+    /// outdated and saving it as is would revert the changes that happened
+    /// since it was fetched, e.g. a pin done through another handle.
+    async fn update_and_save(
+        &self,
+        update: impl FnOnce(&mut ReadOnlyUserIdentity),
+    ) -> Result<(), CryptoStoreError> {
+        let mut identity = match self.store.get_user_identity(self.user_id()).await? {
+            Some(ReadOnlyUserIdentities::Other(identity)) => identity,
+            _ => self.inner.clone(),
+        };
+
+        update(&mut identity);
+
+        let changes = Changes {
+            identities: IdentityChanges { changed: vec![identity.into()], new: vec![] },
+            ..Default::default()
+        };
+
+        self.store.save_changes(changes).await
+    }
+
     /// Manually verify this user.
     ///
     /// This method will attempt to sign the user identity using our private
@@ -360,6 +419,31 @@ pub struct ReadOnlyUserIdentity {
     user_id: OwnedUserId,
     pub(crate) master_key: MasterPubkey,
     self_signing_key: SelfSigningPubkey,
+    /// The master key we pinned for this user.
+    ///
+    /// This is the first master key we have seen for the user, unless a
+    /// change was acknowledged or the identity got verified since. Identities
+    /// that were stored before pinning was introduced don't have this set, in
+    /// which case their current master key is considered to be pinned.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pinned_master_key: Option<MasterPubkey>,
+    /// Did we verify this identity at some point.
+    #[serde(default)]
+    previously_verified: bool,
+}
+
+/// The kind of identity violation a user identity can be in.
+///
+/// Identity violations need to be acknowledged by the user, take a look at the
+/// [`UserIdentity::pin_current_master_key()`] and
+/// [`UserIdentity::withdraw_verification()`] methods.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IdentityViolation {
+    /// The master key of the user changed since we pinned it.
+    PinViolation,
+    /// The user was verified at some point, but their current identity isn't
+    /// verified anymore.
+    VerificationViolation,
 }
 
 impl ReadOnlyUserIdentity {
@@ -379,7 +463,13 @@ impl ReadOnlyUserIdentity {
     ) -> Result<Self, SignatureError> {
         master_key.verify_subkey(&self_signing_key)?;
 
-        Ok(Self { user_id: master_key.user_id().into(), master_key, self_signing_key })
+        Ok(Self {
+            user_id: master_key.user_id().into(),
+            pinned_master_key: Some(master_key.clone()),
+            master_key,
+            self_signing_key,
+            previously_verified: false,
+        })
     }
 
     #[cfg(test)]
@@ -388,7 +478,13 @@ impl ReadOnlyUserIdentity {
         let self_signing_key =
             identity.self_signing_key.lock().await.as_ref().unwrap().public_key.clone();
 
-        Self { user_id: identity.user_id().into(), master_key, self_signing_key }
+        Self {
+            user_id: identity.user_id().into(),
+            pinned_master_key: Some(master_key.clone()),
+            master_key,
+            self_signing_key,
+            previously_verified: false,
+        }
     }
 
     /// Get the user id of this identity.
@@ -406,8 +502,70 @@ impl ReadOnlyUserIdentity {
         &self.self_signing_key
     }
 
+    /// Get the master key we pinned for this user.
+    pub fn pinned_master_key(&self) -> &MasterPubkey {
+        self.pinned_master_key.as_ref().unwrap_or(&self.master_key)
+    }
+
+    /// Has the master key of this user changed since we pinned it.
+    pub fn has_pin_violation(&self) -> bool {
+        self.pinned_master_key() != &self.master_key
+    }
+
+    /// Did we verify this identity at some point.
+    pub fn was_previously_verified(&self) -> bool {
+        self.previously_verified
+    }
+
+    /// Pin the current master key of the identity.
+    pub(crate) fn pin(&mut self) {
+        self.pin_master_key(self.master_key.clone());
+    }
+
+    /// Pin the given master key of the identity.
+    pub(crate) fn pin_master_key(&mut self, master_key: MasterPubkey) {
+        self.pinned_master_key = Some(master_key);
+    }
+
+    /// Remember that this identity was verified, this also pins the current
+    /// master key.
+    pub(crate) fn mark_as_previously_verified(&mut self) {
+        self.previously_verified = true;
+        self.pin();
+    }
+
+    /// Forget that this identity was verified at some point.
+    pub(crate) fn withdraw_verification(&mut self) {
+        self.previously_verified = false;
+    }
+
+    /// Get the identity violation this identity is in, if any.
+    ///
+    /// # Arguments
+    ///
+    /// * `own_identity` - Our own user identity, used to check if this identity
+    ///   is currently verified.
+    pub(crate) fn violation(
+        &self,
+        own_identity: Option<&ReadOnlyOwnUserIdentity>,
+    ) -> Option<IdentityViolation> {
+        let is_verified = own_identity.is_some_and(|o| o.is_identity_signed(self).is_ok());
+
+        if self.previously_verified && !is_verified {
+            Some(IdentityViolation::VerificationViolation)
+        } else if self.has_pin_violation() {
+            Some(IdentityViolation::PinViolation)
+        } else {
+            None
+        }
+    }
+
     /// Update the identity with a new master key and self signing key.
     ///
+    /// Note: The previously pinned master key is kept if the master keys
+    /// differ, the change needs to be acknowledged by pinning the new master
+    /// key.
+    ///
     /// # Arguments
     ///
     /// * `master_key` - The new master key of the user identity.
@@ -422,6 +580,10 @@ impl ReadOnlyUserIdentity {
     ) -> Result<(), SignatureError> {
         master_key.verify_subkey(&self_signing_key)?;
 
+        if self.pinned_master_key.is_none() {
+            self.pinned_master_key = Some(self.master_key.clone());
+        }
+
         self.master_key = master_key;
         self.self_signing_key = self_signing_key;
 
@@ -715,21 +877,26 @@ pub(crate) mod tests {
     use std::{collections::HashMap, sync::Arc};
 
     use assert_matches::assert_matches;
+    use futures_util::{pin_mut, FutureExt, StreamExt};
     use matrix_sdk_test::async_test;
-    use ruma::{device_id, user_id};
+    use ruma::{device_id, user_id, UserId};
     use serde_json::{json, Value};
     use tokio::sync::Mutex;
 
     use super::{
         testing::{device, get_other_identity, get_own_identity},
-        ReadOnlyOwnUserIdentity, ReadOnlyUserIdentities,
+        IdentityViolation, ReadOnlyOwnUserIdentity, ReadOnlyUserIdentities, ReadOnlyUserIdentity,
+        UserIdentity,
     };
     use crate::{
         identities::{manager::testing::own_key_query, Device},
         olm::{PrivateCrossSigningIdentity, ReadOnlyAccount},
-        store::{IntoCryptoStore, MemoryStore},
+        store::{
+            Changes, IdentityChanges, IdentityViolationUpdate, IntoCryptoStore, MemoryStore, Store,
+        },
         types::{CrossSigningKey, MasterPubkey, SelfSigningPubkey, UserSigningPubkey},
         verification::VerificationMachine,
+        OlmMachine,
     };
 
     #[test]
@@ -799,6 +966,105 @@ pub(crate) mod tests {
         assert!(!first.is_verified());
     }
 
+    #[async_test]
+    async fn other_identity_pin_violation() {
+        let mut identity = get_other_identity();
+        assert!(!identity.has_pin_violation());
+        assert_eq!(identity.violation(None), None);
+
+        let private_identity =
+            PrivateCrossSigningIdentity::new(identity.user_id().to_owned()).await;
+        let new_identity = ReadOnlyUserIdentity::from_private(&private_identity).await;
+        identity
+            .update(new_identity.master_key().clone(), new_identity.self_signing_key().clone())
+            .unwrap();
+
+        assert!(identity.has_pin_violation());
+        assert_eq!(identity.violation(None), Some(IdentityViolation::PinViolation));
+
+        identity.pin();
+        assert!(!identity.has_pin_violation());
+        assert_eq!(identity.violation(None), None);
+
+        identity.mark_as_previously_verified();
+        assert_eq!(identity.violation(None), Some(IdentityViolation::VerificationViolation));
+
+        identity.withdraw_verification();
+        assert_eq!(identity.violation(None), None);
+    }
+
+    async fn save_other_identity(store: &Store, identity: &ReadOnlyUserIdentity) {
+        let changes = Changes {
+            identities: IdentityChanges { changed: vec![identity.clone().into()], new: vec![] },
+            ..Default::default()
+        };
+
+        store.save_changes(changes).await.unwrap();
+    }
+
+    async fn other_identity_handle(store: &Store, user_id: &UserId) -> UserIdentity {
+        store.get_identity(user_id).await.unwrap().unwrap().other().unwrap()
+    }
+
+    #[async_test]
+    async fn other_identity_pinning_through_handles() {
+        let machine = OlmMachine::new(user_id!("@example:localhost"), device_id!("DEVICEID")).await;
+        let store = machine.store();
+        let violations = store.identity_violations_stream();
+        pin_mut!(violations);
+
+        let identity = get_other_identity();
+        let user_id = identity.user_id().to_owned();
+
+        save_other_identity(store, &identity).await;
+        assert_eq!(
+            violations.next().now_or_never().flatten().unwrap(),
+            vec![IdentityViolationUpdate { user_id: user_id.clone(), violation: None }]
+        );
+
+        let stale_handle = other_identity_handle(store, &user_id).await;
+
+        // The user, which we verified in the past, changes their identity.
+        let private_identity = PrivateCrossSigningIdentity::new(user_id.clone()).await;
+        let new_identity = ReadOnlyUserIdentity::from_private(&private_identity).await;
+        let mut changed_identity = identity.clone();
+        changed_identity
+            .update(new_identity.master_key().clone(), new_identity.self_signing_key().clone())
+            .unwrap();
+        changed_identity.previously_verified = true;
+
+        save_other_identity(store, &changed_identity).await;
+        assert_eq!(
+            violations.next().now_or_never().flatten().unwrap(),
+            vec![IdentityViolationUpdate {
+                user_id: user_id.clone(),
+                violation: Some(IdentityViolation::VerificationViolation),
+            }]
+        );
+
+        // Pinning through a handle fetched before the change pins the old master
+        // key, it doesn't revert the identity to the old master key.
+        stale_handle.pin_current_master_key().await.unwrap();
+
+        let handle = other_identity_handle(store, &user_id).await;
+        assert_eq!(handle.master_key(), new_identity.master_key());
+        assert!(handle.has_pin_violation());
+        assert!(handle.was_previously_verified());
+
+        // Acknowledging both violations through different handles keeps both
+        // changes.
+        let first_handle = other_identity_handle(store, &user_id).await;
+        let second_handle = other_identity_handle(store, &user_id).await;
+
+        first_handle.pin_current_master_key().await.unwrap();
+        second_handle.withdraw_verification().await.unwrap();
+
+        let handle = other_identity_handle(store, &user_id).await;
+        assert!(!handle.has_pin_violation());
+        assert!(!handle.was_previously_verified());
+        assert_eq!(handle.identity_violation(), None);
+    }
+
     #[async_test]
     async fn own_device_with_private_identity() {
         let response = own_key_query();
@@ -26,6 +26,7 @@ mod identities;
 mod machine;
 pub mod olm;
 pub mod requests;
+pub mod secret_storage;
 mod session_manager;
 pub mod store;
 pub mod types;
@@ -72,12 +73,16 @@ impl RoomKeyImportResult {
 pub use error::{EventError, MegolmError, OlmError, SessionCreationError, SignatureError};
 pub use file_encryption::{
     decrypt_room_key_export, encrypt_room_key_export, AttachmentDecryptor, AttachmentEncryptor,
-    DecryptorError, KeyExportError, MediaEncryptionInfo,
+    AttachmentStreamDecryptor, AttachmentStreamEncryptor, DecryptorError, KeyExportError,
+    MediaEncryptionInfo,
 };
-pub use gossiping::{GossipRequest, GossippedSecret};
+pub use gossiping::{GossipRequest, GossippedSecret, KeyForwardDecision};
+#[cfg(feature = "automatic-room-key-forwarding")]
+pub use gossiping::{KeyForwardRequest, KeyForwardingAuditEntry, KeyForwardingPolicy};
 pub use identities::{
-    Device, LocalTrust, OwnUserIdentity, ReadOnlyDevice, ReadOnlyOwnUserIdentity,
-    ReadOnlyUserIdentities, ReadOnlyUserIdentity, UserDevices, UserIdentities, UserIdentity,
+    Device, IdentityViolation, LocalTrust, OwnUserIdentity, ReadOnlyDevice,
+    ReadOnlyOwnUserIdentity, ReadOnlyUserIdentities, ReadOnlyUserIdentity, UserDevices,
+    UserIdentities, UserIdentity,
 };
 pub use machine::{EncryptionSyncChanges, OlmMachine};
 #[cfg(feature = "qrcode")]
@@ -87,8 +92,10 @@ pub use requests::{
     IncomingResponse, KeysBackupRequest, KeysQueryRequest, OutgoingRequest, OutgoingRequests,
     OutgoingVerificationRequest, RoomMessageRequest, ToDeviceRequest, UploadSigningKeysRequest,
 };
+pub use session_manager::{CollectStrategy, ExclusionReason, RoomKeySharingReport};
 pub use store::{
-    CrossSigningKeyExport, CryptoStoreError, SecretImportError, SecretInfo, TrackedUser,
+    CrossSigningKeyExport, CryptoStoreError, PruneReport, PruneSettings, RoomKeyRetention,
+    SecretImportError, SecretInfo, TrackedUser,
 };
 pub use verification::{
     format_emojis, AcceptSettings, AcceptedProtocols, CancelInfo, Emoji, EmojiShortAuthString, Sas,
@@ -19,6 +19,7 @@ use std::{
 };
 
 use dashmap::DashMap;
+use futures_core::Stream;
 use matrix_sdk_common::deserialized_responses::{
     AlgorithmInfo, DeviceLinkProblem, EncryptionInfo, TimelineEvent, VerificationLevel,
     VerificationState,
@@ -36,9 +37,11 @@ use ruma::{
     },
     assign,
     events::{
-        secret::request::SecretName, AnyMessageLikeEvent, AnyToDeviceEvent, MessageLikeEventContent,
+        secret::request::SecretName, AnyMessageLikeEvent, AnyToDeviceEvent,
+        MessageLikeEventContent, ToDeviceEventType,
     },
     serde::Raw,
+    to_device::DeviceIdOrAllDevices,
     DeviceId, DeviceKeyAlgorithm, OwnedDeviceId, OwnedDeviceKeyId, OwnedTransactionId, OwnedUserId,
     RoomId, TransactionId, UInt, UserId,
 };
@@ -67,10 +70,11 @@ use crate::{
         SessionType,
     },
     requests::{IncomingResponse, OutgoingRequest, UploadSigningKeysRequest},
-    session_manager::{GroupSessionManager, SessionManager},
+    session_manager::{GroupSessionManager, RoomKeySharingReport, SessionManager},
     store::{
         locks::LockStoreError, Changes, DeviceChanges, DynCryptoStore, IdentityChanges,
-        IntoCryptoStore, MemoryStore, Result as StoreResult, RoomKeyInfo, SecretImportError, Store,
+        IntoCryptoStore, MemoryStore, PruneReport, PruneSettings, Result as StoreResult,
+        RoomKeyInfo, SecretImportError, Store,
     },
     types::{
         events::{
@@ -91,6 +95,11 @@ use crate::{
     CrossSigningKeyExport, CryptoStoreError, KeysQueryRequest, LocalTrust, ReadOnlyDevice,
     RoomKeyImportResult, SignatureError, ToDeviceRequest,
 };
+#[cfg(feature = "automatic-room-key-forwarding")]
+use crate::{
+    gossiping::StoredKeyForwardingPolicy, store::KEY_FORWARDING_POLICY_KEY,
+    KeyForwardingAuditEntry, KeyForwardingPolicy,
+};
 
 /// State machine implementation of the Olm/Megolm encryption protocol used for
 /// Matrix end to end encryption.
@@ -326,7 +335,27 @@ impl OlmMachine {
 
         let identity = Arc::new(Mutex::new(identity));
 
-        Ok(OlmMachine::new_helper(user_id, device_id, store, account, identity))
+        let machine = OlmMachine::new_helper(user_id, device_id, store, account, identity);
+
+        #[cfg(feature = "automatic-room-key-forwarding")]
+        if let Some(policy) = machine
+            .store()
+            .get_value::<StoredKeyForwardingPolicy>(KEY_FORWARDING_POLICY_KEY)
+            .await?
+        {
+            if matches!(policy, StoredKeyForwardingPolicy::Custom) {
+                warn!(
+                    "The custom key forwarding policy can't be restored, room key requests \
+                     won't be answered until a policy is set again"
+                );
+            } else {
+                debug!(?policy, "Restored the key forwarding policy");
+            }
+
+            machine.inner.key_request_machine.set_key_forwarding_policy(policy.into());
+        }
+
+        Ok(machine)
     }
 
     /// Get the crypto store associated with this `OlmMachine` instance.
@@ -377,6 +406,42 @@ impl OlmMachine {
         self.inner.key_request_machine.is_room_key_forwarding_enabled()
     }
 
+    /// Set the policy deciding which incoming room key requests are answered.
+    ///
+    /// This only has an effect if room key forwarding is enabled, see
+    /// [`toggle_room_key_forwarding()`](#method.toggle_room_key_forwarding).
+    ///
+    /// The policy is persisted in the store and restored when the machine is
+    /// created again. The closure of a [`KeyForwardingPolicy::Custom`] policy
+    /// can't be persisted, it is restored as [`KeyForwardingPolicy::Never`]
+    /// until it is set again.
+    #[cfg(feature = "automatic-room-key-forwarding")]
+    pub async fn set_key_forwarding_policy(&self, policy: KeyForwardingPolicy) -> StoreResult<()> {
+        self.store()
+            .set_value(KEY_FORWARDING_POLICY_KEY, &StoredKeyForwardingPolicy::from(&policy))
+            .await?;
+        self.inner.key_request_machine.set_key_forwarding_policy(policy);
+
+        Ok(())
+    }
+
+    /// Get the policy deciding which incoming room key requests are answered.
+    #[cfg(feature = "automatic-room-key-forwarding")]
+    pub fn key_forwarding_policy(&self) -> KeyForwardingPolicy {
+        self.inner.key_request_machine.key_forwarding_policy()
+    }
+
+    /// Receive a [`KeyForwardingAuditEntry`] for every incoming room key
+    /// request, describing the decision that was taken for it.
+    ///
+    /// Only the last 1000 entries are buffered for the reader of the stream.
+    /// If it lags further behind, a warning is logged and the oldest entries
+    /// are dropped.
+    #[cfg(feature = "automatic-room-key-forwarding")]
+    pub fn key_forwarding_audit_stream(&self) -> impl Stream<Item = KeyForwardingAuditEntry> {
+        self.inner.key_request_machine.key_forwarding_audit_stream()
+    }
+
     /// Get the outgoing requests that need to be sent out.
     ///
     /// This returns a list of [`OutgoingRequest`]. Those requests need to be
@@ -597,6 +662,23 @@ impl OlmMachine {
         self.inner.session_manager.get_missing_sessions(users).await
     }
 
+    /// Get a key claiming request for the given devices that we are missing
+    /// Olm sessions with.
+    ///
+    /// This works like [`get_missing_sessions`] but only claims one-time keys
+    /// for the given devices, the other devices of their owners are left
+    /// alone.
+    ///
+    /// Returns None if no key claiming request needs to be sent out.
+    ///
+    /// [`get_missing_sessions`]: #method.get_missing_sessions
+    pub async fn get_missing_sessions_for_devices(
+        &self,
+        devices: impl Iterator<Item = &ReadOnlyDevice>,
+    ) -> StoreResult<Option<(OwnedTransactionId, KeysClaimRequest)>> {
+        self.inner.session_manager.get_missing_sessions_for_devices(devices).await
+    }
+
     /// Receive a successful key claim response and create new Olm sessions with
     /// the claimed keys.
     ///
@@ -867,6 +949,121 @@ impl OlmMachine {
         self.inner.group_session_manager.share_room_key(room_id, users, encryption_settings).await
     }
 
+    /// Compute which devices of the given users would receive a room key with
+    /// the given settings, and which devices would be excluded and why.
+    ///
+    /// No room key is shared by this method, it can be used to inform the user
+    /// about the consequences of a [`CollectStrategy`] before sharing a room
+    /// key using the [`share_room_key()`] method.
+    ///
+    /// # Arguments
+    ///
+    /// `users` - The list of users that should receive the room key.
+    ///
+    /// `settings` - The encryption settings, containing the sharing strategy,
+    /// that should be used for the room key.
+    ///
+    /// [`CollectStrategy`]: crate::CollectStrategy
+    /// [`share_room_key()`]: #method.share_room_key
+    pub async fn room_key_sharing_report(
+        &self,
+        users: impl Iterator<Item = &UserId>,
+        settings: &EncryptionSettings,
+    ) -> OlmResult<RoomKeySharingReport> {
+        self.inner.group_session_manager.room_key_sharing_report(users, settings).await
+    }
+
+    /// Encrypt the given content for the given devices and create to-device
+    /// requests that send the encrypted content to them.
+    ///
+    /// This can be used to send arbitrary, application specific, to-device
+    /// messages over Olm. Olm sessions with the devices need to be established
+    /// beforehand using the [`get_missing_sessions()`] method, devices we don't
+    /// share an Olm session with will be skipped.
+    ///
+    /// The recipients will receive the decrypted event, with the given
+    /// `event_type`, as part of the to-device events returned by the
+    /// [`receive_sync_changes()`] method.
+    ///
+    /// # Arguments
+    ///
+    /// * `devices` - The devices that should receive the encrypted content.
+    ///
+    /// * `event_type` - The plaintext type of the event.
+    ///
+    /// * `content` - The plaintext content of the event as a json [`Value`].
+    ///
+    /// Returns the list of to-device requests that need to be sent out and the
+    /// list of devices for which the content could not be encrypted because
+    /// we don't share an Olm session with them.
+    ///
+    /// [`get_missing_sessions()`]: #method.get_missing_sessions
+    /// [`receive_sync_changes()`]: #method.receive_sync_changes
+    #[instrument(skip(self, devices, content))]
+    pub async fn encrypt_content_for_devices(
+        &self,
+        devices: Vec<Device>,
+        event_type: &str,
+        content: &Value,
+    ) -> OlmResult<(Vec<ToDeviceRequest>, Vec<(OwnedUserId, OwnedDeviceId)>)> {
+        let mut changes = Changes::default();
+        let mut encrypted_messages = Vec::new();
+        let mut failed_devices = Vec::new();
+
+        for device in devices {
+            let user_id = device.user_id().to_owned();
+            let device_id = device.device_id().to_owned();
+
+            match device.encrypt(event_type, content.clone()).await {
+                Ok((used_session, message)) => {
+                    // Encryption ratchets the Olm session forward, it needs to
+                    // be persisted again.
+                    changes.sessions.push(used_session);
+                    encrypted_messages.push((user_id, device_id, message.cast()));
+                }
+                Err(
+                    OlmError::MissingSession | OlmError::EventError(EventError::MissingSenderKey),
+                ) => {
+                    warn!(
+                        %user_id,
+                        %device_id,
+                        "Can't encrypt a custom to-device event, no Olm session was found",
+                    );
+                    failed_devices.push((user_id, device_id));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        if !changes.is_empty() {
+            self.store().save_changes(changes).await?;
+        }
+
+        // Chunk the messages out so each to-device request will contain a
+        // limited amount of to-device messages.
+        let requests = encrypted_messages
+            .chunks(GroupSessionManager::MAX_TO_DEVICE_MESSAGES)
+            .map(|chunk| {
+                let mut messages = BTreeMap::new();
+
+                for (user_id, device_id, message) in chunk {
+                    messages.entry(user_id.to_owned()).or_insert_with(BTreeMap::new).insert(
+                        DeviceIdOrAllDevices::DeviceId(device_id.to_owned()),
+                        message.clone(),
+                    );
+                }
+
+                ToDeviceRequest {
+                    event_type: ToDeviceEventType::RoomEncrypted,
+                    txn_id: TransactionId::new(),
+                    messages,
+                }
+            })
+            .collect();
+
+        Ok((requests, failed_devices))
+    }
+
     /// Receive an unencrypted verification event.
     ///
     /// This method can be used to pass verification events that are happening
@@ -942,7 +1139,9 @@ impl OlmMachine {
                 debug!("Received an `m.dummy` event");
             }
             AnyDecryptedOlmEvent::Custom(_) => {
-                warn!("Received an unexpected encrypted to-device event");
+                // Custom events are passed on to the caller, they will be
+                // returned as part of the decrypted to-device events.
+                debug!("Received a custom encrypted to-device event");
             }
         }
 
@@ -983,6 +1182,18 @@ impl OlmMachine {
         self.inner.verification_machine.get_requests(user_id)
     }
 
+    /// Receive the verification requests other users or our other devices
+    /// send to us as a [`Stream`].
+    ///
+    /// A new [`VerificationRequest`] is sent to the stream each time an
+    /// incoming `m.key.verification.request` event is processed, e.g. as part
+    /// of the [`receive_sync_changes()`] method.
+    ///
+    /// [`receive_sync_changes()`]: #method.receive_sync_changes
+    pub fn verification_requests_stream(&self) -> impl Stream<Item = VerificationRequest> {
+        self.inner.verification_machine.verification_requests_stream()
+    }
+
     async fn update_key_counts(
         &self,
         one_time_key_count: &BTreeMap<DeviceKeyAlgorithm, UInt>,
@@ -1317,9 +1528,42 @@ impl OlmMachine {
                     .collect(),
             },
             verification_state,
+            session_id: Some(session.session_id().to_owned()),
         })
     }
 
+    /// Get the up-to-date encryption info of events that were decrypted using
+    /// the given Megolm session.
+    ///
+    /// The [`EncryptionInfo`] of a decrypted event reflects the trust of the
+    /// sending device at the time of decryption. This method can be used to
+    /// recompute it after the trust of the sending device or user identity
+    /// has changed, without having to decrypt the event again.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - The ID of the room the session belongs to.
+    ///
+    /// * `session_id` - The ID of the Megolm session that was used to decrypt
+    ///   the events.
+    ///
+    /// * `sender` - The user ID of the sender of the events.
+    ///
+    /// Returns `None` if we don't have the given session.
+    pub async fn get_session_encryption_info(
+        &self,
+        room_id: &RoomId,
+        session_id: &str,
+        sender: &UserId,
+    ) -> MegolmResult<Option<EncryptionInfo>> {
+        let Some(session) = self.store().get_inbound_group_session(room_id, session_id).await?
+        else {
+            return Ok(None);
+        };
+
+        Ok(Some(self.get_encryption_info(&session, sender).await?))
+    }
+
     async fn decrypt_megolm_events(
         &self,
         room_id: &RoomId,
@@ -1911,6 +2155,16 @@ impl OlmMachine {
         DehydratedDevices { inner: self.to_owned() }
     }
 
+    /// Remove the data that isn't needed anymore from the crypto store.
+    ///
+    /// Which data gets removed is controlled by the given [`PruneSettings`],
+    /// nothing is removed with the default settings.
+    ///
+    /// Returns a [`PruneReport`] with the amount of data that was removed.
+    pub async fn prune_store(&self, settings: &PruneSettings) -> StoreResult<PruneReport> {
+        self.store().prune(settings).await
+    }
+
     #[cfg(any(feature = "testing", test))]
     /// Returns whether this `OlmMachine` is the same another one.
     ///
@@ -1957,6 +2211,7 @@ pub(crate) mod tests {
     use std::{
         collections::BTreeMap,
         iter,
+        ops::Deref,
         sync::Arc,
         time::{Duration, SystemTime},
     };
@@ -2003,7 +2258,7 @@ pub(crate) mod tests {
         error::EventError,
         machine::{EncryptionSyncChanges, OlmMachine},
         olm::{InboundGroupSession, OutboundGroupSession, VerifyJson},
-        store::Changes,
+        store::{Changes, DeviceChanges, PruneReport, PruneSettings, RoomKeyRetention},
         types::{
             events::{
                 room::encrypted::{EncryptedToDeviceEvent, ToDeviceEncryptedEventContent},
@@ -2339,6 +2594,58 @@ pub(crate) mod tests {
         assert_eq!(device.device_id(), alice_device_id);
     }
 
+    #[async_test]
+    async fn test_keys_query_devices_stream() {
+        let (machine, _) = get_prepared_machine(user_id(), false).await;
+        let response = keys_query_response();
+        let alice_id = user_id!("@alice:example.org");
+        let alice_device_id: &DeviceId = device_id!("JLAFKJWSCS");
+
+        let mut devices_stream = Box::pin(machine.store().devices_stream());
+
+        let req_id = TransactionId::new();
+        machine.receive_keys_query_response(&req_id, &response).await.unwrap();
+
+        let updates = devices_stream
+            .next()
+            .now_or_never()
+            .flatten()
+            .expect("We should have received a device update");
+
+        let device = &updates.new[alice_id][alice_device_id];
+        assert_eq!(device.user_id(), alice_id);
+        assert_eq!(device.device_id(), alice_device_id);
+        assert!(updates.changed.is_empty());
+        assert!(updates.deleted.is_empty());
+    }
+
+    #[async_test]
+    async fn test_local_trust_devices_stream() {
+        let (machine, _) = get_prepared_machine(user_id(), false).await;
+        let response = keys_query_response();
+        let alice_id = user_id!("@alice:example.org");
+        let alice_device_id: &DeviceId = device_id!("JLAFKJWSCS");
+
+        let req_id = TransactionId::new();
+        machine.receive_keys_query_response(&req_id, &response).await.unwrap();
+
+        let mut devices_stream = Box::pin(machine.store().devices_stream());
+
+        let device = machine.get_device(alice_id, alice_device_id, None).await.unwrap().unwrap();
+        device.set_local_trust(LocalTrust::Verified).await.unwrap();
+
+        let updates = devices_stream
+            .next()
+            .now_or_never()
+            .flatten()
+            .expect("Changing the local trust should produce a device update");
+
+        let device = &updates.changed[alice_id][alice_device_id];
+        assert!(device.is_locally_trusted());
+        assert!(updates.new.is_empty());
+        assert!(updates.deleted.is_empty());
+    }
+
     #[async_test]
     async fn test_query_keys_for_users() {
         let (machine, _) = get_prepared_machine(user_id(), false).await;
@@ -2575,6 +2882,126 @@ pub(crate) mod tests {
         assert_eq!(room_key_updates[0].session_id, alice_session.session_id());
     }
 
+    #[async_test]
+    async fn test_prune_store() {
+        let (alice, bob) = get_machine_pair_with_session(alice_id(), user_id(), false).await;
+        alice.update_tracked_users([bob.user_id()]).await.unwrap();
+
+        let settings = PruneSettings { remove_orphaned_olm_sessions: true, ..Default::default() };
+
+        // Bob's device is still known, so the session is kept.
+        let report = alice.prune_store(&settings).await.unwrap();
+        assert_eq!(report, PruneReport::default());
+
+        let bob_device = alice.get_device(bob.user_id(), bob.device_id(), None).await.unwrap();
+        let changes = Changes {
+            devices: DeviceChanges {
+                deleted: vec![bob_device.unwrap().inner],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        alice.store().save_changes(changes).await.unwrap();
+
+        // Bob's device list is outdated, so we can't be sure that the device was
+        // deleted.
+        let report = alice.prune_store(&settings).await.unwrap();
+        assert_eq!(report.olm_sessions, 0);
+
+        let (users, sequence_number) = alice.store().users_for_key_query().await.unwrap();
+        alice
+            .store()
+            .mark_tracked_users_as_up_to_date(users.iter().map(Deref::deref), sequence_number)
+            .await
+            .unwrap();
+
+        let report = alice.prune_store(&settings).await.unwrap();
+        assert_eq!(report.olm_sessions, 1);
+        assert!(alice.store().get_session_sender_keys().await.unwrap().is_empty());
+
+        let room_id = room_id!("!test:example.org");
+        let session = alice.create_inbound_session(room_id).await.unwrap();
+        alice.store().save_inbound_group_sessions(&[session.clone()]).await.unwrap();
+
+        let settings = PruneSettings {
+            rooms: [room_id.to_owned()].into(),
+            room_key_retention: RoomKeyRetention::RemoveBackedUp,
+            ..Default::default()
+        };
+
+        // The room key wasn't backed up yet, so it's kept.
+        let report = alice.prune_store(&settings).await.unwrap();
+        assert_eq!(report.inbound_group_sessions, 0);
+
+        session.mark_as_backed_up();
+        alice.store().save_inbound_group_sessions(&[session.clone()]).await.unwrap();
+
+        let report = alice.prune_store(&settings).await.unwrap();
+        assert_eq!(report.inbound_group_sessions, 1);
+        assert!(alice
+            .store()
+            .get_inbound_group_session(room_id, session.session_id())
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[async_test]
+    async fn test_custom_to_device_event_encryption() {
+        let (alice, bob) = get_machine_pair_with_session(alice_id(), user_id(), false).await;
+
+        let bob_device =
+            alice.get_device(bob.user_id(), bob.device_id(), None).await.unwrap().unwrap();
+        let content = json!({ "call_id": "1234", "party_id": "5678" });
+
+        let (requests, failed_devices) = alice
+            .encrypt_content_for_devices(vec![bob_device], "org.example.custom", &content)
+            .await
+            .unwrap();
+
+        assert!(failed_devices.is_empty());
+        assert_eq!(requests.len(), 1);
+
+        let event = ToDeviceEvent::new(
+            alice.user_id().to_owned(),
+            to_device_requests_to_content(requests.into_iter().map(Arc::new).collect()),
+        );
+        let event = json_convert(&event).unwrap();
+
+        let (decrypted, _) = bob
+            .receive_sync_changes(EncryptionSyncChanges {
+                to_device_events: vec![event],
+                changed_devices: &Default::default(),
+                one_time_keys_counts: &Default::default(),
+                unused_fallback_keys: None,
+                next_batch_token: None,
+            })
+            .await
+            .unwrap();
+
+        let decrypted = decrypted[0].deserialize_as::<serde_json::Value>().unwrap();
+
+        assert_eq!(decrypted["type"], "org.example.custom");
+        assert_eq!(decrypted["sender"], alice.user_id().as_str());
+        assert_eq!(decrypted["content"], content);
+    }
+
+    #[async_test]
+    async fn test_custom_to_device_event_encryption_without_session() {
+        let (alice, bob, _) = get_machine_pair(alice_id(), user_id(), false).await;
+
+        let bob_device =
+            alice.get_device(bob.user_id(), bob.device_id(), None).await.unwrap().unwrap();
+
+        let (requests, failed_devices) = alice
+            .encrypt_content_for_devices(vec![bob_device], "org.example.custom", &json!({}))
+            .await
+            .unwrap();
+
+        assert!(requests.is_empty());
+        assert_eq!(failed_devices, vec![(bob.user_id().to_owned(), bob.device_id().to_owned())]);
+    }
+
     #[async_test]
     async fn test_megolm_encryption() {
         let (alice, bob) = get_machine_pair_with_setup_sessions(alice_id(), user_id(), false).await;
@@ -2836,6 +3263,21 @@ pub(crate) mod tests {
         assert_eq!(VerificationState::Verified, encryption_info.verification_state);
         assert_shield!(encryption_info, None, None);
 
+        // The encryption info can be recomputed from the session without decrypting the
+        // event again.
+        let session_id = encryption_info.session_id.as_deref().unwrap();
+        let recomputed = bob
+            .get_session_encryption_info(room_id, session_id, alice.user_id())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(VerificationState::Verified, recomputed.verification_state);
+        assert!(bob
+            .get_session_encryption_info(room_id, "unknown session", alice.user_id())
+            .await
+            .unwrap()
+            .is_none());
+
         // Simulate an imported session, to change verification state
         let imported = InboundGroupSession::from_export(&export).unwrap();
         bob.store().save_inbound_group_sessions(&[imported]).await.unwrap();
@@ -3118,6 +3560,50 @@ pub(crate) mod tests {
         assert_eq!(VerificationState::Unverified(VerificationLevel::UnverifiedIdentity), state);
     }
 
+    #[async_test]
+    #[cfg(feature = "automatic-room-key-forwarding")]
+    async fn test_key_forwarding_policy_is_persisted() {
+        use crate::{
+            gossiping::{KeyForwardDecision, KeyForwardRequest},
+            store::MemoryStore,
+            KeyForwardingPolicy,
+        };
+
+        let store = Arc::new(MemoryStore::new());
+        let device_id = device_id!("ALICEDEVICE");
+
+        let machine = OlmMachine::with_store(alice_id(), device_id, store.clone()).await.unwrap();
+        assert_matches!(machine.key_forwarding_policy(), KeyForwardingPolicy::Default);
+
+        machine
+            .set_key_forwarding_policy(KeyForwardingPolicy::OwnVerifiedDevicesOnly)
+            .await
+            .unwrap();
+        drop(machine);
+
+        // The policy is restored when the machine is created again.
+        let machine = OlmMachine::with_store(alice_id(), device_id, store.clone()).await.unwrap();
+        assert_matches!(
+            machine.key_forwarding_policy(),
+            KeyForwardingPolicy::OwnVerifiedDevicesOnly
+        );
+
+        // A custom policy can't be persisted, it falls back to refusing every
+        // request.
+        fn refuse_all(_: &KeyForwardRequest<'_>) -> Result<Option<u32>, KeyForwardDecision> {
+            Err(KeyForwardDecision::RefusedByPolicy)
+        }
+
+        machine
+            .set_key_forwarding_policy(KeyForwardingPolicy::Custom(Arc::new(refuse_all)))
+            .await
+            .unwrap();
+        drop(machine);
+
+        let machine = OlmMachine::with_store(alice_id(), device_id, store).await.unwrap();
+        assert_matches!(machine.key_forwarding_policy(), KeyForwardingPolicy::Never);
+    }
+
     #[async_test]
     #[cfg(feature = "automatic-room-key-forwarding")]
     async fn test_query_ratcheted_key() {
@@ -55,7 +55,7 @@ use crate::{
         },
         EventEncryptionAlgorithm,
     },
-    Device, ToDeviceRequest,
+    CollectStrategy, Device, ToDeviceRequest,
 };
 
 const ROTATION_PERIOD: Duration = Duration::from_millis(604800000);
@@ -85,6 +85,15 @@ pub struct EncryptionSettings {
     /// excluded from the conversation.
     #[serde(default)]
     pub only_allow_trusted_devices: bool,
+    /// Should sharing the room key fail if the identity of any of the room
+    /// members changed and the change wasn't acknowledged yet. If this is
+    /// `false`, only a warning is logged.
+    #[serde(default)]
+    pub error_on_identity_violation: bool,
+    /// The strategy used to decide which devices should receive the room
+    /// key.
+    #[serde(default)]
+    pub sharing_strategy: CollectStrategy,
 }
 
 impl Default for EncryptionSettings {
@@ -95,6 +104,8 @@ impl Default for EncryptionSettings {
             rotation_period_msgs: ROTATION_MESSAGES,
             history_visibility: HistoryVisibility::Shared,
             only_allow_trusted_devices: false,
+            error_on_identity_violation: false,
+            sharing_strategy: CollectStrategy::default(),
         }
     }
 }
@@ -119,6 +130,8 @@ impl EncryptionSettings {
             rotation_period_msgs,
             history_visibility,
             only_allow_trusted_devices,
+            error_on_identity_violation: false,
+            sharing_strategy: CollectStrategy::default(),
         }
     }
 }
@@ -0,0 +1,234 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Encryption of secrets stored in the user's account data.
+//!
+//! This implements the `m.secret_storage.v1.aes-hmac-sha2` algorithm of the
+//! [secret storage] specification. Only the encryption of the secrets is
+//! handled here, the secret storage key itself and its key description are
+//! managed by the application.
+//!
+//! [secret storage]: https://spec.matrix.org/v1.7/client-server-api/#storage
+
+use std::fmt;
+
+use aes::{
+    cipher::{generic_array::GenericArray, KeyIvInit, StreamCipher},
+    Aes256,
+};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use rand::{distributions::Alphanumeric, thread_rng, Rng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use thiserror::Error;
+use zeroize::Zeroize;
+
+use crate::utilities::{decode, encode, DecodeError};
+
+type Aes256Ctr = ctr::Ctr128BE<Aes256>;
+
+const KEY_SIZE: usize = 32;
+const IV_SIZE: usize = 16;
+const KEY_ID_SIZE: usize = 32;
+
+/// Error type for the decryption of secrets.
+#[derive(Debug, Error)]
+pub enum SecretStorageError {
+    /// The encrypted secret isn't valid base64.
+    #[error(transparent)]
+    Decode(#[from] DecodeError),
+    /// The initialization vector doesn't have the expected length.
+    #[error("The initialization vector of the encrypted secret is invalid")]
+    InvalidIv,
+    /// The MAC of the encrypted secret is invalid, the secret was encrypted
+    /// with another key or for another secret name.
+    #[error("The MAC of the encrypted secret is invalid")]
+    InvalidMac,
+    /// The decrypted secret isn't valid UTF-8.
+    #[error(transparent)]
+    InvalidUtf8(#[from] std::string::FromUtf8Error),
+    /// The decrypted secret doesn't have the format that was expected for
+    /// the secret.
+    #[error("The decrypted secret doesn't have the expected format")]
+    InvalidSecret,
+}
+
+/// A secret encrypted with the `m.secret_storage.v1.aes-hmac-sha2`
+/// algorithm, as found in the `encrypted` object of a secret's account data.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AesHmacSha2EncryptedData {
+    /// The base64-encoded initialization vector.
+    pub iv: String,
+    /// The base64-encoded encrypted secret.
+    pub ciphertext: String,
+    /// The base64-encoded MAC of the ciphertext.
+    pub mac: String,
+}
+
+/// A key used to encrypt secrets in secret storage.
+#[derive(Zeroize)]
+#[zeroize(drop)]
+pub struct SecretStorageKey {
+    #[zeroize(skip)]
+    key_id: String,
+    key: Box<[u8; KEY_SIZE]>,
+}
+
+impl SecretStorageKey {
+    /// Generate a new random secret storage key, with a random key ID.
+    pub fn new() -> Self {
+        let mut key = Box::new([0u8; KEY_SIZE]);
+        thread_rng().fill_bytes(key.as_mut_slice());
+
+        let key_id =
+            thread_rng().sample_iter(Alphanumeric).take(KEY_ID_SIZE).map(char::from).collect();
+
+        Self { key_id, key }
+    }
+
+    /// Create a secret storage key from its ID and raw bytes.
+    pub fn from_bytes(key_id: String, key: [u8; KEY_SIZE]) -> Self {
+        Self { key_id, key: Box::new(key) }
+    }
+
+    /// The ID of the key, used in the `encrypted` object of the secrets.
+    pub fn key_id(&self) -> &str {
+        &self.key_id
+    }
+
+    /// Encrypt a secret with this key.
+    ///
+    /// The `secret_name` is the account data event type the secret is stored
+    /// in, the same name needs to be used to decrypt it.
+    pub fn encrypt(&self, secret: &str, secret_name: &str) -> AesHmacSha2EncryptedData {
+        let keys = self.derive_keys(secret_name);
+
+        let mut iv = [0u8; IV_SIZE];
+        thread_rng().fill_bytes(&mut iv);
+        // Clear bit 63 of the IV to work around quirks in some AES-CTR
+        // implementations, as the specification requires.
+        iv[8] &= 0x7f;
+
+        let mut ciphertext = secret.as_bytes().to_vec();
+        let mut aes = Aes256Ctr::new(GenericArray::from_slice(keys.aes_key.as_slice()), &iv.into());
+        aes.apply_keystream(&mut ciphertext);
+
+        let mut hmac = Hmac::<Sha256>::new_from_slice(keys.mac_key.as_slice())
+            .expect("Can't create HMAC object");
+        hmac.update(&ciphertext);
+        let mac = hmac.finalize().into_bytes();
+
+        AesHmacSha2EncryptedData {
+            iv: encode(iv),
+            ciphertext: encode(ciphertext),
+            mac: encode(mac),
+        }
+    }
+
+    /// Decrypt a secret that was encrypted with this key.
+    pub fn decrypt(
+        &self,
+        data: &AesHmacSha2EncryptedData,
+        secret_name: &str,
+    ) -> Result<String, SecretStorageError> {
+        let keys = self.derive_keys(secret_name);
+
+        let iv: [u8; IV_SIZE] =
+            decode(&data.iv)?.try_into().map_err(|_| SecretStorageError::InvalidIv)?;
+        let mut plaintext = decode(&data.ciphertext)?;
+        let mac = decode(&data.mac)?;
+
+        let mut hmac = Hmac::<Sha256>::new_from_slice(keys.mac_key.as_slice())
+            .expect("Can't create HMAC object");
+        hmac.update(&plaintext);
+        hmac.verify_slice(&mac).map_err(|_| SecretStorageError::InvalidMac)?;
+
+        let mut aes = Aes256Ctr::new(GenericArray::from_slice(keys.aes_key.as_slice()), &iv.into());
+        aes.apply_keystream(&mut plaintext);
+
+        Ok(String::from_utf8(plaintext)?)
+    }
+
+    /// Derive the AES and MAC keys used for the secret with the given name.
+    fn derive_keys(&self, secret_name: &str) -> DerivedKeys {
+        let mut derived_keys = [0u8; KEY_SIZE * 2];
+
+        let hkdf = Hkdf::<Sha256>::new(Some(&[0u8; KEY_SIZE]), self.key.as_slice());
+        hkdf.expand(secret_name.as_bytes(), &mut derived_keys)
+            .expect("We should be able to expand the secret storage key into 64 bytes");
+
+        let mut keys =
+            DerivedKeys { aes_key: Box::new([0u8; KEY_SIZE]), mac_key: Box::new([0u8; KEY_SIZE]) };
+        keys.aes_key.copy_from_slice(&derived_keys[..KEY_SIZE]);
+        keys.mac_key.copy_from_slice(&derived_keys[KEY_SIZE..]);
+
+        derived_keys.zeroize();
+
+        keys
+    }
+}
+
+/// The keys derived from a secret storage key for a given secret.
+#[derive(Zeroize)]
+#[zeroize(drop)]
+struct DerivedKeys {
+    aes_key: Box<[u8; KEY_SIZE]>,
+    mac_key: Box<[u8; KEY_SIZE]>,
+}
+
+impl Default for SecretStorageKey {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(not(tarpaulin_include))]
+impl fmt::Debug for SecretStorageKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SecretStorageKey").field("key_id", &self.key_id).finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+
+    use super::{SecretStorageError, SecretStorageKey};
+
+    #[test]
+    fn test_encrypt_decrypt() {
+        let key = SecretStorageKey::new();
+
+        let encrypted = key.encrypt("It's a secret to everybody", "org.example.secret");
+        let decrypted = key.decrypt(&encrypted, "org.example.secret").unwrap();
+
+        assert_eq!(decrypted, "It's a secret to everybody");
+    }
+
+    #[test]
+    fn test_decrypt_with_another_name_or_key() {
+        let key = SecretStorageKey::new();
+        let encrypted = key.encrypt("It's a secret to everybody", "org.example.secret");
+
+        assert_matches!(
+            key.decrypt(&encrypted, "org.example.other_secret"),
+            Err(SecretStorageError::InvalidMac)
+        );
+        assert_matches!(
+            SecretStorageKey::new().decrypt(&encrypted, "org.example.secret"),
+            Err(SecretStorageError::InvalidMac)
+        );
+    }
+}
@@ -28,12 +28,13 @@ use ruma::{
     OwnedDeviceId, OwnedRoomId, OwnedTransactionId, OwnedUserId, RoomId, TransactionId, UserId,
 };
 use serde_json::Value;
-use tracing::{debug, error, info, instrument, trace};
+use tracing::{debug, error, info, instrument, trace, warn};
 
 use crate::{
     error::{EventError, MegolmResult, OlmResult},
-    identities::device::MaybeEncryptedRoomKey,
+    identities::{device::MaybeEncryptedRoomKey, ReadOnlyUserIdentities, UserDevices},
     olm::{Account, InboundGroupSession, OutboundGroupSession, Session, ShareInfo, ShareState},
+    session_manager::{CollectStrategy, ExclusionReason, RoomKeySharingReport},
     store::{Changes, Result as StoreResult, Store},
     types::events::{room::encrypted::RoomEncryptedEventContent, room_key_withheld::WithheldCode},
     Device, EncryptionSettings, OlmError, ToDeviceRequest,
@@ -141,7 +142,7 @@ pub struct GroupSessionManager {
 }
 
 impl GroupSessionManager {
-    const MAX_TO_DEVICE_MESSAGES: usize = 250;
+    pub(crate) const MAX_TO_DEVICE_MESSAGES: usize = 250;
 
     pub(crate) fn new(account: Account, store: Store) -> Self {
         Self { account, store: store.clone(), sessions: GroupSessionCache::new(store) }
@@ -375,23 +376,53 @@ impl GroupSessionManager {
         // This is calculated in the following code and stored in this variable.
         let mut should_rotate = user_left || visibility_changed || algorithm_changed;
 
+        // Users whose identity changed without the change being acknowledged.
+        let mut violating_users: Vec<OwnedUserId> = Vec::new();
+        // Users whose identity isn't verified, only collected for the
+        // `ErrorOnUnverifiedIdentity` strategy.
+        let mut unverified_users: Vec<OwnedUserId> = Vec::new();
+
         for user_id in users {
             let user_devices = self.store.get_user_devices_filtered(user_id).await?;
 
+            if let Some(ReadOnlyUserIdentities::Other(identity)) =
+                &user_devices.device_owner_identity
+            {
+                if let Some(violation) = identity.violation(user_devices.own_identity.as_ref()) {
+                    warn!(
+                        ?user_id,
+                        ?violation,
+                        room_id = outbound.room_id().as_str(),
+                        "The identity of a room member changed and the change wasn't acknowledged"
+                    );
+                    violating_users.push(user_id.to_owned());
+                }
+            }
+
+            if settings.sharing_strategy == CollectStrategy::ErrorOnUnverifiedIdentity
+                && !Self::is_owner_verified(&user_devices)
+            {
+                unverified_users.push(user_id.to_owned());
+            }
+
             // From all the devices a user has, we're splitting them into two
             // buckets, a bucket of devices that should receive the
             // room key and a bucket of devices that should receive
             // a withheld code.
-            let (recipients, withheld_recipients): (Vec<Device>, Vec<(Device, WithheldCode)>) =
-                user_devices.devices().partition_map(|d| {
-                    if d.is_blacklisted() {
-                        Either::Right((d, WithheldCode::Blacklisted))
-                    } else if settings.only_allow_trusted_devices && !d.is_verified() {
-                        Either::Right((d, WithheldCode::Unverified))
-                    } else {
-                        Either::Left(d)
-                    }
-                });
+            let (recipients, excluded) = Self::split_recipients(&user_devices, settings);
+
+            for (device, reason) in &excluded {
+                debug!(
+                    user_id = ?device.user_id(),
+                    device_id = ?device.device_id(),
+                    ?reason,
+                    room_id = outbound.room_id().as_str(),
+                    "Excluding a device from receiving the room key"
+                );
+            }
+
+            let withheld_recipients =
+                excluded.into_iter().map(|(device, reason)| (device, reason.withheld_code()));
 
             // If we haven't already concluded that the session should be
             // rotated for other reasons, we also need to check whether any
@@ -426,6 +457,14 @@ impl GroupSessionManager {
             withheld_devices.extend(withheld_recipients);
         }
 
+        if settings.error_on_identity_violation && !violating_users.is_empty() {
+            return Err(OlmError::IdentityViolations(violating_users));
+        }
+
+        if !unverified_users.is_empty() {
+            return Err(OlmError::UnverifiedIdentities(unverified_users));
+        }
+
         trace!(
             should_rotate = should_rotate,
             session_id = outbound.session_id(),
@@ -436,6 +475,79 @@ impl GroupSessionManager {
         Ok(CollectRecipientsResult { should_rotate, devices, withheld_devices })
     }
 
+    /// Split the devices of a user into the devices that should receive a room
+    /// key and the devices that should be excluded, along with the reason of
+    /// the exclusion.
+    fn split_recipients(
+        user_devices: &UserDevices,
+        settings: &EncryptionSettings,
+    ) -> (Vec<Device>, Vec<(Device, ExclusionReason)>) {
+        user_devices.devices().partition_map(|d| {
+            match settings
+                .sharing_strategy
+                .exclusion_reason(&d, settings.only_allow_trusted_devices)
+            {
+                Some(reason) => Either::Right((d, reason)),
+                None => Either::Left(d),
+            }
+        })
+    }
+
+    /// Is the identity of the owner of the given devices verified by us.
+    fn is_owner_verified(user_devices: &UserDevices) -> bool {
+        user_devices.device_owner_identity.as_ref().is_some_and(|identity| match identity {
+            ReadOnlyUserIdentities::Own(own_identity) => own_identity.is_verified(),
+            ReadOnlyUserIdentities::Other(identity) => {
+                user_devices.own_identity.as_ref().is_some_and(|own_identity| {
+                    own_identity.is_verified() && own_identity.is_identity_signed(identity).is_ok()
+                })
+            }
+        })
+    }
+
+    /// Compute which devices of the given users would receive a room key with
+    /// the given settings, and which ones would be excluded and why.
+    ///
+    /// This doesn't share any room key, it can be used to inform the user
+    /// about the consequences of a sharing strategy.
+    pub async fn room_key_sharing_report(
+        &self,
+        users: impl Iterator<Item = &UserId>,
+        settings: &EncryptionSettings,
+    ) -> OlmResult<RoomKeySharingReport> {
+        let mut report = RoomKeySharingReport::default();
+
+        for user_id in users.collect::<BTreeSet<_>>() {
+            let user_devices = self.store.get_user_devices_filtered(user_id).await?;
+
+            if settings.sharing_strategy == CollectStrategy::ErrorOnUnverifiedIdentity
+                && !Self::is_owner_verified(&user_devices)
+            {
+                report.unverified_users.insert(user_id.to_owned());
+            }
+
+            let (recipients, excluded) = Self::split_recipients(&user_devices, settings);
+
+            if !recipients.is_empty() {
+                report
+                    .recipients
+                    .entry(user_id.to_owned())
+                    .or_default()
+                    .extend(recipients.iter().map(|d| d.device_id().to_owned()));
+            }
+
+            if !excluded.is_empty() {
+                report
+                    .excluded
+                    .entry(user_id.to_owned())
+                    .or_default()
+                    .extend(excluded.iter().map(|(d, r)| (d.device_id().to_owned(), *r)));
+            }
+        }
+
+        Ok(report)
+    }
+
     pub async fn encrypt_request(
         chunk: Vec<Device>,
         outbound: OutboundGroupSession,
@@ -850,6 +962,7 @@ impl GroupSessionManager {
 mod tests {
     use std::{collections::BTreeSet, ops::Deref, sync::Arc};
 
+    use assert_matches::assert_matches;
     use matrix_sdk_test::{async_test, response_from_file};
     use ruma::{
         api::{
@@ -875,7 +988,8 @@ mod tests {
             },
             EventEncryptionAlgorithm,
         },
-        EncryptionSettings, LocalTrust, OlmMachine, ToDeviceRequest,
+        CollectStrategy, EncryptionSettings, ExclusionReason, LocalTrust, OlmError, OlmMachine,
+        ToDeviceRequest,
     };
 
     fn alice_id() -> &'static UserId {
@@ -1330,6 +1444,93 @@ mod tests {
         assert!(has_blacklist);
     }
 
+    #[async_test]
+    async fn room_key_sharing_report() {
+        let machine = machine().await;
+        let keys_claim = keys_claim_response();
+        let users: Vec<_> = keys_claim.one_time_keys.keys().map(Deref::deref).collect();
+
+        let user_id = user_id!("@example:localhost");
+        let blacklisted = device_id!("MWVTUXDNNM");
+        let trusted = device_id!("MWFXPINOAO");
+
+        machine
+            .get_device(user_id, blacklisted, None)
+            .await
+            .unwrap()
+            .unwrap()
+            .set_local_trust(LocalTrust::BlackListed)
+            .await
+            .unwrap();
+        machine
+            .get_device(user_id, trusted, None)
+            .await
+            .unwrap()
+            .unwrap()
+            .set_local_trust(LocalTrust::Verified)
+            .await
+            .unwrap();
+
+        // Only the blacklisted device is excluded by default.
+        let report = machine
+            .room_key_sharing_report(users.iter().copied(), &EncryptionSettings::default())
+            .await
+            .unwrap();
+
+        assert_eq!(report.excluded[user_id].len(), 1);
+        assert_eq!(report.excluded[user_id][blacklisted], ExclusionReason::Blacklisted);
+        assert!(report.recipients[user_id].contains(trusted));
+        assert!(report.unverified_users.is_empty());
+
+        // Nobody is verified using cross-signing, locally trusted devices are
+        // excluded as well.
+        let settings = EncryptionSettings {
+            sharing_strategy: CollectStrategy::VerifiedUsersOnly,
+            ..Default::default()
+        };
+        let report =
+            machine.room_key_sharing_report(users.iter().copied(), &settings).await.unwrap();
+
+        assert!(report.recipients.is_empty());
+        assert_eq!(report.excluded[user_id][blacklisted], ExclusionReason::Blacklisted);
+        assert_eq!(report.excluded[user_id][trusted], ExclusionReason::UnverifiedUser);
+        assert_eq!(
+            report.excluded[user_id][blacklisted].withheld_code(),
+            WithheldCode::Blacklisted
+        );
+        assert_eq!(report.excluded[user_id][trusted].withheld_code(), WithheldCode::Unverified);
+
+        // Locally trusted devices are allowed if only cross-signed devices
+        // should receive the key.
+        let settings = EncryptionSettings {
+            sharing_strategy: CollectStrategy::CrossSignedOnly,
+            ..Default::default()
+        };
+        let report =
+            machine.room_key_sharing_report(users.iter().copied(), &settings).await.unwrap();
+
+        assert!(report.recipients[user_id].contains(trusted));
+
+        let settings = EncryptionSettings {
+            sharing_strategy: CollectStrategy::ErrorOnUnverifiedIdentity,
+            ..Default::default()
+        };
+
+        // Nobody is verified, the users are listed in the report and sharing
+        // fails.
+        let report =
+            machine.room_key_sharing_report(users.iter().copied(), &settings).await.unwrap();
+
+        assert!(report.unverified_users.contains(user_id));
+        assert!(report.recipients[user_id].contains(trusted));
+        assert_eq!(report.excluded[user_id][blacklisted], ExclusionReason::Blacklisted);
+
+        assert_matches!(
+            machine.share_room_key(room_id!("!test:localhost"), users.into_iter(), settings).await,
+            Err(OlmError::UnverifiedIdentities(_))
+        );
+    }
+
     #[async_test]
     async fn no_olm_withheld_only_sent_once() {
         let keys_query = keys_query_response();
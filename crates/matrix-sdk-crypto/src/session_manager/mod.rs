@@ -14,6 +14,8 @@
 
 mod group_sessions;
 mod sessions;
+mod share_strategy;
 
 pub(crate) use group_sessions::{GroupSessionCache, GroupSessionManager};
 pub(crate) use sessions::SessionManager;
+pub use share_strategy::{CollectStrategy, ExclusionReason, RoomKeySharingReport};
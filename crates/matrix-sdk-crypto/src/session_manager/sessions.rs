@@ -224,42 +224,8 @@ impl SessionManager {
         for user_id in users.filter(|u| !self.failures.contains(u.server_name())) {
             let user_devices = self.get_user_devices(user_id).await?;
 
-            for (device_id, device) in user_devices {
-                if !(device.supports_olm()) {
-                    warn!(
-                        user_id = device.user_id().as_str(),
-                        device_id = device.device_id().as_str(),
-                        algorithms = ?device.algorithms(),
-                        "Device doesn't support any of our 1-to-1 E2EE \
-                        algorithms, can't establish an Olm session"
-                    );
-                } else if let Some(sender_key) = device.curve25519_key() {
-                    let sessions = self.store.get_sessions(&sender_key.to_base64()).await?;
-
-                    let is_missing = if let Some(sessions) = sessions {
-                        sessions.lock().await.is_empty()
-                    } else {
-                        true
-                    };
-
-                    let is_timed_out = self.is_user_timed_out(user_id, &device_id);
-
-                    if is_missing && is_timed_out {
-                        timed_out.entry(user_id.to_owned()).or_default().insert(device_id);
-                    } else if is_missing && !is_timed_out {
-                        missing
-                            .entry(user_id.to_owned())
-                            .or_default()
-                            .insert(device_id, DeviceKeyAlgorithm::SignedCurve25519);
-                    }
-                } else {
-                    warn!(
-                        user_id = device.user_id().as_str(),
-                        device_id = device.device_id().as_str(),
-                        "Device doesn't have a valid Curve25519 key, \
-                        can't establish an Olm session"
-                    );
-                }
+            for device in user_devices.values() {
+                self.check_for_missing_session(device, &mut missing, &mut timed_out).await?;
             }
         }
 
@@ -276,8 +242,88 @@ impl SessionManager {
             }
         }
 
+        Ok(Self::keys_claim_request(missing, timed_out))
+    }
+
+    /// Get a key claiming request for the given devices, if we are missing
+    /// Olm sessions with some of them.
+    ///
+    /// Unlike [`get_missing_sessions`](#method.get_missing_sessions), this
+    /// only considers the given devices and won't claim one-time keys for the
+    /// other devices of their owners.
+    ///
+    /// Returns None if no key claiming request needs to be sent out.
+    ///
+    /// # Arguments
+    ///
+    /// `devices` - The devices that we should check if we lack a session with.
+    pub async fn get_missing_sessions_for_devices(
+        &self,
+        devices: impl Iterator<Item = &ReadOnlyDevice>,
+    ) -> StoreResult<Option<(OwnedTransactionId, KeysClaimRequest)>> {
+        let mut missing = BTreeMap::new();
+        let mut timed_out = BTreeMap::new();
+
+        for device in devices.filter(|d| !self.failures.contains(d.user_id().server_name())) {
+            self.check_for_missing_session(device, &mut missing, &mut timed_out).await?;
+        }
+
+        Ok(Self::keys_claim_request(missing, timed_out))
+    }
+
+    /// Check if we are missing an Olm session with the given device and add
+    /// it to the `missing` or `timed_out` maps accordingly.
+    async fn check_for_missing_session(
+        &self,
+        device: &ReadOnlyDevice,
+        missing: &mut BTreeMap<OwnedUserId, BTreeMap<OwnedDeviceId, DeviceKeyAlgorithm>>,
+        timed_out: &mut BTreeMap<OwnedUserId, BTreeSet<OwnedDeviceId>>,
+    ) -> StoreResult<()> {
+        let user_id = device.user_id();
+        let device_id = device.device_id();
+
+        if !(device.supports_olm()) {
+            warn!(
+                user_id = user_id.as_str(),
+                device_id = device_id.as_str(),
+                algorithms = ?device.algorithms(),
+                "Device doesn't support any of our 1-to-1 E2EE \
+                algorithms, can't establish an Olm session"
+            );
+        } else if let Some(sender_key) = device.curve25519_key() {
+            let sessions = self.store.get_sessions(&sender_key.to_base64()).await?;
+
+            let is_missing =
+                if let Some(sessions) = sessions { sessions.lock().await.is_empty() } else { true };
+
+            let is_timed_out = self.is_user_timed_out(user_id, device_id);
+
+            if is_missing && is_timed_out {
+                timed_out.entry(user_id.to_owned()).or_default().insert(device_id.to_owned());
+            } else if is_missing && !is_timed_out {
+                missing
+                    .entry(user_id.to_owned())
+                    .or_default()
+                    .insert(device_id.to_owned(), DeviceKeyAlgorithm::SignedCurve25519);
+            }
+        } else {
+            warn!(
+                user_id = user_id.as_str(),
+                device_id = device_id.as_str(),
+                "Device doesn't have a valid Curve25519 key, \
+                can't establish an Olm session"
+            );
+        }
+
+        Ok(())
+    }
+
+    fn keys_claim_request(
+        missing: BTreeMap<OwnedUserId, BTreeMap<OwnedDeviceId, DeviceKeyAlgorithm>>,
+        timed_out: BTreeMap<OwnedUserId, BTreeSet<OwnedDeviceId>>,
+    ) -> Option<(OwnedTransactionId, KeysClaimRequest)> {
         if missing.is_empty() {
-            Ok(None)
+            None
         } else {
             debug!(
                 ?missing,
@@ -285,12 +331,12 @@ impl SessionManager {
                 "Collected user/device pairs that are missing an Olm session"
             );
 
-            Ok(Some((
+            Some((
                 TransactionId::new(),
                 assign!(KeysClaimRequest::new(missing), {
                     timeout: Some(Self::KEY_CLAIM_TIMEOUT),
                 }),
-            )))
+            ))
         }
     }
 
@@ -572,6 +618,29 @@ mod tests {
         assert!(manager.get_missing_sessions(iter::once(bob.user_id())).await.unwrap().is_none());
     }
 
+    #[async_test]
+    async fn session_creation_for_devices() {
+        let manager = session_manager().await;
+        let bob = bob_account();
+        let bob_other =
+            ReadOnlyAccount::with_device_id(bob.user_id(), device_id!("BOBOTHERDEVICE"));
+
+        let bob_device = ReadOnlyDevice::from_account(&bob).await;
+        let bob_other_device = ReadOnlyDevice::from_account(&bob_other).await;
+
+        manager.store.save_devices(&[bob_device.clone(), bob_other_device]).await.unwrap();
+
+        let (_, request) = manager
+            .get_missing_sessions_for_devices(iter::once(&bob_device))
+            .await
+            .unwrap()
+            .unwrap();
+
+        let claimed_devices = request.one_time_keys.get(bob.user_id()).unwrap();
+        assert_eq!(claimed_devices.len(), 1);
+        assert!(claimed_devices.contains_key(bob.device_id()));
+    }
+
     #[async_test]
     async fn session_creation_waits_for_keys_query() {
         let manager = session_manager().await;
@@ -0,0 +1,133 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use ruma::{OwnedDeviceId, OwnedUserId};
+use serde::{Deserialize, Serialize};
+
+use crate::{types::events::room_key_withheld::WithheldCode, Device};
+
+/// Strategy to collect the devices that should receive room keys for the
+/// current discussion.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CollectStrategy {
+    /// Share the room key with all the devices of the room members, except
+    /// the ones that were blacklisted.
+    #[default]
+    AllDevices,
+    /// Share the room key only with devices that were cross-signed by their
+    /// owner, or that we manually marked as verified using
+    /// [`LocalTrust::Verified`].
+    ///
+    /// Whether we verified the owner of the device doesn't matter.
+    ///
+    /// [`LocalTrust::Verified`]: crate::LocalTrust::Verified
+    CrossSignedOnly,
+    /// Share the room key only with devices of users we verified, the devices
+    /// need to be cross-signed by their owner as well.
+    VerifiedUsersOnly,
+    /// Share the room key with all the devices of the room members, but refuse
+    /// to share it at all if the identity of any of the room members isn't
+    /// verified.
+    ///
+    /// Sharing will fail with an [`OlmError::UnverifiedIdentities`] error in
+    /// that case, the offending users are listed in the
+    /// [`RoomKeySharingReport::unverified_users`] of the room.
+    ///
+    /// [`OlmError::UnverifiedIdentities`]: crate::OlmError::UnverifiedIdentities
+    ErrorOnUnverifiedIdentity,
+}
+
+impl CollectStrategy {
+    /// Get the reason why the given device should not receive a room key, if
+    /// any.
+    pub(crate) fn exclusion_reason(
+        &self,
+        device: &Device,
+        only_allow_trusted_devices: bool,
+    ) -> Option<ExclusionReason> {
+        if device.is_blacklisted() {
+            return Some(ExclusionReason::Blacklisted);
+        }
+
+        match self {
+            CollectStrategy::AllDevices | CollectStrategy::ErrorOnUnverifiedIdentity => {}
+            CollectStrategy::CrossSignedOnly => {
+                if !device.is_cross_signed_by_owner() && !device.is_locally_trusted() {
+                    return Some(ExclusionReason::NotCrossSigned);
+                }
+            }
+            CollectStrategy::VerifiedUsersOnly => {
+                if !device.is_cross_signing_trusted() {
+                    return Some(ExclusionReason::UnverifiedUser);
+                }
+            }
+        }
+
+        if only_allow_trusted_devices && !device.is_verified() {
+            Some(ExclusionReason::Untrusted)
+        } else {
+            None
+        }
+    }
+}
+
+/// The reason why a device was excluded from receiving a room key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExclusionReason {
+    /// The device was blacklisted.
+    Blacklisted,
+    /// The device wasn't cross-signed by its owner nor manually verified, see
+    /// [`CollectStrategy::CrossSignedOnly`].
+    NotCrossSigned,
+    /// The owner of the device isn't verified, or the device wasn't signed by
+    /// its owner, see [`CollectStrategy::VerifiedUsersOnly`].
+    UnverifiedUser,
+    /// The device isn't verified and only trusted devices are allowed to
+    /// receive room keys, see
+    /// [`EncryptionSettings::only_allow_trusted_devices`].
+    ///
+    /// [`EncryptionSettings::only_allow_trusted_devices`]: crate::EncryptionSettings::only_allow_trusted_devices
+    Untrusted,
+}
+
+impl ExclusionReason {
+    /// The withheld code that should be sent to the excluded device.
+    pub fn withheld_code(&self) -> WithheldCode {
+        match self {
+            ExclusionReason::Blacklisted => WithheldCode::Blacklisted,
+            ExclusionReason::NotCrossSigned
+            | ExclusionReason::UnverifiedUser
+            | ExclusionReason::Untrusted => WithheldCode::Unverified,
+        }
+    }
+}
+
+/// Report of which devices would receive a room key and which ones are
+/// excluded, and why.
+#[derive(Clone, Debug, Default)]
+pub struct RoomKeySharingReport {
+    /// The devices that would receive the room key.
+    pub recipients: BTreeMap<OwnedUserId, BTreeSet<OwnedDeviceId>>,
+    /// The devices that wouldn't receive the room key, with the reason of the
+    /// exclusion.
+    pub excluded: BTreeMap<OwnedUserId, BTreeMap<OwnedDeviceId, ExclusionReason>>,
+    /// The users whose identity isn't verified, which would make sharing the
+    /// room key fail with the [`CollectStrategy::ErrorOnUnverifiedIdentity`]
+    /// strategy.
+    ///
+    /// This is always empty for the other strategies.
+    pub unverified_users: BTreeSet<OwnedUserId>,
+}
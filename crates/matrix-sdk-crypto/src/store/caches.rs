@@ -71,6 +71,16 @@ impl SessionStore {
     pub fn set_for_sender(&self, sender_key: &str, sessions: Vec<Session>) {
         self.entries.insert(sender_key.to_owned(), Arc::new(Mutex::new(sessions)));
     }
+
+    /// Remove all the sessions belonging to the sender key.
+    pub fn remove_for_sender(&self, sender_key: &str) {
+        self.entries.remove(sender_key);
+    }
+
+    /// Get the sender keys of all the sessions in the store.
+    pub fn sender_keys(&self) -> Vec<String> {
+        self.entries.iter().map(|e| e.key().to_owned()).collect()
+    }
 }
 
 #[derive(Debug, Default)]
@@ -119,6 +129,13 @@ impl GroupSessionStore {
     pub fn get(&self, room_id: &RoomId, session_id: &str) -> Option<InboundGroupSession> {
         self.entries.get(room_id)?.get(session_id).cloned()
     }
+
+    /// Remove an inbound group session from our store.
+    ///
+    /// Returns the session if it was in the store.
+    pub fn remove(&self, room_id: &RoomId, session_id: &str) -> Option<InboundGroupSession> {
+        self.entries.get_mut(room_id)?.remove(session_id)
+    }
 }
 
 /// In-memory store holding the devices of users.
@@ -83,6 +83,11 @@ pub enum CryptoStoreError {
     /// An error due to locking.
     #[error(transparent)]
     Lock(#[from] LockStoreError),
+
+    /// The content of a store that was migrated doesn't match the content of
+    /// the store it was migrated from.
+    #[error("the migrated store doesn't match the source store: {0}")]
+    MigrationMismatch(String),
 }
 
 impl CryptoStoreError {
@@ -14,7 +14,8 @@ macro_rules! cryptostore_integration_tests {
                 room_id,
                 serde::{Base64, Raw},
                 to_device::DeviceIdOrAllDevices,
-                user_id, DeviceId, JsOption, OwnedDeviceId, OwnedUserId, TransactionId, UserId,
+                uint, user_id, DeviceId, JsOption, OwnedDeviceId, OwnedUserId,
+                SecondsSinceUnixEpoch, TransactionId, UserId,
             };
             use serde_json::value::to_raw_value;
             use $crate::{
@@ -23,8 +24,9 @@ macro_rules! cryptostore_integration_tests {
                     PrivateCrossSigningIdentity, ReadOnlyAccount, Session,
                 },
                 store::{
-                    BackupKeys, Changes, CryptoStore, DeviceChanges,
-                    GossipRequest, IdentityChanges, BackupDecryptionKey, RoomSettings,
+                    migrate_crypto_store, BackupKeys, Changes, CryptoStore, DeviceChanges,
+                    GossipRequest, IdentityChanges, IntoCryptoStore, BackupDecryptionKey,
+                    MemoryStore, RoomSettings,
                 },
                 testing::{get_device, get_other_identity, get_own_identity},
                 types::{
@@ -41,7 +43,8 @@ macro_rules! cryptostore_integration_tests {
                     },
                     EventEncryptionAlgorithm,
                 },
-                ReadOnlyDevice, SecretInfo, ToDeviceRequest, TrackedUser, GossippedSecret,
+                CollectStrategy, ReadOnlyDevice, SecretInfo, ToDeviceRequest, TrackedUser,
+                GossippedSecret,
             };
 
             use super::get_store;
@@ -184,6 +187,27 @@ macro_rules! cryptostore_integration_tests {
                 assert_eq!(&session, &loaded_session);
             }
 
+            #[async_test]
+            async fn session_deleting() {
+                let store = get_store("session_deleting", None).await;
+                let (account, session) = get_account_and_session().await;
+                let sender_key = session.sender_key.to_base64();
+                store.save_account(account.clone()).await.expect("Can't save account");
+
+                let changes = Changes { sessions: vec![session.clone()], ..Default::default() };
+                store.save_changes(changes).await.unwrap();
+
+                let sender_keys = store.get_session_sender_keys().await.unwrap();
+                assert_eq!(sender_keys, vec![sender_key.clone()]);
+
+                store.delete_sessions(&sender_key).await.unwrap();
+
+                assert!(store.get_session_sender_keys().await.unwrap().is_empty());
+                if let Some(sessions) = store.get_sessions(&sender_key).await.unwrap() {
+                    assert!(sessions.lock().await.is_empty());
+                }
+            }
+
             #[async_test]
             async fn add_and_save_session() {
                 let store_name = "add_and_save_session";
@@ -320,6 +344,78 @@ macro_rules! cryptostore_integration_tests {
                 assert_eq!(to_back_up, vec![session]);
             }
 
+            #[async_test]
+            async fn inbound_group_session_deleting() {
+                let (account, store) = get_loaded_store("inbound_group_session_deleting").await;
+
+                let room_id = &room_id!("!test:localhost");
+                let (_, session) = account.create_group_session_pair_with_defaults(room_id).await;
+                let (_, other_session) =
+                    account.create_group_session_pair_with_defaults(room_id).await;
+
+                let changes = Changes {
+                    inbound_group_sessions: vec![session.clone(), other_session.clone()],
+                    ..Default::default()
+                };
+                store.save_changes(changes).await.expect("Can't save group sessions");
+                assert_eq!(store.inbound_group_session_counts().await.unwrap().total, 2);
+
+                store
+                    .delete_inbound_group_sessions(room_id, &[session.session_id().to_owned()])
+                    .await
+                    .unwrap();
+
+                assert!(store
+                    .get_inbound_group_session(room_id, session.session_id())
+                    .await
+                    .unwrap()
+                    .is_none());
+                assert!(store
+                    .get_inbound_group_session(room_id, other_session.session_id())
+                    .await
+                    .unwrap()
+                    .is_some());
+                assert_eq!(store.inbound_group_session_counts().await.unwrap().total, 1);
+            }
+
+            #[async_test]
+            async fn inbound_group_session_batches() {
+                let (account, store) = get_loaded_store("inbound_group_session_batches").await;
+
+                let mut sessions = Vec::new();
+                for room_id in [room_id!("!a:localhost"), room_id!("!b:localhost")] {
+                    for _ in 0..3 {
+                        let (_, session) =
+                            account.create_group_session_pair_with_defaults(room_id).await;
+                        sessions.push(session);
+                    }
+                }
+
+                let changes =
+                    Changes { inbound_group_sessions: sessions.clone(), ..Default::default() };
+                store.save_changes(changes).await.expect("Can't save group sessions");
+
+                let mut loaded: Vec<InboundGroupSession> = Vec::new();
+                loop {
+                    let after = loaded.last().map(|s| (s.room_id(), s.session_id()));
+                    let batch = store.get_inbound_group_sessions_batch(after, 4).await.unwrap();
+                    assert!(batch.len() <= 4);
+
+                    if batch.is_empty() {
+                        break;
+                    }
+
+                    loaded.extend(batch);
+                }
+
+                let mut expected: Vec<_> =
+                    sessions.iter().map(|s| s.session_id().to_owned()).collect();
+                expected.sort();
+                let mut loaded: Vec<_> = loaded.iter().map(|s| s.session_id().to_owned()).collect();
+                loaded.sort();
+                assert_eq!(loaded, expected);
+            }
+
             #[async_test]
             async fn load_inbound_group_session() {
                 let dir = "load_inbound_group_session";
@@ -356,6 +452,71 @@ macro_rules! cryptostore_integration_tests {
                 assert_eq!(store.inbound_group_session_counts().await.unwrap().total, 1);
             }
 
+            #[async_test]
+            async fn crypto_store_migration() {
+                let (account, session) = get_account_and_session().await;
+                let room_id = room_id!("!test:localhost");
+                let (_, inbound) = account.create_group_session_pair_with_defaults(room_id).await;
+                let device = get_device();
+
+                let source = MemoryStore::new();
+                source
+                    .save_changes(Changes {
+                        account: Some(account.clone()),
+                        sessions: vec![session.clone()],
+                        inbound_group_sessions: vec![inbound.clone()],
+                        devices: DeviceChanges { new: vec![device.clone()], ..Default::default() },
+                        ..Default::default()
+                    })
+                    .await
+                    .unwrap();
+                source.save_tracked_users(&[(device.user_id(), false)]).await.unwrap();
+
+                // The settings of a room without room keys are copied too.
+                let other_room_id = room_id!("!other:localhost");
+                let settings =
+                    RoomSettings { only_allow_trusted_devices: true, ..Default::default() };
+                let room_settings = HashMap::from([(other_room_id.to_owned(), settings.clone())]);
+                source
+                    .save_changes(Changes { room_settings, ..Default::default() })
+                    .await
+                    .unwrap();
+                let source = source.into_crypto_store();
+
+                let target = get_store("crypto_store_migration", None).await.into_crypto_store();
+                let room_ids = [room_id.to_owned(), other_room_id.to_owned()];
+                let report = migrate_crypto_store(&*source, &*target, &room_ids, |_, _, _| {})
+                    .await
+                    .unwrap();
+
+                assert_eq!(report.tracked_users, 1);
+                assert_eq!(report.devices, 1);
+                assert_eq!(report.olm_sessions, 1);
+                assert_eq!(report.inbound_group_sessions, 1);
+                assert_eq!(report.room_settings, 1);
+                assert_eq!(target.get_room_settings(other_room_id).await.unwrap(), Some(settings));
+
+                let loaded = target.load_account().await.unwrap().unwrap();
+                assert_eq!(loaded.identity_keys().curve25519, account.identity_keys().curve25519);
+
+                let sessions =
+                    target.get_sessions(&session.sender_key.to_base64()).await.unwrap().unwrap();
+                assert_eq!(sessions.lock().await[0], session);
+
+                let loaded = target
+                    .get_inbound_group_session(room_id, inbound.session_id())
+                    .await
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(loaded, inbound);
+
+                let loaded = target.get_device(device.user_id(), device.device_id()).await.unwrap();
+                assert_eq!(loaded, Some(device));
+
+                // Migrating again into the same store is possible.
+                migrate_crypto_store(&*source, &*target, &room_ids, |_, _, _| {}).await.unwrap();
+            }
+
             #[async_test]
             async fn test_tracked_users() {
                 let dir = "test_tracked_users";
@@ -582,6 +743,30 @@ macro_rules! cryptostore_integration_tests {
                 assert!(store.is_message_known(&hash).await.unwrap());
             }
 
+            #[async_test]
+            async fn olm_hash_clearing() {
+                let (_, store) = get_loaded_store("olm_hash_clearing").await;
+
+                let hash = OlmMessageHash {
+                    sender_key: "test_sender".to_owned(),
+                    hash: "test_hash".to_owned(),
+                };
+
+                let mut changes = Changes::default();
+                changes.message_hashes.push(hash.clone());
+                store.save_changes(changes).await.unwrap();
+
+                // The hash was just stored, it is newer than the cutoff.
+                let now = SecondsSinceUnixEpoch::now().get();
+                let older_than = SecondsSinceUnixEpoch(now - uint!(60));
+                assert_eq!(store.clear_message_hashes(older_than).await.unwrap(), 0);
+                assert!(store.is_message_known(&hash).await.unwrap());
+
+                let older_than = SecondsSinceUnixEpoch(now + uint!(60));
+                assert_eq!(store.clear_message_hashes(older_than).await.unwrap(), 1);
+                assert!(!store.is_message_known(&hash).await.unwrap());
+            }
+
             #[async_test]
             async fn key_request_saving() {
                 let (account, store) = get_loaded_store("key_request_saving").await;
@@ -618,6 +803,8 @@ macro_rules! cryptostore_integration_tests {
                 let stored_request = store.get_secret_request_by_info(&info).await.unwrap();
                 assert_eq!(request, stored_request);
                 assert!(!store.get_unsent_secret_requests().await.unwrap().is_empty());
+                let all_requests = store.get_all_secret_requests().await.unwrap();
+                assert_eq!(all_requests, request.clone().into_iter().collect::<Vec<_>>());
 
                 let request = GossipRequest {
                     request_recipient: account.user_id().to_owned(),
@@ -631,6 +818,7 @@ macro_rules! cryptostore_integration_tests {
                 store.save_changes(changes).await.unwrap();
 
                 assert!(store.get_unsent_secret_requests().await.unwrap().is_empty());
+                assert_eq!(store.get_all_secret_requests().await.unwrap(), vec![request.clone()]);
                 let stored_request = store.get_outgoing_secret_requests(&id).await.unwrap();
                 assert_eq!(Some(request), stored_request);
 
@@ -642,6 +830,7 @@ macro_rules! cryptostore_integration_tests {
                 let stored_request = store.get_secret_request_by_info(&info).await.unwrap();
                 assert_eq!(None, stored_request);
                 assert!(store.get_unsent_secret_requests().await.unwrap().is_empty());
+                assert!(store.get_all_secret_requests().await.unwrap().is_empty());
             }
 
             #[async_test]
@@ -806,12 +995,16 @@ macro_rules! cryptostore_integration_tests {
                 let settings_1 = RoomSettings {
                     algorithm: EventEncryptionAlgorithm::MegolmV1AesSha2,
                     only_allow_trusted_devices: true,
+                    sharing_strategy: CollectStrategy::VerifiedUsersOnly,
+                    error_on_identity_violation: true,
                 };
 
                 let room_2 = room_id!("!test_2:localhost");
                 let settings_2 = RoomSettings {
                     algorithm: EventEncryptionAlgorithm::OlmV1Curve25519AesSha2,
                     only_allow_trusted_devices: false,
+                    sharing_strategy: CollectStrategy::AllDevices,
+                    error_on_identity_violation: false,
                 };
 
                 let room_3 = room_id!("!test_3:localhost");
@@ -20,13 +20,12 @@ use std::{
 };
 
 use async_trait::async_trait;
-use dashmap::{DashMap, DashSet};
+use dashmap::DashMap;
 use ruma::{
     events::secret::request::SecretName, DeviceId, OwnedDeviceId, OwnedRoomId, OwnedTransactionId,
-    OwnedUserId, RoomId, TransactionId, UserId,
+    OwnedUserId, RoomId, SecondsSinceUnixEpoch, TransactionId, UserId,
 };
 use tokio::sync::{Mutex, RwLock};
-use tracing::warn;
 
 use super::{
     caches::{DeviceStore, GroupSessionStore, SessionStore},
@@ -55,12 +54,13 @@ fn encode_key_info(info: &SecretInfo) -> String {
 pub struct MemoryStore {
     sessions: SessionStore,
     inbound_group_sessions: GroupSessionStore,
-    olm_hashes: DashMap<String, DashSet<String>>,
+    olm_hashes: DashMap<String, DashMap<String, SecondsSinceUnixEpoch>>,
     devices: DeviceStore,
     identities: DashMap<OwnedUserId, ReadOnlyUserIdentities>,
     outgoing_key_requests: DashMap<OwnedTransactionId, GossipRequest>,
     key_requests_by_info: DashMap<String, OwnedTransactionId>,
     direct_withheld_info: DashMap<OwnedRoomId, DashMap<String, RoomKeyWithheldEvent>>,
+    room_settings: DashMap<OwnedRoomId, RoomSettings>,
     custom_values: DashMap<String, Vec<u8>>,
     leases: DashMap<String, (String, Instant)>,
     secret_inbox: DashMap<String, Vec<GossippedSecret>>,
@@ -79,6 +79,7 @@ impl Default for MemoryStore {
             outgoing_key_requests: Default::default(),
             key_requests_by_info: Default::default(),
             direct_withheld_info: Default::default(),
+            room_settings: Default::default(),
             custom_values: Default::default(),
             leases: Default::default(),
             backup_keys: Default::default(),
@@ -154,11 +155,10 @@ impl CryptoStore for MemoryStore {
             let _ = self.identities.insert(identity.user_id().to_owned(), identity.clone());
         }
 
+        let now = SecondsSinceUnixEpoch::now();
+
         for hash in changes.message_hashes {
-            self.olm_hashes
-                .entry(hash.sender_key.to_owned())
-                .or_default()
-                .insert(hash.hash.clone());
+            self.olm_hashes.entry(hash.sender_key.to_owned()).or_default().insert(hash.hash, now);
         }
 
         for key_request in changes.key_requests {
@@ -190,6 +190,10 @@ impl CryptoStore for MemoryStore {
             }
         }
 
+        for (room_id, settings) in changes.room_settings {
+            self.room_settings.insert(room_id, settings);
+        }
+
         // Note: this will save an empty next_batch token, if provided an empty one.
         *self.next_batch_token.write().await = changes.next_batch_token;
 
@@ -200,6 +204,16 @@ impl CryptoStore for MemoryStore {
         Ok(self.sessions.get(sender_key))
     }
 
+    async fn get_session_sender_keys(&self) -> Result<Vec<String>> {
+        Ok(self.sessions.sender_keys())
+    }
+
+    async fn delete_sessions(&self, sender_key: &str) -> Result<()> {
+        self.sessions.remove_for_sender(sender_key);
+
+        Ok(())
+    }
+
     async fn get_inbound_group_session(
         &self,
         room_id: &RoomId,
@@ -223,6 +237,33 @@ impl CryptoStore for MemoryStore {
         Ok(self.inbound_group_sessions.get_all())
     }
 
+    async fn get_inbound_group_sessions_batch(
+        &self,
+        after: Option<(&RoomId, &str)>,
+        limit: usize,
+    ) -> Result<Vec<InboundGroupSession>> {
+        let mut sessions = self.inbound_group_sessions.get_all();
+        sessions.sort_by(|a, b| (a.room_id(), a.session_id()).cmp(&(b.room_id(), b.session_id())));
+
+        Ok(sessions
+            .into_iter()
+            .filter(|s| after.map_or(true, |after| (s.room_id(), s.session_id()) > after))
+            .take(limit)
+            .collect())
+    }
+
+    async fn delete_inbound_group_sessions(
+        &self,
+        room_id: &RoomId,
+        session_ids: &[String],
+    ) -> Result<()> {
+        for session_id in session_ids {
+            self.inbound_group_sessions.remove(room_id, session_id);
+        }
+
+        Ok(())
+    }
+
     async fn inbound_group_session_counts(&self) -> Result<RoomKeyCounts> {
         let backed_up =
             self.get_inbound_group_sessions().await?.into_iter().filter(|s| s.backed_up()).count();
@@ -291,7 +332,21 @@ impl CryptoStore for MemoryStore {
             .olm_hashes
             .entry(message_hash.sender_key.to_owned())
             .or_default()
-            .contains(&message_hash.hash))
+            .contains_key(&message_hash.hash))
+    }
+
+    async fn clear_message_hashes(&self, older_than: SecondsSinceUnixEpoch) -> Result<usize> {
+        let mut count = 0;
+
+        for hashes in self.olm_hashes.iter() {
+            let len = hashes.len();
+            hashes.retain(|_, added_at| *added_at >= older_than);
+            count += len - hashes.len();
+        }
+
+        self.olm_hashes.retain(|_, hashes| !hashes.is_empty());
+
+        Ok(count)
     }
 
     async fn get_outgoing_secret_requests(
@@ -322,6 +377,10 @@ impl CryptoStore for MemoryStore {
             .collect())
     }
 
+    async fn get_all_secret_requests(&self) -> Result<Vec<GossipRequest>> {
+        Ok(self.outgoing_key_requests.iter().map(|i| i.value().clone()).collect())
+    }
+
     async fn delete_outgoing_secret_requests(&self, request_id: &TransactionId) -> Result<()> {
         self.outgoing_key_requests.remove(request_id).and_then(|(_, i)| {
             let key_info_string = encode_key_info(&i.info);
@@ -344,9 +403,8 @@ impl CryptoStore for MemoryStore {
         Ok(())
     }
 
-    async fn get_room_settings(&self, _room_id: &RoomId) -> Result<Option<RoomSettings>> {
-        warn!("Method not implemented");
-        Ok(None)
+    async fn get_room_settings(&self, room_id: &RoomId) -> Result<Option<RoomSettings>> {
+        Ok(self.room_settings.get(room_id).map(|settings| settings.clone()))
     }
 
     async fn get_custom_value(&self, key: &str) -> Result<Option<Vec<u8>>> {
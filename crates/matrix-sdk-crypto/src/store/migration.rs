@@ -0,0 +1,471 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Copy of the content of a crypto store into another crypto store.
+
+use std::collections::{BTreeSet, HashMap};
+
+use ruma::{OwnedRoomId, OwnedUserId, UserId};
+use tracing::{debug, info};
+
+use super::{
+    BackupKeys, Changes, CryptoStoreError, DeviceChanges, DynCryptoStore, Result, SETTING_KEYS,
+};
+
+/// The number of room keys that are loaded from the source store and saved in
+/// the target store at once.
+const ROOM_KEY_BATCH_SIZE: usize = 1000;
+
+/// The steps of a crypto store migration, reported to the progress listener
+/// of [`migrate_crypto_store`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CryptoStoreMigrationStep {
+    /// The Olm account, the private cross-signing identity, the backup keys
+    /// and the settings of the store are copied.
+    Account,
+    /// The users whose devices we track are copied.
+    TrackedUsers,
+    /// The devices and user identities of the tracked users are copied.
+    Devices,
+    /// The Olm sessions are copied.
+    OlmSessions,
+    /// The room keys and the room settings are copied.
+    InboundGroupSessions,
+    /// The outgoing secret and room key requests are copied.
+    SecretRequests,
+    /// The content of the target store is checked against the source store.
+    Verification,
+}
+
+/// Report of the data that was copied by [`migrate_crypto_store`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CryptoStoreMigrationReport {
+    /// The number of tracked users that were copied.
+    pub tracked_users: usize,
+    /// The number of devices that were copied.
+    pub devices: usize,
+    /// The number of user identities that were copied.
+    pub identities: usize,
+    /// The number of Olm sessions that were copied.
+    pub olm_sessions: usize,
+    /// The number of room keys that were copied.
+    pub inbound_group_sessions: usize,
+    /// The number of room settings that were copied.
+    pub room_settings: usize,
+    /// The number of outgoing secret requests that were copied.
+    pub secret_requests: usize,
+}
+
+/// Copy the content of the `source` crypto store into the `target` crypto
+/// store, for example to switch to another store implementation without
+/// having to log in again.
+///
+/// This copies the Olm account, the private cross-signing identity, the backup
+/// keys, the tracked users with their devices and identities, the Olm
+/// sessions, the room keys, the room settings, the outgoing secret requests
+/// and the settings of the store. Once everything is copied, the content of
+/// the target store is checked against the source store.
+///
+/// Data that can't be listed with the [`CryptoStore`] methods, like the
+/// hashes of the decrypted Olm messages or the withheld room key
+/// notifications, isn't copied. Outbound group sessions aren't copied either,
+/// new ones are created when needed.
+///
+/// The target store must be empty or contain the same Olm account as the
+/// source store, so an interrupted migration can be started again.
+///
+/// # Arguments
+///
+/// * `source` - The store to read the data from, it is left untouched.
+///
+/// * `target` - The store to copy the data into.
+///
+/// * `room_ids` - The rooms known to the client, for example the rooms of the
+/// state store. The crypto store can't list the rooms it holds settings for,
+/// so the settings of these rooms are copied, in addition to the settings of
+/// the rooms that have room keys.
+///
+/// * `progress_listener` - A closure that is called with the current step of
+/// the migration, the number of items of this step that were copied so far,
+/// and the total number of items of this step.
+///
+/// [`CryptoStore`]: super::CryptoStore
+pub async fn migrate_crypto_store(
+    source: &DynCryptoStore,
+    target: &DynCryptoStore,
+    room_ids: &[OwnedRoomId],
+    progress_listener: impl Fn(CryptoStoreMigrationStep, usize, usize),
+) -> Result<CryptoStoreMigrationReport> {
+    let mut report = CryptoStoreMigrationReport::default();
+
+    // The account needs to be loaded first, some stores need it to unpickle
+    // the other objects.
+    let account = source.load_account().await?.ok_or(CryptoStoreError::AccountUnset)?;
+
+    if let Some(existing) = target.load_account().await? {
+        if existing.identity_keys().curve25519 != account.identity_keys().curve25519 {
+            return Err(CryptoStoreError::MismatchedAccount {
+                expected: (account.user_id().to_owned(), account.device_id().to_owned()),
+                got: (existing.user_id().to_owned(), existing.device_id().to_owned()),
+            });
+        }
+    }
+
+    let own_user_id = account.user_id().to_owned();
+
+    progress_listener(CryptoStoreMigrationStep::Account, 0, 1);
+
+    let BackupKeys { decryption_key, backup_version } = source.load_backup_keys().await?;
+    let changes = Changes {
+        account: Some(account.clone()),
+        private_identity: source.load_identity().await?,
+        backup_version,
+        backup_decryption_key: decryption_key,
+        next_batch_token: source.next_batch_token().await?,
+        ..Default::default()
+    };
+    target.save_changes(changes).await?;
+
+    for key in SETTING_KEYS {
+        if let Some(value) = source.get_custom_value(key).await? {
+            target.set_custom_value(key, value).await?;
+        }
+    }
+
+    progress_listener(CryptoStoreMigrationStep::Account, 1, 1);
+
+    let tracked_users = source.load_tracked_users().await?;
+    report.tracked_users = tracked_users.len();
+
+    progress_listener(CryptoStoreMigrationStep::TrackedUsers, 0, report.tracked_users);
+    let users: Vec<_> = tracked_users.iter().map(|u| (u.user_id.as_ref(), u.dirty)).collect();
+    target.save_tracked_users(&users).await?;
+    progress_listener(
+        CryptoStoreMigrationStep::TrackedUsers,
+        report.tracked_users,
+        report.tracked_users,
+    );
+
+    let mut users: BTreeSet<OwnedUserId> = tracked_users.into_iter().map(|u| u.user_id).collect();
+    users.insert(own_user_id);
+
+    migrate_devices(source, target, &users, &mut report, &progress_listener).await?;
+    migrate_olm_sessions(source, target, &mut report, &progress_listener).await?;
+    migrate_room_keys(source, target, room_ids, &mut report, &progress_listener).await?;
+
+    let requests = source.get_all_secret_requests().await?;
+    report.secret_requests = requests.len();
+
+    progress_listener(CryptoStoreMigrationStep::SecretRequests, 0, report.secret_requests);
+    target.save_changes(Changes { key_requests: requests, ..Default::default() }).await?;
+    progress_listener(
+        CryptoStoreMigrationStep::SecretRequests,
+        report.secret_requests,
+        report.secret_requests,
+    );
+
+    verify(source, target, &users, &progress_listener).await?;
+
+    info!(?report, "Migrated the crypto store");
+
+    Ok(report)
+}
+
+/// Copy the devices and the user identities of the given users.
+async fn migrate_devices(
+    source: &DynCryptoStore,
+    target: &DynCryptoStore,
+    users: &BTreeSet<OwnedUserId>,
+    report: &mut CryptoStoreMigrationReport,
+    progress_listener: &impl Fn(CryptoStoreMigrationStep, usize, usize),
+) -> Result<()> {
+    let total = users.len();
+    progress_listener(CryptoStoreMigrationStep::Devices, 0, total);
+
+    for (i, user_id) in users.iter().enumerate() {
+        let devices: Vec<_> = source.get_user_devices(user_id).await?.into_values().collect();
+        let identity = source.get_user_identity(user_id).await?;
+
+        report.devices += devices.len();
+        report.identities += usize::from(identity.is_some());
+
+        let mut changes = Changes {
+            devices: DeviceChanges { new: devices, ..Default::default() },
+            ..Default::default()
+        };
+        changes.identities.new.extend(identity);
+        target.save_changes(changes).await?;
+
+        progress_listener(CryptoStoreMigrationStep::Devices, i + 1, total);
+    }
+
+    Ok(())
+}
+
+/// Copy all the Olm sessions, one sender key at a time.
+async fn migrate_olm_sessions(
+    source: &DynCryptoStore,
+    target: &DynCryptoStore,
+    report: &mut CryptoStoreMigrationReport,
+    progress_listener: &impl Fn(CryptoStoreMigrationStep, usize, usize),
+) -> Result<()> {
+    let sender_keys = source.get_session_sender_keys().await?;
+    let total = sender_keys.len();
+    progress_listener(CryptoStoreMigrationStep::OlmSessions, 0, total);
+
+    for (i, sender_key) in sender_keys.iter().enumerate() {
+        if let Some(sessions) = source.get_sessions(sender_key).await? {
+            let sessions = sessions.lock().await.clone();
+            report.olm_sessions += sessions.len();
+
+            target.save_changes(Changes { sessions, ..Default::default() }).await?;
+        }
+
+        progress_listener(CryptoStoreMigrationStep::OlmSessions, i + 1, total);
+    }
+
+    Ok(())
+}
+
+/// Copy all the room keys, in batches, as well as the settings of the rooms
+/// they belong to.
+async fn migrate_room_keys(
+    source: &DynCryptoStore,
+    target: &DynCryptoStore,
+    room_ids: &[OwnedRoomId],
+    report: &mut CryptoStoreMigrationReport,
+    progress_listener: &impl Fn(CryptoStoreMigrationStep, usize, usize),
+) -> Result<()> {
+    let total = source.inbound_group_session_counts().await?.total;
+    progress_listener(CryptoStoreMigrationStep::InboundGroupSessions, 0, total);
+
+    let mut rooms: BTreeSet<_> = room_ids.iter().cloned().collect();
+    let mut last: Option<(OwnedRoomId, String)> = None;
+
+    loop {
+        let after = last.as_ref().map(|(room_id, session_id)| (&**room_id, session_id.as_str()));
+        let batch = source.get_inbound_group_sessions_batch(after, ROOM_KEY_BATCH_SIZE).await?;
+
+        let Some(last_session) = batch.last() else {
+            break;
+        };
+        last = Some((last_session.room_id().to_owned(), last_session.session_id().to_owned()));
+
+        rooms.extend(batch.iter().map(|s| s.room_id().to_owned()));
+        report.inbound_group_sessions += batch.len();
+
+        let changes = Changes { inbound_group_sessions: batch, ..Default::default() };
+        target.save_changes(changes).await?;
+
+        progress_listener(
+            CryptoStoreMigrationStep::InboundGroupSessions,
+            report.inbound_group_sessions,
+            total,
+        );
+    }
+
+    let mut room_settings = HashMap::new();
+
+    for room_id in rooms {
+        if let Some(settings) = source.get_room_settings(&room_id).await? {
+            room_settings.insert(room_id, settings);
+        }
+    }
+
+    report.room_settings = room_settings.len();
+    target.save_changes(Changes { room_settings, ..Default::default() }).await?;
+
+    Ok(())
+}
+
+/// Check that the content of the target store matches the content of the
+/// source store.
+async fn verify(
+    source: &DynCryptoStore,
+    target: &DynCryptoStore,
+    users: &BTreeSet<OwnedUserId>,
+    progress_listener: &impl Fn(CryptoStoreMigrationStep, usize, usize),
+) -> Result<()> {
+    progress_listener(CryptoStoreMigrationStep::Verification, 0, 1);
+
+    let source_account = source.load_account().await?.map(|a| a.identity_keys().curve25519);
+    let target_account = target.load_account().await?.map(|a| a.identity_keys().curve25519);
+
+    if source_account != target_account {
+        return Err(mismatch("the Olm account"));
+    }
+
+    if source.load_tracked_users().await?.len() != target.load_tracked_users().await?.len() {
+        return Err(mismatch("the tracked users"));
+    }
+
+    for user_id in users {
+        verify_user(source, target, user_id).await?;
+    }
+
+    for sender_key in source.get_session_sender_keys().await? {
+        if session_count(source, &sender_key).await? != session_count(target, &sender_key).await? {
+            return Err(mismatch(&format!("the Olm sessions with {sender_key}")));
+        }
+    }
+
+    let source_counts = source.inbound_group_session_counts().await?;
+    let target_counts = target.inbound_group_session_counts().await?;
+
+    if source_counts.total != target_counts.total
+        || source_counts.backed_up != target_counts.backed_up
+    {
+        return Err(mismatch("the room keys"));
+    }
+
+    debug!("The content of the migrated crypto store matches the source store");
+    progress_listener(CryptoStoreMigrationStep::Verification, 1, 1);
+
+    Ok(())
+}
+
+async fn verify_user(
+    source: &DynCryptoStore,
+    target: &DynCryptoStore,
+    user_id: &UserId,
+) -> Result<()> {
+    let source_devices = source.get_user_devices(user_id).await?;
+    let target_devices = target.get_user_devices(user_id).await?;
+
+    if source_devices.len() != target_devices.len()
+        || source_devices.keys().any(|device_id| !target_devices.contains_key(device_id))
+    {
+        return Err(mismatch(&format!("the devices of {user_id}")));
+    }
+
+    let source_identity = source.get_user_identity(user_id).await?;
+    let target_identity = target.get_user_identity(user_id).await?;
+
+    if source_identity.is_some() != target_identity.is_some() {
+        return Err(mismatch(&format!("the identity of {user_id}")));
+    }
+
+    Ok(())
+}
+
+async fn session_count(store: &DynCryptoStore, sender_key: &str) -> Result<usize> {
+    Ok(match store.get_sessions(sender_key).await? {
+        Some(sessions) => sessions.lock().await.len(),
+        None => 0,
+    })
+}
+
+fn mismatch(what: &str) -> CryptoStoreError {
+    CryptoStoreError::MigrationMismatch(format!("{what} differ between the stores"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use matrix_sdk_test::async_test;
+    use ruma::room_id;
+
+    use super::{migrate_crypto_store, CryptoStoreMigrationReport, CryptoStoreMigrationStep};
+    use crate::{
+        identities::device::testing::get_device,
+        olm::{tests::get_account_and_session, ReadOnlyAccount},
+        store::{Changes, CryptoStore, DeviceChanges, IntoCryptoStore, MemoryStore},
+    };
+
+    #[async_test]
+    async fn test_migrate_crypto_store() {
+        let (account, session) = get_account_and_session().await;
+        let room_id = room_id!("!test:localhost");
+        let (_, inbound) = account.create_group_session_pair_with_defaults(room_id).await;
+        let device = get_device();
+
+        let source = MemoryStore::new();
+        source
+            .save_changes(Changes {
+                account: Some(account.clone()),
+                sessions: vec![session.clone()],
+                inbound_group_sessions: vec![inbound.clone()],
+                devices: DeviceChanges { new: vec![device.clone()], ..Default::default() },
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        source.save_tracked_users(&[(device.user_id(), false)]).await.unwrap();
+        let source = source.into_crypto_store();
+
+        let target = MemoryStore::new().into_crypto_store();
+        let steps = Mutex::new(Vec::new());
+
+        let report = migrate_crypto_store(&*source, &*target, &[], |step, done, total| {
+            if done == total {
+                steps.lock().unwrap().push(step);
+            }
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(
+            report,
+            CryptoStoreMigrationReport {
+                tracked_users: 1,
+                devices: 1,
+                olm_sessions: 1,
+                inbound_group_sessions: 1,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            steps.into_inner().unwrap().last(),
+            Some(&CryptoStoreMigrationStep::Verification)
+        );
+
+        let loaded = target.load_account().await.unwrap().unwrap();
+        assert_eq!(loaded.identity_keys().curve25519, account.identity_keys().curve25519);
+
+        let sessions = target.get_sessions(&session.sender_key.to_base64()).await.unwrap().unwrap();
+        assert_eq!(sessions.lock().await[0], session);
+
+        let loaded =
+            target.get_inbound_group_session(room_id, inbound.session_id()).await.unwrap().unwrap();
+        assert_eq!(loaded, inbound);
+
+        let loaded = target.get_device(device.user_id(), device.device_id()).await.unwrap();
+        assert_eq!(loaded, Some(device));
+
+        // Migrating again into the same store is possible.
+        migrate_crypto_store(&*source, &*target, &[], |_, _, _| {}).await.unwrap();
+    }
+
+    #[async_test]
+    async fn test_migrate_crypto_store_into_other_account() {
+        let (account, _) = get_account_and_session().await;
+        let other = ReadOnlyAccount::with_device_id(account.user_id(), account.device_id());
+
+        let source = MemoryStore::new();
+        source.save_account(account).await.unwrap();
+        let target = MemoryStore::new();
+        target.save_account(other).await.unwrap();
+
+        let result = migrate_crypto_store(
+            &*source.into_crypto_store(),
+            &*target.into_crypto_store(),
+            &[],
+            |_, _, _| {},
+        )
+        .await;
+
+        assert!(result.is_err());
+    }
+}
@@ -39,7 +39,7 @@
 //! [`CryptoStore`]: trait.Cryptostore.html
 
 use std::{
-    collections::{BTreeMap, HashMap, HashSet},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     fmt::Debug,
     ops::Deref,
     sync::{atomic::AtomicBool, Arc},
@@ -52,7 +52,8 @@ use dashmap::DashSet;
 use futures_core::Stream;
 use futures_util::stream::StreamExt;
 use ruma::{
-    events::secret::request::SecretName, DeviceId, OwnedDeviceId, OwnedRoomId, OwnedUserId, UserId,
+    events::secret::request::SecretName, DeviceId, OwnedDeviceId, OwnedRoomId, OwnedUserId, RoomId,
+    UserId,
 };
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use thiserror::Error;
@@ -66,7 +67,8 @@ use crate::{
     gossiping::GossippedSecret,
     identities::{
         user::{OwnUserIdentity, UserIdentities, UserIdentity},
-        Device, ReadOnlyDevice, ReadOnlyUserIdentities, UserDevices,
+        Device, IdentityViolation, ReadOnlyDevice, ReadOnlyOwnUserIdentity, ReadOnlyUserIdentities,
+        UserDevices,
     },
     olm::{
         InboundGroupSession, OlmMessageHash, OutboundGroupSession, PrivateCrossSigningIdentity,
@@ -75,13 +77,15 @@ use crate::{
     types::{events::room_key_withheld::RoomKeyWithheldEvent, EventEncryptionAlgorithm},
     utilities::encode,
     verification::VerificationMachine,
-    CrossSigningStatus,
+    CollectStrategy, CrossSigningStatus,
 };
 
 pub mod caches;
 mod error;
 pub mod locks;
 mod memorystore;
+mod migration;
+mod prune;
 mod traits;
 
 #[cfg(any(test, feature = "testing"))]
@@ -93,11 +97,27 @@ use caches::{SequenceNumber, UsersForKeyQuery};
 pub use error::{CryptoStoreError, Result};
 use matrix_sdk_common::timeout::timeout;
 pub use memorystore::MemoryStore;
+pub use migration::{migrate_crypto_store, CryptoStoreMigrationReport, CryptoStoreMigrationStep};
+pub use prune::{PruneReport, PruneSettings, RoomKeyRetention};
 pub use traits::{CryptoStore, DynCryptoStore, IntoCryptoStore};
 
-use self::locks::CryptoStoreLock;
+use self::locks::{CryptoStoreLock, LockableCryptoStore};
 pub use crate::gossiping::{GossipRequest, SecretInfo};
 
+/// The key of the custom value holding the global flag to only share room
+/// keys with trusted devices.
+const ONLY_ALLOW_TRUSTED_DEVICES_KEY: &str = "only_allow_trusted_devices";
+
+/// The key of the custom value holding the key forwarding policy.
+pub(crate) const KEY_FORWARDING_POLICY_KEY: &str = "key_forwarding_policy";
+
+/// The keys of the custom values holding the settings of the store, they are
+/// copied by [`migrate_crypto_store`].
+///
+/// Any new setting stored as a custom value needs to be added here.
+pub(crate) const SETTING_KEYS: &[&str] =
+    &[ONLY_ALLOW_TRUSTED_DEVICES_KEY, KEY_FORWARDING_POLICY_KEY];
+
 /// A wrapper for our CryptoStore trait object.
 ///
 /// This is needed because we want to have a generic interface so we can
@@ -137,6 +157,14 @@ struct StoreInner {
     /// The sender side of a broadcast channel which sends out secrets we
     /// received as a `m.secret.send` event.
     secrets_broadcaster: broadcast::Sender<GossippedSecret>,
+
+    /// The sender side of a broadcast stream that is notified whenever the
+    /// identity of another user gets stored.
+    identity_violations_sender: broadcast::Sender<Vec<IdentityViolationUpdate>>,
+
+    /// The sender side of a broadcast stream that is notified whenever user
+    /// identities or devices get stored.
+    identities_broadcaster: broadcast::Sender<(IdentityChanges, DeviceChanges)>,
 }
 
 /// Aggregated changes to be saved in the database.
@@ -216,6 +244,29 @@ pub struct DeviceChanges {
     pub deleted: Vec<ReadOnlyDevice>,
 }
 
+/// Updates about the user identities that were stored, as sent by the
+/// [`Store::user_identities_stream()`].
+#[derive(Clone, Debug, Default)]
+pub struct IdentityUpdates {
+    /// Identities of users we didn't know about before.
+    pub new: BTreeMap<OwnedUserId, UserIdentities>,
+    /// Identities that changed, e.g. because the user changed their
+    /// cross-signing keys.
+    pub changed: BTreeMap<OwnedUserId, UserIdentities>,
+}
+
+/// Updates about the devices that were stored, as sent by the
+/// [`Store::devices_stream()`].
+#[derive(Clone, Debug, Default)]
+pub struct DeviceUpdates {
+    /// Devices we didn't know about before.
+    pub new: BTreeMap<OwnedUserId, BTreeMap<OwnedDeviceId, Device>>,
+    /// Devices whose keys, display name or trust state changed.
+    pub changed: BTreeMap<OwnedUserId, BTreeMap<OwnedDeviceId, Device>>,
+    /// The IDs of the devices that were deleted.
+    pub deleted: BTreeMap<OwnedUserId, BTreeSet<OwnedDeviceId>>,
+}
+
 /// The private part of a backup key.
 ///
 /// The private part of the key is not used on a regular basis. Rather, it is
@@ -352,6 +403,13 @@ pub struct RoomSettings {
     /// Should untrusted devices receive the room key, or should they be
     /// excluded from the conversation.
     pub only_allow_trusted_devices: bool,
+    /// The strategy deciding which devices should receive the room key.
+    #[serde(default)]
+    pub sharing_strategy: CollectStrategy,
+    /// Should sharing the room key fail if any of the room members has an
+    /// unacknowledged identity violation, or should only a warning be logged.
+    #[serde(default)]
+    pub error_on_identity_violation: bool,
 }
 
 impl Default for RoomSettings {
@@ -359,6 +417,8 @@ impl Default for RoomSettings {
         Self {
             algorithm: EventEncryptionAlgorithm::MegolmV1AesSha2,
             only_allow_trusted_devices: false,
+            sharing_strategy: CollectStrategy::default(),
+            error_on_identity_violation: false,
         }
     }
 }
@@ -393,6 +453,18 @@ impl From<&InboundGroupSession> for RoomKeyInfo {
     }
 }
 
+/// Information about the identity violation state of a user, sent out
+/// whenever the identity of the user gets updated.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IdentityViolationUpdate {
+    /// The user whose identity was updated.
+    pub user_id: OwnedUserId,
+
+    /// The identity violation the identity of the user is in, `None` if the
+    /// identity doesn't violate anything.
+    pub violation: Option<IdentityViolation>,
+}
+
 impl Store {
     /// Create a new Store
     pub(crate) fn new(
@@ -403,6 +475,10 @@ impl Store {
     ) -> Self {
         let room_keys_received_sender = broadcast::Sender::new(10);
         let secrets_broadcaster = broadcast::Sender::new(10);
+        let identity_violations_sender = broadcast::Sender::new(10);
+        // Share the broadcaster with the verification machine, it stores trust changes
+        // by itself.
+        let identities_broadcaster = verification_machine.store.identities_broadcaster.clone();
 
         let inner = Arc::new(StoreInner {
             user_id,
@@ -416,6 +492,8 @@ impl Store {
             tracked_user_loading_lock: Mutex::new(()),
             room_keys_received_sender,
             secrets_broadcaster,
+            identity_violations_sender,
+            identities_broadcaster,
         });
 
         Self { inner }
@@ -459,6 +537,10 @@ impl Store {
             changes.inbound_group_sessions.iter().map(RoomKeyInfo::from).collect();
 
         let secrets = changes.secrets.to_owned();
+        let identity_violation_updates =
+            self.identity_violation_updates(&changes.identities).await?;
+        let identity_updates = (!changes.identities.is_empty() || !changes.devices.is_empty())
+            .then(|| (changes.identities.clone(), changes.devices.clone()));
 
         self.inner.store.save_changes(changes).await?;
 
@@ -467,6 +549,14 @@ impl Store {
             let _ = self.inner.room_keys_received_sender.send(room_key_updates);
         }
 
+        if !identity_violation_updates.is_empty() {
+            let _ = self.inner.identity_violations_sender.send(identity_violation_updates);
+        }
+
+        if let Some(identity_updates) = identity_updates {
+            let _ = self.inner.identities_broadcaster.send(identity_updates);
+        }
+
         for secret in secrets {
             let _ = self.inner.secrets_broadcaster.send(secret);
         }
@@ -474,6 +564,47 @@ impl Store {
         Ok(())
     }
 
+    /// Get our own identity, preferring the one that is part of the given
+    /// changes, since it might have changed as well.
+    async fn own_identity_with_changes(
+        &self,
+        changes: &IdentityChanges,
+    ) -> Result<Option<ReadOnlyOwnUserIdentity>> {
+        Ok(match changes.new.iter().chain(&changes.changed).find_map(|i| i.own()) {
+            Some(i) => Some(i.clone()),
+            None => self
+                .inner
+                .store
+                .get_user_identity(self.user_id())
+                .await?
+                .and_then(|i| i.own().cloned()),
+        })
+    }
+
+    /// Compute the identity violation state of the identities of other users
+    /// that are about to be saved.
+    async fn identity_violation_updates(
+        &self,
+        changes: &IdentityChanges,
+    ) -> Result<Vec<IdentityViolationUpdate>> {
+        let identities: Vec<_> =
+            changes.new.iter().chain(&changes.changed).filter_map(|i| i.other()).collect();
+
+        if identities.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let own_identity = self.own_identity_with_changes(changes).await?;
+
+        Ok(identities
+            .into_iter()
+            .map(|i| IdentityViolationUpdate {
+                user_id: i.user_id().to_owned(),
+                violation: i.violation(own_identity.as_ref()),
+            })
+            .collect())
+    }
+
     /// Compare the given `InboundGroupSession` with an existing session we have
     /// in the store.
     ///
@@ -636,34 +767,122 @@ impl Store {
         // let own_identity =
         // self.inner.get_user_identity(self.user_id()).await?.and_then(|i| i.own());
         Ok(if let Some(identity) = self.inner.store.get_user_identity(user_id).await? {
-            Some(match identity {
-                ReadOnlyUserIdentities::Own(i) => OwnUserIdentity {
-                    inner: i,
-                    verification_machine: self.inner.verification_machine.clone(),
+            let own_identity = match identity {
+                ReadOnlyUserIdentities::Own(_) => None,
+                ReadOnlyUserIdentities::Other(_) => {
+                    self.inner.store.get_user_identity(self.user_id()).await?.and_then(|i| {
+                        if let ReadOnlyUserIdentities::Own(i) = i {
+                            Some(i)
+                        } else {
+                            None
+                        }
+                    })
                 }
-                .into(),
-                ReadOnlyUserIdentities::Other(i) => {
-                    let own_identity =
-                        self.inner.store.get_user_identity(self.user_id()).await?.and_then(|i| {
-                            if let ReadOnlyUserIdentities::Own(i) = i {
-                                Some(i)
-                            } else {
-                                None
-                            }
-                        });
-                    UserIdentity {
-                        inner: i,
-                        verification_machine: self.inner.verification_machine.clone(),
-                        own_identity,
-                    }
-                    .into()
-                }
-            })
+            };
+
+            Some(self.to_user_identities(identity, own_identity))
         } else {
             None
         })
     }
 
+    /// Wrap the given read-only identity into a [`UserIdentities`] object.
+    fn to_user_identities(
+        &self,
+        identity: ReadOnlyUserIdentities,
+        own_identity: Option<ReadOnlyOwnUserIdentity>,
+    ) -> UserIdentities {
+        match identity {
+            ReadOnlyUserIdentities::Own(i) => OwnUserIdentity {
+                inner: i,
+                verification_machine: self.inner.verification_machine.clone(),
+            }
+            .into(),
+            ReadOnlyUserIdentities::Other(i) => UserIdentity {
+                inner: i,
+                verification_machine: self.inner.verification_machine.clone(),
+                own_identity,
+                store: self.clone(),
+            }
+            .into(),
+        }
+    }
+
+    /// Convert stored identity changes into [`IdentityUpdates`].
+    async fn identity_updates(&self, changes: IdentityChanges) -> Result<IdentityUpdates> {
+        let own_identity = self.own_identity_with_changes(&changes).await?;
+
+        let convert = |identities: Vec<ReadOnlyUserIdentities>| -> BTreeMap<_, _> {
+            identities
+                .into_iter()
+                .map(|i| (i.user_id().to_owned(), self.to_user_identities(i, own_identity.clone())))
+                .collect()
+        };
+
+        let new = convert(changes.new);
+        let changed = convert(changes.changed);
+
+        Ok(IdentityUpdates { new, changed })
+    }
+
+    /// Convert stored device changes into [`DeviceUpdates`].
+    async fn device_updates(
+        &self,
+        identities: IdentityChanges,
+        devices: DeviceChanges,
+    ) -> Result<DeviceUpdates> {
+        let own_identity = self.own_identity_with_changes(&identities).await?;
+
+        // Prefer the identities that were stored alongside the devices.
+        let mut owner_identities: BTreeMap<OwnedUserId, Option<ReadOnlyUserIdentities>> =
+            identities
+                .new
+                .into_iter()
+                .chain(identities.changed)
+                .map(|i| (i.user_id().to_owned(), Some(i)))
+                .collect();
+
+        let mut updates = DeviceUpdates::default();
+
+        for (device, is_new) in devices
+            .new
+            .into_iter()
+            .map(|d| (d, true))
+            .chain(devices.changed.into_iter().map(|d| (d, false)))
+        {
+            let user_id = device.user_id().to_owned();
+
+            let device_owner_identity = match owner_identities.get(&user_id) {
+                Some(identity) => identity.clone(),
+                None => {
+                    let identity = self.inner.store.get_user_identity(&user_id).await?;
+                    owner_identities.insert(user_id.clone(), identity.clone());
+                    identity
+                }
+            };
+
+            let device = Device {
+                inner: device,
+                verification_machine: self.inner.verification_machine.clone(),
+                own_identity: own_identity.clone(),
+                device_owner_identity,
+            };
+
+            let map = if is_new { &mut updates.new } else { &mut updates.changed };
+            map.entry(user_id).or_default().insert(device.device_id().to_owned(), device);
+        }
+
+        for device in devices.deleted {
+            updates
+                .deleted
+                .entry(device.user_id().to_owned())
+                .or_default()
+                .insert(device.device_id().to_owned());
+        }
+
+        Ok(updates)
+    }
+
     /// Try to export the secret with the given secret name.
     ///
     /// The exported secret will be encoded as unpadded base64. Returns `Null`
@@ -969,7 +1188,7 @@ impl Store {
     /// Check whether there is a global flag to only encrypt messages for
     /// trusted devices or for everyone.
     pub async fn get_only_allow_trusted_devices(&self) -> Result<bool> {
-        let value = self.get_value("only_allow_trusted_devices").await?.unwrap_or_default();
+        let value = self.get_value(ONLY_ALLOW_TRUSTED_DEVICES_KEY).await?.unwrap_or_default();
         Ok(value)
     }
 
@@ -979,7 +1198,56 @@ impl Store {
         &self,
         block_untrusted_devices: bool,
     ) -> Result<()> {
-        self.set_value("only_allow_trusted_devices", &block_untrusted_devices).await
+        self.set_value(ONLY_ALLOW_TRUSTED_DEVICES_KEY, &block_untrusted_devices).await
+    }
+
+    /// Check whether sharing the room keys of the given room should fail if
+    /// some of its members have unacknowledged identity violations.
+    pub async fn get_error_on_identity_violation(&self, room_id: &RoomId) -> Result<bool> {
+        let settings = self.get_room_settings(room_id).await?;
+        Ok(settings.map(|s| s.error_on_identity_violation).unwrap_or_default())
+    }
+
+    /// Set whether sharing the room keys of the given room should fail if
+    /// some of its members have unacknowledged identity violations, or
+    /// whether only a warning should be logged.
+    pub async fn set_error_on_identity_violation(
+        &self,
+        room_id: &RoomId,
+        error: bool,
+    ) -> Result<()> {
+        let mut settings = self.get_room_settings(room_id).await?.unwrap_or_default();
+        settings.error_on_identity_violation = error;
+
+        self.save_changes(Changes {
+            room_settings: HashMap::from([(room_id.to_owned(), settings)]),
+            ..Default::default()
+        })
+        .await
+    }
+
+    /// Get the strategy deciding which devices should receive the room keys
+    /// of the given room.
+    pub async fn get_room_key_sharing_strategy(&self, room_id: &RoomId) -> Result<CollectStrategy> {
+        let settings = self.get_room_settings(room_id).await?;
+        Ok(settings.map(|s| s.sharing_strategy).unwrap_or_default())
+    }
+
+    /// Set the strategy deciding which devices should receive the room keys
+    /// of the given room.
+    pub async fn set_room_key_sharing_strategy(
+        &self,
+        room_id: &RoomId,
+        strategy: CollectStrategy,
+    ) -> Result<()> {
+        let mut settings = self.get_room_settings(room_id).await?.unwrap_or_default();
+        settings.sharing_strategy = strategy;
+
+        self.save_changes(Changes {
+            room_settings: HashMap::from([(room_id.to_owned(), settings)]),
+            ..Default::default()
+        })
+        .await
     }
 
     /// Get custom stored value associated with a key
@@ -1035,10 +1303,112 @@ impl Store {
         })
     }
 
+    /// Receive notifications of updates to the identities of other users as a
+    /// [`Stream`], along with the identity violation they are in.
+    ///
+    /// Each time the identity of another user is stored, e.g. after a
+    /// `/keys/query` response or after an identity violation was acknowledged,
+    /// an update will be sent to the stream. Updates that happen at the same
+    /// time are batched into a [`Vec`].
+    ///
+    /// If the reader of the stream lags too far behind, a warning will be
+    /// logged and items will be dropped.
+    pub fn identity_violations_stream(&self) -> impl Stream<Item = Vec<IdentityViolationUpdate>> {
+        let stream = BroadcastStream::new(self.inner.identity_violations_sender.subscribe());
+
+        // the raw BroadcastStream gives us Results which can fail with
+        // BroadcastStreamRecvError if the reader falls behind. That's annoying to work
+        // with, so here we just drop the errors.
+        stream.filter_map(|result| async move {
+            match result {
+                Ok(r) => Some(r),
+                Err(BroadcastStreamRecvError::Lagged(lag)) => {
+                    warn!("identity_violations_stream missed {lag} updates");
+                    None
+                }
+            }
+        })
+    }
+
+    /// Receive the raw identity and device changes that get stored.
+    fn identities_stream(&self) -> impl Stream<Item = (IdentityChanges, DeviceChanges)> {
+        let stream = BroadcastStream::new(self.inner.identities_broadcaster.subscribe());
+
+        stream.filter_map(|result| async move {
+            match result {
+                Ok(r) => Some(r),
+                Err(BroadcastStreamRecvError::Lagged(lag)) => {
+                    warn!("identities_stream missed {lag} updates");
+                    None
+                }
+            }
+        })
+    }
+
+    /// Receive notifications of new and changed user identities as a
+    /// [`Stream`].
+    ///
+    /// Each time user identities get stored, e.g. after a `/keys/query`
+    /// response, an [`IdentityUpdates`] will be sent to the stream.
+    ///
+    /// If the reader of the stream lags too far behind, a warning will be
+    /// logged and items will be dropped.
+    pub fn user_identities_stream(&self) -> impl Stream<Item = IdentityUpdates> {
+        let store = self.clone();
+
+        self.identities_stream().filter_map(move |(identities, _)| {
+            let store = store.clone();
+
+            async move {
+                if identities.is_empty() {
+                    return None;
+                }
+
+                match store.identity_updates(identities).await {
+                    Ok(updates) => Some(updates),
+                    Err(e) => {
+                        warn!("Couldn't convert user identity updates: {e:?}");
+                        None
+                    }
+                }
+            }
+        })
+    }
+
+    /// Receive notifications of new, changed and deleted devices as a
+    /// [`Stream`].
+    ///
+    /// Each time devices get stored, e.g. after a `/keys/query` response, a
+    /// [`DeviceUpdates`] will be sent to the stream.
+    ///
+    /// If the reader of the stream lags too far behind, a warning will be
+    /// logged and items will be dropped.
+    pub fn devices_stream(&self) -> impl Stream<Item = DeviceUpdates> {
+        let store = self.clone();
+
+        self.identities_stream().filter_map(move |(identities, devices)| {
+            let store = store.clone();
+
+            async move {
+                if devices.is_empty() {
+                    return None;
+                }
+
+                match store.device_updates(identities, devices).await {
+                    Ok(updates) => Some(updates),
+                    Err(e) => {
+                        warn!("Couldn't convert device updates: {e:?}");
+                        None
+                    }
+                }
+            }
+        })
+    }
+
     /// Creates a `CryptoStoreLock` for this store, that will contain the given
     /// key and value when hold.
     pub fn create_store_lock(&self, lock_key: String, lock_value: String) -> CryptoStoreLock {
-        CryptoStoreLock::new(self.inner.store.clone(), lock_key, lock_value)
+        CryptoStoreLock::new(LockableCryptoStore(self.inner.store.clone()), lock_key, lock_value)
     }
 
     /// Receive notifications of gossipped secrets being received and stored in
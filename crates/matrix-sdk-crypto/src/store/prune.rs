@@ -0,0 +1,254 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Removal of the data of the crypto store that isn't needed anymore.
+
+use std::{
+    collections::{hash_map::Entry, BTreeMap, BTreeSet, HashMap, HashSet},
+    time::Duration,
+};
+
+use ruma::{OwnedRoomId, OwnedUserId, SecondsSinceUnixEpoch, UInt};
+use tracing::{debug, info};
+
+use super::{Result, Store};
+use crate::gossiping::SecretInfo;
+
+/// The number of room keys loaded at once when looking for the room keys to
+/// remove.
+const ROOM_KEY_BATCH_SIZE: usize = 1000;
+
+/// Which room keys of the pruned rooms may be removed from the store.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RoomKeyRetention {
+    /// Keep all the room keys.
+    #[default]
+    KeepAll,
+    /// Only remove the room keys that were uploaded to the server-side key
+    /// backup, so they can still be recovered later.
+    RemoveBackedUp,
+    /// Remove the room keys, whether they were backed up or not.
+    RemoveAll,
+}
+
+/// Settings describing which data should be removed from the crypto store
+/// when it is pruned.
+///
+/// Nothing is removed by default.
+#[derive(Clone, Debug, Default)]
+pub struct PruneSettings {
+    /// Remove the Olm sessions that were established with devices that were
+    /// deleted by their owner.
+    ///
+    /// Only the devices of tracked users whose device list is up to date are
+    /// considered, a device is deleted if it isn't part of that list anymore.
+    pub remove_orphaned_olm_sessions: bool,
+    /// The rooms whose room keys may be removed, usually the rooms the user
+    /// left and forgot.
+    pub rooms: BTreeSet<OwnedRoomId>,
+    /// Which of the room keys of [`PruneSettings::rooms`] should be removed.
+    pub room_key_retention: RoomKeyRetention,
+    /// Remove the room key requests we sent out and that can't be useful
+    /// anymore, because we already have the room key or because it belongs to
+    /// one of the [`PruneSettings::rooms`].
+    pub remove_stale_key_requests: bool,
+    /// Remove the hashes of the Olm messages we decrypted longer than the
+    /// given duration ago.
+    ///
+    /// Those hashes are used to detect replayed Olm messages, removing them
+    /// makes it possible to replay an old message, as long as the one-time key
+    /// it used is still available. The hashes stored by older versions, whose
+    /// age isn't known, are always removed.
+    pub olm_message_hashes_max_age: Option<Duration>,
+}
+
+/// Report of the data that was removed when pruning the crypto store.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PruneReport {
+    /// The number of Olm sessions that were removed.
+    pub olm_sessions: usize,
+    /// The number of room keys that were removed.
+    pub inbound_group_sessions: usize,
+    /// The number of outgoing room key requests that were removed.
+    pub key_requests: usize,
+    /// The number of Olm message hashes that were removed.
+    pub olm_message_hashes: usize,
+}
+
+impl Store {
+    /// Remove the data that isn't needed anymore from the store, following
+    /// the given settings.
+    pub(crate) async fn prune(&self, settings: &PruneSettings) -> Result<PruneReport> {
+        let mut report = PruneReport::default();
+
+        if settings.remove_orphaned_olm_sessions {
+            report.olm_sessions = self.prune_olm_sessions().await?;
+        }
+
+        if settings.room_key_retention != RoomKeyRetention::KeepAll && !settings.rooms.is_empty() {
+            report.inbound_group_sessions =
+                self.prune_room_keys(&settings.rooms, settings.room_key_retention).await?;
+        }
+
+        if settings.remove_stale_key_requests {
+            report.key_requests = self.prune_key_requests(&settings.rooms).await?;
+        }
+
+        if let Some(max_age) = settings.olm_message_hashes_max_age {
+            let now = SecondsSinceUnixEpoch::now().get();
+            let max_age = UInt::new_saturating(max_age.as_secs());
+            let older_than = SecondsSinceUnixEpoch(now.saturating_sub(max_age));
+
+            report.olm_message_hashes = self.clear_message_hashes(older_than).await?;
+        }
+
+        info!(?report, "Pruned the crypto store");
+
+        Ok(report)
+    }
+
+    /// Remove the Olm sessions that were established with devices that are
+    /// known to be deleted.
+    ///
+    /// A device is known to be deleted if its owner is tracked, the device
+    /// list of the owner is up to date and the device isn't part of it
+    /// anymore. The sessions of users we don't track, or whose device list
+    /// is outdated, are kept since we can't tell if the device still exists.
+    async fn prune_olm_sessions(&self) -> Result<usize> {
+        let mut tracked_users = self.tracked_users().await?;
+        tracked_users.insert(self.user_id().to_owned());
+        let (outdated_users, _) = self.users_for_key_query().await?;
+
+        let mut known_keys: HashMap<OwnedUserId, HashSet<String>> = HashMap::new();
+        let mut removed = 0;
+
+        for sender_key in self.get_session_sender_keys().await? {
+            let Some(sessions) = self.get_sessions(&sender_key).await? else {
+                continue;
+            };
+            let sessions = sessions.lock().await;
+
+            let Some(user_id) = sessions.first().map(|s| s.user_id.clone()) else {
+                continue;
+            };
+
+            if !tracked_users.contains(&user_id) || outdated_users.contains(&user_id) {
+                continue;
+            }
+
+            let keys = match known_keys.entry(user_id.clone()) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => entry.insert(
+                    self.get_readonly_devices_unfiltered(&user_id)
+                        .await?
+                        .values()
+                        .filter_map(|d| d.curve25519_key().map(|k| k.to_base64()))
+                        .collect(),
+                ),
+            };
+
+            if keys.contains(&sender_key) {
+                continue;
+            }
+
+            debug!(%user_id, sender_key, "Removing the Olm sessions of a deleted device");
+            removed += sessions.len();
+            drop(sessions);
+            self.delete_sessions(&sender_key).await?;
+        }
+
+        Ok(removed)
+    }
+
+    /// Remove the room keys of the given rooms, following the given retention
+    /// rule.
+    async fn prune_room_keys(
+        &self,
+        rooms: &BTreeSet<OwnedRoomId>,
+        retention: RoomKeyRetention,
+    ) -> Result<usize> {
+        let mut sessions_to_remove: BTreeMap<OwnedRoomId, Vec<String>> = BTreeMap::new();
+        let mut last: Option<(OwnedRoomId, String)> = None;
+
+        // Only the IDs of the sessions to remove are kept in memory, the
+        // sessions themselves are loaded one batch at a time. They are removed
+        // once all the batches were seen, so the pagination isn't disturbed.
+        loop {
+            let after =
+                last.as_ref().map(|(room_id, session_id)| (&**room_id, session_id.as_str()));
+            let batch = self.get_inbound_group_sessions_batch(after, ROOM_KEY_BATCH_SIZE).await?;
+
+            let Some(last_session) = batch.last() else {
+                break;
+            };
+            last = Some((last_session.room_id().to_owned(), last_session.session_id().to_owned()));
+
+            for session in batch {
+                let remove = rooms.contains(session.room_id())
+                    && match retention {
+                        RoomKeyRetention::KeepAll => false,
+                        RoomKeyRetention::RemoveBackedUp => session.backed_up(),
+                        RoomKeyRetention::RemoveAll => true,
+                    };
+
+                if remove {
+                    sessions_to_remove
+                        .entry(session.room_id().to_owned())
+                        .or_default()
+                        .push(session.session_id().to_owned());
+                }
+            }
+        }
+
+        let mut removed = 0;
+
+        for (room_id, session_ids) in sessions_to_remove {
+            debug!(%room_id, count = session_ids.len(), "Removing room keys");
+            self.delete_inbound_group_sessions(&room_id, &session_ids).await?;
+            removed += session_ids.len();
+        }
+
+        Ok(removed)
+    }
+
+    /// Remove the room key requests that were sent out and aren't useful
+    /// anymore.
+    async fn prune_key_requests(&self, rooms: &BTreeSet<OwnedRoomId>) -> Result<usize> {
+        let mut removed = 0;
+
+        for request in self.get_all_secret_requests().await? {
+            if !request.sent_out {
+                continue;
+            }
+
+            let stale = match &request.info {
+                SecretInfo::KeyRequest(info) => {
+                    rooms.contains(info.room_id())
+                        || self
+                            .get_inbound_group_session(info.room_id(), info.session_id())
+                            .await?
+                            .is_some()
+                }
+                SecretInfo::SecretRequest(_) => false,
+            };
+
+            if stale {
+                self.delete_outgoing_secret_requests(&request.request_id).await?;
+                removed += 1;
+            }
+        }
+
+        Ok(removed)
+    }
+}
@@ -17,7 +17,8 @@ use std::{collections::HashMap, fmt, sync::Arc};
 use async_trait::async_trait;
 use matrix_sdk_common::AsyncTraitDeps;
 use ruma::{
-    events::secret::request::SecretName, DeviceId, OwnedDeviceId, RoomId, TransactionId, UserId,
+    events::secret::request::SecretName, DeviceId, OwnedDeviceId, RoomId, SecondsSinceUnixEpoch,
+    TransactionId, UserId,
 };
 use tokio::sync::Mutex;
 
@@ -70,6 +71,16 @@ pub trait CryptoStore: AsyncTraitDeps {
         sender_key: &str,
     ) -> Result<Option<Arc<Mutex<Vec<Session>>>>, Self::Error>;
 
+    /// Get the sender keys of all the Olm sessions we have stored.
+    async fn get_session_sender_keys(&self) -> Result<Vec<String>, Self::Error>;
+
+    /// Delete all the sessions that belong to the given sender key.
+    ///
+    /// # Arguments
+    ///
+    /// * `sender_key` - The sender key that was used to establish the sessions.
+    async fn delete_sessions(&self, sender_key: &str) -> Result<(), Self::Error>;
+
     /// Get the inbound group session from our store.
     ///
     /// # Arguments
@@ -98,6 +109,37 @@ pub trait CryptoStore: AsyncTraitDeps {
     /// Get all the inbound group sessions we have stored.
     async fn get_inbound_group_sessions(&self) -> Result<Vec<InboundGroupSession>, Self::Error>;
 
+    /// Get a batch of the inbound group sessions we have stored, to go
+    /// through all of them without loading them in memory at once.
+    ///
+    /// The sessions are returned in an order that is specific to the store
+    /// but doesn't change between calls.
+    ///
+    /// # Arguments
+    ///
+    /// * `after` - The room ID and the session ID of the last session of the
+    ///   previous batch, or `None` to get the first batch.
+    ///
+    /// * `limit` - The maximum number of sessions to return.
+    async fn get_inbound_group_sessions_batch(
+        &self,
+        after: Option<(&RoomId, &str)>,
+        limit: usize,
+    ) -> Result<Vec<InboundGroupSession>, Self::Error>;
+
+    /// Delete the given inbound group sessions from the store.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - The room id of the room that the sessions belong to.
+    ///
+    /// * `session_ids` - The unique ids of the sessions.
+    async fn delete_inbound_group_sessions(
+        &self,
+        room_id: &RoomId,
+        session_ids: &[String],
+    ) -> Result<(), Self::Error>;
+
     /// Get the number inbound group sessions we have and how many of them are
     /// backed up.
     async fn inbound_group_session_counts(&self) -> Result<RoomKeyCounts, Self::Error>;
@@ -164,6 +206,15 @@ pub trait CryptoStore: AsyncTraitDeps {
     /// Check if a hash for an Olm message stored in the database.
     async fn is_message_known(&self, message_hash: &OlmMessageHash) -> Result<bool, Self::Error>;
 
+    /// Delete the stored hashes of Olm messages that were stored before the
+    /// given time.
+    ///
+    /// Returns the number of hashes that were deleted.
+    async fn clear_message_hashes(
+        &self,
+        older_than: SecondsSinceUnixEpoch,
+    ) -> Result<usize, Self::Error>;
+
     /// Get an outgoing secret request that we created that matches the given
     /// request id.
     ///
@@ -190,6 +241,10 @@ pub trait CryptoStore: AsyncTraitDeps {
     /// Get all outgoing secret requests that we have in the store.
     async fn get_unsent_secret_requests(&self) -> Result<Vec<GossipRequest>, Self::Error>;
 
+    /// Get all the outgoing secret requests we have in the store, whether they
+    /// were sent out or not.
+    async fn get_all_secret_requests(&self) -> Result<Vec<GossipRequest>, Self::Error>;
+
     /// Delete an outgoing key request that we created that matches the given
     /// request id.
     ///
@@ -296,6 +351,14 @@ impl<T: CryptoStore> CryptoStore for EraseCryptoStoreError<T> {
         self.0.get_sessions(sender_key).await.map_err(Into::into)
     }
 
+    async fn get_session_sender_keys(&self) -> Result<Vec<String>> {
+        self.0.get_session_sender_keys().await.map_err(Into::into)
+    }
+
+    async fn delete_sessions(&self, sender_key: &str) -> Result<()> {
+        self.0.delete_sessions(sender_key).await.map_err(Into::into)
+    }
+
     async fn get_inbound_group_session(
         &self,
         room_id: &RoomId,
@@ -308,6 +371,22 @@ impl<T: CryptoStore> CryptoStore for EraseCryptoStoreError<T> {
         self.0.get_inbound_group_sessions().await.map_err(Into::into)
     }
 
+    async fn get_inbound_group_sessions_batch(
+        &self,
+        after: Option<(&RoomId, &str)>,
+        limit: usize,
+    ) -> Result<Vec<InboundGroupSession>> {
+        self.0.get_inbound_group_sessions_batch(after, limit).await.map_err(Into::into)
+    }
+
+    async fn delete_inbound_group_sessions(
+        &self,
+        room_id: &RoomId,
+        session_ids: &[String],
+    ) -> Result<()> {
+        self.0.delete_inbound_group_sessions(room_id, session_ids).await.map_err(Into::into)
+    }
+
     async fn inbound_group_session_counts(&self) -> Result<RoomKeyCounts> {
         self.0.inbound_group_session_counts().await.map_err(Into::into)
     }
@@ -365,6 +444,10 @@ impl<T: CryptoStore> CryptoStore for EraseCryptoStoreError<T> {
         self.0.is_message_known(message_hash).await.map_err(Into::into)
     }
 
+    async fn clear_message_hashes(&self, older_than: SecondsSinceUnixEpoch) -> Result<usize> {
+        self.0.clear_message_hashes(older_than).await.map_err(Into::into)
+    }
+
     async fn get_outgoing_secret_requests(
         &self,
         request_id: &TransactionId,
@@ -383,6 +466,10 @@ impl<T: CryptoStore> CryptoStore for EraseCryptoStoreError<T> {
         self.0.get_unsent_secret_requests().await.map_err(Into::into)
     }
 
+    async fn get_all_secret_requests(&self) -> Result<Vec<GossipRequest>> {
+        self.0.get_all_secret_requests().await.map_err(Into::into)
+    }
+
     async fn delete_outgoing_secret_requests(&self, request_id: &TransactionId) -> Result<()> {
         self.0.delete_outgoing_secret_requests(request_id).await.map_err(Into::into)
     }
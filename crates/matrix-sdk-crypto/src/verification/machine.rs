@@ -19,6 +19,8 @@ use std::{
 };
 
 use dashmap::DashMap;
+use futures_core::Stream;
+use futures_util::stream::StreamExt;
 use ruma::{
     events::{
         key::verification::VerificationMethod, AnyToDeviceEvent, AnyToDeviceEventContent,
@@ -28,7 +30,8 @@ use ruma::{
     uint, DeviceId, EventId, MilliSecondsSinceUnixEpoch, OwnedDeviceId, OwnedUserId, RoomId,
     SecondsSinceUnixEpoch, TransactionId, UInt, UserId,
 };
-use tokio::sync::Mutex;
+use tokio::sync::{broadcast, Mutex};
+use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream};
 use tracing::{debug, info, instrument, trace, warn};
 
 use super::{
@@ -51,6 +54,9 @@ pub struct VerificationMachine {
     pub(crate) store: VerificationStore,
     verifications: VerificationCache,
     requests: Arc<DashMap<OwnedUserId, HashMap<String, VerificationRequest>>>,
+    /// The sender side of a broadcast stream that is notified whenever we
+    /// receive a new verification request.
+    requests_sender: broadcast::Sender<VerificationRequest>,
 }
 
 impl VerificationMachine {
@@ -60,9 +66,15 @@ impl VerificationMachine {
         store: Arc<DynCryptoStore>,
     ) -> Self {
         Self {
-            store: VerificationStore { account, private_identity: identity, inner: store },
+            store: VerificationStore {
+                account,
+                private_identity: identity,
+                inner: store,
+                identities_broadcaster: broadcast::Sender::new(10),
+            },
             verifications: VerificationCache::new(),
             requests: Default::default(),
+            requests_sender: broadcast::Sender::new(10),
         }
     }
 
@@ -164,13 +176,34 @@ impl VerificationMachine {
             .unwrap_or_default()
     }
 
+    /// Receive the verification requests other users or devices send to us as
+    /// a [`Stream`].
+    ///
+    /// If the reader of the stream lags too far behind, a warning will be
+    /// logged and items will be dropped.
+    pub fn verification_requests_stream(&self) -> impl Stream<Item = VerificationRequest> {
+        let stream = BroadcastStream::new(self.requests_sender.subscribe());
+
+        stream.filter_map(|result| async move {
+            match result {
+                Ok(r) => Some(r),
+                Err(BroadcastStreamRecvError::Lagged(lag)) => {
+                    warn!("verification_requests_stream missed {lag} updates");
+                    None
+                }
+            }
+        })
+    }
+
     /// Add a new `VerificationRequest` object to the cache.
     /// If there are any existing requests with this user (and different
     /// flow_id), both the existing and new request will be cancelled.
-    fn insert_request(&self, request: VerificationRequest) {
+    ///
+    /// Returns `false` if the request was already known.
+    fn insert_request(&self, request: VerificationRequest) -> bool {
         if let Some(r) = self.get_request(request.other_user(), request.flow_id().as_str()) {
             debug!(flow_id = r.flow_id().as_str(), "Ignoring known verification request",);
-            return;
+            return false;
         }
 
         let mut entry = self.requests.entry(request.other_user().to_owned()).or_default();
@@ -200,6 +233,8 @@ impl VerificationMachine {
         // want to inspect the verification object a matching
         // `m.key.verification.request` produced.
         user_requests.insert(request.flow_id().as_str().to_owned(), request);
+
+        true
     }
 
     pub fn get_verification(&self, user_id: &UserId, flow_id: &str) -> Option<Verification> {
@@ -381,7 +416,10 @@ impl VerificationMachine {
                     r,
                 );
 
-                self.insert_request(request);
+                if self.insert_request(request.clone()) {
+                    // Ignore the result. It can only fail if there are no listeners.
+                    let _ = self.requests_sender.send(request);
+                }
             }
             AnyVerificationContent::Cancel(c) => {
                 if let Some(verification) = self.get_request(event.sender(), flow_id.as_str()) {
@@ -45,14 +45,14 @@ use ruma::{
     UserId,
 };
 pub use sas::{AcceptSettings, AcceptedProtocols, EmojiShortAuthString, Sas, SasState};
-use tokio::sync::Mutex;
+use tokio::sync::{broadcast, Mutex};
 use tracing::{error, info, trace, warn};
 
 use crate::{
     error::SignatureError,
     gossiping::{GossipMachine, GossipRequest},
     olm::{PrivateCrossSigningIdentity, ReadOnlyAccount, Session},
-    store::{Changes, DynCryptoStore},
+    store::{Changes, DeviceChanges, DynCryptoStore, IdentityChanges},
     types::Signatures,
     CryptoStoreError, LocalTrust, OutgoingVerificationRequest, ReadOnlyDevice,
     ReadOnlyOwnUserIdentity, ReadOnlyUserIdentities,
@@ -63,6 +63,12 @@ pub(crate) struct VerificationStore {
     pub account: ReadOnlyAccount,
     pub private_identity: Arc<Mutex<PrivateCrossSigningIdentity>>,
     inner: Arc<DynCryptoStore>,
+    /// The sender side of the broadcast stream that is notified whenever user
+    /// identities or devices get stored, shared with the [`Store`] so trust
+    /// changes made during verifications are sent to its streams as well.
+    ///
+    /// [`Store`]: crate::store::Store
+    pub identities_broadcaster: broadcast::Sender<(IdentityChanges, DeviceChanges)>,
 }
 
 /// An emoji that is used for interactive verification using a short auth
@@ -155,7 +161,17 @@ impl VerificationStore {
     }
 
     pub async fn save_changes(&self, changes: Changes) -> Result<(), CryptoStoreError> {
-        self.inner.save_changes(changes).await
+        let identity_updates = (!changes.identities.is_empty() || !changes.devices.is_empty())
+            .then(|| (changes.identities.clone(), changes.devices.clone()));
+
+        self.inner.save_changes(changes).await?;
+
+        if let Some(identity_updates) = identity_updates {
+            // Ignore the result. It can only fail if there are no listeners.
+            let _ = self.identities_broadcaster.send(identity_updates);
+        }
+
+        Ok(())
     }
 
     pub async fn get_user_devices(
@@ -748,7 +764,7 @@ pub(crate) mod tests {
         events::{AnyToDeviceEventContent, ToDeviceEvent},
         user_id, DeviceId, UserId,
     };
-    use tokio::sync::Mutex;
+    use tokio::sync::{broadcast, Mutex};
 
     use super::{event_enums::OutgoingContent, VerificationStore};
     use crate::{
@@ -877,12 +893,14 @@ pub(crate) mod tests {
             account: alice,
             inner: alice_store.into_crypto_store(),
             private_identity: alice_private_identity.into(),
+            identities_broadcaster: broadcast::Sender::new(10),
         };
 
         let bob_store = VerificationStore {
             account: bob.clone(),
             inner: bob_store.into_crypto_store(),
             private_identity: bob_private_identity.into(),
+            identities_broadcaster: broadcast::Sender::new(10),
         };
 
         (alice_store, bob_store)
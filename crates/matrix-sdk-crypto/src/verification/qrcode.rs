@@ -887,7 +887,7 @@ mod tests {
     use matrix_sdk_qrcode::QrVerificationData;
     use matrix_sdk_test::async_test;
     use ruma::{device_id, event_id, room_id, user_id, DeviceId, UserId};
-    use tokio::sync::Mutex;
+    use tokio::sync::{broadcast, Mutex};
 
     use crate::{
         olm::{PrivateCrossSigningIdentity, ReadOnlyAccount},
@@ -924,6 +924,7 @@ mod tests {
             account: account.clone(),
             inner: store,
             private_identity: Mutex::new(private_identity).into(),
+            identities_broadcaster: broadcast::Sender::new(10),
         };
 
         let flow_id = FlowId::ToDevice("test_transaction".into());
@@ -987,6 +988,7 @@ mod tests {
                 account: alice_account.clone(),
                 inner: store,
                 private_identity: Mutex::new(private_identity).into(),
+                identities_broadcaster: broadcast::Sender::new(10),
             };
 
             let bob_account =
@@ -1025,6 +1027,7 @@ mod tests {
                 account: bob_account.clone(),
                 inner: bob_store,
                 private_identity: Mutex::new(private_identity).into(),
+                identities_broadcaster: broadcast::Sender::new(10),
             };
 
             let mut changes = Changes::default();
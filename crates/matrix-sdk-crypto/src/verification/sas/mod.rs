@@ -871,7 +871,7 @@ mod tests {
         events::key::verification::{accept::AcceptMethod, ShortAuthenticationString},
         user_id, DeviceId, TransactionId, UserId,
     };
-    use tokio::sync::Mutex;
+    use tokio::sync::{broadcast, Mutex};
 
     use super::Sas;
     use crate::{
@@ -912,6 +912,7 @@ mod tests {
             account: alice.clone(),
             inner: MemoryStore::new().into_crypto_store(),
             private_identity: Mutex::new(PrivateCrossSigningIdentity::empty(alice_id())).into(),
+            identities_broadcaster: broadcast::Sender::new(10),
         };
 
         let bob_store = MemoryStore::new();
@@ -921,6 +922,7 @@ mod tests {
             account: bob.clone(),
             inner: bob_store.into_crypto_store(),
             private_identity: Mutex::new(PrivateCrossSigningIdentity::empty(bob_id())).into(),
+            identities_broadcaster: broadcast::Sender::new(10),
         };
 
         (alice_store, alice_device, bob_store, bob_device)
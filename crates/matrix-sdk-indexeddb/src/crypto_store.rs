@@ -13,7 +13,7 @@
 // limitations under the License.
 
 use std::{
-    collections::HashMap,
+    collections::{BTreeSet, HashMap},
     sync::{Arc, RwLock},
 };
 
@@ -22,7 +22,7 @@ use gloo_utils::format::JsValueSerdeExt;
 use indexed_db_futures::prelude::*;
 use matrix_sdk_crypto::{
     olm::{
-        IdentityKeys, InboundGroupSession, OlmMessageHash, OutboundGroupSession,
+        IdentityKeys, InboundGroupSession, OlmMessageHash, OutboundGroupSession, PickledSession,
         PrivateCrossSigningIdentity, Session,
     },
     store::{
@@ -36,14 +36,21 @@ use matrix_sdk_crypto::{
 use matrix_sdk_store_encryption::StoreCipher;
 use ruma::{
     events::secret::request::SecretName, DeviceId, MilliSecondsSinceUnixEpoch, OwnedDeviceId,
-    OwnedUserId, RoomId, TransactionId, UserId,
+    OwnedUserId, RoomId, SecondsSinceUnixEpoch, TransactionId, UserId,
+};
+use serde::{
+    de::{DeserializeOwned, IgnoredAny},
+    Serialize,
 };
-use serde::{de::DeserializeOwned, Serialize};
 use tokio::sync::Mutex;
+use tracing::warn;
 use wasm_bindgen::JsValue;
 use web_sys::IdbKeyRange;
 
-use crate::safe_encode::SafeEncode;
+use crate::{
+    maintenance::{self, CorruptedEntry, IntegrityReport, StoreStats},
+    safe_encode::SafeEncode,
+};
 
 mod keys {
     // stores
@@ -70,6 +77,23 @@ mod keys {
 
     pub const DIRECT_WITHHELD_INFO: &str = "direct_withheld_info";
 
+    /// The stores whose values are serialized with the store cipher, the
+    /// values of the other stores are plain JS values.
+    pub const SERIALIZED_VALUE_STORES: &[&str] = &[
+        CORE,
+        SESSION,
+        INBOUND_GROUP_SESSIONS,
+        OUTBOUND_GROUP_SESSIONS,
+        DEVICES,
+        IDENTITIES,
+        OUTGOING_SECRET_REQUESTS,
+        UNSENT_SECRET_REQUESTS,
+        ROOM_SETTINGS,
+        SECRETS_INBOX,
+        DIRECT_WITHHELD_INFO,
+        BACKUP_KEYS,
+    ];
+
     // keys
     pub const STORE_CIPHER: &str = "store_cipher";
     pub const ACCOUNT: &str = "account";
@@ -97,6 +121,9 @@ pub struct IndexeddbCryptoStore {
     pub(crate) inner: IdbDatabase,
 
     store_cipher: Option<Arc<StoreCipher>>,
+    /// Whether the store cipher is persisted in the meta database of this
+    /// store, rather than in the one of the state store.
+    has_own_store_cipher: bool,
 
     session_cache: SessionStore,
 }
@@ -122,6 +149,8 @@ pub enum IndexeddbCryptoStoreError {
     },
     #[error(transparent)]
     CryptoStoreError(#[from] CryptoStoreError),
+    #[error("The store isn't encrypted with its own passphrase")]
+    NotEncrypted,
 }
 
 impl From<indexed_db_futures::web_sys::DomException> for IndexeddbCryptoStoreError {
@@ -232,6 +261,7 @@ impl IndexeddbCryptoStore {
             session_cache,
             inner: db,
             store_cipher,
+            has_own_store_cipher: false,
             account_info: RwLock::new(None).into(),
         })
     }
@@ -270,8 +300,9 @@ impl IndexeddbCryptoStore {
         })
     }
 
-    /// Open a new `IndexeddbCryptoStore` with given name and passphrase
-    pub async fn open_with_passphrase(prefix: &str, passphrase: &str) -> Result<Self> {
+    /// Open the database containing the store cipher of the store with the
+    /// given prefix.
+    async fn open_meta_db(prefix: &str) -> Result<IdbDatabase> {
         let name = format!("{prefix:0}::matrix-sdk-crypto-meta");
 
         let mut db_req: OpenDbRequest = IdbDatabase::open_u32(&name, 1)?;
@@ -286,7 +317,20 @@ impl IndexeddbCryptoStore {
             Ok(())
         }));
 
-        let db: IdbDatabase = db_req.into_future().await?;
+        Ok(db_req.into_future().await?)
+    }
+
+    /// Close the database of this store.
+    ///
+    /// The store can't be used anymore afterwards. This is needed before
+    /// deleting the store with [`crate::delete_stores`].
+    pub fn close(&self) {
+        self.inner.close();
+    }
+
+    /// Open a new `IndexeddbCryptoStore` with given name and passphrase
+    pub async fn open_with_passphrase(prefix: &str, passphrase: &str) -> Result<Self> {
+        let db = Self::open_meta_db(prefix).await?;
 
         let tx: IdbTransaction<'_> =
             db.transaction_on_one_with_mode("matrix-sdk-crypto", IdbTransactionMode::Readonly)?;
@@ -327,7 +371,100 @@ impl IndexeddbCryptoStore {
         // dropping it.
         db.close();
 
-        IndexeddbCryptoStore::open_with_store_cipher(prefix, Some(store_cipher.into())).await
+        let mut store =
+            IndexeddbCryptoStore::open_with_store_cipher(prefix, Some(store_cipher.into())).await?;
+        store.has_own_store_cipher = true;
+
+        Ok(store)
+    }
+
+    /// Change the passphrase that protects the encryption key of this store.
+    ///
+    /// The data of the store doesn't need to be re-encrypted, so this is a
+    /// cheap operation. The store must have been opened with
+    /// [`IndexeddbCryptoStore::open_with_passphrase`]. A store opened by
+    /// [`make_store_config`] shares the encryption key of the state store,
+    /// use [`IndexeddbStateStore::change_passphrase`] instead.
+    ///
+    /// # Limitations
+    ///
+    /// Unlike the SQLite stores, the data of an IndexedDB store can't be
+    /// re-encrypted in place to rotate its encryption keys: the store cipher
+    /// and the data live in separate databases, and IndexedDB transactions
+    /// can't span both. To rotate the keys, or to encrypt a store that has
+    /// no passphrase, use [`rekey_stores`], which copies the data into new
+    /// stores.
+    ///
+    /// [`make_store_config`]: crate::make_store_config
+    /// [`IndexeddbStateStore::change_passphrase`]: crate::IndexeddbStateStore::change_passphrase
+    /// [`rekey_stores`]: crate::rekey_stores
+    pub async fn change_passphrase(&self, new_passphrase: &str) -> Result<()> {
+        let store_cipher = match &self.store_cipher {
+            Some(store_cipher) if self.has_own_store_cipher => store_cipher,
+            _ => return Err(IndexeddbCryptoStoreError::NotEncrypted),
+        };
+
+        #[cfg(not(test))]
+        let export = store_cipher.export(new_passphrase);
+        #[cfg(test)]
+        let export = store_cipher._insecure_export_fast_for_testing(new_passphrase);
+        let export = export.map_err(CryptoStoreError::backend)?;
+
+        let prefix = self.name.trim_end_matches("::matrix-sdk-crypto");
+        let db = Self::open_meta_db(prefix).await?;
+
+        let tx: IdbTransaction<'_> =
+            db.transaction_on_one_with_mode("matrix-sdk-crypto", IdbTransactionMode::Readwrite)?;
+        tx.object_store("matrix-sdk-crypto")?
+            .put_key_val(&JsValue::from_str(keys::STORE_CIPHER), &JsValue::from_serde(&export)?)?;
+        tx.await.into_result()?;
+
+        // Must release the database access manually as it's not done when
+        // dropping it.
+        db.close();
+
+        Ok(())
+    }
+
+    /// Get the number of entries of each object store of this store.
+    pub async fn statistics(&self) -> Result<StoreStats> {
+        Ok(maintenance::store_stats(&self.inner).await?)
+    }
+
+    /// Check that every value of this store can be decrypted and
+    /// deserialized.
+    ///
+    /// Like with [`IndexeddbStateStore::check_integrity()`], the entries that
+    /// fail this check are left in place, they are only reported.
+    ///
+    /// [`IndexeddbStateStore::check_integrity()`]: crate::IndexeddbStateStore::check_integrity
+    pub async fn check_integrity(&self) -> Result<IntegrityReport> {
+        let mut report = IntegrityReport::default();
+
+        for name in keys::SERIALIZED_VALUE_STORES {
+            let tx = self.inner.transaction_on_one_with_mode(name, IdbTransactionMode::Readonly)?;
+            let Some(cursor) = tx.object_store(name)?.open_cursor()?.await? else {
+                continue;
+            };
+
+            loop {
+                report.checked_entries += 1;
+
+                if let Err(e) = self.deserialize_value::<IgnoredAny>(cursor.value()) {
+                    warn!(object_store = name, "Found a corrupted entry: {e}");
+                    report.corrupted_entries.push(CorruptedEntry {
+                        object_store: (*name).to_owned(),
+                        error: e.to_string(),
+                    });
+                }
+
+                if !cursor.continue_cursor()?.await? {
+                    break;
+                }
+            }
+        }
+
+        Ok(report)
     }
 
     /// Open a new `IndexeddbCryptoStore` with given name and no passphrase
@@ -563,10 +700,12 @@ impl_crypto_store! {
 
         if !olm_hashes.is_empty() {
             let hashes = tx.object_store(keys::OLM_HASHES)?;
+            let now = JsValue::from(u64::from(SecondsSinceUnixEpoch::now().get()) as f64);
+
             for hash in &olm_hashes {
                 hashes.put_key_val(
                     &self.encode_key(keys::OLM_HASHES, (&hash.sender_key, &hash.hash)),
-                    &JsValue::TRUE,
+                    &now,
                 )?;
             }
         }
@@ -808,6 +947,37 @@ impl_crypto_store! {
         Ok(self.session_cache.get(sender_key))
     }
 
+    async fn get_session_sender_keys(&self) -> Result<Vec<String>> {
+        let sender_keys: BTreeSet<String> = self
+            .inner
+            .transaction_on_one_with_mode(keys::SESSION, IdbTransactionMode::Readonly)?
+            .object_store(keys::SESSION)?
+            .get_all()?
+            .await?
+            .iter()
+            .map(|value| {
+                let pickle: PickledSession = self.deserialize_value(value)?;
+                Ok(pickle.sender_key.to_base64())
+            })
+            .collect::<Result<_, CryptoStoreError>>()?;
+
+        Ok(sender_keys.into_iter().collect())
+    }
+
+    async fn delete_sessions(&self, sender_key: &str) -> Result<()> {
+        let range = self.encode_to_range(keys::SESSION, sender_key)?;
+        let tx = self
+            .inner
+            .transaction_on_one_with_mode(keys::SESSION, IdbTransactionMode::Readwrite)?;
+
+        tx.object_store(keys::SESSION)?.delete(&range)?;
+        tx.await.into_result()?;
+
+        self.session_cache.remove_for_sender(sender_key);
+
+        Ok(())
+    }
+
     async fn get_inbound_group_session(
         &self,
         room_id: &RoomId,
@@ -847,6 +1017,63 @@ impl_crypto_store! {
             .collect())
     }
 
+    async fn get_inbound_group_sessions_batch(
+        &self,
+        after: Option<(&RoomId, &str)>,
+        limit: usize,
+    ) -> Result<Vec<InboundGroupSession>> {
+        // The keys are strings, so the empty string is lower than all of them.
+        let (lower, open) = match after {
+            Some((room_id, session_id)) => {
+                (self.encode_key(keys::INBOUND_GROUP_SESSIONS, (room_id, session_id)), true)
+            }
+            None => (JsValue::from_str(""), false),
+        };
+        let range = IdbKeyRange::lower_bound_with_open(&lower, open).map_err(|e| {
+            IndexeddbCryptoStoreError::DomException {
+                code: 0,
+                name: "IdbKeyRangeMakeError".to_owned(),
+                message: format!("{e:?}"),
+            }
+        })?;
+        let limit = u32::try_from(limit).unwrap_or(u32::MAX);
+
+        self.inner
+            .transaction_on_one_with_mode(
+                keys::INBOUND_GROUP_SESSIONS,
+                IdbTransactionMode::Readonly,
+            )?
+            .object_store(keys::INBOUND_GROUP_SESSIONS)?
+            .get_all_with_key_and_limit(&range, limit)?
+            .await?
+            .iter()
+            .map(|i| {
+                let pickle = self.deserialize_value(i)?;
+                Ok(InboundGroupSession::from_pickle(pickle).map_err(CryptoStoreError::from)?)
+            })
+            .collect()
+    }
+
+    async fn delete_inbound_group_sessions(
+        &self,
+        room_id: &RoomId,
+        session_ids: &[String],
+    ) -> Result<()> {
+        let tx = self.inner.transaction_on_one_with_mode(
+            keys::INBOUND_GROUP_SESSIONS,
+            IdbTransactionMode::Readwrite,
+        )?;
+        let sessions = tx.object_store(keys::INBOUND_GROUP_SESSIONS)?;
+
+        for session_id in session_ids {
+            let key =
+                self.encode_key(keys::INBOUND_GROUP_SESSIONS, (room_id, session_id.as_str()));
+            sessions.delete(&key)?;
+        }
+
+        tx.await.into_result().map_err(|e| e.into())
+    }
+
     async fn inbound_group_session_counts(&self) -> Result<RoomKeyCounts> {
         let all = self.get_inbound_group_sessions().await?;
         let backed_up = all.iter().filter(|s| s.backed_up()).count();
@@ -958,6 +1185,43 @@ impl_crypto_store! {
             .is_some())
     }
 
+    async fn clear_message_hashes(&self, older_than: SecondsSinceUnixEpoch) -> Result<usize> {
+        let older_than = u64::from(older_than.get()) as f64;
+        let tx = self
+            .inner
+            .transaction_on_one_with_mode(keys::OLM_HASHES, IdbTransactionMode::Readwrite)?;
+        let hashes = tx.object_store(keys::OLM_HASHES)?;
+
+        let mut old_keys = Vec::new();
+
+        if let Some(cursor) = hashes.open_cursor()?.await? {
+            loop {
+                // Hashes stored by older versions only have `true` as their
+                // value, their age isn't known so they are considered to be
+                // the oldest ones.
+                let added_at = cursor.value().as_f64().unwrap_or_default();
+
+                if added_at < older_than {
+                    if let Some(key) = cursor.key() {
+                        old_keys.push(key);
+                    }
+                }
+
+                if !cursor.continue_cursor()?.await? {
+                    break;
+                }
+            }
+        }
+
+        for key in &old_keys {
+            hashes.delete(key)?;
+        }
+
+        tx.await.into_result()?;
+
+        Ok(old_keys.len())
+    }
+
     async fn get_secrets_from_inbox(
         &self,
         secret_name: &SecretName,
@@ -1028,6 +1292,25 @@ impl_crypto_store! {
             .collect())
     }
 
+    async fn get_all_secret_requests(&self) -> Result<Vec<GossipRequest>> {
+        let dbs = [keys::OUTGOING_SECRET_REQUESTS, keys::UNSENT_SECRET_REQUESTS];
+        let tx = self.inner.transaction_on_multi_with_mode(&dbs, IdbTransactionMode::Readonly)?;
+
+        let mut requests = Vec::new();
+
+        for db in dbs {
+            requests.extend(
+                tx.object_store(db)?
+                    .get_all()?
+                    .await?
+                    .iter()
+                    .filter_map(|i| self.deserialize_value(i).ok()),
+            );
+        }
+
+        Ok(requests)
+    }
+
     async fn delete_outgoing_secret_requests(&self, request_id: &TransactionId) -> Result<()> {
         let jskey = self.encode_key(keys::KEY_REQUEST, request_id); //.as_str());
         let dbs = [
@@ -1,15 +1,25 @@
 #![cfg_attr(not(target_arch = "wasm32"), allow(unused))]
 
+#[cfg(feature = "e2e-encryption")]
+use std::sync::Arc;
+
+use indexed_db_futures::prelude::*;
+#[cfg(feature = "e2e-encryption")]
+use matrix_sdk_base::store::{migrate_state_store, IntoStateStore, StateStore};
 use matrix_sdk_base::store::{StoreConfig, StoreError};
+#[cfg(feature = "e2e-encryption")]
+use matrix_sdk_crypto::store::{migrate_crypto_store, CryptoStore, IntoCryptoStore};
 use thiserror::Error;
 
 #[cfg(feature = "e2e-encryption")]
 mod crypto_store;
+mod maintenance;
 mod safe_encode;
 mod state_store;
 
 #[cfg(feature = "e2e-encryption")]
 pub use crypto_store::{IndexeddbCryptoStore, IndexeddbCryptoStoreError};
+pub use maintenance::{CorruptedEntry, IntegrityReport, ObjectStoreStats, StoreStats};
 pub use state_store::{
     IndexeddbStateStore, IndexeddbStateStoreBuilder, IndexeddbStateStoreError,
     MigrationConflictStrategy,
@@ -18,7 +28,7 @@ pub use state_store::{
 /// Create a [`IndexeddbStateStore`] and a [`IndexeddbCryptoStore`] that use the
 /// same name and passphrase.
 #[cfg(feature = "e2e-encryption")]
-async fn open_stores_with_name(
+pub async fn open_stores_with_name(
     name: &str,
     passphrase: Option<&str>,
 ) -> Result<(IndexeddbStateStore, IndexeddbCryptoStore), OpenStoreError> {
@@ -35,6 +45,117 @@ async fn open_stores_with_name(
     Ok((state_store, crypto_store))
 }
 
+/// The key of the custom value marking the complete copy of the stores being
+/// rekeyed by [`rekey_stores`].
+#[cfg(feature = "e2e-encryption")]
+const REKEY_COMPLETE_KEY: &[u8] = b"indexeddb.rekey_complete";
+
+/// Re-encrypt all the data of the IndexedDB state and crypto stores with the
+/// given name with new encryption keys, protected by the given passphrase.
+///
+/// If the stores have no passphrase, their data gets encrypted and their keys
+/// hashed. Otherwise, both the key used to encrypt the values and the key used
+/// to hash the keys are rotated.
+///
+/// The original keys can't be recovered from their hashes, so the data is
+/// copied into temporary stores with [`migrate_state_store`] and
+/// [`migrate_crypto_store`], and these stores then replace the original ones.
+/// The data that isn't copied by these functions, like the media cache, is
+/// dropped and recreated when needed.
+///
+/// This is an offline operation: the stores must be closed while they are
+/// rekeyed, with [`IndexeddbStateStore::close`] and
+/// [`IndexeddbCryptoStore::close`]. They can be opened with the new passphrase
+/// once this is done, for example with [`make_store_config`]. If the operation
+/// is interrupted, calling this function again with the same arguments
+/// resumes it.
+///
+/// # Arguments
+///
+/// * `name` - The name of the stores.
+///
+/// * `old_passphrase` - The current passphrase of the stores, if any.
+///
+/// * `new_passphrase` - The passphrase that should protect the new encryption
+///   keys.
+#[cfg(feature = "e2e-encryption")]
+pub async fn rekey_stores(
+    name: &str,
+    old_passphrase: Option<&str>,
+    new_passphrase: &str,
+) -> Result<(), OpenStoreError> {
+    let tmp_name = format!("{name}::rekey");
+
+    let (tmp_state, tmp_crypto) = open_stores_with_name(&tmp_name, Some(new_passphrase)).await?;
+    let (tmp_state, tmp_crypto) = (Arc::new(tmp_state), Arc::new(tmp_crypto));
+
+    // The marker is only set once the copy is complete, an incomplete copy is
+    // resumed by migrating the data again.
+    if tmp_state.get_custom_value(REKEY_COMPLETE_KEY).await.map_err(StoreError::from)?.is_none() {
+        let (state, crypto) = open_stores_with_name(name, old_passphrase).await?;
+        let (state, crypto) = (Arc::new(state), Arc::new(crypto));
+
+        migrate_state_store(
+            &*state.clone().into_state_store(),
+            &*tmp_state.clone().into_state_store(),
+            |_, _, _| {},
+        )
+        .await?;
+
+        // Stores that were never logged in don't have any crypto data.
+        if crypto.load_account().await?.is_some() {
+            let room_infos = state.get_room_infos().await.map_err(StoreError::from)?;
+            let room_ids: Vec<_> = room_infos.iter().map(|r| r.room_id().to_owned()).collect();
+
+            migrate_crypto_store(
+                &*crypto.clone().into_crypto_store(),
+                &*tmp_crypto.clone().into_crypto_store(),
+                &room_ids,
+                |_, _, _| {},
+            )
+            .await
+            .map_err(IndexeddbCryptoStoreError::from)?;
+        }
+
+        state.close();
+        crypto.close();
+
+        tmp_state
+            .set_custom_value(REKEY_COMPLETE_KEY, Vec::new())
+            .await
+            .map_err(StoreError::from)?;
+    }
+
+    // Replace the original stores with the copy, including the store cipher
+    // of the copy.
+    delete_stores(name).await.map_err(StoreError::from)?;
+
+    let (state, crypto) = open_stores_with_name(name, Some(new_passphrase)).await?;
+    let copied = async {
+        maintenance::copy_database(&tmp_state.meta, &state.meta).await?;
+        maintenance::copy_database(&tmp_state.inner, &state.inner).await?;
+        maintenance::copy_database(&tmp_crypto.inner, &crypto.inner).await
+    }
+    .await;
+
+    state.close();
+    crypto.close();
+    tmp_state.close();
+    tmp_crypto.close();
+
+    copied.map_err(|e| StoreError::from(IndexeddbStateStoreError::from(e)))?;
+
+    let (state, crypto) = open_stores_with_name(name, Some(new_passphrase)).await?;
+    let removed = state.remove_custom_value(REKEY_COMPLETE_KEY).await;
+    state.close();
+    crypto.close();
+    removed.map_err(StoreError::from)?;
+
+    delete_stores(&tmp_name).await.map_err(StoreError::from)?;
+
+    Ok(())
+}
+
 /// Create a [`StoreConfig`] with an opened indexeddb [`IndexeddbStateStore`]
 /// that uses the given name and passphrase. If `encryption` is enabled, a
 /// [`IndexeddbCryptoStore`] with the same parameters is also opened.
@@ -68,6 +189,27 @@ pub async fn make_store_config(
     panic!("the IndexedDB is only available on the 'wasm32' arch")
 }
 
+/// Delete the IndexedDB state and crypto stores with the given name, with all
+/// their data.
+///
+/// The stores must be closed beforehand, with [`IndexeddbStateStore::close`]
+/// and `IndexeddbCryptoStore::close`, otherwise the deletion is blocked until
+/// they are.
+pub async fn delete_stores(name: &str) -> Result<(), IndexeddbStateStoreError> {
+    let databases = [
+        name.to_owned(),
+        format!("{name}::{}", state_store::keys::INTERNAL_STATE),
+        format!("{name}::matrix-sdk-crypto"),
+        format!("{name}::matrix-sdk-crypto-meta"),
+    ];
+
+    for database in databases {
+        IdbDatabase::delete_by_name(&database)?.into_future().await?;
+    }
+
+    Ok(())
+}
+
 /// All the errors that can occur when opening an IndexedDB store.
 #[derive(Error, Debug)]
 pub enum OpenStoreError {
@@ -80,3 +222,70 @@ pub enum OpenStoreError {
     #[error(transparent)]
     Crypto(#[from] IndexeddbCryptoStoreError),
 }
+
+#[cfg(all(test, target_arch = "wasm32", feature = "e2e-encryption"))]
+mod tests {
+    wasm_bindgen_test::wasm_bindgen_test_configure!(run_in_browser);
+
+    use assert_matches::assert_matches;
+    use matrix_sdk_base::{StateStore, StateStoreDataKey, StateStoreDataValue};
+    use matrix_sdk_crypto::{store::CryptoStore, ReadOnlyAccount};
+    use matrix_sdk_test::async_test;
+    use ruma::{device_id, user_id};
+    use uuid::Uuid;
+
+    use super::{open_stores_with_name, rekey_stores, OpenStoreError};
+
+    async fn assert_store_content(
+        name: &str,
+        passphrase: &str,
+        account: &ReadOnlyAccount,
+    ) -> Result<(), OpenStoreError> {
+        let (state_store, crypto_store) = open_stores_with_name(name, Some(passphrase)).await?;
+
+        let sync_token = state_store.get_kv_data(StateStoreDataKey::SyncToken).await.unwrap();
+        assert_matches!(sync_token, Some(StateStoreDataValue::SyncToken(token)) if token == "token");
+
+        let loaded = crypto_store.load_account().await?.expect("The account should be copied");
+        assert_eq!(loaded.identity_keys(), account.identity_keys());
+
+        state_store.close();
+        crypto_store.close();
+
+        Ok(())
+    }
+
+    #[async_test]
+    async fn test_rekey_stores() -> Result<(), OpenStoreError> {
+        let name = format!("rekey-stores-{}", Uuid::new_v4().as_hyphenated());
+        let account =
+            ReadOnlyAccount::with_device_id(user_id!("@alice:localhost"), device_id!("A"));
+
+        let (state_store, crypto_store) = open_stores_with_name(&name, None).await?;
+        state_store
+            .set_kv_data(
+                StateStoreDataKey::SyncToken,
+                StateStoreDataValue::SyncToken("token".to_owned()),
+            )
+            .await
+            .unwrap();
+        crypto_store.save_account(account.clone()).await?;
+        state_store.close();
+        crypto_store.close();
+
+        // Encrypt the stores that had no passphrase.
+        rekey_stores(&name, None, "secret").await?;
+        assert_store_content(&name, "secret", &account).await?;
+
+        // Rotate the keys.
+        rekey_stores(&name, Some("secret"), "other").await?;
+        assert_store_content(&name, "other", &account).await?;
+
+        assert_matches!(
+            open_stores_with_name(&name, Some("secret")).await,
+            Err(OpenStoreError::State(_))
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,113 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Diagnostics of the stores: size statistics and integrity checks.
+
+use indexed_db_futures::{prelude::*, web_sys::DomException};
+
+/// Statistics about the size of an IndexedDB store.
+///
+/// Browsers don't expose the size of the data of an object store, so only
+/// the number of entries is available.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct StoreStats {
+    /// The statistics of each object store of the database.
+    pub object_stores: Vec<ObjectStoreStats>,
+}
+
+/// Statistics about the size of an object store of an IndexedDB store.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct ObjectStoreStats {
+    /// The name of the object store.
+    pub name: String,
+
+    /// The number of entries in the object store.
+    pub entry_count: u32,
+}
+
+/// The result of an integrity check of an IndexedDB store.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct IntegrityReport {
+    /// The number of values that were checked.
+    pub checked_entries: u64,
+
+    /// The entries whose value couldn't be decrypted or deserialized.
+    pub corrupted_entries: Vec<CorruptedEntry>,
+}
+
+impl IntegrityReport {
+    /// Whether no problem was found in the store.
+    pub fn is_ok(&self) -> bool {
+        self.corrupted_entries.is_empty()
+    }
+}
+
+/// An entry that failed the integrity check of an IndexedDB store.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct CorruptedEntry {
+    /// The name of the object store containing the entry.
+    pub object_store: String,
+
+    /// The reason why the check failed.
+    pub error: String,
+}
+
+/// Count the entries of every object store of the given database.
+pub(crate) async fn store_stats(db: &IdbDatabase) -> Result<StoreStats, DomException> {
+    let mut object_stores = Vec::new();
+
+    for name in db.object_store_names() {
+        let entry_count = db
+            .transaction_on_one_with_mode(&name, IdbTransactionMode::Readonly)?
+            .object_store(&name)?
+            .count()?
+            .await?;
+
+        object_stores.push(ObjectStoreStats { name, entry_count });
+    }
+
+    Ok(StoreStats { object_stores })
+}
+
+/// Copy the entries of every object store of the `source` database into the
+/// object store with the same name of the `target` database, as they are.
+pub(crate) async fn copy_database(
+    source: &IdbDatabase,
+    target: &IdbDatabase,
+) -> Result<(), DomException> {
+    for name in source.object_store_names() {
+        let source_tx = source.transaction_on_one_with_mode(&name, IdbTransactionMode::Readonly)?;
+        let Some(cursor) = source_tx.object_store(&name)?.open_cursor()?.await? else {
+            continue;
+        };
+
+        let entries = cursor.into_vec(0).await?;
+
+        let target_tx =
+            target.transaction_on_one_with_mode(&name, IdbTransactionMode::Readwrite)?;
+        let target_store = target_tx.object_store(&name)?;
+
+        for entry in entries {
+            target_store.put_key_val(entry.key(), entry.value())?;
+        }
+
+        target_tx.await.into_result()?;
+    }
+
+    Ok(())
+}
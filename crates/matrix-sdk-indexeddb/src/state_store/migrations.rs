@@ -110,13 +110,9 @@ pub async fn upgrade_meta_db(
             StoreCipher::import(passphrase, &inner)?
         } else {
             let cipher = StoreCipher::new()?;
-            #[cfg(not(test))]
-            let export = cipher.export(passphrase)?;
-            #[cfg(test)]
-            let export = cipher._insecure_export_fast_for_testing(passphrase)?;
             ob.put_key_val(
                 &JsValue::from_str(keys::STORE_KEY),
-                &JsValue::from_serde(&StoreKeyWrapper(export))?,
+                &export_store_cipher(&cipher, passphrase)?,
             )?;
             cipher
         };
@@ -130,6 +126,33 @@ pub async fn upgrade_meta_db(
     Ok((meta_db, store_cipher))
 }
 
+/// Save the given store cipher in the meta database, encrypted with the given
+/// passphrase.
+pub async fn save_store_cipher(
+    meta_db: &IdbDatabase,
+    store_cipher: &StoreCipher,
+    passphrase: &str,
+) -> Result<()> {
+    let export = export_store_cipher(store_cipher, passphrase)?;
+
+    let tx = meta_db
+        .transaction_on_one_with_mode(keys::INTERNAL_STATE, IdbTransactionMode::Readwrite)?;
+    tx.object_store(keys::INTERNAL_STATE)?
+        .put_key_val(&JsValue::from_str(keys::STORE_KEY), &export)?;
+    tx.await.into_result()?;
+
+    Ok(())
+}
+
+fn export_store_cipher(store_cipher: &StoreCipher, passphrase: &str) -> Result<JsValue> {
+    #[cfg(not(test))]
+    let export = store_cipher.export(passphrase)?;
+    #[cfg(test)]
+    let export = store_cipher._insecure_export_fast_for_testing(passphrase)?;
+
+    Ok(JsValue::from_serde(&StoreKeyWrapper(export))?)
+}
+
 // Helper struct for upgrading the inner DB.
 #[derive(Debug, Clone, Default)]
 pub struct OngoingMigration {
@@ -757,6 +780,42 @@ mod tests {
         Ok(())
     }
 
+    #[async_test]
+    pub async fn test_change_passphrase() -> Result<()> {
+        let name = format!("change-passphrase-{}", Uuid::new_v4().as_hyphenated().to_string());
+
+        let store = IndexeddbStateStore::builder().name(name.clone()).build().await?;
+        assert_matches!(
+            store.change_passphrase("secret").await,
+            Err(IndexeddbStateStoreError::NotEncrypted)
+        );
+
+        let name = format!("change-passphrase-{}", Uuid::new_v4().as_hyphenated().to_string());
+
+        let store = IndexeddbStateStore::builder()
+            .name(name.clone())
+            .passphrase("old".to_owned())
+            .build()
+            .await?;
+        store.set_custom_value(CUSTOM_DATA_KEY, CUSTOM_DATA.to_vec()).await?;
+        store.change_passphrase("new").await?;
+
+        assert_matches!(
+            IndexeddbStateStore::builder()
+                .name(name.clone())
+                .passphrase("old".to_owned())
+                .build()
+                .await,
+            Err(IndexeddbStateStoreError::Encryption(_))
+        );
+
+        let store =
+            IndexeddbStateStore::builder().name(name).passphrase("new".to_owned()).build().await?;
+        assert_eq!(store.get_custom_value(CUSTOM_DATA_KEY).await?, Some(CUSTOM_DATA.to_vec()));
+
+        Ok(())
+    }
+
     #[async_test]
     pub async fn test_migrating_v1_to_v2_plain() -> Result<()> {
         let name = format!("migrating-v2-no-cipher-{}", Uuid::new_v4().as_hyphenated().to_string());
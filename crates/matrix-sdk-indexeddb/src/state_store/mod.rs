@@ -41,9 +41,13 @@ use ruma::{
         GlobalAccountDataEventType, RoomAccountDataEventType, StateEventType, SyncStateEvent,
     },
     serde::Raw,
-    CanonicalJsonObject, EventId, MxcUri, OwnedEventId, OwnedUserId, RoomId, RoomVersionId, UserId,
+    CanonicalJsonObject, EventId, MilliSecondsSinceUnixEpoch, MxcUri, OwnedEventId, OwnedUserId,
+    RoomId, RoomVersionId, UserId,
+};
+use serde::{
+    de::{DeserializeOwned, IgnoredAny},
+    Deserialize, Serialize,
 };
-use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use tracing::{debug, warn};
 use wasm_bindgen::JsValue;
 use web_sys::IdbKeyRange;
@@ -51,8 +55,11 @@ use web_sys::IdbKeyRange;
 mod migrations;
 
 pub use self::migrations::MigrationConflictStrategy;
-use self::migrations::{upgrade_inner_db, upgrade_meta_db};
-use crate::safe_encode::SafeEncode;
+use self::migrations::{save_store_cipher, upgrade_inner_db, upgrade_meta_db};
+use crate::{
+    maintenance::{self, CorruptedEntry, IntegrityReport, StoreStats},
+    safe_encode::SafeEncode,
+};
 
 #[derive(Debug, thiserror::Error)]
 pub enum IndexeddbStateStoreError {
@@ -66,6 +73,8 @@ pub enum IndexeddbStateStoreError {
     StoreError(#[from] StoreError),
     #[error("Can't migrate {name} from {old_version} to {new_version} without deleting data. See MigrationConflictStrategy for ways to configure.")]
     MigrationConflict { name: String, old_version: u32, new_version: u32 },
+    #[error("The store isn't encrypted")]
+    NotEncrypted,
 }
 
 impl From<indexed_db_futures::web_sys::DomException> for IndexeddbStateStoreError {
@@ -89,7 +98,7 @@ impl From<IndexeddbStateStoreError> for StoreError {
     }
 }
 
-mod keys {
+pub(crate) mod keys {
     pub const INTERNAL_STATE: &str = "matrix-sdk-state";
     pub const BACKUPS_META: &str = "backups";
 
@@ -115,6 +124,9 @@ mod keys {
     pub const CUSTOM: &str = "custom";
     pub const KV: &str = "kv";
 
+    /// Prefix of the lease locks' keys in the [`KV`] store.
+    pub const LEASE_LOCKS: &str = "lease_locks";
+
     /// All names of the current state stores for convenience.
     pub const ALL_STORES: &[&str] = &[
         ACCOUNT_DATA,
@@ -257,6 +269,15 @@ impl IndexeddbStateStore {
         IndexeddbStateStoreBuilder::new()
     }
 
+    /// Close the databases of this store.
+    ///
+    /// The store can't be used anymore afterwards. This is needed before
+    /// deleting the store with [`crate::delete_stores`].
+    pub fn close(&self) {
+        self.inner.close();
+        self.meta.close();
+    }
+
     /// The version of the database containing the data.
     pub fn version(&self) -> u32 {
         self.inner.version() as u32
@@ -289,6 +310,73 @@ impl IndexeddbStateStore {
             .and_then(|c| c.value().as_string()))
     }
 
+    /// Change the passphrase that protects the encryption key of this store.
+    ///
+    /// The data of the store doesn't need to be re-encrypted, so this is a
+    /// cheap operation. The store must have been built with a passphrase.
+    ///
+    /// The [`IndexeddbCryptoStore`] opened alongside this store by
+    /// [`make_store_config`] shares its encryption key, so it is protected by
+    /// the new passphrase too.
+    ///
+    /// # Limitations
+    ///
+    /// Unlike the SQLite stores, the data of an IndexedDB store can't be
+    /// re-encrypted in place to rotate its encryption keys: the store cipher
+    /// and the data live in separate databases, and IndexedDB transactions
+    /// can't span both. To rotate the keys, or to encrypt a store that has
+    /// no passphrase, use [`rekey_stores`], which copies the data into new
+    /// stores.
+    ///
+    /// [`IndexeddbCryptoStore`]: crate::IndexeddbCryptoStore
+    /// [`make_store_config`]: crate::make_store_config
+    /// [`rekey_stores`]: crate::rekey_stores
+    pub async fn change_passphrase(&self, new_passphrase: &str) -> Result<()> {
+        let store_cipher =
+            self.store_cipher.as_deref().ok_or(IndexeddbStateStoreError::NotEncrypted)?;
+
+        save_store_cipher(&self.meta, store_cipher, new_passphrase).await
+    }
+
+    /// Get the number of entries of each object store of this store.
+    pub async fn statistics(&self) -> Result<StoreStats> {
+        Ok(maintenance::store_stats(&self.inner).await?)
+    }
+
+    /// Check that every value of this store can be decrypted and
+    /// deserialized.
+    ///
+    /// Unlike the SQLite store, the entries that fail this check are left in
+    /// place, they are only reported.
+    pub async fn check_integrity(&self) -> Result<IntegrityReport> {
+        let mut report = IntegrityReport::default();
+
+        for name in keys::ALL_STORES {
+            let tx = self.inner.transaction_on_one_with_mode(name, IdbTransactionMode::Readonly)?;
+            let Some(cursor) = tx.object_store(name)?.open_cursor()?.await? else {
+                continue;
+            };
+
+            loop {
+                report.checked_entries += 1;
+
+                if let Err(e) = self.deserialize_event::<IgnoredAny>(&cursor.value()) {
+                    warn!(object_store = name, "Found a corrupted entry: {e}");
+                    report.corrupted_entries.push(CorruptedEntry {
+                        object_store: (*name).to_owned(),
+                        error: e.to_string(),
+                    });
+                }
+
+                if !cursor.continue_cursor()?.await? {
+                    break;
+                }
+            }
+        }
+
+        Ok(report)
+    }
+
     fn serialize_event(&self, event: &impl Serialize) -> Result<JsValue> {
         serialize_event(self.store_cipher.as_deref(), event)
     }
@@ -1136,6 +1224,100 @@ impl_state_store!({
             .collect::<Vec<_>>())
     }
 
+    async fn get_all_state_events(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<RawAnySyncOrStrippedState>> {
+        let tx = self.inner.transaction_on_multi_with_mode(
+            &[keys::ROOM_STATE, keys::STRIPPED_ROOM_STATE],
+            IdbTransactionMode::Readonly,
+        )?;
+
+        let range = self.encode_to_range(keys::ROOM_STATE, room_id)?;
+        let mut events = tx
+            .object_store(keys::ROOM_STATE)?
+            .get_all_with_key(&range)?
+            .await?
+            .iter()
+            .map(|f| self.deserialize_event(&f).map(RawAnySyncOrStrippedState::Sync))
+            .collect::<Result<Vec<_>>>()?;
+
+        let range = self.encode_to_range(keys::STRIPPED_ROOM_STATE, room_id)?;
+        let stripped_events = tx
+            .object_store(keys::STRIPPED_ROOM_STATE)?
+            .get_all_with_key(&range)?
+            .await?
+            .iter()
+            .map(|f| self.deserialize_event(&f).map(RawAnySyncOrStrippedState::Stripped))
+            .collect::<Result<Vec<_>>>()?;
+        events.extend(stripped_events);
+
+        Ok(events)
+    }
+
+    async fn get_all_account_data_events(&self) -> Result<Vec<Raw<AnyGlobalAccountDataEvent>>> {
+        self.inner
+            .transaction_on_one_with_mode(keys::ACCOUNT_DATA, IdbTransactionMode::Readonly)?
+            .object_store(keys::ACCOUNT_DATA)?
+            .get_all()?
+            .await?
+            .iter()
+            .map(|f| self.deserialize_event(&f))
+            .collect()
+    }
+
+    async fn get_all_room_account_data_events(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<Raw<AnyRoomAccountDataEvent>>> {
+        let range = self.encode_to_range(keys::ROOM_ACCOUNT_DATA, room_id)?;
+        self.inner
+            .transaction_on_one_with_mode(keys::ROOM_ACCOUNT_DATA, IdbTransactionMode::Readonly)?
+            .object_store(keys::ROOM_ACCOUNT_DATA)?
+            .get_all_with_key(&range)?
+            .await?
+            .iter()
+            .map(|f| self.deserialize_event(&f))
+            .collect()
+    }
+
+    async fn get_room_receipt_events(
+        &self,
+        room_id: &RoomId,
+        receipt_type: ReceiptType,
+    ) -> Result<Vec<(OwnedUserId, OwnedEventId, Receipt)>> {
+        // The user receipts don't contain the user ID, so we look up the
+        // receipts of every event that has a receipt to get it.
+        let range = self.encode_to_range(keys::ROOM_USER_RECEIPTS, (room_id, &receipt_type))?;
+        let events = self
+            .inner
+            .transaction_on_one_with_mode(keys::ROOM_USER_RECEIPTS, IdbTransactionMode::Readonly)?
+            .object_store(keys::ROOM_USER_RECEIPTS)?
+            .get_all_with_key(&range)?
+            .await?
+            .iter()
+            .map(|f| {
+                self.deserialize_event::<(OwnedEventId, Receipt)>(&f).map(|(event_id, receipt)| {
+                    ((receipt.thread.as_str().map(ToOwned::to_owned), event_id), receipt.thread)
+                })
+            })
+            .collect::<Result<BTreeMap<_, _>>>()?;
+
+        let mut receipts = Vec::new();
+        for ((_, event_id), thread) in events {
+            let event_receipts = self
+                .get_event_room_receipt_events(room_id, receipt_type.clone(), thread, &event_id)
+                .await?;
+            receipts.extend(
+                event_receipts
+                    .into_iter()
+                    .map(|(user_id, receipt)| (user_id, event_id.clone(), receipt)),
+            );
+        }
+
+        Ok(receipts)
+    }
+
     async fn add_media_content(&self, request: &MediaRequest, data: Vec<u8>) -> Result<()> {
         let key = self
             .encode_key(keys::MEDIA, (request.source.unique_key(), request.format.unique_key()));
@@ -1275,6 +1457,45 @@ impl_state_store!({
     async fn get_joined_user_ids(&self, room_id: &RoomId) -> Result<Vec<OwnedUserId>> {
         self.get_user_ids(room_id, RoomMemberships::JOIN).await
     }
+
+    async fn try_take_leased_lock(
+        &self,
+        lease_duration_ms: u32,
+        key: &str,
+        holder: &str,
+    ) -> Result<bool> {
+        #[derive(Deserialize, Serialize)]
+        struct Lease {
+            holder: String,
+            expiration_ts: u64,
+        }
+
+        let key = self.encode_key(keys::KV, (keys::LEASE_LOCKS, key));
+        let now_ts: u64 = MilliSecondsSinceUnixEpoch::now().get().into();
+        let expiration_ts = now_ts + lease_duration_ms as u64;
+
+        let tx =
+            self.inner.transaction_on_one_with_mode(keys::KV, IdbTransactionMode::Readwrite)?;
+        let store = tx.object_store(keys::KV)?;
+
+        let acquired = match store.get(&key)?.await? {
+            Some(prev) => {
+                let lease: Lease = self.deserialize_event(&prev)?;
+                lease.holder == holder || lease.expiration_ts < now_ts
+            }
+            None => true,
+        };
+
+        if acquired {
+            store.put_key_val(
+                &key,
+                &self.serialize_event(&Lease { holder: holder.to_owned(), expiration_ts })?,
+            )?;
+        }
+
+        tx.await.into_result()?;
+        Ok(acquired)
+    }
 });
 
 /// A room member.
@@ -0,0 +1,288 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The key-value abstraction the stores of this crate are built upon.
+
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use matrix_sdk_common::AsyncTraitDeps;
+
+/// A small, ordered key-value store.
+///
+/// Data is organized in tables, identified by their name. Tables that were
+/// never written to must behave like empty tables. Keys are compared
+/// lexicographically, as byte strings.
+///
+/// Implementing this trait is enough to get a full [`StateStore`] and
+/// [`CryptoStore`] with [`KvStateStore`] and [`KvCryptoStore`].
+///
+/// [`StateStore`]: matrix_sdk_base::store::StateStore
+/// [`CryptoStore`]: https://docs.rs/matrix-sdk-crypto/latest/matrix_sdk_crypto/store/trait.CryptoStore.html
+/// [`KvStateStore`]: crate::KvStateStore
+/// [`KvCryptoStore`]: crate::KvCryptoStore
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait KvBackend: AsyncTraitDeps {
+    /// The error type used by this backend.
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    /// Get the value for the given key in the given table.
+    async fn get(&self, table: &str, key: &[u8]) -> Result<Option<Vec<u8>>, Self::Error>;
+
+    /// Get all the entries of the given table whose key is in the given range,
+    /// ordered by key.
+    async fn range(
+        &self,
+        table: &str,
+        range: &KeyRange,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Self::Error>;
+
+    /// Get the first `limit` entries of the given table whose key is in the
+    /// given range, ordered by key.
+    ///
+    /// The default implementation truncates the result of [`Self::range`],
+    /// backends should override it to stop reading once the limit is reached.
+    async fn range_with_limit(
+        &self,
+        table: &str,
+        range: &KeyRange,
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Self::Error> {
+        let mut entries = self.range(table, range).await?;
+        entries.truncate(limit);
+        Ok(entries)
+    }
+
+    /// Apply all the operations of the given transaction, in order.
+    ///
+    /// This must be atomic: either all the operations are applied, or none of
+    /// them is.
+    async fn transaction(&self, transaction: Transaction) -> Result<(), Self::Error>;
+
+    /// Set the value for the given key in the given table.
+    async fn put(&self, table: &str, key: Vec<u8>, value: Vec<u8>) -> Result<(), Self::Error> {
+        let mut transaction = Transaction::new();
+        transaction.put(table, key, value);
+        self.transaction(transaction).await
+    }
+
+    /// Remove the given key from the given table.
+    async fn delete(&self, table: &str, key: Vec<u8>) -> Result<(), Self::Error> {
+        let mut transaction = Transaction::new();
+        transaction.delete(table, key);
+        self.transaction(transaction).await
+    }
+}
+
+/// A range of keys, including `start` and excluding `end`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyRange {
+    /// The first key of the range.
+    pub start: Vec<u8>,
+    /// The end of the range, excluded. `None` means that the range is
+    /// unbounded.
+    pub end: Option<Vec<u8>>,
+}
+
+impl KeyRange {
+    /// A range containing all the keys.
+    pub fn all() -> Self {
+        Self { start: Vec::new(), end: None }
+    }
+
+    /// A range containing all the keys starting with the given prefix.
+    pub fn prefix(prefix: Vec<u8>) -> Self {
+        let mut end = prefix.clone();
+
+        // The end of the range is the smallest key greater than all the keys with
+        // that prefix, if there is one.
+        while let Some(last) = end.pop() {
+            if last < u8::MAX {
+                end.push(last + 1);
+                return Self { start: prefix, end: Some(end) };
+            }
+        }
+
+        Self { start: prefix, end: None }
+    }
+
+    /// Whether the given key is in this range.
+    pub fn contains(&self, key: &[u8]) -> bool {
+        key >= self.start.as_slice() && self.end.as_deref().map_or(true, |end| key < end)
+    }
+}
+
+/// An operation of a [`Transaction`].
+#[derive(Clone, Debug)]
+pub enum Operation {
+    /// Set the value for a key.
+    Put {
+        /// The name of the table.
+        table: String,
+        /// The key.
+        key: Vec<u8>,
+        /// The new value.
+        value: Vec<u8>,
+    },
+    /// Remove a key.
+    Delete {
+        /// The name of the table.
+        table: String,
+        /// The key.
+        key: Vec<u8>,
+    },
+    /// Remove all the keys in a range.
+    DeleteRange {
+        /// The name of the table.
+        table: String,
+        /// The range of keys to remove.
+        range: KeyRange,
+    },
+}
+
+/// A list of write operations, to be applied atomically by
+/// [`KvBackend::transaction`].
+#[derive(Clone, Debug, Default)]
+pub struct Transaction {
+    operations: Vec<Operation>,
+}
+
+impl Transaction {
+    /// Create a new empty transaction.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the value for the given key in the given table.
+    pub fn put(&mut self, table: &str, key: Vec<u8>, value: Vec<u8>) {
+        self.operations.push(Operation::Put { table: table.to_owned(), key, value });
+    }
+
+    /// Remove the given key from the given table.
+    pub fn delete(&mut self, table: &str, key: Vec<u8>) {
+        self.operations.push(Operation::Delete { table: table.to_owned(), key });
+    }
+
+    /// Remove all the keys in the given range from the given table.
+    pub fn delete_range(&mut self, table: &str, range: KeyRange) {
+        self.operations.push(Operation::DeleteRange { table: table.to_owned(), range });
+    }
+
+    /// Whether this transaction has no operations.
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+
+    /// The operations of this transaction, in the order they must be applied.
+    pub fn operations(&self) -> &[Operation] {
+        &self.operations
+    }
+
+    /// Consume this transaction to get its operations, in the order they must
+    /// be applied.
+    pub fn into_operations(self) -> Vec<Operation> {
+        self.operations
+    }
+
+    /// Get the value the given key will have after this transaction, if this
+    /// transaction touches it.
+    ///
+    /// Returns `Some(None)` if the key is removed by this transaction.
+    pub(crate) fn get(&self, table: &str, key: &[u8]) -> Option<Option<&[u8]>> {
+        self.operations.iter().rev().find_map(|operation| match operation {
+            Operation::Put { table: t, key: k, value } if t == table && k == key => {
+                Some(Some(value.as_slice()))
+            }
+            Operation::Delete { table: t, key: k } if t == table && k == key => Some(None),
+            Operation::DeleteRange { table: t, range } if t == table && range.contains(key) => {
+                Some(None)
+            }
+            _ => None,
+        })
+    }
+
+    /// Apply the operations of this transaction on the given table to the given
+    /// entries of the given range, to get the entries of that range after this
+    /// transaction.
+    pub(crate) fn apply_to_range(
+        &self,
+        table: &str,
+        range: &KeyRange,
+        entries: Vec<(Vec<u8>, Vec<u8>)>,
+    ) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut entries: BTreeMap<_, _> = entries.into_iter().collect();
+
+        for operation in &self.operations {
+            match operation {
+                Operation::Put { table: t, key, value } if t == table && range.contains(key) => {
+                    entries.insert(key.clone(), value.clone());
+                }
+                Operation::Delete { table: t, key } if t == table => {
+                    entries.remove(key);
+                }
+                Operation::DeleteRange { table: t, range } if t == table => {
+                    entries.retain(|key, _| !range.contains(key));
+                }
+                _ => {}
+            }
+        }
+
+        entries.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{KeyRange, Transaction};
+
+    #[test]
+    fn test_prefix_range() {
+        let range = KeyRange::prefix(vec![1, 2]);
+        assert_eq!(range.end, Some(vec![1, 3]));
+        assert!(range.contains(&[1, 2]));
+        assert!(range.contains(&[1, 2, 255, 0]));
+        assert!(!range.contains(&[1, 3]));
+        assert!(!range.contains(&[1]));
+
+        let range = KeyRange::prefix(vec![1, 255]);
+        assert_eq!(range.end, Some(vec![2]));
+        assert!(range.contains(&[1, 255, 255]));
+
+        let range = KeyRange::prefix(vec![255, 255]);
+        assert_eq!(range.end, None);
+        assert!(range.contains(&[255, 255, 1]));
+        assert!(!range.contains(&[255]));
+    }
+
+    #[test]
+    fn test_transaction_overlay() {
+        let mut transaction = Transaction::new();
+        transaction.put("table", vec![1, 1], vec![0]);
+        transaction.put("other", vec![1, 2], vec![0]);
+        transaction.delete_range("table", KeyRange::prefix(vec![1]));
+        transaction.put("table", vec![1, 3], vec![1]);
+
+        assert_eq!(transaction.get("table", &[1, 1]), Some(None));
+        assert_eq!(transaction.get("table", &[1, 3]), Some(Some([1].as_slice())));
+        assert_eq!(transaction.get("table", &[2]), None);
+
+        let entries = transaction.apply_to_range(
+            "table",
+            &KeyRange::all(),
+            vec![(vec![1, 0], vec![0]), (vec![2], vec![0])],
+        );
+        assert_eq!(entries, vec![(vec![1, 3], vec![1]), (vec![2], vec![0])]);
+    }
+}
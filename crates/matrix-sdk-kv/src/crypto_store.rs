@@ -0,0 +1,826 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt,
+    sync::{Arc, RwLock},
+};
+
+use async_trait::async_trait;
+use matrix_sdk_crypto::{
+    olm::{
+        IdentityKeys, InboundGroupSession, OlmMessageHash, OutboundGroupSession,
+        PickledInboundGroupSession, PickledSession, PrivateCrossSigningIdentity, Session,
+    },
+    store::{caches::SessionStore, BackupKeys, Changes, CryptoStore, RoomKeyCounts, RoomSettings},
+    types::events::room_key_withheld::RoomKeyWithheldEvent,
+    GossipRequest, GossippedSecret, ReadOnlyAccount, ReadOnlyDevice, ReadOnlyUserIdentities,
+    SecretInfo, TrackedUser,
+};
+use ruma::{
+    events::secret::request::SecretName, DeviceId, MilliSecondsSinceUnixEpoch, OwnedDeviceId,
+    OwnedTransactionId, OwnedUserId, RoomId, SecondsSinceUnixEpoch, TransactionId, UserId,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::{
+    backend::{KeyRange, KvBackend, Transaction},
+    encoding::Codec,
+    error::{Error, Result},
+    get_or_create_store_cipher, OpenStoreError,
+};
+
+mod keys {
+    // Tables
+    pub const CORE: &str = "crypto_core";
+    pub const SESSIONS: &str = "crypto_sessions";
+    pub const INBOUND_GROUP_SESSIONS: &str = "crypto_inbound_group_sessions";
+    pub const OUTBOUND_GROUP_SESSIONS: &str = "crypto_outbound_group_sessions";
+    pub const DEVICES: &str = "crypto_devices";
+    pub const IDENTITIES: &str = "crypto_identities";
+    pub const OLM_HASHES: &str = "crypto_olm_hashes";
+    pub const TRACKED_USERS: &str = "crypto_tracked_users";
+    pub const SECRET_REQUESTS: &str = "crypto_secret_requests";
+    pub const SECRET_REQUESTS_BY_INFO: &str = "crypto_secret_requests_by_info";
+    pub const DIRECT_WITHHELD_INFO: &str = "crypto_direct_withheld_info";
+    pub const ROOM_SETTINGS: &str = "crypto_room_settings";
+    pub const SECRETS_INBOX: &str = "crypto_secrets_inbox";
+    pub const CUSTOM: &str = "crypto_custom";
+    pub const LEASE_LOCKS: &str = "crypto_lease_locks";
+
+    // Keys of the core table
+    pub const ACCOUNT: &str = "account";
+    pub const PRIVATE_IDENTITY: &str = "private_identity";
+    pub const NEXT_BATCH_TOKEN: &str = "next_batch_token";
+    pub const BACKUP_VERSION: &str = "backup_version_v1";
+    pub const RECOVERY_KEY: &str = "recovery_key_v1";
+
+    // Keys
+    pub const STORE_CIPHER: &str = "crypto_store_cipher";
+}
+
+#[derive(Clone, Debug)]
+pub struct AccountInfo {
+    user_id: OwnedUserId,
+    device_id: OwnedDeviceId,
+    identity_keys: Arc<IdentityKeys>,
+}
+
+/// A crypto store on top of a [`KvBackend`].
+///
+/// Like for the [`KvStateStore`](crate::KvStateStore), taking a leased lock is
+/// only atomic between the handles of this store living in the same process,
+/// unless the backend makes it atomic across processes.
+#[derive(Clone)]
+pub struct KvCryptoStore<B> {
+    backend: B,
+    codec: Codec,
+    /// Serializes the operations that need to read the store before writing
+    /// to it.
+    write_lock: Arc<Mutex<()>>,
+
+    // DB values cached in memory
+    account_info: Arc<RwLock<Option<AccountInfo>>>,
+    session_cache: SessionStore,
+}
+
+impl<B: fmt::Debug> fmt::Debug for KvCryptoStore<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KvCryptoStore").field("backend", &self.backend).finish()
+    }
+}
+
+impl<B: KvBackend> KvCryptoStore<B> {
+    /// Open a crypto store on top of the given backend, using the given
+    /// passphrase to encrypt private data.
+    pub async fn open(backend: B, passphrase: Option<&str>) -> Result<Self, OpenStoreError> {
+        let store_cipher = match passphrase {
+            Some(p) => {
+                Some(Arc::new(get_or_create_store_cipher(p, &backend, keys::STORE_CIPHER).await?))
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            backend,
+            codec: Codec::new(store_cipher),
+            write_lock: Default::default(),
+            account_info: Arc::new(RwLock::new(None)),
+            session_cache: SessionStore::new(),
+        })
+    }
+
+    fn encode_key(&self, table_name: &str, parts: &[&[u8]]) -> Vec<u8> {
+        self.codec.encode_key(table_name, parts)
+    }
+
+    fn encode_secret_request_key(&self, request_id: &TransactionId) -> Vec<u8> {
+        self.encode_key(keys::SECRET_REQUESTS, &[request_id.as_bytes()])
+    }
+
+    fn encode_secret_info_key(&self, info: &SecretInfo) -> Vec<u8> {
+        self.encode_key(keys::SECRET_REQUESTS_BY_INFO, &[info.as_key().as_bytes()])
+    }
+
+    fn get_account_info(&self) -> Option<AccountInfo> {
+        self.account_info.read().unwrap().clone()
+    }
+
+    fn set_account_info(&self, account: &ReadOnlyAccount) {
+        let account_info = AccountInfo {
+            user_id: account.user_id.clone(),
+            device_id: account.device_id.clone(),
+            identity_keys: account.identity_keys.clone(),
+        };
+
+        *self.account_info.write().unwrap() = Some(account_info);
+    }
+
+    async fn get(&self, table_name: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.backend.get(table_name, key).await.map_err(Error::backend)
+    }
+
+    async fn get_core_value<T: serde::de::DeserializeOwned>(&self, key: &str) -> Result<Option<T>> {
+        let key = self.encode_key(keys::CORE, &[key.as_bytes()]);
+        self.get(keys::CORE, &key)
+            .await?
+            .map(|value| self.codec.deserialize_value(&value))
+            .transpose()
+    }
+
+    async fn range(&self, table_name: &str, range: &KeyRange) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.backend.range(table_name, range).await.map_err(Error::backend)
+    }
+
+    async fn get_all<T: serde::de::DeserializeOwned>(&self, table_name: &str) -> Result<Vec<T>> {
+        self.range(table_name, &KeyRange::all())
+            .await?
+            .iter()
+            .map(|(_, value)| self.codec.deserialize_value(value))
+            .collect()
+    }
+
+    async fn commit(&self, transaction: Transaction) -> Result<()> {
+        if transaction.is_empty() {
+            return Ok(());
+        }
+
+        self.backend.transaction(transaction).await.map_err(Error::backend)
+    }
+
+    async fn get_inbound_group_session_pickles(&self) -> Result<Vec<PickledInboundGroupSession>> {
+        self.get_all(keys::INBOUND_GROUP_SESSIONS).await
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl<B: KvBackend> CryptoStore for KvCryptoStore<B> {
+    type Error = Error;
+
+    async fn load_account(&self) -> Result<Option<ReadOnlyAccount>> {
+        let Some(pickle) = self.get_core_value(keys::ACCOUNT).await? else {
+            return Ok(None);
+        };
+
+        let account = ReadOnlyAccount::from_pickle(pickle).map_err(|_| Error::Unpickle)?;
+        self.set_account_info(&account);
+
+        Ok(Some(account))
+    }
+
+    async fn save_account(&self, account: ReadOnlyAccount) -> Result<()> {
+        self.save_changes(Changes { account: Some(account), ..Default::default() }).await
+    }
+
+    async fn load_identity(&self) -> Result<Option<PrivateCrossSigningIdentity>> {
+        let Some(pickle) = self.get_core_value(keys::PRIVATE_IDENTITY).await? else {
+            return Ok(None);
+        };
+
+        Ok(Some(
+            PrivateCrossSigningIdentity::from_pickle(pickle).await.map_err(|_| Error::Unpickle)?,
+        ))
+    }
+
+    async fn save_changes(&self, changes: Changes) -> Result<()> {
+        let _guard = self.write_lock.lock().await;
+        let mut txn = Transaction::new();
+
+        let core_key = |key: &str| self.encode_key(keys::CORE, &[key.as_bytes()]);
+
+        if let Some(account) = &changes.account {
+            self.set_account_info(account);
+            let pickle = account.pickle().await;
+            txn.put(keys::CORE, core_key(keys::ACCOUNT), self.codec.serialize_value(&pickle)?);
+        }
+
+        if let Some(identity) = &changes.private_identity {
+            let pickle = identity.pickle().await;
+            txn.put(
+                keys::CORE,
+                core_key(keys::PRIVATE_IDENTITY),
+                self.codec.serialize_value(&pickle)?,
+            );
+        }
+
+        if let Some(token) = &changes.next_batch_token {
+            txn.put(
+                keys::CORE,
+                core_key(keys::NEXT_BATCH_TOKEN),
+                self.codec.serialize_value(token)?,
+            );
+        }
+
+        if let Some(decryption_key) = &changes.backup_decryption_key {
+            txn.put(
+                keys::CORE,
+                core_key(keys::RECOVERY_KEY),
+                self.codec.serialize_value(decryption_key)?,
+            );
+        }
+
+        if let Some(backup_version) = &changes.backup_version {
+            txn.put(
+                keys::CORE,
+                core_key(keys::BACKUP_VERSION),
+                self.codec.serialize_value(backup_version)?,
+            );
+        }
+
+        for session in &changes.sessions {
+            let sender_key = session.sender_key().to_base64();
+            let key = self.encode_key(
+                keys::SESSIONS,
+                &[sender_key.as_bytes(), session.session_id().as_bytes()],
+            );
+            let pickle = session.pickle().await;
+            txn.put(keys::SESSIONS, key, self.codec.serialize_value(&pickle)?);
+        }
+
+        for session in &changes.inbound_group_sessions {
+            let key = self.encode_key(
+                keys::INBOUND_GROUP_SESSIONS,
+                &[session.room_id().as_bytes(), session.session_id().as_bytes()],
+            );
+            let pickle = session.pickle().await;
+            txn.put(keys::INBOUND_GROUP_SESSIONS, key, self.codec.serialize_value(&pickle)?);
+        }
+
+        for session in &changes.outbound_group_sessions {
+            let key =
+                self.encode_key(keys::OUTBOUND_GROUP_SESSIONS, &[session.room_id().as_bytes()]);
+            let pickle = session.pickle().await;
+            txn.put(keys::OUTBOUND_GROUP_SESSIONS, key, self.codec.serialize_json(&pickle)?);
+        }
+
+        for device in changes.devices.new.iter().chain(&changes.devices.changed) {
+            let key = self.encode_key(
+                keys::DEVICES,
+                &[device.user_id().as_bytes(), device.device_id().as_bytes()],
+            );
+            txn.put(keys::DEVICES, key, self.codec.serialize_value(device)?);
+        }
+
+        for device in &changes.devices.deleted {
+            let key = self.encode_key(
+                keys::DEVICES,
+                &[device.user_id().as_bytes(), device.device_id().as_bytes()],
+            );
+            txn.delete(keys::DEVICES, key);
+        }
+
+        for identity in changes.identities.changed.iter().chain(&changes.identities.new) {
+            let key = self.encode_key(keys::IDENTITIES, &[identity.user_id().as_bytes()]);
+            txn.put(keys::IDENTITIES, key, self.codec.serialize_value(identity)?);
+        }
+
+        let now = u64::from(SecondsSinceUnixEpoch::now().get()).to_be_bytes();
+
+        for hash in &changes.message_hashes {
+            let key = self
+                .encode_key(keys::OLM_HASHES, &[hash.sender_key.as_bytes(), hash.hash.as_bytes()]);
+            txn.put(keys::OLM_HASHES, key, now.to_vec());
+        }
+
+        for request in &changes.key_requests {
+            txn.put(
+                keys::SECRET_REQUESTS_BY_INFO,
+                self.encode_secret_info_key(&request.info),
+                self.codec.serialize_value(&request.request_id)?,
+            );
+            txn.put(
+                keys::SECRET_REQUESTS,
+                self.encode_secret_request_key(&request.request_id),
+                self.codec.serialize_value(request)?,
+            );
+        }
+
+        for (room_id, data) in &changes.withheld_session_info {
+            for (session_id, event) in data {
+                let key = self.encode_key(
+                    keys::DIRECT_WITHHELD_INFO,
+                    &[room_id.as_bytes(), session_id.as_bytes()],
+                );
+                txn.put(keys::DIRECT_WITHHELD_INFO, key, self.codec.serialize_json(event)?);
+            }
+        }
+
+        for (room_id, settings) in &changes.room_settings {
+            let key = self.encode_key(keys::ROOM_SETTINGS, &[room_id.as_bytes()]);
+            txn.put(keys::ROOM_SETTINGS, key, self.codec.serialize_value(settings)?);
+        }
+
+        for secret in &changes.secrets {
+            let key = self.encode_key(
+                keys::SECRETS_INBOX,
+                &[
+                    secret.secret_name.as_str().as_bytes(),
+                    secret.event.content.request_id.as_bytes(),
+                ],
+            );
+            txn.put(keys::SECRETS_INBOX, key, self.codec.serialize_json(secret)?);
+        }
+
+        self.commit(txn).await?;
+
+        // All good, let's update our caches.
+        for session in changes.sessions {
+            self.session_cache.add(session).await;
+        }
+
+        Ok(())
+    }
+
+    async fn get_sessions(&self, sender_key: &str) -> Result<Option<Arc<Mutex<Vec<Session>>>>> {
+        let account_info = self.get_account_info().ok_or(Error::AccountUnset)?;
+
+        if self.session_cache.get(sender_key).is_none() {
+            let range = self.codec.encode_prefix(keys::SESSIONS, &[sender_key.as_bytes()]);
+            let sessions = self
+                .range(keys::SESSIONS, &range)
+                .await?
+                .iter()
+                .map(|(_, value)| {
+                    let pickle = self.codec.deserialize_value(value)?;
+                    Ok(Session::from_pickle(
+                        account_info.user_id.clone(),
+                        account_info.device_id.clone(),
+                        account_info.identity_keys.clone(),
+                        pickle,
+                    ))
+                })
+                .collect::<Result<_>>()?;
+
+            self.session_cache.set_for_sender(sender_key, sessions);
+        }
+
+        Ok(self.session_cache.get(sender_key))
+    }
+
+    async fn get_session_sender_keys(&self) -> Result<Vec<String>> {
+        let pickles: Vec<PickledSession> = self.get_all(keys::SESSIONS).await?;
+        let sender_keys: BTreeSet<_> =
+            pickles.into_iter().map(|pickle| pickle.sender_key.to_base64()).collect();
+
+        Ok(sender_keys.into_iter().collect())
+    }
+
+    async fn delete_sessions(&self, sender_key: &str) -> Result<()> {
+        let mut txn = Transaction::new();
+        txn.delete_range(
+            keys::SESSIONS,
+            self.codec.encode_prefix(keys::SESSIONS, &[sender_key.as_bytes()]),
+        );
+        self.commit(txn).await?;
+        self.session_cache.remove_for_sender(sender_key);
+
+        Ok(())
+    }
+
+    async fn get_inbound_group_session(
+        &self,
+        room_id: &RoomId,
+        session_id: &str,
+    ) -> Result<Option<InboundGroupSession>> {
+        let key = self
+            .encode_key(keys::INBOUND_GROUP_SESSIONS, &[room_id.as_bytes(), session_id.as_bytes()]);
+
+        self.get(keys::INBOUND_GROUP_SESSIONS, &key)
+            .await?
+            .map(|value| {
+                let pickle = self.codec.deserialize_value(&value)?;
+                InboundGroupSession::from_pickle(pickle).map_err(|_| Error::Unpickle)
+            })
+            .transpose()
+    }
+
+    async fn get_withheld_info(
+        &self,
+        room_id: &RoomId,
+        session_id: &str,
+    ) -> Result<Option<RoomKeyWithheldEvent>> {
+        let key = self
+            .encode_key(keys::DIRECT_WITHHELD_INFO, &[room_id.as_bytes(), session_id.as_bytes()]);
+
+        self.get(keys::DIRECT_WITHHELD_INFO, &key)
+            .await?
+            .map(|value| self.codec.deserialize_json(&value))
+            .transpose()
+    }
+
+    async fn get_inbound_group_sessions(&self) -> Result<Vec<InboundGroupSession>> {
+        self.get_inbound_group_session_pickles()
+            .await?
+            .into_iter()
+            .map(|pickle| InboundGroupSession::from_pickle(pickle).map_err(|_| Error::Unpickle))
+            .collect()
+    }
+
+    async fn get_inbound_group_sessions_batch(
+        &self,
+        after: Option<(&RoomId, &str)>,
+        limit: usize,
+    ) -> Result<Vec<InboundGroupSession>> {
+        let range = match after {
+            Some((room_id, session_id)) => {
+                let mut start = self.encode_key(
+                    keys::INBOUND_GROUP_SESSIONS,
+                    &[room_id.as_bytes(), session_id.as_bytes()],
+                );
+                // The smallest key that comes after the key of the last session.
+                start.push(0);
+                KeyRange { start, end: None }
+            }
+            None => KeyRange::all(),
+        };
+
+        self.backend
+            .range_with_limit(keys::INBOUND_GROUP_SESSIONS, &range, limit)
+            .await
+            .map_err(Error::backend)?
+            .iter()
+            .map(|(_, value)| {
+                let pickle = self.codec.deserialize_value(value)?;
+                InboundGroupSession::from_pickle(pickle).map_err(|_| Error::Unpickle)
+            })
+            .collect()
+    }
+
+    async fn delete_inbound_group_sessions(
+        &self,
+        room_id: &RoomId,
+        session_ids: &[String],
+    ) -> Result<()> {
+        let mut txn = Transaction::new();
+
+        for session_id in session_ids {
+            let key = self.encode_key(
+                keys::INBOUND_GROUP_SESSIONS,
+                &[room_id.as_bytes(), session_id.as_bytes()],
+            );
+            txn.delete(keys::INBOUND_GROUP_SESSIONS, key);
+        }
+
+        self.commit(txn).await
+    }
+
+    async fn inbound_group_session_counts(&self) -> Result<RoomKeyCounts> {
+        let pickles = self.get_inbound_group_session_pickles().await?;
+        let backed_up = pickles.iter().filter(|pickle| pickle.backed_up).count();
+
+        Ok(RoomKeyCounts { total: pickles.len(), backed_up })
+    }
+
+    async fn inbound_group_sessions_for_backup(
+        &self,
+        limit: usize,
+    ) -> Result<Vec<InboundGroupSession>> {
+        self.get_inbound_group_session_pickles()
+            .await?
+            .into_iter()
+            .filter(|pickle| !pickle.backed_up)
+            .take(limit)
+            .map(|pickle| InboundGroupSession::from_pickle(pickle).map_err(|_| Error::Unpickle))
+            .collect()
+    }
+
+    async fn reset_backup_state(&self) -> Result<()> {
+        let _guard = self.write_lock.lock().await;
+        let mut txn = Transaction::new();
+
+        for (key, value) in self.range(keys::INBOUND_GROUP_SESSIONS, &KeyRange::all()).await? {
+            let mut pickle: PickledInboundGroupSession = self.codec.deserialize_value(&value)?;
+
+            if pickle.backed_up {
+                pickle.backed_up = false;
+                txn.put(keys::INBOUND_GROUP_SESSIONS, key, self.codec.serialize_value(&pickle)?);
+            }
+        }
+
+        self.commit(txn).await
+    }
+
+    async fn load_backup_keys(&self) -> Result<BackupKeys> {
+        let backup_version = self.get_core_value(keys::BACKUP_VERSION).await?;
+        let decryption_key = self.get_core_value(keys::RECOVERY_KEY).await?;
+
+        Ok(BackupKeys { backup_version, decryption_key })
+    }
+
+    async fn get_outbound_group_session(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Option<OutboundGroupSession>> {
+        let key = self.encode_key(keys::OUTBOUND_GROUP_SESSIONS, &[room_id.as_bytes()]);
+        let Some(value) = self.get(keys::OUTBOUND_GROUP_SESSIONS, &key).await? else {
+            return Ok(None);
+        };
+
+        let account_info = self.get_account_info().ok_or(Error::AccountUnset)?;
+
+        let pickle = self.codec.deserialize_json(&value)?;
+        let session = OutboundGroupSession::from_pickle(
+            account_info.device_id,
+            account_info.identity_keys,
+            pickle,
+        )
+        .map_err(|_| Error::Unpickle)?;
+
+        Ok(Some(session))
+    }
+
+    async fn load_tracked_users(&self) -> Result<Vec<TrackedUser>> {
+        self.get_all(keys::TRACKED_USERS).await
+    }
+
+    async fn save_tracked_users(&self, tracked_users: &[(&UserId, bool)]) -> Result<()> {
+        let mut txn = Transaction::new();
+
+        for (user_id, dirty) in tracked_users {
+            let key = self.encode_key(keys::TRACKED_USERS, &[user_id.as_bytes()]);
+            let value = self
+                .codec
+                .serialize_value(&TrackedUser { user_id: (*user_id).into(), dirty: *dirty })?;
+            txn.put(keys::TRACKED_USERS, key, value);
+        }
+
+        self.commit(txn).await
+    }
+
+    async fn get_device(
+        &self,
+        user_id: &UserId,
+        device_id: &DeviceId,
+    ) -> Result<Option<ReadOnlyDevice>> {
+        let key = self.encode_key(keys::DEVICES, &[user_id.as_bytes(), device_id.as_bytes()]);
+        self.get(keys::DEVICES, &key)
+            .await?
+            .map(|value| self.codec.deserialize_value(&value))
+            .transpose()
+    }
+
+    async fn get_user_devices(
+        &self,
+        user_id: &UserId,
+    ) -> Result<HashMap<OwnedDeviceId, ReadOnlyDevice>> {
+        let range = self.codec.encode_prefix(keys::DEVICES, &[user_id.as_bytes()]);
+        self.range(keys::DEVICES, &range)
+            .await?
+            .iter()
+            .map(|(_, value)| {
+                let device: ReadOnlyDevice = self.codec.deserialize_value(value)?;
+                Ok((device.device_id().to_owned(), device))
+            })
+            .collect()
+    }
+
+    async fn get_user_identity(&self, user_id: &UserId) -> Result<Option<ReadOnlyUserIdentities>> {
+        let key = self.encode_key(keys::IDENTITIES, &[user_id.as_bytes()]);
+        self.get(keys::IDENTITIES, &key)
+            .await?
+            .map(|value| self.codec.deserialize_value(&value))
+            .transpose()
+    }
+
+    async fn is_message_known(&self, message_hash: &OlmMessageHash) -> Result<bool> {
+        let key = self.encode_key(
+            keys::OLM_HASHES,
+            &[message_hash.sender_key.as_bytes(), message_hash.hash.as_bytes()],
+        );
+        Ok(self.get(keys::OLM_HASHES, &key).await?.is_some())
+    }
+
+    async fn clear_message_hashes(&self, older_than: SecondsSinceUnixEpoch) -> Result<usize> {
+        let _guard = self.write_lock.lock().await;
+
+        let older_than = u64::from(older_than.get());
+        let mut txn = Transaction::new();
+        let mut count = 0;
+
+        for (key, value) in self.range(keys::OLM_HASHES, &KeyRange::all()).await? {
+            // The value holds the time at which the hash was stored.
+            let added_at = value.try_into().map(u64::from_be_bytes).unwrap_or_default();
+
+            if added_at < older_than {
+                txn.delete(keys::OLM_HASHES, key);
+                count += 1;
+            }
+        }
+
+        self.commit(txn).await?;
+
+        Ok(count)
+    }
+
+    async fn get_outgoing_secret_requests(
+        &self,
+        request_id: &TransactionId,
+    ) -> Result<Option<GossipRequest>> {
+        let key = self.encode_secret_request_key(request_id);
+        self.get(keys::SECRET_REQUESTS, &key)
+            .await?
+            .map(|value| self.codec.deserialize_value(&value))
+            .transpose()
+    }
+
+    async fn get_secret_request_by_info(
+        &self,
+        key_info: &SecretInfo,
+    ) -> Result<Option<GossipRequest>> {
+        let key = self.encode_secret_info_key(key_info);
+        let Some(value) = self.get(keys::SECRET_REQUESTS_BY_INFO, &key).await? else {
+            return Ok(None);
+        };
+
+        let request_id: OwnedTransactionId = self.codec.deserialize_value(&value)?;
+        self.get_outgoing_secret_requests(&request_id).await
+    }
+
+    async fn get_unsent_secret_requests(&self) -> Result<Vec<GossipRequest>> {
+        Ok(self
+            .get_all_secret_requests()
+            .await?
+            .into_iter()
+            .filter(|request| !request.sent_out)
+            .collect())
+    }
+
+    async fn get_all_secret_requests(&self) -> Result<Vec<GossipRequest>> {
+        self.get_all(keys::SECRET_REQUESTS).await
+    }
+
+    async fn delete_outgoing_secret_requests(&self, request_id: &TransactionId) -> Result<()> {
+        let _guard = self.write_lock.lock().await;
+        let mut txn = Transaction::new();
+
+        if let Some(request) = self.get_outgoing_secret_requests(request_id).await? {
+            txn.delete(keys::SECRET_REQUESTS_BY_INFO, self.encode_secret_info_key(&request.info));
+        }
+        txn.delete(keys::SECRET_REQUESTS, self.encode_secret_request_key(request_id));
+
+        self.commit(txn).await
+    }
+
+    async fn get_secrets_from_inbox(
+        &self,
+        secret_name: &SecretName,
+    ) -> Result<Vec<GossippedSecret>> {
+        let range =
+            self.codec.encode_prefix(keys::SECRETS_INBOX, &[secret_name.as_str().as_bytes()]);
+
+        self.range(keys::SECRETS_INBOX, &range)
+            .await?
+            .iter()
+            .map(|(_, value)| self.codec.deserialize_json(value))
+            .collect()
+    }
+
+    async fn delete_secrets_from_inbox(&self, secret_name: &SecretName) -> Result<()> {
+        let mut txn = Transaction::new();
+        txn.delete_range(
+            keys::SECRETS_INBOX,
+            self.codec.encode_prefix(keys::SECRETS_INBOX, &[secret_name.as_str().as_bytes()]),
+        );
+        self.commit(txn).await
+    }
+
+    async fn get_room_settings(&self, room_id: &RoomId) -> Result<Option<RoomSettings>> {
+        let key = self.encode_key(keys::ROOM_SETTINGS, &[room_id.as_bytes()]);
+        self.get(keys::ROOM_SETTINGS, &key)
+            .await?
+            .map(|value| self.codec.deserialize_value(&value))
+            .transpose()
+    }
+
+    async fn get_custom_value(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let key = self.encode_key(keys::CUSTOM, &[key.as_bytes()]);
+        self.get(keys::CUSTOM, &key)
+            .await?
+            .map(|value| Ok(self.codec.decode_value(&value)?.into_owned()))
+            .transpose()
+    }
+
+    async fn set_custom_value(&self, key: &str, value: Vec<u8>) -> Result<()> {
+        let key = self.encode_key(keys::CUSTOM, &[key.as_bytes()]);
+        let value = self.codec.encode_value(value)?;
+        self.backend.put(keys::CUSTOM, key, value).await.map_err(Error::backend)
+    }
+
+    async fn try_take_leased_lock(
+        &self,
+        lease_duration_ms: u32,
+        key: &str,
+        holder: &str,
+    ) -> Result<bool> {
+        let _guard = self.write_lock.lock().await;
+
+        let key = self.encode_key(keys::LEASE_LOCKS, &[key.as_bytes()]);
+        let now_ts: u64 = MilliSecondsSinceUnixEpoch::now().get().into();
+
+        if let Some(value) = self.get(keys::LEASE_LOCKS, &key).await? {
+            let lease: Lease = self.codec.deserialize_value(&value)?;
+            if lease.holder != holder && lease.expiration_ts >= now_ts {
+                return Ok(false);
+            }
+        }
+
+        let lease =
+            Lease { holder: holder.to_owned(), expiration_ts: now_ts + lease_duration_ms as u64 };
+        let value = self.codec.serialize_value(&lease)?;
+        self.backend.put(keys::LEASE_LOCKS, key, value).await.map_err(Error::backend)?;
+
+        Ok(true)
+    }
+
+    async fn next_batch_token(&self) -> Result<Option<String>> {
+        self.get_core_value(keys::NEXT_BATCH_TOKEN).await
+    }
+}
+
+/// A lease on a lock, see [`CryptoStore::try_take_leased_lock`].
+#[derive(Debug, Serialize, Deserialize)]
+struct Lease {
+    holder: String,
+    expiration_ts: u64,
+}
+
+#[cfg(all(test, feature = "redb"))]
+mod tests {
+    use matrix_sdk_crypto::{cryptostore_integration_tests, cryptostore_integration_tests_time};
+    use once_cell::sync::Lazy;
+    use tempfile::{tempdir, TempDir};
+
+    use super::KvCryptoStore;
+    use crate::RedbBackend;
+
+    static TMP_DIR: Lazy<TempDir> = Lazy::new(|| tempdir().unwrap());
+
+    async fn get_store(name: &str, passphrase: Option<&str>) -> KvCryptoStore<RedbBackend> {
+        let backend = RedbBackend::open(TMP_DIR.path().join(format!("{name}.redb")))
+            .expect("Can't open the redb database");
+
+        KvCryptoStore::open(backend, passphrase).await.expect("Can't create the store")
+    }
+
+    cryptostore_integration_tests!();
+    cryptostore_integration_tests_time!();
+}
+
+#[cfg(all(test, feature = "redb"))]
+mod encrypted_tests {
+    use matrix_sdk_crypto::{cryptostore_integration_tests, cryptostore_integration_tests_time};
+    use once_cell::sync::Lazy;
+    use tempfile::{tempdir, TempDir};
+
+    use super::KvCryptoStore;
+    use crate::RedbBackend;
+
+    static TMP_DIR: Lazy<TempDir> = Lazy::new(|| tempdir().unwrap());
+
+    async fn get_store(name: &str, passphrase: Option<&str>) -> KvCryptoStore<RedbBackend> {
+        let backend = RedbBackend::open(TMP_DIR.path().join(format!("{name}.redb")))
+            .expect("Can't open the redb database");
+        let pass = passphrase.unwrap_or("default_test_password");
+
+        KvCryptoStore::open(backend, Some(pass))
+            .await
+            .expect("Can't create a passphrase protected store")
+    }
+
+    cryptostore_integration_tests!();
+    cryptostore_integration_tests_time!();
+}
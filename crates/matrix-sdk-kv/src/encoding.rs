@@ -0,0 +1,128 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Encoding of the keys and values of the stores.
+//!
+//! Keys are made of several parts, for example a room ID and a user ID. Each
+//! part is written with its length as a prefix, so all the keys sharing their
+//! first parts can be found with a prefix scan. If the store is encrypted, each
+//! part is hashed with the [`StoreCipher`] before being written, so scans keep
+//! working without leaking the content of the keys.
+
+use std::{borrow::Cow, sync::Arc};
+
+use matrix_sdk_store_encryption::StoreCipher;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{backend::KeyRange, error::Result};
+
+/// Encodes and, if needed, encrypts the keys and values of a store.
+#[derive(Clone)]
+pub(crate) struct Codec {
+    store_cipher: Option<Arc<StoreCipher>>,
+}
+
+impl Codec {
+    pub(crate) fn new(store_cipher: Option<Arc<StoreCipher>>) -> Self {
+        Self { store_cipher }
+    }
+
+    /// Encode the given parts into a key of the given table.
+    pub(crate) fn encode_key(&self, table_name: &str, parts: &[&[u8]]) -> Vec<u8> {
+        let mut key = Vec::new();
+
+        for part in parts {
+            let part = self.encode_part(table_name, part);
+            let len = u32::try_from(part.len()).expect("key parts should be smaller than 4 GiB");
+            key.extend_from_slice(&len.to_be_bytes());
+            key.extend_from_slice(&part);
+        }
+
+        key
+    }
+
+    /// The range of all the keys of the given table starting with the given
+    /// parts.
+    pub(crate) fn encode_prefix(&self, table_name: &str, parts: &[&[u8]]) -> KeyRange {
+        KeyRange::prefix(self.encode_key(table_name, parts))
+    }
+
+    fn encode_part<'a>(&self, table_name: &str, part: &'a [u8]) -> Cow<'a, [u8]> {
+        if let Some(store_cipher) = &self.store_cipher {
+            Cow::Owned(store_cipher.hash_key(table_name, part).to_vec())
+        } else {
+            Cow::Borrowed(part)
+        }
+    }
+
+    pub(crate) fn encode_value(&self, value: Vec<u8>) -> Result<Vec<u8>> {
+        if let Some(key) = &self.store_cipher {
+            let encrypted = key.encrypt_value_data(value)?;
+            Ok(rmp_serde::to_vec_named(&encrypted)?)
+        } else {
+            Ok(value)
+        }
+    }
+
+    pub(crate) fn decode_value<'a>(&self, value: &'a [u8]) -> Result<Cow<'a, [u8]>> {
+        if let Some(key) = &self.store_cipher {
+            let encrypted = rmp_serde::from_slice(value)?;
+            let decrypted = key.decrypt_value_data(encrypted)?;
+            Ok(Cow::Owned(decrypted))
+        } else {
+            Ok(Cow::Borrowed(value))
+        }
+    }
+
+    pub(crate) fn serialize_json(&self, value: &impl Serialize) -> Result<Vec<u8>> {
+        let serialized = serde_json::to_vec(value)?;
+        self.encode_value(serialized)
+    }
+
+    pub(crate) fn deserialize_json<T: DeserializeOwned>(&self, data: &[u8]) -> Result<T> {
+        let decoded = self.decode_value(data)?;
+        Ok(serde_json::from_slice(&decoded)?)
+    }
+
+    pub(crate) fn serialize_value(&self, value: &impl Serialize) -> Result<Vec<u8>> {
+        let serialized = rmp_serde::to_vec_named(value)?;
+        self.encode_value(serialized)
+    }
+
+    pub(crate) fn deserialize_value<T: DeserializeOwned>(&self, value: &[u8]) -> Result<T> {
+        let decoded = self.decode_value(value)?;
+        Ok(rmp_serde::from_slice(&decoded)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use matrix_sdk_store_encryption::StoreCipher;
+
+    use super::Codec;
+
+    #[test]
+    fn test_prefix_matches_longer_keys_only() {
+        for codec in [Codec::new(None), Codec::new(Some(Arc::new(StoreCipher::new().unwrap())))] {
+            let prefix = codec.encode_prefix("table", &[b"!room:localhost"]);
+
+            assert!(prefix.contains(&codec.encode_key("table", &[b"!room:localhost", b"a"])));
+            assert!(prefix.contains(&codec.encode_key("table", &[b"!room:localhost", b""])));
+            assert!(!prefix.contains(&codec.encode_key("table", &[b"!room:localhost2", b"a"])));
+            assert!(!prefix.contains(&codec.encode_key("table", &[b"!room:local", b"a"])));
+        }
+    }
+}
@@ -0,0 +1,119 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Storage backends for the Matrix SDK on top of any ordered key-value store.
+//!
+//! Implementing the small [`KvBackend`] trait is enough to get a
+//! [`KvStateStore`] and a [`KvCryptoStore`], which can be used in
+//! environments where neither SQLite nor IndexedDB are available. Both stores
+//! can share the same backend, and can encrypt their data with a passphrase.
+//!
+//! With the `redb` feature, which is enabled by default, the crate provides
+//! [`RedbBackend`], a backend using the [redb] embedded database, written in
+//! pure Rust.
+//!
+//! [redb]: https://www.redb.org
+#![cfg_attr(
+    not(any(feature = "state-store", feature = "crypto-store")),
+    allow(dead_code, unused_imports)
+)]
+
+#[cfg(feature = "state-store")]
+use matrix_sdk_base::store::StoreConfig;
+use matrix_sdk_store_encryption::StoreCipher;
+
+mod backend;
+#[cfg(feature = "crypto-store")]
+mod crypto_store;
+mod encoding;
+mod error;
+#[cfg(feature = "redb")]
+mod redb;
+#[cfg(feature = "state-store")]
+mod state_store;
+
+#[cfg(feature = "crypto-store")]
+pub use self::crypto_store::KvCryptoStore;
+#[cfg(feature = "redb")]
+pub use self::redb::RedbBackend;
+#[cfg(feature = "state-store")]
+pub use self::state_store::KvStateStore;
+pub use self::{
+    backend::{KeyRange, KvBackend, Operation, Transaction},
+    error::OpenStoreError,
+};
+
+/// The table holding the metadata of the stores, like their ciphers.
+const META_TABLE: &str = "meta";
+
+async fn get_or_create_store_cipher<B: KvBackend>(
+    passphrase: &str,
+    backend: &B,
+    key: &str,
+) -> Result<StoreCipher, OpenStoreError> {
+    let encrypted_cipher = backend
+        .get(META_TABLE, key.as_bytes())
+        .await
+        .map_err(|e| OpenStoreError::LoadCipher(Box::new(e)))?;
+
+    let cipher = if let Some(encrypted) = encrypted_cipher {
+        StoreCipher::import(passphrase, &encrypted)?
+    } else {
+        let cipher = StoreCipher::new()?;
+        let export = export_store_cipher(&cipher, passphrase)?;
+        backend
+            .put(META_TABLE, key.as_bytes().to_vec(), export)
+            .await
+            .map_err(|e| OpenStoreError::SaveCipher(Box::new(e)))?;
+        cipher
+    };
+
+    Ok(cipher)
+}
+
+/// Export the given store cipher, encrypted with the given passphrase, to
+/// persist it in the backend.
+fn export_store_cipher(
+    cipher: &StoreCipher,
+    passphrase: &str,
+) -> Result<Vec<u8>, matrix_sdk_store_encryption::Error> {
+    if cfg!(test) {
+        cipher._insecure_export_fast_for_testing(passphrase)
+    } else {
+        cipher.export(passphrase)
+    }
+}
+
+/// Create a [`StoreConfig`] with a [`KvStateStore`] on top of the given
+/// backend, using the given passphrase. If the `crypto-store` feature is
+/// enabled, a [`KvCryptoStore`] sharing the same backend is also created.
+#[cfg(feature = "state-store")]
+pub async fn make_store_config<B: KvBackend + Clone>(
+    backend: B,
+    passphrase: Option<&str>,
+) -> Result<StoreConfig, OpenStoreError> {
+    let state_store = KvStateStore::open(backend.clone(), passphrase).await?;
+    let config = StoreConfig::new().state_store(state_store);
+
+    #[cfg(feature = "crypto-store")]
+    {
+        let crypto_store = KvCryptoStore::open(backend, passphrase).await?;
+        Ok(config.crypto_store(crypto_store))
+    }
+
+    #[cfg(not(feature = "crypto-store"))]
+    {
+        Ok(config)
+    }
+}
@@ -0,0 +1,232 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A [`KvBackend`] using the [redb](https://www.redb.org) embedded database.
+
+use std::{
+    fmt,
+    ops::Bound,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use redb::{Database, ReadableTable, TableDefinition, TableError};
+use tokio::task::spawn_blocking;
+
+use crate::{
+    backend::{KeyRange, KvBackend, Operation, Transaction},
+    OpenStoreError,
+};
+
+/// A [`KvBackend`] storing its data in a [redb](https://www.redb.org) database
+/// file.
+///
+/// A database file can only be opened once at a time. Clones of this backend
+/// share the same database, so they can be used to open a [`KvStateStore`] and
+/// a [`KvCryptoStore`] in the same file.
+///
+/// [`KvStateStore`]: crate::KvStateStore
+/// [`KvCryptoStore`]: crate::KvCryptoStore
+#[derive(Clone)]
+pub struct RedbBackend {
+    database: Arc<Database>,
+    path: PathBuf,
+}
+
+#[cfg(not(tarpaulin_include))]
+impl fmt::Debug for RedbBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RedbBackend").field("path", &self.path).finish()
+    }
+}
+
+impl RedbBackend {
+    /// Open the redb database at the given path, creating it if it doesn't
+    /// exist.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, OpenStoreError> {
+        let path = path.as_ref();
+        let database = Database::create(path).map_err(|e| OpenStoreError::Backend(Box::new(e)))?;
+
+        Ok(Self { database: Arc::new(database), path: path.to_owned() })
+    }
+
+    /// Run the given blocking closure with the database on a thread where
+    /// blocking is acceptable.
+    async fn run<T, F>(&self, f: F) -> Result<T, redb::Error>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Database) -> Result<T, redb::Error> + Send + 'static,
+    {
+        let database = self.database.clone();
+
+        match spawn_blocking(move || f(&database)).await {
+            Ok(result) => result,
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        }
+    }
+}
+
+fn table_definition(name: &str) -> TableDefinition<'_, &'static [u8], &'static [u8]> {
+    TableDefinition::new(name)
+}
+
+fn bounds(range: &KeyRange) -> (Bound<&[u8]>, Bound<&[u8]>) {
+    let end = match &range.end {
+        Some(end) => Bound::Excluded(end.as_slice()),
+        None => Bound::Unbounded,
+    };
+
+    (Bound::Included(range.start.as_slice()), end)
+}
+
+#[async_trait]
+impl KvBackend for RedbBackend {
+    type Error = redb::Error;
+
+    async fn get(&self, table: &str, key: &[u8]) -> Result<Option<Vec<u8>>, Self::Error> {
+        let table = table.to_owned();
+        let key = key.to_owned();
+
+        self.run(move |database| {
+            let txn = database.begin_read()?;
+            let table = match txn.open_table(table_definition(&table)) {
+                Ok(table) => table,
+                // Tables are only created when they are first written to.
+                Err(TableError::TableDoesNotExist(_)) => return Ok(None),
+                Err(e) => return Err(e.into()),
+            };
+
+            let value = table.get(key.as_slice())?.map(|value| value.value().to_vec());
+            Ok(value)
+        })
+        .await
+    }
+
+    async fn range(
+        &self,
+        table: &str,
+        range: &KeyRange,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Self::Error> {
+        self.range_with_limit(table, range, usize::MAX).await
+    }
+
+    async fn range_with_limit(
+        &self,
+        table: &str,
+        range: &KeyRange,
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Self::Error> {
+        let table = table.to_owned();
+        let range = range.clone();
+
+        self.run(move |database| {
+            let txn = database.begin_read()?;
+            let table = match txn.open_table(table_definition(&table)) {
+                Ok(table) => table,
+                Err(TableError::TableDoesNotExist(_)) => return Ok(Vec::new()),
+                Err(e) => return Err(e.into()),
+            };
+
+            let mut entries = Vec::new();
+            for entry in table.range::<&[u8]>(bounds(&range))?.take(limit) {
+                let (key, value) = entry?;
+                entries.push((key.value().to_vec(), value.value().to_vec()));
+            }
+
+            Ok(entries)
+        })
+        .await
+    }
+
+    async fn transaction(&self, transaction: Transaction) -> Result<(), Self::Error> {
+        self.run(move |database| {
+            let txn = database.begin_write()?;
+
+            for operation in transaction.into_operations() {
+                match operation {
+                    Operation::Put { table, key, value } => {
+                        let mut table = txn.open_table(table_definition(&table))?;
+                        table.insert(key.as_slice(), value.as_slice())?;
+                    }
+                    Operation::Delete { table, key } => {
+                        let mut table = txn.open_table(table_definition(&table))?;
+                        table.remove(key.as_slice())?;
+                    }
+                    Operation::DeleteRange { table, range } => {
+                        let mut table = txn.open_table(table_definition(&table))?;
+                        let keys = table
+                            .range::<&[u8]>(bounds(&range))?
+                            .map(|entry| entry.map(|(key, _)| key.value().to_vec()))
+                            .collect::<Result<Vec<_>, _>>()?;
+
+                        for key in keys {
+                            table.remove(key.as_slice())?;
+                        }
+                    }
+                }
+            }
+
+            txn.commit()?;
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use matrix_sdk_test::async_test;
+    use tempfile::tempdir;
+
+    use super::RedbBackend;
+    use crate::backend::{KeyRange, KvBackend, Transaction};
+
+    #[async_test]
+    async fn test_transaction_and_range() {
+        let dir = tempdir().unwrap();
+        let backend = RedbBackend::open(dir.path().join("db.redb")).unwrap();
+
+        // Reading a table that was never written to is fine.
+        assert!(backend.get("table", b"a").await.unwrap().is_none());
+        assert!(backend.range("table", &KeyRange::all()).await.unwrap().is_empty());
+
+        let mut transaction = Transaction::new();
+        transaction.put("table", b"aa".to_vec(), b"1".to_vec());
+        transaction.put("table", b"ab".to_vec(), b"2".to_vec());
+        transaction.put("table", b"b".to_vec(), b"3".to_vec());
+        transaction.put("other", b"ac".to_vec(), b"4".to_vec());
+        backend.transaction(transaction).await.unwrap();
+
+        assert_eq!(backend.get("table", b"ab").await.unwrap().unwrap(), b"2");
+        assert_eq!(
+            backend.range("table", &KeyRange::prefix(b"a".to_vec())).await.unwrap(),
+            vec![(b"aa".to_vec(), b"1".to_vec()), (b"ab".to_vec(), b"2".to_vec())]
+        );
+
+        let mut transaction = Transaction::new();
+        transaction.delete_range("table", KeyRange::prefix(b"a".to_vec()));
+        transaction.put("table", b"ac".to_vec(), b"5".to_vec());
+        backend.transaction(transaction).await.unwrap();
+
+        assert_eq!(
+            backend.range("table", &KeyRange::all()).await.unwrap(),
+            vec![(b"ac".to_vec(), b"5".to_vec()), (b"b".to_vec(), b"3".to_vec())]
+        );
+        assert_eq!(backend.get("other", b"ac").await.unwrap().unwrap(), b"4");
+
+        backend.delete("table", b"b".to_vec()).await.unwrap();
+        assert!(backend.get("table", b"b").await.unwrap().is_none());
+    }
+}
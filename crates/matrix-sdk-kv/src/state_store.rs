@@ -0,0 +1,1031 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use matrix_sdk_base::{
+    deserialized_responses::RawAnySyncOrStrippedState,
+    media::{MediaRequest, UniqueKey},
+    MinimalRoomMemberEvent, RoomInfo, RoomMemberships, RoomState, StateChanges, StateStore,
+    StateStoreDataKey, StateStoreDataValue,
+};
+use ruma::{
+    canonical_json::{redact, RedactedBecause},
+    events::{
+        presence::PresenceEvent,
+        receipt::{Receipt, ReceiptThread, ReceiptType},
+        room::member::{MembershipState, StrippedRoomMemberEvent, SyncRoomMemberEvent},
+        AnyGlobalAccountDataEvent, AnyRoomAccountDataEvent, AnySyncStateEvent,
+        GlobalAccountDataEventType, RoomAccountDataEventType, StateEventType,
+    },
+    serde::Raw,
+    CanonicalJsonObject, EventId, MilliSecondsSinceUnixEpoch, MxcUri, OwnedEventId, OwnedUserId,
+    RoomId, RoomVersionId, UserId,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::{debug, warn};
+
+use crate::{
+    backend::{KeyRange, KvBackend, Transaction},
+    encoding::Codec,
+    error::{Error, Result},
+    get_or_create_store_cipher, OpenStoreError,
+};
+
+mod keys {
+    // Tables
+    pub const KV: &str = "state_kv";
+    pub const ACCOUNT_DATA: &str = "state_account_data";
+    pub const PRESENCE: &str = "state_presence";
+    pub const PROFILES: &str = "state_profiles";
+    pub const DISPLAY_NAMES: &str = "state_display_names";
+    pub const MEMBERS: &str = "state_members";
+    pub const STRIPPED_MEMBERS: &str = "state_stripped_members";
+    pub const ROOM_INFOS: &str = "state_room_infos";
+    pub const ROOM_STATE: &str = "state_room_state";
+    pub const STRIPPED_ROOM_STATE: &str = "state_stripped_room_state";
+    pub const ROOM_ACCOUNT_DATA: &str = "state_room_account_data";
+    pub const ROOM_USER_RECEIPTS: &str = "state_room_user_receipts";
+    pub const ROOM_EVENT_RECEIPTS: &str = "state_room_event_receipts";
+    pub const MEDIA: &str = "state_media";
+    pub const CUSTOM: &str = "state_custom";
+    pub const LEASE_LOCKS: &str = "state_lease_locks";
+
+    /// All the tables whose keys start with a room ID.
+    pub const ROOM_TABLES: &[&str] = &[
+        PROFILES,
+        DISPLAY_NAMES,
+        MEMBERS,
+        STRIPPED_MEMBERS,
+        ROOM_INFOS,
+        ROOM_STATE,
+        STRIPPED_ROOM_STATE,
+        ROOM_ACCOUNT_DATA,
+        ROOM_USER_RECEIPTS,
+        ROOM_EVENT_RECEIPTS,
+    ];
+
+    // Keys
+    pub const STORE_CIPHER: &str = "state_store_cipher";
+}
+
+/// A state store on top of a [`KvBackend`].
+///
+/// The writes of this store are serialized within a process, which makes
+/// [`StateStore::try_take_leased_lock`] atomic between all the handles of this
+/// store in that process. Sharing the lock with other processes requires a
+/// backend that can be opened by several processes at once and makes the whole
+/// read-modify-write of the lease atomic.
+#[derive(Clone)]
+pub struct KvStateStore<B> {
+    backend: B,
+    codec: Codec,
+    /// Serializes the operations that need to read the store before writing
+    /// to it.
+    write_lock: Arc<Mutex<()>>,
+}
+
+impl<B: fmt::Debug> fmt::Debug for KvStateStore<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KvStateStore").field("backend", &self.backend).finish()
+    }
+}
+
+impl<B: KvBackend> KvStateStore<B> {
+    /// Open a state store on top of the given backend, using the given
+    /// passphrase to encrypt private data.
+    pub async fn open(backend: B, passphrase: Option<&str>) -> Result<Self, OpenStoreError> {
+        let store_cipher = match passphrase {
+            Some(p) => {
+                Some(Arc::new(get_or_create_store_cipher(p, &backend, keys::STORE_CIPHER).await?))
+            }
+            None => None,
+        };
+
+        Ok(Self { backend, codec: Codec::new(store_cipher), write_lock: Default::default() })
+    }
+
+    fn encode_key(&self, table_name: &str, parts: &[&[u8]]) -> Vec<u8> {
+        self.codec.encode_key(table_name, parts)
+    }
+
+    fn encode_room_prefix(&self, table_name: &str, room_id: &RoomId) -> KeyRange {
+        self.codec.encode_prefix(table_name, &[room_id.as_bytes()])
+    }
+
+    fn encode_state_store_data_key(&self, key: StateStoreDataKey<'_>) -> Vec<u8> {
+        let key_s = match key {
+            StateStoreDataKey::SyncToken => Cow::Borrowed(StateStoreDataKey::SYNC_TOKEN),
+            StateStoreDataKey::Filter(f) => {
+                Cow::Owned(format!("{}:{f}", StateStoreDataKey::FILTER))
+            }
+            StateStoreDataKey::UserAvatarUrl(u) => {
+                Cow::Owned(format!("{}:{u}", StateStoreDataKey::USER_AVATAR_URL))
+            }
+        };
+
+        self.encode_key(keys::KV, &[key_s.as_bytes()])
+    }
+
+    async fn get(&self, table_name: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.backend.get(table_name, key).await.map_err(Error::backend)
+    }
+
+    async fn range(&self, table_name: &str, range: &KeyRange) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.backend.range(table_name, range).await.map_err(Error::backend)
+    }
+
+    async fn commit(&self, transaction: Transaction) -> Result<()> {
+        if transaction.is_empty() {
+            return Ok(());
+        }
+
+        self.backend.transaction(transaction).await.map_err(Error::backend)
+    }
+
+    /// Get the value the given key will have once the given transaction is
+    /// committed.
+    async fn get_pending(
+        &self,
+        transaction: &Transaction,
+        table_name: &str,
+        key: &[u8],
+    ) -> Result<Option<Vec<u8>>> {
+        match transaction.get(table_name, key) {
+            Some(value) => Ok(value.map(ToOwned::to_owned)),
+            None => self.get(table_name, key).await,
+        }
+    }
+
+    /// Get the entries the given range will have once the given transaction is
+    /// committed.
+    async fn range_pending(
+        &self,
+        transaction: &Transaction,
+        table_name: &str,
+        range: &KeyRange,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let entries = self.range(table_name, range).await?;
+        Ok(transaction.apply_to_range(table_name, range, entries))
+    }
+
+    async fn get_room_version(
+        &self,
+        transaction: &Transaction,
+        room_id: &RoomId,
+    ) -> Result<RoomVersionId> {
+        let key = self.encode_key(keys::ROOM_INFOS, &[room_id.as_bytes()]);
+
+        Ok(self
+            .get_pending(transaction, keys::ROOM_INFOS, &key)
+            .await?
+            .and_then(|v| self.codec.deserialize_json::<RoomInfo>(&v).ok())
+            .and_then(|info| info.room_version().cloned())
+            .unwrap_or_else(|| {
+                warn!(?room_id, "Unable to find the room version, assume version 9");
+                RoomVersionId::V9
+            }))
+    }
+
+    async fn get_maybe_stripped_state_events(
+        &self,
+        room_id: &RoomId,
+        event_type: &StateEventType,
+    ) -> Result<Vec<RawAnySyncOrStrippedState>> {
+        let event_type = event_type.to_string();
+        let parts: &[&[u8]] = &[room_id.as_bytes(), event_type.as_bytes()];
+
+        let range = self.codec.encode_prefix(keys::STRIPPED_ROOM_STATE, parts);
+        let stripped = self.range(keys::STRIPPED_ROOM_STATE, &range).await?;
+
+        if !stripped.is_empty() {
+            return stripped
+                .iter()
+                .map(|(_, value)| {
+                    Ok(RawAnySyncOrStrippedState::Stripped(self.codec.deserialize_json(value)?))
+                })
+                .collect();
+        }
+
+        let range = self.codec.encode_prefix(keys::ROOM_STATE, parts);
+        self.range(keys::ROOM_STATE, &range)
+            .await?
+            .iter()
+            .map(|(_, value)| {
+                Ok(RawAnySyncOrStrippedState::Sync(self.codec.deserialize_json(value)?))
+            })
+            .collect()
+    }
+
+    async fn get_user_ids_inner(
+        &self,
+        table_name: &str,
+        room_id: &RoomId,
+        memberships: RoomMemberships,
+    ) -> Result<Vec<OwnedUserId>> {
+        let range = self.encode_room_prefix(table_name, room_id);
+        let mut user_ids = Vec::new();
+
+        for (_, value) in self.range(table_name, &range).await? {
+            let member: RoomMember = self.codec.deserialize_value(&value)?;
+            if memberships.matches(&member.membership) {
+                user_ids.push(member.user_id);
+            }
+        }
+
+        Ok(user_ids)
+    }
+
+    fn encode_receipt_thread(thread: &ReceiptThread) -> Result<Vec<u8>> {
+        // Rely on serialization instead of the string representation, to be able to
+        // represent `ReceiptThread::Unthreaded`.
+        Ok(rmp_serde::to_vec_named(thread)?)
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl<B: KvBackend> StateStore for KvStateStore<B> {
+    type Error = Error;
+
+    async fn get_kv_data(&self, key: StateStoreDataKey<'_>) -> Result<Option<StateStoreDataValue>> {
+        self.get(keys::KV, &self.encode_state_store_data_key(key))
+            .await?
+            .map(|data| {
+                let string = self.codec.deserialize_value(&data)?;
+                Ok(match key {
+                    StateStoreDataKey::SyncToken => StateStoreDataValue::SyncToken(string),
+                    StateStoreDataKey::Filter(_) => StateStoreDataValue::Filter(string),
+                    StateStoreDataKey::UserAvatarUrl(_) => {
+                        StateStoreDataValue::UserAvatarUrl(string)
+                    }
+                })
+            })
+            .transpose()
+    }
+
+    async fn set_kv_data(
+        &self,
+        key: StateStoreDataKey<'_>,
+        value: StateStoreDataValue,
+    ) -> Result<()> {
+        let value = match key {
+            StateStoreDataKey::SyncToken => {
+                value.into_sync_token().expect("Session data not a sync token")
+            }
+            StateStoreDataKey::Filter(_) => value.into_filter().expect("Session data not a filter"),
+            StateStoreDataKey::UserAvatarUrl(_) => {
+                value.into_user_avatar_url().expect("Session data not an user avatar url")
+            }
+        };
+
+        self.backend
+            .put(
+                keys::KV,
+                self.encode_state_store_data_key(key),
+                self.codec.serialize_value(&value)?,
+            )
+            .await
+            .map_err(Error::backend)
+    }
+
+    async fn remove_kv_data(&self, key: StateStoreDataKey<'_>) -> Result<()> {
+        self.backend
+            .delete(keys::KV, self.encode_state_store_data_key(key))
+            .await
+            .map_err(Error::backend)
+    }
+
+    async fn save_changes(&self, changes: &StateChanges) -> Result<()> {
+        let StateChanges {
+            sync_token,
+            account_data,
+            presence,
+            profiles,
+            state,
+            room_account_data,
+            room_infos,
+            receipts,
+            redactions,
+            stripped_state,
+            ambiguity_maps,
+            notifications: _,
+        } = changes;
+
+        // Receipts and redactions depend on the current content of the store.
+        let _guard = self.write_lock.lock().await;
+        let mut txn = Transaction::new();
+
+        if let Some(sync_token) = sync_token {
+            let key = self.encode_state_store_data_key(StateStoreDataKey::SyncToken);
+            txn.put(keys::KV, key, self.codec.serialize_value(sync_token)?);
+        }
+
+        for (event_type, event) in account_data {
+            let key = self.encode_key(keys::ACCOUNT_DATA, &[event_type.to_string().as_bytes()]);
+            txn.put(keys::ACCOUNT_DATA, key, self.codec.serialize_json(event)?);
+        }
+
+        for (room_id, events) in room_account_data {
+            for (event_type, event) in events {
+                let key = self.encode_key(
+                    keys::ROOM_ACCOUNT_DATA,
+                    &[room_id.as_bytes(), event_type.to_string().as_bytes()],
+                );
+                txn.put(keys::ROOM_ACCOUNT_DATA, key, self.codec.serialize_json(event)?);
+            }
+        }
+
+        for (user_id, event) in presence {
+            let key = self.encode_key(keys::PRESENCE, &[user_id.as_bytes()]);
+            txn.put(keys::PRESENCE, key, self.codec.serialize_json(event)?);
+        }
+
+        for (room_id, room_info) in room_infos {
+            // Remove non-stripped data for stripped rooms and vice-versa.
+            let (state_table, members_table) = if room_info.state() == RoomState::Invited {
+                (keys::ROOM_STATE, keys::MEMBERS)
+            } else {
+                (keys::STRIPPED_ROOM_STATE, keys::STRIPPED_MEMBERS)
+            };
+            txn.delete_range(state_table, self.encode_room_prefix(state_table, room_id));
+            txn.delete_range(members_table, self.encode_room_prefix(members_table, room_id));
+
+            let key = self.encode_key(keys::ROOM_INFOS, &[room_id.as_bytes()]);
+            txn.put(keys::ROOM_INFOS, key, self.codec.serialize_json(room_info)?);
+        }
+
+        for (room_id, users) in profiles {
+            for (user_id, profile) in users {
+                let key =
+                    self.encode_key(keys::PROFILES, &[room_id.as_bytes(), user_id.as_bytes()]);
+                txn.put(keys::PROFILES, key, self.codec.serialize_json(profile)?);
+            }
+        }
+
+        for (room_id, state_event_types) in state {
+            for (event_type, state_events) in state_event_types {
+                let event_type_s = event_type.to_string();
+
+                for (state_key, raw_state_event) in state_events {
+                    let key = self.encode_key(
+                        keys::ROOM_STATE,
+                        &[room_id.as_bytes(), event_type_s.as_bytes(), state_key.as_bytes()],
+                    );
+                    txn.put(keys::ROOM_STATE, key, self.codec.serialize_json(raw_state_event)?);
+
+                    if *event_type == StateEventType::RoomMember {
+                        let member_event =
+                            match raw_state_event.deserialize_as::<SyncRoomMemberEvent>() {
+                                Ok(ev) => ev,
+                                Err(e) => {
+                                    debug!("Failed to deserialize member event: {e}");
+                                    continue;
+                                }
+                            };
+
+                        let member = RoomMember {
+                            user_id: member_event.state_key().clone(),
+                            membership: member_event.membership().clone(),
+                        };
+                        let key = self
+                            .encode_key(keys::MEMBERS, &[room_id.as_bytes(), state_key.as_bytes()]);
+                        txn.put(keys::MEMBERS, key, self.codec.serialize_value(&member)?);
+                    }
+                }
+            }
+        }
+
+        for (room_id, stripped_state_event_types) in stripped_state {
+            for (event_type, stripped_state_events) in stripped_state_event_types {
+                let event_type_s = event_type.to_string();
+
+                for (state_key, raw_stripped_state_event) in stripped_state_events {
+                    let key = self.encode_key(
+                        keys::STRIPPED_ROOM_STATE,
+                        &[room_id.as_bytes(), event_type_s.as_bytes(), state_key.as_bytes()],
+                    );
+                    txn.put(
+                        keys::STRIPPED_ROOM_STATE,
+                        key,
+                        self.codec.serialize_json(raw_stripped_state_event)?,
+                    );
+
+                    if *event_type == StateEventType::RoomMember {
+                        let member_event = match raw_stripped_state_event
+                            .deserialize_as::<StrippedRoomMemberEvent>()
+                        {
+                            Ok(ev) => ev,
+                            Err(e) => {
+                                debug!("Failed to deserialize stripped member event: {e}");
+                                continue;
+                            }
+                        };
+
+                        let member = RoomMember {
+                            user_id: member_event.state_key,
+                            membership: member_event.content.membership,
+                        };
+                        let key = self.encode_key(
+                            keys::STRIPPED_MEMBERS,
+                            &[room_id.as_bytes(), state_key.as_bytes()],
+                        );
+                        txn.put(keys::STRIPPED_MEMBERS, key, self.codec.serialize_value(&member)?);
+                    }
+                }
+            }
+        }
+
+        for (room_id, receipt_event) in receipts {
+            for (event_id, receipt_types) in receipt_event.iter() {
+                for (receipt_type, receipt_users) in receipt_types {
+                    for (user_id, receipt) in receipt_users {
+                        let thread = Self::encode_receipt_thread(&receipt.thread)?;
+                        let user_key = self.encode_key(
+                            keys::ROOM_USER_RECEIPTS,
+                            &[
+                                room_id.as_bytes(),
+                                receipt_type.as_str().as_bytes(),
+                                &thread,
+                                user_id.as_bytes(),
+                            ],
+                        );
+
+                        // Remove the previous receipt of the user from the receipts of the event
+                        // it was attached to.
+                        if let Some(previous) =
+                            self.get_pending(&txn, keys::ROOM_USER_RECEIPTS, &user_key).await?
+                        {
+                            let previous: ReceiptData = self.codec.deserialize_json(&previous)?;
+                            let key = self.encode_key(
+                                keys::ROOM_EVENT_RECEIPTS,
+                                &[
+                                    room_id.as_bytes(),
+                                    receipt_type.as_str().as_bytes(),
+                                    &thread,
+                                    previous.event_id.as_bytes(),
+                                    user_id.as_bytes(),
+                                ],
+                            );
+                            txn.delete(keys::ROOM_EVENT_RECEIPTS, key);
+                        }
+
+                        let data = self.codec.serialize_json(&ReceiptData {
+                            receipt: receipt.clone(),
+                            event_id: event_id.clone(),
+                            user_id: user_id.clone(),
+                        })?;
+                        let event_key = self.encode_key(
+                            keys::ROOM_EVENT_RECEIPTS,
+                            &[
+                                room_id.as_bytes(),
+                                receipt_type.as_str().as_bytes(),
+                                &thread,
+                                event_id.as_bytes(),
+                                user_id.as_bytes(),
+                            ],
+                        );
+
+                        txn.put(keys::ROOM_USER_RECEIPTS, user_key, data.clone());
+                        txn.put(keys::ROOM_EVENT_RECEIPTS, event_key, data);
+                    }
+                }
+            }
+        }
+
+        for (room_id, redactions) in redactions {
+            let range = self.encode_room_prefix(keys::ROOM_STATE, room_id);
+            let mut room_version = None;
+
+            for (key, value) in self.range_pending(&txn, keys::ROOM_STATE, &range).await? {
+                let raw_event: Raw<AnySyncStateEvent> = self.codec.deserialize_json(&value)?;
+                let Ok(Some(event_id)) = raw_event.get_field::<OwnedEventId>("event_id") else {
+                    continue;
+                };
+
+                if let Some(redaction) = redactions.get(&event_id) {
+                    if room_version.is_none() {
+                        room_version = Some(self.get_room_version(&txn, room_id).await?);
+                    }
+
+                    let redacted = redact(
+                        raw_event.deserialize_as::<CanonicalJsonObject>()?,
+                        room_version.as_ref().expect("the room version was just loaded"),
+                        Some(RedactedBecause::from_raw_event(redaction)?),
+                    )
+                    .map_err(Error::Redaction)?;
+
+                    txn.put(keys::ROOM_STATE, key, self.codec.serialize_json(&redacted)?);
+                }
+            }
+        }
+
+        for (room_id, display_names) in ambiguity_maps {
+            for (name, user_ids) in display_names {
+                let key =
+                    self.encode_key(keys::DISPLAY_NAMES, &[room_id.as_bytes(), name.as_bytes()]);
+
+                if user_ids.is_empty() {
+                    txn.delete(keys::DISPLAY_NAMES, key);
+                } else {
+                    txn.put(keys::DISPLAY_NAMES, key, self.codec.serialize_json(user_ids)?);
+                }
+            }
+        }
+
+        self.commit(txn).await
+    }
+
+    async fn get_presence_event(&self, user_id: &UserId) -> Result<Option<Raw<PresenceEvent>>> {
+        let key = self.encode_key(keys::PRESENCE, &[user_id.as_bytes()]);
+        self.get(keys::PRESENCE, &key)
+            .await?
+            .map(|data| self.codec.deserialize_json(&data))
+            .transpose()
+    }
+
+    async fn get_presence_events(
+        &self,
+        user_ids: &[OwnedUserId],
+    ) -> Result<Vec<Raw<PresenceEvent>>> {
+        let mut events = Vec::with_capacity(user_ids.len());
+
+        for user_id in user_ids {
+            if let Some(event) = self.get_presence_event(user_id).await? {
+                events.push(event);
+            }
+        }
+
+        Ok(events)
+    }
+
+    async fn get_state_event(
+        &self,
+        room_id: &RoomId,
+        event_type: StateEventType,
+        state_key: &str,
+    ) -> Result<Option<RawAnySyncOrStrippedState>> {
+        let event_type = event_type.to_string();
+        let parts: &[&[u8]] = &[room_id.as_bytes(), event_type.as_bytes(), state_key.as_bytes()];
+
+        let key = self.encode_key(keys::STRIPPED_ROOM_STATE, parts);
+        if let Some(value) = self.get(keys::STRIPPED_ROOM_STATE, &key).await? {
+            return Ok(Some(RawAnySyncOrStrippedState::Stripped(
+                self.codec.deserialize_json(&value)?,
+            )));
+        }
+
+        let key = self.encode_key(keys::ROOM_STATE, parts);
+        self.get(keys::ROOM_STATE, &key)
+            .await?
+            .map(|value| Ok(RawAnySyncOrStrippedState::Sync(self.codec.deserialize_json(&value)?)))
+            .transpose()
+    }
+
+    async fn get_state_events(
+        &self,
+        room_id: &RoomId,
+        event_type: StateEventType,
+    ) -> Result<Vec<RawAnySyncOrStrippedState>> {
+        self.get_maybe_stripped_state_events(room_id, &event_type).await
+    }
+
+    async fn get_state_events_for_keys(
+        &self,
+        room_id: &RoomId,
+        event_type: StateEventType,
+        state_keys: &[&str],
+    ) -> Result<Vec<RawAnySyncOrStrippedState>, Self::Error> {
+        let mut events = Vec::with_capacity(state_keys.len());
+
+        for state_key in state_keys {
+            if let Some(event) =
+                self.get_state_event(room_id, event_type.clone(), state_key).await?
+            {
+                events.push(event);
+            }
+        }
+
+        Ok(events)
+    }
+
+    async fn get_profile(
+        &self,
+        room_id: &RoomId,
+        user_id: &UserId,
+    ) -> Result<Option<MinimalRoomMemberEvent>> {
+        let key = self.encode_key(keys::PROFILES, &[room_id.as_bytes(), user_id.as_bytes()]);
+        self.get(keys::PROFILES, &key)
+            .await?
+            .map(|data| self.codec.deserialize_json(&data))
+            .transpose()
+    }
+
+    async fn get_profiles<'a>(
+        &self,
+        room_id: &RoomId,
+        user_ids: &'a [OwnedUserId],
+    ) -> Result<BTreeMap<&'a UserId, MinimalRoomMemberEvent>> {
+        let mut profiles = BTreeMap::new();
+
+        for user_id in user_ids {
+            if let Some(profile) = self.get_profile(room_id, user_id).await? {
+                profiles.insert(user_id.as_ref(), profile);
+            }
+        }
+
+        Ok(profiles)
+    }
+
+    async fn get_user_ids(
+        &self,
+        room_id: &RoomId,
+        memberships: RoomMemberships,
+    ) -> Result<Vec<OwnedUserId>> {
+        let user_ids =
+            self.get_user_ids_inner(keys::STRIPPED_MEMBERS, room_id, memberships).await?;
+        if !user_ids.is_empty() {
+            return Ok(user_ids);
+        }
+
+        self.get_user_ids_inner(keys::MEMBERS, room_id, memberships).await
+    }
+
+    async fn get_invited_user_ids(&self, room_id: &RoomId) -> Result<Vec<OwnedUserId>> {
+        self.get_user_ids(room_id, RoomMemberships::INVITE).await
+    }
+
+    async fn get_joined_user_ids(&self, room_id: &RoomId) -> Result<Vec<OwnedUserId>> {
+        self.get_user_ids(room_id, RoomMemberships::JOIN).await
+    }
+
+    async fn get_room_infos(&self) -> Result<Vec<RoomInfo>> {
+        self.range(keys::ROOM_INFOS, &KeyRange::all())
+            .await?
+            .iter()
+            .map(|(_, data)| self.codec.deserialize_json(data))
+            .collect()
+    }
+
+    async fn get_stripped_room_infos(&self) -> Result<Vec<RoomInfo>> {
+        Ok(self
+            .get_room_infos()
+            .await?
+            .into_iter()
+            .filter(|info| info.state() == RoomState::Invited)
+            .collect())
+    }
+
+    async fn get_users_with_display_name(
+        &self,
+        room_id: &RoomId,
+        display_name: &str,
+    ) -> Result<BTreeSet<OwnedUserId>> {
+        let key =
+            self.encode_key(keys::DISPLAY_NAMES, &[room_id.as_bytes(), display_name.as_bytes()]);
+
+        Ok(self
+            .get(keys::DISPLAY_NAMES, &key)
+            .await?
+            .map(|data| self.codec.deserialize_json(&data))
+            .transpose()?
+            .unwrap_or_default())
+    }
+
+    async fn get_users_with_display_names<'a>(
+        &self,
+        room_id: &RoomId,
+        display_names: &'a [String],
+    ) -> Result<BTreeMap<&'a str, BTreeSet<OwnedUserId>>> {
+        let mut users = BTreeMap::new();
+
+        for display_name in display_names {
+            let user_ids = self.get_users_with_display_name(room_id, display_name).await?;
+            if !user_ids.is_empty() {
+                users.insert(display_name.as_str(), user_ids);
+            }
+        }
+
+        Ok(users)
+    }
+
+    async fn get_account_data_event(
+        &self,
+        event_type: GlobalAccountDataEventType,
+    ) -> Result<Option<Raw<AnyGlobalAccountDataEvent>>> {
+        let key = self.encode_key(keys::ACCOUNT_DATA, &[event_type.to_string().as_bytes()]);
+        self.get(keys::ACCOUNT_DATA, &key)
+            .await?
+            .map(|value| self.codec.deserialize_json(&value))
+            .transpose()
+    }
+
+    async fn get_room_account_data_event(
+        &self,
+        room_id: &RoomId,
+        event_type: RoomAccountDataEventType,
+    ) -> Result<Option<Raw<AnyRoomAccountDataEvent>>> {
+        let key = self.encode_key(
+            keys::ROOM_ACCOUNT_DATA,
+            &[room_id.as_bytes(), event_type.to_string().as_bytes()],
+        );
+        self.get(keys::ROOM_ACCOUNT_DATA, &key)
+            .await?
+            .map(|value| self.codec.deserialize_json(&value))
+            .transpose()
+    }
+
+    async fn get_user_room_receipt_event(
+        &self,
+        room_id: &RoomId,
+        receipt_type: ReceiptType,
+        thread: ReceiptThread,
+        user_id: &UserId,
+    ) -> Result<Option<(OwnedEventId, Receipt)>> {
+        let thread = Self::encode_receipt_thread(&thread)?;
+        let key = self.encode_key(
+            keys::ROOM_USER_RECEIPTS,
+            &[room_id.as_bytes(), receipt_type.as_str().as_bytes(), &thread, user_id.as_bytes()],
+        );
+
+        self.get(keys::ROOM_USER_RECEIPTS, &key)
+            .await?
+            .map(|value| {
+                self.codec.deserialize_json::<ReceiptData>(&value).map(|d| (d.event_id, d.receipt))
+            })
+            .transpose()
+    }
+
+    async fn get_event_room_receipt_events(
+        &self,
+        room_id: &RoomId,
+        receipt_type: ReceiptType,
+        thread: ReceiptThread,
+        event_id: &EventId,
+    ) -> Result<Vec<(OwnedUserId, Receipt)>> {
+        let thread = Self::encode_receipt_thread(&thread)?;
+        let range = self.codec.encode_prefix(
+            keys::ROOM_EVENT_RECEIPTS,
+            &[room_id.as_bytes(), receipt_type.as_str().as_bytes(), &thread, event_id.as_bytes()],
+        );
+
+        self.range(keys::ROOM_EVENT_RECEIPTS, &range)
+            .await?
+            .iter()
+            .map(|(_, value)| {
+                self.codec.deserialize_json::<ReceiptData>(value).map(|d| (d.user_id, d.receipt))
+            })
+            .collect()
+    }
+
+    async fn get_all_state_events(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<RawAnySyncOrStrippedState>> {
+        let mut events = Vec::new();
+
+        let range = self.encode_room_prefix(keys::ROOM_STATE, room_id);
+        for (_, value) in self.range(keys::ROOM_STATE, &range).await? {
+            events.push(RawAnySyncOrStrippedState::Sync(self.codec.deserialize_json(&value)?));
+        }
+
+        let range = self.encode_room_prefix(keys::STRIPPED_ROOM_STATE, room_id);
+        for (_, value) in self.range(keys::STRIPPED_ROOM_STATE, &range).await? {
+            events.push(RawAnySyncOrStrippedState::Stripped(self.codec.deserialize_json(&value)?));
+        }
+
+        Ok(events)
+    }
+
+    async fn get_all_account_data_events(&self) -> Result<Vec<Raw<AnyGlobalAccountDataEvent>>> {
+        self.range(keys::ACCOUNT_DATA, &KeyRange::all())
+            .await?
+            .iter()
+            .map(|(_, value)| self.codec.deserialize_json(value))
+            .collect()
+    }
+
+    async fn get_all_room_account_data_events(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<Raw<AnyRoomAccountDataEvent>>> {
+        let range = self.encode_room_prefix(keys::ROOM_ACCOUNT_DATA, room_id);
+        self.range(keys::ROOM_ACCOUNT_DATA, &range)
+            .await?
+            .iter()
+            .map(|(_, value)| self.codec.deserialize_json(value))
+            .collect()
+    }
+
+    async fn get_room_receipt_events(
+        &self,
+        room_id: &RoomId,
+        receipt_type: ReceiptType,
+    ) -> Result<Vec<(OwnedUserId, OwnedEventId, Receipt)>> {
+        let range = self.codec.encode_prefix(
+            keys::ROOM_USER_RECEIPTS,
+            &[room_id.as_bytes(), receipt_type.as_str().as_bytes()],
+        );
+
+        self.range(keys::ROOM_USER_RECEIPTS, &range)
+            .await?
+            .iter()
+            .map(|(_, value)| {
+                self.codec
+                    .deserialize_json::<ReceiptData>(value)
+                    .map(|d| (d.user_id, d.event_id, d.receipt))
+            })
+            .collect()
+    }
+
+    async fn get_custom_value(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let key = self.encode_key(keys::CUSTOM, &[key]);
+        self.get(keys::CUSTOM, &key)
+            .await?
+            .map(|value| Ok(self.codec.decode_value(&value)?.into_owned()))
+            .transpose()
+    }
+
+    async fn set_custom_value(&self, key: &[u8], value: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        let _guard = self.write_lock.lock().await;
+
+        let previous = self.get_custom_value(key).await?;
+        let key = self.encode_key(keys::CUSTOM, &[key]);
+        let value = self.codec.encode_value(value)?;
+        self.backend.put(keys::CUSTOM, key, value).await.map_err(Error::backend)?;
+
+        Ok(previous)
+    }
+
+    async fn remove_custom_value(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let _guard = self.write_lock.lock().await;
+
+        let previous = self.get_custom_value(key).await?;
+        if previous.is_some() {
+            let key = self.encode_key(keys::CUSTOM, &[key]);
+            self.backend.delete(keys::CUSTOM, key).await.map_err(Error::backend)?;
+        }
+
+        Ok(previous)
+    }
+
+    async fn add_media_content(&self, request: &MediaRequest, content: Vec<u8>) -> Result<()> {
+        let key = self.encode_key(
+            keys::MEDIA,
+            &[request.source.unique_key().as_bytes(), request.format.unique_key().as_bytes()],
+        );
+        let data = self.codec.encode_value(content)?;
+        self.backend.put(keys::MEDIA, key, data).await.map_err(Error::backend)
+    }
+
+    async fn get_media_content(&self, request: &MediaRequest) -> Result<Option<Vec<u8>>> {
+        let key = self.encode_key(
+            keys::MEDIA,
+            &[request.source.unique_key().as_bytes(), request.format.unique_key().as_bytes()],
+        );
+        self.get(keys::MEDIA, &key)
+            .await?
+            .map(|value| Ok(self.codec.decode_value(&value)?.into_owned()))
+            .transpose()
+    }
+
+    async fn remove_media_content(&self, request: &MediaRequest) -> Result<()> {
+        let key = self.encode_key(
+            keys::MEDIA,
+            &[request.source.unique_key().as_bytes(), request.format.unique_key().as_bytes()],
+        );
+        self.backend.delete(keys::MEDIA, key).await.map_err(Error::backend)
+    }
+
+    async fn remove_media_content_for_uri(&self, uri: &MxcUri) -> Result<()> {
+        let mut txn = Transaction::new();
+        txn.delete_range(
+            keys::MEDIA,
+            self.codec.encode_prefix(keys::MEDIA, &[uri.as_str().as_bytes()]),
+        );
+        self.commit(txn).await
+    }
+
+    async fn remove_room(&self, room_id: &RoomId) -> Result<()> {
+        let mut txn = Transaction::new();
+
+        for table_name in keys::ROOM_TABLES {
+            txn.delete_range(table_name, self.encode_room_prefix(table_name, room_id));
+        }
+
+        self.commit(txn).await
+    }
+
+    async fn try_take_leased_lock(
+        &self,
+        lease_duration_ms: u32,
+        key: &str,
+        holder: &str,
+    ) -> Result<bool> {
+        let _guard = self.write_lock.lock().await;
+
+        let key = self.encode_key(keys::LEASE_LOCKS, &[key.as_bytes()]);
+        let now_ts: u64 = MilliSecondsSinceUnixEpoch::now().get().into();
+
+        if let Some(value) = self.get(keys::LEASE_LOCKS, &key).await? {
+            let lease: Lease = self.codec.deserialize_value(&value)?;
+            if lease.holder != holder && lease.expiration_ts >= now_ts {
+                return Ok(false);
+            }
+        }
+
+        let lease =
+            Lease { holder: holder.to_owned(), expiration_ts: now_ts + lease_duration_ms as u64 };
+        let value = self.codec.serialize_value(&lease)?;
+        self.backend.put(keys::LEASE_LOCKS, key, value).await.map_err(Error::backend)?;
+
+        Ok(true)
+    }
+}
+
+/// A room member.
+#[derive(Debug, Serialize, Deserialize)]
+struct RoomMember {
+    user_id: OwnedUserId,
+    membership: MembershipState,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ReceiptData {
+    receipt: Receipt,
+    event_id: OwnedEventId,
+    user_id: OwnedUserId,
+}
+
+/// A lease on a lock, see [`StateStore::try_take_leased_lock`].
+#[derive(Debug, Serialize, Deserialize)]
+struct Lease {
+    holder: String,
+    expiration_ts: u64,
+}
+
+#[cfg(all(test, feature = "redb"))]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering::SeqCst};
+
+    use matrix_sdk_base::{statestore_integration_tests, StateStore, StoreError};
+    use once_cell::sync::Lazy;
+    use tempfile::{tempdir, TempDir};
+
+    use super::KvStateStore;
+    use crate::RedbBackend;
+
+    static TMP_DIR: Lazy<TempDir> = Lazy::new(|| tempdir().unwrap());
+    static NUM: AtomicU32 = AtomicU32::new(0);
+
+    async fn get_store() -> Result<impl StateStore, StoreError> {
+        let name = NUM.fetch_add(1, SeqCst);
+        let backend = RedbBackend::open(TMP_DIR.path().join(format!("{name}.redb"))).unwrap();
+
+        Ok(KvStateStore::open(backend, None).await.unwrap())
+    }
+
+    statestore_integration_tests!(with_media_tests);
+}
+
+#[cfg(all(test, feature = "redb"))]
+mod encrypted_tests {
+    use std::sync::atomic::{AtomicU32, Ordering::SeqCst};
+
+    use matrix_sdk_base::{statestore_integration_tests, StateStore, StoreError};
+    use once_cell::sync::Lazy;
+    use tempfile::{tempdir, TempDir};
+
+    use super::KvStateStore;
+    use crate::RedbBackend;
+
+    static TMP_DIR: Lazy<TempDir> = Lazy::new(|| tempdir().unwrap());
+    static NUM: AtomicU32 = AtomicU32::new(0);
+
+    async fn get_store() -> Result<impl StateStore, StoreError> {
+        let name = NUM.fetch_add(1, SeqCst);
+        let backend = RedbBackend::open(TMP_DIR.path().join(format!("{name}.redb"))).unwrap();
+
+        Ok(KvStateStore::open(backend, Some("default_test_password")).await.unwrap())
+    }
+
+    statestore_integration_tests!(with_media_tests);
+}
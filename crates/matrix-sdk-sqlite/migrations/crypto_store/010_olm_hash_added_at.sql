@@ -0,0 +1,4 @@
+-- Remember when the hashes of the Olm messages were stored, so that only the
+-- old ones get removed when the store is pruned. The age of the hashes that
+-- are already stored isn't known, they are considered to be the oldest ones.
+ALTER TABLE "olm_hash" ADD COLUMN "added_at" INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,6 @@
+CREATE TABLE "quarantine" (
+    "table_name" TEXT NOT NULL,
+    "keys" BLOB NOT NULL,
+    "data" BLOB NOT NULL,
+    "error" TEXT NOT NULL
+);
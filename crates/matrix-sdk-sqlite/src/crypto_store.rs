@@ -14,7 +14,7 @@
 
 use std::{
     borrow::Cow,
-    collections::HashMap,
+    collections::{BTreeSet, HashMap},
     fmt,
     path::{Path, PathBuf},
     sync::{Arc, RwLock},
@@ -25,7 +25,7 @@ use deadpool_sqlite::{Object as SqliteConn, Pool as SqlitePool, Runtime};
 use matrix_sdk_crypto::{
     olm::{
         IdentityKeys, InboundGroupSession, OutboundGroupSession, PickledInboundGroupSession,
-        PrivateCrossSigningIdentity, Session,
+        PickledSession, PrivateCrossSigningIdentity, Session,
     },
     store::{caches::SessionStore, BackupKeys, Changes, CryptoStore, RoomKeyCounts, RoomSettings},
     types::events::room_key_withheld::RoomKeyWithheldEvent,
@@ -35,7 +35,7 @@ use matrix_sdk_crypto::{
 use matrix_sdk_store_encryption::StoreCipher;
 use ruma::{
     events::secret::request::SecretName, DeviceId, MilliSecondsSinceUnixEpoch, OwnedDeviceId,
-    OwnedUserId, RoomId, TransactionId, UserId,
+    OwnedUserId, RoomId, SecondsSinceUnixEpoch, TransactionId, UserId,
 };
 use rusqlite::OptionalExtension;
 use serde::{de::DeserializeOwned, Serialize};
@@ -45,10 +45,12 @@ use tracing::{debug, instrument, warn};
 use crate::{
     error::{Error, Result},
     get_or_create_store_cipher,
+    maintenance::{self, IntegrityReport, StoreStats, QUARANTINE_TABLE},
+    rekey::{self, RekeyTable, ValueFormat},
     utils::{
         load_db_version, Key, SqliteConnectionExt as _, SqliteObjectExt, SqliteObjectStoreExt as _,
     },
-    OpenStoreError,
+    OpenStoreError, CRYPTO_STORE_DATABASE_NAME,
 };
 
 #[derive(Clone, Debug)]
@@ -90,7 +92,7 @@ impl SqliteCryptoStore {
     ) -> Result<Self, OpenStoreError> {
         let path = path.as_ref();
         fs::create_dir_all(path).await.map_err(OpenStoreError::CreateDir)?;
-        let cfg = deadpool_sqlite::Config::new(path.join("matrix-sdk-crypto.sqlite3"));
+        let cfg = deadpool_sqlite::Config::new(path.join(CRYPTO_STORE_DATABASE_NAME));
         let pool = cfg.create_pool(Runtime::Tokio1)?;
 
         Self::open_with_pool(pool, passphrase).await
@@ -119,6 +121,111 @@ impl SqliteCryptoStore {
         })
     }
 
+    /// Close the connections to the database of this store.
+    ///
+    /// The store, and all its clones, can't be used anymore afterwards. The
+    /// connections that are in use are closed once they are released.
+    pub fn close(&self) {
+        self.pool.close();
+    }
+
+    /// Change the passphrase that protects the encryption keys of this store.
+    ///
+    /// The data of the store doesn't need to be re-encrypted, so this is a
+    /// cheap operation. The store must have been opened with a passphrase.
+    pub async fn change_passphrase(&self, new_passphrase: &str) -> Result<(), OpenStoreError> {
+        let conn = self.pool.get().await?;
+        rekey::change_passphrase(&conn, self.store_cipher.as_deref(), new_passphrase).await
+    }
+
+    /// Re-encrypt all the data of the store at the given path with new
+    /// encryption keys, protected by the given passphrase.
+    ///
+    /// If the store has no passphrase, its data gets encrypted and its keys
+    /// hashed. Otherwise, the key used to encrypt the values is rotated.
+    ///
+    /// This is an offline operation: the store must not be open while it is
+    /// rekeyed, since the open handles would keep using the old encryption
+    /// keys. It can be opened with the new passphrase once this is done.
+    ///
+    /// The whole operation happens in a single transaction: if it is
+    /// interrupted, the store is left untouched and the operation can be
+    /// started again.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the store.
+    ///
+    /// * `old_passphrase` - The current passphrase of the store, if any.
+    ///
+    /// * `new_passphrase` - The passphrase that should protect the new
+    ///   encryption keys.
+    ///
+    /// # Limitations
+    ///
+    /// The key used to hash the keys of an encrypted store is not rotated,
+    /// since the original keys can't be recovered from their hashes. To rotate
+    /// it too, copy the data into a new store with [`migrate_crypto_store`].
+    ///
+    /// [`migrate_crypto_store`]: matrix_sdk_crypto::store::migrate_crypto_store
+    pub async fn rekey(
+        path: impl AsRef<Path>,
+        old_passphrase: Option<&str>,
+        new_passphrase: &str,
+    ) -> Result<(), OpenStoreError> {
+        let store = Self::open(path, old_passphrase).await?;
+        let conn = store.pool.get().await?;
+
+        let result =
+            rekey::rekey_store(&conn, REKEY_TABLES, store.store_cipher.clone(), new_passphrase)
+                .await;
+
+        drop(conn);
+        store.close();
+
+        result
+    }
+
+    /// Get statistics about the size of this store and of each of its tables.
+    pub async fn statistics(&self) -> Result<StoreStats> {
+        let conn = self.acquire().await?;
+        maintenance::store_stats(&conn).await
+    }
+
+    /// Check the integrity of this store.
+    ///
+    /// Besides checking the structure of the database, this verifies that
+    /// every value can be decrypted and deserialized. The rows that fail this
+    /// check are moved to a `quarantine` table, where they are kept for
+    /// inspection, so they don't cause errors when the store is used.
+    ///
+    /// Rows removed by the check might still be cached by this store, so it
+    /// should be reopened afterwards.
+    pub async fn check_integrity(&self) -> Result<IntegrityReport> {
+        let conn = self.acquire().await?;
+        maintenance::check_integrity(&conn, REKEY_TABLES, self.store_cipher.clone()).await
+    }
+
+    /// Move the rows that were quarantined by [`Self::check_integrity`] back
+    /// to their table, and return how many rows were restored.
+    ///
+    /// This is useful once the cause of the corruption has been fixed, for
+    /// example after an update of the SDK. Rows that are still corrupted are
+    /// quarantined again by the next integrity check.
+    pub async fn restore_quarantined_rows(&self) -> Result<u64> {
+        let conn = self.acquire().await?;
+        maintenance::restore_quarantined_rows(&conn, REKEY_TABLES).await
+    }
+
+    /// Reclaim the unused space of this store, and optimize its database.
+    ///
+    /// This can be done while the store is in use, but writes are blocked
+    /// until it is done, which can take a while for big stores.
+    pub async fn vacuum(&self) -> Result<()> {
+        let conn = self.acquire().await?;
+        maintenance::vacuum(&conn).await
+    }
+
     fn encode_value(&self, value: Vec<u8>) -> Result<Vec<u8>> {
         if let Some(key) = &self.store_cipher {
             let encrypted = key.encrypt_value_data(value)?;
@@ -196,7 +303,35 @@ impl SqliteCryptoStore {
     }
 }
 
-const DATABASE_VERSION: u8 = 8;
+const DATABASE_VERSION: u8 = 10;
+
+/// The tables of the crypto store that contain encrypted data.
+const REKEY_TABLES: &[RekeyTable] = &[
+    RekeyTable {
+        value_column: "value",
+        // This table contains custom values, whose format is unknown.
+        value_format: ValueFormat::Opaque,
+        condition: Some("key NOT IN ('cipher', 'version')"),
+        ..RekeyTable::new("kv", "kv", &[])
+    },
+    RekeyTable::new("session", "session", &["session_id", "sender_key"])
+        .with_format(ValueFormat::MessagePack),
+    RekeyTable::new("inbound_group_session", "inbound_group_session", &["session_id", "room_id"])
+        .with_format(ValueFormat::MessagePack),
+    RekeyTable::new("outbound_group_session", "outbound_group_session", &["room_id"]),
+    RekeyTable::new("device", "device", &["user_id", "device_id"])
+        .with_format(ValueFormat::MessagePack),
+    RekeyTable::new("identity", "identity", &["user_id"]).with_format(ValueFormat::MessagePack),
+    RekeyTable::new("tracked_user", "tracked_users", &["user_id"])
+        .with_format(ValueFormat::MessagePack),
+    RekeyTable::new("key_requests", "key_requests", &["request_id"])
+        .with_format(ValueFormat::MessagePack),
+    RekeyTable::new("room_settings", "room_settings", &["room_id"])
+        .with_format(ValueFormat::MessagePack),
+    RekeyTable::new("direct_withheld_info", "direct_withheld_info", &["session_id", "room_id"]),
+    RekeyTable::new("secrets", "secrets", &["secret_name"]),
+    QUARANTINE_TABLE,
+];
 
 /// Run migrations for the given version of the database.
 async fn run_migrations(conn: &SqliteConn, version: u8) -> Result<()> {
@@ -271,6 +406,20 @@ async fn run_migrations(conn: &SqliteConn, version: u8) -> Result<()> {
         .await?;
     }
 
+    if version < 9 {
+        conn.with_transaction(|txn| {
+            txn.execute_batch(include_str!("../migrations/crypto_store/009_quarantine.sql"))
+        })
+        .await?;
+    }
+
+    if version < 10 {
+        conn.with_transaction(|txn| {
+            txn.execute_batch(include_str!("../migrations/crypto_store/010_olm_hash_added_at.sql"))
+        })
+        .await?;
+    }
+
     conn.set_kv("version", vec![DATABASE_VERSION]).await?;
 
     Ok(())
@@ -299,7 +448,7 @@ trait SqliteConnectionExt {
 
     fn set_identity(&self, user_id: &[u8], data: &[u8]) -> rusqlite::Result<()>;
 
-    fn add_olm_hash(&self, data: &[u8]) -> rusqlite::Result<()>;
+    fn add_olm_hash(&self, data: &[u8], added_at: u64) -> rusqlite::Result<()>;
 
     fn set_key_request(
         &self,
@@ -390,8 +539,11 @@ impl SqliteConnectionExt for rusqlite::Connection {
         Ok(())
     }
 
-    fn add_olm_hash(&self, data: &[u8]) -> rusqlite::Result<()> {
-        self.execute("INSERT INTO olm_hash (data) VALUES (?) ON CONFLICT DO NOTHING", (data,))?;
+    fn add_olm_hash(&self, data: &[u8], added_at: u64) -> rusqlite::Result<()> {
+        self.execute(
+            "INSERT INTO olm_hash (data, added_at) VALUES (?, ?) ON CONFLICT DO NOTHING",
+            (data, added_at),
+        )?;
         Ok(())
     }
 
@@ -456,6 +608,19 @@ trait SqliteObjectCryptoStoreExt: SqliteObjectExt {
             .await?)
     }
 
+    async fn get_all_sessions(&self) -> Result<Vec<Vec<u8>>> {
+        Ok(self
+            .prepare("SELECT data FROM session", |mut stmt| {
+                stmt.query(())?.mapped(|row| row.get(0)).collect()
+            })
+            .await?)
+    }
+
+    async fn delete_sessions(&self, sender_key: Key) -> Result<()> {
+        self.execute("DELETE FROM session WHERE sender_key = ?", (sender_key,)).await?;
+        Ok(())
+    }
+
     async fn get_inbound_group_session(
         &self,
         session_id: Key,
@@ -478,6 +643,43 @@ trait SqliteObjectCryptoStoreExt: SqliteObjectExt {
             .await?)
     }
 
+    async fn get_inbound_group_sessions_batch(
+        &self,
+        after: Option<Key>,
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, bool)>> {
+        Ok(self
+            .prepare(
+                "SELECT data, backed_up FROM inbound_group_session \
+                 WHERE ?1 IS NULL OR session_id > ?1 ORDER BY session_id LIMIT ?2",
+                move |mut stmt| {
+                    stmt.query((after, limit))?
+                        .mapped(|row| Ok((row.get(0)?, row.get(1)?)))
+                        .collect()
+                },
+            )
+            .await?)
+    }
+
+    async fn delete_inbound_group_sessions(
+        &self,
+        room_id: Key,
+        session_ids: Vec<Key>,
+    ) -> Result<()> {
+        Ok(self
+            .prepare(
+                "DELETE FROM inbound_group_session WHERE session_id = ?1 AND room_id = ?2",
+                move |mut stmt| {
+                    for session_id in session_ids {
+                        stmt.execute((session_id, &room_id))?;
+                    }
+
+                    Ok(())
+                },
+            )
+            .await?)
+    }
+
     async fn get_inbound_group_session_counts(&self) -> Result<RoomKeyCounts> {
         let total = self
             .query_row("SELECT count(*) FROM inbound_group_session", (), |row| row.get(0))
@@ -552,6 +754,10 @@ trait SqliteObjectCryptoStoreExt: SqliteObjectExt {
             > 0)
     }
 
+    async fn clear_olm_hashes(&self, older_than: u64) -> Result<usize> {
+        Ok(self.execute("DELETE FROM olm_hash WHERE added_at < ?", (older_than,)).await?)
+    }
+
     async fn get_tracked_users(&self) -> Result<Vec<Vec<u8>>> {
         Ok(self
             .prepare("SELECT data FROM tracked_user", |mut stmt| {
@@ -817,9 +1023,11 @@ impl CryptoStore for SqliteCryptoStore {
                     txn.set_outbound_group_session(room_id, &serialized_session)?;
                 }
 
+                let now: u64 = SecondsSinceUnixEpoch::now().get().into();
+
                 for hash in &changes.message_hashes {
                     let hash = rmp_serde::to_vec(hash)?;
-                    txn.add_olm_hash(&hash)?;
+                    txn.add_olm_hash(&hash, now)?;
                 }
 
                 for request in changes.key_requests {
@@ -883,6 +1091,25 @@ impl CryptoStore for SqliteCryptoStore {
         Ok(self.session_cache.get(sender_key))
     }
 
+    async fn get_session_sender_keys(&self) -> Result<Vec<String>> {
+        let mut sender_keys = BTreeSet::new();
+
+        for value in self.acquire().await?.get_all_sessions().await? {
+            let pickle: PickledSession = self.deserialize_value(&value)?;
+            sender_keys.insert(pickle.sender_key.to_base64());
+        }
+
+        Ok(sender_keys.into_iter().collect())
+    }
+
+    async fn delete_sessions(&self, sender_key: &str) -> Result<()> {
+        let key = self.encode_key("session", sender_key.as_bytes());
+        self.acquire().await?.delete_sessions(key).await?;
+        self.session_cache.remove_for_sender(sender_key);
+
+        Ok(())
+    }
+
     #[instrument(skip(self))]
     async fn get_inbound_group_session(
         &self,
@@ -920,6 +1147,42 @@ impl CryptoStore for SqliteCryptoStore {
             .collect()
     }
 
+    async fn get_inbound_group_sessions_batch(
+        &self,
+        after: Option<(&RoomId, &str)>,
+        limit: usize,
+    ) -> Result<Vec<InboundGroupSession>> {
+        // The session ID is the primary key of the table, so it is enough to
+        // know where the previous batch ended.
+        let after =
+            after.map(|(_, session_id)| self.encode_key("inbound_group_session", session_id));
+
+        self.acquire()
+            .await?
+            .get_inbound_group_sessions_batch(after, limit)
+            .await?
+            .into_iter()
+            .map(|(value, backed_up)| {
+                let pickle = self.deserialize_pickled_inbound_group_session(&value, backed_up)?;
+                Ok(InboundGroupSession::from_pickle(pickle)?)
+            })
+            .collect()
+    }
+
+    async fn delete_inbound_group_sessions(
+        &self,
+        room_id: &RoomId,
+        session_ids: &[String],
+    ) -> Result<()> {
+        let room_id = self.encode_key("inbound_group_session", room_id.as_bytes());
+        let session_ids = session_ids
+            .iter()
+            .map(|session_id| self.encode_key("inbound_group_session", session_id))
+            .collect();
+
+        self.acquire().await?.delete_inbound_group_sessions(room_id, session_ids).await
+    }
+
     async fn inbound_group_session_counts(&self) -> Result<RoomKeyCounts> {
         Ok(self.acquire().await?.get_inbound_group_session_counts().await?)
     }
@@ -1060,6 +1323,10 @@ impl CryptoStore for SqliteCryptoStore {
         Ok(self.acquire().await?.has_olm_hash(value).await?)
     }
 
+    async fn clear_message_hashes(&self, older_than: SecondsSinceUnixEpoch) -> Result<usize> {
+        self.acquire().await?.clear_olm_hashes(older_than.get().into()).await
+    }
+
     async fn get_outgoing_secret_requests(
         &self,
         request_id: &TransactionId,
@@ -1101,6 +1368,16 @@ impl CryptoStore for SqliteCryptoStore {
             .collect()
     }
 
+    async fn get_all_secret_requests(&self) -> Result<Vec<GossipRequest>> {
+        self.acquire()
+            .await?
+            .get_outgoing_secret_requests()
+            .await?
+            .iter()
+            .map(|(value, sent_out)| self.deserialize_key_request(value, *sent_out))
+            .collect()
+    }
+
     async fn delete_outgoing_secret_requests(&self, request_id: &TransactionId) -> Result<()> {
         let request_id = self.encode_key("key_requests", request_id.as_bytes());
         Ok(self.acquire().await?.delete_key_request(request_id).await?)
@@ -1270,3 +1547,147 @@ mod encrypted_tests {
     cryptostore_integration_tests!();
     cryptostore_integration_tests_time!();
 }
+
+#[cfg(test)]
+mod rekey_tests {
+    use matrix_sdk_crypto::store::{Changes, CryptoStore};
+    use matrix_sdk_test::async_test;
+    use ruma::user_id;
+    use tempfile::tempdir;
+
+    use super::SqliteCryptoStore;
+
+    async fn populate(store: &SqliteCryptoStore) {
+        store.save_tracked_users(&[(user_id!("@alice:localhost"), true)]).await.unwrap();
+        store.set_custom_value("custom", b"value".to_vec()).await.unwrap();
+
+        let changes = Changes { next_batch_token: Some("token".to_owned()), ..Default::default() };
+        store.save_changes(changes).await.unwrap();
+    }
+
+    async fn assert_populated(store: &SqliteCryptoStore) {
+        let tracked_users = store.load_tracked_users().await.unwrap();
+        assert_eq!(tracked_users.len(), 1);
+        assert_eq!(tracked_users[0].user_id, user_id!("@alice:localhost"));
+        assert!(tracked_users[0].dirty);
+
+        assert_eq!(store.get_custom_value("custom").await.unwrap().unwrap(), b"value");
+        assert_eq!(store.next_batch_token().await.unwrap().as_deref(), Some("token"));
+    }
+
+    #[async_test]
+    async fn test_rekey() {
+        let dir = tempdir().unwrap();
+        let store = SqliteCryptoStore::open(dir.path(), None).await.unwrap();
+        populate(&store).await;
+        store.close();
+
+        // Encrypt the store.
+        SqliteCryptoStore::rekey(dir.path(), None, "secret").await.unwrap();
+
+        let store = SqliteCryptoStore::open(dir.path(), Some("secret")).await.unwrap();
+        assert_populated(&store).await;
+        store.close();
+
+        // Rotate the encryption key and change the passphrase at the same time.
+        SqliteCryptoStore::rekey(dir.path(), Some("secret"), "new secret").await.unwrap();
+
+        SqliteCryptoStore::open(dir.path(), Some("secret"))
+            .await
+            .expect_err("The old passphrase shouldn't work anymore");
+
+        let store = SqliteCryptoStore::open(dir.path(), Some("new secret")).await.unwrap();
+        assert_populated(&store).await;
+
+        store.change_passphrase("other secret").await.unwrap();
+        drop(store);
+
+        let store = SqliteCryptoStore::open(dir.path(), Some("other secret")).await.unwrap();
+        assert_populated(&store).await;
+    }
+}
+
+#[cfg(test)]
+mod maintenance_tests {
+    use matrix_sdk_crypto::store::CryptoStore;
+    use matrix_sdk_test::async_test;
+    use ruma::user_id;
+    use tempfile::tempdir;
+
+    use super::SqliteCryptoStore;
+    use crate::utils::SqliteObjectExt as _;
+
+    async fn tracked_user_data(store: &SqliteCryptoStore) -> Vec<u8> {
+        let conn = store.acquire().await.unwrap();
+        conn.query_row("SELECT data FROM tracked_user", (), |row| row.get(0)).await.unwrap()
+    }
+
+    #[async_test]
+    async fn test_check_integrity() {
+        let dir = tempdir().unwrap();
+        let store = SqliteCryptoStore::open(dir.path(), Some("secret")).await.unwrap();
+        store.save_tracked_users(&[(user_id!("@alice:localhost"), true)]).await.unwrap();
+        store.set_custom_value("custom", b"value".to_vec()).await.unwrap();
+
+        let report = store.check_integrity().await.unwrap();
+        assert!(report.is_ok(), "{report:?}");
+        assert!(report.checked_rows > 0);
+
+        // Corrupt the tracked user.
+        let data = tracked_user_data(&store).await;
+        let conn = store.acquire().await.unwrap();
+        conn.execute("UPDATE tracked_user SET data = X'00'", ()).await.unwrap();
+
+        let report = store.check_integrity().await.unwrap();
+        assert!(report.database_errors.is_empty());
+        assert_eq!(report.quarantined_rows.len(), 1);
+        assert_eq!(report.quarantined_rows[0].table, "tracked_user");
+        assert!(store.check_integrity().await.unwrap().is_ok());
+
+        // The corrupted row doesn't break the store anymore.
+        let store = SqliteCryptoStore::open(dir.path(), Some("secret")).await.unwrap();
+        assert!(store.load_tracked_users().await.unwrap().is_empty());
+        assert_eq!(store.get_custom_value("custom").await.unwrap().unwrap(), b"value");
+
+        // Once the quarantined row is repaired, it can be restored.
+        conn.execute("UPDATE quarantine SET data = ?", (data,)).await.unwrap();
+        assert_eq!(store.restore_quarantined_rows().await.unwrap(), 1);
+        assert!(store.check_integrity().await.unwrap().is_ok());
+
+        let store = SqliteCryptoStore::open(dir.path(), Some("secret")).await.unwrap();
+        let tracked_users = store.load_tracked_users().await.unwrap();
+        assert_eq!(tracked_users.len(), 1);
+        assert_eq!(tracked_users[0].user_id, user_id!("@alice:localhost"));
+    }
+
+    #[async_test]
+    async fn test_rekey_quarantined_rows() {
+        let dir = tempdir().unwrap();
+        let store = SqliteCryptoStore::open(dir.path(), None).await.unwrap();
+        store.save_tracked_users(&[(user_id!("@alice:localhost"), true)]).await.unwrap();
+
+        // Quarantine the tracked user with an invalid MessagePack value.
+        let data = tracked_user_data(&store).await;
+        let conn = store.acquire().await.unwrap();
+        conn.execute("UPDATE tracked_user SET data = X'C1'", ()).await.unwrap();
+        assert_eq!(store.check_integrity().await.unwrap().quarantined_rows.len(), 1);
+
+        // Repair the quarantined row, and encrypt the store.
+        conn.execute("UPDATE quarantine SET data = ?", (data,)).await.unwrap();
+        drop(conn);
+        store.close();
+        SqliteCryptoStore::rekey(dir.path(), None, "secret").await.unwrap();
+        let store = SqliteCryptoStore::open(dir.path(), Some("secret")).await.unwrap();
+
+        // The restored row has been encrypted, and its key hashed, like the
+        // other rows of its table.
+        assert_eq!(store.restore_quarantined_rows().await.unwrap(), 1);
+        assert!(store.check_integrity().await.unwrap().is_ok());
+        drop(store);
+
+        let store = SqliteCryptoStore::open(dir.path(), Some("secret")).await.unwrap();
+        let tracked_users = store.load_tracked_users().await.unwrap();
+        assert_eq!(tracked_users.len(), 1);
+        assert_eq!(tracked_users[0].user_id, user_id!("@alice:localhost"));
+    }
+}
@@ -63,6 +63,14 @@ pub enum OpenStoreError {
     /// Failed to save the store cipher to the DB.
     #[error("Failed to save the store cipher to the DB")]
     SaveCipher(#[source] rusqlite::Error),
+
+    /// The store isn't encrypted, so it has no passphrase to change.
+    #[error("The store isn't encrypted")]
+    NotEncrypted,
+
+    /// Failed to re-encrypt the content of the store.
+    #[error("Failed to re-encrypt the store")]
+    Rekey(#[source] Error),
 }
 
 #[derive(Debug, Error)]
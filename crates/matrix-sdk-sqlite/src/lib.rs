@@ -21,20 +21,31 @@ use std::path::Path;
 use deadpool_sqlite::Object as SqliteConn;
 use matrix_sdk_base::store::StoreConfig;
 use matrix_sdk_store_encryption::StoreCipher;
+use tokio::fs;
 
 #[cfg(feature = "crypto-store")]
 mod crypto_store;
 mod error;
+mod maintenance;
+mod rekey;
 #[cfg(feature = "state-store")]
 mod state_store;
 mod utils;
 
 #[cfg(feature = "crypto-store")]
 pub use self::crypto_store::SqliteCryptoStore;
-pub use self::error::OpenStoreError;
 #[cfg(feature = "state-store")]
 pub use self::state_store::SqliteStateStore;
 use self::utils::SqliteObjectStoreExt;
+pub use self::{
+    error::OpenStoreError,
+    maintenance::{IntegrityReport, QuarantinedRow, StoreStats, TableStats},
+};
+
+/// The name of the file of the state store's database.
+const STATE_STORE_DATABASE_NAME: &str = "matrix-sdk-state.sqlite3";
+/// The name of the file of the crypto store's database.
+const CRYPTO_STORE_DATABASE_NAME: &str = "matrix-sdk-crypto.sqlite3";
 
 async fn get_or_create_store_cipher(
     passphrase: &str,
@@ -46,17 +57,27 @@ async fn get_or_create_store_cipher(
         StoreCipher::import(passphrase, &encrypted)?
     } else {
         let cipher = StoreCipher::new()?;
-        #[cfg(not(test))]
-        let export = cipher.export(passphrase);
-        #[cfg(test)]
-        let export = cipher._insecure_export_fast_for_testing(passphrase);
-        conn.set_kv("cipher", export?).await.map_err(OpenStoreError::SaveCipher)?;
+        let export = export_store_cipher(&cipher, passphrase)?;
+        conn.set_kv("cipher", export).await.map_err(OpenStoreError::SaveCipher)?;
         cipher
     };
 
     Ok(cipher)
 }
 
+/// Export the given store cipher, encrypted with the given passphrase, to
+/// persist it in the database.
+fn export_store_cipher(
+    cipher: &StoreCipher,
+    passphrase: &str,
+) -> Result<Vec<u8>, matrix_sdk_store_encryption::Error> {
+    if cfg!(test) {
+        cipher._insecure_export_fast_for_testing(passphrase)
+    } else {
+        cipher.export(passphrase)
+    }
+}
+
 #[cfg(test)]
 #[ctor::ctor]
 fn init_logging() {
@@ -67,6 +88,36 @@ fn init_logging() {
         .init();
 }
 
+/// Delete the SQLite state and crypto stores in the given directory, with all
+/// their data.
+///
+/// Only the files of the stores are deleted, other files in the directory are
+/// left untouched. The directory itself is removed if it ends up empty.
+///
+/// The stores should be closed beforehand, see [`SqliteStateStore::close`]
+/// and `SqliteCryptoStore::close`. Otherwise, on Unix their files are deleted
+/// anyway and the data written to them afterwards is lost when they are
+/// closed, while other platforms, like Windows, return an error.
+pub async fn delete_stores(path: impl AsRef<Path>) -> std::io::Result<()> {
+    let path = path.as_ref();
+
+    for database in [STATE_STORE_DATABASE_NAME, CRYPTO_STORE_DATABASE_NAME] {
+        // SQLite keeps temporary files next to the database in WAL mode and
+        // while a transaction is in progress.
+        for suffix in ["", "-wal", "-shm", "-journal"] {
+            match fs::remove_file(path.join(format!("{database}{suffix}"))).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+    }
+
+    // This fails if the directory isn't empty, which is fine.
+    let _ = fs::remove_dir(path).await;
+
+    Ok(())
+}
+
 /// Create a [`StoreConfig`] with an opened [`SqliteStateStore`] in the given
 /// directory and using the given passphrase. If the `crypto-store` feature is
 /// enabled, a [`SqliteCryptoStore`] with the same parameters is also opened.
@@ -89,3 +140,34 @@ pub async fn make_store_config(
         Ok(config)
     }
 }
+
+#[cfg(all(test, feature = "state-store"))]
+mod tests {
+    use matrix_sdk_base::StateStore;
+    use matrix_sdk_test::async_test;
+    use tempfile::tempdir;
+
+    use super::{delete_stores, SqliteStateStore};
+
+    #[async_test]
+    async fn test_delete_stores() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("store");
+
+        let store = SqliteStateStore::open(&path, Some("secret")).await.unwrap();
+        store.set_custom_value(b"custom", b"value".to_vec()).await.unwrap();
+        drop(store);
+
+        // Unrelated files are kept.
+        std::fs::write(path.join("other"), b"other").unwrap();
+        delete_stores(&path).await.unwrap();
+        assert_eq!(std::fs::read_dir(&path).unwrap().count(), 1);
+
+        std::fs::remove_file(path.join("other")).unwrap();
+        delete_stores(&path).await.unwrap();
+        assert!(!path.exists());
+
+        let store = SqliteStateStore::open(&path, Some("other secret")).await.unwrap();
+        assert!(store.get_custom_value(b"custom").await.unwrap().is_none());
+    }
+}
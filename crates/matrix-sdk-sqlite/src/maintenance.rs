@@ -0,0 +1,496 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Diagnostics and maintenance of the stores: size statistics, integrity
+//! checks and vacuuming.
+
+use std::sync::Arc;
+
+use deadpool_sqlite::Object as SqliteConn;
+use matrix_sdk_store_encryption::StoreCipher;
+use rusqlite::{params_from_iter, types::Value, Transaction};
+use serde::{de::IgnoredAny, Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{
+    error::{Error, Result},
+    rekey::{decrypt, decrypt_table_value, encrypt, RekeyTable, ValueFormat},
+    utils::SqliteObjectExt as _,
+};
+
+/// Statistics about the size of a SQLite store.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct StoreStats {
+    /// The size of the database, in bytes.
+    ///
+    /// This doesn't include the write-ahead log.
+    pub database_size: u64,
+
+    /// The size of the unused pages of the database, in bytes.
+    ///
+    /// This space can be reclaimed by vacuuming the store.
+    pub free_size: u64,
+
+    /// The statistics of each table of the database.
+    pub tables: Vec<TableStats>,
+}
+
+/// Statistics about the size of a table of a SQLite store.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct TableStats {
+    /// The name of the table.
+    pub name: String,
+
+    /// The number of rows in the table.
+    pub row_count: u64,
+
+    /// The size of the data stored in the rows of the table, in bytes.
+    ///
+    /// This doesn't include the indexes and the overhead of SQLite's storage
+    /// format.
+    pub data_size: u64,
+}
+
+/// The result of an integrity check of a SQLite store.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct IntegrityReport {
+    /// The problems found by SQLite in the structure of the database.
+    ///
+    /// If this isn't empty, the database file is corrupted and the store
+    /// should be deleted.
+    pub database_errors: Vec<String>,
+
+    /// The number of values that were checked.
+    pub checked_rows: u64,
+
+    /// The rows whose value couldn't be decrypted or deserialized.
+    ///
+    /// These rows have been moved out of their table, to the `quarantine`
+    /// table. They can be moved back to their table with
+    /// `restore_quarantined_rows`, once the cause of the problem is fixed.
+    pub quarantined_rows: Vec<QuarantinedRow>,
+}
+
+impl IntegrityReport {
+    /// Whether no problem was found in the store.
+    pub fn is_ok(&self) -> bool {
+        self.database_errors.is_empty() && self.quarantined_rows.is_empty()
+    }
+}
+
+/// A row that failed the integrity check of a SQLite store.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct QuarantinedRow {
+    /// The name of the table containing the row.
+    pub table: String,
+
+    /// The reason why the check failed.
+    pub error: String,
+}
+
+/// Compute the size statistics of the database.
+pub(crate) async fn store_stats(conn: &SqliteConn) -> Result<StoreStats> {
+    conn.with_transaction(|txn| {
+        let page_size: u64 = txn.query_row("PRAGMA page_size", (), |row| row.get(0))?;
+        let page_count: u64 = txn.query_row("PRAGMA page_count", (), |row| row.get(0))?;
+        let freelist_count: u64 = txn.query_row("PRAGMA freelist_count", (), |row| row.get(0))?;
+
+        let table_names = txn
+            .prepare(
+                "SELECT name FROM sqlite_master \
+                 WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY name",
+            )?
+            .query_map((), |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let tables = table_names
+            .into_iter()
+            .map(|name| table_stats(txn, name))
+            .collect::<rusqlite::Result<_>>()?;
+
+        Result::<_, Error>::Ok(StoreStats {
+            database_size: page_count * page_size,
+            free_size: freelist_count * page_size,
+            tables,
+        })
+    })
+    .await
+}
+
+fn table_stats(txn: &Transaction<'_>, name: String) -> rusqlite::Result<TableStats> {
+    let columns = txn
+        .prepare(&format!("PRAGMA table_info(\"{name}\")"))?
+        .query_map((), |row| row.get::<_, String>("name"))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    // Cast the values to blobs to get their size in bytes rather than in
+    // characters.
+    let sizes: Vec<_> = columns
+        .iter()
+        .map(|c| format!("COALESCE(SUM(LENGTH(CAST(\"{c}\" AS BLOB))), 0)"))
+        .chain(["0".to_owned()])
+        .collect();
+
+    let (row_count, data_size) = txn.query_row(
+        &format!("SELECT COUNT(*), {} FROM \"{name}\"", sizes.join(" + ")),
+        (),
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+
+    Ok(TableStats { name, row_count, data_size })
+}
+
+/// The description of the `quarantine` table, where the rows that fail the
+/// integrity check are moved.
+///
+/// It must be part of the tables that are re-keyed, so the quarantined rows
+/// can still be restored once the store cipher changed, see
+/// [`rekey_quarantine`].
+pub(crate) const QUARANTINE_TABLE: RekeyTable =
+    RekeyTable::new("quarantine", "quarantine", &[]).with_format(ValueFormat::Opaque);
+
+/// The number of rows loaded at once by the integrity check.
+const CHECK_BATCH_SIZE: i64 = 1000;
+
+/// The value of a column of a quarantined row.
+///
+/// The `keys` column of the `quarantine` table contains all the columns of the
+/// row, except its value, so the row can be restored as it was.
+#[derive(Debug, Serialize, Deserialize)]
+enum ColumnValue {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+impl From<Value> for ColumnValue {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Null => Self::Null,
+            Value::Integer(i) => Self::Integer(i),
+            Value::Real(r) => Self::Real(r),
+            Value::Text(t) => Self::Text(t),
+            Value::Blob(b) => Self::Blob(b),
+        }
+    }
+}
+
+impl From<ColumnValue> for Value {
+    fn from(value: ColumnValue) -> Self {
+        match value {
+            ColumnValue::Null => Self::Null,
+            ColumnValue::Integer(i) => Self::Integer(i),
+            ColumnValue::Real(r) => Self::Real(r),
+            ColumnValue::Text(t) => Self::Text(t),
+            ColumnValue::Blob(b) => Self::Blob(b),
+        }
+    }
+}
+
+/// Check the integrity of the database, and that all the values of the given
+/// tables can be decrypted and deserialized.
+///
+/// The rows that fail the check are moved to the `quarantine` table.
+pub(crate) async fn check_integrity(
+    conn: &SqliteConn,
+    tables: &'static [RekeyTable],
+    store_cipher: Option<Arc<StoreCipher>>,
+) -> Result<IntegrityReport> {
+    conn.with_transaction(move |txn| {
+        let mut report = IntegrityReport {
+            database_errors: txn
+                .prepare("PRAGMA integrity_check")?
+                .query_map((), |row| row.get::<_, String>(0))?
+                .collect::<rusqlite::Result<Vec<_>>>()?,
+            ..Default::default()
+        };
+
+        // The check returns a single `ok` row if no problem was found.
+        if report.database_errors == ["ok"] {
+            report.database_errors.clear();
+        }
+
+        for table in tables.iter().filter(|t| t.name != QUARANTINE_TABLE.name) {
+            check_table(txn, table, store_cipher.as_deref(), &mut report)?;
+        }
+
+        Result::<_, Error>::Ok(report)
+    })
+    .await
+}
+
+fn check_table(
+    txn: &Transaction<'_>,
+    table: &RekeyTable,
+    store_cipher: Option<&StoreCipher>,
+    report: &mut IntegrityReport,
+) -> Result<()> {
+    let condition = table.condition.map(|c| format!(" AND ({c})")).unwrap_or_default();
+
+    let mut statement = txn.prepare(&format!(
+        "SELECT rowid, * FROM {} WHERE rowid > ?{condition} ORDER BY rowid LIMIT ?",
+        table.name
+    ))?;
+    let columns: Vec<String> =
+        statement.column_names().into_iter().skip(1).map(ToOwned::to_owned).collect();
+
+    // The rows are loaded in batches, so the whole table isn't held in memory.
+    let mut last_row_id = i64::MIN;
+
+    loop {
+        let rows = statement
+            .query_map((last_row_id, CHECK_BATCH_SIZE), |row| {
+                let row_id: i64 = row.get(0)?;
+                let values = (1..=columns.len())
+                    .map(|i| row.get::<_, Value>(i))
+                    .collect::<rusqlite::Result<Vec<_>>>()?;
+
+                Ok((row_id, values))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let Some((row_id, _)) = rows.last() else {
+            break;
+        };
+        last_row_id = *row_id;
+
+        for (row_id, values) in rows {
+            check_row(txn, table, store_cipher, &columns, row_id, values, report)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn check_row(
+    txn: &Transaction<'_>,
+    table: &RekeyTable,
+    store_cipher: Option<&StoreCipher>,
+    columns: &[String],
+    row_id: i64,
+    values: Vec<Value>,
+    report: &mut IntegrityReport,
+) -> Result<()> {
+    report.checked_rows += 1;
+
+    let mut row: Vec<(String, ColumnValue)> =
+        columns.iter().cloned().zip(values.into_iter().map(Into::into)).collect();
+
+    let Some(position) = row.iter().position(|(name, _)| name == table.value_column) else {
+        return Ok(());
+    };
+    let ColumnValue::Blob(value) = row.remove(position).1 else {
+        return Ok(());
+    };
+
+    let first_key = table.key_columns.first().and_then(|key_column| {
+        row.iter().find_map(|(name, value)| match value {
+            ColumnValue::Blob(key) if name == key_column => Some(key.as_slice()),
+            _ => None,
+        })
+    });
+
+    let Err(error) = check_value(table, store_cipher, first_key, &value) else {
+        return Ok(());
+    };
+
+    warn!(table = table.name, "Quarantining a corrupted row: {error}");
+
+    txn.execute(
+        "INSERT INTO quarantine (table_name, keys, data, error) VALUES (?, ?, ?, ?)",
+        (table.name, rmp_serde::to_vec(&row)?, &value, error.to_string()),
+    )?;
+    txn.execute(&format!("DELETE FROM {} WHERE rowid = ?", table.name), (row_id,))?;
+
+    report
+        .quarantined_rows
+        .push(QuarantinedRow { table: table.name.to_owned(), error: error.to_string() });
+
+    Ok(())
+}
+
+fn check_value(
+    table: &RekeyTable,
+    store_cipher: Option<&StoreCipher>,
+    first_key: Option<&[u8]>,
+    value: &[u8],
+) -> Result<()> {
+    let decrypted = match store_cipher {
+        Some(cipher) => match decrypt_table_value(table, cipher, value)? {
+            Some(decrypted) => decrypted,
+            // Values that are stored as-is have no known format.
+            None => return Ok(()),
+        },
+        None => {
+            let is_raw = table
+                .raw_value_key_prefix
+                .is_some_and(|prefix| first_key.is_some_and(|k| k.starts_with(prefix)));
+
+            if is_raw {
+                return Ok(());
+            }
+
+            value.to_owned()
+        }
+    };
+
+    match table.value_format {
+        ValueFormat::Json => {
+            serde_json::from_slice::<IgnoredAny>(&decrypted)?;
+        }
+        ValueFormat::MessagePack => {
+            rmp_serde::from_slice::<IgnoredAny>(&decrypted)?;
+        }
+        ValueFormat::Opaque => {}
+    }
+
+    Ok(())
+}
+
+/// A row of the `quarantine` table.
+struct QuarantineEntry {
+    row_id: i64,
+    table_name: String,
+    keys: Vec<u8>,
+    data: Vec<u8>,
+}
+
+fn quarantine_entries(txn: &Transaction<'_>) -> rusqlite::Result<Vec<QuarantineEntry>> {
+    txn.prepare("SELECT rowid, table_name, keys, data FROM quarantine")?
+        .query_map((), |row| {
+            Ok(QuarantineEntry {
+                row_id: row.get(0)?,
+                table_name: row.get(1)?,
+                keys: row.get(2)?,
+                data: row.get(3)?,
+            })
+        })?
+        .collect()
+}
+
+/// Move the quarantined rows back to their table, and return how many rows
+/// were restored.
+///
+/// A row that is still corrupted will be quarantined again by the next
+/// integrity check.
+pub(crate) async fn restore_quarantined_rows(
+    conn: &SqliteConn,
+    tables: &'static [RekeyTable],
+) -> Result<u64> {
+    conn.with_transaction(move |txn| {
+        let mut restored = 0;
+
+        for entry in quarantine_entries(txn)? {
+            let Some(table) = tables
+                .iter()
+                .find(|t| t.name == entry.table_name && t.name != QUARANTINE_TABLE.name)
+            else {
+                warn!(table = entry.table_name, "Can't restore a row of an unknown table");
+                continue;
+            };
+
+            let row: Vec<(String, ColumnValue)> = rmp_serde::from_slice(&entry.keys)?;
+
+            let columns: Vec<_> = row
+                .iter()
+                .map(|(name, _)| format!("\"{name}\""))
+                .chain([format!("\"{}\"", table.value_column)])
+                .collect();
+            let placeholders = vec!["?"; columns.len()].join(", ");
+            let params = row
+                .into_iter()
+                .map(|(_, value)| Value::from(value))
+                .chain([Value::Blob(entry.data)]);
+
+            txn.execute(
+                &format!(
+                    "INSERT OR REPLACE INTO {} ({}) VALUES ({placeholders})",
+                    table.name,
+                    columns.join(", ")
+                ),
+                params_from_iter(params),
+            )?;
+            txn.execute("DELETE FROM quarantine WHERE rowid = ?", (entry.row_id,))?;
+
+            restored += 1;
+        }
+
+        Result::<_, Error>::Ok(restored)
+    })
+    .await
+}
+
+/// Re-encrypt the quarantined rows with the `new` store cipher.
+///
+/// This works like the re-keying of the other tables, except that the keys of
+/// the quarantined rows are hashed with the hash table name of the table they
+/// come from, and that the values that can't be decrypted are kept as-is,
+/// since they are corrupted anyway.
+pub(crate) fn rekey_quarantine(
+    txn: &Transaction<'_>,
+    tables: &[RekeyTable],
+    old: Option<&StoreCipher>,
+    new: &StoreCipher,
+) -> Result<()> {
+    for entry in quarantine_entries(txn)? {
+        let Some(table) = tables.iter().find(|t| t.name == entry.table_name) else {
+            continue;
+        };
+
+        let (keys, data) = match old {
+            Some(old) => {
+                let data = match decrypt(old, &entry.data) {
+                    Ok(decrypted) => encrypt(new, decrypted)?,
+                    Err(_) => entry.data,
+                };
+
+                (entry.keys, data)
+            }
+            None => {
+                let mut row: Vec<(String, ColumnValue)> = rmp_serde::from_slice(&entry.keys)?;
+
+                for (name, value) in &mut row {
+                    if let ColumnValue::Blob(key) = value {
+                        if table.key_columns.contains(&name.as_str()) {
+                            *key = new.hash_key(table.hash_table_name, key).to_vec();
+                        }
+                    }
+                }
+
+                (rmp_serde::to_vec(&row)?, encrypt(new, entry.data)?)
+            }
+        };
+
+        txn.execute(
+            "UPDATE quarantine SET keys = ?, data = ? WHERE rowid = ?",
+            (keys, data, entry.row_id),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Reclaim the unused space of the database, and optimize it.
+pub(crate) async fn vacuum(conn: &SqliteConn) -> Result<()> {
+    // `VACUUM` can't run inside a transaction. The checkpoint truncates the
+    // write-ahead log, which could have grown during the vacuum.
+    conn.execute_batch("PRAGMA optimize; VACUUM; PRAGMA wal_checkpoint(TRUNCATE);").await?;
+    Ok(())
+}
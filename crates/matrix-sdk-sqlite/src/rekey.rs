@@ -0,0 +1,245 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Re-encryption of the content of a store with a new store cipher.
+
+use std::sync::Arc;
+
+use deadpool_sqlite::Object as SqliteConn;
+use matrix_sdk_store_encryption::StoreCipher;
+use rusqlite::{params_from_iter, types::Value, Transaction};
+
+use crate::{
+    error::{Error, Result},
+    export_store_cipher,
+    maintenance::{rekey_quarantine, QUARANTINE_TABLE},
+    utils::{SqliteConnectionExt as _, SqliteObjectExt as _, SqliteObjectStoreExt as _},
+    OpenStoreError,
+};
+
+/// A table whose content needs to be transformed when the store cipher
+/// changes.
+///
+/// This description is also used to check the integrity of the values of the
+/// table, see [`crate::maintenance`].
+pub(crate) struct RekeyTable {
+    /// The name of the table in the database.
+    pub name: &'static str,
+    /// The table name that is used to hash the keys of this table with
+    /// [`StoreCipher::hash_key`].
+    pub hash_table_name: &'static str,
+    /// The columns containing keys that are hashed when the store is
+    /// encrypted.
+    pub key_columns: &'static [&'static str],
+    /// The column containing the value that is encrypted when the store is
+    /// encrypted.
+    pub value_column: &'static str,
+    /// The format of the values, once decrypted.
+    pub value_format: ValueFormat,
+    /// A SQL condition to only select some of the rows of the table.
+    pub condition: Option<&'static str>,
+    /// The prefix of the keys, in the first key column, whose values are
+    /// stored as-is, even in an encrypted store.
+    pub raw_value_key_prefix: Option<&'static [u8]>,
+}
+
+impl RekeyTable {
+    /// A table whose values are JSON, in the `data` column.
+    pub const fn new(
+        name: &'static str,
+        hash_table_name: &'static str,
+        key_columns: &'static [&'static str],
+    ) -> Self {
+        Self {
+            name,
+            hash_table_name,
+            key_columns,
+            value_column: "data",
+            value_format: ValueFormat::Json,
+            condition: None,
+            raw_value_key_prefix: None,
+        }
+    }
+
+    /// Set the format of the values of this table.
+    pub const fn with_format(self, value_format: ValueFormat) -> Self {
+        Self { value_format, ..self }
+    }
+}
+
+/// The serialization format of the values of a table.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum ValueFormat {
+    /// The values are serialized as JSON.
+    Json,
+    /// The values are serialized as MessagePack.
+    MessagePack,
+    /// The values are stored as they were given to the store.
+    Opaque,
+}
+
+/// Persist the given store cipher encrypted with a new passphrase.
+pub(crate) async fn change_passphrase(
+    conn: &SqliteConn,
+    store_cipher: Option<&StoreCipher>,
+    new_passphrase: &str,
+) -> Result<(), OpenStoreError> {
+    let store_cipher = store_cipher.ok_or(OpenStoreError::NotEncrypted)?;
+    let export = export_store_cipher(store_cipher, new_passphrase)?;
+
+    conn.set_kv("cipher", export).await.map_err(OpenStoreError::SaveCipher)
+}
+
+/// Re-encrypt the given tables with a new store cipher, protected by the given
+/// passphrase.
+///
+/// If the store was already encrypted, its encryption key is rotated,
+/// otherwise a new store cipher is created. Everything happens in a single
+/// transaction, so if it is interrupted the store is left untouched and the
+/// operation can be started again.
+pub(crate) async fn rekey_store(
+    conn: &SqliteConn,
+    tables: &'static [RekeyTable],
+    old: Option<Arc<StoreCipher>>,
+    passphrase: &str,
+) -> Result<(), OpenStoreError> {
+    let new = match &old {
+        Some(old) => old.rotate_encryption_key()?,
+        None => StoreCipher::new()?,
+    };
+    let export = export_store_cipher(&new, passphrase)?;
+
+    conn.with_transaction(move |txn| {
+        rekey_tables(txn, tables, old.as_deref(), &new)?;
+        txn.set_kv("cipher", &export)?;
+
+        Result::<_, Error>::Ok(())
+    })
+    .await
+    .map_err(OpenStoreError::Rekey)
+}
+
+/// Re-encrypt the given tables with the `new` store cipher.
+///
+/// If `old` is `None`, the store wasn't encrypted until now, so the keys are
+/// hashed in addition to encrypting the values. Otherwise, the `new` store
+/// cipher must have been created with [`StoreCipher::rotate_encryption_key`],
+/// so the keys are already hashed correctly and only the values are
+/// re-encrypted.
+fn rekey_tables(
+    txn: &Transaction<'_>,
+    tables: &[RekeyTable],
+    old: Option<&StoreCipher>,
+    new: &StoreCipher,
+) -> Result<()> {
+    for table in tables {
+        if table.name == QUARANTINE_TABLE.name {
+            rekey_quarantine(txn, tables, old, new)?;
+        } else {
+            rekey_table(txn, table, old, new)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn rekey_table(
+    txn: &Transaction<'_>,
+    table: &RekeyTable,
+    old: Option<&StoreCipher>,
+    new: &StoreCipher,
+) -> Result<()> {
+    let condition = table.condition.map(|c| format!(" WHERE {c}")).unwrap_or_default();
+    let row_ids = txn
+        .prepare(&format!("SELECT rowid FROM {}{condition}", table.name))?
+        .query_map((), |row| row.get::<_, i64>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    let key_columns: &[&str] = if old.is_none() { table.key_columns } else { &[] };
+    let columns: Vec<&str> = key_columns.iter().copied().chain([table.value_column]).collect();
+
+    let select = format!("SELECT {} FROM {} WHERE rowid = ?", columns.join(", "), table.name);
+    let assignments: Vec<_> = columns.iter().map(|c| format!("{c} = ?")).collect();
+    let update = format!("UPDATE {} SET {} WHERE rowid = ?", table.name, assignments.join(", "));
+
+    for row_id in row_ids {
+        let (keys, value) = txn.prepare_cached(&select)?.query_row((row_id,), |row| {
+            let keys = (0..key_columns.len())
+                .map(|i| row.get::<_, Option<Vec<u8>>>(i))
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            let value: Vec<u8> = row.get(key_columns.len())?;
+
+            Ok((keys, value))
+        })?;
+
+        let value = match old {
+            Some(old) => match decrypt_table_value(table, old, &value)? {
+                Some(decrypted) => encrypt(new, decrypted)?,
+                None => value,
+            },
+            None => {
+                let is_raw = table.raw_value_key_prefix.is_some_and(|prefix| {
+                    keys.first().and_then(Option::as_deref).is_some_and(|k| k.starts_with(prefix))
+                });
+
+                if is_raw {
+                    value
+                } else {
+                    encrypt(new, value)?
+                }
+            }
+        };
+
+        let params = keys
+            .into_iter()
+            .map(|key| match key {
+                Some(key) => Value::Blob(new.hash_key(table.hash_table_name, &key).to_vec()),
+                None => Value::Null,
+            })
+            .chain([Value::Blob(value), Value::Integer(row_id)]);
+
+        txn.prepare_cached(&update)?.execute(params_from_iter(params))?;
+    }
+
+    Ok(())
+}
+
+pub(crate) fn encrypt(cipher: &StoreCipher, value: Vec<u8>) -> Result<Vec<u8>> {
+    let encrypted = cipher.encrypt_value_data(value)?;
+    Ok(rmp_serde::to_vec_named(&encrypted)?)
+}
+
+/// Decrypt a value of the given table of an encrypted store.
+///
+/// Returns `None` if the value isn't an encrypted value, which only happens
+/// for the values that the table stores as-is, see
+/// [`RekeyTable::raw_value_key_prefix`]. Their keys are hashed, so the raw
+/// values are recognized by their format instead. A value that is encrypted
+/// but can't be decrypted is an error.
+pub(crate) fn decrypt_table_value(
+    table: &RekeyTable,
+    cipher: &StoreCipher,
+    value: &[u8],
+) -> Result<Option<Vec<u8>>> {
+    match rmp_serde::from_slice(value) {
+        Ok(encrypted) => Ok(Some(cipher.decrypt_value_data(encrypted)?)),
+        Err(_) if table.raw_value_key_prefix.is_some() => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub(crate) fn decrypt(cipher: &StoreCipher, value: &[u8]) -> Result<Vec<u8>> {
+    let encrypted = rmp_serde::from_slice(value)?;
+    Ok(cipher.decrypt_value_data(encrypted)?)
+}
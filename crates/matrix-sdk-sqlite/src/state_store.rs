@@ -29,7 +29,8 @@ use ruma::{
         GlobalAccountDataEventType, RoomAccountDataEventType, StateEventType,
     },
     serde::Raw,
-    CanonicalJsonObject, EventId, OwnedEventId, OwnedUserId, RoomId, RoomVersionId, UserId,
+    CanonicalJsonObject, EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedUserId, RoomId,
+    RoomVersionId, UserId,
 };
 use rusqlite::{limits::Limit, OptionalExtension, Transaction};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
@@ -39,8 +40,10 @@ use tracing::{debug, warn};
 use crate::{
     error::{Error, Result},
     get_or_create_store_cipher,
+    maintenance::{self, IntegrityReport, StoreStats, QUARANTINE_TABLE},
+    rekey::{self, RekeyTable, ValueFormat},
     utils::{load_db_version, Key, SqliteObjectExt},
-    OpenStoreError, SqliteObjectStoreExt,
+    OpenStoreError, SqliteObjectStoreExt, STATE_STORE_DATABASE_NAME,
 };
 
 mod keys {
@@ -57,7 +60,36 @@ mod keys {
     pub const MEDIA: &str = "media";
 }
 
-const DATABASE_VERSION: u8 = 2;
+const DATABASE_VERSION: u8 = 4;
+
+/// The tables of the state store that contain encrypted data.
+const REKEY_TABLES: &[RekeyTable] = &[
+    RekeyTable {
+        value_column: "value",
+        value_format: ValueFormat::MessagePack,
+        raw_value_key_prefix: Some(b"custom:"),
+        ..RekeyTable::new("kv_blob", keys::KV_BLOB, &["key"])
+    },
+    RekeyTable::new("room_info", keys::ROOM_INFO, &["room_id", "state"]),
+    RekeyTable::new(
+        "state_event",
+        keys::STATE_EVENT,
+        &["room_id", "event_type", "state_key", "event_id"],
+    ),
+    RekeyTable::new("global_account_data", keys::GLOBAL_ACCOUNT_DATA, &["event_type"]),
+    RekeyTable::new("room_account_data", keys::ROOM_ACCOUNT_DATA, &["room_id", "event_type"]),
+    RekeyTable::new("member", keys::MEMBER, &["room_id", "user_id", "membership"])
+        .with_format(ValueFormat::MessagePack),
+    RekeyTable::new("profile", keys::PROFILE, &["room_id", "user_id"]),
+    RekeyTable::new(
+        "receipt",
+        keys::RECEIPT,
+        &["room_id", "user_id", "receipt_type", "thread", "event_id"],
+    ),
+    RekeyTable::new("display_name", keys::DISPLAY_NAME, &["room_id", "name"]),
+    RekeyTable::new("media", keys::MEDIA, &["uri", "format"]).with_format(ValueFormat::Opaque),
+    QUARANTINE_TABLE,
+];
 
 /// A sqlite based cryptostore.
 #[derive(Clone)]
@@ -113,6 +145,108 @@ impl SqliteStateStore {
         Ok(this)
     }
 
+    /// Close the connections to the database of this store.
+    ///
+    /// The store, and all its clones, can't be used anymore afterwards. The
+    /// connections that are in use are closed once they are released.
+    pub fn close(&self) {
+        self.pool.close();
+    }
+
+    /// Change the passphrase that protects the encryption keys of this store.
+    ///
+    /// The data of the store doesn't need to be re-encrypted, so this is a
+    /// cheap operation. The store must have been opened with a passphrase.
+    pub async fn change_passphrase(&self, new_passphrase: &str) -> Result<(), OpenStoreError> {
+        let conn = self.pool.get().await?;
+        rekey::change_passphrase(&conn, self.store_cipher.as_deref(), new_passphrase).await
+    }
+
+    /// Re-encrypt all the data of the store at the given path with new
+    /// encryption keys, protected by the given passphrase.
+    ///
+    /// If the store has no passphrase, its data gets encrypted and its keys
+    /// hashed. Otherwise, the key used to encrypt the values is rotated.
+    ///
+    /// This is an offline operation: the store must not be open while it is
+    /// rekeyed, since the open handles would keep using the old encryption
+    /// keys. It can be opened with the new passphrase once this is done.
+    ///
+    /// The whole operation happens in a single transaction: if it is
+    /// interrupted, the store is left untouched and the operation can be
+    /// started again.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the store.
+    ///
+    /// * `old_passphrase` - The current passphrase of the store, if any.
+    ///
+    /// * `new_passphrase` - The passphrase that should protect the new
+    ///   encryption keys.
+    ///
+    /// # Limitations
+    ///
+    /// The key used to hash the keys of an encrypted store is not rotated,
+    /// since the original keys can't be recovered from their hashes. To rotate
+    /// it too, copy the data into a new store with [`migrate_state_store`].
+    ///
+    /// [`migrate_state_store`]: matrix_sdk_base::store::migrate_state_store
+    pub async fn rekey(
+        path: impl AsRef<Path>,
+        old_passphrase: Option<&str>,
+        new_passphrase: &str,
+    ) -> Result<(), OpenStoreError> {
+        let store = Self::open(path, old_passphrase).await?;
+        let conn = store.pool.get().await?;
+
+        let result =
+            rekey::rekey_store(&conn, REKEY_TABLES, store.store_cipher.clone(), new_passphrase)
+                .await;
+
+        drop(conn);
+        store.close();
+
+        result
+    }
+
+    /// Get statistics about the size of this store and of each of its tables.
+    pub async fn statistics(&self) -> Result<StoreStats> {
+        let conn = self.acquire().await?;
+        maintenance::store_stats(&conn).await
+    }
+
+    /// Check the integrity of this store.
+    ///
+    /// Besides checking the structure of the database, this verifies that
+    /// every value can be decrypted and deserialized. The rows that fail this
+    /// check are moved to a `quarantine` table, where they are kept for
+    /// inspection, so they don't cause errors when the store is used.
+    pub async fn check_integrity(&self) -> Result<IntegrityReport> {
+        let conn = self.acquire().await?;
+        maintenance::check_integrity(&conn, REKEY_TABLES, self.store_cipher.clone()).await
+    }
+
+    /// Move the rows that were quarantined by [`Self::check_integrity`] back
+    /// to their table, and return how many rows were restored.
+    ///
+    /// This is useful once the cause of the corruption has been fixed, for
+    /// example after an update of the SDK. Rows that are still corrupted are
+    /// quarantined again by the next integrity check.
+    pub async fn restore_quarantined_rows(&self) -> Result<u64> {
+        let conn = self.acquire().await?;
+        maintenance::restore_quarantined_rows(&conn, REKEY_TABLES).await
+    }
+
+    /// Reclaim the unused space of this store, and optimize its database.
+    ///
+    /// This can be done while the store is in use, but writes are blocked
+    /// until it is done, which can take a while for big stores.
+    pub async fn vacuum(&self) -> Result<()> {
+        let conn = self.acquire().await?;
+        maintenance::vacuum(&conn).await
+    }
+
     /// Run database migrations from the given `from` version to the given `to`
     /// version
     ///
@@ -162,6 +296,20 @@ impl SqliteStateStore {
             .await?;
         }
 
+        if from < 3 && to >= 3 {
+            conn.with_transaction(|txn| {
+                txn.execute_batch(include_str!("../migrations/state_store/003_lock_leases.sql"))
+            })
+            .await?;
+        }
+
+        if from < 4 && to >= 4 {
+            conn.with_transaction(|txn| {
+                txn.execute_batch(include_str!("../migrations/state_store/004_quarantine.sql"))
+            })
+            .await?;
+        }
+
         conn.set_kv("version", vec![to]).await?;
 
         Ok(())
@@ -259,7 +407,7 @@ impl SqliteStateStore {
 
 async fn create_pool(path: &Path) -> Result<SqlitePool, OpenStoreError> {
     fs::create_dir_all(path).await.map_err(OpenStoreError::CreateDir)?;
-    let cfg = deadpool_sqlite::Config::new(path.join("matrix-sdk-state.sqlite3"));
+    let cfg = deadpool_sqlite::Config::new(path.join(STATE_STORE_DATABASE_NAME));
     Ok(cfg.create_pool(Runtime::Tokio1)?)
 }
 
@@ -640,6 +788,17 @@ trait SqliteObjectStateStoreExt: SqliteObjectExt {
             .await?)
     }
 
+    async fn get_all_maybe_stripped_state_events(
+        &self,
+        room_id: Key,
+    ) -> Result<Vec<(bool, Vec<u8>)>> {
+        Ok(self
+            .prepare("SELECT stripped, data FROM state_event WHERE room_id = ?", |mut stmt| {
+                stmt.query((room_id,))?.mapped(|row| Ok((row.get(0)?, row.get(1)?))).collect()
+            })
+            .await?)
+    }
+
     async fn get_profiles(
         &self,
         room_id: Key,
@@ -699,6 +858,14 @@ trait SqliteObjectStateStoreExt: SqliteObjectExt {
             .optional()?)
     }
 
+    async fn get_all_global_account_data(&self) -> Result<Vec<Vec<u8>>> {
+        Ok(self
+            .prepare("SELECT data FROM global_account_data", |mut stmt| {
+                stmt.query(())?.mapped(|row| row.get(0)).collect()
+            })
+            .await?)
+    }
+
     async fn get_room_account_data(
         &self,
         room_id: Key,
@@ -714,6 +881,14 @@ trait SqliteObjectStateStoreExt: SqliteObjectExt {
             .optional()?)
     }
 
+    async fn get_all_room_account_data(&self, room_id: Key) -> Result<Vec<Vec<u8>>> {
+        Ok(self
+            .prepare("SELECT data FROM room_account_data WHERE room_id = ?", |mut stmt| {
+                stmt.query((room_id,))?.mapped(|row| row.get(0)).collect()
+            })
+            .await?)
+    }
+
     async fn get_display_names(
         &self,
         room_id: Key,
@@ -774,6 +949,15 @@ trait SqliteObjectStateStoreExt: SqliteObjectExt {
             .await?)
     }
 
+    async fn get_room_receipts(&self, room_id: Key, receipt_type: Key) -> Result<Vec<Vec<u8>>> {
+        Ok(self
+            .prepare(
+                "SELECT data FROM receipt WHERE room_id = ? AND receipt_type = ?",
+                |mut stmt| stmt.query((room_id, receipt_type))?.mapped(|row| row.get(0)).collect(),
+            )
+            .await?)
+    }
+
     async fn set_media(&self, uri: Key, format: Key, data: Vec<u8>) -> Result<()> {
         self.execute(
             "INSERT OR REPLACE INTO media (uri, format, data) VALUES (?, ?, ?)",
@@ -1463,6 +1647,72 @@ impl StateStore for SqliteStateStore {
             .collect()
     }
 
+    async fn get_all_state_events(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<RawAnySyncOrStrippedState>> {
+        let room_id = self.encode_key(keys::STATE_EVENT, room_id);
+        self.acquire()
+            .await?
+            .get_all_maybe_stripped_state_events(room_id)
+            .await?
+            .into_iter()
+            .map(|(stripped, data)| {
+                let ev = if stripped {
+                    RawAnySyncOrStrippedState::Stripped(self.deserialize_json(&data)?)
+                } else {
+                    RawAnySyncOrStrippedState::Sync(self.deserialize_json(&data)?)
+                };
+
+                Ok(ev)
+            })
+            .collect()
+    }
+
+    async fn get_all_account_data_events(&self) -> Result<Vec<Raw<AnyGlobalAccountDataEvent>>> {
+        self.acquire()
+            .await?
+            .get_all_global_account_data()
+            .await?
+            .iter()
+            .map(|value| self.deserialize_json(value))
+            .collect()
+    }
+
+    async fn get_all_room_account_data_events(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<Raw<AnyRoomAccountDataEvent>>> {
+        let room_id = self.encode_key(keys::ROOM_ACCOUNT_DATA, room_id);
+        self.acquire()
+            .await?
+            .get_all_room_account_data(room_id)
+            .await?
+            .iter()
+            .map(|value| self.deserialize_json(value))
+            .collect()
+    }
+
+    async fn get_room_receipt_events(
+        &self,
+        room_id: &RoomId,
+        receipt_type: ReceiptType,
+    ) -> Result<Vec<(OwnedUserId, OwnedEventId, Receipt)>> {
+        let room_id = self.encode_key(keys::RECEIPT, room_id);
+        let receipt_type = self.encode_key(keys::RECEIPT, receipt_type.to_string());
+
+        self.acquire()
+            .await?
+            .get_room_receipts(room_id, receipt_type)
+            .await?
+            .iter()
+            .map(|value| {
+                self.deserialize_json::<ReceiptData>(value)
+                    .map(|d| (d.user_id, d.event_id, d.receipt))
+            })
+            .collect()
+    }
+
     async fn get_custom_value(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
         self.acquire().await?.get_kv_blob(self.encode_custom_key(key)).await
     }
@@ -1542,6 +1792,39 @@ impl StateStore for SqliteStateStore {
             })
             .await
     }
+
+    async fn try_take_leased_lock(
+        &self,
+        lease_duration_ms: u32,
+        key: &str,
+        holder: &str,
+    ) -> Result<bool> {
+        let key = key.to_owned();
+        let holder = holder.to_owned();
+
+        let now_ts: u64 = MilliSecondsSinceUnixEpoch::now().get().into();
+        let expiration_ts = now_ts + lease_duration_ms as u64;
+
+        let num_touched = self
+            .acquire()
+            .await?
+            .with_transaction(move |txn| {
+                txn.execute(
+                    "INSERT INTO lease_locks (key, holder, expiration_ts)
+                    VALUES (?1, ?2, ?3)
+                    ON CONFLICT (key)
+                    DO
+                        UPDATE SET holder = ?2, expiration_ts = ?3
+                        WHERE holder = ?2
+                        OR expiration_ts < ?4
+                ",
+                    (key, holder, expiration_ts, now_ts),
+                )
+            })
+            .await?;
+
+        Ok(num_touched == 1)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -1644,6 +1927,203 @@ mod encrypted_tests {
     statestore_integration_tests!(with_media_tests);
 }
 
+#[cfg(test)]
+mod rekey_tests {
+    use std::{
+        path::PathBuf,
+        sync::atomic::{AtomicU32, Ordering::SeqCst},
+    };
+
+    use assert_matches::assert_matches;
+    use matrix_sdk_base::{
+        store::{IntoStateStore, StateStoreIntegrationTests},
+        StateStore, StateStoreDataKey,
+    };
+    use matrix_sdk_store_encryption::StoreCipher;
+    use matrix_sdk_test::async_test;
+    use once_cell::sync::Lazy;
+    use ruma::{room_id, user_id};
+    use tempfile::{tempdir, TempDir};
+
+    use super::{SqliteObjectStateStoreExt, SqliteStateStore};
+    use crate::OpenStoreError;
+
+    static TMP_DIR: Lazy<TempDir> = Lazy::new(|| tempdir().unwrap());
+    static NUM: AtomicU32 = AtomicU32::new(0);
+
+    fn new_path() -> PathBuf {
+        let name = NUM.fetch_add(1, SeqCst).to_string();
+        TMP_DIR.path().join(name)
+    }
+
+    async fn populate(store: &SqliteStateStore) {
+        store.clone().into_state_store().populate().await.unwrap();
+        store.set_custom_value(b"custom", b"value".to_vec()).await.unwrap();
+    }
+
+    async fn assert_populated(store: &SqliteStateStore) {
+        let user_id = user_id!("@example:localhost");
+
+        assert!(store.get_kv_data(StateStoreDataKey::SyncToken).await.unwrap().is_some());
+        assert!(store.get_presence_event(user_id).await.unwrap().is_some());
+        assert_eq!(store.get_room_infos().await.unwrap().len(), 2);
+        assert!(store
+            .get_member_event(room_id!("!test:localhost"), user_id)
+            .await
+            .unwrap()
+            .is_some());
+        assert_eq!(store.get_custom_value(b"custom").await.unwrap().unwrap(), b"value");
+    }
+
+    #[async_test]
+    pub async fn test_rekey() {
+        let path = new_path();
+        let store = SqliteStateStore::open(&path, None).await.unwrap();
+        populate(&store).await;
+        store.close();
+
+        // Encrypt the store.
+        SqliteStateStore::rekey(&path, None, "secret").await.unwrap();
+
+        let store = SqliteStateStore::open(&path, Some("secret")).await.unwrap();
+        assert_populated(&store).await;
+        store.close();
+
+        // Rotate the encryption key.
+        SqliteStateStore::rekey(&path, Some("secret"), "secret").await.unwrap();
+
+        let store = SqliteStateStore::open(&path, Some("secret")).await.unwrap();
+        assert_populated(&store).await;
+    }
+
+    #[async_test]
+    pub async fn test_rekey_undecryptable_value() {
+        let path = new_path();
+        let store = SqliteStateStore::open(&path, Some("secret")).await.unwrap();
+        populate(&store).await;
+
+        // A value encrypted with another key in the table that also contains
+        // raw values.
+        let other_cipher = StoreCipher::new().unwrap();
+        let value = other_cipher.encrypt_value_data(b"token".to_vec()).unwrap();
+        let key = store.encode_state_store_data_key(StateStoreDataKey::SyncToken);
+        store
+            .acquire()
+            .await
+            .unwrap()
+            .set_kv_blob(key, rmp_serde::to_vec_named(&value).unwrap())
+            .await
+            .unwrap();
+
+        store.close();
+
+        assert_matches!(
+            SqliteStateStore::rekey(&path, Some("secret"), "secret").await,
+            Err(OpenStoreError::Rekey(_))
+        );
+
+        // The store was left untouched.
+        let store = SqliteStateStore::open(&path, Some("secret")).await.unwrap();
+        assert_eq!(store.get_custom_value(b"custom").await.unwrap().unwrap(), b"value");
+    }
+
+    #[async_test]
+    pub async fn test_change_passphrase() {
+        let store = SqliteStateStore::open(new_path(), None).await.unwrap();
+        assert_matches!(store.change_passphrase("secret").await, Err(OpenStoreError::NotEncrypted));
+
+        let path = new_path();
+        let store = SqliteStateStore::open(&path, Some("old")).await.unwrap();
+        populate(&store).await;
+
+        store.change_passphrase("new").await.unwrap();
+        drop(store);
+
+        assert_matches!(
+            SqliteStateStore::open(&path, Some("old")).await,
+            Err(OpenStoreError::InitCipher(_))
+        );
+
+        let store = SqliteStateStore::open(&path, Some("new")).await.unwrap();
+        assert_populated(&store).await;
+    }
+}
+
+#[cfg(test)]
+mod maintenance_tests {
+    use matrix_sdk_base::{
+        store::{IntoStateStore, StateStoreIntegrationTests},
+        StateStore,
+    };
+    use matrix_sdk_test::async_test;
+    use tempfile::tempdir;
+
+    use super::SqliteStateStore;
+    use crate::utils::SqliteObjectExt;
+
+    #[async_test]
+    pub async fn test_statistics_and_vacuum() {
+        let dir = tempdir().unwrap();
+        let store = SqliteStateStore::open(dir.path(), Some("secret")).await.unwrap();
+        store.clone().into_state_store().populate().await.unwrap();
+
+        let stats = store.statistics().await.unwrap();
+        assert!(stats.database_size > 0);
+
+        let room_info = stats.tables.iter().find(|t| t.name == "room_info").unwrap();
+        assert_eq!(room_info.row_count, 2);
+        assert!(room_info.data_size > 0);
+
+        let quarantine = stats.tables.iter().find(|t| t.name == "quarantine").unwrap();
+        assert_eq!(quarantine.row_count, 0);
+
+        store.vacuum().await.unwrap();
+        assert_eq!(store.get_room_infos().await.unwrap().len(), 2);
+    }
+
+    #[async_test]
+    pub async fn test_check_integrity() {
+        let dir = tempdir().unwrap();
+        let store = SqliteStateStore::open(dir.path(), Some("secret")).await.unwrap();
+        store.clone().into_state_store().populate().await.unwrap();
+        store.set_custom_value(b"custom", b"value".to_vec()).await.unwrap();
+
+        let report = store.check_integrity().await.unwrap();
+        assert!(report.is_ok(), "{report:?}");
+        assert!(report.checked_rows > 0);
+
+        // Corrupt the room infos.
+        let conn = store.acquire().await.unwrap();
+        conn.execute("UPDATE room_info SET data = X'00'", ()).await.unwrap();
+
+        let report = store.check_integrity().await.unwrap();
+        assert!(report.database_errors.is_empty());
+        assert_eq!(report.quarantined_rows.len(), 2);
+        assert!(report.quarantined_rows.iter().all(|row| row.table == "room_info"));
+
+        // The corrupted rows don't break the store anymore.
+        assert!(store.get_room_infos().await.unwrap().is_empty());
+        assert_eq!(store.get_custom_value(b"custom").await.unwrap().unwrap(), b"value");
+
+        let stats = store.statistics().await.unwrap();
+        let quarantine = stats.tables.iter().find(|t| t.name == "quarantine").unwrap();
+        assert_eq!(quarantine.row_count, 2);
+
+        assert!(store.check_integrity().await.unwrap().is_ok());
+
+        // The quarantined rows can be restored, but they are still corrupted.
+        assert_eq!(store.restore_quarantined_rows().await.unwrap(), 2);
+
+        let stats = store.statistics().await.unwrap();
+        let room_info = stats.tables.iter().find(|t| t.name == "room_info").unwrap();
+        assert_eq!(room_info.row_count, 2);
+        let quarantine = stats.tables.iter().find(|t| t.name == "quarantine").unwrap();
+        assert_eq!(quarantine.row_count, 0);
+
+        assert_eq!(store.check_integrity().await.unwrap().quarantined_rows.len(), 2);
+    }
+}
+
 #[cfg(test)]
 mod migration_tests {
     use std::{
@@ -97,6 +97,29 @@ impl StoreCipher {
         Ok(Self { inner: Keys::new()? })
     }
 
+    /// Create a new store cipher with a freshly generated encryption key.
+    ///
+    /// The key used to hash the keys of the store, see
+    /// [`StoreCipher::hash_key`], is kept, so the hashed keys stay valid and
+    /// only the values need to be re-encrypted with the new store cipher.
+    ///
+    /// Values encrypted with the current store cipher can't be decrypted by
+    /// the new one.
+    ///
+    /// Rotating the key used to hash the keys of the store requires the
+    /// original keys, so it isn't possible in place: a new store cipher must
+    /// be created with [`StoreCipher::new`], and the data of the store copied
+    /// over with its original keys.
+    pub fn rotate_encryption_key(&self) -> Result<Self, Error> {
+        let mut encryption_key = Box::new([0u8; 32]);
+        encryption_key.try_fill(&mut thread_rng())?;
+
+        let mut mac_key_seed = Box::new([0u8; 32]);
+        mac_key_seed.copy_from_slice(self.inner.mac_key_seed());
+
+        Ok(Self { inner: Keys { encryption_key, mac_key_seed } })
+    }
+
     /// Encrypt the store cipher using the given passphrase and export it.
     ///
     /// This method can be used to persist the `StoreCipher` in an unencrypted
@@ -828,6 +851,36 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn rotating_encryption_key() -> Result<(), Error> {
+        let event = json!({
+            "content": {
+                "body": "Bee Gees - Stayin' Alive",
+            },
+            "type": "m.room.message",
+        });
+
+        let store_cipher = StoreCipher::new()?;
+        let rotated = store_cipher.rotate_encryption_key()?;
+
+        assert_eq!(
+            store_cipher.hash_key("test_table", b"key"),
+            rotated.hash_key("test_table", b"key"),
+            "The hashed keys should stay the same"
+        );
+
+        let encrypted = store_cipher.encrypt_value(&event)?;
+        rotated
+            .decrypt_value::<Value>(&encrypted)
+            .expect_err("The rotated cipher shouldn't decrypt old values");
+
+        let encrypted = rotated.encrypt_value(&event)?;
+        let decrypted: Value = rotated.decrypt_value(&encrypted)?;
+        assert_eq!(event, decrypted);
+
+        Ok(())
+    }
+
     #[test]
     fn encrypting_keys() -> Result<(), Error> {
         let store_cipher = StoreCipher::new()?;
@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use std::{
+    collections::{BTreeMap, BTreeSet},
     sync::{Arc, Mutex},
     time::Duration,
 };
@@ -31,10 +32,10 @@ use ruma::{
     },
     push::Action,
     serde::Raw,
-    uint, EventId, OwnedEventId, RoomId, UserId,
+    uint, EventId, OwnedEventId, OwnedRoomId, RoomId, UserId,
 };
 use thiserror::Error;
-use tokio::sync::Mutex as AsyncMutex;
+use tokio::sync::{Mutex as AsyncMutex, OnceCell};
 
 use crate::encryption_sync::{EncryptionSync, WithLocking};
 
@@ -57,11 +58,12 @@ pub struct NotificationClient {
     /// Should the encryption sync happening in case the notification event was
     /// encrypted use a cross-process lock?
     ///
-    /// Only meaningful if `retry_decryption` is true.
+    /// If true, the cross-process lock of the parent client's state store is
+    /// also enabled, and taken when reading a notification from it.
     with_cross_process_lock: bool,
 
     /// Should we try to filter out the notification event according to the push
-    /// rules?
+    /// rules, evaluated on the device?
     filter_by_push_rules: bool,
 
     /// A mutex to serialize requests to sliding sync.
@@ -98,18 +100,77 @@ impl NotificationClient {
         room_id: &RoomId,
         event_id: &EventId,
     ) -> Result<Option<NotificationItem>, Error> {
-        match self.get_notification_with_sliding_sync(room_id, event_id).await? {
+        let encryption_sync = OnceCell::new();
+
+        match self
+            .get_notification_with_sliding_sync_inner(room_id, event_id, &encryption_sync)
+            .await?
+        {
             NotificationStatus::Event(event) => Ok(Some(event)),
             NotificationStatus::EventFilteredOut => Ok(None),
             NotificationStatus::EventNotFound => {
-                self.get_notification_with_context(room_id, event_id).await
+                self.get_notification_with_context_inner(room_id, event_id, &encryption_sync).await
+            }
+        }
+    }
+
+    /// Fetches the content of several notifications at once.
+    ///
+    /// This works like [`Self::get_notification`], but all the rooms are
+    /// subscribed to in a single short-lived sliding sync, and at most one
+    /// encryption sync is run to retry decrypting the events that are still
+    /// encrypted. Rooms that appear in several requests are only subscribed to
+    /// once.
+    ///
+    /// An error result means that the whole batch couldn't be resolved, e.g.
+    /// because the sliding sync couldn't be set up. Otherwise, the result
+    /// contains an entry for each requested event, with the same meaning as
+    /// the result of [`Self::get_notification`].
+    pub async fn get_notifications(
+        &self,
+        requests: &[NotificationItemsRequest],
+    ) -> Result<BatchNotificationFetchingResult, Error> {
+        let encryption_sync = OnceCell::new();
+
+        let mut statuses =
+            self.get_notifications_with_sliding_sync_inner(requests, &encryption_sync).await?;
+
+        let mut notifications = BatchNotificationFetchingResult::new();
+
+        for request in requests {
+            for event_id in &request.event_ids {
+                // The status is missing if the event was already requested before.
+                let Some(status) = statuses.remove(event_id) else {
+                    continue;
+                };
+
+                let result = match status {
+                    Ok(NotificationStatus::Event(item)) => Ok(Some(item)),
+                    Ok(NotificationStatus::EventFilteredOut) => Ok(None),
+                    Ok(NotificationStatus::EventNotFound) => {
+                        self.get_notification_with_context_inner(
+                            &request.room_id,
+                            event_id,
+                            &encryption_sync,
+                        )
+                        .await
+                    }
+                    Err(err) => Err(err),
+                };
+
+                notifications.insert(event_id.clone(), result);
             }
         }
+
+        Ok(notifications)
     }
 
-    /// Run an encryption sync loop, in case an event is still encrypted.
+    /// Retry decrypting an event, in case it is still encrypted.
+    ///
+    /// The encryption sync is run at most once for all the events sharing the
+    /// same `encryption_sync` cell, which holds whether it succeeded.
     ///
-    /// Will return true if and only:
+    /// Will return the decrypted event if and only if:
     /// - retry_decryption was enabled,
     /// - the event was encrypted,
     /// - we successfully ran an encryption sync.
@@ -117,6 +178,7 @@ impl NotificationClient {
         &self,
         room: &Room,
         raw_event: &Raw<AnySyncTimelineEvent>,
+        encryption_sync: &OnceCell<bool>,
     ) -> Result<Option<TimelineEvent>, Error> {
         if !self.retry_decryption {
             return Ok(None);
@@ -139,8 +201,20 @@ impl NotificationClient {
         }
 
         // The message is still encrypted, and the client is configured to retry
-        // decryption.
-        //
+        // decryption. Run the encryption sync only once for a batch of notifications.
+        if !*encryption_sync.get_or_init(|| self.run_encryption_sync()).await {
+            return Ok(None);
+        }
+
+        let new_event = room.decrypt_event(raw_event.cast_ref()).await?;
+        Ok(Some(new_event))
+    }
+
+    /// Run an encryption sync, to receive the room keys that may be missing to
+    /// decrypt the notification events.
+    ///
+    /// Returns whether the encryption sync succeeded.
+    async fn run_encryption_sync(&self) -> bool {
         // Spawn an `EncryptionSync` that runs two iterations of the sliding sync loop:
         // - the first iteration allows to get SS events as well as send e2ee requests.
         // - the second one let the SS proxy forward events triggered by the sending of
@@ -164,56 +238,62 @@ impl NotificationClient {
 
         match encryption_sync {
             Ok(sync) => match sync.run_fixed_iterations(2).await {
-                Ok(()) => {
-                    let new_event = room.decrypt_event(raw_event.cast_ref()).await?;
-                    Ok(Some(new_event))
-                }
+                Ok(()) => true,
                 Err(err) => {
                     tracing::warn!(
                         "error when running encryption_sync in get_notification: {err:#}"
                     );
-                    Ok(None)
+                    false
                 }
             },
             Err(err) => {
                 tracing::warn!("error when building encryption_sync in get_notification: {err:#}",);
-                Ok(None)
+                false
             }
         }
     }
 
-    /// Try to run a sliding sync (without encryption) to retrieve the event
-    /// from the notification.
+    /// Try to run a sliding sync (without encryption) to retrieve the events
+    /// from the notifications.
     ///
     /// This works by requesting explicit state that'll be useful for building
-    /// the `NotificationItem`, and subscribing to the room which the
-    /// notification relates to.
+    /// the `NotificationItem`s, and subscribing to the rooms which the
+    /// notifications relate to.
     async fn try_sliding_sync(
         &self,
-        room_id: &RoomId,
-        event_id: &EventId,
-    ) -> Result<Option<RawNotificationEvent>, Error> {
+        requests: &[NotificationItemsRequest],
+    ) -> Result<BTreeMap<OwnedEventId, RawNotificationEvent>, Error> {
+        let target_event_ids: Arc<BTreeSet<OwnedEventId>> = Arc::new(
+            requests.iter().flat_map(|request| request.event_ids.iter().cloned()).collect(),
+        );
+
+        if target_event_ids.is_empty() {
+            return Ok(BTreeMap::new());
+        }
+
         // Serialize all the calls to this method by taking a lock at the beginning,
         // that will be dropped later.
         let _guard = self.sliding_sync_mutex.lock().await;
 
-        // Set up a sliding sync that only subscribes to the room that had the
-        // notification, so we can figure out the full event and associated
+        // Set up a sliding sync that only subscribes to the rooms that had the
+        // notifications, so we can figure out the full events and associated
         // information.
 
-        let notification = Arc::new(Mutex::new(None));
+        let notifications = Arc::new(Mutex::new(BTreeMap::new()));
 
-        let cloned_notif = notification.clone();
-        let target_event_id = event_id.to_owned();
+        let cloned_notifs = notifications.clone();
+        let cloned_target_event_ids = target_event_ids.clone();
         let timeline_event_handler =
             self.client.add_event_handler(move |raw: Raw<AnySyncTimelineEvent>| async move {
                 match raw.get_field::<OwnedEventId>("event_id") {
                     Ok(Some(event_id)) => {
-                        if event_id == target_event_id {
+                        if cloned_target_event_ids.contains(&event_id) {
                             // found it! There shouldn't be a previous event before, but if there
                             // is, that should be ok to just replace it.
-                            *cloned_notif.lock().unwrap() =
-                                Some(RawNotificationEvent::Timeline(raw));
+                            cloned_notifs
+                                .lock()
+                                .unwrap()
+                                .insert(event_id, RawNotificationEvent::Timeline(raw));
                         }
                     }
                     Ok(None) | Err(_) => {
@@ -222,16 +302,19 @@ impl NotificationClient {
                 }
             });
 
-        let cloned_notif = notification.clone();
-        let target_event_id = event_id.to_owned();
+        let cloned_notifs = notifications.clone();
+        let cloned_target_event_ids = target_event_ids.clone();
         let stripped_member_handler =
             self.client.add_event_handler(move |raw: Raw<StrippedRoomMemberEvent>| async move {
                 match raw.get_field::<OwnedEventId>("event_id") {
                     Ok(Some(event_id)) => {
-                        if event_id == target_event_id {
+                        if cloned_target_event_ids.contains(&event_id) {
                             // found it! There shouldn't be a previous event before, but if there
                             // is, that should be ok to just replace it.
-                            *cloned_notif.lock().unwrap() = Some(RawNotificationEvent::Invite(raw));
+                            cloned_notifs
+                                .lock()
+                                .unwrap()
+                                .insert(event_id, RawNotificationEvent::Invite(raw));
                         }
                     }
                     Ok(None) | Err(_) => {
@@ -274,13 +357,19 @@ impl NotificationClient {
             .build()
             .await?;
 
-        sync.subscribe_to_room(
-            room_id.to_owned(),
-            Some(assign!(RoomSubscription::default(), {
-                required_state,
-                timeline_limit: Some(uint!(16))
-            })),
-        );
+        // Subscribe only once to each room, even if it has several notifications.
+        let room_ids: BTreeSet<&RoomId> =
+            requests.iter().map(|request| request.room_id.as_ref()).collect();
+
+        for room_id in room_ids {
+            sync.subscribe_to_room(
+                room_id.to_owned(),
+                Some(assign!(RoomSubscription::default(), {
+                    required_state: required_state.clone(),
+                    timeline_limit: Some(uint!(16))
+                })),
+            );
+        }
 
         let mut remaining_attempts = 3;
 
@@ -293,8 +382,8 @@ impl NotificationClient {
                 break;
             }
 
-            if notification.lock().unwrap().is_some() {
-                // We got the event.
+            if notifications.lock().unwrap().len() == target_event_ids.len() {
+                // We got all the events.
                 break;
             }
 
@@ -308,8 +397,8 @@ impl NotificationClient {
         self.client.remove_event_handler(stripped_member_handler);
         self.client.remove_event_handler(timeline_event_handler);
 
-        let maybe_event = notification.lock().unwrap().take();
-        Ok(maybe_event)
+        let events = std::mem::take(&mut *notifications.lock().unwrap());
+        Ok(events)
     }
 
     /// Get a full notification, given a room id and event id.
@@ -321,12 +410,71 @@ impl NotificationClient {
         room_id: &RoomId,
         event_id: &EventId,
     ) -> Result<NotificationStatus, Error> {
-        tracing::info!("fetching notification event with a sliding sync");
+        self.get_notification_with_sliding_sync_inner(room_id, event_id, &OnceCell::new()).await
+    }
 
-        let Some(mut raw_event) = self.try_sliding_sync(room_id, event_id).await? else {
-            return Ok(NotificationStatus::EventNotFound);
-        };
+    async fn get_notification_with_sliding_sync_inner(
+        &self,
+        room_id: &RoomId,
+        event_id: &EventId,
+        encryption_sync: &OnceCell<bool>,
+    ) -> Result<NotificationStatus, Error> {
+        let requests = [NotificationItemsRequest {
+            room_id: room_id.to_owned(),
+            event_ids: vec![event_id.to_owned()],
+        }];
+
+        let mut statuses =
+            self.get_notifications_with_sliding_sync_inner(&requests, encryption_sync).await?;
 
+        statuses.remove(event_id).unwrap_or(Ok(NotificationStatus::EventNotFound))
+    }
+
+    /// Get the status of several notifications, with a single sliding sync.
+    async fn get_notifications_with_sliding_sync_inner(
+        &self,
+        requests: &[NotificationItemsRequest],
+        encryption_sync: &OnceCell<bool>,
+    ) -> Result<BTreeMap<OwnedEventId, Result<NotificationStatus, Error>>, Error> {
+        tracing::info!("fetching notification events with a sliding sync");
+
+        let mut raw_events = self.try_sliding_sync(requests).await?;
+
+        let mut statuses = BTreeMap::new();
+
+        for request in requests {
+            for event_id in &request.event_ids {
+                if statuses.contains_key(event_id) {
+                    continue;
+                }
+
+                let status = match raw_events.remove(event_id) {
+                    Some(raw_event) => {
+                        self.sliding_sync_notification_status(
+                            &request.room_id,
+                            raw_event,
+                            encryption_sync,
+                        )
+                        .await
+                    }
+                    None => Ok(NotificationStatus::EventNotFound),
+                };
+
+                statuses.insert(event_id.clone(), status);
+            }
+        }
+
+        Ok(statuses)
+    }
+
+    /// Build the status of a notification whose event was retrieved by the
+    /// sliding sync.
+    async fn sliding_sync_notification_status(
+        &self,
+        room_id: &RoomId,
+        mut raw_event: RawNotificationEvent,
+        encryption_sync: &OnceCell<bool>,
+    ) -> Result<NotificationStatus, Error> {
         // At this point it should have been added by the sync, if it's not, give up.
         let Some(room) = self.client.get_room(room_id) else { return Err(Error::UnknownRoom) };
 
@@ -334,7 +482,7 @@ impl NotificationClient {
             RawNotificationEvent::Timeline(timeline_event) => {
                 // Timeline events may be encrypted, so make sure they get decrypted first.
                 if let Some(timeline_event) =
-                    self.maybe_retry_decryption(&room, timeline_event).await?
+                    self.maybe_retry_decryption(&room, timeline_event, encryption_sync).await?
                 {
                     raw_event = RawNotificationEvent::Timeline(timeline_event.event.cast());
                     timeline_event.push_actions
@@ -348,10 +496,8 @@ impl NotificationClient {
             }
         };
 
-        if let Some(push_actions) = &push_actions {
-            if self.filter_by_push_rules && !push_actions.iter().any(|a| a.should_notify()) {
-                return Ok(NotificationStatus::EventFilteredOut);
-            }
+        if self.is_filtered_out(push_actions.as_deref()) {
+            return Ok(NotificationStatus::EventFilteredOut);
         }
 
         Ok(NotificationStatus::Event(
@@ -359,6 +505,17 @@ impl NotificationClient {
         ))
     }
 
+    /// Whether a notification with the given push actions should be filtered
+    /// out.
+    ///
+    /// The push actions are computed on the device, on the decrypted event. If
+    /// they couldn't be computed, because the event is still encrypted or the
+    /// room state is incomplete, the notification is kept.
+    fn is_filtered_out(&self, push_actions: Option<&[Action]>) -> bool {
+        self.filter_by_push_rules
+            && push_actions.is_some_and(|actions| !actions.iter().any(|a| a.should_notify()))
+    }
+
     /// Retrieve a notification using a `/context` query.
     ///
     /// This is for clients that are already running other sliding syncs in the
@@ -375,9 +532,25 @@ impl NotificationClient {
         &self,
         room_id: &RoomId,
         event_id: &EventId,
+    ) -> Result<Option<NotificationItem>, Error> {
+        self.get_notification_with_context_inner(room_id, event_id, &OnceCell::new()).await
+    }
+
+    async fn get_notification_with_context_inner(
+        &self,
+        room_id: &RoomId,
+        event_id: &EventId,
+        encryption_sync: &OnceCell<bool>,
     ) -> Result<Option<NotificationItem>, Error> {
         tracing::info!("fetching notification event with a /context query");
 
+        // If the state store is shared with another process, taking the lock reloads
+        // the rooms it may have written in the meantime. It's not held any longer, to
+        // not block the other process during the network requests.
+        if self.with_cross_process_lock {
+            drop(self.parent_client.spin_lock_state_store(None).await?);
+        }
+
         // See above comment.
         let Some(room) = self.parent_client.get_room(room_id) else {
             return Err(Error::UnknownRoom);
@@ -386,18 +559,14 @@ impl NotificationClient {
         let (mut timeline_event, state_events) =
             room.event_with_context(event_id, true).await?.ok_or(Error::ContextMissingEvent)?;
 
-        if let Some(decrypted_event) =
-            self.maybe_retry_decryption(&room, timeline_event.event.cast_ref()).await?
+        if let Some(decrypted_event) = self
+            .maybe_retry_decryption(&room, timeline_event.event.cast_ref(), encryption_sync)
+            .await?
         {
             timeline_event = decrypted_event;
         }
 
-        if self.filter_by_push_rules
-            && !timeline_event
-                .push_actions
-                .as_ref()
-                .is_some_and(|actions| actions.iter().any(|a| a.should_notify()))
-        {
+        if self.is_filtered_out(timeline_event.push_actions.as_deref()) {
             return Ok(None);
         }
 
@@ -413,6 +582,20 @@ impl NotificationClient {
     }
 }
 
+/// A request for the notifications of several events in the same room.
+#[derive(Debug, Clone)]
+pub struct NotificationItemsRequest {
+    /// The ID of the room of the events.
+    pub room_id: OwnedRoomId,
+    /// The IDs of the events in the notifications.
+    pub event_ids: Vec<OwnedEventId>,
+}
+
+/// The result of [`NotificationClient::get_notifications`], for each requested
+/// event ID.
+pub type BatchNotificationFetchingResult =
+    BTreeMap<OwnedEventId, Result<Option<NotificationItem>, Error>>;
+
 #[derive(Debug)]
 pub enum NotificationStatus {
     Event(NotificationItem),
@@ -448,8 +631,12 @@ impl NotificationClientBuilder {
         })
     }
 
-    /// Filter out the notification event according to the push rules present in
-    /// the event.
+    /// Filter out the notification event according to the push rules of the
+    /// user.
+    ///
+    /// The push rules are evaluated on the device, on the decrypted event, so
+    /// the user's settings for mentions, keywords and rooms are respected in
+    /// encrypted rooms too.
     pub fn filter_by_push_rules(mut self) -> Self {
         self.filter_by_push_rules = true;
         self
@@ -461,7 +648,8 @@ impl NotificationClientBuilder {
     /// The boolean indicates whether we're making use of a cross-process lock
     /// for the crypto-store. This should be set to true, if and only if,
     /// the notification is received in a process that's different from the
-    /// main app.
+    /// main app; in that case, the cross-process lock of the state store is
+    /// enabled too.
     pub fn retry_decryption(mut self, with_cross_process_lock: bool) -> Self {
         self.retry_decryption = true;
         self.with_cross_process_lock = with_cross_process_lock;
@@ -470,6 +658,11 @@ impl NotificationClientBuilder {
 
     /// Finishes configuring the `NotificationClient`.
     pub fn build(self) -> NotificationClient {
+        if self.with_cross_process_lock {
+            self.parent_client
+                .enable_cross_process_state_store_lock(NotificationClient::LOCK_ID.to_owned());
+        }
+
         NotificationClient {
             client: self.client,
             parent_client: self.parent_client,
@@ -530,6 +723,12 @@ pub struct NotificationItem {
     ///
     /// It is set if and only if the push actions could be determined.
     pub is_noisy: Option<bool>,
+
+    /// Does the event mention the user? (i.e. does any push action contain a
+    /// highlight action)
+    ///
+    /// It is set if and only if the push actions could be determined.
+    pub has_mention: Option<bool>,
 }
 
 impl NotificationItem {
@@ -586,6 +785,7 @@ impl NotificationItem {
         }
 
         let is_noisy = push_actions.map(|actions| actions.iter().any(|a| a.sound().is_some()));
+        let has_mention = push_actions.map(|actions| actions.iter().any(|a| a.is_highlight()));
 
         let item = NotificationItem {
             event,
@@ -598,6 +798,7 @@ impl NotificationItem {
             is_room_encrypted: room.is_encrypted().await.ok(),
             joined_members_count: room.joined_members_count(),
             is_noisy,
+            has_mention,
         };
 
         Ok(item)
@@ -74,8 +74,9 @@ use eyeball::{SharedObservable, Subscriber};
 use futures_util::{pin_mut, Stream, StreamExt};
 pub use matrix_sdk::RoomListEntry;
 use matrix_sdk::{
-    sliding_sync::Ranges, Client, Error as SlidingSyncError, SlidingSync, SlidingSyncList,
-    SlidingSyncListBuilder, SlidingSyncMode,
+    notification_settings::NotificationSettings, sliding_sync::Ranges, Client,
+    Error as SlidingSyncError, SlidingSync, SlidingSyncList, SlidingSyncListBuilder,
+    SlidingSyncMode,
 };
 use matrix_sdk_base::ring_buffer::RingBuffer;
 pub use room::*;
@@ -110,6 +111,10 @@ pub struct RoomListService {
     /// Room cache, to avoid recreating `Room`s every time users fetch them.
     rooms: Arc<RwLock<RingBuffer<Room>>>,
 
+    /// The notification settings shared by all the rooms, to compute their
+    /// notification modes.
+    notification_settings: NotificationSettings,
+
     /// The current viewport ranges.
     ///
     /// This is useful to avoid resetting the ranges to the same value,
@@ -206,12 +211,15 @@ impl RoomListService {
             .map(Arc::new)
             .map_err(Error::SlidingSync)?;
 
+        let notification_settings = client.notification_settings().await;
+
         Ok(Self {
             client,
             sliding_sync,
             state: SharedObservable::new(State::Init),
             rooms: Arc::new(RwLock::new(RingBuffer::new(Self::ROOM_OBJECT_CACHE_SIZE))),
             viewport_ranges: Mutex::new(vec![VISIBLE_ROOMS_DEFAULT_RANGE]),
+            notification_settings,
         })
     }
 
@@ -391,7 +399,9 @@ impl RoomListService {
         }
 
         let room = match self.sliding_sync.get_room(room_id).await {
-            Some(room) => Room::new(self.sliding_sync.clone(), room)?,
+            Some(room) => {
+                Room::new(self.sliding_sync.clone(), room, self.notification_settings.clone())?
+            }
             None => return Err(Error::RoomNotFound(room_id.to_owned())),
         };
 
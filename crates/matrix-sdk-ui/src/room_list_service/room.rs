@@ -17,7 +17,13 @@
 use std::sync::Arc;
 
 use async_once_cell::OnceCell as AsyncOnceCell;
-use matrix_sdk::{SlidingSync, SlidingSyncRoom};
+use async_stream::stream;
+use futures_core::Stream;
+use futures_util::{pin_mut, StreamExt};
+use matrix_sdk::{
+    notification_settings::{NotificationSettings, RoomNotificationMode},
+    SlidingSync, SlidingSyncRoom,
+};
 use ruma::{
     api::client::sync::sync_events::{v4::RoomSubscription, UnreadNotificationsCount},
     OwnedMxcUri, RoomId,
@@ -50,6 +56,10 @@ struct RoomInner {
 
     /// The timeline of the room.
     timeline: AsyncOnceCell<Arc<Timeline>>,
+
+    /// The notification settings shared by all the rooms of the
+    /// `RoomListService`.
+    notification_settings: NotificationSettings,
 }
 
 impl Room {
@@ -57,6 +67,7 @@ impl Room {
     pub(super) fn new(
         sliding_sync: Arc<SlidingSync>,
         sliding_sync_room: SlidingSyncRoom,
+        notification_settings: NotificationSettings,
     ) -> Result<Self, Error> {
         let room = sliding_sync_room
             .client()
@@ -69,6 +80,7 @@ impl Room {
                 sliding_sync_room,
                 room,
                 timeline: AsyncOnceCell::new(),
+                notification_settings,
             }),
         })
     }
@@ -170,4 +182,35 @@ impl Room {
     pub fn unread_notifications(&self) -> UnreadNotificationsCount {
         self.inner.sliding_sync_room.unread_notifications()
     }
+
+    /// Get the notification mode of the room, and a stream of its updates.
+    ///
+    /// A new mode is received whenever the push rules change, either from this
+    /// client or from another device.
+    pub async fn notification_mode(
+        &self,
+    ) -> (Option<RoomNotificationMode>, impl Stream<Item = Option<RoomNotificationMode>>) {
+        let room = self.inner.room.clone();
+        let notification_settings = self.inner.notification_settings.clone();
+
+        // Subscribe before computing the current mode to not miss any change.
+        let changes = notification_settings.subscribe_to_changes();
+        let mut current_mode = room.notification_mode_with_settings(&notification_settings).await;
+        let initial_mode = current_mode.clone();
+
+        let stream = stream! {
+            pin_mut!(changes);
+
+            while changes.next().await.is_some() {
+                let mode = room.notification_mode_with_settings(&notification_settings).await;
+
+                if mode != current_mode {
+                    current_mode = mode.clone();
+                    yield mode;
+                }
+            }
+        };
+
+        (initial_mode, stream)
+    }
 }
@@ -421,7 +421,8 @@ pub struct SyncServiceBuilder {
     /// (true), or is it fused in the main `RoomList` sliding sync (false)?
     with_encryption_sync: bool,
 
-    /// Is the cross-process lock for the crypto store enabled?
+    /// Are the cross-process locks for the crypto store and the state store
+    /// enabled?
     with_cross_process_lock: bool,
 
     /// Application identifier, used as the cross-process lock value, if
@@ -447,7 +448,8 @@ impl SyncServiceBuilder {
     /// It's also a prerequisite if another process can *also* process
     /// encryption events; in that case, the `with_cross_process_lock`
     /// boolean must be set to `true` to enable the cross-process crypto
-    /// store lock. This is only applicable to very specific use cases, like
+    /// store and state store locks. This is only applicable to very specific
+    /// use cases, like
     /// an external process attempting to decrypt notifications. In general,
     /// `with_cross_process_lock` can remain `false`.
     ///
@@ -473,6 +475,12 @@ impl SyncServiceBuilder {
     /// the background. The resulting `SyncService` must be kept alive as
     /// long as the sliding syncs are supposed to run.
     pub async fn build(self) -> Result<SyncService, Error> {
+        if self.with_cross_process_lock {
+            // Make sure the sync responses of the room list aren't processed while
+            // another process is writing into the same state store.
+            self.client.enable_cross_process_state_store_lock(self.identifier.clone());
+        }
+
         let (room_list, encryption_sync) = if self.with_encryption_sync {
             let room_list = RoomListService::new(self.client.clone()).await?;
             let encryption_sync = EncryptionSync::new(
@@ -16,6 +16,8 @@ use std::sync::Arc;
 
 use async_std::sync::Mutex;
 use eyeball::SharedObservable;
+#[cfg(feature = "e2e-encryption")]
+use futures_util::{pin_mut, stream, StreamExt};
 use imbl::Vector;
 use matrix_sdk::{
     deserialized_responses::SyncTimelineEvent, executor::spawn, sync::RoomUpdate, Room,
@@ -220,6 +222,33 @@ impl TimelineBuilder {
             .instrument(info_span!("room_update_handler", room_id = ?room.room_id()))
         });
 
+        // The shields of the decrypted events depend on the trust of the
+        // sending devices and user identities, refresh them when it changes.
+        #[cfg(feature = "e2e-encryption")]
+        let encryption_changes_join_handle = spawn({
+            let inner = inner.clone();
+            let decryptor = room.clone();
+            let encryption = client.encryption();
+
+            async move {
+                let (Some(devices), Some(identities)) =
+                    (encryption.devices_stream().await, encryption.user_identities_stream().await)
+                else {
+                    return;
+                };
+
+                // Only the fact that something changed matters here.
+                let changes = stream::select(devices.map(|_| ()), identities.map(|_| ()));
+                pin_mut!(changes);
+
+                while changes.next().await.is_some() {
+                    trace!("Devices or user identities changed, refreshing the encryption info");
+                    inner.refresh_encryption_info(decryptor.clone()).await;
+                }
+            }
+            .instrument(info_span!("encryption_changes_handler", room_id = ?room.room_id()))
+        });
+
         // Not using room.add_event_handler here because RoomKey events are
         // to-device events that are not received in the context of a room.
 
@@ -254,6 +283,8 @@ impl TimelineBuilder {
                 client,
                 event_handler_handles: handles,
                 room_update_join_handle,
+                #[cfg(feature = "e2e-encryption")]
+                encryption_changes_join_handle,
             }),
         };
 
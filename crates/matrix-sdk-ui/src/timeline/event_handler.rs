@@ -82,6 +82,7 @@ pub(super) struct TimelineEventContext {
     pub(super) timestamp: MilliSecondsSinceUnixEpoch,
     pub(super) is_own_event: bool,
     pub(super) encryption_info: Option<EncryptionInfo>,
+    pub(super) is_room_encrypted: bool,
     pub(super) read_receipts: IndexMap<OwnedUserId, Receipt>,
     pub(super) is_highlighted: bool,
     pub(super) flow: Flow,
@@ -824,6 +825,7 @@ impl<'a> TimelineEventHandler<'a> {
                     is_own: self.ctx.is_own_event,
                     is_highlighted: self.ctx.is_highlighted,
                     encryption_info: self.ctx.encryption_info.clone(),
+                    is_room_encrypted: self.ctx.is_room_encrypted,
                     original_json: Some(raw_event.clone()),
                     latest_edit_json: None,
                     origin,
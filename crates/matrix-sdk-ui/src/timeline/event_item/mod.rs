@@ -15,7 +15,10 @@
 use std::sync::Arc;
 
 use indexmap::IndexMap;
-use matrix_sdk::{deserialized_responses::EncryptionInfo, Client, Error};
+use matrix_sdk::{
+    deserialized_responses::{EncryptionInfo, ShieldState},
+    Client, Error,
+};
 use matrix_sdk_base::deserialized_responses::SyncTimelineEvent;
 use once_cell::sync::Lazy;
 use ruma::{
@@ -131,6 +134,9 @@ impl EventTimelineItem {
         // Probably the origin of the event doesn't matter for the preview.
         let origin = RemoteEventOrigin::Sync;
 
+        let room = client.get_room(room_id);
+        let is_room_encrypted = room.as_ref().map_or(false, RoomDataProvider::is_encrypted);
+
         let event_kind = RemoteEventTimelineItem {
             event_id,
             reactions,
@@ -138,13 +144,13 @@ impl EventTimelineItem {
             is_own,
             is_highlighted,
             encryption_info,
+            is_room_encrypted,
             original_json: Some(raw_sync_event),
             latest_edit_json,
             origin,
         }
         .into();
 
-        let room = client.get_room(room_id);
         let sender_profile = if let Some(room) = room {
             room.profile(&sender)
                 .await
@@ -309,6 +315,45 @@ impl EventTimelineItem {
         }
     }
 
+    /// Get the authenticity shield that should be presented alongside the
+    /// event.
+    ///
+    /// This is computed from the [`EncryptionInfo`] of the event. Messages
+    /// that were sent in the clear in an encrypted room get a red shield as
+    /// well. Local echoes, events that couldn't be decrypted and events
+    /// without user-visible content never get a shield.
+    ///
+    /// # Arguments
+    ///
+    /// * `strict` - Whether to use the strict ruleset to compute the shield,
+    ///   see [`VerificationState::to_shield_state_strict()`] and
+    ///   [`VerificationState::to_shield_state_lax()`].
+    ///
+    /// [`VerificationState::to_shield_state_strict()`]: matrix_sdk::deserialized_responses::VerificationState::to_shield_state_strict
+    /// [`VerificationState::to_shield_state_lax()`]: matrix_sdk::deserialized_responses::VerificationState::to_shield_state_lax
+    pub fn get_shield(&self, strict: bool) -> ShieldState {
+        let EventTimelineItemKind::Remote(remote_event) = &self.kind else {
+            return ShieldState::None;
+        };
+
+        if !remote_event.is_room_encrypted {
+            return ShieldState::None;
+        }
+
+        match (&remote_event.encryption_info, &self.content) {
+            (_, TimelineItemContent::UnableToDecrypt(_)) => ShieldState::None,
+            (Some(info), _) if strict => info.verification_state.to_shield_state_strict(),
+            (Some(info), _) => info.verification_state.to_shield_state_lax(),
+            (
+                None,
+                TimelineItemContent::Message(_)
+                | TimelineItemContent::Sticker(_)
+                | TimelineItemContent::Poll(_),
+            ) => ShieldState::sent_in_clear(),
+            (None, _) => ShieldState::None,
+        }
+    }
+
     /// Get the raw JSON representation of the initial event (the one that
     /// caused this timeline item to be created).
     ///
@@ -44,6 +44,8 @@ pub(in crate::timeline) struct RemoteEventTimelineItem {
     pub is_highlighted: bool,
     /// Encryption information.
     pub encryption_info: Option<EncryptionInfo>,
+    /// Whether the room the event was received in is encrypted.
+    pub is_room_encrypted: bool,
     /// JSON of the original event.
     ///
     /// If the event is edited, this *won't* change, instead `latest_edit_json`
@@ -78,6 +80,11 @@ impl RemoteEventTimelineItem {
         Self { reactions, ..self.clone() }
     }
 
+    /// Clone the current event item, and update its `encryption_info`.
+    pub fn with_encryption_info(&self, encryption_info: Option<EncryptionInfo>) -> Self {
+        Self { encryption_info, ..self.clone() }
+    }
+
     /// Clone the current event item, and clear its `reactions` as well as the
     /// JSON representation fields.
     pub fn redact(&self) -> Self {
@@ -114,6 +121,7 @@ impl fmt::Debug for RemoteEventTimelineItem {
             read_receipts,
             is_own,
             encryption_info,
+            is_room_encrypted,
             original_json: _,
             latest_edit_json: _,
             is_highlighted,
@@ -127,6 +135,7 @@ impl fmt::Debug for RemoteEventTimelineItem {
             .field("is_own", is_own)
             .field("is_highlighted", is_highlighted)
             .field("encryption_info", encryption_info)
+            .field("is_room_encrypted", is_room_encrypted)
             .field("origin", origin)
             .finish_non_exhaustive()
     }
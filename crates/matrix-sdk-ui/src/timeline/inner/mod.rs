@@ -13,7 +13,7 @@
 // limitations under the License.
 
 #[cfg(feature = "e2e-encryption")]
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::{fmt, sync::Arc};
 
 use async_rx::StreamExt as _;
@@ -24,6 +24,8 @@ use imbl::Vector;
 use itertools::Itertools;
 #[cfg(all(test, feature = "e2e-encryption"))]
 use matrix_sdk::crypto::OlmMachine;
+#[cfg(feature = "e2e-encryption")]
+use matrix_sdk::push::get_push_actions;
 use matrix_sdk::{
     deserialized_responses::{SyncTimelineEvent, TimelineEvent},
     sync::{JoinedRoom, Timeline},
@@ -31,6 +33,8 @@ use matrix_sdk::{
 };
 #[cfg(test)]
 use ruma::events::receipt::ReceiptEventContent;
+#[cfg(feature = "e2e-encryption")]
+use ruma::OwnedUserId;
 #[cfg(all(test, feature = "e2e-encryption"))]
 use ruma::RoomId;
 use ruma::{
@@ -766,8 +770,8 @@ impl<P: RoomDataProvider> TimelineInner<P> {
                 };
 
                 event.push_actions =
-                    push_rules_context.as_ref().map(|(push_rules, push_context)| {
-                        push_rules.get_actions(&event.event, push_context).to_owned()
+                    push_rules_context.as_ref().and_then(|(push_rules, push_context)| {
+                        get_push_actions(push_rules, &event.event, push_context)
                     });
 
                 let result = state
@@ -788,6 +792,69 @@ impl<P: RoomDataProvider> TimelineInner<P> {
         });
     }
 
+    /// Recompute the encryption info of all the decrypted events in the
+    /// timeline, and update the items whose verification state changed.
+    #[cfg(feature = "e2e-encryption")]
+    pub(super) async fn refresh_encryption_info(&self, decryptor: impl Decryptor) {
+        trace!("Refreshing the encryption info of decrypted events");
+
+        // Collect the sessions first, so the timeline isn't locked while the store
+        // is queried, and each session is only looked up once.
+        let sessions: BTreeSet<(OwnedUserId, String)> = {
+            let state = self.state.lock().await;
+            state
+                .items
+                .iter()
+                .filter_map(|item| {
+                    let encryption_info = item.as_event()?.as_remote()?.encryption_info.as_ref()?;
+                    let session_id = encryption_info.session_id.clone()?;
+                    Some((encryption_info.sender.clone(), session_id))
+                })
+                .collect()
+        };
+
+        let mut new_infos = BTreeMap::new();
+        for (sender, session_id) in sessions {
+            if let Some(new_info) = decryptor.get_encryption_info_impl(&session_id, &sender).await {
+                new_infos.insert((sender, session_id), new_info);
+            }
+        }
+
+        if new_infos.is_empty() {
+            trace!("No encryption info to refresh");
+            return;
+        }
+
+        let mut state = self.state.lock().await;
+        let mut entries = state.items.entries();
+        while let Some(mut entry) = entries.next() {
+            let Some(event_item) = entry.as_event() else { continue };
+            let Some(remote_event) = event_item.as_remote() else { continue };
+            let Some(encryption_info) = &remote_event.encryption_info else { continue };
+            let Some(session_id) = &encryption_info.session_id else { continue };
+
+            let Some(new_info) =
+                new_infos.get(&(encryption_info.sender.clone(), session_id.clone()))
+            else {
+                continue;
+            };
+
+            if new_info.verification_state == encryption_info.verification_state
+                && new_info.sender_device == encryption_info.sender_device
+            {
+                continue;
+            }
+
+            trace!(event_id = ?remote_event.event_id, "Updating the encryption info");
+            let updated_item =
+                event_item.with_kind(remote_event.with_encryption_info(Some(new_info.clone())));
+            let new_item = entry.with_kind(updated_item);
+            ObservableVectorEntry::set(&mut entry, new_item);
+        }
+
+        trace!("Done refreshing the encryption info");
+    }
+
     pub(super) async fn set_sender_profiles_pending(&self) {
         self.set_non_ready_sender_profiles(TimelineDetails::Pending).await;
     }
@@ -251,6 +251,7 @@ impl TimelineInnerState {
             timestamp,
             is_own_event,
             encryption_info: event.encryption_info,
+            is_room_encrypted: room_data_provider.is_encrypted(),
             read_receipts: if settings.track_read_receipts {
                 self.load_read_receipts_for_event(&event_id, room_data_provider).await
             } else {
@@ -279,6 +280,8 @@ impl TimelineInnerState {
             is_own_event: true,
             // FIXME: Should we supply something here for encrypted rooms?
             encryption_info: None,
+            // Only used for remote events.
+            is_room_encrypted: false,
             read_receipts: Default::default(),
             // An event sent by ourself is never matched against push rules.
             is_highlighted: false,
@@ -306,6 +309,8 @@ impl TimelineInnerState {
             is_own_event: true,
             // FIXME: Should we supply something here for encrypted rooms?
             encryption_info: None,
+            // Only used for remote events.
+            is_room_encrypted: false,
             read_receipts: Default::default(),
             // An event sent by ourself is never matched against push rules.
             is_highlighted: false,
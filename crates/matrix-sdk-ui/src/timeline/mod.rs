@@ -279,6 +279,22 @@ impl Timeline {
         self.inner.retry_event_decryption(self.room(), None).await;
     }
 
+    /// Recompute the encryption info, and thus the shields, of the decrypted
+    /// events in the timeline.
+    ///
+    /// The [`EncryptionInfo`] of an event reflects the trust of the sending
+    /// device at the time the event was decrypted. This is done automatically
+    /// when the devices or user identities known to the client change, so the
+    /// shields returned by [`EventTimelineItem::get_shield()`] are up to date,
+    /// but it can also be triggered manually.
+    ///
+    /// [`EncryptionInfo`]: matrix_sdk::deserialized_responses::EncryptionInfo
+    #[cfg(feature = "e2e-encryption")]
+    #[instrument(skip(self), fields(room_id = ?self.room().room_id()))]
+    pub async fn refresh_encryption_info(&self) {
+        self.inner.refresh_encryption_info(self.room().to_owned()).await;
+    }
+
     /// Get the current timeline item for the given event ID, if any.
     ///
     /// It's preferable to store the timeline items in the model for your UI, if
@@ -673,6 +689,8 @@ struct TimelineDropHandle {
     client: Client,
     event_handler_handles: Vec<EventHandlerHandle>,
     room_update_join_handle: JoinHandle<()>,
+    #[cfg(feature = "e2e-encryption")]
+    encryption_changes_join_handle: JoinHandle<()>,
 }
 
 impl Drop for TimelineDropHandle {
@@ -681,6 +699,8 @@ impl Drop for TimelineDropHandle {
             self.client.remove_event_handler(handle);
         }
         self.room_update_join_handle.abort();
+        #[cfg(feature = "e2e-encryption")]
+        self.encryption_changes_join_handle.abort();
     }
 }
 
@@ -14,24 +14,41 @@
 
 #![cfg(not(target_arch = "wasm32"))]
 
-use std::{io::Cursor, iter};
+use std::{collections::BTreeMap, io::Cursor, iter};
 
 use assert_matches::assert_matches;
+use async_trait::async_trait;
 use eyeball_im::VectorDiff;
-use matrix_sdk::crypto::{decrypt_room_key_export, OlmMachine};
+use futures_util::{FutureExt, StreamExt};
+use matrix_sdk::{
+    crypto::{decrypt_room_key_export, OlmMachine},
+    deserialized_responses::{
+        AlgorithmInfo, EncryptionInfo, ShieldState, SyncTimelineEvent, TimelineEvent,
+        VerificationLevel, VerificationState,
+    },
+};
 use matrix_sdk_test::async_test;
 use ruma::{
     assign,
-    events::room::encrypted::{
-        EncryptedEventScheme, MegolmV1AesSha2ContentInit, Relation, Replacement,
-        RoomEncryptedEventContent,
+    events::{
+        room::{
+            encrypted::{
+                EncryptedEventScheme, MegolmV1AesSha2ContentInit, Relation, Replacement,
+                RoomEncryptedEventContent,
+            },
+            message::RoomMessageEventContent,
+            topic::RoomTopicEventContent,
+        },
+        AnySyncTimelineEvent,
     },
-    room_id, user_id,
+    room_id,
+    serde::Raw,
+    user_id, UserId,
 };
 use stream_assert::assert_next_matches;
 
-use super::{TestTimeline, BOB};
-use crate::timeline::{EncryptedMessage, TimelineItemContent};
+use super::{TestRoomDataProvider, TestTimeline, BOB};
+use crate::timeline::{traits::Decryptor, EncryptedMessage, TimelineItemContent};
 
 #[async_test]
 async fn retry_message_decryption() {
@@ -408,3 +425,121 @@ async fn retry_message_decryption_highlighted() {
     assert_eq!(text, "A secret to everybody but Alice");
     assert!(event.is_highlighted());
 }
+
+#[async_test]
+async fn shields_in_encrypted_room() {
+    let timeline =
+        TestTimeline::with_room_data_provider(TestRoomDataProvider { is_encrypted: true });
+    let mut stream = timeline.subscribe_events().await;
+
+    timeline
+        .handle_live_message_event(&BOB, RoomMessageEventContent::text_plain("Not a secret"))
+        .await;
+
+    let item = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    assert_eq!(item.get_shield(false), ShieldState::sent_in_clear());
+    assert_eq!(item.get_shield(true), ShieldState::sent_in_clear());
+
+    // State events are never encrypted, they don't get a shield.
+    timeline
+        .handle_live_state_event(&BOB, RoomTopicEventContent::new("Topic".to_owned()), None)
+        .await;
+
+    let item = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    assert_eq!(item.get_shield(true), ShieldState::None);
+}
+
+#[async_test]
+async fn no_shields_in_unencrypted_room() {
+    let timeline = TestTimeline::new();
+    let mut stream = timeline.subscribe_events().await;
+
+    timeline
+        .handle_live_message_event(&BOB, RoomMessageEventContent::text_plain("Not a secret"))
+        .await;
+
+    let item = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    assert_eq!(item.get_shield(false), ShieldState::None);
+    assert_eq!(item.get_shield(true), ShieldState::None);
+}
+
+/// A [`Decryptor`] that returns the same verification state for every
+/// session.
+#[derive(Clone)]
+struct FixedVerificationState(VerificationState);
+
+#[async_trait]
+impl Decryptor for FixedVerificationState {
+    async fn decrypt_event_impl(
+        &self,
+        _raw: &Raw<AnySyncTimelineEvent>,
+    ) -> matrix_sdk::Result<TimelineEvent> {
+        // This decryptor is only used to refresh the encryption info.
+        Err(matrix_sdk::Error::NoOlmMachine)
+    }
+
+    async fn get_encryption_info_impl(
+        &self,
+        session_id: &str,
+        sender: &UserId,
+    ) -> Option<EncryptionInfo> {
+        Some(encryption_info(sender, session_id, self.0.clone()))
+    }
+}
+
+fn encryption_info(
+    sender: &UserId,
+    session_id: &str,
+    verification_state: VerificationState,
+) -> EncryptionInfo {
+    EncryptionInfo {
+        sender: sender.to_owned(),
+        sender_device: None,
+        algorithm_info: AlgorithmInfo::MegolmV1AesSha2 {
+            curve25519_key: "DeFdtMaDcDZ0EOKLYeDKWEVw9Nc7Nwq4ARm7TWj4VlM".to_owned(),
+            sender_claimed_keys: BTreeMap::new(),
+        },
+        verification_state,
+        session_id: Some(session_id.to_owned()),
+    }
+}
+
+#[async_test]
+async fn refresh_encryption_info() {
+    let timeline =
+        TestTimeline::with_room_data_provider(TestRoomDataProvider { is_encrypted: true });
+    let mut stream = timeline.subscribe_events().await;
+
+    let event = timeline.make_message_event(&BOB, RoomMessageEventContent::text_plain("Secret"));
+    timeline
+        .inner
+        .handle_live_event(SyncTimelineEvent {
+            event: Raw::new(&event).unwrap().cast(),
+            encryption_info: Some(encryption_info(
+                &BOB,
+                "session",
+                VerificationState::Unverified(VerificationLevel::UnverifiedIdentity),
+            )),
+            push_actions: vec![],
+        })
+        .await;
+
+    let item = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    assert_matches!(item.get_shield(true), ShieldState::Red { .. });
+
+    // Bob's identity gets verified, the shield goes away.
+    timeline
+        .inner
+        .refresh_encryption_info(FixedVerificationState(VerificationState::Verified))
+        .await;
+
+    let item = assert_next_matches!(stream, VectorDiff::Set { index: 0, value } => value);
+    assert_eq!(item.get_shield(true), ShieldState::None);
+
+    // Nothing changed, the item isn't updated again.
+    timeline
+        .inner
+        .refresh_encryption_info(FixedVerificationState(VerificationState::Verified))
+        .await;
+    assert!(stream.next().now_or_never().is_none());
+}
@@ -87,7 +87,11 @@ struct TestTimeline {
 
 impl TestTimeline {
     fn new() -> Self {
-        Self { inner: TimelineInner::new(TestRoomDataProvider), next_ts: AtomicU64::new(0) }
+        Self::with_room_data_provider(TestRoomDataProvider::default())
+    }
+
+    fn with_room_data_provider(room_data_provider: TestRoomDataProvider) -> Self {
+        Self { inner: TimelineInner::new(room_data_provider), next_ts: AtomicU64::new(0) }
     }
 
     fn with_settings(mut self, settings: TimelineInnerSettings) -> Self {
@@ -403,8 +407,10 @@ impl TestTimeline {
     }
 }
 
-#[derive(Clone)]
-struct TestRoomDataProvider;
+#[derive(Clone, Default)]
+struct TestRoomDataProvider {
+    is_encrypted: bool,
+}
 
 #[async_trait]
 impl RoomDataProvider for TestRoomDataProvider {
@@ -416,6 +422,10 @@ impl RoomDataProvider for TestRoomDataProvider {
         RoomVersionId::V10
     }
 
+    fn is_encrypted(&self) -> bool {
+        self.is_encrypted
+    }
+
     async fn profile(&self, _user_id: &UserId) -> Option<Profile> {
         None
     }
@@ -16,7 +16,10 @@ use async_trait::async_trait;
 use indexmap::IndexMap;
 use matrix_sdk::Room;
 #[cfg(feature = "e2e-encryption")]
-use matrix_sdk::{deserialized_responses::TimelineEvent, Result};
+use matrix_sdk::{
+    deserialized_responses::{EncryptionInfo, TimelineEvent},
+    Result,
+};
 use ruma::{
     events::receipt::{Receipt, ReceiptThread, ReceiptType},
     push::{PushConditionRoomCtx, Ruleset},
@@ -66,6 +69,7 @@ impl RoomExt for Room {
 pub(super) trait RoomDataProvider: Clone + Send + Sync + 'static {
     fn own_user_id(&self) -> &UserId;
     fn room_version(&self) -> RoomVersionId;
+    fn is_encrypted(&self) -> bool;
     async fn profile(&self, user_id: &UserId) -> Option<Profile>;
     async fn read_receipts_for_event(&self, event_id: &EventId) -> IndexMap<OwnedUserId, Receipt>;
     async fn push_rules_and_context(&self) -> Option<(Ruleset, PushConditionRoomCtx)>;
@@ -84,6 +88,10 @@ impl RoomDataProvider for Room {
         })
     }
 
+    fn is_encrypted(&self) -> bool {
+        (**self).is_encrypted()
+    }
+
     async fn profile(&self, user_id: &UserId) -> Option<Profile> {
         match self.get_member_no_sync(user_id).await {
             Ok(Some(member)) => Some(Profile {
@@ -141,6 +149,11 @@ impl RoomDataProvider for Room {
 #[async_trait]
 pub(super) trait Decryptor: Clone + Send + Sync + 'static {
     async fn decrypt_event_impl(&self, raw: &Raw<AnySyncTimelineEvent>) -> Result<TimelineEvent>;
+    async fn get_encryption_info_impl(
+        &self,
+        session_id: &str,
+        sender: &UserId,
+    ) -> Option<EncryptionInfo>;
 }
 
 #[cfg(feature = "e2e-encryption")]
@@ -149,6 +162,14 @@ impl Decryptor for Room {
     async fn decrypt_event_impl(&self, raw: &Raw<AnySyncTimelineEvent>) -> Result<TimelineEvent> {
         self.decrypt_event(raw.cast_ref()).await
     }
+
+    async fn get_encryption_info_impl(
+        &self,
+        session_id: &str,
+        sender: &UserId,
+    ) -> Option<EncryptionInfo> {
+        self.get_encryption_info(session_id, sender).await
+    }
 }
 
 #[cfg(all(test, feature = "e2e-encryption"))]
@@ -159,4 +180,13 @@ impl Decryptor for (matrix_sdk_base::crypto::OlmMachine, ruma::OwnedRoomId) {
         let event = olm_machine.decrypt_room_event(raw.cast_ref(), room_id).await?;
         Ok(event)
     }
+
+    async fn get_encryption_info_impl(
+        &self,
+        session_id: &str,
+        sender: &UserId,
+    ) -> Option<EncryptionInfo> {
+        let (olm_machine, room_id) = self;
+        olm_machine.get_session_encryption_info(room_id, session_id, sender).await.ok().flatten()
+    }
 }
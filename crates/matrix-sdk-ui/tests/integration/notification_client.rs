@@ -3,13 +3,18 @@ use std::{sync::Mutex, time::Duration};
 use matrix_sdk::config::SyncSettings;
 use matrix_sdk_test::{async_test, JoinedRoomBuilder, SyncResponseBuilder, TimelineTestEvent};
 use matrix_sdk_ui::notification_client::{
-    NotificationClient, NotificationEvent, NotificationStatus,
+    NotificationClient, NotificationEvent, NotificationItemsRequest, NotificationStatus,
+};
+use ruma::{
+    event_id,
+    events::TimelineEventType,
+    push::{PredefinedUnderrideRuleId, RuleKind, Ruleset},
+    room_id, user_id, EventId, RoomId,
 };
-use ruma::{event_id, events::TimelineEventType, room_id, user_id};
 use serde_json::json;
 use wiremock::{
     matchers::{header, method, path},
-    Mock, Request, ResponseTemplate,
+    Match as _, Mock, Request, ResponseTemplate,
 };
 
 use crate::{
@@ -295,4 +300,498 @@ async fn test_notification_client_sliding_sync() {
     assert_eq!(item.sender_avatar_url.as_deref(), Some(sender_avatar_url));
     assert_eq!(item.room_display_name, room_name);
     assert_eq!(item.is_noisy, Some(false));
+    assert_eq!(item.has_mention, Some(false));
+}
+
+#[async_test]
+async fn test_notification_client_push_rules_evaluated_on_device() {
+    let room_id = room_id!("!a98sd12bjh:example.org");
+    let (client, server) = logged_in_client().await;
+
+    let sender = user_id!("@user:example.org");
+    let my_user_id = client.user_id().unwrap().to_owned();
+
+    // The user only wants to be notified for mentions and keywords.
+    let mut push_rules = Ruleset::server_default(&my_user_id);
+    push_rules
+        .set_actions(RuleKind::Underride, PredefinedUnderrideRuleId::Message, vec![])
+        .unwrap();
+
+    // A message containing the user's display name, which isn't a mention
+    // because the event uses intentional mentions.
+    let not_mentioned_event_id = event_id!("$not_mentioned");
+    // A message mentioning the user with intentional mentions.
+    let mentioned_event_id = event_id!("$mentioned");
+
+    let message = |event_id: &EventId, body: &str, mentions: serde_json::Value| {
+        json!({
+            "content": {
+                "body": body,
+                "msgtype": "m.text",
+                "m.mentions": mentions,
+            },
+            "room_id": room_id,
+            "event_id": event_id,
+            "origin_server_ts": 152049794,
+            "sender": sender,
+            "type": "m.room.message",
+        })
+    };
+    let timeline = json!([
+        message(not_mentioned_event_id, "Hello My Self", json!({})),
+        message(mentioned_event_id, "Hello", json!({ "user_ids": [my_user_id] })),
+    ]);
+
+    let pos = Mutex::new(0);
+    Mock::given(SlidingSyncMatcher)
+        .respond_with(move |request: &Request| {
+            let partial_request: PartialSlidingSyncRequest = request.body_json().unwrap();
+            let mut pos = pos.lock().unwrap();
+            *pos += 1;
+            let pos_as_str = (*pos).to_string();
+            ResponseTemplate::new(200).set_body_json(json!({
+                "txn_id": partial_request.txn_id,
+                "pos": pos_as_str,
+                "rooms": {
+                    "!a98sd12bjh:example.org": {
+                        "name": "The Maltese Falcon",
+                        "initial": true,
+
+                        "required_state": [
+                            // Own member information.
+                            {
+                                "content": {
+                                    "displayname": "My Self",
+                                    "membership": "join"
+                                },
+                                "room_id": room_id,
+                                "event_id": "$151800140517rflkc:example.org",
+                                "origin_server_ts": 151800140,
+                                "sender": my_user_id.clone(),
+                                "state_key": my_user_id.clone(),
+                                "type": "m.room.member",
+                            },
+
+                            // Power levels.
+                            {
+                                "content": {
+                                    "users": {
+                                        "@example:localhost": 100,
+                                    },
+                                    "users_default": 0
+                                },
+                                "event_id": "$15139375512JaHAW:localhost",
+                                "origin_server_ts": 151393755,
+                                "sender": "@example:localhost",
+                                "state_key": "",
+                                "type": "m.room.power_levels",
+                            }
+                        ],
+
+                        "timeline": timeline.clone(),
+                    }
+                },
+
+                "extensions": {
+                    "account_data": {
+                        "global": [
+                            {
+                                "type": "m.push_rules",
+                                "content": { "global": push_rules.clone() },
+                            }
+                        ]
+                    }
+                }
+            }))
+        })
+        .mount(&server)
+        .await;
+
+    let notification_client =
+        NotificationClient::builder(client).await.unwrap().filter_by_push_rules().build();
+
+    // The display name in the body doesn't notify.
+    let status = notification_client
+        .get_notification_with_sliding_sync(room_id, not_mentioned_event_id)
+        .await
+        .unwrap();
+    assert_matches::assert_matches!(status, NotificationStatus::EventFilteredOut);
+
+    // The intentional mention notifies.
+    let status = notification_client
+        .get_notification_with_sliding_sync(room_id, mentioned_event_id)
+        .await
+        .unwrap();
+    let NotificationStatus::Event(item) = status else {
+        panic!("the mention should notify");
+    };
+    assert_eq!(item.has_mention, Some(true));
+    assert_eq!(item.is_noisy, Some(true));
+}
+
+#[async_test]
+async fn test_notification_client_batch_sliding_sync() {
+    let first_room_id = room_id!("!first:example.org");
+    let second_room_id = room_id!("!second:example.org");
+    let (client, server) = logged_in_client().await;
+
+    let first_event_id = event_id!("$first");
+    let second_event_id = event_id!("$second");
+    let third_event_id = event_id!("$third");
+    let sender = user_id!("@user:example.org");
+
+    let message = |room_id: &RoomId, event_id: &EventId| {
+        json!({
+            "content": {
+                "body": "Hello world!",
+                "msgtype": "m.text",
+            },
+            "room_id": room_id,
+            "event_id": event_id,
+            "origin_server_ts": 152049794,
+            "sender": sender,
+            "type": "m.room.message",
+        })
+    };
+    let first_timeline =
+        json!([message(first_room_id, first_event_id), message(first_room_id, second_event_id),]);
+    let second_timeline = json!([message(second_room_id, third_event_id)]);
+
+    let pos = Mutex::new(0);
+    Mock::given(SlidingSyncMatcher)
+        .respond_with(move |request: &Request| {
+            let partial_request: PartialSlidingSyncRequest = request.body_json().unwrap();
+            let mut pos = pos.lock().unwrap();
+            *pos += 1;
+            let pos_as_str = (*pos).to_string();
+            ResponseTemplate::new(200).set_body_json(json!({
+                "txn_id": partial_request.txn_id,
+                "pos": pos_as_str,
+                "rooms": {
+                    "!first:example.org": {
+                        "name": "First room",
+                        "initial": true,
+                        "timeline": first_timeline.clone(),
+                    },
+                    "!second:example.org": {
+                        "name": "Second room",
+                        "initial": true,
+                        "timeline": second_timeline.clone(),
+                    },
+                },
+
+                "extensions": {
+                    "account_data": {}
+                }
+            }))
+        })
+        .mount(&server)
+        .await;
+
+    let notification_client = NotificationClient::builder(client).await.unwrap().build();
+
+    // The first room appears twice, and one of its events is requested twice.
+    let notifications = notification_client
+        .get_notifications(&[
+            NotificationItemsRequest {
+                room_id: first_room_id.to_owned(),
+                event_ids: vec![first_event_id.to_owned(), second_event_id.to_owned()],
+            },
+            NotificationItemsRequest {
+                room_id: second_room_id.to_owned(),
+                event_ids: vec![third_event_id.to_owned()],
+            },
+            NotificationItemsRequest {
+                room_id: first_room_id.to_owned(),
+                event_ids: vec![first_event_id.to_owned()],
+            },
+        ])
+        .await
+        .unwrap();
+
+    let required_state = json!([
+        ["m.room.avatar", ""],
+        ["m.room.encryption", ""],
+        ["m.room.member", "$LAZY"],
+        ["m.room.member", "$ME"],
+        ["m.room.canonical_alias", ""],
+        ["m.room.name", ""],
+        ["m.room.power_levels", ""],
+    ]);
+
+    // All the events were found with a single request, subscribing once to each
+    // room.
+    check_requests(
+        server,
+        &[json!({
+            "conn_id": "notifications",
+            "lists": {
+                "invites": {
+                    "ranges": [
+                        [0, 16]
+                    ],
+                    "required_state": required_state,
+                    "filters": {
+                        "is_invite": true,
+                        "is_tombstoned": false,
+                        "not_room_types": ["m.space"],
+                    },
+                    "sort": ["by_recency", "by_name"],
+                    "timeline_limit": 8,
+                }
+            },
+            "room_subscriptions": {
+                "!first:example.org": {
+                    "required_state": required_state,
+                    "timeline_limit": 16,
+                },
+                "!second:example.org": {
+                    "required_state": required_state,
+                    "timeline_limit": 16,
+                },
+            },
+            "extensions": {
+                "account_data": {
+                    "enabled": true,
+                }
+            }
+        })],
+    )
+    .await;
+
+    assert_eq!(notifications.len(), 3);
+
+    for (event_id, room_name) in [
+        (first_event_id, "First room"),
+        (second_event_id, "First room"),
+        (third_event_id, "Second room"),
+    ] {
+        let item = notifications
+            .get(event_id)
+            .expect("there should be a result for each event")
+            .as_ref()
+            .expect("the notification should be resolved")
+            .as_ref()
+            .expect("the notification shouldn't be filtered out");
+
+        assert_matches::assert_matches!(&item.event, NotificationEvent::Timeline(event) => {
+            assert_eq!(event.event_id(), event_id);
+        });
+        assert_eq!(item.room_display_name, room_name);
+    }
+}
+
+#[async_test]
+async fn test_notification_client_batch_runs_encryption_sync_once() {
+    let room_id = room_id!("!a98sd12bjh:example.org");
+    let (client, server) = logged_in_client().await;
+
+    let first_event_id = event_id!("$first");
+    let second_event_id = event_id!("$second");
+    let sender = user_id!("@user:example.org");
+
+    let encrypted_message = |event_id: &EventId| {
+        json!({
+            "content": {
+                "algorithm": "m.megolm.v1.aes-sha2",
+                "ciphertext": "AwgAEnAC",
+                "device_id": "USERDEVICE",
+                "sender_key": "sender_key",
+                "session_id": "session_id",
+            },
+            "room_id": room_id,
+            "event_id": event_id,
+            "origin_server_ts": 152049794,
+            "sender": sender,
+            "type": "m.room.encrypted",
+        })
+    };
+    let timeline = json!([encrypted_message(first_event_id), encrypted_message(second_event_id)]);
+
+    let pos = Mutex::new(0);
+    Mock::given(SlidingSyncMatcher)
+        .respond_with(move |request: &Request| {
+            let partial_request: PartialSlidingSyncRequest = request.body_json().unwrap();
+            let mut pos = pos.lock().unwrap();
+            *pos += 1;
+            let pos_as_str = (*pos).to_string();
+
+            // The encryption sync doesn't receive the missing room keys.
+            if partial_request.conn_id.as_deref() == Some("encryption") {
+                return ResponseTemplate::new(200).set_body_json(json!({
+                    "txn_id": partial_request.txn_id,
+                    "pos": pos_as_str,
+                }));
+            }
+
+            ResponseTemplate::new(200).set_body_json(json!({
+                "txn_id": partial_request.txn_id,
+                "pos": pos_as_str,
+                "rooms": {
+                    "!a98sd12bjh:example.org": {
+                        "name": "The Maltese Falcon",
+                        "initial": true,
+                        "timeline": timeline.clone(),
+                    },
+                },
+
+                "extensions": {
+                    "account_data": {}
+                }
+            }))
+        })
+        .mount(&server)
+        .await;
+
+    let notification_client =
+        NotificationClient::builder(client).await.unwrap().retry_decryption(false).build();
+
+    let notifications = notification_client
+        .get_notifications(&[NotificationItemsRequest {
+            room_id: room_id.to_owned(),
+            event_ids: vec![first_event_id.to_owned(), second_event_id.to_owned()],
+        }])
+        .await
+        .unwrap();
+
+    // Both events are still encrypted after the encryption sync, since the room
+    // keys are missing.
+    assert_eq!(notifications.len(), 2);
+    assert!(notifications[first_event_id].is_err());
+    assert!(notifications[second_event_id].is_err());
+
+    // A single encryption sync ran its two iterations for the whole batch.
+    let encryption_sync_requests = server
+        .received_requests()
+        .await
+        .expect("Request recording has been disabled")
+        .into_iter()
+        .filter(|request| SlidingSyncMatcher.matches(request))
+        .filter(|request| {
+            let partial_request: PartialSlidingSyncRequest = request.body_json().unwrap();
+            partial_request.conn_id.as_deref() == Some("encryption")
+        })
+        .count();
+    assert_eq!(encryption_sync_requests, 2);
+}
+
+#[async_test]
+async fn test_notification_client_batch_context_fallback() {
+    let room_id = room_id!("!a98sd12bjh:example.org");
+    let (client, server) = logged_in_client().await;
+
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let recent_event_id = event_id!("$recent");
+    let old_event_id = event_id!("$old");
+    let sender = user_id!("@user:example.org");
+
+    let message = |event_id: &EventId| {
+        json!({
+            "content": {
+                "body": "Hello world!",
+                "msgtype": "m.text",
+            },
+            "room_id": room_id,
+            "event_id": event_id,
+            "origin_server_ts": 152049794,
+            "sender": sender,
+            "type": "m.room.message",
+        })
+    };
+
+    // The room must be known by the parent client for the `/context` query.
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id)
+            .add_timeline_event(TimelineTestEvent::Custom(message(recent_event_id))),
+    );
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings).await.unwrap();
+    server.reset().await;
+
+    // The old event is outside of the `timeline_limit` of the room subscription, so
+    // the sliding sync only returns the recent one.
+    let timeline = json!([message(recent_event_id)]);
+
+    let pos = Mutex::new(0);
+    Mock::given(SlidingSyncMatcher)
+        .respond_with(move |request: &Request| {
+            let partial_request: PartialSlidingSyncRequest = request.body_json().unwrap();
+            let mut pos = pos.lock().unwrap();
+            *pos += 1;
+            let pos_as_str = (*pos).to_string();
+            ResponseTemplate::new(200).set_body_json(json!({
+                "txn_id": partial_request.txn_id,
+                "pos": pos_as_str,
+                "rooms": {
+                    "!a98sd12bjh:example.org": {
+                        "name": "The Maltese Falcon",
+                        "initial": true,
+                        "timeline": timeline.clone(),
+                    },
+                },
+
+                "extensions": {
+                    "account_data": {}
+                }
+            }))
+        })
+        .mount(&server)
+        .await;
+
+    // Only the old event is retrieved via `/rooms/*/context/`.
+    Mock::given(method("GET"))
+        .and(path(format!("/_matrix/client/r0/rooms/{room_id}/context/{old_event_id}")))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "event": message(old_event_id),
+            "state": [
+                {
+                    "content": {
+                        "displayname": "John Mastodon",
+                        "membership": "join"
+                    },
+                    "room_id": room_id,
+                    "event_id": "$151800140517rfvjc:example.org",
+                    "origin_server_ts": 151800140,
+                    "sender": sender,
+                    "state_key": sender,
+                    "type": "m.room.member",
+                }
+            ]
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    mock_encryption_state(&server, false).await;
+
+    let notification_client = NotificationClient::builder(client).await.unwrap().build();
+
+    let notifications = notification_client
+        .get_notifications(&[NotificationItemsRequest {
+            room_id: room_id.to_owned(),
+            event_ids: vec![recent_event_id.to_owned(), old_event_id.to_owned()],
+        }])
+        .await
+        .unwrap();
+
+    assert_eq!(notifications.len(), 2);
+
+    for event_id in [recent_event_id, old_event_id] {
+        let item = notifications[event_id]
+            .as_ref()
+            .expect("the notification should be resolved")
+            .as_ref()
+            .expect("the notification shouldn't be filtered out");
+
+        assert_matches::assert_matches!(&item.event, NotificationEvent::Timeline(event) => {
+            assert_eq!(event.event_id(), event_id);
+        });
+    }
+
+    // The member information comes from the `/context` response.
+    let old_item = notifications[old_event_id].as_ref().unwrap().as_ref().unwrap();
+    assert_eq!(old_item.sender_display_name.as_deref(), Some("John Mastodon"));
 }
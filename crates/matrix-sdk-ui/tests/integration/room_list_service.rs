@@ -4,8 +4,8 @@ use assert_matches::assert_matches;
 use eyeball_im::VectorDiff;
 use futures_util::{pin_mut, FutureExt, StreamExt};
 use imbl::vector;
-use matrix_sdk::Client;
-use matrix_sdk_test::async_test;
+use matrix_sdk::{notification_settings::RoomNotificationMode, Client};
+use matrix_sdk_test::{async_test, notification_settings::build_ruleset};
 use matrix_sdk_ui::{
     room_list_service::{
         filters::{new_filter_all, new_filter_fuzzy_match_room_name},
@@ -20,7 +20,9 @@ use ruma::{
     api::client::sync::sync_events::{v4::RoomSubscription, UnreadNotificationsCount},
     assign, event_id,
     events::{room::message::RoomMessageEventContent, StateEventType},
-    mxc_uri, room_id, uint, TransactionId,
+    mxc_uri,
+    push::RuleKind,
+    room_id, uint, TransactionId,
 };
 use serde_json::json;
 use stream_assert::{assert_next_matches, assert_pending};
@@ -2231,6 +2233,101 @@ async fn test_room_unread_notifications() -> Result<(), Error> {
     Ok(())
 }
 
+#[async_test]
+async fn test_room_notification_mode() -> Result<(), Error> {
+    let (_, server, room_list) = new_room_list_service().await?;
+
+    let sync = room_list.sync();
+    pin_mut!(sync);
+
+    let room_id = room_id!("!r0:bar.org");
+    let ruleset = build_ruleset(vec![(RuleKind::Room, room_id, true)]);
+
+    sync_then_assert_request_and_fake_response! {
+        [server, room_list, sync]
+        assert request >= {
+            "lists": {
+                ALL_ROOMS: {
+                    "ranges": [[0, 19]],
+                },
+            },
+        },
+        respond with = {
+            "pos": "0",
+            "lists": {
+                ALL_ROOMS: {
+                    "count": 1,
+                    "ops": [
+                        {
+                            "op": "SYNC",
+                            "range": [0, 0],
+                            "room_ids": [room_id],
+                        },
+                    ],
+                },
+            },
+            "rooms": {
+                room_id: {
+                    "name": "Room #0",
+                    "initial": true,
+                },
+            },
+            "extensions": {
+                "account_data": {
+                    "global": [
+                        {
+                            "type": "m.push_rules",
+                            "content": { "global": ruleset },
+                        },
+                    ],
+                },
+            },
+        },
+    };
+
+    let room = room_list.room(room_id).await.unwrap();
+
+    let (mode, mode_stream) = room.notification_mode().await;
+    pin_mut!(mode_stream);
+
+    assert_eq!(mode, Some(RoomNotificationMode::AllMessages));
+    assert_pending!(mode_stream);
+
+    // The room is muted from another device.
+    let ruleset = build_ruleset(vec![(RuleKind::Override, room_id, false)]);
+
+    sync_then_assert_request_and_fake_response! {
+        [server, room_list, sync]
+        assert request >= {
+            "lists": {
+                ALL_ROOMS: {
+                    "ranges": [[0, 0]],
+                },
+            },
+        },
+        respond with = {
+            "pos": "1",
+            "lists": {},
+            "rooms": {},
+            "extensions": {
+                "account_data": {
+                    "global": [
+                        {
+                            "type": "m.push_rules",
+                            "content": { "global": ruleset },
+                        },
+                    ],
+                },
+            },
+        },
+    };
+
+    assert_next_matches!(mode_stream, Some(RoomNotificationMode::Mute));
+    assert_pending!(mode_stream);
+
+    Ok(())
+}
+
 #[async_test]
 async fn test_room_timeline() -> Result<(), Error> {
     let (_, server, room_list) = new_room_list_service().await?;
@@ -297,6 +297,24 @@ impl ClientBuilder {
         self
     }
 
+    /// In addition to [identity assertion], all outgoing http requests will
+    /// have a GET query key-value appended with `org.matrix.msc3202.device_id`
+    /// being the key and the `device_id` from the `Session` being the value.
+    ///
+    /// This lets an application service act as one of the devices of its
+    /// users, for example to upload their encryption keys, as described in
+    /// [MSC3202].
+    ///
+    /// [identity assertion]: https://spec.matrix.org/unstable/application-service-api/#identity-assertion
+    /// [MSC3202]: https://github.com/matrix-org/matrix-spec-proposals/pull/3202
+    #[doc(hidden)]
+    #[cfg(feature = "appservice")]
+    pub fn assert_device_identity(mut self) -> Self {
+        self.request_config.assert_identity = true;
+        self.request_config.assert_device_identity = true;
+        self
+    }
+
     /// Specify the Matrix versions supported by the homeserver manually, rather
     /// than `build()` doing it using a `get_supported_versions` request.
     ///
@@ -373,6 +391,8 @@ impl ClientBuilder {
             HttpConfig::Custom(c) => c,
         };
 
+        let mut stores = BuilderStores::Custom;
+
         let base_client = if let Some(base_client) = self.base_client {
             base_client
         } else {
@@ -380,11 +400,62 @@ impl ClientBuilder {
             let store_config = match self.store_config {
                 #[cfg(feature = "sqlite")]
                 BuilderStoreConfig::Sqlite { path, passphrase } => {
-                    matrix_sdk_sqlite::make_store_config(&path, passphrase.as_deref()).await?
+                    let state_store =
+                        matrix_sdk_sqlite::SqliteStateStore::open(&path, passphrase.as_deref())
+                            .await?;
+                    let config = StoreConfig::new().state_store(state_store.clone());
+
+                    #[cfg(feature = "e2e-encryption")]
+                    let crypto_store =
+                        matrix_sdk_sqlite::SqliteCryptoStore::open(&path, passphrase.as_deref())
+                            .await?;
+                    #[cfg(feature = "e2e-encryption")]
+                    let config = config.crypto_store(crypto_store.clone());
+
+                    stores = BuilderStores::Sqlite {
+                        path,
+                        state_store,
+                        #[cfg(feature = "e2e-encryption")]
+                        crypto_store,
+                    };
+                    config
                 }
                 #[cfg(feature = "indexeddb")]
                 BuilderStoreConfig::IndexedDb { name, passphrase } => {
-                    matrix_sdk_indexeddb::make_store_config(&name, passphrase.as_deref()).await?
+                    #[cfg(feature = "e2e-encryption")]
+                    let (state_store, crypto_store) =
+                        matrix_sdk_indexeddb::open_stores_with_name(&name, passphrase.as_deref())
+                            .await?;
+
+                    #[cfg(not(feature = "e2e-encryption"))]
+                    let state_store = {
+                        let mut builder =
+                            matrix_sdk_indexeddb::IndexeddbStateStore::builder().name(name.clone());
+                        if let Some(passphrase) = passphrase {
+                            builder = builder.passphrase(passphrase);
+                        }
+                        builder.build().await.map_err(|e| {
+                            matrix_sdk_indexeddb::OpenStoreError::from(
+                                matrix_sdk_base::store::StoreError::from(e),
+                            )
+                        })?
+                    };
+
+                    let state_store = Arc::new(state_store);
+                    let config = StoreConfig::new().state_store(state_store.clone());
+
+                    #[cfg(feature = "e2e-encryption")]
+                    let crypto_store = Arc::new(crypto_store);
+                    #[cfg(feature = "e2e-encryption")]
+                    let config = config.crypto_store(crypto_store.clone());
+
+                    stores = BuilderStores::IndexedDb {
+                        name,
+                        state_store,
+                        #[cfg(feature = "e2e-encryption")]
+                        crypto_store,
+                    };
+                    config
                 }
                 BuilderStoreConfig::Custom(config) => config,
             };
@@ -460,6 +531,7 @@ impl ClientBuilder {
             self.appservice_mode,
             self.respect_login_well_known,
             self.handle_refresh_tokens,
+            stores,
         ));
 
         debug!("Done building the Client");
@@ -515,6 +587,67 @@ impl Default for HttpConfig {
     }
 }
 
+/// The stores opened by the [`ClientBuilder`], which are closed and deleted by
+/// [`Client::logout_and_wipe`].
+pub(crate) enum BuilderStores {
+    #[cfg(feature = "sqlite")]
+    Sqlite {
+        path: std::path::PathBuf,
+        state_store: matrix_sdk_sqlite::SqliteStateStore,
+        #[cfg(feature = "e2e-encryption")]
+        crypto_store: matrix_sdk_sqlite::SqliteCryptoStore,
+    },
+    #[cfg(feature = "indexeddb")]
+    IndexedDb {
+        name: String,
+        state_store: Arc<matrix_sdk_indexeddb::IndexeddbStateStore>,
+        #[cfg(feature = "e2e-encryption")]
+        crypto_store: Arc<matrix_sdk_indexeddb::IndexeddbCryptoStore>,
+    },
+    /// The stores were set up with [`ClientBuilder::store_config`], or are in
+    /// memory, so the client doesn't know how to delete them.
+    Custom,
+}
+
+impl BuilderStores {
+    /// Close the stores and delete their data.
+    pub(crate) async fn close_and_delete(&self) -> crate::Result<()> {
+        match self {
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite {
+                path,
+                state_store,
+                #[cfg(feature = "e2e-encryption")]
+                crypto_store,
+            } => {
+                state_store.close();
+                #[cfg(feature = "e2e-encryption")]
+                crypto_store.close();
+
+                matrix_sdk_sqlite::delete_stores(path).await?;
+            }
+            #[cfg(feature = "indexeddb")]
+            Self::IndexedDb {
+                name,
+                state_store,
+                #[cfg(feature = "e2e-encryption")]
+                crypto_store,
+            } => {
+                state_store.close();
+                #[cfg(feature = "e2e-encryption")]
+                crypto_store.close();
+
+                matrix_sdk_indexeddb::delete_stores(name)
+                    .await
+                    .map_err(matrix_sdk_base::store::StoreError::from)?;
+            }
+            Self::Custom => {}
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Clone)]
 enum BuilderStoreConfig {
     #[cfg(feature = "sqlite")]
@@ -84,85 +84,89 @@ where
     fn into_future(self) -> Self::IntoFuture {
         let Self { client, request, config, send_progress } = self;
         Box::pin(async move {
-            let res =
+            send_with_token_refresh(&client, || {
                 Box::pin(client.send_inner(request.clone(), config, None, send_progress.clone()))
-                    .await;
+            })
+            .await
+        })
+    }
+}
 
-            // An `M_UNKNOWN_TOKEN` error can potentially be fixed with a token refresh.
-            if let Err(Some(ErrorKind::UnknownToken { soft_logout })) =
-                res.as_ref().map_err(HttpError::client_api_error_kind)
-            {
-                trace!("Token refresh: Unknown token error received.");
-                // If automatic token refresh isn't supported, there is nothing more to do.
-                if !client.inner.handle_refresh_tokens {
-                    trace!("Token refresh: Automatic refresh disabled.");
+/// Send a request using the given closure, refreshing the access token and
+/// sending the request again if it failed with an `M_UNKNOWN_TOKEN` error.
+pub(super) async fn send_with_token_refresh<T, F, Fut>(client: &Client, send: F) -> HttpResult<T>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = HttpResult<T>>,
+{
+    let res = send().await;
+
+    // An `M_UNKNOWN_TOKEN` error can potentially be fixed with a token refresh.
+    if let Err(Some(ErrorKind::UnknownToken { soft_logout })) =
+        res.as_ref().map_err(HttpError::client_api_error_kind)
+    {
+        trace!("Token refresh: Unknown token error received.");
+        // If automatic token refresh isn't supported, there is nothing more to do.
+        if !client.inner.handle_refresh_tokens {
+            trace!("Token refresh: Automatic refresh disabled.");
+            client.broadcast_unknown_token(soft_logout);
+            return res;
+        }
+
+        #[cfg(feature = "experimental-oidc")]
+        let refresh_token =
+            client.session().as_ref().and_then(|s| s.get_refresh_token().map(ToOwned::to_owned));
+
+        // Try to refresh the token and retry the request.
+        if let Err(refresh_error) = client.refresh_access_token().await {
+            match &refresh_error {
+                RefreshTokenError::RefreshTokenRequired => {
+                    trace!("Token refresh: The session doesn't have a refresh token.");
+                    // Refreshing access tokens is not supported by this `Session`, ignore.
                     client.broadcast_unknown_token(soft_logout);
-                    return res;
                 }
-
                 #[cfg(feature = "experimental-oidc")]
-                let refresh_token = client
-                    .session()
-                    .as_ref()
-                    .and_then(|s| s.get_refresh_token().map(ToOwned::to_owned));
+                RefreshTokenError::Oidc(oidc_error) => {
+                    let oidc_error = oidc_error.deref();
+                    match oidc_error {
+                        OidcError::Oidc(OidcClientError::TokenRefresh(
+                            TokenRefreshError::Token(TokenRequestError::Http(OidcHttpError {
+                                body:
+                                    Some(OidcErrorBody { error: ClientErrorCode::InvalidGrant, .. }),
+                                ..
+                            })),
+                        )) => {
+                            let hash = refresh_token.map(|t| {
+                                let mut hasher = DefaultHasher::new();
+                                t.hash(&mut hasher);
+                                hasher.finish()
+                            });
 
-                // Try to refresh the token and retry the request.
-                if let Err(refresh_error) = client.refresh_access_token().await {
-                    match &refresh_error {
-                        RefreshTokenError::RefreshTokenRequired => {
-                            trace!("Token refresh: The session doesn't have a refresh token.");
-                            // Refreshing access tokens is not supported by this `Session`, ignore.
+                            error!("Token refresh: OIDC refresh_token rejected {:?}", hash);
+                            // The refresh was denied, signal to sign out the user.
                             client.broadcast_unknown_token(soft_logout);
                         }
-                        #[cfg(feature = "experimental-oidc")]
-                        RefreshTokenError::Oidc(oidc_error) => {
-                            let oidc_error = oidc_error.deref();
-                            match oidc_error {
-                                OidcError::Oidc(OidcClientError::TokenRefresh(
-                                    TokenRefreshError::Token(TokenRequestError::Http(
-                                        OidcHttpError {
-                                            body:
-                                                Some(OidcErrorBody {
-                                                    error: ClientErrorCode::InvalidGrant,
-                                                    ..
-                                                }),
-                                            ..
-                                        },
-                                    )),
-                                )) => {
-                                    let hash = refresh_token.map(|t| {
-                                        let mut hasher = DefaultHasher::new();
-                                        t.hash(&mut hasher);
-                                        hasher.finish()
-                                    });
-
-                                    error!("Token refresh: OIDC refresh_token rejected {:?}", hash);
-                                    // The refresh was denied, signal to sign out the user.
-                                    client.broadcast_unknown_token(soft_logout);
-                                }
-                                _ => {
-                                    trace!("Token refresh: OIDC refresh encountered a problem.");
-                                    // The refresh failed for other reasons, no
-                                    // need to sign out.
-                                }
-                            };
-                            return Err(refresh_error.into());
-                        }
                         _ => {
-                            trace!("Token refresh: Token refresh failed.");
-                            // This isn't necessarily correct, but matches the behaviour when
-                            // implementing OIDC.
-                            client.broadcast_unknown_token(soft_logout);
-                            return Err(refresh_error.into());
+                            trace!("Token refresh: OIDC refresh encountered a problem.");
+                            // The refresh failed for other reasons, no
+                            // need to sign out.
                         }
-                    }
-                } else {
-                    trace!("Token refresh: Refresh succeeded, retrying request.");
-                    return Box::pin(client.send_inner(request, config, None, send_progress)).await;
+                    };
+                    return Err(refresh_error.into());
+                }
+                _ => {
+                    trace!("Token refresh: Token refresh failed.");
+                    // This isn't necessarily correct, but matches the behaviour when
+                    // implementing OIDC.
+                    client.broadcast_unknown_token(soft_logout);
+                    return Err(refresh_error.into());
                 }
             }
-
-            res
-        })
+        } else {
+            trace!("Token refresh: Refresh succeeded, retrying request.");
+            return send().await;
+        }
     }
+
+    res
 }
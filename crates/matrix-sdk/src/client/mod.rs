@@ -22,7 +22,10 @@ use std::{
     future::Future,
     hash::{Hash, Hasher},
     pin::Pin,
-    sync::{Arc, Mutex as StdMutex},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex as StdMutex,
+    },
 };
 
 use dashmap::DashMap;
@@ -39,14 +42,15 @@ use mas_oidc_client::{
 #[cfg(feature = "e2e-encryption")]
 use matrix_sdk_base::crypto::store::locks::CryptoStoreLock;
 use matrix_sdk_base::{
-    store::DynStateStore, BaseClient, RoomState, RoomStateFilter, SendOutsideWasm, SessionMeta,
-    SyncOutsideWasm,
+    store::{
+        locks::{StateStoreLock, StateStoreLockGuard},
+        DynStateStore,
+    },
+    BaseClient, RoomState, RoomStateFilter, SendOutsideWasm, SessionMeta, SyncOutsideWasm,
 };
 use matrix_sdk_common::instant::Instant;
 #[cfg(feature = "experimental-sliding-sync")]
 use ruma::api::client::error::ErrorKind;
-#[cfg(feature = "appservice")]
-use ruma::TransactionId;
 use ruma::{
     api::{
         client::{
@@ -77,13 +81,15 @@ use ruma::{
     DeviceId, OwnedDeviceId, OwnedRoomId, OwnedServerName, RoomAliasId, RoomId, RoomOrAliasId,
     ServerName, UInt, UserId,
 };
+#[cfg(feature = "appservice")]
+use ruma::{OwnedTransactionId, TransactionId};
 use serde::de::DeserializeOwned;
 use tokio::sync::{broadcast, Mutex, OnceCell, RwLock, RwLockReadGuard};
-use tracing::{debug, error, info, instrument, trace, Instrument, Span};
+use tracing::{debug, error, info, instrument, trace, warn, Instrument, Span};
 use url::Url;
 
 #[cfg(feature = "e2e-encryption")]
-use crate::encryption::Encryption;
+use crate::encryption::{dehydrated_devices::PickleKeySource, Encryption};
 #[cfg(feature = "experimental-oidc")]
 use crate::oidc::{Oidc, OidcError};
 use crate::{
@@ -104,6 +110,7 @@ use crate::{
 mod builder;
 mod futures;
 
+use self::builder::BuilderStores;
 pub use self::{
     builder::{ClientBuildError, ClientBuilder},
     futures::SendRequest,
@@ -144,8 +151,28 @@ pub enum SessionChange {
     },
     /// The session's tokens have been refreshed.
     TokensRefreshed,
+    /// The session has been logged out and its local data has been deleted,
+    /// see [`Client::logout_and_wipe`].
+    Wiped,
 }
 
+/// Maximum backoff, in milliseconds, when waiting for the cross-process state
+/// store lock before processing a sync response.
+pub(crate) const STATE_STORE_LOCK_MAX_BACKOFF_MS: u32 = 60000;
+
+/// The key of the custom value holding the ids of the last processed
+/// appservice transactions.
+#[cfg(feature = "appservice")]
+const PROCESSED_TRANSACTIONS_KEY: &[u8] =
+    matrix_sdk_base::store::custom_value_keys::PROCESSED_TRANSACTIONS;
+
+/// The number of processed appservice transaction ids that are remembered.
+///
+/// The homeserver only retries the transaction it is currently trying to
+/// push, so only the most recent ids are needed to detect retries.
+#[cfg(feature = "appservice")]
+const MAX_PROCESSED_TRANSACTIONS: usize = 100;
+
 /// An async/await enabled Matrix client.
 ///
 /// All of the state is held in an `Arc` so the `Client` can be cloned freely.
@@ -209,6 +236,16 @@ pub(crate) struct ClientInner {
     pub(crate) session_change_sender: broadcast::Sender<SessionChange>,
     /// Authentication data to keep in memory.
     pub(crate) auth_data: OnceCell<AuthData>,
+    /// The stores opened by the [`ClientBuilder`], to delete them in
+    /// [`Client::logout_and_wipe`].
+    stores: BuilderStores,
+    /// Whether the session was logged out and its local data deleted, see
+    /// [`Client::logout_and_wipe`].
+    wiped: AtomicBool,
+
+    /// Cross-process lock on the state store, see
+    /// [`Client::enable_cross_process_state_store_lock`].
+    pub(crate) cross_process_state_store_lock: OnceCell<StateStoreLock>,
 
     #[cfg(feature = "e2e-encryption")]
     pub(crate) cross_process_crypto_store_lock: OnceCell<CryptoStoreLock>,
@@ -232,6 +269,13 @@ pub(crate) struct ClientInner {
     /// outside the `OlmMachine`.
     #[cfg(feature = "e2e-encryption")]
     pub(crate) crypto_store_generation: Arc<Mutex<Option<u64>>>,
+
+    /// The pickle key used to rehydrate the dehydrated device after the next
+    /// login, see [`DehydratedDevices::rehydrate_on_login`].
+    ///
+    /// [`DehydratedDevices::rehydrate_on_login`]: crate::encryption::dehydrated_devices::DehydratedDevices::rehydrate_on_login
+    #[cfg(feature = "e2e-encryption")]
+    pub(crate) rehydration_pickle_key: StdMutex<Option<PickleKeySource>>,
 }
 
 impl ClientInner {
@@ -246,6 +290,7 @@ impl ClientInner {
         appservice_mode: bool,
         respect_login_well_known: bool,
         handle_refresh_tokens: bool,
+        stores: BuilderStores,
     ) -> Self {
         let session_change_sender = broadcast::Sender::new(1);
 
@@ -275,10 +320,15 @@ impl ClientInner {
             refresh_token_lock: Mutex::new(Ok(())),
             session_change_sender,
             auth_data: Default::default(),
+            stores,
+            wiped: AtomicBool::new(false),
+            cross_process_state_store_lock: OnceCell::new(),
             #[cfg(feature = "e2e-encryption")]
             cross_process_crypto_store_lock: OnceCell::new(),
             #[cfg(feature = "e2e-encryption")]
             crypto_store_generation: Arc::new(Mutex::new(None)),
+            #[cfg(feature = "e2e-encryption")]
+            rehydration_pickle_key: StdMutex::new(None),
         }
     }
 }
@@ -358,11 +408,15 @@ impl Client {
     /// Process a [transaction] received from the homeserver which has been
     /// converted into a sync response.
     ///
+    /// The ids of the last processed transactions are persisted in the store,
+    /// a transaction that was already processed is ignored. A transaction is
+    /// only marked as processed once its sync response was processed
+    /// successfully, so it can be retried after an error.
+    ///
     /// # Arguments
     ///
     /// * `transaction_id` - The id of the transaction, used to guard against
-    ///   the same transaction being sent twice. This guarding currently isn't
-    ///   implemented.
+    ///   the same transaction being sent twice.
     /// * `sync_response` - The sync response converted from a transaction
     ///   received from the homeserver.
     ///
@@ -373,32 +427,103 @@ impl Client {
         transaction_id: &TransactionId,
         sync_response: sync_events::v3::Response,
     ) -> Result<()> {
-        const TXN_ID_KEY: &[u8] = b"appservice.txn_id";
-
-        let store = self.store();
-        let store_tokens = store.get_custom_value(TXN_ID_KEY).await?;
-        let mut txn_id_bytes = transaction_id.as_bytes().to_vec();
-        if let Some(mut store_tokens) = store_tokens {
-            // The data is separated by a NULL byte.
-            let mut store_tokens_split = store_tokens.split(|x| *x == b'\0');
-            if store_tokens_split.any(|x| x == transaction_id.as_bytes()) {
-                // We already encountered this transaction id before, so we exit early instead
-                // of processing further.
-                //
-                // Spec: https://spec.matrix.org/v1.3/application-service-api/#pushing-events
-                return Ok(());
-            }
-            store_tokens.push(b'\0');
-            store_tokens.append(&mut txn_id_bytes);
-            self.store().set_custom_value(TXN_ID_KEY, store_tokens).await?;
-        } else {
-            self.store().set_custom_value(TXN_ID_KEY, txn_id_bytes).await?;
+        let mut processed = self.processed_transactions().await?;
+        if processed.iter().any(|id| id == transaction_id) {
+            // We already encountered this transaction id before, so we exit early instead
+            // of processing further.
+            //
+            // Spec: https://spec.matrix.org/v1.3/application-service-api/#pushing-events
+            return Ok(());
         }
+
         self.process_sync(sync_response).await?;
 
+        processed.push(transaction_id.to_owned());
+        if processed.len() > MAX_PROCESSED_TRANSACTIONS {
+            processed.drain(..processed.len() - MAX_PROCESSED_TRANSACTIONS);
+        }
+        self.store()
+            .set_custom_value(PROCESSED_TRANSACTIONS_KEY, serde_json::to_vec(&processed)?)
+            .await?;
+
+        // Only a client that acts as one of the devices of its user can send
+        // the requests of its `OlmMachine`, like key uploads.
+        #[cfg(feature = "e2e-encryption")]
+        if self.inner.http_client.request_config.assert_device_identity {
+            self.encryption().send_outgoing_requests().await?;
+        }
+
         Ok(())
     }
 
+    /// Whether the [transaction] with the given id was already processed by
+    /// [`Client::receive_transaction`].
+    ///
+    /// Only the ids of the last 100 processed transactions are remembered.
+    ///
+    /// [transaction]: https://matrix.org/docs/spec/application_service/r0.1.2#put-matrix-app-v1-transactions-txnid
+    #[cfg(feature = "appservice")]
+    pub async fn is_transaction_processed(&self, transaction_id: &TransactionId) -> Result<bool> {
+        Ok(self.processed_transactions().await?.iter().any(|id| id == transaction_id))
+    }
+
+    #[cfg(feature = "appservice")]
+    async fn processed_transactions(&self) -> Result<Vec<OwnedTransactionId>> {
+        match self.store().get_custom_value(PROCESSED_TRANSACTIONS_KEY).await? {
+            Some(value) => Ok(serde_json::from_slice(&value)?),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Send an arbitrary request to the server, on behalf of the given user.
+    ///
+    /// This uses [identity assertion], so this client must use the access
+    /// token of an application service, and the user must be in one of its
+    /// namespaces.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - A filled out and valid request for the endpoint to be hit
+    ///
+    /// * `user_id` - The user on behalf of which the request is sent.
+    ///
+    /// * `config` - Optional request configuration for the HTTP client,
+    ///   overrides the default request setting if one was set.
+    ///
+    /// [identity assertion]: https://spec.matrix.org/unstable/application-service-api/#identity-assertion
+    #[cfg(feature = "appservice")]
+    pub async fn send_as<Request>(
+        &self,
+        request: Request,
+        user_id: &UserId,
+        config: Option<RequestConfig>,
+    ) -> HttpResult<Request::IncomingResponse>
+    where
+        Request: OutgoingRequest + Debug,
+        HttpError: From<FromHttpResponseError<Request::EndpointError>>,
+    {
+        let mut config = config.unwrap_or_else(|| self.request_config());
+        config.assert_identity = true;
+        config.assert_device_identity = false;
+
+        let homeserver = self.homeserver().await.to_string();
+        let access_token = self.access_token();
+
+        self.inner
+            .http_client
+            .send(
+                request,
+                Some(config),
+                homeserver,
+                access_token.as_deref(),
+                Some(user_id),
+                None,
+                self.server_versions().await?,
+                Default::default(),
+            )
+            .await
+    }
+
     /// Get a copy of the default request config.
     ///
     /// The default request config is what's used when sending requests if no
@@ -528,6 +653,76 @@ impl Client {
         self.base_client().store()
     }
 
+    /// Enables the state store cross-process lock.
+    ///
+    /// This is required if there are multiple processes that may process sync
+    /// responses into the same state store, for instance an app and its
+    /// notification extension. Once enabled, sync responses are only
+    /// processed while holding the lock, and the in-memory state of this
+    /// client is reloaded whenever another process wrote to the store in the
+    /// meantime.
+    ///
+    /// The provided `lock_value` must be a unique identifier for this process.
+    pub fn enable_cross_process_state_store_lock(&self, lock_value: String) {
+        if let Some(prev_lock) = self.inner.cross_process_state_store_lock.get() {
+            let prev_holder = prev_lock.lock_holder();
+            if prev_holder != lock_value {
+                warn!(
+                    "the cross-process state store lock has already been enabled with a \
+                     different holder value: prev was {prev_holder}, new is {lock_value}"
+                );
+            }
+            return;
+        }
+
+        let lock = self
+            .base_client()
+            .create_state_store_lock("cross_process_state_store_lock".to_owned(), lock_value);
+
+        // Ignore the error, it only means the lock was set concurrently.
+        let _ = self.inner.cross_process_state_store_lock.set(lock);
+    }
+
+    /// If a lock was created with
+    /// [`Self::enable_cross_process_state_store_lock`], spin-waits until the
+    /// lock is available.
+    ///
+    /// Reloads the rooms from the state store, after obtaining the lock, if
+    /// another process wrote to it in the meantime.
+    pub async fn spin_lock_state_store(
+        &self,
+        max_backoff: Option<u32>,
+    ) -> Result<Option<StateStoreLockGuard>> {
+        if let Some(lock) = self.inner.cross_process_state_store_lock.get() {
+            let guard = lock.spin_lock(max_backoff).await?;
+
+            self.base_client().reload_state_if_modified(lock.lock_holder()).await?;
+
+            Ok(Some(guard))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// If a lock was created with
+    /// [`Self::enable_cross_process_state_store_lock`], attempts to lock it
+    /// once.
+    ///
+    /// Returns a guard to the lock, if it was obtained.
+    pub async fn try_lock_state_store_once(&self) -> Result<Option<StateStoreLockGuard>> {
+        if let Some(lock) = self.inner.cross_process_state_store_lock.get() {
+            let maybe_guard = lock.try_lock_once().await?;
+
+            if maybe_guard.is_some() {
+                self.base_client().reload_state_if_modified(lock.lock_holder()).await?;
+            }
+
+            Ok(maybe_guard)
+        } else {
+            Ok(None)
+        }
+    }
+
     /// Access the native Matrix authentication API with this client.
     pub fn matrix_auth(&self) -> MatrixAuth {
         MatrixAuth::new(self.clone())
@@ -1423,12 +1618,83 @@ impl Client {
                 homeserver,
                 access_token,
                 self.user_id(),
+                self.device_id(),
                 self.server_versions().await?,
                 send_progress,
             )
             .await
     }
 
+    /// Send the given download request and get the raw response, so its body
+    /// can be streamed instead of being buffered in memory.
+    ///
+    /// Like for [`Client::send()`], the access token is refreshed if needed.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) async fn download_inner<Request>(
+        &self,
+        request: Request,
+        config: Option<RequestConfig>,
+    ) -> HttpResult<reqwest::Response>
+    where
+        Request: OutgoingRequest + Clone + Debug,
+        HttpError: From<FromHttpResponseError<Request::EndpointError>>,
+    {
+        futures::send_with_token_refresh(self, || async {
+            let homeserver = self.homeserver().await.to_string();
+            let access_token = self.access_token();
+
+            self.inner
+                .http_client
+                .download(
+                    request.clone(),
+                    config,
+                    homeserver,
+                    access_token.as_deref(),
+                    self.user_id(),
+                    self.device_id(),
+                    self.server_versions().await?,
+                )
+                .await
+        })
+        .await
+    }
+
+    /// Send the given upload request, streaming the given `body` instead of
+    /// the serialized body of the request.
+    ///
+    /// The request isn't retried, not even after refreshing the access token,
+    /// since the body can only be consumed once.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) async fn upload_stream_inner<Request>(
+        &self,
+        request: Request,
+        body: reqwest::Body,
+        content_length: u64,
+        config: Option<RequestConfig>,
+    ) -> HttpResult<Request::IncomingResponse>
+    where
+        Request: OutgoingRequest + Debug,
+        HttpError: From<FromHttpResponseError<Request::EndpointError>>,
+    {
+        let homeserver = self.homeserver().await.to_string();
+        let access_token = self.access_token();
+
+        self.inner
+            .http_client
+            .upload_stream(
+                request,
+                body,
+                content_length,
+                config,
+                homeserver,
+                access_token.as_deref(),
+                self.user_id(),
+                self.device_id(),
+                self.server_versions().await?,
+            )
+            .await
+    }
+
     fn broadcast_unknown_token(&self, soft_logout: &bool) {
         info!("An unknown token error has been encountered.");
         _ = self
@@ -1664,6 +1930,10 @@ impl Client {
         &self,
         sync_settings: crate::config::SyncSettings,
     ) -> Result<SyncResponse> {
+        if self.is_wiped() {
+            return Err(Error::AuthenticationRequired);
+        }
+
         // The sync might not return for quite a while due to the timeout.
         // We'll see if there's anything crypto related to send out before we
         // sync, i.e. if we closed our client after a sync but before the
@@ -1688,6 +1958,12 @@ impl Client {
         }
 
         let response = self.send(request, Some(request_config)).await?;
+
+        // Don't restore any data if the session was wiped during the request.
+        if self.is_wiped() {
+            return Err(Error::AuthenticationRequired);
+        }
+
         let next_batch = response.next_batch.clone();
         let response = self.process_sync(response).await?;
 
@@ -1915,6 +2191,11 @@ impl Client {
         }
 
         loop {
+            if self.is_wiped() {
+                debug!("The session was wiped, stopping the sync loop");
+                break;
+            }
+
             trace!("Syncing");
             let result = self.sync_loop_helper(&mut sync_settings).await;
 
@@ -1988,6 +2269,10 @@ impl Client {
 
         async_stream::stream! {
             loop {
+                if self.is_wiped() {
+                    break;
+                }
+
                 yield self.sync_loop_helper(&mut sync_settings).instrument(parent_span.clone()).await;
 
                 Client::delay_sync(&mut last_sync_time).await
@@ -2013,6 +2298,60 @@ impl Client {
         broadcast.subscribe()
     }
 
+    /// Log out of the current session and delete all its local data.
+    ///
+    /// This invalidates the tokens of the session on the server, then:
+    ///
+    /// - stops the sync loops of this client, including the sliding sync ones,
+    /// - closes and deletes the stores set up with
+    ///   [`ClientBuilder::sqlite_store`] or `ClientBuilder::indexeddb_store`,
+    ///   which contain the state, the encryption keys, the media cache and the
+    ///   sliding sync caches,
+    /// - forgets the state loaded in memory,
+    /// - broadcasts [`SessionChange::Wiped`], see
+    ///   [`Client::subscribe_to_session_changes`].
+    ///
+    /// The local data is deleted even if the request to log out fails. The
+    /// error is returned afterwards, since the tokens might still be valid on
+    /// the server.
+    ///
+    /// Stores set up with [`ClientBuilder::store_config`] are not deleted,
+    /// since the client doesn't know where they are. Their data must be deleted
+    /// separately, for example with `matrix_sdk_sqlite::delete_stores` for
+    /// SQLite stores, which can also be used without a `Client`.
+    ///
+    /// This client, and all its clones, must not be used afterwards.
+    pub async fn logout_and_wipe(&self) -> Result<()> {
+        let logout_result = match self.auth_api() {
+            Some(AuthApi::Matrix(api)) => api.logout().await.map(|_| ()).map_err(Error::from),
+            #[cfg(feature = "experimental-oidc")]
+            Some(AuthApi::Oidc(api)) => api.logout().await.map(|_| ()).map_err(Error::from),
+            None => Ok(()),
+        };
+
+        if let Err(error) = &logout_result {
+            warn!("Failed to log out, deleting the local data anyway: {error}");
+        }
+
+        self.inner.wiped.store(true, Ordering::SeqCst);
+
+        // Forget the state loaded in memory first, it holds the `OlmMachine` which
+        // uses the crypto store.
+        self.inner.base_client.clear_in_memory_state().await;
+        let delete_result = self.inner.stores.close_and_delete().await;
+
+        _ = self.inner.session_change_sender.send(SessionChange::Wiped);
+
+        delete_result?;
+        logout_result
+    }
+
+    /// Whether the session was logged out and its local data deleted with
+    /// [`Client::logout_and_wipe`].
+    pub fn is_wiped(&self) -> bool {
+        self.inner.wiped.load(Ordering::SeqCst)
+    }
+
     /// Sets a given pusher
     pub async fn set_pusher(&self, pusher: Pusher) -> HttpResult<set_pusher::v3::Response> {
         let request = set_pusher::v3::Request::post(pusher);
@@ -2059,6 +2398,9 @@ impl Client {
                 self.inner.appservice_mode,
                 self.inner.respect_login_well_known,
                 self.inner.handle_refresh_tokens,
+                // The state store of this client is in memory, its data doesn't need to be
+                // deleted.
+                BuilderStores::Custom,
             )),
         };
 
@@ -2076,6 +2418,7 @@ impl Client {
 pub(crate) mod tests {
     use std::time::Duration;
 
+    use assert_matches::assert_matches;
     use matrix_sdk_base::RoomState;
     use matrix_sdk_test::{
         async_test, test_json, JoinedRoomBuilder, StateTestEvent, SyncResponseBuilder,
@@ -2084,16 +2427,18 @@ pub(crate) mod tests {
     wasm_bindgen_test::wasm_bindgen_test_configure!(run_in_browser);
 
     use ruma::{events::ignored_user_list::IgnoredUserListEventContent, UserId};
+    use serde_json::json;
     use url::Url;
     use wiremock::{
-        matchers::{body_json, header, method, path},
+        matchers::{body_json, header, method, path, path_regex},
         Mock, MockServer, ResponseTemplate,
     };
 
-    use super::Client;
+    use super::{Client, SessionChange};
     use crate::{
         config::{RequestConfig, SyncSettings},
         test_utils::{logged_in_client, no_retry_test_client, test_client_builder},
+        Error,
     };
 
     #[async_test]
@@ -2305,4 +2650,77 @@ pub(crate) mod tests {
         assert_eq!(response.results.len(), 1);
         assert!(!response.limited);
     }
+
+    #[async_test]
+    async fn logout_and_wipe() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(Some(server.uri())).await;
+
+        Mock::given(method("GET"))
+            .and(path("/_matrix/client/r0/sync".to_owned()))
+            .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::SYNC))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path_regex(r"^/_matrix/client/.*/logout"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        client.sync_once(SyncSettings::default()).await.unwrap();
+        assert!(!client.rooms().is_empty());
+
+        let mut session_changes = client.subscribe_to_session_changes();
+        client.logout_and_wipe().await.unwrap();
+
+        assert!(client.is_wiped());
+        assert_matches!(session_changes.recv().await, Ok(SessionChange::Wiped));
+        assert!(client.rooms().is_empty());
+        assert_matches!(
+            client.sync_once(SyncSettings::default()).await,
+            Err(Error::AuthenticationRequired)
+        );
+    }
+
+    #[cfg(all(feature = "sqlite", not(target_arch = "wasm32")))]
+    #[async_test]
+    async fn logout_and_wipe_sqlite_stores() {
+        use matrix_sdk_base::{SessionMeta, StateStoreDataKey};
+        use ruma::{device_id, user_id};
+
+        use crate::matrix_auth::{Session, SessionTokens};
+
+        let server = MockServer::start().await;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("store");
+
+        let client = test_client_builder(Some(server.uri()))
+            .request_config(RequestConfig::new().disable_retry())
+            .sqlite_store(&path, None)
+            .build()
+            .await
+            .unwrap();
+        let session = Session {
+            meta: SessionMeta {
+                user_id: user_id!("@example:localhost").to_owned(),
+                device_id: device_id!("DEVICEID").to_owned(),
+            },
+            tokens: SessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+        };
+        client.matrix_auth().restore_session(session).await.unwrap();
+
+        Mock::given(method("POST"))
+            .and(path_regex(r"^/_matrix/client/.*/logout"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+            .mount(&server)
+            .await;
+
+        assert!(path.exists());
+        client.logout_and_wipe().await.unwrap();
+
+        // The stores were closed, so their files could be deleted.
+        assert!(!path.exists());
+        assert!(client.store().get_kv_data(StateStoreDataKey::SyncToken).await.is_err());
+    }
 }
@@ -0,0 +1,738 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! High-level support for dehydrated devices, as defined in [MSC3814].
+//!
+//! A dehydrated device is a virtual device that lives on the homeserver and
+//! receives room keys while none of the user's real devices are online. Once
+//! the user logs in on a new device, the dehydrated device can be rehydrated,
+//! its to-device events downloaded and the room keys it received imported into
+//! our own crypto store.
+//!
+//! The private keys of the dehydrated device are encrypted using a pickle key
+//! that needs to be provided by the application, the same pickle key is needed
+//! to rehydrate the device later on. The pickle key can be stored in the
+//! user's [secret storage], using
+//! [`DehydratedDevices::store_pickle_key()`], so it is available on all the
+//! devices that have access to the secret storage key.
+//!
+//! Applications that want the dehydrated device to be rehydrated as soon as
+//! the user logs in can use [`DehydratedDevices::rehydrate_on_login()`].
+//!
+//! [secret storage]: https://spec.matrix.org/v1.7/client-server-api/#storage
+//! [MSC3814]: https://github.com/matrix-org/matrix-spec-proposals/pull/3814
+
+use std::{collections::BTreeMap, fmt, time::Duration};
+
+pub use matrix_sdk_base::crypto::{
+    dehydrated_devices::DehydrationError,
+    secret_storage::{SecretStorageError, SecretStorageKey},
+};
+use matrix_sdk_base::crypto::{
+    dehydrated_devices::RehydratedDevice,
+    secret_storage::AesHmacSha2EncryptedData,
+    vodozemac::{base64_decode, base64_encode},
+};
+use ruma::{
+    api::client::{
+        config::get_global_account_data,
+        dehydrated_device::{delete_dehydrated_device, get_dehydrated_device, get_events},
+        error::ErrorKind,
+    },
+    assign,
+    events::GlobalAccountDataEventType,
+    serde::Raw,
+    OwnedDeviceId,
+};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, instrument, warn};
+use zeroize::Zeroize;
+
+use crate::{Client, Error, HttpError, Result};
+
+/// The default display name of a dehydrated device.
+const DEFAULT_DISPLAY_NAME: &str = "Dehydrated device";
+
+/// The name of the secret the pickle key is stored under in secret storage,
+/// as defined in [MSC3814].
+///
+/// [MSC3814]: https://github.com/matrix-org/matrix-spec-proposals/pull/3814
+const PICKLE_KEY_SECRET_NAME: &str = "org.matrix.msc3814";
+
+/// Where the pickle key used to rehydrate the dehydrated device after login
+/// comes from, see [`DehydratedDevices::rehydrate_on_login()`].
+pub enum PickleKeySource {
+    /// The pickle key itself.
+    Key(Box<[u8; 32]>),
+    /// The secret storage key the pickle key was stored with, using
+    /// [`DehydratedDevices::store_pickle_key()`].
+    SecretStorage(SecretStorageKey),
+}
+
+#[cfg(not(tarpaulin_include))]
+impl fmt::Debug for PickleKeySource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Key(_) => f.debug_tuple("Key").field(&"<redacted>").finish(),
+            Self::SecretStorage(key) => f.debug_tuple("SecretStorage").field(key).finish(),
+        }
+    }
+}
+
+/// The content of the account data event a secret is stored in.
+#[derive(Debug, Deserialize, Serialize)]
+struct SecretEventContent {
+    encrypted: BTreeMap<String, AesHmacSha2EncryptedData>,
+}
+
+/// A high-level API to manage dehydrated devices.
+///
+/// To get this, use [`Encryption::dehydrated_devices()`].
+///
+/// [`Encryption::dehydrated_devices()`]: crate::encryption::Encryption::dehydrated_devices
+#[derive(Debug, Clone)]
+pub struct DehydratedDevices {
+    pub(super) client: Client,
+}
+
+impl DehydratedDevices {
+    /// Create a new dehydrated device and upload it to the homeserver.
+    ///
+    /// The homeserver only keeps a single dehydrated device around, uploading
+    /// a new one will replace the existing one. This means that this method
+    /// can also be used to rotate the dehydrated device.
+    ///
+    /// Cross signing needs to be set up and the private self-signing key needs
+    /// to be available, since the dehydrated device will be signed by it.
+    ///
+    /// # Arguments
+    ///
+    /// * `pickle_key` - The key that should be used to encrypt the private
+    ///   parts of the device keys before they are uploaded.
+    ///
+    /// * `display_name` - The human-readable name the dehydrated device should
+    ///   have, defaults to "Dehydrated device".
+    ///
+    /// Returns the device ID of the newly created dehydrated device.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::Client;
+    /// # use url::Url;
+    /// # async {
+    /// # let homeserver = Url::parse("http://example.com")?;
+    /// # let client = Client::new(homeserver).await?;
+    /// // Don't use a zero key for real.
+    /// let pickle_key = [0u8; 32];
+    ///
+    /// let device_id = client
+    ///     .encryption()
+    ///     .dehydrated_devices()
+    ///     .create(&pickle_key, None)
+    ///     .await?;
+    ///
+    /// println!("Uploaded the dehydrated device {device_id}");
+    /// # anyhow::Ok(()) };
+    /// ```
+    #[instrument(skip_all)]
+    pub async fn create(
+        &self,
+        pickle_key: &[u8; 32],
+        display_name: Option<String>,
+    ) -> Result<OwnedDeviceId> {
+        let request = {
+            let olm = self.client.olm_machine().await;
+            let olm = olm.as_ref().ok_or(Error::NoOlmMachine)?;
+
+            let device = olm.dehydrated_devices().create();
+            let display_name = display_name.unwrap_or_else(|| DEFAULT_DISPLAY_NAME.to_owned());
+
+            device.keys_for_upload(display_name, pickle_key).await?
+        };
+
+        let device_id = request.device_id.clone();
+        debug!(%device_id, "Uploading a new dehydrated device");
+
+        self.client.send(request, None).await?;
+
+        info!(%device_id, "Successfully uploaded a new dehydrated device");
+
+        Ok(device_id)
+    }
+
+    /// Rehydrate the dehydrated device that currently exists on the
+    /// homeserver, if any, and import the room keys it received.
+    ///
+    /// This downloads the dehydrated device, decrypts it using the given
+    /// pickle key and then fetches all the to-device events the dehydrated
+    /// device has received, page by page. The room keys contained in those
+    /// events are imported into our own crypto store.
+    ///
+    /// This should be called after logging in on a new device, before the
+    /// first sync. Once this is done, a new dehydrated device should be
+    /// created using the [`DehydratedDevices::create()`] method.
+    ///
+    /// # Arguments
+    ///
+    /// * `pickle_key` - The key that was used to encrypt the dehydrated device
+    ///   when it was created.
+    ///
+    /// Returns the number of room keys that were imported, or `None` if no
+    /// dehydrated device exists on the homeserver.
+    #[instrument(skip_all)]
+    pub async fn rehydrate(&self, pickle_key: &[u8; 32]) -> Result<Option<usize>> {
+        let request = get_dehydrated_device::unstable::Request::new();
+
+        let response = match self.client.send(request, None).await {
+            Ok(response) => response,
+            Err(e) if e.client_api_error_kind() == Some(&ErrorKind::NotFound) => {
+                debug!("No dehydrated device exists on the homeserver");
+                return Ok(None);
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let device_id = response.device_id;
+
+        let rehydrated = {
+            let olm = self.client.olm_machine().await;
+            let olm = olm.as_ref().ok_or(Error::NoOlmMachine)?;
+
+            olm.dehydrated_devices().rehydrate(pickle_key, &device_id, response.device_data).await?
+        };
+
+        info!(%device_id, "Rehydrated the dehydrated device, fetching its to-device events");
+
+        let imported = self.receive_events(&rehydrated, device_id).await?;
+
+        info!(imported, "Imported the room keys of the dehydrated device");
+
+        Ok(Some(imported))
+    }
+
+    /// Delete the dehydrated device that currently exists on the homeserver,
+    /// if any.
+    ///
+    /// The room keys that were sent to the dehydrated device and haven't been
+    /// imported using [`DehydratedDevices::rehydrate()`] will be lost.
+    ///
+    /// Returns the device ID of the deleted device, or `None` if no dehydrated
+    /// device exists on the homeserver.
+    #[instrument(skip_all)]
+    pub async fn delete(&self) -> Result<Option<OwnedDeviceId>> {
+        let request = delete_dehydrated_device::unstable::Request::new();
+
+        match self.client.send(request, None).await {
+            Ok(response) => {
+                info!(device_id = %response.device_id, "Deleted the dehydrated device");
+                Ok(Some(response.device_id))
+            }
+            Err(e) if e.client_api_error_kind() == Some(&ErrorKind::NotFound) => {
+                debug!("No dehydrated device exists on the homeserver");
+                Ok(None)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Fetch all the to-device events the rehydrated device has received and
+    /// feed them into it.
+    async fn receive_events(
+        &self,
+        rehydrated: &RehydratedDevice,
+        device_id: OwnedDeviceId,
+    ) -> Result<usize> {
+        let mut next_batch = None;
+        let mut imported = 0;
+
+        loop {
+            let request = assign!(get_events::unstable::Request::new(device_id.clone()), {
+                next_batch,
+            });
+            let response = self.client.send(request, None).await?;
+
+            if response.events.is_empty() {
+                break;
+            }
+
+            debug!(event_count = response.events.len(), "Received a page of to-device events");
+
+            imported += rehydrated.receive_events(response.events).await?.len();
+            next_batch = response.next_batch;
+        }
+
+        Ok(imported)
+    }
+
+    /// Store the pickle key in the user's secret storage, encrypted with the
+    /// given secret storage key.
+    ///
+    /// The pickle key can then be retrieved on any device that has access to
+    /// the secret storage key, using
+    /// [`DehydratedDevices::pickle_key_from_secret_storage()`].
+    ///
+    /// # Arguments
+    ///
+    /// * `pickle_key` - The key used to encrypt the dehydrated device.
+    ///
+    /// * `secret_storage_key` - The secret storage key the pickle key should be
+    ///   encrypted with.
+    #[instrument(skip_all)]
+    pub async fn store_pickle_key(
+        &self,
+        pickle_key: &[u8; 32],
+        secret_storage_key: &SecretStorageKey,
+    ) -> Result<()> {
+        let mut encoded = base64_encode(pickle_key);
+        let encrypted = secret_storage_key.encrypt(&encoded, PICKLE_KEY_SECRET_NAME);
+        encoded.zeroize();
+
+        let content = SecretEventContent {
+            encrypted: BTreeMap::from([(secret_storage_key.key_id().to_owned(), encrypted)]),
+        };
+
+        self.client
+            .account()
+            .set_account_data_raw(PICKLE_KEY_SECRET_NAME.into(), Raw::new(&content)?.cast())
+            .await?;
+
+        debug!("Stored the pickle key in secret storage");
+
+        Ok(())
+    }
+
+    /// Get the pickle key that was stored in the user's secret storage using
+    /// [`DehydratedDevices::store_pickle_key()`].
+    ///
+    /// # Arguments
+    ///
+    /// * `secret_storage_key` - The secret storage key the pickle key was
+    ///   encrypted with.
+    ///
+    /// Returns `None` if no pickle key is stored in secret storage, or if it
+    /// wasn't encrypted with the given secret storage key.
+    #[instrument(skip_all)]
+    pub async fn pickle_key_from_secret_storage(
+        &self,
+        secret_storage_key: &SecretStorageKey,
+    ) -> Result<Option<Box<[u8; 32]>>> {
+        let user_id =
+            self.client.user_id().ok_or_else(|| Error::from(HttpError::AuthenticationRequired))?;
+        let request = get_global_account_data::v3::Request::new(
+            user_id.to_owned(),
+            GlobalAccountDataEventType::from(PICKLE_KEY_SECRET_NAME),
+        );
+
+        let response = match self.client.send(request, None).await {
+            Ok(response) => response,
+            Err(e) if e.client_api_error_kind() == Some(&ErrorKind::NotFound) => {
+                debug!("No pickle key is stored in secret storage");
+                return Ok(None);
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let content: SecretEventContent = response.account_data.deserialize_as()?;
+
+        let Some(encrypted) = content.encrypted.get(secret_storage_key.key_id()) else {
+            debug!("The pickle key isn't encrypted with the given secret storage key");
+            return Ok(None);
+        };
+
+        let mut encoded = secret_storage_key.decrypt(encrypted, PICKLE_KEY_SECRET_NAME)?;
+        let decoded = base64_decode(&encoded);
+        encoded.zeroize();
+
+        let mut decoded = decoded.map_err(|_| SecretStorageError::InvalidSecret)?;
+        let mut pickle_key = Box::new([0u8; 32]);
+
+        let result = if decoded.len() == pickle_key.len() {
+            pickle_key.copy_from_slice(&decoded);
+            Ok(Some(pickle_key))
+        } else {
+            Err(SecretStorageError::InvalidSecret.into())
+        };
+
+        decoded.zeroize();
+
+        result
+    }
+
+    /// Rehydrate the dehydrated device automatically after the next
+    /// successful login of this client.
+    ///
+    /// Rehydration errors don't make the login fail, they are logged instead.
+    /// The pickle key source is only used for a single login.
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - Where the pickle key needed to rehydrate the device should
+    ///   be taken from.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::{
+    /// #     encryption::dehydrated_devices::{PickleKeySource, SecretStorageKey},
+    /// #     Client,
+    /// # };
+    /// # use url::Url;
+    /// # async {
+    /// # let homeserver = Url::parse("http://example.com")?;
+    /// # let client = Client::new(homeserver).await?;
+    /// # let secret_storage_key = SecretStorageKey::new();
+    /// client
+    ///     .encryption()
+    ///     .dehydrated_devices()
+    ///     .rehydrate_on_login(PickleKeySource::SecretStorage(secret_storage_key));
+    ///
+    /// client.matrix_auth().login_username("example", "wordpass").send().await?;
+    /// # anyhow::Ok(()) };
+    /// ```
+    pub fn rehydrate_on_login(&self, source: PickleKeySource) {
+        *self.client.inner.rehydration_pickle_key.lock().unwrap() = Some(source);
+    }
+
+    /// Rehydrate the dehydrated device if
+    /// [`DehydratedDevices::rehydrate_on_login()`] was called before the
+    /// login.
+    pub(crate) async fn rehydrate_after_login(&self) {
+        let Some(source) = self.client.inner.rehydration_pickle_key.lock().unwrap().take() else {
+            return;
+        };
+
+        let pickle_key = match source {
+            PickleKeySource::Key(pickle_key) => pickle_key,
+            PickleKeySource::SecretStorage(secret_storage_key) => {
+                match self.pickle_key_from_secret_storage(&secret_storage_key).await {
+                    Ok(Some(pickle_key)) => pickle_key,
+                    Ok(None) => {
+                        info!("No pickle key found in secret storage, not rehydrating");
+                        return;
+                    }
+                    Err(e) => {
+                        warn!(error = ?e, "Couldn't get the pickle key from secret storage");
+                        return;
+                    }
+                }
+            }
+        };
+
+        if let Err(e) = self.rehydrate(&pickle_key).await {
+            warn!(error = ?e, "Couldn't rehydrate the dehydrated device after login");
+        }
+    }
+
+    /// Periodically replace the dehydrated device with a new one.
+    ///
+    /// Rotating the dehydrated device limits the amount of to-device events
+    /// that pile up for it on the homeserver, and the amount of room keys an
+    /// attacker gains access to if the pickle key is compromised.
+    ///
+    /// This future never completes, it should be spawned on an executor or
+    /// dropped once rotation should stop. Errors while rotating the device are
+    /// logged and the rotation is retried after the next period.
+    ///
+    /// # Arguments
+    ///
+    /// * `pickle_key` - The key that should be used to encrypt the private
+    ///   parts of the device keys before they are uploaded.
+    ///
+    /// * `period` - The time to wait between two rotations.
+    pub async fn rotate_periodically(&self, pickle_key: &[u8; 32], period: Duration) {
+        loop {
+            sleep(period).await;
+
+            if let Err(e) = self.create(pickle_key, None).await {
+                warn!(error = ?e, "Couldn't rotate the dehydrated device");
+            }
+        }
+    }
+}
+
+async fn sleep(duration: Duration) {
+    #[cfg(target_arch = "wasm32")]
+    gloo_timers::future::TimeoutFuture::new(duration.as_millis().try_into().unwrap_or(u32::MAX))
+        .await;
+
+    #[cfg(not(target_arch = "wasm32"))]
+    tokio::time::sleep(duration).await;
+}
+
+// The http mocking library is not supported for wasm32
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use matrix_sdk_test::{async_test, test_json};
+    use ruma::device_id;
+    use serde_json::{json, Value};
+    use wiremock::{
+        matchers::{body_partial_json, method, path, path_regex},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::{PickleKeySource, SecretStorageKey};
+    use crate::{
+        test_utils::{logged_in_client, no_retry_test_client},
+        Client,
+    };
+
+    const PICKLE_KEY: &[u8; 32] = &[0u8; 32];
+
+    async fn client_with_cross_signing(server: &MockServer) -> Client {
+        let client = logged_in_client(Some(server.uri())).await;
+
+        client.olm_machine().await.as_ref().unwrap().bootstrap_cross_signing(false).await.unwrap();
+
+        client
+    }
+
+    /// Create a dehydrated device and return the body of the upload request.
+    async fn create_dehydrated_device(server: &MockServer, client: &Client) -> Value {
+        let _guard = Mock::given(method("PUT"))
+            .and(path_regex(r"/dehydrated_device$"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "device_id": "DEHYDRATED",
+            })))
+            .expect(1)
+            .mount_as_scoped(server)
+            .await;
+
+        let device_id =
+            client.encryption().dehydrated_devices().create(PICKLE_KEY, None).await.unwrap();
+
+        let requests = server.received_requests().await.unwrap();
+        let body: Value = requests.last().unwrap().body_json().unwrap();
+
+        assert_eq!(body["device_id"], device_id.as_str());
+        assert_eq!(body["initial_device_display_name"], "Dehydrated device");
+
+        body
+    }
+
+    #[async_test]
+    async fn test_create() {
+        let server = MockServer::start().await;
+        let client = client_with_cross_signing(&server).await;
+
+        let body = create_dehydrated_device(&server, &client).await;
+
+        assert!(body["device_data"].is_object());
+        assert!(body["device_keys"].is_object());
+        assert!(!body["one_time_keys"].as_object().unwrap().is_empty());
+    }
+
+    #[async_test]
+    async fn test_rehydrate() {
+        let server = MockServer::start().await;
+        let client = client_with_cross_signing(&server).await;
+
+        let body = create_dehydrated_device(&server, &client).await;
+        let device_id = body["device_id"].as_str().unwrap().to_owned();
+
+        Mock::given(method("GET"))
+            .and(path_regex(r"/dehydrated_device$"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "device_id": device_id,
+                "device_data": body["device_data"],
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        // The events are fetched page by page, until an empty page is returned.
+        Mock::given(method("POST"))
+            .and(path_regex(format!(r"/dehydrated_device/{device_id}/events$")))
+            .and(body_partial_json(json!({ "next_batch": "page_2" })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "events": [],
+                "next_batch": "page_3",
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        Mock::given(method("POST"))
+            .and(path_regex(format!(r"/dehydrated_device/{device_id}/events$")))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "events": [{
+                    "type": "org.example.custom",
+                    "sender": "@example:localhost",
+                    "content": {},
+                }],
+                "next_batch": "page_2",
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let imported =
+            client.encryption().dehydrated_devices().rehydrate(PICKLE_KEY).await.unwrap();
+
+        // The page didn't contain any room keys.
+        assert_eq!(imported, Some(0));
+    }
+
+    #[async_test]
+    async fn test_rehydrate_with_the_wrong_pickle_key() {
+        let server = MockServer::start().await;
+        let client = client_with_cross_signing(&server).await;
+
+        let body = create_dehydrated_device(&server, &client).await;
+
+        Mock::given(method("GET"))
+            .and(path_regex(r"/dehydrated_device$"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "device_id": body["device_id"],
+                "device_data": body["device_data"],
+            })))
+            .mount(&server)
+            .await;
+
+        client
+            .encryption()
+            .dehydrated_devices()
+            .rehydrate(&[1u8; 32])
+            .await
+            .expect_err("We shouldn't be able to rehydrate the device with another pickle key");
+    }
+
+    #[async_test]
+    async fn test_rehydrate_without_dehydrated_device() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(Some(server.uri())).await;
+
+        Mock::given(method("GET"))
+            .and(path_regex(r"/dehydrated_device$"))
+            .respond_with(ResponseTemplate::new(404).set_body_json(json!({
+                "errcode": "M_NOT_FOUND",
+                "error": "No dehydrated device found",
+            })))
+            .mount(&server)
+            .await;
+
+        let imported =
+            client.encryption().dehydrated_devices().rehydrate(PICKLE_KEY).await.unwrap();
+
+        assert_eq!(imported, None);
+    }
+
+    #[async_test]
+    async fn test_delete() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(Some(server.uri())).await;
+
+        Mock::given(method("DELETE"))
+            .and(path_regex(r"/dehydrated_device$"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "device_id": "DEHYDRATED",
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let deleted = client.encryption().dehydrated_devices().delete().await.unwrap();
+
+        assert_eq!(deleted.as_deref(), Some(device_id!("DEHYDRATED")));
+    }
+
+    #[async_test]
+    async fn test_delete_without_dehydrated_device() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(Some(server.uri())).await;
+
+        Mock::given(method("DELETE"))
+            .and(path_regex(r"/dehydrated_device$"))
+            .respond_with(ResponseTemplate::new(404).set_body_json(json!({
+                "errcode": "M_NOT_FOUND",
+                "error": "No dehydrated device found",
+            })))
+            .mount(&server)
+            .await;
+
+        let deleted = client.encryption().dehydrated_devices().delete().await.unwrap();
+
+        assert_eq!(deleted, None);
+    }
+
+    #[async_test]
+    async fn test_store_pickle_key_in_secret_storage() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(Some(server.uri())).await;
+        let dehydrated_devices = client.encryption().dehydrated_devices();
+        let secret_storage_key = SecretStorageKey::new();
+
+        Mock::given(method("PUT"))
+            .and(path_regex(r"/user/.*/account_data/org.matrix.msc3814$"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        dehydrated_devices.store_pickle_key(PICKLE_KEY, &secret_storage_key).await.unwrap();
+
+        let requests = server.received_requests().await.unwrap();
+        let content: Value = requests.last().unwrap().body_json().unwrap();
+        assert!(content["encrypted"][secret_storage_key.key_id()].is_object());
+
+        Mock::given(method("GET"))
+            .and(path_regex(r"/user/.*/account_data/org.matrix.msc3814$"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(content))
+            .mount(&server)
+            .await;
+
+        let pickle_key =
+            dehydrated_devices.pickle_key_from_secret_storage(&secret_storage_key).await.unwrap();
+        assert_eq!(pickle_key.as_deref(), Some(PICKLE_KEY));
+
+        // The pickle key wasn't stored with this secret storage key.
+        let pickle_key = dehydrated_devices
+            .pickle_key_from_secret_storage(&SecretStorageKey::new())
+            .await
+            .unwrap();
+        assert_eq!(pickle_key, None);
+    }
+
+    #[async_test]
+    async fn test_rehydrate_on_login() {
+        let server = MockServer::start().await;
+        let client = no_retry_test_client(Some(server.uri())).await;
+
+        Mock::given(method("POST"))
+            .and(path("/_matrix/client/r0/login"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::LOGIN))
+            .mount(&server)
+            .await;
+
+        Mock::given(method("GET"))
+            .and(path_regex(r"/dehydrated_device$"))
+            .respond_with(ResponseTemplate::new(404).set_body_json(json!({
+                "errcode": "M_NOT_FOUND",
+                "error": "No dehydrated device found",
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        client
+            .encryption()
+            .dehydrated_devices()
+            .rehydrate_on_login(PickleKeySource::Key(Box::new(*PICKLE_KEY)));
+
+        client.matrix_auth().login_username("example", "wordpass").send().await.unwrap();
+
+        // The pickle key is only used for a single login.
+        assert!(client.inner.rehydration_pickle_key.lock().unwrap().is_none());
+    }
+}
@@ -84,6 +84,10 @@
 //! [cross signing keys]: https://spec.matrix.org/unstable/client-server-api/#cross-signing
 //! [device keys]: https://spec.matrix.org/unstable/client-server-api/#device-keys
 
+use std::collections::{BTreeMap, BTreeSet};
+
+use ruma::{OwnedDeviceId, OwnedUserId};
+
 mod devices;
 mod users;
 
@@ -91,6 +95,33 @@ pub use devices::{Device, UserDevices};
 pub use matrix_sdk_base::crypto::types::MasterPubkey;
 pub use users::UserIdentity;
 
+/// Updates about the devices of users that were received from the server,
+/// as sent by [`Encryption::devices_stream()`].
+///
+/// [`Encryption::devices_stream()`]: crate::encryption::Encryption::devices_stream
+#[derive(Clone, Debug, Default)]
+pub struct DeviceUpdates {
+    /// Devices we didn't know about before.
+    pub new: BTreeMap<OwnedUserId, BTreeMap<OwnedDeviceId, Device>>,
+    /// Devices whose keys, display name or trust state changed.
+    pub changed: BTreeMap<OwnedUserId, BTreeMap<OwnedDeviceId, Device>>,
+    /// The IDs of the devices that were deleted.
+    pub deleted: BTreeMap<OwnedUserId, BTreeSet<OwnedDeviceId>>,
+}
+
+/// Updates about the identities of users that were received from the server,
+/// as sent by [`Encryption::user_identities_stream()`].
+///
+/// [`Encryption::user_identities_stream()`]: crate::encryption::Encryption::user_identities_stream
+#[derive(Clone, Debug, Default)]
+pub struct IdentityUpdates {
+    /// Identities of users we didn't know about before.
+    pub new: BTreeMap<OwnedUserId, UserIdentity>,
+    /// Identities that changed, e.g. because the user changed their
+    /// cross-signing keys.
+    pub changed: BTreeMap<OwnedUserId, UserIdentity>,
+}
+
 /// Error for the manual verification step, when we manually sign users or
 /// devices.
 #[derive(thiserror::Error, Debug)]
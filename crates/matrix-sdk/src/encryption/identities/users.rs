@@ -16,8 +16,8 @@ use std::sync::Arc;
 
 use matrix_sdk_base::{
     crypto::{
-        types::MasterPubkey, OwnUserIdentity as InnerOwnUserIdentity,
-        UserIdentity as InnerUserIdentity,
+        types::MasterPubkey, CryptoStoreError, IdentityViolation,
+        OwnUserIdentity as InnerOwnUserIdentity, UserIdentity as InnerUserIdentity,
     },
     RoomMemberships,
 };
@@ -395,6 +395,43 @@ impl UserIdentity {
             UserIdentities::Other(i) => i.inner.master_key(),
         }
     }
+
+    /// Get the identity violation this user identity is in, if any.
+    ///
+    /// Our own identity is never in violation, so this always returns `None`
+    /// for it. For other users, a violation is reported if their master key
+    /// changed since we first saw it and the change wasn't acknowledged, or if
+    /// we verified them in the past but the current identity isn't verified.
+    pub fn identity_violation(&self) -> Option<IdentityViolation> {
+        match &self.inner {
+            UserIdentities::Own(_) => None,
+            UserIdentities::Other(i) => i.inner.identity_violation(),
+        }
+    }
+
+    /// Acknowledge a change of the master key of this user identity.
+    ///
+    /// This resolves an [`IdentityViolation::PinViolation`], the current
+    /// master key will be pinned and considered to be the expected one from
+    /// now on. This does nothing for our own identity.
+    pub async fn pin_current_master_key(&self) -> Result<(), CryptoStoreError> {
+        match &self.inner {
+            UserIdentities::Own(_) => Ok(()),
+            UserIdentities::Other(i) => i.inner.pin_current_master_key().await,
+        }
+    }
+
+    /// Withdraw the verification of this user identity.
+    ///
+    /// This resolves an [`IdentityViolation::VerificationViolation`], the user
+    /// won't be considered to be a previously verified user anymore. This does
+    /// nothing for our own identity.
+    pub async fn withdraw_verification(&self) -> Result<(), CryptoStoreError> {
+        match &self.inner {
+            UserIdentities::Own(_) => Ok(()),
+            UserIdentities::Other(i) => i.inner.withdraw_verification().await,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -20,17 +20,18 @@ use std::{
     collections::{BTreeMap, HashSet},
     io::{Cursor, Read, Write},
     iter,
+    ops::Deref,
     path::PathBuf,
 };
 
 use eyeball::SharedObservable;
 use futures_util::{
     future::try_join,
-    stream::{self, StreamExt},
+    stream::{self, Stream, StreamExt},
 };
 use matrix_sdk_base::crypto::{
-    store::locks::CryptoStoreLockGuard, OlmMachine, OutgoingRequest, RoomMessageRequest,
-    ToDeviceRequest,
+    store::locks::CryptoStoreLockGuard, OlmMachine, OutgoingRequest, ReadOnlyDevice,
+    RoomMessageRequest, ToDeviceRequest,
 };
 use ruma::{
     api::client::{
@@ -54,19 +55,24 @@ use ruma::{
     },
     DeviceId, OwnedDeviceId, OwnedUserId, TransactionId, UserId,
 };
-use tokio::sync::RwLockReadGuard;
+use serde::Serialize;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    sync::RwLockReadGuard,
+};
 use tracing::{debug, instrument, trace, warn};
 
 use crate::{
     attachment::{AttachmentInfo, Thumbnail},
     encryption::{
-        identities::{Device, UserDevices},
+        identities::{Device, DeviceUpdates, IdentityUpdates, UserDevices, UserIdentity},
         verification::{SasVerification, Verification, VerificationRequest},
     },
     error::HttpResult,
     Client, Error, Result, Room, TransmissionProgress,
 };
 
+pub mod dehydrated_devices;
 mod futures;
 pub mod identities;
 pub mod verification;
@@ -76,14 +82,24 @@ pub use matrix_sdk_base::crypto::{
         SessionCreationError as MegolmSessionCreationError,
         SessionExportError as OlmSessionExportError,
     },
-    vodozemac, CrossSigningStatus, CryptoStoreError, DecryptorError, EventError, KeyExportError,
-    LocalTrust, MediaEncryptionInfo, MegolmError, OlmError, RoomKeyImportResult, SecretImportError,
-    SessionCreationError, SignatureError, VERSION,
+    store::IdentityViolationUpdate,
+    vodozemac, CollectStrategy, CrossSigningStatus, CryptoStoreError, DecryptorError, EventError,
+    ExclusionReason, IdentityViolation, KeyExportError, KeyForwardDecision, LocalTrust,
+    MediaEncryptionInfo, MegolmError, OlmError, PruneReport, PruneSettings, RoomKeyImportResult,
+    RoomKeyRetention, RoomKeySharingReport, SecretImportError, SessionCreationError,
+    SignatureError, VERSION,
+};
+#[cfg(feature = "automatic-room-key-forwarding")]
+pub use matrix_sdk_base::crypto::{
+    KeyForwardRequest, KeyForwardingAuditEntry, KeyForwardingPolicy,
 };
 
 pub use self::futures::PrepareEncryptedFile;
 pub use crate::error::RoomKeyImportError;
 
+/// The size of the chunks in which attachments are encrypted and uploaded.
+const ATTACHMENT_CHUNK_SIZE: usize = 64 * 1024;
+
 impl Client {
     pub(crate) async fn olm_machine(&self) -> RwLockReadGuard<'_, Option<OlmMachine>> {
         self.base_client().olm_machine().await
@@ -165,6 +181,124 @@ impl Client {
         PrepareEncryptedFile::new(self, content_type, reader)
     }
 
+    /// Construct a [`EncryptedFile`][ruma::events::room::EncryptedFile] by
+    /// encrypting and uploading the chunks of the provided stream.
+    ///
+    /// Unlike [`Client::prepare_encrypted_file()`], the chunks are encrypted
+    /// and sent to the homeserver as they come, without holding the whole
+    /// file in memory. See [`Media::upload_stream()`] for the details about
+    /// the upload.
+    ///
+    /// # Arguments
+    ///
+    /// * `content_type` - The content type of the file.
+    /// * `content_length` - The total length of the chunks of the `stream`.
+    /// * `stream` - The stream of chunks that should be encrypted and uploaded.
+    /// * `send_progress` - An observable that is updated with the number of
+    ///   bytes sent to the homeserver.
+    ///
+    /// On WebAssembly, the request body can't be streamed: the chunks are
+    /// still encrypted as they come, but the encrypted file is held in memory
+    /// until it is uploaded.
+    ///
+    /// [`Media::upload_stream()`]: crate::Media::upload_stream
+    pub async fn prepare_encrypted_file_stream<S, B>(
+        &self,
+        content_type: &mime::Mime,
+        content_length: u64,
+        stream: S,
+        send_progress: SharedObservable<TransmissionProgress>,
+    ) -> Result<ruma::events::room::EncryptedFile>
+    where
+        S: Stream<Item = std::io::Result<B>> + Send + Unpin,
+        B: AsRef<[u8]>,
+    {
+        let mut encryptor = matrix_sdk_base::crypto::AttachmentStreamEncryptor::new(stream);
+
+        // The encrypted data has the same length as the plaintext.
+        #[cfg(not(target_arch = "wasm32"))]
+        let response = self
+            .media()
+            .upload_stream(content_type, content_length, encryptor.by_ref(), send_progress)
+            .await?;
+
+        #[cfg(target_arch = "wasm32")]
+        let response = {
+            let mut data = Vec::with_capacity(usize::try_from(content_length).unwrap_or_default());
+
+            while let Some(chunk) = encryptor.next().await {
+                data.extend_from_slice(&chunk?);
+            }
+
+            self.media()
+                .upload(content_type, data)
+                .with_send_progress_observable(send_progress)
+                .await?
+        };
+
+        let keys = encryptor.finish();
+
+        Ok(ruma::events::room::EncryptedFileInit {
+            url: response.content_uri,
+            key: keys.key,
+            iv: keys.iv,
+            hashes: keys.hashes,
+            v: keys.version,
+        }
+        .into())
+    }
+
+    /// Construct a [`EncryptedFile`][ruma::events::room::EncryptedFile] by
+    /// encrypting and uploading the content of the provided asynchronous
+    /// reader.
+    ///
+    /// The content is read, encrypted and uploaded in chunks, like with
+    /// [`Client::prepare_encrypted_file_stream()`].
+    ///
+    /// # Arguments
+    ///
+    /// * `content_type` - The content type of the file.
+    /// * `content_length` - The length of the content of the `reader`.
+    /// * `reader` - The reader that should be encrypted and uploaded.
+    /// * `send_progress` - An observable that is updated with the number of
+    ///   bytes sent to the homeserver.
+    pub async fn prepare_encrypted_file_reader<R>(
+        &self,
+        content_type: &mime::Mime,
+        content_length: u64,
+        mut reader: R,
+        send_progress: SharedObservable<TransmissionProgress>,
+    ) -> Result<ruma::events::room::EncryptedFile>
+    where
+        R: AsyncRead + Send + Unpin,
+    {
+        let chunks = async_stream::stream! {
+            loop {
+                let mut chunk = vec![0; ATTACHMENT_CHUNK_SIZE];
+
+                match reader.read(&mut chunk).await {
+                    Ok(0) => break,
+                    Ok(len) => {
+                        chunk.truncate(len);
+                        yield Ok(chunk);
+                    }
+                    Err(error) => {
+                        yield Err(error);
+                        break;
+                    }
+                }
+            }
+        };
+
+        self.prepare_encrypted_file_stream(
+            content_type,
+            content_length,
+            Box::pin(chunks),
+            send_progress,
+        )
+        .await
+    }
+
     /// Encrypt and upload the file to be read from `reader` and construct an
     /// attachment message with `body`, `content_type`, `info` and `thumbnail`.
     pub(crate) async fn prepare_encrypted_attachment_message(
@@ -179,11 +313,17 @@ impl Client {
         let upload_thumbnail =
             self.upload_encrypted_thumbnail(thumbnail, content_type, send_progress.clone());
 
+        // Encrypt the attachment chunk by chunk while it's being uploaded, instead of
+        // holding an encrypted copy of it in memory.
         let upload_attachment = async {
-            let mut cursor = Cursor::new(data);
-            self.prepare_encrypted_file(content_type, &mut cursor)
-                .with_send_progress_observable(send_progress)
-                .await
+            let chunks = stream::iter(data.chunks(ATTACHMENT_CHUNK_SIZE).map(Ok));
+            self.prepare_encrypted_file_stream(
+                content_type,
+                data.len() as u64,
+                chunks,
+                send_progress,
+            )
+            .await
         };
 
         let ((thumbnail_source, thumbnail_info), file) =
@@ -287,6 +427,30 @@ impl Client {
         Ok(())
     }
 
+    /// Claim one-time keys for the given devices we don't share an Olm session
+    /// with yet, leaving the other devices of their owners alone.
+    #[instrument(skip(self, devices))]
+    pub(crate) async fn claim_one_time_keys_for_devices(
+        &self,
+        devices: impl Iterator<Item = &ReadOnlyDevice>,
+    ) -> Result<()> {
+        let _lock = self.inner.key_claim_lock.lock().await;
+
+        if let Some((request_id, request)) = self
+            .olm_machine()
+            .await
+            .as_ref()
+            .ok_or(Error::NoOlmMachine)?
+            .get_missing_sessions_for_devices(devices)
+            .await?
+        {
+            let response = self.send(request, None).await?;
+            self.mark_request_as_sent(&request_id, &response).await?;
+        }
+
+        Ok(())
+    }
+
     /// Upload the E2E encryption keys.
     ///
     /// This uploads the long lived device keys as well as the required amount
@@ -648,22 +812,290 @@ impl Encryption {
         &self,
         user_id: &UserId,
     ) -> Result<Option<crate::encryption::identities::UserIdentity>, CryptoStoreError> {
-        use crate::encryption::identities::UserIdentity;
-
         let olm = self.client.olm_machine().await;
         let Some(olm) = olm.as_ref() else { return Ok(None) };
         let identity = olm.get_identity(user_id, None).await?;
 
-        Ok(identity.map(|i| match i {
-            matrix_sdk_base::crypto::UserIdentities::Own(i) => {
-                UserIdentity::new_own(self.client.clone(), i)
-            }
-            matrix_sdk_base::crypto::UserIdentities::Other(i) => {
-                UserIdentity::new(self.client.clone(), i, self.client.get_dm_room(user_id))
+        Ok(identity.map(|i| wrap_user_identity(&self.client, i)))
+    }
+
+    /// Get a stream of updates to the devices of the users we track.
+    ///
+    /// Each time new devices are received from the server, e.g. after a
+    /// `/keys/query` request was sent out, or a device changed, a
+    /// [`DeviceUpdates`] will be sent to the stream.
+    ///
+    /// Returns `None` if the client isn't logged in.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::Client;
+    /// # use url::Url;
+    /// # use futures_util::{pin_mut, StreamExt};
+    /// # async {
+    /// # let homeserver = Url::parse("http://example.com")?;
+    /// # let client = Client::new(homeserver).await?;
+    /// let encryption = client.encryption();
+    ///
+    /// if let Some(updates) = encryption.devices_stream().await {
+    ///     pin_mut!(updates);
+    ///
+    ///     while let Some(updates) = updates.next().await {
+    ///         for (user_id, devices) in updates.new {
+    ///             println!("{user_id} has {} new devices", devices.len());
+    ///         }
+    ///     }
+    /// }
+    /// # anyhow::Ok(()) };
+    /// ```
+    pub async fn devices_stream(&self) -> Option<impl Stream<Item = DeviceUpdates>> {
+        let olm = self.client.olm_machine().await;
+        let stream = olm.as_ref()?.store().devices_stream();
+        let client = self.client.to_owned();
+
+        Some(stream.map(move |updates| {
+            let wrap = |devices: BTreeMap<OwnedUserId, BTreeMap<OwnedDeviceId, _>>| {
+                devices
+                    .into_iter()
+                    .map(|(user_id, devices)| {
+                        let devices: BTreeMap<_, _> = devices
+                            .into_iter()
+                            .map(|(device_id, inner)| {
+                                (device_id, Device { inner, client: client.to_owned() })
+                            })
+                            .collect();
+
+                        (user_id, devices)
+                    })
+                    .collect::<BTreeMap<_, _>>()
+            };
+
+            DeviceUpdates {
+                new: wrap(updates.new),
+                changed: wrap(updates.changed),
+                deleted: updates.deleted,
             }
         }))
     }
 
+    /// Get a stream of updates to the identities of the users we track.
+    ///
+    /// Each time new user identities are received from the server, or an
+    /// identity changed, an [`IdentityUpdates`] will be sent to the stream.
+    ///
+    /// Returns `None` if the client isn't logged in.
+    pub async fn user_identities_stream(&self) -> Option<impl Stream<Item = IdentityUpdates>> {
+        let olm = self.client.olm_machine().await;
+        let stream = olm.as_ref()?.store().user_identities_stream();
+        let client = self.client.to_owned();
+
+        Some(stream.map(move |updates| {
+            let wrap = |identities: BTreeMap<OwnedUserId, _>| -> BTreeMap<_, _> {
+                identities
+                    .into_iter()
+                    .map(|(user_id, identity)| (user_id, wrap_user_identity(&client, identity)))
+                    .collect()
+            };
+
+            IdentityUpdates { new: wrap(updates.new), changed: wrap(updates.changed) }
+        }))
+    }
+
+    /// Get a stream of the verification requests other users, or our other
+    /// devices, send to us.
+    ///
+    /// Returns `None` if the client isn't logged in.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::Client;
+    /// # use url::Url;
+    /// # use futures_util::{pin_mut, StreamExt};
+    /// # async {
+    /// # let homeserver = Url::parse("http://example.com")?;
+    /// # let client = Client::new(homeserver).await?;
+    /// let encryption = client.encryption();
+    ///
+    /// if let Some(requests) = encryption.verification_requests_stream().await {
+    ///     pin_mut!(requests);
+    ///
+    ///     while let Some(request) = requests.next().await {
+    ///         println!("{} wants to verify with us", request.other_user_id());
+    ///         request.accept().await?;
+    ///     }
+    /// }
+    /// # anyhow::Ok(()) };
+    /// ```
+    pub async fn verification_requests_stream(
+        &self,
+    ) -> Option<impl Stream<Item = VerificationRequest>> {
+        let olm = self.client.olm_machine().await;
+        let stream = olm.as_ref()?.verification_requests_stream();
+        let client = self.client.to_owned();
+
+        Some(stream.map(move |inner| VerificationRequest { inner, client: client.to_owned() }))
+    }
+
+    /// Get a stream of updates to the identity violations of other users.
+    ///
+    /// Each time the identity of another user changes, or an identity
+    /// violation gets acknowledged, the new violation state of the user will
+    /// be sent to the stream, see [`IdentityViolation`] for more info.
+    ///
+    /// Returns `None` if the client isn't logged in.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::Client;
+    /// # use url::Url;
+    /// # use futures_util::StreamExt;
+    /// # async {
+    /// # let homeserver = Url::parse("http://example.com")?;
+    /// # let client = Client::new(homeserver).await?;
+    /// let encryption = client.encryption();
+    ///
+    /// if let Some(mut updates) = encryption.identity_violations_stream().await {
+    ///     while let Some(updates) = updates.next().await {
+    ///         for update in updates {
+    ///             println!(
+    ///                 "{} is now in violation {:?}",
+    ///                 update.user_id, update.violation
+    ///             );
+    ///         }
+    ///     }
+    /// }
+    /// # anyhow::Ok(()) };
+    /// ```
+    pub async fn identity_violations_stream(
+        &self,
+    ) -> Option<impl Stream<Item = Vec<IdentityViolationUpdate>>> {
+        let olm = self.client.olm_machine().await;
+        Some(olm.as_ref()?.store().identity_violations_stream())
+    }
+
+    /// Remove the data that isn't needed anymore from the crypto store.
+    ///
+    /// This can be used to remove the Olm sessions of deleted devices, or the
+    /// room keys of rooms that were left and forgotten. Nothing is removed
+    /// with the default [`PruneSettings`].
+    pub async fn prune_store(&self, settings: PruneSettings) -> Result<PruneReport> {
+        let olm = self.client.olm_machine().await;
+        let olm = olm.as_ref().ok_or(Error::NoOlmMachine)?;
+
+        Ok(olm.prune_store(&settings).await?)
+    }
+
+    /// Set the policy deciding which incoming room key requests from other
+    /// devices are answered.
+    ///
+    /// The policy is persisted in the crypto store. The closure of a
+    /// [`KeyForwardingPolicy::Custom`] policy can't be persisted though, no
+    /// room key request is answered after the client is restored until the
+    /// policy is set again.
+    #[cfg(feature = "automatic-room-key-forwarding")]
+    pub async fn set_key_forwarding_policy(&self, policy: KeyForwardingPolicy) -> Result<()> {
+        let olm = self.client.olm_machine().await;
+        let olm = olm.as_ref().ok_or(Error::NoOlmMachine)?;
+
+        Ok(olm.set_key_forwarding_policy(policy).await?)
+    }
+
+    /// Get a stream of the incoming room key requests and of the decision
+    /// that was taken for each of them.
+    ///
+    /// If the reader of the stream lags too far behind, a warning is logged
+    /// and the oldest entries are dropped.
+    ///
+    /// Returns `None` if the client isn't logged in.
+    #[cfg(feature = "automatic-room-key-forwarding")]
+    pub async fn key_forwarding_audit_stream(
+        &self,
+    ) -> Option<impl Stream<Item = KeyForwardingAuditEntry>> {
+        let olm = self.client.olm_machine().await;
+        Some(olm.as_ref()?.key_forwarding_audit_stream())
+    }
+
+    /// Encrypt and send a custom to-device event to the given devices.
+    ///
+    /// This can be used to send arbitrary, application specific, payloads to
+    /// other devices over an Olm encrypted channel. Olm sessions with the
+    /// recipient devices will be established first, if they are missing.
+    ///
+    /// The recipients will receive the decrypted event with the given
+    /// `event_type` as a normal to-device event, meaning that event handlers
+    /// for the custom event type can be registered on the receiving side using
+    /// [`Client::add_event_handler()`].
+    ///
+    /// # Arguments
+    ///
+    /// * `recipient_devices` - The devices that should receive the event.
+    ///
+    /// * `event_type` - The type of the event that should be sent.
+    ///
+    /// * `content` - The content of the event that should be sent.
+    ///
+    /// Returns the list of devices the event could not be sent to because no
+    /// Olm session could be established with them.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::{Client, ruma::{device_id, user_id}};
+    /// # use url::Url;
+    /// # use serde_json::json;
+    /// # async {
+    /// # let alice = user_id!("@alice:example.org");
+    /// # let homeserver = Url::parse("http://example.com")?;
+    /// # let client = Client::new(homeserver).await?;
+    /// if let Some(device) =
+    ///     client.encryption().get_device(alice, device_id!("DEVICEID")).await?
+    /// {
+    ///     let failed_devices = client
+    ///         .encryption()
+    ///         .encrypt_and_send_custom_to_device(
+    ///             vec![&device],
+    ///             "org.example.call.key",
+    ///             json!({ "key": "secret" }),
+    ///         )
+    ///         .await?;
+    ///
+    ///     if !failed_devices.is_empty() {
+    ///         println!("Couldn't send the key to {failed_devices:?}");
+    ///     }
+    /// }
+    /// # anyhow::Ok(()) };
+    /// ```
+    pub async fn encrypt_and_send_custom_to_device(
+        &self,
+        recipient_devices: Vec<&Device>,
+        event_type: &str,
+        content: impl Serialize,
+    ) -> Result<Vec<(OwnedUserId, OwnedDeviceId)>> {
+        let content = serde_json::to_value(content)?;
+
+        // Establish Olm sessions with the devices we don't share one with yet.
+        self.client
+            .claim_one_time_keys_for_devices(recipient_devices.iter().copied().map(Deref::deref))
+            .await?;
+
+        let (requests, failed_devices) = {
+            let olm = self.client.olm_machine().await;
+            let olm = olm.as_ref().ok_or(Error::NoOlmMachine)?;
+
+            let devices = recipient_devices.into_iter().map(|d| d.inner.clone()).collect();
+            olm.encrypt_content_for_devices(devices, event_type, &content).await?
+        };
+
+        for request in requests {
+            self.client.send_to_device(&request).await?;
+        }
+
+        Ok(failed_devices)
+    }
+
     /// Create and upload a new cross signing identity.
     ///
     /// # Arguments
@@ -856,6 +1288,15 @@ impl Encryption {
         Ok(olm.import_room_keys(import, false, |_, _| {}).await?)
     }
 
+    /// Get the dehydrated devices manager of the client.
+    ///
+    /// Dehydrated devices allow room keys to be received while none of our
+    /// devices are online, take a look at the [`dehydrated_devices`] module
+    /// for more info.
+    pub fn dehydrated_devices(&self) -> dehydrated_devices::DehydratedDevices {
+        dehydrated_devices::DehydratedDevices { client: self.client.clone() }
+    }
+
     /// Enables the crypto-store cross-process lock.
     ///
     /// This may be required if there are multiple processes that may do writes
@@ -969,6 +1410,21 @@ impl Encryption {
     }
 }
 
+/// Wrap a user identity coming from the crypto crate into our own
+/// [`UserIdentity`] type.
+fn wrap_user_identity(
+    client: &Client,
+    identity: matrix_sdk_base::crypto::UserIdentities,
+) -> UserIdentity {
+    match identity {
+        matrix_sdk_base::crypto::UserIdentities::Own(i) => UserIdentity::new_own(client.clone(), i),
+        matrix_sdk_base::crypto::UserIdentities::Other(i) => {
+            let room = client.get_dm_room(i.user_id());
+            UserIdentity::new(client.clone(), i, room)
+        }
+    }
+}
+
 #[cfg(all(test, not(target_arch = "wasm32")))]
 mod tests {
     use std::time::Duration;
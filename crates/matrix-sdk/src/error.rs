@@ -20,7 +20,8 @@ use std::{io::Error as IoError, sync::Arc};
 use matrix_sdk_base::crypto::ScanError;
 #[cfg(feature = "e2e-encryption")]
 use matrix_sdk_base::crypto::{
-    CryptoStoreError, DecryptorError, KeyExportError, MegolmError, OlmError,
+    dehydrated_devices::DehydrationError, secret_storage::SecretStorageError, CryptoStoreError,
+    DecryptorError, KeyExportError, MegolmError, OlmError,
 };
 use matrix_sdk_base::{Error as SdkBaseError, RoomState, StoreError};
 use reqwest::Error as ReqwestError;
@@ -215,6 +216,16 @@ pub enum Error {
     #[error(transparent)]
     DecryptorError(#[from] DecryptorError),
 
+    /// An error occurred while creating or rehydrating a dehydrated device.
+    #[cfg(feature = "e2e-encryption")]
+    #[error(transparent)]
+    DehydrationError(#[from] DehydrationError),
+
+    /// An error occurred while decrypting a secret from secret storage.
+    #[cfg(feature = "e2e-encryption")]
+    #[error(transparent)]
+    SecretStorage(#[from] SecretStorageError),
+
     /// An error occurred in the state store.
     #[error(transparent)]
     StateStore(#[from] StoreError),
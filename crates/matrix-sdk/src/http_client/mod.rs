@@ -30,7 +30,7 @@ use ruma::{
         error::{FromHttpResponseError, IntoHttpError},
         AuthScheme, MatrixVersion, OutgoingRequest, OutgoingRequestAppserviceExt, SendAccessToken,
     },
-    UserId,
+    DeviceId, UserId,
 };
 use tracing::{debug, field::debug, instrument, trace};
 
@@ -70,6 +70,7 @@ impl HttpClient {
         homeserver: String,
         access_token: Option<&str>,
         user_id: Option<&UserId>,
+        device_id: Option<&DeviceId>,
         server_versions: &[MatrixVersion],
     ) -> Result<http::Request<Bytes>, IntoHttpError>
     where
@@ -81,12 +82,18 @@ impl HttpClient {
         let request = if let Some((access_token, user_id)) =
             access_token.filter(|_| config.assert_identity).zip(user_id)
         {
-            request.try_into_http_request_with_user_id::<BytesMut>(
+            let mut request = request.try_into_http_request_with_user_id::<BytesMut>(
                 &homeserver,
                 SendAccessToken::Always(access_token),
                 user_id,
                 server_versions,
-            )?
+            )?;
+
+            if let Some(device_id) = device_id.filter(|_| config.assert_device_identity) {
+                append_device_id(&mut request, device_id)?;
+            }
+
+            request
         } else {
             let send_access_token = match access_token {
                 Some(access_token) => {
@@ -113,7 +120,7 @@ impl HttpClient {
 
     #[allow(clippy::too_many_arguments)]
     #[instrument(
-        skip(self, access_token, config, request, user_id, send_progress),
+        skip(self, access_token, config, request, user_id, device_id, send_progress),
         fields(
             config,
             path,
@@ -132,6 +139,7 @@ impl HttpClient {
         homeserver: String,
         access_token: Option<&str>,
         user_id: Option<&UserId>,
+        device_id: Option<&DeviceId>,
         server_versions: &[MatrixVersion],
         send_progress: SharedObservable<TransmissionProgress>,
     ) -> Result<R::IncomingResponse, HttpError>
@@ -171,6 +179,7 @@ impl HttpClient {
                 homeserver,
                 access_token,
                 user_id,
+                device_id,
                 server_versions,
             )?;
 
@@ -221,6 +230,25 @@ pub struct TransmissionProgress {
     pub total: usize,
 }
 
+/// Append the `device_id` used for device masquerading, as described in
+/// [MSC3202], to the query string of the given request.
+///
+/// [MSC3202]: https://github.com/matrix-org/matrix-spec-proposals/pull/3202
+fn append_device_id(
+    request: &mut http::Request<BytesMut>,
+    device_id: &DeviceId,
+) -> Result<(), IntoHttpError> {
+    let uri = request.uri().to_string();
+    let separator = if request.uri().query().is_some() { '&' } else { '?' };
+    let device_id: String = url::form_urlencoded::byte_serialize(device_id.as_bytes()).collect();
+
+    *request.uri_mut() = format!("{uri}{separator}org.matrix.msc3202.device_id={device_id}")
+        .parse()
+        .map_err(http::Error::from)?;
+
+    Ok(())
+}
+
 async fn response_to_http_response(
     mut response: reqwest::Response,
 ) -> Result<http::Response<Bytes>, reqwest::Error> {
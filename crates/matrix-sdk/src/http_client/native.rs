@@ -24,12 +24,15 @@ use bytes::Bytes;
 use bytesize::ByteSize;
 use eyeball::SharedObservable;
 use http::header::CONTENT_LENGTH;
-use ruma::api::{
-    client::error::{ErrorBody as ClientApiErrorBody, ErrorKind as ClientApiErrorKind},
-    error::FromHttpResponseError,
-    IncomingResponse, OutgoingRequest,
+use ruma::{
+    api::{
+        client::error::{ErrorBody as ClientApiErrorBody, ErrorKind as ClientApiErrorKind},
+        error::FromHttpResponseError,
+        IncomingResponse, MatrixVersion, OutgoingRequest,
+    },
+    DeviceId, UserId,
 };
-use tracing::{info, warn};
+use tracing::{debug, info, warn};
 
 use super::{response_to_http_response, HttpClient, TransmissionProgress, DEFAULT_REQUEST_TIMEOUT};
 use crate::{config::RequestConfig, error::HttpError, RumaApiError};
@@ -59,38 +62,7 @@ impl HttpClient {
                 };
 
                 // Turn errors into permanent errors when the retry limit is reached
-                let error_type = if stop {
-                    RetryError::Permanent
-                } else {
-                    |err: HttpError| {
-                        if let Some(api_error) = err.as_ruma_api_error() {
-                            let status_code = match api_error {
-                                RumaApiError::ClientApi(e) => match e.body {
-                                    ClientApiErrorBody::Standard {
-                                        kind: ClientApiErrorKind::LimitExceeded { retry_after_ms },
-                                        ..
-                                    } => {
-                                        return RetryError::Transient {
-                                            err,
-                                            retry_after: retry_after_ms,
-                                        };
-                                    }
-                                    _ => Some(e.status_code),
-                                },
-                                RumaApiError::Uiaa(_) => None,
-                                RumaApiError::Other(e) => Some(e.status_code),
-                            };
-
-                            if let Some(status_code) = status_code {
-                                if status_code.is_server_error() {
-                                    return RetryError::Transient { err, retry_after: None };
-                                }
-                            }
-                        }
-
-                        RetryError::Permanent(err)
-                    }
-                };
+                let error_type = if stop { RetryError::Permanent } else { retry_error };
 
                 let response = send_request(&self.inner, &request, config.timeout, send_progress)
                     .await
@@ -109,6 +81,161 @@ impl HttpClient {
 
         retry::<_, HttpError, _, _, _>(backoff, send_request).await
     }
+
+    /// Send the given request and return the raw response, without reading
+    /// its body.
+    ///
+    /// This is meant for downloads of potentially large payloads, which
+    /// should be streamed by the caller instead of being held in memory. The
+    /// request is retried like in [`HttpClient::send()`], until the response
+    /// headers were received successfully.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) async fn download<R>(
+        &self,
+        request: R,
+        config: Option<RequestConfig>,
+        homeserver: String,
+        access_token: Option<&str>,
+        user_id: Option<&UserId>,
+        device_id: Option<&DeviceId>,
+        server_versions: &[MatrixVersion],
+    ) -> Result<reqwest::Response, HttpError>
+    where
+        R: OutgoingRequest + Debug,
+        HttpError: From<FromHttpResponseError<R::EndpointError>>,
+    {
+        let config = config.unwrap_or(self.request_config);
+
+        let request = self.serialize_request(
+            request,
+            config,
+            homeserver,
+            access_token,
+            user_id,
+            device_id,
+            server_versions,
+        )?;
+
+        let backoff =
+            ExponentialBackoff { max_elapsed_time: config.retry_timeout, ..Default::default() };
+        let retry_count = AtomicU64::new(1);
+
+        let send_request = || async {
+            let stop = if let Some(retry_limit) = config.retry_limit {
+                retry_count.fetch_add(1, Ordering::Relaxed) >= retry_limit
+            } else {
+                false
+            };
+
+            // Turn errors into permanent errors when the retry limit is reached
+            let error_type = if stop { RetryError::Permanent } else { retry_error };
+
+            let mut download_request = reqwest::Request::try_from(clone_request(&request))
+                .map_err(|e| RetryError::Permanent(e.into()))?;
+            *download_request.timeout_mut() = Some(config.timeout);
+
+            debug!(path = download_request.url().path(), "Sending download request");
+
+            let response =
+                self.inner.execute(download_request).await.map_err(|e| error_type(e.into()))?;
+
+            let Err(status_error) = response.error_for_status_ref() else {
+                return Ok(response);
+            };
+
+            // Try to get the Matrix error out of the body, fall back to the
+            // status code error otherwise.
+            let response =
+                response_to_http_response(response).await.map_err(|e| error_type(e.into()))?;
+            let error = match R::IncomingResponse::try_from_http_response(response) {
+                Ok(_) => HttpError::from(status_error),
+                Err(e) => HttpError::from(e),
+            };
+
+            Err(error_type(error))
+        };
+
+        retry::<_, HttpError, _, _, _>(backoff, send_request).await
+    }
+
+    /// Send the given request, using the given `body` instead of the
+    /// serialized body of the request.
+    ///
+    /// This is meant for uploads of potentially large payloads, which should
+    /// be streamed instead of being held in memory. Unlike
+    /// [`HttpClient::send()`], the request is not retried, since the body can
+    /// only be consumed once.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) async fn upload_stream<R>(
+        &self,
+        request: R,
+        body: reqwest::Body,
+        content_length: u64,
+        config: Option<RequestConfig>,
+        homeserver: String,
+        access_token: Option<&str>,
+        user_id: Option<&UserId>,
+        device_id: Option<&DeviceId>,
+        server_versions: &[MatrixVersion],
+    ) -> Result<R::IncomingResponse, HttpError>
+    where
+        R: OutgoingRequest + Debug,
+        HttpError: From<FromHttpResponseError<R::EndpointError>>,
+    {
+        let config = config.unwrap_or(self.request_config);
+
+        let request = self.serialize_request(
+            request,
+            config,
+            homeserver,
+            access_token,
+            user_id,
+            device_id,
+            server_versions,
+        )?;
+
+        let mut request = reqwest::Request::try_from(request)?;
+        *request.body_mut() = Some(body);
+        // reqwest / hyper doesn't know how large a streamed body is, so it doesn't
+        // set the content-length header (required by some servers). Set it manually.
+        request.headers_mut().insert(CONTENT_LENGTH, content_length.into());
+        *request.timeout_mut() = Some(config.timeout);
+
+        debug!(path = request.url().path(), "Sending upload request");
+
+        let response = self.inner.execute(request).await?;
+        let response = response_to_http_response(response).await?;
+
+        Ok(R::IncomingResponse::try_from_http_response(response)?)
+    }
+}
+
+/// Turn the given error into a transient error if the request should be
+/// retried, i.e. if the server is rate limiting us or had an internal error.
+fn retry_error(err: HttpError) -> RetryError<HttpError> {
+    if let Some(api_error) = err.as_ruma_api_error() {
+        let status_code = match api_error {
+            RumaApiError::ClientApi(e) => match e.body {
+                ClientApiErrorBody::Standard {
+                    kind: ClientApiErrorKind::LimitExceeded { retry_after_ms },
+                    ..
+                } => {
+                    return RetryError::Transient { err, retry_after: retry_after_ms };
+                }
+                _ => Some(e.status_code),
+            },
+            RumaApiError::Uiaa(_) => None,
+            RumaApiError::Other(e) => Some(e.status_code),
+        };
+
+        if let Some(status_code) = status_code {
+            if status_code.is_server_error() {
+                return RetryError::Transient { err, retry_after: None };
+            }
+        }
+    }
+
+    RetryError::Permanent(err)
 }
 
 #[cfg(not(target_arch = "wasm32"))]
@@ -21,8 +21,8 @@ pub use bytes;
 #[cfg(feature = "e2e-encryption")]
 pub use matrix_sdk_base::crypto;
 pub use matrix_sdk_base::{
-    deserialized_responses,
-    store::{DynStateStore, MemoryStore, StateStoreExt},
+    deserialized_responses, push,
+    store::{custom_value_keys, DynStateStore, MemoryStore, StateStoreExt},
     DisplayName, Room as BaseRoom, RoomInfo, RoomMember as BaseRoomMember, RoomMemberships,
     RoomState, SessionMeta, StateChanges, StateStore, StoreError,
 };
@@ -819,6 +819,9 @@ impl MatrixAuth {
 
         self.set_session(response.into()).await?;
 
+        #[cfg(feature = "e2e-encryption")]
+        self.client.encryption().dehydrated_devices().rehydrate_after_login().await;
+
         Ok(())
     }
 
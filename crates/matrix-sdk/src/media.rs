@@ -21,8 +21,16 @@ use std::io::Read;
 use std::path::Path;
 use std::time::Duration;
 
+#[cfg(not(target_arch = "wasm32"))]
+use bytes::Bytes;
 use eyeball::SharedObservable;
 use futures_util::future::try_join;
+#[cfg(not(target_arch = "wasm32"))]
+use futures_util::{
+    future::join,
+    stream::{BoxStream, StreamExt, TryStreamExt},
+    Stream,
+};
 pub use matrix_sdk_base::media::*;
 use mime::Mime;
 #[cfg(not(target_arch = "wasm32"))]
@@ -42,7 +50,7 @@ use ruma::{
 #[cfg(not(target_arch = "wasm32"))]
 use tempfile::{Builder as TempFileBuilder, NamedTempFile, TempDir};
 #[cfg(not(target_arch = "wasm32"))]
-use tokio::{fs::File as TokioFile, io::AsyncWriteExt};
+use tokio::{fs::File as TokioFile, io::AsyncWriteExt, sync::mpsc};
 
 use crate::{
     attachment::{AttachmentInfo, Thumbnail},
@@ -53,6 +61,10 @@ use crate::{
 const DEFAULT_UPLOAD_SPEED: u64 = 125_000;
 /// 5 min minimal upload request timeout, used to clamp the request timeout.
 const MIN_UPLOAD_REQUEST_TIMEOUT: Duration = Duration::from_secs(60 * 5);
+/// The number of chunks of a streamed upload that can be buffered before being
+/// sent.
+#[cfg(not(target_arch = "wasm32"))]
+const UPLOAD_STREAM_BUFFER_SIZE: usize = 4;
 
 /// A high-level API to interact with the media API.
 #[derive(Debug, Clone)]
@@ -131,6 +143,79 @@ impl Media {
         self.client.send(request, Some(request_config))
     }
 
+    /// Upload some media to the server, streaming its content instead of
+    /// holding it in memory.
+    ///
+    /// Unlike [`Media::upload()`], the request is not retried if it fails,
+    /// since the stream can only be consumed once.
+    ///
+    /// # Arguments
+    ///
+    /// * `content_type` - The type of the media, this will be used as the
+    ///   content-type header.
+    ///
+    /// * `content_length` - The total length of the chunks of the `stream`,
+    ///   some servers refuse uploads without a content-length header.
+    ///
+    /// * `stream` - The stream of chunks of the media, an error stops the
+    ///   upload.
+    ///
+    /// * `send_progress` - An observable that is updated with the number of
+    ///   bytes sent to the homeserver.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn upload_stream<S, B>(
+        &self,
+        content_type: &Mime,
+        content_length: u64,
+        stream: S,
+        send_progress: SharedObservable<TransmissionProgress>,
+    ) -> Result<create_content::v3::Response>
+    where
+        S: Stream<Item = std::io::Result<B>> + Send,
+        B: Into<Bytes>,
+    {
+        let timeout = std::cmp::max(
+            Duration::from_secs(content_length / DEFAULT_UPLOAD_SPEED),
+            MIN_UPLOAD_REQUEST_TIMEOUT,
+        );
+
+        // The body of the request is filled from a channel, so the stream doesn't need
+        // to be `'static`.
+        let (sender, receiver) = mpsc::channel(UPLOAD_STREAM_BUFFER_SIZE);
+        let body = reqwest::Body::wrap_stream(ReceiverStream(receiver));
+
+        let request = assign!(create_content::v3::Request::new(Vec::new()), {
+            content_type: Some(content_type.essence_str().to_owned()),
+        });
+        let request_config = self.client.request_config().timeout(timeout);
+
+        send_progress.update(|p| p.total += usize::try_from(content_length).unwrap_or(usize::MAX));
+
+        let upload =
+            self.client.upload_stream_inner(request, body, content_length, Some(request_config));
+
+        let forward_chunks = async move {
+            let mut stream = Box::pin(stream);
+
+            while let Some(chunk) = stream.next().await {
+                let chunk = chunk.map(Into::into);
+
+                if let Ok(chunk) = &chunk {
+                    send_progress.update(|p| p.current += chunk.len());
+                }
+
+                // The upload ended early, its result tells us why.
+                if sender.send(chunk).await.is_err() {
+                    break;
+                }
+            }
+        };
+
+        let (response, ()) = join(upload, forward_chunks).await;
+
+        Ok(response?)
+    }
+
     /// Gets a media file by copying it to a temporary location on disk.
     ///
     /// The file won't be encrypted even if it is encrypted on the server.
@@ -160,8 +245,51 @@ impl Media {
         use_cache: bool,
         temp_dir: Option<String>,
     ) -> Result<MediaFileHandle> {
-        let data = self.get_media_content(request, use_cache).await?;
+        self.get_media_file_with_progress(
+            request,
+            body,
+            content_type,
+            use_cache,
+            temp_dir,
+            Default::default(),
+        )
+        .await
+    }
 
+    /// Gets a media file by copying it to a temporary location on disk, while
+    /// reporting the progress of the download.
+    ///
+    /// This works like [`get_media_file`](#method.get_media_file), except that
+    /// the content is streamed from the homeserver to the file, and
+    /// decrypted on the fly if needed, instead of being held in memory.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The `MediaRequest` of the content.
+    ///
+    /// * `content_type` - The type of the media, this will be used to set the
+    ///   temporary file's extension.
+    ///
+    /// * `use_cache` - If we should use the media cache for this request. Note
+    ///   that the whole content needs to be held in memory to be added to the
+    ///   cache.
+    ///
+    /// * `temp_dir` - Path to a directory where temporary directories can be
+    ///   created. If not provided, a default, global temporary directory will
+    ///   be used.
+    ///
+    /// * `download_progress` - An observable that is updated with the number of
+    ///   bytes received from the homeserver.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn get_media_file_with_progress(
+        &self,
+        request: &MediaRequest,
+        body: Option<String>,
+        content_type: &Mime,
+        use_cache: bool,
+        temp_dir: Option<String>,
+        download_progress: SharedObservable<TransmissionProgress>,
+    ) -> Result<MediaFileHandle> {
         let inferred_extension = mime2ext::mime2ext(content_type);
 
         let body_path = body.as_ref().map(Path::new);
@@ -208,11 +336,100 @@ impl Media {
             _ => (TempFileBuilder::new().tempfile()?, None),
         };
 
-        TokioFile::from_std(temp_file.reopen()?).write_all(&data).await?;
+        let mut file = TokioFile::from_std(temp_file.reopen()?);
+
+        let cached =
+            if use_cache { self.client.store().get_media_content(request).await? } else { None };
+
+        if let Some(data) = cached {
+            download_progress.update(|p| {
+                p.current += data.len();
+                p.total += data.len();
+            });
+            file.write_all(&data).await?;
+        } else {
+            let mut stream = self.get_media_content_stream(request, download_progress).await?;
+            let mut content = Vec::new();
+
+            while let Some(chunk) = stream.try_next().await? {
+                file.write_all(&chunk).await?;
+
+                if use_cache {
+                    content.extend_from_slice(&chunk);
+                }
+            }
+
+            if use_cache {
+                self.client.store().add_media_content(request, content).await?;
+            }
+        }
+
+        file.flush().await?;
 
         Ok(MediaFileHandle { file: temp_file, _directory: temp_dir })
     }
 
+    /// Get a media file's content as a stream of chunks, while reporting the
+    /// progress of the download.
+    ///
+    /// If the content is encrypted and encryption is enabled, the chunks will
+    /// be decrypted as they are received. The content is only authenticated
+    /// once the stream ended without an error, the chunks must not be trusted
+    /// before that.
+    ///
+    /// The media cache is not used.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The `MediaRequest` of the content.
+    ///
+    /// * `download_progress` - An observable that is updated with the number of
+    ///   bytes received from the homeserver.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn get_media_content_stream(
+        &self,
+        request: &MediaRequest,
+        download_progress: SharedObservable<TransmissionProgress>,
+    ) -> Result<BoxStream<'static, Result<Bytes>>> {
+        let response = match &request.source {
+            MediaSource::Encrypted(file) => {
+                let request = get_content::v3::Request::from_url(&file.url)?;
+                self.client.download_inner(request, None).await?
+            }
+            MediaSource::Plain(uri) => {
+                if let MediaFormat::Thumbnail(size) = &request.format {
+                    let request =
+                        get_content_thumbnail::v3::Request::from_url(uri, size.width, size.height)?;
+                    self.client.download_inner(request, None).await?
+                } else {
+                    let request = get_content::v3::Request::from_url(uri)?;
+                    self.client.download_inner(request, None).await?
+                }
+            }
+        };
+
+        if let Some(total) = response.content_length() {
+            download_progress.update(|p| p.total += usize::try_from(total).unwrap_or(usize::MAX));
+        }
+
+        let stream = response
+            .bytes_stream()
+            .inspect_ok(move |chunk| download_progress.update(|p| p.current += chunk.len()));
+
+        match &request.source {
+            #[cfg(feature = "e2e-encryption")]
+            MediaSource::Encrypted(file) => {
+                let decryptor = matrix_sdk_base::crypto::AttachmentStreamDecryptor::new(
+                    stream.boxed(),
+                    file.as_ref().clone().into(),
+                )?;
+
+                Ok(decryptor.map_ok(Bytes::from).map_err(Into::into).boxed())
+            }
+            _ => Ok(stream.map_err(Into::into).boxed()),
+        }
+    }
+
     /// Get a media file's content.
     ///
     /// If the content is encrypted and encryption is enabled, the content will
@@ -492,3 +709,20 @@ impl Media {
         }
     }
 }
+
+/// A [`Stream`] of the chunks received by a channel, used as the body of
+/// streamed uploads.
+#[cfg(not(target_arch = "wasm32"))]
+struct ReceiverStream(mpsc::Receiver<std::io::Result<Bytes>>);
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Stream for ReceiverStream {
+    type Item = std::io::Result<Bytes>;
+
+    fn poll_next(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        self.0.poll_recv(cx)
+    }
+}
@@ -3,8 +3,8 @@ use std::fmt::Debug;
 use ruma::{
     api::client::push::RuleScope,
     push::{
-        Action, NewConditionalPushRule, NewPushRule, NewSimplePushRule, PushCondition, RuleKind,
-        Tweak,
+        Action, NewConditionalPushRule, NewPatternedPushRule, NewPushRule, NewSimplePushRule,
+        PushCondition, RuleKind, Tweak,
     },
     OwnedRoomId,
 };
@@ -18,6 +18,8 @@ pub(crate) enum Command {
     SetRoomPushRule { scope: RuleScope, room_id: OwnedRoomId, notify: bool },
     /// Set a new `Override` push rule matching a `RoomId`
     SetOverridePushRule { scope: RuleScope, rule_id: String, room_id: OwnedRoomId, notify: bool },
+    /// Set a new `Content` push rule matching a keyword
+    SetKeywordPushRule { scope: RuleScope, rule_id: String, keyword: String, actions: Vec<Action> },
     /// Set whether a push rule is enabled
     SetPushRuleEnabled { scope: RuleScope, kind: RuleKind, rule_id: String, enabled: bool },
     /// Delete a push rule
@@ -26,7 +28,7 @@ pub(crate) enum Command {
     SetPushRuleActions { scope: RuleScope, kind: RuleKind, rule_id: String, actions: Vec<Action> },
 }
 
-fn get_notify_actions(notify: bool) -> Vec<Action> {
+pub(crate) fn get_notify_actions(notify: bool) -> Vec<Action> {
     if notify {
         vec![Action::Notify, Action::SetTweak(Tweak::Sound("default".into()))]
     } else {
@@ -57,6 +59,13 @@ impl Command {
                 Ok(NewPushRule::Override(new_rule))
             }
 
+            Self::SetKeywordPushRule { scope: _, rule_id, keyword, actions } => {
+                // `Content` push rule matching this keyword
+                let new_rule =
+                    NewPatternedPushRule::new(rule_id.clone(), keyword.clone(), actions.clone());
+                Ok(NewPushRule::Content(new_rule))
+            }
+
             Self::SetPushRuleEnabled { .. }
             | Self::DeletePushRule { .. }
             | Self::SetPushRuleActions { .. } => Err(NotificationSettingsError::InvalidParameter(
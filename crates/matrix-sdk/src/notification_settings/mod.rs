@@ -1,7 +1,9 @@
 //! High-level push notification settings API
 
-use std::sync::Arc;
+use std::{collections::BTreeSet, sync::Arc};
 
+use eyeball::SharedObservable;
+use futures_core::Stream;
 use ruma::{
     api::client::push::{
         delete_pushrule, set_pushrule, set_pushrule_actions, set_pushrule_enabled,
@@ -10,7 +12,7 @@ use ruma::{
     push::{Action, RuleKind, Ruleset, Tweak},
     RoomId,
 };
-use tokio::sync::RwLock;
+use tokio::sync::{broadcast, RwLock};
 
 use self::{command::Command, rule_commands::RuleCommands, rules::Rules};
 
@@ -19,7 +21,7 @@ mod rule_commands;
 mod rules;
 
 use crate::{
-    config::RequestConfig, error::NotificationSettingsError, event_handler::EventHandlerHandle,
+    config::RequestConfig, error::NotificationSettingsError, event_handler::EventHandlerDropGuard,
     Client, Result,
 };
 
@@ -79,14 +81,13 @@ pub struct NotificationSettings {
     client: Client,
     /// Owner's account push rules. They will be updated on sync.
     rules: Arc<RwLock<Rules>>,
-    /// Event handler for push rules event
-    push_rules_event_handler: EventHandlerHandle,
-}
-
-impl Drop for NotificationSettings {
-    fn drop(&mut self) {
-        self.client.remove_event_handler(self.push_rules_event_handler.clone());
-    }
+    /// Keywords of the owner's enabled keyword rules.
+    keywords: SharedObservable<BTreeSet<String>>,
+    /// Sender notifying that the push rules changed.
+    changes_sender: broadcast::Sender<()>,
+    /// Event handler for push rules event, removed when the last clone of
+    /// this `NotificationSettings` is dropped.
+    _push_rules_event_handler: Arc<EventHandlerDropGuard>,
 }
 
 impl NotificationSettings {
@@ -97,17 +98,62 @@ impl NotificationSettings {
     /// * `client` - A `Client` used to perform API calls
     /// * `ruleset` - A `Ruleset` containing account's owner push rules
     pub fn new(client: Client, ruleset: Ruleset) -> Self {
-        let rules = Arc::new(RwLock::new(Rules::new(ruleset)));
+        let rules = Rules::new(ruleset);
+        let keywords = SharedObservable::new(rules.get_keywords());
+        let rules = Arc::new(RwLock::new(rules));
+        let (changes_sender, _) = broadcast::channel(8);
 
         // Listen for PushRulesEvent
         let push_rules_event_handler = client.add_event_handler({
             let rules = Arc::clone(&rules);
+            let keywords = keywords.clone();
+            let changes_sender = changes_sender.clone();
             move |ev: PushRulesEvent| async move {
-                *rules.write().await = Rules::new(ev.content.global);
+                let new_rules = Rules::new(ev.content.global);
+                let rules = &mut *rules.write().await;
+
+                // The server echoes the changes made by this client, ignore them.
+                if *rules == new_rules {
+                    return;
+                }
+
+                keywords.set_if_not_eq(new_rules.get_keywords());
+                *rules = new_rules;
+                _ = changes_sender.send(());
             }
         });
+        let push_rules_event_handler =
+            Arc::new(client.event_handler_drop_guard(push_rules_event_handler));
+
+        Self {
+            client,
+            rules,
+            keywords,
+            changes_sender,
+            _push_rules_event_handler: push_rules_event_handler,
+        }
+    }
 
-        Self { client, rules, push_rules_event_handler }
+    /// Subscribe to the changes of the push rules.
+    ///
+    /// A notification is received whenever the push rules are modified,
+    /// either through this `NotificationSettings` or from another device. The
+    /// room notification modes, the default modes, the mention and keyword
+    /// settings should then be fetched again.
+    ///
+    /// If the stream lags behind, the missed notifications are merged into a
+    /// single one.
+    pub fn subscribe_to_changes(&self) -> impl Stream<Item = ()> {
+        let mut receiver = self.changes_sender.subscribe();
+
+        async_stream::stream! {
+            loop {
+                match receiver.recv().await {
+                    Ok(()) | Err(broadcast::error::RecvError::Lagged(_)) => yield (),
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        }
     }
 
     /// Get the user defined notification mode for a room.
@@ -143,6 +189,93 @@ impl NotificationSettings {
         self.rules.read().await.contains_keyword_rules()
     }
 
+    /// Get the keywords for which the owner is notified.
+    ///
+    /// Keywords are matched against the body of the messages by `Content`
+    /// push rules.
+    pub fn keywords(&self) -> BTreeSet<String> {
+        self.keywords.get()
+    }
+
+    /// Subscribe to the changes of the keywords for which the owner is
+    /// notified.
+    ///
+    /// The stream yields the new list of keywords whenever it changes, either
+    /// from this `NotificationSettings` or from another device.
+    pub fn subscribe_to_keywords(&self) -> impl Stream<Item = BTreeSet<String>> {
+        self.keywords.subscribe()
+    }
+
+    /// Add a keyword for which the owner will be notified.
+    ///
+    /// The keyword rule notifies with the same actions as the default room
+    /// mode when it is `AllMessages`, and is kept in line with it when the
+    /// default mode changes. If a rule already exists for this keyword, it is
+    /// enabled instead of creating a new one.
+    ///
+    /// Note that the homeserver can't match keywords in encrypted rooms, so
+    /// keyword notifications for those rooms only work if the events are
+    /// evaluated on the device.
+    pub async fn add_keyword(&self, keyword: String) -> Result<(), NotificationSettingsError> {
+        if keyword.is_empty() {
+            return Err(NotificationSettingsError::InvalidParameter(
+                "the keyword cannot be empty.".to_owned(),
+            ));
+        }
+
+        let rules = self.rules.read().await.clone();
+
+        // Check that the keyword is not already enabled.
+        if rules.get_keywords().contains(&keyword) {
+            return Ok(());
+        }
+
+        let existing_rules = rules.get_keyword_rules(&keyword);
+        let actions = rules.get_keyword_actions();
+        let mut rule_commands = RuleCommands::new(rules.ruleset);
+
+        if let Some(rule_id) = existing_rules.first() {
+            // Reuse the disabled rule, making sure that it uses the keyword actions.
+            let has_actions = rule_commands
+                .rules
+                .get(RuleKind::Content, rule_id)
+                .is_some_and(|r| rules::same_actions(r.actions(), &actions));
+            if !has_actions {
+                rule_commands.set_rule_actions(RuleKind::Content, rule_id, actions)?;
+            }
+            rule_commands.set_rule_enabled(RuleKind::Content, rule_id, true)?;
+        } else {
+            rule_commands.insert_keyword_rule(keyword, actions)?;
+        }
+
+        self.run_server_commands(&rule_commands).await?;
+        self.apply(rule_commands).await;
+
+        Ok(())
+    }
+
+    /// Remove a keyword for which the owner is notified.
+    ///
+    /// All the keyword rules matching this keyword are deleted.
+    pub async fn remove_keyword(&self, keyword: &str) -> Result<(), NotificationSettingsError> {
+        let rules = self.rules.read().await.clone();
+
+        let existing_rules = rules.get_keyword_rules(keyword);
+        if existing_rules.is_empty() {
+            return Ok(());
+        }
+
+        let mut rule_commands = RuleCommands::new(rules.ruleset);
+        for rule_id in existing_rules {
+            rule_commands.delete_rule(RuleKind::Content, rule_id)?;
+        }
+
+        self.run_server_commands(&rule_commands).await?;
+        self.apply(rule_commands).await;
+
+        Ok(())
+    }
+
     /// Get whether a push rule is enabled.
     pub async fn is_push_rule_enabled(
         &self,
@@ -165,9 +298,7 @@ impl NotificationSettings {
         rule_commands.set_rule_enabled(kind, rule_id, enabled)?;
 
         self.run_server_commands(&rule_commands).await?;
-
-        let rules = &mut *self.rules.write().await;
-        rules.apply(rule_commands);
+        self.apply(rule_commands).await;
 
         Ok(())
     }
@@ -201,10 +332,12 @@ impl NotificationSettings {
 
         rule_commands.set_rule_actions(RuleKind::Underride, rule_id.as_str(), actions)?;
 
-        self.run_server_commands(&rule_commands).await?;
+        // The keyword rules follow the default room mode.
+        let keyword_actions = Rules::new(rule_commands.rules.clone()).get_keyword_actions();
+        rule_commands.set_keyword_rules_actions(&keyword_actions)?;
 
-        let rules = &mut *self.rules.write().await;
-        rules.apply(rule_commands);
+        self.run_server_commands(&rule_commands).await?;
+        self.apply(rule_commands).await;
 
         Ok(())
     }
@@ -255,9 +388,7 @@ impl NotificationSettings {
         }
 
         self.run_server_commands(&rule_commands).await?;
-
-        let rules = &mut *self.rules.write().await;
-        rules.apply(rule_commands);
+        self.apply(rule_commands).await;
 
         Ok(())
     }
@@ -280,9 +411,7 @@ impl NotificationSettings {
         }
 
         self.run_server_commands(&rule_commands).await?;
-
-        let rules = &mut *self.rules.write().await;
-        rules.apply(rule_commands);
+        self.apply(rule_commands).await;
 
         Ok(())
     }
@@ -321,6 +450,15 @@ impl NotificationSettings {
         }
     }
 
+    /// Apply the given commands to the local rules, once they have been run on
+    /// the server.
+    async fn apply(&self, rule_commands: RuleCommands) {
+        let rules = &mut *self.rules.write().await;
+        rules.apply(rule_commands);
+        self.keywords.set_if_not_eq(rules.get_keywords());
+        _ = self.changes_sender.send(());
+    }
+
     /// Convert commands into requests to the server, and run them.
     async fn run_server_commands(
         &self,
@@ -356,6 +494,14 @@ impl NotificationSettings {
                         .await
                         .map_err(|_| NotificationSettingsError::UnableToAddPushRule)?;
                 }
+                Command::SetKeywordPushRule { scope, rule_id: _, keyword: _, actions: _ } => {
+                    let push_rule = command.to_push_rule()?;
+                    let request = set_pushrule::v3::Request::new(scope.clone(), push_rule);
+                    self.client
+                        .send(request, request_config)
+                        .await
+                        .map_err(|_| NotificationSettingsError::UnableToAddPushRule)?;
+                }
                 Command::SetPushRuleEnabled { scope, kind, rule_id, enabled } => {
                     let request = set_pushrule_enabled::v3::Request::new(
                         scope.clone(),
@@ -390,9 +536,11 @@ impl NotificationSettings {
 #[cfg(all(test, not(target_arch = "wasm32")))]
 mod tests {
     use assert_matches::assert_matches;
+    use futures_util::{pin_mut, FutureExt, StreamExt};
     use matrix_sdk_test::{
         async_test,
         notification_settings::{build_ruleset, get_server_default_ruleset},
+        GlobalAccountDataTestEvent, SyncResponseBuilder,
     };
     use ruma::{
         push::{
@@ -401,9 +549,15 @@ mod tests {
         },
         OwnedRoomId, RoomId,
     };
-    use wiremock::{http::Method, matchers::method, Mock, MockServer, ResponseTemplate};
+    use serde_json::json;
+    use wiremock::{
+        http::Method,
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
 
     use crate::{
+        config::SyncSettings,
         error::NotificationSettingsError,
         notification_settings::{
             IsEncrypted, IsOneToOne, NotificationSettings, RoomNotificationMode,
@@ -583,6 +737,255 @@ mod tests {
         assert!(contains_keywords_rules);
     }
 
+    #[async_test]
+    async fn test_add_keyword() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(Some(server.uri())).await;
+        let settings = NotificationSettings::new(client, get_server_default_ruleset());
+
+        Mock::given(method("PUT")).respond_with(ResponseTemplate::new(200)).mount(&server).await;
+
+        let keywords_stream = settings.subscribe_to_keywords();
+        pin_mut!(keywords_stream);
+
+        settings.add_keyword("team".to_owned()).await.unwrap();
+
+        // Test the request sent, the ID of the rule is generated
+        let requests = server.received_requests().await.unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, Method::Put);
+        assert!(requests[0]
+            .url
+            .path()
+            .starts_with("/_matrix/client/r0/pushrules/global/content/keyword."));
+
+        // The keyword rule must notify like the default mode of group rooms
+        {
+            let rules = settings.rules.read().await;
+            let rule = rules.ruleset.content.iter().find(|r| r.pattern == "team").unwrap();
+            let message_rule =
+                rules.ruleset.get(RuleKind::Underride, PredefinedUnderrideRuleId::Message).unwrap();
+            assert!(rule.actions.iter().any(|a| a.should_notify()));
+            assert_eq!(
+                serde_json::to_value(&rule.actions).unwrap(),
+                serde_json::to_value(message_rule.actions()).unwrap()
+            );
+        }
+
+        // The new keyword must be listed and sent to the subscribers
+        assert!(settings.keywords().contains("team"));
+        assert_matches!(keywords_stream.next().await, Some(keywords) => {
+            assert!(keywords.contains("team"));
+        });
+
+        // Adding the keyword again is a no-op
+        settings.add_keyword("team".to_owned()).await.unwrap();
+        assert_eq!(server.received_requests().await.unwrap().len(), 1);
+    }
+
+    #[async_test]
+    async fn test_keyword_actions_follow_default_mode() {
+        let server = MockServer::start().await;
+        Mock::given(method("PUT")).respond_with(ResponseTemplate::new(200)).mount(&server).await;
+        let client = logged_in_client(Some(server.uri())).await;
+
+        // Initialize with a keyword rule that uses the actions of the default mode
+        let mut ruleset = get_server_default_ruleset();
+        for rule_id in [PredefinedUnderrideRuleId::Message, PredefinedUnderrideRuleId::Encrypted] {
+            ruleset.set_actions(RuleKind::Underride, rule_id, vec![Action::Notify]).unwrap();
+        }
+        let rule = NewPatternedPushRule::new("team".into(), "team".into(), vec![Action::Notify]);
+        ruleset.insert(NewPushRule::Content(rule), None, None).unwrap();
+        let settings = NotificationSettings::new(client, ruleset);
+
+        // Group rooms switch to `MentionsAndKeywordsOnly`, the keyword rule now uses
+        // the actions of the encrypted group rooms, which are the same.
+        settings
+            .set_default_room_notification_mode(
+                IsEncrypted::No,
+                IsOneToOne::No,
+                RoomNotificationMode::MentionsAndKeywordsOnly,
+            )
+            .await
+            .unwrap();
+        assert_eq!(server.received_requests().await.unwrap().len(), 1);
+
+        // Encrypted group rooms switch to `AllMessages` with a sound, the keyword rule
+        // follows.
+        settings
+            .set_default_room_notification_mode(
+                IsEncrypted::Yes,
+                IsOneToOne::No,
+                RoomNotificationMode::AllMessages,
+            )
+            .await
+            .unwrap();
+
+        let requests = server.received_requests().await.unwrap();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(
+            requests[2].url.path(),
+            "/_matrix/client/r0/pushrules/global/content/team/actions"
+        );
+        assert_matches!(settings.rules.read().await.ruleset.get(RuleKind::Content, "team"),
+            Some(AnyPushRuleRef::Content(rule)) => {
+                assert_eq!(rule.actions.len(), 2);
+            }
+        );
+    }
+
+    #[async_test]
+    async fn test_add_keyword_enables_existing_rule() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(Some(server.uri())).await;
+
+        // Initialize with a disabled keyword rule that doesn't notify
+        let mut ruleset = get_server_default_ruleset();
+        let rule = NewPatternedPushRule::new("team_rule".into(), "team".into(), vec![]);
+        ruleset.insert(NewPushRule::Content(rule), None, None).unwrap();
+        ruleset.set_enabled(RuleKind::Content, "team_rule", false).unwrap();
+
+        let settings = NotificationSettings::new(client, ruleset);
+        assert!(settings.keywords().is_empty());
+
+        Mock::given(method("PUT")).respond_with(ResponseTemplate::new(200)).mount(&server).await;
+
+        settings.add_keyword("team".to_owned()).await.unwrap();
+
+        // The existing rule must have been updated rather than creating a new one
+        let requests = server.received_requests().await.unwrap();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(
+            requests[0].url.path(),
+            "/_matrix/client/r0/pushrules/global/content/team_rule/actions"
+        );
+        assert_eq!(
+            requests[1].url.path(),
+            "/_matrix/client/r0/pushrules/global/content/team_rule/enabled"
+        );
+
+        assert!(settings.keywords().contains("team"));
+        assert!(settings.rules.read().await.ruleset.get(RuleKind::Content, "team").is_none());
+    }
+
+    #[async_test]
+    async fn test_add_keyword_empty() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(Some(server.uri())).await;
+        let settings = NotificationSettings::new(client, get_server_default_ruleset());
+
+        assert_matches!(
+            settings.add_keyword(String::new()).await,
+            Err(NotificationSettingsError::InvalidParameter(_))
+        );
+    }
+
+    #[async_test]
+    async fn test_remove_keyword() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(Some(server.uri())).await;
+
+        // Initialize with two rules for the same keyword
+        let mut ruleset = get_server_default_ruleset();
+        for rule_id in ["team", "team_2"] {
+            let rule =
+                NewPatternedPushRule::new(rule_id.into(), "team".into(), vec![Action::Notify]);
+            ruleset.insert(NewPushRule::Content(rule), None, None).unwrap();
+        }
+
+        let settings = NotificationSettings::new(client, ruleset);
+        assert!(settings.keywords().contains("team"));
+
+        Mock::given(method("DELETE")).respond_with(ResponseTemplate::new(200)).mount(&server).await;
+
+        settings.remove_keyword("team").await.unwrap();
+
+        // Both rules must have been deleted
+        let requests = server.received_requests().await.unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(requests.iter().all(|r| r.method == Method::Delete));
+        assert!(settings.keywords().is_empty());
+        assert!(!settings.contains_keyword_rules().await);
+
+        // Removing an unknown keyword is a no-op
+        settings.remove_keyword("team").await.unwrap();
+        assert_eq!(server.received_requests().await.unwrap().len(), 2);
+    }
+
+    #[async_test]
+    async fn test_remove_keyword_api_error() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(Some(server.uri())).await;
+
+        let mut ruleset = get_server_default_ruleset();
+        let rule = NewPatternedPushRule::new("team".into(), "team".into(), vec![Action::Notify]);
+        ruleset.insert(NewPushRule::Content(rule), None, None).unwrap();
+
+        let settings = NotificationSettings::new(client, ruleset);
+
+        // If the server returns an error
+        Mock::given(method("DELETE")).respond_with(ResponseTemplate::new(500)).mount(&server).await;
+
+        assert_eq!(
+            settings.remove_keyword("team").await,
+            Err(NotificationSettingsError::UnableToRemovePushRule)
+        );
+
+        // The keyword must still be there
+        assert!(settings.keywords().contains("team"));
+    }
+
+    #[async_test]
+    async fn test_subscribe_to_changes() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(Some(server.uri())).await;
+        let settings = NotificationSettings::new(client.clone(), get_server_default_ruleset());
+        let changes = settings.subscribe_to_changes();
+        pin_mut!(changes);
+
+        // A change made with the settings must be notified
+        Mock::given(method("PUT")).respond_with(ResponseTemplate::new(200)).mount(&server).await;
+        settings
+            .set_push_rule_enabled(
+                RuleKind::Override,
+                PredefinedOverrideRuleId::Reaction.as_str(),
+                false,
+            )
+            .await
+            .unwrap();
+        assert_matches!(changes.next().now_or_never(), Some(Some(())));
+
+        // A change made from another device must be notified
+        let mut ruleset = settings.rules.read().await.ruleset.clone();
+        ruleset
+            .set_actions(RuleKind::Underride, PredefinedUnderrideRuleId::RoomOneToOne, vec![])
+            .unwrap();
+        let mut sync_builder = SyncResponseBuilder::new();
+        sync_builder.add_global_account_data_event(GlobalAccountDataTestEvent::Custom(json!({
+            "type": "m.push_rules",
+            "content": { "global": ruleset },
+        })));
+        Mock::given(method("GET"))
+            .and(path("/_matrix/client/r0/sync"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(sync_builder.build_json_sync_response()),
+            )
+            .mount(&server)
+            .await;
+
+        client.sync_once(SyncSettings::default()).await.unwrap();
+
+        assert_matches!(changes.next().now_or_never(), Some(Some(())));
+        assert_eq!(
+            settings.get_default_room_notification_mode(IsEncrypted::No, IsOneToOne::Yes).await,
+            RoomNotificationMode::MentionsAndKeywordsOnly
+        );
+
+        // Receiving the same push rules again must not be notified
+        client.sync_once(SyncSettings::default()).await.unwrap();
+        assert_matches!(changes.next().now_or_never(), None);
+    }
+
     #[async_test]
     async fn test_is_push_rule_enabled() {
         let server = MockServer::start().await;
@@ -4,10 +4,10 @@ use ruma::{
         Action, PredefinedContentRuleId, PredefinedOverrideRuleId, RemovePushRuleError, RuleKind,
         Ruleset,
     },
-    RoomId,
+    RoomId, TransactionId,
 };
 
-use super::command::Command;
+use super::{command::Command, rules::same_actions};
 use crate::NotificationSettingsError;
 
 /// A `RuleCommand` allows to generate a list of `Command` needed to modify a
@@ -55,6 +55,28 @@ impl RuleCommands {
         Ok(())
     }
 
+    /// Insert a new `Content` rule matching the given keyword
+    ///
+    /// Keywords can contain characters that are not allowed in a rule ID, so
+    /// the ID of the rule is generated.
+    pub(crate) fn insert_keyword_rule(
+        &mut self,
+        keyword: String,
+        actions: Vec<Action>,
+    ) -> Result<(), NotificationSettingsError> {
+        let command = Command::SetKeywordPushRule {
+            scope: RuleScope::Global,
+            rule_id: format!("keyword.{}", TransactionId::new()),
+            keyword,
+            actions,
+        };
+
+        self.rules.insert(command.to_push_rule()?, None, None)?;
+        self.commands.push(command);
+
+        Ok(())
+    }
+
     /// Delete a rule
     pub(crate) fn delete_rule(
         &mut self,
@@ -172,6 +194,27 @@ impl RuleCommands {
         });
         Ok(())
     }
+
+    /// Set the actions of the user defined `Content` rules that use different
+    /// actions
+    pub(crate) fn set_keyword_rules_actions(
+        &mut self,
+        actions: &[Action],
+    ) -> Result<(), NotificationSettingsError> {
+        let rule_ids: Vec<String> = self
+            .rules
+            .content
+            .iter()
+            .filter(|r| !r.default && !same_actions(&r.actions, actions))
+            .map(|r| r.rule_id.clone())
+            .collect();
+
+        for rule_id in rule_ids {
+            self.set_rule_actions(RuleKind::Content, &rule_id, actions.to_vec())?;
+        }
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -181,9 +224,9 @@ mod tests {
     use ruma::{
         api::client::push::RuleScope,
         push::{
-            Action, NewPushRule, NewSimplePushRule, PredefinedContentRuleId,
-            PredefinedOverrideRuleId, PredefinedUnderrideRuleId, RemovePushRuleError, RuleKind,
-            Ruleset, Tweak,
+            Action, AnyPushRuleRef, NewPatternedPushRule, NewPushRule, NewSimplePushRule,
+            PredefinedContentRuleId, PredefinedOverrideRuleId, PredefinedUnderrideRuleId,
+            RemovePushRuleError, RuleKind, Ruleset, Tweak,
         },
         OwnedRoomId, RoomId, UserId,
     };
@@ -262,6 +305,54 @@ mod tests {
         );
     }
 
+    #[async_test]
+    async fn test_insert_keyword_rule() {
+        let mut rule_commands = RuleCommands::new(get_server_default_ruleset());
+        rule_commands.insert_keyword_rule("team".to_owned(), vec![Action::Notify]).unwrap();
+
+        // A `Content` rule must have been inserted in the ruleset, with a generated ID.
+        let rule = rule_commands.rules.content.iter().find(|r| r.pattern == "team").unwrap();
+        assert!(rule.rule_id.starts_with("keyword."));
+        assert!(rule.enabled);
+        assert!(rule.actions.iter().any(|a| a.should_notify()));
+
+        // Exactly one command must have been created.
+        assert_eq!(rule_commands.commands.len(), 1);
+        assert_matches!(&rule_commands.commands[0],
+            Command::SetKeywordPushRule { scope, rule_id, keyword, actions } => {
+                assert_eq!(scope, &RuleScope::Global);
+                assert_eq!(rule_id, &rule.rule_id);
+                assert_eq!(keyword, "team");
+                assert_eq!(actions.len(), 1);
+            }
+        );
+    }
+
+    #[async_test]
+    async fn test_set_keyword_rules_actions() {
+        let mut ruleset = get_server_default_ruleset();
+        let rule = NewPatternedPushRule::new("team".into(), "team".into(), vec![Action::Notify]);
+        ruleset.insert(NewPushRule::Content(rule), None, None).unwrap();
+        let rule = NewPatternedPushRule::new("project".into(), "project".into(), vec![]);
+        ruleset.insert(NewPushRule::Content(rule), None, None).unwrap();
+
+        let mut rule_commands = RuleCommands::new(ruleset);
+        rule_commands.set_keyword_rules_actions(&[Action::Notify]).unwrap();
+
+        // Only the rule with different actions must have been updated.
+        assert_matches!(rule_commands.rules.get(RuleKind::Content, "project"),
+            Some(AnyPushRuleRef::Content(rule)) => {
+                assert!(rule.actions.iter().any(|a| a.should_notify()));
+            }
+        );
+        assert_eq!(rule_commands.commands.len(), 1);
+        assert_matches!(&rule_commands.commands[0],
+            Command::SetPushRuleActions { kind: RuleKind::Content, rule_id, .. } => {
+                assert_eq!(rule_id, "project");
+            }
+        );
+    }
+
     #[async_test]
     async fn test_delete_rule() {
         let room_id = get_test_room_id();
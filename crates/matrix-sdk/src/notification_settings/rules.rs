@@ -1,15 +1,21 @@
 //! Ruleset utility struct
 
+use std::collections::BTreeSet;
+
 use imbl::HashSet;
 use ruma::{
     push::{
-        AnyPushRuleRef, PredefinedContentRuleId, PredefinedOverrideRuleId,
+        Action, AnyPushRuleRef, PredefinedContentRuleId, PredefinedOverrideRuleId,
         PredefinedUnderrideRuleId, PushCondition, RuleKind, Ruleset,
     },
     RoomId,
 };
 
-use super::{command::Command, rule_commands::RuleCommands, RoomNotificationMode};
+use super::{
+    command::{get_notify_actions, Command},
+    rule_commands::RuleCommands,
+    RoomNotificationMode,
+};
 use crate::{
     error::NotificationSettingsError,
     notification_settings::{IsEncrypted, IsOneToOne},
@@ -20,6 +26,17 @@ pub(crate) struct Rules {
     pub ruleset: Ruleset,
 }
 
+impl PartialEq for Rules {
+    fn eq(&self, other: &Self) -> bool {
+        // Push rules are only compared by ID, so compare the serialized rulesets
+        // to also take the conditions, actions and enabled state into account.
+        match (serde_json::to_value(&self.ruleset), serde_json::to_value(&other.ruleset)) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
 impl Rules {
     pub(crate) fn new(ruleset: Ruleset) -> Self {
         Rules { ruleset }
@@ -214,6 +231,62 @@ impl Rules {
         self.ruleset.content.iter().any(|r| !r.default && r.enabled)
     }
 
+    /// Get the keywords of the enabled user defined `Content` rules.
+    pub(crate) fn get_keywords(&self) -> BTreeSet<String> {
+        self.ruleset
+            .content
+            .iter()
+            .filter(|r| !r.default && r.enabled)
+            .map(|r| r.pattern.clone())
+            .collect()
+    }
+
+    /// Get the actions of the keyword rules.
+    ///
+    /// Keyword rules apply to every room, so they use the same actions
+    /// whether a room is encrypted or one-to-one: the actions of the first
+    /// default room rule that notifies, looking at group rooms before
+    /// one-to-one rooms and at unencrypted rooms before encrypted ones. If no
+    /// default room mode is `AllMessages`, the actions that this mode would
+    /// use are returned.
+    pub(crate) fn get_keyword_actions(&self) -> Vec<Action> {
+        let variants = [
+            (IsEncrypted::No, IsOneToOne::No),
+            (IsEncrypted::Yes, IsOneToOne::No),
+            (IsEncrypted::No, IsOneToOne::Yes),
+            (IsEncrypted::Yes, IsOneToOne::Yes),
+        ];
+
+        variants
+            .into_iter()
+            .find_map(|(is_encrypted, is_one_to_one)| {
+                let rule_id = get_predefined_underride_room_rule_id(is_encrypted, is_one_to_one);
+                self.ruleset
+                    .underride
+                    .iter()
+                    .find(|r| {
+                        r.enabled
+                            && r.rule_id == rule_id.as_str()
+                            && r.actions.iter().any(|a| a.should_notify())
+                    })
+                    .map(|r| r.actions.clone())
+            })
+            .unwrap_or_else(|| get_notify_actions(true))
+    }
+
+    /// Get the IDs of the user defined `Content` rules matching a keyword.
+    ///
+    /// Other clients may have created several rules for the same keyword, so
+    /// more than one rule may be returned.
+    pub(crate) fn get_keyword_rules(&self, keyword: &str) -> Vec<String> {
+        self.ruleset
+            .content
+            .iter()
+            .filter(|r| !r.default && r.pattern == keyword)
+            .map(|r| r.rule_id.clone())
+            .collect()
+    }
+
     /// Get whether a rule is enabled.
     pub(crate) fn is_enabled(
         &self,
@@ -241,7 +314,9 @@ impl Rules {
                 Command::DeletePushRule { scope: _, kind, rule_id } => {
                     _ = self.ruleset.remove(kind, rule_id);
                 }
-                Command::SetRoomPushRule { .. } | Command::SetOverridePushRule { .. } => {
+                Command::SetRoomPushRule { .. }
+                | Command::SetOverridePushRule { .. }
+                | Command::SetKeywordPushRule { .. } => {
                     if let Ok(push_rule) = command.to_push_rule() {
                         _ = self.ruleset.insert(push_rule, None, None);
                     }
@@ -264,6 +339,15 @@ impl Rules {
 ///
 /// * `is_encrypted` - `Yes` if the room is encrypted
 /// * `is_one_to_one` - `Yes` if the room is a direct chat involving two people
+/// Whether the given lists of actions are the same.
+pub(crate) fn same_actions(a: &[Action], b: &[Action]) -> bool {
+    // Compare the serialized actions, like for the rulesets.
+    match (serde_json::to_value(a), serde_json::to_value(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
 pub(crate) fn get_predefined_underride_room_rule_id(
     is_encrypted: IsEncrypted,
     is_one_to_one: IsOneToOne,
@@ -285,8 +369,9 @@ pub(crate) mod tests {
     };
     use ruma::{
         push::{
-            Action, NewConditionalPushRule, NewPushRule, PredefinedContentRuleId,
-            PredefinedOverrideRuleId, PredefinedUnderrideRuleId, PushCondition, RuleKind,
+            Action, NewConditionalPushRule, NewPatternedPushRule, NewPushRule,
+            PredefinedContentRuleId, PredefinedOverrideRuleId, PredefinedUnderrideRuleId,
+            PushCondition, RuleKind, Tweak,
         },
         OwnedRoomId, RoomId,
     };
@@ -602,6 +687,99 @@ pub(crate) mod tests {
             .unwrap());
     }
 
+    #[async_test]
+    async fn test_eq() {
+        let rules = Rules::new(get_server_default_ruleset());
+        let mut other_rules = Rules::new(get_server_default_ruleset());
+        assert!(rules == other_rules);
+
+        // Rules with the same IDs but a different state must not be equal
+        other_rules
+            .ruleset
+            .set_enabled(RuleKind::Override, PredefinedOverrideRuleId::Reaction, false)
+            .unwrap();
+        assert!(rules != other_rules);
+    }
+
+    #[async_test]
+    async fn test_get_keywords() {
+        // Without keyword rules
+        let rules = Rules::new(get_server_default_ruleset());
+        assert!(rules.get_keywords().is_empty());
+
+        // With two rules for the same keyword, and a disabled one
+        let mut ruleset = get_server_default_ruleset();
+        for (rule_id, pattern) in [("team", "team"), ("team_2", "team"), ("project", "project")] {
+            let rule =
+                NewPatternedPushRule::new(rule_id.into(), pattern.into(), vec![Action::Notify]);
+            ruleset.insert(NewPushRule::Content(rule), None, None).unwrap();
+        }
+        ruleset.set_enabled(RuleKind::Content, "project", false).unwrap();
+        let rules = Rules::new(ruleset);
+
+        let keywords = rules.get_keywords();
+        assert_eq!(keywords.len(), 1);
+        assert!(keywords.contains("team"));
+
+        let mut rule_ids = rules.get_keyword_rules("team");
+        rule_ids.sort();
+        assert_eq!(rule_ids, vec!["team", "team_2"]);
+        assert_eq!(rules.get_keyword_rules("project"), vec!["project"]);
+        assert!(rules.get_keyword_rules("unknown").is_empty());
+    }
+
+    #[async_test]
+    async fn test_get_keyword_actions() {
+        let sound = Action::SetTweak(Tweak::Sound("default".into()));
+
+        // The actions of the first default rule that notifies are used.
+        let mut ruleset = get_server_default_ruleset();
+        ruleset
+            .set_actions(RuleKind::Underride, PredefinedUnderrideRuleId::Message, vec![])
+            .unwrap();
+        ruleset
+            .set_actions(
+                RuleKind::Underride,
+                PredefinedUnderrideRuleId::Encrypted,
+                vec![Action::Notify],
+            )
+            .unwrap();
+        ruleset
+            .set_actions(
+                RuleKind::Underride,
+                PredefinedUnderrideRuleId::RoomOneToOne,
+                vec![Action::Notify, sound.clone()],
+            )
+            .unwrap();
+        let rules = Rules::new(ruleset.clone());
+        assert!(rules::same_actions(&rules.get_keyword_actions(), &[Action::Notify]));
+
+        // Without any default rule that notifies, the keyword rules still notify.
+        for rule_id in [
+            PredefinedUnderrideRuleId::Encrypted,
+            PredefinedUnderrideRuleId::RoomOneToOne,
+            PredefinedUnderrideRuleId::EncryptedRoomOneToOne,
+        ] {
+            ruleset.set_actions(RuleKind::Underride, rule_id, vec![]).unwrap();
+        }
+        let rules = Rules::new(ruleset);
+        assert!(rules::same_actions(&rules.get_keyword_actions(), &[Action::Notify, sound]));
+    }
+
+    #[async_test]
+    async fn test_apply_set_keyword_command() {
+        let mut rules = Rules::new(get_server_default_ruleset());
+
+        // Build a `RuleCommands` inserting a keyword rule
+        let mut rules_commands = RuleCommands::new(rules.ruleset.clone());
+        rules_commands.insert_keyword_rule("team".to_owned(), vec![Action::Notify]).unwrap();
+
+        rules.apply(rules_commands);
+
+        // The keyword must be in the updated rules
+        assert!(rules.get_keywords().contains("team"));
+    }
+
     #[async_test]
     async fn test_get_rooms_with_user_defined_rules() {
         // Without user-defined rules
@@ -751,6 +751,9 @@ impl Oidc {
         };
         self.client.base_client().set_session_meta(session).await.map_err(crate::Error::from)?;
 
+        #[cfg(feature = "e2e-encryption")]
+        self.client.encryption().dehydrated_devices().rehydrate_after_login().await;
+
         Ok(())
     }
 
@@ -3,12 +3,25 @@
 use std::{borrow::Borrow, collections::BTreeMap, ops::Deref, sync::Arc, time::Duration};
 
 use eyeball::SharedObservable;
+#[cfg(feature = "e2e-encryption")]
+use futures_core::Stream;
+#[cfg(feature = "e2e-encryption")]
+use futures_util::{pin_mut, StreamExt};
+#[cfg(feature = "e2e-encryption")]
+use matrix_sdk_base::{
+    crypto::{
+        store::IdentityViolationUpdate, CollectStrategy, EncryptionSettings, IdentityViolation,
+        RoomKeySharingReport,
+    },
+    deserialized_responses::EncryptionInfo,
+};
 use matrix_sdk_base::{
     deserialized_responses::{
         MembersResponse, RawAnySyncOrStrippedState, RawSyncOrStrippedState, SyncOrStrippedState,
         TimelineEvent,
     },
     instant::Instant,
+    push::get_push_actions,
     store::StateStoreExt,
     RoomMemberships, StateChanges,
 };
@@ -75,7 +88,7 @@ use crate::{
     error::WrongRoomState,
     event_handler::{EventHandler, EventHandlerHandle, SyncEvent},
     media::{MediaFormat, MediaRequest},
-    notification_settings::{IsEncrypted, IsOneToOne, RoomNotificationMode},
+    notification_settings::{IsEncrypted, IsOneToOne, NotificationSettings, RoomNotificationMode},
     sync::RoomUpdate,
     BaseRoom, Client, Error, HttpError, HttpResult, Result, RoomState, TransmissionProgress,
 };
@@ -974,6 +987,214 @@ impl Room {
         }
     }
 
+    /// Get the up-to-date encryption info of events that were decrypted using
+    /// the given Megolm session.
+    ///
+    /// This can be used to refresh the [`EncryptionInfo`] of already decrypted
+    /// events once the trust of the sending device or user identity changes.
+    ///
+    /// # Arguments
+    /// * `session_id` - The ID of the Megolm session that was used to decrypt
+    ///   the events.
+    ///
+    /// * `sender` - The user ID of the sender of the events.
+    ///
+    /// Returns `None` if we don't have the session or if the encryption info
+    /// couldn't be computed.
+    #[cfg(feature = "e2e-encryption")]
+    pub async fn get_encryption_info(
+        &self,
+        session_id: &str,
+        sender: &UserId,
+    ) -> Option<EncryptionInfo> {
+        let machine = self.client.olm_machine().await;
+
+        match machine
+            .as_ref()?
+            .get_session_encryption_info(self.room_id(), session_id, sender)
+            .await
+        {
+            Ok(info) => info,
+            Err(e) => {
+                warn!(session_id, "Couldn't get the encryption info of a session: {e}");
+                None
+            }
+        }
+    }
+
+    /// Get the members of this room whose identity is in violation.
+    ///
+    /// A member's identity is in violation if its master key changed without
+    /// the change being acknowledged, or if the member was verified in the past
+    /// but isn't anymore, see [`IdentityViolation`].
+    ///
+    /// Violations can be resolved using
+    /// [`UserIdentity::pin_current_master_key()`] or
+    /// [`UserIdentity::withdraw_verification()`].
+    ///
+    /// [`UserIdentity::pin_current_master_key()`]: crate::encryption::identities::UserIdentity::pin_current_master_key
+    /// [`UserIdentity::withdraw_verification()`]: crate::encryption::identities::UserIdentity::withdraw_verification
+    #[cfg(feature = "e2e-encryption")]
+    pub async fn identity_violations(&self) -> Result<Vec<(OwnedUserId, IdentityViolation)>> {
+        let machine = self.client.olm_machine().await;
+        let machine = machine.as_ref().ok_or(Error::NoOlmMachine)?;
+
+        let members =
+            self.client.store().get_user_ids(self.room_id(), RoomMemberships::ACTIVE).await?;
+        let mut violations = Vec::new();
+
+        for user_id in members {
+            if let Some(violation) = machine
+                .get_identity(&user_id, None)
+                .await?
+                .and_then(|i| i.other())
+                .and_then(|i| i.identity_violation())
+            {
+                violations.push((user_id, violation));
+            }
+        }
+
+        Ok(violations)
+    }
+
+    /// Receive the updates of the identity violations of the members of this
+    /// room as a [`Stream`].
+    ///
+    /// This is [`Encryption::identity_violations_stream()`] restricted to the
+    /// users that are active members of this room when the update is
+    /// received. Batches without any member of this room are skipped.
+    ///
+    /// [`Encryption::identity_violations_stream()`]: crate::encryption::Encryption::identity_violations_stream
+    #[cfg(feature = "e2e-encryption")]
+    pub async fn identity_violations_stream(
+        &self,
+    ) -> Result<impl Stream<Item = Vec<IdentityViolationUpdate>>> {
+        let machine = self.client.olm_machine().await;
+        let updates =
+            machine.as_ref().ok_or(Error::NoOlmMachine)?.store().identity_violations_stream();
+        let room = self.clone();
+
+        Ok(async_stream::stream! {
+            pin_mut!(updates);
+
+            while let Some(updates) = updates.next().await {
+                let members = match room
+                    .client
+                    .store()
+                    .get_user_ids(room.room_id(), RoomMemberships::ACTIVE)
+                    .await
+                {
+                    Ok(members) => members,
+                    Err(error) => {
+                        warn!(room_id = ?room.room_id(), ?error, "Couldn't load the room members");
+                        continue;
+                    }
+                };
+
+                let updates: Vec<_> =
+                    updates.into_iter().filter(|u| members.contains(&u.user_id)).collect();
+
+                if !updates.is_empty() {
+                    yield updates;
+                }
+            }
+        })
+    }
+
+    /// Check whether sharing the room keys of this room fails if some of its
+    /// members have unacknowledged identity violations.
+    ///
+    /// See [`Room::set_error_on_identity_violation()`].
+    #[cfg(feature = "e2e-encryption")]
+    pub async fn error_on_identity_violation(&self) -> Result<bool> {
+        let machine = self.client.olm_machine().await;
+        let machine = machine.as_ref().ok_or(Error::NoOlmMachine)?;
+
+        Ok(machine.store().get_error_on_identity_violation(self.room_id()).await?)
+    }
+
+    /// Set whether room keys should refuse to be shared in this room if some
+    /// of its members have unacknowledged identity violations.
+    ///
+    /// If this is set to `false`, which is the default, identity violations
+    /// will only be logged when a room key is shared. Otherwise sending a
+    /// message into this room will fail with an
+    /// [`OlmError::IdentityViolations`] error, until the violations are
+    /// resolved.
+    ///
+    /// [`OlmError::IdentityViolations`]: crate::encryption::OlmError::IdentityViolations
+    #[cfg(feature = "e2e-encryption")]
+    pub async fn set_error_on_identity_violation(&self, error: bool) -> Result<()> {
+        let machine = self.client.olm_machine().await;
+        let machine = machine.as_ref().ok_or(Error::NoOlmMachine)?;
+
+        Ok(machine.store().set_error_on_identity_violation(self.room_id(), error).await?)
+    }
+
+    /// Get the strategy deciding which devices should receive the room keys
+    /// of this room.
+    ///
+    /// By default, room keys are shared with all the devices that weren't
+    /// blacklisted, see [`CollectStrategy`] for the other available
+    /// strategies.
+    #[cfg(feature = "e2e-encryption")]
+    pub async fn room_key_sharing_strategy(&self) -> Result<CollectStrategy> {
+        let machine = self.client.olm_machine().await;
+        let machine = machine.as_ref().ok_or(Error::NoOlmMachine)?;
+
+        Ok(machine.store().get_room_key_sharing_strategy(self.room_id()).await?)
+    }
+
+    /// Set the strategy deciding which devices should receive the room keys
+    /// of this room.
+    ///
+    /// The current room key is discarded, the next message sent to the room
+    /// will use a new room key shared according to the new strategy.
+    #[cfg(feature = "e2e-encryption")]
+    pub async fn set_room_key_sharing_strategy(&self, strategy: CollectStrategy) -> Result<()> {
+        let machine = self.client.olm_machine().await;
+        let machine = machine.as_ref().ok_or(Error::NoOlmMachine)?;
+
+        machine.store().set_room_key_sharing_strategy(self.room_id(), strategy).await?;
+        machine.invalidate_group_session(self.room_id()).await?;
+
+        Ok(())
+    }
+
+    /// Compute which devices of the room members would receive the room key
+    /// if the given sharing strategy was used, and which devices would be
+    /// excluded and why.
+    ///
+    /// This doesn't share any room key, it can be used to show the
+    /// consequences of a stricter [`CollectStrategy`] before enabling it with
+    /// [`Room::set_room_key_sharing_strategy()`].
+    #[cfg(feature = "e2e-encryption")]
+    pub async fn room_key_sharing_report(
+        &self,
+        strategy: CollectStrategy,
+    ) -> Result<RoomKeySharingReport> {
+        use ruma::EventEncryptionAlgorithm;
+
+        let machine = self.client.olm_machine().await;
+        let machine = machine.as_ref().ok_or(Error::NoOlmMachine)?;
+
+        let history_visibility = self.history_visibility();
+        let filter = if history_visibility == HistoryVisibility::Joined {
+            RoomMemberships::JOIN
+        } else {
+            RoomMemberships::ACTIVE
+        };
+        let members = self.client.store().get_user_ids(self.room_id(), filter).await?;
+
+        let content = self.encryption_settings().unwrap_or_else(|| {
+            RoomEncryptionEventContent::new(EventEncryptionAlgorithm::MegolmV1AesSha2)
+        });
+        let mut settings = EncryptionSettings::new(content, history_visibility, false);
+        settings.sharing_strategy = strategy;
+
+        Ok(machine.room_key_sharing_report(members.iter().map(Deref::deref), &settings).await?)
+    }
+
     /// Ban the user with `UserId` from this room.
     ///
     /// # Arguments
@@ -2304,7 +2525,10 @@ impl Room {
     /// Get the push actions for the given event with the current room state.
     ///
     /// Note that it is possible that no push action is returned because the
-    /// current room state does not have all the required state events.
+    /// current room state does not have all the required state events, or
+    /// because the event is still encrypted.
+    ///
+    /// See [`get_push_actions()`] for how the push rules are evaluated.
     pub async fn event_push_actions<T>(&self, event: &Raw<T>) -> Result<Option<Vec<Action>>> {
         let Some(push_context) = self.push_context().await? else {
             debug!("Could not aggregate push context");
@@ -2313,7 +2537,7 @@ impl Room {
 
         let push_rules = self.client().account().push_rules().await?;
 
-        Ok(Some(push_rules.get_actions(event, &push_context).to_owned()))
+        Ok(get_push_actions(&push_rules, event, &push_context))
     }
 
     /// The membership details of the (latest) invite for the logged-in user in
@@ -2368,6 +2592,21 @@ impl Room {
         }
         let notification_settings = self.client().notification_settings().await;
 
+        self.notification_mode_with_settings(&notification_settings).await
+    }
+
+    /// Get the notification mode, from the given notification settings.
+    ///
+    /// This avoids fetching the push rules again when the notification modes
+    /// of many rooms are needed.
+    pub async fn notification_mode_with_settings(
+        &self,
+        notification_settings: &NotificationSettings,
+    ) -> Option<RoomNotificationMode> {
+        if !matches!(self.state(), RoomState::Joined) {
+            return None;
+        }
+
         // Get the user-defined mode if available
         let notification_mode =
             notification_settings.get_user_defined_room_notification_mode(self.room_id()).await;
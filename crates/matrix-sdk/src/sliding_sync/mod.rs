@@ -57,7 +57,7 @@ use self::{
     sticky_parameters::{LazyTransactionId, SlidingSyncStickyManager, StickyData},
     utils::JoinHandleExt as _,
 };
-use crate::{config::RequestConfig, Client, Result};
+use crate::{client::STATE_STORE_LOCK_MAX_BACKOFF_MS, config::RequestConfig, Client, Result};
 
 /// The Sliding Sync instance.
 ///
@@ -557,6 +557,10 @@ impl SlidingSync {
 
     #[instrument(skip_all, fields(pos))]
     async fn sync_once(&self) -> Result<UpdateSummary> {
+        if self.inner.client.is_wiped() {
+            return Err(crate::Error::AuthenticationRequired);
+        }
+
         let (request, request_config, requested_room_unsubscriptions, mut position_guard) =
             self.generate_sync_request(&mut LazyTransactionId::new()).await?;
 
@@ -659,11 +663,26 @@ impl SlidingSync {
                     .retain(|room_id| !requested_room_unsubscriptions.contains(room_id));
             }
 
+            // If other processes share the state store, make sure only one of them writes
+            // a response into it at a time.
+            let state_store_guard = this
+                .inner
+                .client
+                .spin_lock_state_store(Some(STATE_STORE_LOCK_MAX_BACKOFF_MS))
+                .await?;
+
+            // Don't restore any data if the session was wiped during the request.
+            if this.inner.client.is_wiped() {
+                return Err(crate::Error::AuthenticationRequired);
+            }
+
             // Handle the response.
             let updates = this.handle_response(response, &mut position_guard).await?;
 
             this.cache_to_storage(&position_guard).await?;
 
+            drop(state_store_guard);
+
             // Release the position guard lock.
             // It means that other responses can be generated and then handled later.
             drop(position_guard);
@@ -39,7 +39,9 @@ use ruma::{
 };
 use tracing::{debug, error, warn};
 
-use crate::{event_handler::HandlerKind, Client, Result, Room};
+use crate::{
+    client::STATE_STORE_LOCK_MAX_BACKOFF_MS, event_handler::HandlerKind, Client, Result, Room,
+};
 
 /// The processed response of a `/sync` request.
 #[derive(Clone, Default)]
@@ -148,7 +150,11 @@ impl Client {
         &self,
         response: sync_events::v3::Response,
     ) -> Result<BaseSyncResponse> {
-        let response = Box::pin(self.base_client().receive_sync_response(response)).await?;
+        let response = {
+            let _state_store_guard =
+                self.spin_lock_state_store(Some(STATE_STORE_LOCK_MAX_BACKOFF_MS)).await?;
+            Box::pin(self.base_client().receive_sync_response(response)).await?
+        };
         self.handle_sync_response(&response).await?;
         Ok(response)
     }
@@ -1,11 +1,13 @@
 use std::{collections::BTreeMap, time::Duration};
 
 use assert_matches::assert_matches;
-use futures_util::FutureExt;
+use eyeball::SharedObservable;
+use futures_util::{stream, FutureExt, TryStreamExt};
 use matrix_sdk::{
-    config::SyncSettings,
+    config::{RequestConfig, SyncSettings},
     media::{MediaFormat, MediaRequest, MediaThumbnailSize},
     sync::RoomUpdate,
+    TransmissionProgress,
 };
 use matrix_sdk_base::RoomState;
 use matrix_sdk_test::{async_test, test_json};
@@ -29,7 +31,7 @@ use wiremock::{
     Mock, ResponseTemplate,
 };
 
-use crate::{logged_in_client, mock_sync, no_retry_test_client};
+use crate::{logged_in_client, mock_sync, no_retry_test_client, test_client_builder};
 
 #[async_test]
 async fn sync() {
@@ -337,6 +339,199 @@ async fn get_media_file() {
         .unwrap();
 }
 
+#[async_test]
+async fn get_media_content_stream() {
+    let (client, server) = logged_in_client().await;
+
+    let request = MediaRequest {
+        source: MediaSource::Plain(mxc_uri!("mxc://localhost/textfile").to_owned()),
+        format: MediaFormat::File,
+    };
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/media/r0/download/localhost/textfile"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("Some very interesting text."))
+        .mount(&server)
+        .await;
+
+    let progress = SharedObservable::new(TransmissionProgress::default());
+    let content: Vec<u8> = client
+        .media()
+        .get_media_content_stream(&request, progress.clone())
+        .await
+        .unwrap()
+        .map_ok(|chunk| chunk.to_vec())
+        .try_concat()
+        .await
+        .unwrap();
+
+    assert_eq!(content, b"Some very interesting text.");
+    assert_eq!(progress.get().current, content.len());
+    assert_eq!(progress.get().total, content.len());
+}
+
+#[async_test]
+async fn get_media_content_stream_retry() {
+    let (builder, server) = test_client_builder().await;
+    let client = builder.request_config(RequestConfig::new().retry_limit(3)).build().await.unwrap();
+
+    let request = MediaRequest {
+        source: MediaSource::Plain(mxc_uri!("mxc://localhost/textfile").to_owned()),
+        format: MediaFormat::File,
+    };
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/media/r0/download/localhost/textfile"))
+        .respond_with(ResponseTemplate::new(500).set_body_json(json!({
+            "errcode": "M_UNKNOWN",
+            "error": "Internal server error",
+        })))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/media/r0/download/localhost/textfile"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("Some very interesting text."))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let content = client
+        .media()
+        .get_media_content_stream(&request, Default::default())
+        .await
+        .unwrap()
+        .map_ok(|chunk| chunk.to_vec())
+        .try_concat()
+        .await
+        .unwrap();
+
+    assert_eq!(content, b"Some very interesting text.");
+}
+
+#[async_test]
+async fn upload_stream() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/media/r0/upload"))
+        .and(header("content-type", "text/plain"))
+        .and(header("content-length", "27"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+          "content_uri": "mxc://example.com/AQwafuaFswefuhsfAFAgsw"
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let chunks = ["Some very ", "interesting ", "text."].map(|c| Ok(c.as_bytes().to_vec()));
+    let progress = SharedObservable::new(TransmissionProgress::default());
+
+    let response = client
+        .media()
+        .upload_stream(&mime::TEXT_PLAIN, 27, stream::iter(chunks), progress.clone())
+        .await
+        .unwrap();
+
+    assert_eq!(response.content_uri, "mxc://example.com/AQwafuaFswefuhsfAFAgsw");
+    assert_eq!(progress.get().current, 27);
+    assert_eq!(progress.get().total, 27);
+
+    let requests = server.received_requests().await.unwrap();
+    let upload = requests.iter().find(|r| r.url.path() == "/_matrix/media/r0/upload").unwrap();
+    assert_eq!(upload.body, b"Some very interesting text.");
+}
+
+#[cfg(feature = "e2e-encryption")]
+#[async_test]
+async fn encrypted_file_stream_roundtrip() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/media/r0/upload"))
+        .and(header("content-length", "27"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+          "content_uri": "mxc://example.com/AQwafuaFswefuhsfAFAgsw"
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let chunks = ["Some very ", "interesting ", "text."].map(|c| Ok(c.as_bytes().to_vec()));
+    let file = client
+        .prepare_encrypted_file_stream(
+            &mime::TEXT_PLAIN,
+            27,
+            stream::iter(chunks),
+            Default::default(),
+        )
+        .await
+        .unwrap();
+
+    // Serve the encrypted data that was uploaded.
+    let requests = server.received_requests().await.unwrap();
+    let upload = requests.iter().find(|r| r.url.path() == "/_matrix/media/r0/upload").unwrap();
+    assert_ne!(upload.body, b"Some very interesting text.");
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/media/r0/download/example.com/AQwafuaFswefuhsfAFAgsw"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(upload.body.clone()))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let request =
+        MediaRequest { source: MediaSource::Encrypted(Box::new(file)), format: MediaFormat::File };
+    let content = client
+        .media()
+        .get_media_content_stream(&request, Default::default())
+        .await
+        .unwrap()
+        .map_ok(|chunk| chunk.to_vec())
+        .try_concat()
+        .await
+        .unwrap();
+
+    assert_eq!(content, b"Some very interesting text.");
+}
+
+#[cfg(feature = "e2e-encryption")]
+#[async_test]
+async fn encrypted_file_reader() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/media/r0/upload"))
+        .and(header("content-length", "27"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+          "content_uri": "mxc://example.com/AQwafuaFswefuhsfAFAgsw"
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let progress = SharedObservable::new(TransmissionProgress::default());
+    let file = client
+        .prepare_encrypted_file_reader(
+            &mime::TEXT_PLAIN,
+            27,
+            &b"Some very interesting text."[..],
+            progress.clone(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(file.url, "mxc://example.com/AQwafuaFswefuhsfAFAgsw");
+    assert_eq!(progress.get().current, 27);
+
+    let requests = server.received_requests().await.unwrap();
+    let upload = requests.iter().find(|r| r.url.path() == "/_matrix/media/r0/upload").unwrap();
+    assert_eq!(upload.body.len(), 27);
+    assert_ne!(upload.body, b"Some very interesting text.");
+}
+
 #[async_test]
 async fn whoami() {
     let (client, server) = logged_in_client().await;
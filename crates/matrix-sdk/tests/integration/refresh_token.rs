@@ -1,11 +1,12 @@
 use std::time::Duration;
 
 use assert_matches::assert_matches;
-use futures_util::StreamExt;
+use futures_util::{StreamExt, TryStreamExt};
 use matrix_sdk::{
     config::RequestConfig,
     executor::spawn,
     matrix_auth::{Session, SessionTokens},
+    media::{MediaFormat, MediaRequest},
     HttpError, RefreshTokenError,
 };
 use matrix_sdk_base::SessionMeta;
@@ -15,7 +16,9 @@ use ruma::{
         client::{account::register, error::ErrorKind},
         MatrixVersion,
     },
-    assign, device_id, user_id,
+    assign, device_id,
+    events::room::MediaSource,
+    mxc_uri, user_id,
 };
 use serde_json::json;
 use tokio::sync::mpsc;
@@ -303,6 +306,65 @@ async fn refresh_token_handled_success() {
     changed_join_handle.await.unwrap();
 }
 
+#[async_test]
+async fn refresh_token_handled_media_download() {
+    let (builder, server) = test_client_builder().await;
+    let client = builder
+        .request_config(RequestConfig::new().disable_retry())
+        .server_versions([MatrixVersion::V1_3])
+        .handle_refresh_tokens()
+        .build()
+        .await
+        .unwrap();
+    let auth = client.matrix_auth();
+
+    let session = session();
+    auth.restore_session(session).await.unwrap();
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/v3/refresh"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::REFRESH_TOKEN))
+        .expect(1)
+        .named("`POST /refresh`")
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/media/v3/download/localhost/textfile"))
+        .respond_with(
+            ResponseTemplate::new(401).set_body_json(&*test_json::UNKNOWN_TOKEN_SOFT_LOGOUT),
+        )
+        .up_to_n_times(1)
+        .expect(1)
+        .named("`GET /download` wrong token")
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/media/v3/download/localhost/textfile"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("Some very interesting text."))
+        .expect(1)
+        .named("`GET /download` good token")
+        .mount(&server)
+        .await;
+
+    let request = MediaRequest {
+        source: MediaSource::Plain(mxc_uri!("mxc://localhost/textfile").to_owned()),
+        format: MediaFormat::File,
+    };
+    let content = client
+        .media()
+        .get_media_content_stream(&request, Default::default())
+        .await
+        .unwrap()
+        .map_ok(|chunk| chunk.to_vec())
+        .try_concat()
+        .await
+        .unwrap();
+
+    assert_eq!(content, b"Some very interesting text.");
+}
+
 #[async_test]
 async fn refresh_token_handled_failure() {
     let (builder, server) = test_client_builder().await;
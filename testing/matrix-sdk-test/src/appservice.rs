@@ -1,7 +1,10 @@
 use ruma::{events::AnyTimelineEvent, serde::Raw};
 use serde_json::Value;
 
-use crate::{event_builder::TimelineTestEvent, test_json};
+use crate::{
+    event_builder::{EphemeralTestEvent, PresenceTestEvent, TimelineTestEvent},
+    test_json,
+};
 
 /// Clones the given [`Value`] and adds a `room_id` to it
 ///
@@ -20,6 +23,7 @@ pub fn value_with_room_id(value: &mut Value) {
 #[derive(Debug, Default)]
 pub struct TransactionBuilder {
     events: Vec<Raw<AnyTimelineEvent>>,
+    ephemeral: Vec<Value>,
 }
 
 impl TransactionBuilder {
@@ -38,12 +42,37 @@ impl TransactionBuilder {
         self
     }
 
+    /// Add an ephemeral room event.
+    pub fn add_ephemeral_event(&mut self, event: EphemeralTestEvent) -> &mut Self {
+        let mut val = event.into_json_value();
+        value_with_room_id(&mut val);
+
+        self.ephemeral.push(val);
+        self
+    }
+
+    /// Add a presence event.
+    pub fn add_presence_event(&mut self, event: PresenceTestEvent) -> &mut Self {
+        self.ephemeral.push(event.into_json_value());
+        self
+    }
+
     /// Build the transaction as a serialized HTTP body
     pub fn build_transaction(&self) -> Vec<u8> {
-        serde_json::to_vec(&serde_json::json!({ "events": self.events })).unwrap()
+        let mut transaction = serde_json::json!({ "events": self.events });
+
+        if !self.ephemeral.is_empty() {
+            transaction
+                .as_object_mut()
+                .expect("transaction is an object")
+                .insert("de.sorunome.msc2409.ephemeral".to_owned(), self.ephemeral.clone().into());
+        }
+
+        serde_json::to_vec(&transaction).unwrap()
     }
 
     pub fn clear(&mut self) {
         self.events.clear();
+        self.ephemeral.clear();
     }
 }